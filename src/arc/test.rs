@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::io;
+
+use super::*;
+
+/// The private key that test messages are sealed with.
+const KEY: &str = include_str!("../test/certs/arc.key");
+
+/// The public key of [`KEY`], as published in DNS.
+const PUBLIC_KEY: &str = include_str!("../test/certs/arc.pub");
+
+/// Publishes [`PUBLIC_KEY`] under `arc._domainkey.example.com`, and a revoked key under
+/// `revoked._domainkey.example.com`.
+struct Stub;
+
+impl Resolver for Stub {
+    async fn lookup_ip(&self, _: &str) -> io::Result<Vec<std::net::IpAddr>> {
+        Ok(Vec::new())
+    }
+
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+        Ok(match name {
+            "arc._domainkey.example.com" => {
+                vec![format!("v=DKIM1; k=rsa; p={}", PUBLIC_KEY.trim())]
+            }
+            "revoked._domainkey.example.com" => vec!["v=DKIM1; p=".to_owned()],
+            _ => Vec::new(),
+        })
+    }
+}
+
+/// Create a message from `smith@example.com` with the subject `subject`, if any.
+fn unsealed(subject: Option<&str>) -> std::result::Result<Message, Box<dyn std::error::Error>> {
+    let mut builder = Message::builder()
+        .reverse_path("smith@example.com")
+        .forward_path("jones@example.com")
+        .header("From", "<smith@example.com>");
+    if let Some(subject) = subject {
+        builder = builder.header("Subject", subject);
+    }
+
+    Ok(builder.body("body\r\n").build()?)
+}
+
+#[test]
+fn test_canonicalization() {
+    let (fields, body) = canonical::split(
+        b"Subject:  Hello\r\n\tWorld  \r\nFrom: <smith@example.com>\r\n\r\n a  b \r\n\r\n\r\n",
+    );
+
+    assert_eq!(fields.len(), 2);
+    assert!(fields[0].is("subject"));
+    assert_eq!(fields[0].value(), b"  Hello\r\n\tWorld  ");
+    assert_eq!(body, b" a  b \r\n\r\n\r\n");
+
+    let mut header = Vec::new();
+    Canonicalization::Relaxed.header(fields[0].raw, &mut header);
+    assert_eq!(header, b"subject:Hello World\r\n");
+    assert_eq!(Canonicalization::Relaxed.body(body), b" a b\r\n");
+    assert_eq!(Canonicalization::Simple.body(body), b" a  b \r\n");
+    assert_eq!(Canonicalization::Relaxed.body(b""), b"");
+    assert_eq!(Canonicalization::Simple.body(b"\r\n\r\n"), b"\r\n");
+
+    assert_eq!(
+        signature::without_signature(b"ARC-Seal: i=1; b=abc\r\n\tdef; d=example.com\r\n"),
+        b"ARC-Seal: i=1; b=; d=example.com\r\n"
+    );
+    assert_eq!(
+        signature::without_signature(b"ARC-Seal: i=1; bh=abc; b=abc\r\n"),
+        b"ARC-Seal: i=1; bh=abc; b=\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_seal_and_verify() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let sealer = ArcSealer::from_pem("example.com", "arc", KEY)?;
+    let mut message = unsealed(Some("test"))?;
+
+    assert_eq!(verify(&Stub, message.data()).await, ArcResult::None);
+
+    // Tests that a sealed message validates, and can be sealed again.
+    sealer.seal(&mut message, ArcResult::None, "mx.example.com")?;
+    assert!(message.headers().contains(ARC_SEAL));
+    assert_eq!(verify(&Stub, message.data()).await, ArcResult::Pass);
+
+    sealer.seal(&mut message, ArcResult::Pass, "relay.example.com")?;
+    assert_eq!(verify(&Stub, message.data()).await, ArcResult::Pass);
+    assert!(message
+        .headers()
+        .get_all(ARC_AUTHENTICATION_RESULTS)
+        .any(|results| results.starts_with("i=2; relay.example.com;")));
+
+    // Tests that modifying the message after the latest seal breaks the chain.
+    let mut modified = message.clone();
+    modified.replace_header("Subject", "modified")?;
+    assert_eq!(verify(&Stub, modified.data()).await, ArcResult::Fail);
+
+    // Tests that modifying it before the latest seal does not.
+    let mut modified = unsealed(Some("test"))?;
+    sealer.seal(&mut modified, ArcResult::None, "mx.example.com")?;
+    modified.replace_header("Subject", "modified")?;
+    sealer.seal(&mut modified, ArcResult::Pass, "relay.example.com")?;
+    assert_eq!(verify(&Stub, modified.data()).await, ArcResult::Pass);
+
+    // Tests that a chain reported as broken stays broken.
+    let mut broken = unsealed(None)?;
+    sealer.seal(&mut broken, ArcResult::None, "mx.example.com")?;
+    sealer.seal(&mut broken, ArcResult::Fail, "relay.example.com")?;
+    assert_eq!(verify(&Stub, broken.data()).await, ArcResult::Fail);
+
+    // Tests that the key must be published.
+    let revoked = ArcSealer::from_pem("example.com", "revoked", KEY)?;
+    let mut unverifiable = unsealed(None)?;
+    revoked.seal(&mut unverifiable, ArcResult::None, "mx.example.com")?;
+    assert_eq!(verify(&Stub, unverifiable.data()).await, ArcResult::Fail);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invalid_chains() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let sealer = ArcSealer::from_pem("example.com", "arc", KEY)?;
+
+    // Tests that a set missing a field fails.
+    let mut message = unsealed(None)?;
+    sealer.seal(&mut message, ArcResult::None, "mx.example.com")?;
+    message.remove_header(ARC_AUTHENTICATION_RESULTS);
+    assert_eq!(verify(&Stub, message.data()).await, ArcResult::Fail);
+
+    // Tests that instances must start from 1.
+    let data = String::from_utf8(message.data().to_vec())?.replace("i=1;", "i=2;");
+    assert_eq!(verify(&Stub, data.as_bytes()).await, ArcResult::Fail);
+
+    assert_eq!(
+        ArcSealer::from_pem("example.com", "arc", "not a key").map(|_| ()),
+        Err(ArcError::InvalidKey)
+    );
+
+    Ok(())
+}
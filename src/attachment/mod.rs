@@ -0,0 +1,432 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Blocking attachments of dangerous types, such as programs and documents with macros.
+//!
+//! See [`AttachmentPolicy`].
+
+#[cfg(test)]
+mod test;
+
+use std::{collections::BTreeMap, fmt::Display, ptr};
+
+use crate::{
+    handler::Response,
+    message::{mime::Attachment, Message},
+    ServerConfig,
+};
+
+/// The extensions of the files that are blocked by default: programs, scripts, shortcuts, and
+/// Office documents that may contain macros.
+const DEFAULT_EXTENSIONS: &[&str] = &[
+    "ade", "adp", "app", "bat", "chm", "cmd", "com", "cpl", "dll", "docm", "dotm", "exe", "hta",
+    "inf", "ins", "isp", "jar", "js", "jse", "lnk", "msc", "msi", "msp", "mst", "pif", "potm",
+    "ppam", "ppsm", "pptm", "ps1", "reg", "scr", "sct", "shb", "shs", "sldm", "vb", "vbe", "vbs",
+    "wsc", "wsf", "wsh", "xlam", "xlsm", "xltm",
+];
+
+/// The types of the files that are blocked by default.
+const DEFAULT_CONTENT_TYPES: &[&str] = &[
+    "application/hta",
+    "application/javascript",
+    "application/vnd.ms-excel.addin.macroenabled.12",
+    "application/vnd.ms-excel.sheet.macroenabled.12",
+    "application/vnd.ms-excel.template.macroenabled.12",
+    "application/vnd.ms-powerpoint.addin.macroenabled.12",
+    "application/vnd.ms-powerpoint.presentation.macroenabled.12",
+    "application/vnd.ms-powerpoint.slideshow.macroenabled.12",
+    "application/vnd.ms-word.document.macroenabled.12",
+    "application/vnd.ms-word.template.macroenabled.12",
+    "application/x-dosexec",
+    "application/x-executable",
+    "application/x-javascript",
+    "application/x-ms-shortcut",
+    "application/x-msdos-program",
+    "application/x-msdownload",
+    "application/x-msi",
+    "application/x-sh",
+];
+
+/// The first bytes of programs for Windows (and DOS), Linux, and macOS.
+const EXECUTABLE_MAGIC: &[&[u8]] = &[
+    b"MZ",
+    b"\x7fELF",
+    b"\xfe\xed\xfa\xce",
+    b"\xfe\xed\xfa\xcf",
+    b"\xce\xfa\xed\xfe",
+    b"\xcf\xfa\xed\xfe",
+    b"\xca\xfe\xba\xbe",
+];
+
+/// The first bytes of an OLE compound file, such as a `.doc` or `.xls` document.
+const OLE_MAGIC: &[u8] = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1";
+
+/// The first bytes of a ZIP archive, such as a `.docx` or `.xlsx` document.
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// The name of the stream of an OLE compound file that holds its macros, in UTF-16LE as directory
+/// entries are.
+const OLE_MACROS: &[u8] = b"_\0V\0B\0A\0_\0P\0R\0O\0J\0E\0C\0T\0";
+
+/// The name of the file in an Office Open XML document that holds its macros. File names are not
+/// compressed in ZIP archives.
+const OOXML_MACROS: &[u8] = b"vbaProject.bin";
+
+/// Which attachments are blocked, by default and for the recipients of particular domains.
+///
+/// The attachments of each message are checked once its data is received, before it is handed to
+/// [`crate::SmtpHandler::on_message`]. Every accepted recipient is checked with the
+/// [`AttachmentRules`] of its domain, or [`Self::rules`] if its domain has none, and the message
+/// is rejected with `552 5.7.0` if any attachment is blocked for any recipient.
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::attachment::{AttachmentPolicy, AttachmentRules};
+/// #
+/// let policy = AttachmentPolicy::default()
+///     .with_domain("Lab.Example.com", AttachmentRules::allow_all().with_extension("exe"));
+///
+/// assert!(!policy.rules_for("lab.example.com").block_macros());
+/// assert!(policy.rules_for("example.com").block_macros());
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct AttachmentPolicy {
+    /// The rules for recipients whose domains do not have their own.
+    rules: AttachmentRules,
+    /// The rules for the recipients of particular domains.
+    domains: BTreeMap<String, AttachmentRules>,
+}
+
+impl AttachmentPolicy {
+    /// Create a new [`Self`] that checks the attachments for every recipient with `rules`.
+    #[must_use]
+    pub const fn new(rules: AttachmentRules) -> Self {
+        Self {
+            rules,
+            domains: BTreeMap::new(),
+        }
+    }
+
+    /// Get the rules for recipients whose domains do not have their own, [`AttachmentRules`]'s
+    /// default by default.
+    #[must_use]
+    pub const fn rules(&self) -> &AttachmentRules {
+        &self.rules
+    }
+
+    /// Get each domain that has its own rules, with those rules.
+    pub fn domains(&self) -> impl Iterator<Item = (&str, &AttachmentRules)> {
+        self.domains
+            .iter()
+            .map(|(domain, rules)| (domain.as_str(), rules))
+    }
+
+    /// Get the rules for the recipients of `domain`, matched without regard to ASCII case, which
+    /// are [`Self::rules`] if it does not have its own.
+    #[must_use]
+    pub fn rules_for(&self, domain: &str) -> &AttachmentRules {
+        self.domains
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(domain))
+            .map_or(&self.rules, |(_, rules)| rules)
+    }
+
+    /// Set the rules for recipients whose domains do not have their own. See [`Self::rules`].
+    #[must_use]
+    pub fn with_rules(mut self, rules: AttachmentRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Set the rules for the recipients of `domain`, in place of [`Self::rules`]. Subdomains are
+    /// not included. See [`Self::rules_for`].
+    #[must_use]
+    pub fn with_domain(mut self, domain: &str, rules: AttachmentRules) -> Self {
+        self.domains.insert(domain.to_ascii_lowercase(), rules);
+        self
+    }
+}
+
+/// Which attachments are blocked, by the extensions of their names, by their declared types, and
+/// by what their data is.
+///
+/// Defaults to blocking programs, scripts, shortcuts, and Office documents with macros.
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::attachment::AttachmentRules;
+/// #
+/// let rules = AttachmentRules::default()
+///     .with_extension(".iso")
+///     .with_block_macros(false);
+///
+/// assert!(rules.extensions().iter().any(|extension| extension == "iso"));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct AttachmentRules {
+    /// The extensions of the names of blocked files, without the leading period.
+    extensions: Vec<String>,
+    /// The declared types of blocked files, such as `application/x-msdownload`.
+    content_types: Vec<String>,
+    /// Whether programs are blocked by their first bytes, whatever their names and types.
+    block_executables: bool,
+    /// Whether Office documents with macros are blocked by their contents, whatever their names
+    /// and types.
+    block_macros: bool,
+}
+
+impl AttachmentRules {
+    /// Create a new [`Self`] that blocks nothing, to add to with the `with_*` methods.
+    #[must_use]
+    pub const fn allow_all() -> Self {
+        Self {
+            extensions: Vec::new(),
+            content_types: Vec::new(),
+            block_executables: false,
+            block_macros: false,
+        }
+    }
+
+    /// Get the extensions of the names of blocked files, such as `exe` and `js`, matched without
+    /// regard to ASCII case.
+    #[must_use]
+    pub fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    /// Get the declared types of blocked files, such as `application/x-msdownload`, matched
+    /// without regard to ASCII case and ignoring parameters.
+    #[must_use]
+    pub fn content_types(&self) -> &[String] {
+        &self.content_types
+    }
+
+    /// Get whether programs for Windows, Linux, and macOS are blocked by their first bytes,
+    /// whatever their names and declared types. Defaults to `true`.
+    #[must_use]
+    pub const fn block_executables(&self) -> bool {
+        self.block_executables
+    }
+
+    /// Get whether Office documents with macros are blocked by their contents, whatever their
+    /// names and declared types. Defaults to `true`.
+    ///
+    /// Both legacy documents (such as `.doc`) and Office Open XML documents (such as `.docm`) are
+    /// detected.
+    #[must_use]
+    pub const fn block_macros(&self) -> bool {
+        self.block_macros
+    }
+
+    /// Also block files whose names end with `extension`, with or without the leading period. See
+    /// [`Self::extensions`].
+    #[must_use]
+    pub fn with_extension(mut self, extension: &str) -> Self {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.extensions.push(extension);
+        self
+    }
+
+    /// Set the extensions of the names of blocked files, replacing the rest. See
+    /// [`Self::extensions`].
+    #[must_use]
+    pub fn with_extensions<T: AsRef<str>>(
+        mut self,
+        extensions: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.extensions.clear();
+        extensions.into_iter().fold(self, |rules, extension| {
+            rules.with_extension(extension.as_ref())
+        })
+    }
+
+    /// Also block files of the declared type `content_type`. See [`Self::content_types`].
+    #[must_use]
+    pub fn with_content_type(mut self, content_type: &str) -> Self {
+        self.content_types.push(content_type.to_ascii_lowercase());
+        self
+    }
+
+    /// Set the declared types of blocked files, replacing the rest. See [`Self::content_types`].
+    #[must_use]
+    pub fn with_content_types<T: AsRef<str>>(
+        mut self,
+        content_types: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.content_types.clear();
+        content_types.into_iter().fold(self, |rules, content_type| {
+            rules.with_content_type(content_type.as_ref())
+        })
+    }
+
+    /// Set whether programs are blocked by their first bytes. See [`Self::block_executables`].
+    #[must_use]
+    pub const fn with_block_executables(mut self, block: bool) -> Self {
+        self.block_executables = block;
+        self
+    }
+
+    /// Set whether Office documents with macros are blocked by their contents. See
+    /// [`Self::block_macros`].
+    #[must_use]
+    pub const fn with_block_macros(mut self, block: bool) -> Self {
+        self.block_macros = block;
+        self
+    }
+
+    /// Check `attachment` against the rules, returning why it is blocked, or `None` if it is not.
+    #[must_use]
+    pub fn check(&self, attachment: &Attachment) -> Option<AttachmentBlock> {
+        if let Some(extension) = attachment.filename().and_then(extension) {
+            if self.extensions.iter().any(|blocked| {
+                blocked
+                    .trim_start_matches('.')
+                    .eq_ignore_ascii_case(extension)
+            }) {
+                return Some(AttachmentBlock::Extension(extension.to_ascii_lowercase()));
+            }
+        }
+
+        let content_type = attachment.content_type().mime_type();
+        if self
+            .content_types
+            .iter()
+            .any(|blocked| blocked.eq_ignore_ascii_case(content_type))
+        {
+            return Some(AttachmentBlock::ContentType(content_type.to_owned()));
+        }
+
+        let data = attachment.data();
+        if self.block_executables && is_executable(data) {
+            return Some(AttachmentBlock::Executable);
+        }
+        if self.block_macros && has_macros(data) {
+            return Some(AttachmentBlock::Macros);
+        }
+
+        None
+    }
+}
+
+impl Default for AttachmentRules {
+    fn default() -> Self {
+        Self::allow_all()
+            .with_extensions(DEFAULT_EXTENSIONS)
+            .with_content_types(DEFAULT_CONTENT_TYPES)
+            .with_block_executables(true)
+            .with_block_macros(true)
+    }
+}
+
+/// Why an attachment is blocked by [`AttachmentRules`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum AttachmentBlock {
+    /// The name of the file ends with a blocked extension, in lowercase.
+    Extension(String),
+    /// The file has a blocked declared type.
+    ContentType(String),
+    /// The file is a program.
+    Executable,
+    /// The file is an Office document with macros.
+    Macros,
+}
+
+impl Display for AttachmentBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Extension(extension) => write!(f, "blocked extension .{extension}"),
+            Self::ContentType(content_type) => write!(f, "blocked type {content_type}"),
+            Self::Executable => "program".fmt(f),
+            Self::Macros => "document with macros".fmt(f),
+        }
+    }
+}
+
+/// Get the extension of the name of a file, ignoring any directories and the trailing periods and
+/// spaces that Windows drops.
+fn extension(filename: &str) -> Option<&str> {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or(filename);
+    let (_, extension) = name.trim_end_matches(['.', ' ']).rsplit_once('.')?;
+
+    (!extension.is_empty()).then_some(extension)
+}
+
+/// Get whether `data` starts like a program for Windows, Linux, or macOS.
+fn is_executable(data: &[u8]) -> bool {
+    EXECUTABLE_MAGIC.iter().any(|magic| data.starts_with(magic))
+}
+
+/// Get whether `data` is a legacy or Office Open XML document with macros.
+fn has_macros(data: &[u8]) -> bool {
+    let needle = if data.starts_with(OLE_MAGIC) {
+        OLE_MACROS
+    } else if data.starts_with(ZIP_MAGIC) {
+        OOXML_MACROS
+    } else {
+        return false;
+    };
+
+    data.windows(needle.len()).any(|window| window == needle)
+}
+
+/// Reject `message` if any of its attachments is blocked for any of its recipients by
+/// [`ServerConfig::attachment_policy`].
+pub(crate) fn apply(config: &ServerConfig, message: Message) -> Result<Message, Response> {
+    let Some(policy) = config.attachment_policy() else {
+        return Ok(message);
+    };
+    let attachments = message.attachments();
+    if attachments.is_empty() {
+        return Ok(message);
+    }
+    let id = message.session().id();
+
+    // Each set of rules is only checked once, however many recipients it applies to.
+    let mut rules: Vec<&AttachmentRules> = Vec::new();
+    for recipient in message.envelope().accepted() {
+        let forward_path = recipient.forward_path().as_str();
+        let domain = forward_path
+            .rsplit_once('@')
+            .map_or("", |(_, domain)| domain);
+        let domain_rules = policy.rules_for(domain);
+        if !rules.iter().any(|seen| ptr::eq(*seen, domain_rules)) {
+            rules.push(domain_rules);
+        }
+    }
+    if rules.is_empty() {
+        rules.push(policy.rules());
+    }
+
+    for attachment in &attachments {
+        if let Some(block) = rules.iter().find_map(|rules| rules.check(attachment)) {
+            println!(
+                "[{id}] Rejected attachment {:?}: {block}",
+                attachment.filename().unwrap_or_default()
+            );
+            return Err(Response::parse("552 5.7.0 Attachment type not allowed")
+                .expect("written in code as a valid reply"));
+        }
+    }
+
+    Ok(message)
+}
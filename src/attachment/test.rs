@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use bytes::Bytes;
+
+use super::*;
+use crate::message::mime;
+
+/// Create an attachment named `filename` of the declared type `content_type` holding `data`.
+fn attachment(filename: &str, content_type: &str, data: &[u8]) -> Attachment {
+    let headers = format!(
+        "Content-Type: {content_type}\r\n\
+         Content-Disposition: attachment; filename=\"{filename}\"\r\n\
+         Content-Transfer-Encoding: binary\r\n\
+         \r\n"
+    );
+
+    mime::attachments(&Bytes::from([headers.as_bytes(), data].concat()))
+        .pop()
+        .expect("the message has an attachment")
+}
+
+#[test]
+fn test_extension() {
+    assert_eq!(extension("report.PDF"), Some("PDF"));
+    assert_eq!(extension("archive.tar.gz"), Some("gz"));
+    assert_eq!(extension("invoice.exe. . "), Some("exe"));
+    assert_eq!(extension(r"C:\Users\smith\run.bat"), Some("bat"));
+    assert_eq!(extension("dir.d/README"), None);
+    assert_eq!(extension("README"), None);
+    assert_eq!(extension("trailing."), None);
+}
+
+#[test]
+fn test_rules() {
+    let rules = AttachmentRules::default();
+    let octets = "application/octet-stream";
+
+    // Tests that names and declared types are matched without regard to case.
+    assert_eq!(
+        rules.check(&attachment("Invoice.PDF.EXE", octets, b"data")),
+        Some(AttachmentBlock::Extension("exe".to_owned()))
+    );
+    assert_eq!(
+        rules.check(&attachment("script", "Application/JavaScript", b"data")),
+        Some(AttachmentBlock::ContentType(
+            "application/javascript".to_owned()
+        ))
+    );
+    assert_eq!(
+        rules.check(&attachment("report.pdf", octets, b"%PDF-1.7")),
+        None
+    );
+
+    // Tests that programs and documents with macros are found by their data, whatever their
+    // names.
+    assert_eq!(
+        rules.check(&attachment("photo.jpg", octets, b"MZ\x90\x00")),
+        Some(AttachmentBlock::Executable)
+    );
+    assert_eq!(
+        rules.check(&attachment("tool", octets, b"\x7fELF\x02\x01")),
+        Some(AttachmentBlock::Executable)
+    );
+    let docx = [ZIP_MAGIC, b"\x14\x00word/vbaProject.bin"].concat();
+    assert_eq!(
+        rules.check(&attachment("letter.docx", octets, &docx)),
+        Some(AttachmentBlock::Macros)
+    );
+    let doc = [OLE_MAGIC, b"\x00\x00", OLE_MACROS].concat();
+    assert_eq!(
+        rules.check(&attachment("letter.doc", octets, &doc)),
+        Some(AttachmentBlock::Macros)
+    );
+    let plain = [ZIP_MAGIC, b"\x14\x00word/document.xml"].concat();
+    assert_eq!(
+        rules.check(&attachment("letter.docx", octets, &plain)),
+        None
+    );
+
+    // Tests that rules can be loosened and extended.
+    let rules = AttachmentRules::allow_all().with_extensions([".ISO", "img"]);
+    assert_eq!(
+        rules.check(&attachment("disk.iso", octets, b"data")),
+        Some(AttachmentBlock::Extension("iso".to_owned()))
+    );
+    assert_eq!(rules.check(&attachment("setup.exe", octets, b"MZ")), None);
+}
+
+#[test]
+fn test_policy() {
+    let lab = AttachmentRules::allow_all();
+    let policy = AttachmentPolicy::default().with_domain("Lab.Example.com", lab.clone());
+
+    // Tests that domains are matched without regard to case, and that subdomains are not.
+    assert_eq!(policy.rules_for("LAB.example.COM"), &lab);
+    assert_eq!(policy.rules_for("a.lab.example.com"), policy.rules());
+    assert_eq!(policy.rules_for(""), policy.rules());
+    assert_eq!(
+        policy.domains().collect::<Vec<_>>(),
+        [("lab.example.com", &lab)]
+    );
+}
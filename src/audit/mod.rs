@@ -0,0 +1,316 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! An audit trail of every `4xx` and `5xx` reply, with what triggered it, so that operators can
+//! tell why mail was rejected without recording whole transcripts.
+//!
+//! See [`Rejection`] and [`crate::ServerHandle::rejections`].
+
+#[cfg(test)]
+mod test;
+
+use std::{fmt::Display, net::SocketAddr, sync::Mutex, time::SystemTime};
+
+use tokio::sync::broadcast;
+
+use crate::{
+    redact::Redactor,
+    session::{SessionId, SessionInfo},
+    stats::reply_lines,
+};
+
+/// The most rejections that are kept for a receiver that falls behind, after which the oldest are
+/// skipped.
+pub(crate) const BUFFERED_REJECTIONS: usize = 1024;
+
+/// A `4xx` or `5xx` reply that the server sent, and why, see [`crate::ServerHandle::rejections`].
+///
+/// Formats with [`Display`] as one line for a log, such as
+/// `[67110a4500000001] 192.0.2.1:49152 RCPT TO:<jones@example.com>: 550 5.1.1 User unknown
+/// (directory)`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Rejection {
+    /// When the reply was sent.
+    at: SystemTime,
+    /// The session that the reply was sent in, if it started.
+    session_id: Option<SessionId>,
+    /// The address of the client.
+    peer_addr: SocketAddr,
+    /// The command that was replied to, if any.
+    command: Option<String>,
+    /// The code of the reply.
+    code: u16,
+    /// The last line of the reply.
+    reply: String,
+    /// What triggered the reply.
+    source: RejectionSource,
+}
+
+impl Rejection {
+    /// Create a new [`Self`] for a reply sent now.
+    fn new(
+        session_id: Option<SessionId>,
+        peer_addr: SocketAddr,
+        command: Option<String>,
+        code: u16,
+        reply: String,
+        source: RejectionSource,
+    ) -> Self {
+        Self {
+            at: SystemTime::now(),
+            session_id,
+            peer_addr,
+            command,
+            code,
+            reply,
+            source,
+        }
+    }
+
+    /// Get when the reply was sent.
+    #[must_use]
+    pub const fn at(&self) -> SystemTime {
+        self.at
+    }
+
+    /// Get the session that the reply was sent in, or `None` if the connection was refused before
+    /// its session started, such as by [`crate::config::ServerConfig::max_connections`].
+    #[must_use]
+    pub const fn session_id(&self) -> Option<SessionId> {
+        self.session_id
+    }
+
+    /// Get the address of the client.
+    #[must_use]
+    pub const fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Get the command line that was replied to without its line ending, or `None` if the reply
+    /// came before the first command, such as in place of the greeting, or the line was too long.
+    ///
+    /// Credentials given with `AUTH` are replaced with `[redacted]`, as in
+    /// [`crate::transcript::Transcript`].
+    #[must_use]
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_deref()
+    }
+
+    /// Get the code of the reply, such as `550`.
+    #[must_use]
+    pub const fn code(&self) -> u16 {
+        self.code
+    }
+
+    /// Get the last line of the reply without its line ending, including its code.
+    #[must_use]
+    pub fn reply(&self) -> &str {
+        &self.reply
+    }
+
+    /// Get what triggered the reply.
+    #[must_use]
+    pub const fn source(&self) -> RejectionSource {
+        self.source
+    }
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(id) = self.session_id {
+            write!(f, "[{id}] ")?;
+        }
+        write!(f, "{} ", self.peer_addr)?;
+        if let Some(command) = &self.command {
+            write!(f, "{command}: ")?;
+        }
+        write!(f, "{} ({})", self.reply, self.source)
+    }
+}
+
+/// What triggered a [`Rejection`].
+///
+/// Formats with [`Display`] as the name of the source in `snake_case`, such as `handler` or the
+/// name of the policy or limit.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum RejectionSource {
+    /// The command was malformed, out of sequence, or not implemented, or the client broke the
+    /// protocol in another way.
+    Protocol,
+    /// A limit of the configuration was exceeded, named like its setting, such as
+    /// `max_message_size` for [`crate::ServerConfig::max_message_size`].
+    Limit(&'static str),
+    /// A policy check of the server, named like its module or setting, such as `dnsbl` or `spf`.
+    Policy(&'static str),
+    /// The handler decided so, or failed.
+    Handler,
+    /// The server is shutting down or failed, such as when a message could not be spooled.
+    Server,
+}
+
+impl Display for RejectionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Protocol => "protocol",
+            Self::Limit(name) | Self::Policy(name) => name,
+            Self::Handler => "handler",
+            Self::Server => "server",
+        })
+    }
+}
+
+/// Turns the `4xx` and `5xx` replies of a session into [`Rejection`]s, shared between its
+/// [`crate::handler::SessionContext`], which notes what is being replied to and why, and its
+/// connection, which sees every reply.
+#[derive(Debug)]
+pub(crate) struct Audit {
+    /// Sends each rejection to the receivers, if any.
+    rejections: broadcast::Sender<Rejection>,
+    /// The session that the replies are sent in.
+    session_id: SessionId,
+    /// The address of the client.
+    peer_addr: SocketAddr,
+    /// What is being replied to, and why.
+    state: Mutex<AuditState>,
+}
+
+/// The mutable part of an [`Audit`].
+#[derive(Debug, Default)]
+struct AuditState {
+    /// The command being replied to, if any.
+    command: Option<String>,
+    /// What triggers the next rejection, or `None` for [`RejectionSource::Protocol`].
+    source: Option<RejectionSource>,
+    /// Redacts the credentials in the commands.
+    redactor: Redactor,
+}
+
+impl Audit {
+    /// Create a new [`Self`] for `session`, sending its rejections through `rejections`.
+    pub(crate) fn new(session: &SessionInfo, rejections: broadcast::Sender<Rejection>) -> Self {
+        Self {
+            rejections,
+            session_id: session.id(),
+            peer_addr: session.peer_addr(),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Note that `line` was received from the client, or `None` if it was too long to keep, so
+    /// that the next replies are to it.
+    pub(crate) fn command(&self, line: Option<&[u8]>) {
+        let mut state = self.lock();
+
+        state.command = line.map(|line| {
+            state
+                .redactor
+                .received(&String::from_utf8_lossy(line))
+                .into_owned()
+        });
+        state.source = None;
+    }
+
+    /// Get whether the last reply was a `334` challenge, so the next line received is a
+    /// credential rather than a command.
+    pub(crate) fn awaits_credential(&self) -> bool {
+        self.lock().redactor.is_challenged()
+    }
+
+    /// Note that the next rejection is triggered by `source`.
+    pub(crate) fn source(&self, source: RejectionSource) {
+        self.lock().source = Some(source);
+    }
+
+    /// Turn the `4xx` and `5xx` replies written to the client in `written` into [`Rejection`]s.
+    pub(crate) fn replied(&self, written: &[u8]) {
+        let mut state = self.lock();
+
+        for (code, line) in reply_lines(written) {
+            state.redactor.sent(&String::from_utf8_lossy(line));
+            if !(400..600).contains(&code) || self.rejections.receiver_count() == 0 {
+                continue;
+            }
+
+            let _ = self.rejections.send(Rejection::new(
+                Some(self.session_id),
+                self.peer_addr,
+                state.command.clone(),
+                code,
+                String::from_utf8_lossy(line).into_owned(),
+                state.source.take().unwrap_or(RejectionSource::Protocol),
+            ));
+        }
+    }
+
+    /// Send a [`Rejection`] for `reply`, which refused the session in place of the greeting
+    /// because of `source`.
+    pub(crate) fn refused(&self, reply: &str, source: RejectionSource) {
+        send(
+            &self.rejections,
+            Some(self.session_id),
+            self.peer_addr,
+            reply,
+            source,
+        );
+    }
+
+    /// Lock the mutable part of `self`.
+    fn lock(&self) -> std::sync::MutexGuard<'_, AuditState> {
+        self.state
+            .lock()
+            .expect("the lock is never held across a panic")
+    }
+}
+
+/// Send a [`Rejection`] through `rejections` for a connection from `peer_addr` that was refused
+/// with `reply` before its session started.
+pub(crate) fn refused(
+    rejections: &broadcast::Sender<Rejection>,
+    peer_addr: SocketAddr,
+    reply: &str,
+    source: RejectionSource,
+) {
+    send(rejections, None, peer_addr, reply, source);
+}
+
+/// Send a [`Rejection`] for `reply`, which was sent in place of the greeting, through
+/// `rejections` if anything receives it.
+fn send(
+    rejections: &broadcast::Sender<Rejection>,
+    session_id: Option<SessionId>,
+    peer_addr: SocketAddr,
+    reply: &str,
+    source: RejectionSource,
+) {
+    if rejections.receiver_count() == 0 {
+        return;
+    }
+
+    let code = reply
+        .get(..3)
+        .and_then(|code| code.parse().ok())
+        .unwrap_or_default();
+    let _ = rejections.send(Rejection::new(
+        session_id,
+        peer_addr,
+        None,
+        code,
+        reply.to_owned(),
+        source,
+    ));
+}
@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use super::*;
+
+/// Create an [`Audit`] for a new session, along with a receiver of its rejections.
+fn audit() -> (Audit, broadcast::Receiver<Rejection>) {
+    let local = SocketAddr::from((Ipv4Addr::LOCALHOST, 25));
+    let peer = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 49152));
+    let (sender, receiver) = broadcast::channel(8);
+
+    (Audit::new(&SessionInfo::new(local, peer), sender), receiver)
+}
+
+#[test]
+fn test_replied() {
+    let (audit, mut receiver) = audit();
+
+    audit.command(Some(b"FOO\r\n".as_slice()));
+    audit.replied(b"500 Command not recognized\r\n");
+    let rejection = receiver.try_recv().unwrap();
+    assert_eq!(rejection.command(), Some("FOO"));
+    assert_eq!(rejection.code(), 500);
+    assert_eq!(rejection.reply(), "500 Command not recognized");
+    assert_eq!(rejection.source(), RejectionSource::Protocol);
+    assert_eq!(rejection.peer_addr().port(), 49152);
+    assert!(rejection.session_id().is_some());
+
+    // Only `4xx` and `5xx` replies are rejections, and only the last line of each counts.
+    audit.command(Some(b"RCPT TO:<jones@example.com>\r\n".as_slice()));
+    audit.source(RejectionSource::Policy("directory"));
+    audit.replied(b"250 OK\r\n550-5.1.1 No such\r\n550 5.1.1 User unknown\r\n");
+    let rejection = receiver.try_recv().unwrap();
+    assert_eq!(rejection.reply(), "550 5.1.1 User unknown");
+    assert_eq!(rejection.source(), RejectionSource::Policy("directory"));
+    assert!(receiver.try_recv().is_err());
+
+    // The source only applies to the next rejection.
+    audit.replied(b"503 Bad sequence of commands\r\n");
+    assert_eq!(
+        receiver.try_recv().unwrap().source(),
+        RejectionSource::Protocol
+    );
+
+    audit.command(None);
+    audit.replied(b"500 Line too long\r\n");
+    assert_eq!(receiver.try_recv().unwrap().command(), None);
+}
+
+#[test]
+fn test_redacted() {
+    let (audit, mut receiver) = audit();
+
+    audit.command(Some(b"AUTH PLAIN dGVzdAB0ZXN0AHRlc3Q=\r\n".as_slice()));
+    audit.replied(b"535 5.7.8 Authentication credentials invalid\r\n");
+    assert_eq!(
+        receiver.try_recv().unwrap().command(),
+        Some("AUTH PLAIN [redacted]")
+    );
+
+    audit.command(Some(b"AUTH LOGIN\r\n".as_slice()));
+    audit.replied(b"334 VXNlcm5hbWU6\r\n");
+    audit.command(Some(b"c21pdGg=\r\n".as_slice()));
+    audit.replied(b"535 5.7.8 Authentication credentials invalid\r\n");
+    assert_eq!(receiver.try_recv().unwrap().command(), Some("[redacted]"));
+
+    audit.command(Some(b"NOOP\r\n".as_slice()));
+    audit.replied(b"421 4.3.2 Service not available\r\n");
+    assert_eq!(receiver.try_recv().unwrap().command(), Some("NOOP"));
+}
+
+#[test]
+fn test_refused() {
+    let (sender, mut receiver) = broadcast::channel(8);
+    let peer = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 49152));
+
+    refused(
+        &sender,
+        peer,
+        "421 4.3.2 mx.example.com Too many connections, try again later",
+        RejectionSource::Limit("max_connections"),
+    );
+    let rejection = receiver.try_recv().unwrap();
+    assert_eq!(rejection.session_id(), None);
+    assert_eq!(rejection.code(), 421);
+    assert_eq!(
+        rejection.to_string(),
+        "192.0.2.1:49152 421 4.3.2 mx.example.com Too many connections, try again later \
+         (max_connections)"
+    );
+}
@@ -0,0 +1,317 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Scanning messages for viruses with [ClamAV](https://www.clamav.net/).
+//!
+//! See [`ClamAvPolicy`] and [`scan`].
+//!
+//! The message is streamed to `clamd` with the `INSTREAM` command, as described by the
+//! [documentation of ClamAV](https://docs.clamav.net/manual/Usage/Scanning.html#clamd).
+
+#[cfg(test)]
+mod test;
+
+use std::{io, time::Duration};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    handler::{Defer, Response},
+    message::Message,
+    ServerConfig,
+};
+
+/// The longest response that is read from `clamd`, in bytes.
+const MAX_RESPONSE: u64 = 4 * 1024;
+
+/// The most data that is sent to `clamd` in each chunk of `INSTREAM`, in bytes.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Where messages are scanned by ClamAV, and what is done with those that have viruses.
+///
+/// Each message is streamed to `clamd` once its data is received, before the end of the data is
+/// acknowledged. Messages with viruses are rejected with `550 5.7.1`, or accepted for the
+/// handler to quarantine with [`ClamAvAction::Quarantine`]. Every accepted message gets an
+/// `X-Virus-Status:` header (replacing any that the client sent) and keeps its [`ClamAvVerdict`]
+/// in [`Message::clamav`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::clamav::{ClamAvAction, ClamAvPolicy};
+/// #
+/// let policy = ClamAvPolicy::new("/run/clamav/clamd.ctl")
+///     .with_action(ClamAvAction::Quarantine)
+///     .with_defer_on_error(true);
+///
+/// assert_eq!(policy.address(), "/run/clamav/clamd.ctl");
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ClamAvPolicy {
+    /// The host and port of `clamd`, or the path of its Unix socket.
+    address: String,
+    /// How long to wait for a scan.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    timeout: Duration,
+    /// What is done with messages that have viruses.
+    action: ClamAvAction,
+    /// Whether messages are deferred when they cannot be scanned.
+    defer_on_error: bool,
+}
+
+impl ClamAvPolicy {
+    /// Create a new [`Self`] that scans messages with the `clamd` at `address`, a host and port
+    /// such as `127.0.0.1:3310`, or the absolute path of a Unix socket such as
+    /// `/run/clamav/clamd.ctl`.
+    #[must_use]
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Get the host and port of `clamd`, or the path of its Unix socket, `127.0.0.1:3310` by
+    /// default.
+    #[must_use]
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Get how long to wait for a scan, including connecting to `clamd`, 30 seconds by default.
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Get what is done with messages that have viruses, [`ClamAvAction::Reject`] by default.
+    #[must_use]
+    pub const fn action(&self) -> ClamAvAction {
+        self.action
+    }
+
+    /// Get whether messages are deferred with `451 4.3.0` when they cannot be scanned (failing
+    /// closed), or accepted without a verdict (failing open), which is the default.
+    #[must_use]
+    pub const fn defer_on_error(&self) -> bool {
+        self.defer_on_error
+    }
+
+    /// Get whether [`Self::address`] is the path of a Unix socket, rather than a host and port.
+    pub(crate) fn is_socket_path(&self) -> bool {
+        self.address.starts_with('/')
+    }
+
+    /// Set how long to wait for a scan. See [`Self::timeout`].
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set what is done with messages that have viruses. See [`Self::action`].
+    #[must_use]
+    pub const fn with_action(mut self, action: ClamAvAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Set whether messages are deferred when they cannot be scanned. See
+    /// [`Self::defer_on_error`].
+    #[must_use]
+    pub const fn with_defer_on_error(mut self, defer: bool) -> Self {
+        self.defer_on_error = defer;
+        self
+    }
+}
+
+impl Default for ClamAvPolicy {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:3310".to_owned(),
+            timeout: Duration::from_secs(30),
+            action: ClamAvAction::Reject,
+            defer_on_error: false,
+        }
+    }
+}
+
+/// What is done with a message that ClamAV found a virus in, see [`ClamAvPolicy`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ClamAvAction {
+    /// Reject the message.
+    #[default]
+    Reject,
+    /// Accept the message, marking it as infected for the handler to quarantine.
+    Quarantine,
+}
+
+/// The result of scanning a message with ClamAV, see [`scan`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClamAvVerdict {
+    /// The name of the virus that was found, or `None` if the message is clean.
+    virus: Option<String>,
+}
+
+impl ClamAvVerdict {
+    /// Get the name of the virus that was found, such as `Eicar-Signature`, or `None` if the
+    /// message is clean.
+    #[must_use]
+    pub fn virus(&self) -> Option<&str> {
+        self.virus.as_deref()
+    }
+
+    /// Get whether a virus was found in the message.
+    #[must_use]
+    pub const fn is_infected(&self) -> bool {
+        self.virus.is_some()
+    }
+
+    /// Get the value of the `X-Virus-Status:` header for the message, as `clamav-milter` formats
+    /// it.
+    #[must_use]
+    pub fn status(&self) -> String {
+        self.virus
+            .as_ref()
+            .map_or_else(|| "Clean".to_owned(), |virus| format!("Infected ({virus})"))
+    }
+}
+
+/// Scan `message` with the `clamd` of `policy`.
+///
+/// # Errors
+///
+/// - [`io::Error`] if `clamd` cannot be reached in time, or its response is not a valid result.
+pub async fn scan(policy: &ClamAvPolicy, message: &Message) -> io::Result<ClamAvVerdict> {
+    let response = tokio::time::timeout(policy.timeout, async {
+        #[cfg(unix)]
+        if policy.is_socket_path() {
+            let stream = tokio::net::UnixStream::connect(&policy.address).await?;
+            return instream(stream, message.data()).await;
+        }
+
+        instream(TcpStream::connect(&policy.address).await?, message.data()).await
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "clamd did not respond in time"))??;
+
+    parse(&response)
+}
+
+/// Stream `data` to `clamd` over `stream` with `INSTREAM`, returning its response.
+async fn instream(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    data: &[u8],
+) -> io::Result<Vec<u8>> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    for chunk in data.chunks(CHUNK_SIZE) {
+        let length = u32::try_from(chunk.len()).expect("chunks are smaller than 4 GiB");
+        stream.write_all(&length.to_be_bytes()).await?;
+        stream.write_all(chunk).await?;
+    }
+    stream.write_all(&0_u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE).read_to_end(&mut response).await?;
+
+    Ok(response)
+}
+
+/// Parse the `response` of `clamd` to `INSTREAM` into a [`ClamAvVerdict`].
+///
+/// # Errors
+///
+/// - [`io::Error`] if `response` is malformed or is an error.
+fn parse(response: &[u8]) -> io::Result<ClamAvVerdict> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed response from clamd");
+
+    let response = std::str::from_utf8(response)
+        .map_err(|_| malformed())?
+        .trim_end_matches(['\0', '\r', '\n']);
+    if response.ends_with(" ERROR") {
+        return Err(io::Error::other(format!("clamd responded with {response}")));
+    }
+
+    // `stream: OK` or `stream: Eicar-Signature FOUND`
+    let result = response
+        .strip_prefix("stream:")
+        .map(str::trim)
+        .ok_or_else(malformed)?;
+    if result == "OK" {
+        return Ok(ClamAvVerdict { virus: None });
+    }
+
+    let virus = result
+        .strip_suffix(" FOUND")
+        .map(str::trim)
+        .filter(|virus| !virus.is_empty())
+        .ok_or_else(malformed)?;
+
+    Ok(ClamAvVerdict {
+        virus: Some(virus.to_owned()),
+    })
+}
+
+/// Scan `message` if [`ServerConfig::clamav_policy`] is set, keeping the verdict in it and adding
+/// an `X-Virus-Status:` header to it.
+///
+/// # Errors
+///
+/// - The [`Response`] to reply with if the message is rejected or deferred.
+pub(crate) async fn apply(
+    config: &ServerConfig,
+    mut message: Message,
+) -> Result<Message, Response> {
+    let Some(policy) = config.clamav_policy() else {
+        return Ok(message);
+    };
+    let id = message.session().id();
+
+    let verdict = match scan(policy, &message).await {
+        Ok(verdict) => verdict,
+        Err(error) => {
+            println!("[{id}] Failed to scan message with ClamAV: {error}");
+            if policy.defer_on_error {
+                return Err(Defer::LocalError.response());
+            }
+            return Ok(message);
+        }
+    };
+    if let Some(virus) = &verdict.virus {
+        println!("[{id}] ClamAV found {virus} in message");
+        if policy.action == ClamAvAction::Reject {
+            return Err(Response::parse(&format!("550 5.7.1 Virus found: {virus}"))
+                .or_else(|_| Response::parse("550 5.7.1 Virus found"))
+                .expect("written in code as a valid reply"));
+        }
+    }
+
+    if let Err(error) = message.replace_header("X-Virus-Status", &verdict.status()) {
+        println!("[{id}] Failed to add X-Virus-Status header: {error}");
+    }
+
+    Ok(message.with_clamav(verdict))
+}
@@ -0,0 +1,50 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use super::*;
+
+#[test]
+fn test_parse() -> io::Result<()> {
+    // Tests that clean and infected results are told apart.
+    assert_eq!(parse(b"stream: OK\0")?.virus(), None);
+    let verdict = parse(b"stream: Win.Test.EICAR_HDB-1 FOUND\0")?;
+    assert_eq!(verdict.virus(), Some("Win.Test.EICAR_HDB-1"));
+    assert_eq!(verdict.status(), "Infected (Win.Test.EICAR_HDB-1)");
+    assert_eq!(parse(b"stream: OK\n")?.status(), "Clean");
+
+    // Tests that errors and malformed results are not taken as clean.
+    for response in [
+        &b"INSTREAM size limit exceeded. ERROR\0"[..],
+        b"",
+        b"stream: \0",
+        b"stream:  FOUND\0",
+        b"OK\0",
+        b"stream: \xff FOUND\0",
+    ] {
+        assert!(parse(response).is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_socket_path() {
+    assert!(ClamAvPolicy::new("/run/clamav/clamd.ctl").is_socket_path());
+    assert!(!ClamAvPolicy::default().is_socket_path());
+}
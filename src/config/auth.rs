@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Limits on failed authentication, past which clients that guess credentials are slowed down,
+//! disconnected, and banned.
+//!
+//! See [`AuthLimits`].
+
+use std::time::Duration;
+
+/// The most times that [`AuthLimits::failure_delay`] is doubled.
+const MAX_DOUBLINGS: u32 = 6;
+
+/// Limits on the failed `AUTH` attempts of each session and each client address.
+///
+/// The server does not implement `AUTH` itself, so these limits only apply to a handler that does
+/// in [`crate::SmtpHandler::on_command`]: an attempt fails when the handler replies to a command
+/// with `535`. Without such a handler, no attempt ever fails, and these limits do nothing.
+///
+/// After each failure, the next reply is delayed by [`Self::failure_delay`], doubled for each
+/// earlier failure of the session. Past [`Self::max_failures`] or [`Self::max_failure_rate`], the
+/// session is closed with `421`, and past [`Self::max_failure_rate`], connections from the address
+/// are also refused with `421` for [`Self::ban_duration`].
+///
+/// Each lockout is sent to [`crate::ServerHandle::rejections`] with the source `auth_limits`, so that
+/// tools like fail2ban can act on it.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use smtp_gateway::config::AuthLimits;
+/// #
+/// let limits = AuthLimits::unlimited()
+///     .with_max_failures(3)
+///     .with_failure_delay(Duration::from_secs(1))
+///     .with_max_failure_rate(10, Duration::from_mins(10))
+///     .with_ban_duration(Duration::from_hours(1));
+///
+/// assert_eq!(limits.failure_delay_after(3), Duration::from_secs(4));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct AuthLimits {
+    /// The maximum number of failed attempts in one session.
+    max_failures: Option<usize>,
+    /// How long to wait before replying to a client after its first failed attempt.
+    #[cfg_attr(feature = "serde", serde(with = "super::seconds"))]
+    failure_delay: Duration,
+    /// The maximum number of failed attempts from one client address in a period of time.
+    #[cfg_attr(feature = "serde", serde(with = "super::seconds::failure_rate"))]
+    max_failure_rate: Option<(usize, Duration)>,
+    /// How long connections from an address are refused after it exceeds `max_failure_rate`.
+    #[cfg_attr(feature = "serde", serde(with = "super::seconds"))]
+    ban_duration: Duration,
+}
+
+impl AuthLimits {
+    /// Create a new [`Self`] without any limits, delay, or bans.
+    #[must_use]
+    pub const fn unlimited() -> Self {
+        Self {
+            max_failures: None,
+            failure_delay: Duration::ZERO,
+            max_failure_rate: None,
+            ban_duration: Duration::ZERO,
+        }
+    }
+
+    /// Get the maximum number of failed attempts in one session, or `None` for no limit.
+    ///
+    /// The session is closed with `421` once the client fails this many times.
+    #[must_use]
+    pub const fn max_failures(&self) -> Option<usize> {
+        self.max_failures
+    }
+
+    /// Get how long to wait before replying to the next command after the first failed attempt of
+    /// a session. See [`Self::failure_delay_after`].
+    #[must_use]
+    pub const fn failure_delay(&self) -> Duration {
+        self.failure_delay
+    }
+
+    /// Get the maximum number of failed attempts from one client address in a period of time, or
+    /// `None` for no limit.
+    ///
+    /// Addresses are grouped as [`crate::ServerConfig::ipv6_prefix_length`] describes, and the
+    /// attempts of every session from them are counted.
+    #[must_use]
+    pub const fn max_failure_rate(&self) -> Option<(usize, Duration)> {
+        self.max_failure_rate
+    }
+
+    /// Get how long connections from an address are refused with `421` after it exceeds
+    /// [`Self::max_failure_rate`]. Without a ban, only the session that exceeded it is closed.
+    #[must_use]
+    pub const fn ban_duration(&self) -> Duration {
+        self.ban_duration
+    }
+
+    /// Get how long to wait before replying to the next command after `failures` failed attempts
+    /// in a session, which is [`Self::failure_delay`] doubled for each failure after the first, up
+    /// to 64 times as long.
+    #[must_use]
+    pub fn failure_delay_after(&self, failures: usize) -> Duration {
+        let doublings = u32::try_from(failures.saturating_sub(1))
+            .unwrap_or(u32::MAX)
+            .min(MAX_DOUBLINGS);
+
+        self.failure_delay.saturating_mul(1 << doublings)
+    }
+
+    /// Limit the number of failed attempts in one session. See [`Self::max_failures`].
+    #[must_use]
+    pub const fn with_max_failures(mut self, failures: usize) -> Self {
+        self.max_failures = Some(failures);
+        self
+    }
+
+    /// Wait before replying after failed attempts. See [`Self::failure_delay`].
+    #[must_use]
+    pub const fn with_failure_delay(mut self, delay: Duration) -> Self {
+        self.failure_delay = delay;
+        self
+    }
+
+    /// Limit the number of failed attempts from one client address in each `period`. See
+    /// [`Self::max_failure_rate`].
+    #[must_use]
+    pub const fn with_max_failure_rate(mut self, failures: usize, period: Duration) -> Self {
+        self.max_failure_rate = Some((failures, period));
+        self
+    }
+
+    /// Refuse connections from addresses that exceed [`Self::max_failure_rate`]. See
+    /// [`Self::ban_duration`].
+    #[must_use]
+    pub const fn with_ban_duration(mut self, duration: Duration) -> Self {
+        self.ban_duration = duration;
+        self
+    }
+}
@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Limits on the commands of each session, past which misbehaving clients are slowed down and
+//! disconnected.
+//!
+//! See [`CommandLimits`].
+
+use std::time::Duration;
+
+/// Limits on the commands of one session.
+///
+/// Syntax errors, including invalid arguments and overlong lines, and unrecognized commands are
+/// counted as errors until a command succeeds. Past [`Self::max_errors`] or
+/// [`Self::max_command_rate`], the session is closed with `421`.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use smtp_gateway::config::CommandLimits;
+/// #
+/// let limits = CommandLimits::unlimited()
+///     .with_max_errors(10)
+///     .with_error_delay(Duration::from_secs(1))
+///     .with_max_command_rate(100, Duration::from_mins(1));
+///
+/// assert_eq!(limits.max_errors(), Some(10));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct CommandLimits {
+    /// The maximum number of errors in a row.
+    max_errors: Option<usize>,
+    /// How long to wait before replying to a client that made an error in its last command.
+    #[cfg_attr(feature = "serde", serde(with = "super::seconds"))]
+    error_delay: Duration,
+    /// The maximum number of commands in a period of time.
+    #[cfg_attr(feature = "serde", serde(with = "super::seconds::command_rate"))]
+    max_command_rate: Option<(usize, Duration)>,
+}
+
+impl CommandLimits {
+    /// Create a new [`Self`] without any limits or delay.
+    #[must_use]
+    pub const fn unlimited() -> Self {
+        Self {
+            max_errors: None,
+            error_delay: Duration::ZERO,
+            max_command_rate: None,
+        }
+    }
+
+    /// Get the maximum number of errors in a row, or `None` for no limit.
+    ///
+    /// The session is closed with `421` once the client makes this many.
+    #[must_use]
+    pub const fn max_errors(&self) -> Option<usize> {
+        self.max_errors
+    }
+
+    /// Get how long to wait before replying to each command after the client made an error, to
+    /// slow down misbehaving clients.
+    #[must_use]
+    pub const fn error_delay(&self) -> Duration {
+        self.error_delay
+    }
+
+    /// Get the maximum number of commands in a period of time, or `None` for no limit.
+    ///
+    /// Every command line is counted, but not the lines of the data of a message.
+    #[must_use]
+    pub const fn max_command_rate(&self) -> Option<(usize, Duration)> {
+        self.max_command_rate
+    }
+
+    /// Limit the number of errors in a row. See [`Self::max_errors`].
+    #[must_use]
+    pub const fn with_max_errors(mut self, errors: usize) -> Self {
+        self.max_errors = Some(errors);
+        self
+    }
+
+    /// Wait before replying after errors. See [`Self::error_delay`].
+    #[must_use]
+    pub const fn with_error_delay(mut self, delay: Duration) -> Self {
+        self.error_delay = delay;
+        self
+    }
+
+    /// Limit the number of commands in each `period`. See [`Self::max_command_rate`].
+    #[must_use]
+    pub const fn with_max_command_rate(mut self, commands: usize, period: Duration) -> Self {
+        self.max_command_rate = Some((commands, period));
+        self
+    }
+}
@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The checks made on the identity that clients give in `HELO`.
+//!
+//! See [`HeloPolicy`].
+
+use std::fmt::Display;
+
+/// The checks made on the identity that clients give in `HELO` and `EHLO`, and what is done when
+/// each one fails.
+///
+/// Every check is skipped by default, as [RFC 5321 section
+/// 4.1.4](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.4) forbids refusing a message
+/// only because the identity could not be verified. Checks that fail with
+/// [`HeloAction::Annotate`] are kept in [`crate::session::SessionInfo::helo_failures`] and noted
+/// in the `Received:` header.
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::config::{HeloAction, HeloPolicy};
+/// #
+/// let policy = HeloPolicy::accept_all()
+///     .with_syntax(HeloAction::Reject)
+///     .with_resolves(HeloAction::Annotate);
+///
+/// assert_eq!(policy.matches_peer(), HeloAction::Accept);
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct HeloPolicy {
+    /// What to do when the identity is not a domain name or address literal.
+    syntax: HeloAction,
+    /// What to do when the domain name does not resolve.
+    resolves: HeloAction,
+    /// What to do when the address literal is not the address of the client.
+    matches_peer: HeloAction,
+}
+
+impl HeloPolicy {
+    /// Create a new [`Self`] that skips every check.
+    #[must_use]
+    pub const fn accept_all() -> Self {
+        Self {
+            syntax: HeloAction::Accept,
+            resolves: HeloAction::Accept,
+            matches_peer: HeloAction::Accept,
+        }
+    }
+
+    /// Get what is done when the identity is not a domain name or address literal, see
+    /// [`HeloCheck::Syntax`]. Rejected with `501`.
+    #[must_use]
+    pub const fn syntax(&self) -> HeloAction {
+        self.syntax
+    }
+
+    /// Get what is done when the domain name does not resolve to any address, see
+    /// [`HeloCheck::Resolves`]. Rejected with `550`.
+    #[must_use]
+    pub const fn resolves(&self) -> HeloAction {
+        self.resolves
+    }
+
+    /// Get what is done when the address literal is not the address of the client, see
+    /// [`HeloCheck::MatchesPeer`]. Rejected with `550`.
+    #[must_use]
+    pub const fn matches_peer(&self) -> HeloAction {
+        self.matches_peer
+    }
+
+    /// Get the action for `check`.
+    #[must_use]
+    pub const fn action(&self, check: HeloCheck) -> HeloAction {
+        match check {
+            HeloCheck::Syntax => self.syntax,
+            HeloCheck::Resolves => self.resolves,
+            HeloCheck::MatchesPeer => self.matches_peer,
+        }
+    }
+
+    /// Set what is done when the identity is not a domain name or address literal. See
+    /// [`Self::syntax`].
+    #[must_use]
+    pub const fn with_syntax(mut self, action: HeloAction) -> Self {
+        self.syntax = action;
+        self
+    }
+
+    /// Set what is done when the domain name does not resolve. See [`Self::resolves`].
+    #[must_use]
+    pub const fn with_resolves(mut self, action: HeloAction) -> Self {
+        self.resolves = action;
+        self
+    }
+
+    /// Set what is done when the address literal is not the address of the client. See
+    /// [`Self::matches_peer`].
+    #[must_use]
+    pub const fn with_matches_peer(mut self, action: HeloAction) -> Self {
+        self.matches_peer = action;
+        self
+    }
+}
+
+/// What is done when a check of [`HeloPolicy`] fails.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum HeloAction {
+    /// Skip the check.
+    #[default]
+    Accept,
+    /// Make the check and accept the identity either way, noting the failure.
+    Annotate,
+    /// Make the check and reject the identity if it fails, leaving the client to try again.
+    Reject,
+}
+
+/// A check of [`HeloPolicy`] that the identity given in `HELO` can fail.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HeloCheck {
+    /// The identity is a domain name or address literal.
+    ///
+    /// [RFC 5321 section 4.1.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.2).
+    Syntax,
+    /// The domain name resolves to at least one address. Address literals always pass.
+    Resolves,
+    /// The address literal is the address of the client. Domain names always pass.
+    MatchesPeer,
+}
+
+impl Display for HeloCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Syntax => "invalid syntax",
+            Self::Resolves => "does not resolve",
+            Self::MatchesPeer => "does not match the client address",
+        })
+    }
+}
@@ -0,0 +1,1704 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The configuration of the server, shared by every session.
+//!
+//! See [`ServerConfig`].
+
+mod auth;
+mod command;
+mod helo;
+mod network;
+mod null_sender;
+mod reload;
+mod reverse_dns;
+#[cfg(feature = "serde")]
+pub(crate) mod seconds;
+mod sender_domain;
+mod tarpit;
+#[cfg(test)]
+mod test;
+
+use std::{fmt::Display, net::IpAddr, time::Duration};
+
+pub use auth::AuthLimits;
+pub use command::CommandLimits;
+pub use helo::{HeloAction, HeloCheck, HeloPolicy};
+pub(crate) use network::mask;
+pub use network::{Network, PeerLimits};
+pub use null_sender::NullSenderPolicy;
+pub use reload::ConfigHandle;
+pub use reverse_dns::ReverseDnsPolicy;
+pub use sender_domain::SenderDomainPolicy;
+pub use tarpit::Tarpit;
+
+#[cfg(feature = "arc")]
+use crate::arc::ArcSealer;
+#[cfg(feature = "attachment")]
+use crate::attachment::AttachmentPolicy;
+#[cfg(feature = "clamav")]
+use crate::clamav::ClamAvPolicy;
+#[cfg(feature = "directory")]
+use crate::directory::{AddressVerifier, SharedAddressVerifier};
+#[cfg(feature = "dmarc")]
+use crate::dmarc::DmarcPolicy;
+#[cfg(feature = "dnsbl")]
+use crate::dnsbl::DnsblPolicy;
+#[cfg(feature = "filter")]
+use crate::filter::FilterRule;
+#[cfg(feature = "greylist")]
+use crate::greylist::GreylistPolicy;
+#[cfg(feature = "rspamd")]
+use crate::rspamd::RspamdPolicy;
+#[cfg(feature = "spamassassin")]
+use crate::spamassassin::SpamAssassinPolicy;
+#[cfg(feature = "spf")]
+use crate::spf::SpfPolicy;
+#[cfg(feature = "spool")]
+use crate::spool::Spool;
+#[cfg(feature = "tls")]
+use crate::tls::{TlsConfig, TlsPolicy};
+use crate::{
+    is_smtp_domain_name,
+    resolver::{Resolver, SharedResolver},
+    str::{max_lengths, SmtpString},
+    timeouts::Timeouts,
+    transcript::TranscriptTarget,
+};
+
+/// The configuration of the server: how it identifies itself, the limits that it holds clients to,
+/// and what it adds to received messages.
+///
+/// Created by [`Self::builder`], or with [`Default`] for the defaults of [`ServerConfigBuilder`].
+/// Given to [`crate::listen`] and the other ways to start a server, and shared by every session.
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::ServerConfig;
+/// #
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = ServerConfig::builder()
+///     .hostname("mx.example.com")
+///     .banner("ESMTP ready")
+///     .max_message_size(25 * 1024 * 1024)
+///     .build()?;
+///
+/// assert_eq!(config.hostname(), "mx.example.com");
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "ServerConfigBuilder", into = "ServerConfigBuilder")
+)]
+#[cfg_attr(
+    feature = "arc",
+    expect(
+        clippy::struct_excessive_bools,
+        reason = "each of the flags switches an independent feature"
+    )
+)]
+pub struct ServerConfig {
+    /// The domain name that the server identifies itself with.
+    hostname: String,
+    /// The text of the greeting after the domain name.
+    banner: SmtpString,
+    /// The text of the reply to `QUIT`.
+    quit_text: SmtpString,
+    /// The text of the reply that refuses a connection after the domain name.
+    refusal_text: SmtpString,
+    /// The text of the reply that closes a session early after the domain name.
+    unavailable_text: SmtpString,
+    /// The maximum size of the data of a message in bytes.
+    max_message_size: usize,
+    /// The maximum size of the header section of a message in bytes.
+    max_header_size: usize,
+    /// The maximum number of header fields in a message.
+    max_header_fields: usize,
+    /// The maximum length of a single header field in bytes, including its folded lines.
+    max_header_field_length: usize,
+    /// The maximum length of a command line in bytes, including its line ending.
+    max_command_line: usize,
+    /// The maximum length of a line of the data of a message in bytes, including its line ending.
+    max_text_line: usize,
+    /// The maximum number of recipients accepted in one mail transaction.
+    max_recipients: usize,
+    /// The maximum number of sessions at once, or `None` for no limit.
+    max_connections: Option<usize>,
+    /// What to do with connections past [`Self::max_connections`].
+    connection_overflow: ConnectionOverflow,
+    /// The limits on the connections from each client address.
+    peer_limits: PeerLimits,
+    /// The limits on the connections from each client address in particular networks.
+    network_limits: Vec<(Network, PeerLimits)>,
+    /// The length of the prefix that IPv6 client addresses are grouped by.
+    ipv6_prefix_length: u8,
+    /// What is done with connections from particular networks.
+    access_rules: Vec<(Network, Access)>,
+    /// What is done with connections from outside of the networks in `access_rules`.
+    default_access: Access,
+    /// The limits on the commands of each session.
+    command_limits: CommandLimits,
+    /// The limits on the failed authentication attempts of each session and client address.
+    auth_limits: AuthLimits,
+    /// The delays before the replies to suspicious clients.
+    tarpit: Tarpit,
+    /// How long to wait for the client to talk before greeting it.
+    greeting_delay: Duration,
+    /// Whether to reject clients that talk before they are greeted.
+    reject_early_talkers: bool,
+    /// The checks made on the identity that clients give in `HELO`.
+    helo_policy: HeloPolicy,
+    /// The limits on mail transactions with the null reverse-path.
+    null_sender_policy: NullSenderPolicy,
+    /// Whether the names of clients are looked up, and what is done with the results.
+    reverse_dns_policy: ReverseDnsPolicy,
+    /// Whether the domains of senders are checked for existence, and what is done with the
+    /// results.
+    sender_domain_policy: SenderDomainPolicy,
+    /// Whether SPF is checked, and what is done with the results.
+    #[cfg(feature = "spf")]
+    spf_policy: SpfPolicy,
+    /// What is done with messages that fail DMARC, or `None` if it is not evaluated.
+    #[cfg(feature = "dmarc")]
+    dmarc_policy: Option<DmarcPolicy>,
+    /// The DNS blocklists that clients are looked up in, or `None` if they are not looked up.
+    #[cfg(feature = "dnsbl")]
+    dnsbl_policy: Option<DnsblPolicy>,
+    /// Looks up whether recipients have a mailbox, or `None` if they are not looked up.
+    #[cfg(feature = "directory")]
+    address_verifier: Option<SharedAddressVerifier>,
+    /// How recipients are greylisted, or `None` if they are not.
+    #[cfg(feature = "greylist")]
+    greylist_policy: Option<GreylistPolicy>,
+    /// Where messages are scanned by ClamAV, or `None` if they are not.
+    #[cfg(feature = "clamav")]
+    clamav_policy: Option<ClamAvPolicy>,
+    /// Where messages are scanned by Rspamd, or `None` if they are not.
+    #[cfg(feature = "rspamd")]
+    rspamd_policy: Option<RspamdPolicy>,
+    /// Where messages are scored by SpamAssassin, or `None` if they are not.
+    #[cfg(feature = "spamassassin")]
+    spamassassin_policy: Option<SpamAssassinPolicy>,
+    /// Where accepted messages are kept until they are processed, or `None` if they are not.
+    #[cfg(feature = "spool")]
+    spool: Option<Spool>,
+    /// The rules that messages are checked against, in order.
+    #[cfg(feature = "filter")]
+    filter_rules: Vec<FilterRule>,
+    /// Which attachments are blocked, or `None` if they are not checked.
+    #[cfg(feature = "attachment")]
+    attachment_policy: Option<AttachmentPolicy>,
+    /// Whether the ARC of each message is validated.
+    #[cfg(feature = "arc")]
+    verify_arc: bool,
+    /// Seals each message on receipt, or `None` if messages are not sealed.
+    #[cfg(feature = "arc")]
+    arc_sealer: Option<ArcSealer>,
+    /// The resolver that DNS lookups are made with.
+    resolver: SharedResolver,
+    /// How sessions are encrypted after `STARTTLS`, or `None` if it is not offered.
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+    /// Whether `STARTTLS` is offered and required.
+    #[cfg(feature = "tls")]
+    tls_policy: TlsPolicy,
+    /// How long to wait on the client in each phase of the session.
+    timeouts: Timeouts,
+    /// Whether to add a `Return-Path:` header to received messages.
+    stamp_return_path: bool,
+    /// Whether to add a `Message-ID:` header to received messages that lack one.
+    stamp_message_id: bool,
+    /// How the opening and closing of each session is logged.
+    log_format: LogFormat,
+    /// Where the transcript of each session is kept, or `None` if it is not recorded.
+    transcripts: Option<TranscriptTarget>,
+}
+
+impl ServerConfig {
+    /// Create a [`ServerConfigBuilder`] to construct a [`Self`].
+    pub fn builder() -> ServerConfigBuilder {
+        ServerConfigBuilder::new()
+    }
+
+    /// Get the domain name that the server identifies itself with, such as in the greeting and in
+    /// the `Received:` header.
+    #[must_use]
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// Get the text of the greeting after the domain name, such as `ESMTP ready`.
+    #[must_use]
+    pub const fn banner(&self) -> &SmtpString {
+        &self.banner
+    }
+
+    /// Get the text of the `221` reply to `QUIT`, such as `Bye`.
+    #[must_use]
+    pub const fn quit_text(&self) -> &SmtpString {
+        &self.quit_text
+    }
+
+    /// Get the text after the domain name of the `554` reply that refuses a connection in place of
+    /// the greeting, such as `No SMTP service here`. See [`crate::handler::ConnectDecision`].
+    #[must_use]
+    pub const fn refusal_text(&self) -> &SmtpString {
+        &self.refusal_text
+    }
+
+    /// Get the text after the domain name of the `421` reply that closes a session early, such as
+    /// when a [`crate::handler::HandlerError`] closes the session.
+    #[must_use]
+    pub const fn unavailable_text(&self) -> &SmtpString {
+        &self.unavailable_text
+    }
+
+    /// Get the maximum size of the data of a message in bytes, as counted by
+    /// [`crate::message::Size`].
+    ///
+    /// This is advertised to clients with the `SIZE` extension in reply to `EHLO`. A message that
+    /// is declared to be larger in `MAIL` is refused there, and one that turns out to be larger is
+    /// read to its end and discarded, then refused with `552`. RFC 5321 requires at least 64,000
+    /// bytes, see [RFC 5321 section
+    /// 4.5.3.1.7](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.1.7).
+    #[must_use]
+    pub const fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Get the maximum size of the header section of a message in bytes, as counted by
+    /// [`crate::message::Size`].
+    #[must_use]
+    pub const fn max_header_size(&self) -> usize {
+        self.max_header_size
+    }
+
+    /// Get the maximum number of header fields in a message.
+    #[must_use]
+    pub const fn max_header_fields(&self) -> usize {
+        self.max_header_fields
+    }
+
+    /// Get the maximum length of a single header field in bytes, including its folded lines.
+    #[must_use]
+    pub const fn max_header_field_length(&self) -> usize {
+        self.max_header_field_length
+    }
+
+    /// Get the maximum length of a command line in bytes, including its line ending.
+    ///
+    /// Longer lines are read to their end and discarded, then replied to with `500`. Defaults to
+    /// [`max_lengths::COMMAND_LINE`].
+    #[must_use]
+    pub const fn max_command_line(&self) -> usize {
+        self.max_command_line
+    }
+
+    /// Get the maximum length of a line of the data of a message in bytes, including its line
+    /// ending.
+    ///
+    /// A message with a longer line is read to its end and discarded, then refused with `552`.
+    /// Defaults to [`max_lengths::TEXT_LINE`].
+    #[must_use]
+    pub const fn max_text_line(&self) -> usize {
+        self.max_text_line
+    }
+
+    /// Get the maximum number of recipients accepted in one mail transaction, past which
+    /// recipients are deferred.
+    #[must_use]
+    pub const fn max_recipients(&self) -> usize {
+        self.max_recipients
+    }
+
+    /// Get the maximum number of sessions at once, or `None` for no limit.
+    ///
+    /// Sessions that are waiting out a [`crate::handler::ConnectDecision::Tarpit`] are counted.
+    /// Connections past the limit are handled according to [`Self::connection_overflow`].
+    #[must_use]
+    pub const fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    /// Get what is done with connections past [`Self::max_connections`].
+    #[must_use]
+    pub const fn connection_overflow(&self) -> ConnectionOverflow {
+        self.connection_overflow
+    }
+
+    /// Get the limits on the connections from each client address, outside of the networks in
+    /// [`Self::network_limits`].
+    #[must_use]
+    pub const fn peer_limits(&self) -> PeerLimits {
+        self.peer_limits
+    }
+
+    /// Get the limits on the connections from each client address in particular networks, such as
+    /// trusted relays. The first network that contains an address decides its limits.
+    #[must_use]
+    pub fn network_limits(&self) -> &[(Network, PeerLimits)] {
+        &self.network_limits
+    }
+
+    /// Get the length of the prefix that IPv6 client addresses are grouped by when counting their
+    /// connections, `64` by default.
+    #[must_use]
+    pub const fn ipv6_prefix_length(&self) -> u8 {
+        self.ipv6_prefix_length
+    }
+
+    /// Get the checks made on the identity that clients give in `HELO` and `EHLO`, none by
+    /// default.
+    #[must_use]
+    pub const fn helo_policy(&self) -> HeloPolicy {
+        self.helo_policy
+    }
+
+    /// Get the limits on mail transactions with the null reverse-path (`MAIL FROM:<>`), none by
+    /// default.
+    #[must_use]
+    pub const fn null_sender_policy(&self) -> NullSenderPolicy {
+        self.null_sender_policy
+    }
+
+    /// Get whether the address of each client is checked for a name that resolves back to it when
+    /// it connects, and what is done with the result. Defaults to [`ReverseDnsPolicy::Off`].
+    #[must_use]
+    pub const fn reverse_dns_policy(&self) -> ReverseDnsPolicy {
+        self.reverse_dns_policy
+    }
+
+    /// Get whether the domain of the reverse-path given in `MAIL FROM` is checked for records
+    /// that show it exists, and what is done when it does not. Defaults to
+    /// [`SenderDomainPolicy::Off`].
+    #[must_use]
+    pub const fn sender_domain_policy(&self) -> SenderDomainPolicy {
+        self.sender_domain_policy
+    }
+
+    /// Get whether SPF is checked for the identities that clients give in `HELO` and `MAIL FROM`,
+    /// and what is done with the results. Defaults to [`SpfPolicy::Off`].
+    #[cfg(feature = "spf")]
+    #[must_use]
+    pub const fn spf_policy(&self) -> SpfPolicy {
+        self.spf_policy
+    }
+
+    /// Get what is done with messages that fail DMARC, or `None` if it is not evaluated, which is
+    /// the default.
+    ///
+    /// DMARC is evaluated for the domain in the `From:` header of each message, after its data is
+    /// received. Only the results of SPF are used, so [`Self::spf_policy`] should be set too.
+    #[cfg(feature = "dmarc")]
+    #[must_use]
+    pub const fn dmarc_policy(&self) -> Option<DmarcPolicy> {
+        self.dmarc_policy
+    }
+
+    /// Get the DNS blocklists that the address of each client is looked up in when it connects,
+    /// and what is done when it is listed, or `None` if they are not looked up, which is the
+    /// default.
+    #[cfg(feature = "dnsbl")]
+    #[must_use]
+    pub const fn dnsbl_policy(&self) -> Option<&DnsblPolicy> {
+        self.dnsbl_policy.as_ref()
+    }
+
+    /// Get what looks up whether recipients have a mailbox, or `None` if every recipient that the
+    /// handler accepts is, which is the default.
+    ///
+    /// Recipients that do not have a mailbox are rejected with `550 5.1.1`, and those whose lookup
+    /// fails are deferred with `451 4.3.0`. `postmaster` is never looked up. It also answers
+    /// `VRFY`, which is otherwise not implemented, and can be refused with
+    /// [`crate::SmtpHandler::on_command`] to keep addresses from being harvested.
+    #[cfg(feature = "directory")]
+    #[must_use]
+    pub const fn address_verifier(&self) -> Option<&SharedAddressVerifier> {
+        self.address_verifier.as_ref()
+    }
+
+    /// Get how recipients are greylisted, deferring them until the client tries again if it has
+    /// not sent mail from the same sender to them before, or `None` if they are not, which is the
+    /// default.
+    #[cfg(feature = "greylist")]
+    #[must_use]
+    pub const fn greylist_policy(&self) -> Option<&GreylistPolicy> {
+        self.greylist_policy.as_ref()
+    }
+
+    /// Get where each message is scanned by ClamAV before it is handed to the handler, which
+    /// rejects or quarantines messages with viruses, or `None` if messages are not scanned, which
+    /// is the default.
+    #[cfg(feature = "clamav")]
+    #[must_use]
+    pub const fn clamav_policy(&self) -> Option<&ClamAvPolicy> {
+        self.clamav_policy.as_ref()
+    }
+
+    /// Get where each message is scanned by Rspamd before it is handed to the handler, which
+    /// rejects, defers, or marks it as spam as Rspamd recommends, or `None` if messages are not
+    /// scanned, which is the default.
+    #[cfg(feature = "rspamd")]
+    #[must_use]
+    pub const fn rspamd_policy(&self) -> Option<&RspamdPolicy> {
+        self.rspamd_policy.as_ref()
+    }
+
+    /// Get where each message is scored by SpamAssassin before it is handed to the handler, which
+    /// rejects or tags it by the thresholds of the policy, or `None` if messages are not scored,
+    /// which is the default.
+    #[cfg(feature = "spamassassin")]
+    #[must_use]
+    pub const fn spamassassin_policy(&self) -> Option<&SpamAssassinPolicy> {
+        self.spamassassin_policy.as_ref()
+    }
+
+    /// Get where accepted messages are kept on disk until the consumer has processed them, or
+    /// `None` if they are not, which is the default.
+    ///
+    /// Each message is written to the spool before it is handed to the handler, and a message that
+    /// cannot be written is deferred with `451 4.3.0`. See [`Spool`].
+    #[cfg(feature = "spool")]
+    #[must_use]
+    pub const fn spool(&self) -> Option<&Spool> {
+        self.spool.as_ref()
+    }
+
+    /// Get the rules that each message is checked against in order before it is handed to the
+    /// handler, which may reject, defer, tag, or route it, empty by default.
+    #[cfg(feature = "filter")]
+    #[must_use]
+    pub fn filter_rules(&self) -> &[FilterRule] {
+        &self.filter_rules
+    }
+
+    /// Get which attachments of each message are blocked before it is handed to the handler,
+    /// which may differ by the domain of each recipient, or `None` if attachments are not checked,
+    /// which is the default.
+    #[cfg(feature = "attachment")]
+    #[must_use]
+    pub const fn attachment_policy(&self) -> Option<&AttachmentPolicy> {
+        self.attachment_policy.as_ref()
+    }
+
+    /// Get whether the Authenticated Received Chain of each message is validated, keeping the
+    /// result in [`Message::arc`]. Defaults to `false`, though it is validated anyway when
+    /// [`Self::arc_sealer`] is set.
+    #[cfg(feature = "arc")]
+    #[must_use]
+    pub const fn verify_arc(&self) -> bool {
+        self.verify_arc
+    }
+
+    /// Get what seals each message with an ARC set on receipt, after its ARC is validated, or
+    /// `None` if messages are not sealed, which is the default.
+    #[cfg(feature = "arc")]
+    #[must_use]
+    pub const fn arc_sealer(&self) -> Option<&ArcSealer> {
+        self.arc_sealer.as_ref()
+    }
+
+    /// Get the resolver that every DNS lookup is made with, such as for
+    /// [`HeloCheck::Resolves`]. Defaults to [`crate::resolver::SystemResolver`].
+    #[must_use]
+    pub const fn resolver(&self) -> &SharedResolver {
+        &self.resolver
+    }
+
+    /// Get how sessions are encrypted when clients ask for it with `STARTTLS`, or `None` if
+    /// `STARTTLS` is not offered, which is the default.
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub const fn tls(&self) -> Option<&TlsConfig> {
+        self.tls.as_ref()
+    }
+
+    /// Get whether `STARTTLS` is offered, and whether clients must use it before sending mail.
+    /// Defaults to [`TlsPolicy::Opportunistic`].
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub const fn tls_policy(&self) -> TlsPolicy {
+        self.tls_policy
+    }
+
+    /// Get whether `STARTTLS` is offered, which is when [`Self::tls`] is set and
+    /// [`Self::tls_policy`] is not [`TlsPolicy::Off`].
+    #[cfg(feature = "tls")]
+    pub(crate) fn offers_tls(&self) -> bool {
+        self.tls.is_some() && self.tls_policy != TlsPolicy::Off
+    }
+
+    /// Get what is done with connections from particular networks, before anything else is done
+    /// with them. The first network that contains an address decides its access.
+    #[must_use]
+    pub fn access_rules(&self) -> &[(Network, Access)] {
+        &self.access_rules
+    }
+
+    /// Get what is done with connections from outside of the networks in [`Self::access_rules`].
+    /// Defaults to [`Access::Accept`].
+    #[must_use]
+    pub const fn default_access(&self) -> Access {
+        self.default_access
+    }
+
+    /// Get what is done with connections from `address`, from [`Self::access_rules`] or else
+    /// [`Self::default_access`].
+    #[must_use]
+    pub fn access_for(&self, address: IpAddr) -> Access {
+        self.access_rules
+            .iter()
+            .find(|(network, _)| network.contains(address))
+            .map_or(self.default_access, |&(_, access)| access)
+    }
+
+    /// Get the limits on the connections from `address`, from [`Self::network_limits`] or else
+    /// [`Self::peer_limits`].
+    #[must_use]
+    pub fn limits_for(&self, address: IpAddr) -> PeerLimits {
+        self.network_limits
+            .iter()
+            .find(|(network, _)| network.contains(address))
+            .map_or(self.peer_limits, |&(_, limits)| limits)
+    }
+
+    /// Get the limits on the commands of each session, past which the client is slowed down or
+    /// disconnected. Unlimited by default.
+    #[must_use]
+    pub const fn command_limits(&self) -> CommandLimits {
+        self.command_limits
+    }
+
+    /// Get the limits on the failed `AUTH` attempts of each session and each client address, past
+    /// which the client is slowed down, disconnected, or banned. Unlimited by default.
+    #[must_use]
+    pub const fn auth_limits(&self) -> AuthLimits {
+        self.auth_limits
+    }
+
+    /// Get the delays before the replies to clients that were flagged as suspicious, see
+    /// [`crate::handler::SessionContext::tarpit`].
+    #[must_use]
+    pub const fn tarpit(&self) -> Tarpit {
+        self.tarpit
+    }
+
+    /// Get how long to wait before greeting each client, zero by default.
+    ///
+    /// Clients that send anything during the wait are noted as early talkers, see
+    /// [`crate::session::SessionInfo::is_early_talker`]. Legitimate clients wait for the greeting
+    /// per [RFC 5321 section 4.3.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.3.1), so
+    /// talking early is a strong sign of spam.
+    #[must_use]
+    pub const fn greeting_delay(&self) -> Duration {
+        self.greeting_delay
+    }
+
+    /// Get whether early talkers are rejected with `554` in place of the greeting, instead of only
+    /// being noted. See [`Self::greeting_delay`].
+    #[must_use]
+    pub const fn reject_early_talkers(&self) -> bool {
+        self.reject_early_talkers
+    }
+
+    /// Get how long the server waits on the client in each phase of the session.
+    #[must_use]
+    pub const fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    /// Get whether a `Return-Path:` header is added to received messages, for when the server is
+    /// the final delivery hop rather than a relay. See [`crate::message::trace::return_path`].
+    #[must_use]
+    pub const fn stamp_return_path(&self) -> bool {
+        self.stamp_return_path
+    }
+
+    /// Get whether a `Message-ID:` header is added to received messages that lack one. See
+    /// [`crate::message::id::generate`].
+    ///
+    /// Streamed messages are not stamped, as their header section has not been received when they
+    /// are handed off.
+    #[must_use]
+    pub const fn stamp_message_id(&self) -> bool {
+        self.stamp_message_id
+    }
+
+    /// Get how the opening and closing of each session is logged.
+    #[must_use]
+    pub const fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
+    /// Get where the transcript of each session is kept, or `None` if it is not recorded, which is
+    /// the default.
+    ///
+    /// A transcript holds every line sent and received in a session, with credentials sent for
+    /// `AUTH` redacted. Recording one is meant for debugging, as it costs a copy of every line.
+    /// See [`crate::transcript`].
+    #[must_use]
+    pub const fn transcripts(&self) -> Option<&TranscriptTarget> {
+        self.transcripts.as_ref()
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfigBuilder::new()
+            .build()
+            .expect("the defaults are valid")
+    }
+}
+
+/// Builds a [`ServerConfig`], validating it once it is built.
+///
+/// Created by [`ServerConfig::builder`], or from an existing [`ServerConfig`] to change it.
+///
+/// With the `serde` feature, this and [`ServerConfig`] can be deserialized from configuration
+/// files, with every missing field left at its default and every [`ServerConfig`] validated as if
+/// it were built. Durations are given in seconds.
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::{config::ServerConfigBuilder, ServerConfig};
+/// #
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = ServerConfig::builder().hostname("mx.example.com").build()?;
+/// let changed = ServerConfigBuilder::from(config).banner("ESMTP ready").build()?;
+///
+/// assert_eq!(changed.hostname(), "mx.example.com");
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+#[must_use]
+#[cfg_attr(
+    feature = "arc",
+    expect(
+        clippy::struct_excessive_bools,
+        reason = "each of the flags switches an independent feature"
+    )
+)]
+pub struct ServerConfigBuilder {
+    /// The domain name that the server identifies itself with.
+    hostname: String,
+    /// The text of the greeting after the domain name.
+    banner: String,
+    /// The text of the reply to `QUIT`.
+    quit_text: String,
+    /// The text of the reply that refuses a connection after the domain name.
+    refusal_text: String,
+    /// The text of the reply that closes a session early after the domain name.
+    unavailable_text: String,
+    /// The maximum size of the data of a message in bytes.
+    max_message_size: usize,
+    /// The maximum size of the header section of a message in bytes.
+    max_header_size: usize,
+    /// The maximum number of header fields in a message.
+    max_header_fields: usize,
+    /// The maximum length of a single header field in bytes, including its folded lines.
+    max_header_field_length: usize,
+    /// The maximum length of a command line in bytes, including its line ending.
+    max_command_line: usize,
+    /// The maximum length of a line of the data of a message in bytes, including its line ending.
+    max_text_line: usize,
+    /// The maximum number of recipients accepted in one mail transaction.
+    max_recipients: usize,
+    /// The maximum number of sessions at once, or `None` for no limit.
+    max_connections: Option<usize>,
+    /// What to do with connections past [`Self::max_connections`].
+    connection_overflow: ConnectionOverflow,
+    /// The limits on the connections from each client address.
+    peer_limits: PeerLimits,
+    /// The limits on the connections from each client address in particular networks.
+    network_limits: Vec<(Network, PeerLimits)>,
+    /// The length of the prefix that IPv6 client addresses are grouped by.
+    ipv6_prefix_length: u8,
+    /// What is done with connections from particular networks.
+    access_rules: Vec<(Network, Access)>,
+    /// What is done with connections from outside of the networks in `access_rules`.
+    default_access: Access,
+    /// The limits on the commands of each session.
+    command_limits: CommandLimits,
+    /// The limits on the failed authentication attempts of each session and client address.
+    auth_limits: AuthLimits,
+    /// The delays before the replies to suspicious clients.
+    tarpit: Tarpit,
+    /// How long to wait for the client to talk before greeting it.
+    #[cfg_attr(feature = "serde", serde(with = "seconds"))]
+    greeting_delay: Duration,
+    /// Whether to reject clients that talk before they are greeted.
+    reject_early_talkers: bool,
+    /// The checks made on the identity that clients give in `HELO`.
+    helo_policy: HeloPolicy,
+    /// The limits on mail transactions with the null reverse-path.
+    null_sender_policy: NullSenderPolicy,
+    /// Whether the names of clients are looked up, and what is done with the results.
+    reverse_dns_policy: ReverseDnsPolicy,
+    /// Whether the domains of senders are checked for existence, and what is done with the
+    /// results.
+    sender_domain_policy: SenderDomainPolicy,
+    /// Whether SPF is checked, and what is done with the results.
+    #[cfg(feature = "spf")]
+    spf_policy: SpfPolicy,
+    /// What is done with messages that fail DMARC, or `None` if it is not evaluated.
+    #[cfg(feature = "dmarc")]
+    dmarc_policy: Option<DmarcPolicy>,
+    /// The DNS blocklists that clients are looked up in, or `None` if they are not looked up.
+    #[cfg(feature = "dnsbl")]
+    dnsbl_policy: Option<DnsblPolicy>,
+    /// Looks up whether recipients have a mailbox.
+    #[cfg(feature = "directory")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    address_verifier: Option<SharedAddressVerifier>,
+    /// How recipients are greylisted, or `None` if they are not.
+    #[cfg(feature = "greylist")]
+    greylist_policy: Option<GreylistPolicy>,
+    /// Where messages are scanned by ClamAV, or `None` if they are not.
+    #[cfg(feature = "clamav")]
+    clamav_policy: Option<ClamAvPolicy>,
+    /// Where messages are scanned by Rspamd, or `None` if they are not.
+    #[cfg(feature = "rspamd")]
+    rspamd_policy: Option<RspamdPolicy>,
+    /// Where messages are scored by SpamAssassin, or `None` if they are not.
+    #[cfg(feature = "spamassassin")]
+    spamassassin_policy: Option<SpamAssassinPolicy>,
+    /// Where accepted messages are kept until they are processed.
+    #[cfg(feature = "spool")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    spool: Option<Spool>,
+    /// The rules that messages are checked against, in order.
+    #[cfg(feature = "filter")]
+    filter_rules: Vec<FilterRule>,
+    /// Which attachments are blocked, or `None` if they are not checked.
+    #[cfg(feature = "attachment")]
+    attachment_policy: Option<AttachmentPolicy>,
+    /// Whether the ARC of each message is validated.
+    #[cfg(feature = "arc")]
+    verify_arc: bool,
+    /// Seals each message on receipt.
+    #[cfg(feature = "arc")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    arc_sealer: Option<ArcSealer>,
+    /// The resolver that DNS lookups are made with.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    resolver: SharedResolver,
+    /// How sessions are encrypted after `STARTTLS`.
+    #[cfg(feature = "tls")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    tls: Option<TlsConfig>,
+    /// Whether `STARTTLS` is offered and required.
+    #[cfg(feature = "tls")]
+    tls_policy: TlsPolicy,
+    /// How long to wait on the client in each phase of the session.
+    timeouts: Timeouts,
+    /// Whether to add a `Return-Path:` header to received messages.
+    stamp_return_path: bool,
+    /// Whether to add a `Message-ID:` header to received messages that lack one.
+    stamp_message_id: bool,
+    /// How the opening and closing of each session is logged.
+    log_format: LogFormat,
+    /// Where the transcript of each session is kept, or `None` if it is not recorded.
+    transcripts: Option<TranscriptTarget>,
+}
+
+impl ServerConfigBuilder {
+    /// Create a new [`Self`] with the defaults.
+    ///
+    /// The hostname defaults to `localhost`, which should be replaced with the domain name of the
+    /// server. Messages default to at most 10 MiB with a 256 KiB header section of 1000 fields of
+    /// 64 KiB each, and transactions default to 100 recipients, the minimum that [RFC 5321 section
+    /// 4.5.3.1.8](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.1.8) allows. Timeouts
+    /// default to [`Timeouts::rfc5321`]. No headers are added to messages other than `Received:`.
+    pub fn new() -> Self {
+        Self {
+            hostname: "localhost".to_owned(),
+            banner: "ESMTP service ready".to_owned(),
+            quit_text: "Bye".to_owned(),
+            refusal_text: "No SMTP service here".to_owned(),
+            unavailable_text: "Service not available, closing transmission channel".to_owned(),
+            max_message_size: 10 * 1024 * 1024,
+            max_header_size: 256 * 1024,
+            max_header_fields: 1000,
+            max_header_field_length: 64 * 1024,
+            max_command_line: max_lengths::COMMAND_LINE,
+            max_text_line: max_lengths::TEXT_LINE,
+            max_recipients: 100,
+            max_connections: None,
+            connection_overflow: ConnectionOverflow::Refuse,
+            peer_limits: PeerLimits::unlimited(),
+            network_limits: Vec::new(),
+            ipv6_prefix_length: 64,
+            access_rules: Vec::new(),
+            default_access: Access::Accept,
+            command_limits: CommandLimits::unlimited(),
+            auth_limits: AuthLimits::unlimited(),
+            tarpit: Tarpit::default(),
+            greeting_delay: Duration::ZERO,
+            reject_early_talkers: false,
+            helo_policy: HeloPolicy::accept_all(),
+            null_sender_policy: NullSenderPolicy::unlimited(),
+            reverse_dns_policy: ReverseDnsPolicy::Off,
+            sender_domain_policy: SenderDomainPolicy::Off,
+            #[cfg(feature = "spf")]
+            spf_policy: SpfPolicy::Off,
+            #[cfg(feature = "dmarc")]
+            dmarc_policy: None,
+            #[cfg(feature = "dnsbl")]
+            dnsbl_policy: None,
+            #[cfg(feature = "directory")]
+            address_verifier: None,
+            #[cfg(feature = "greylist")]
+            greylist_policy: None,
+            #[cfg(feature = "clamav")]
+            clamav_policy: None,
+            #[cfg(feature = "rspamd")]
+            rspamd_policy: None,
+            #[cfg(feature = "spamassassin")]
+            spamassassin_policy: None,
+            #[cfg(feature = "spool")]
+            spool: None,
+            #[cfg(feature = "filter")]
+            filter_rules: Vec::new(),
+            #[cfg(feature = "attachment")]
+            attachment_policy: None,
+            #[cfg(feature = "arc")]
+            verify_arc: false,
+            #[cfg(feature = "arc")]
+            arc_sealer: None,
+            resolver: SharedResolver::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "tls")]
+            tls_policy: TlsPolicy::Opportunistic,
+            timeouts: Timeouts::rfc5321(),
+            stamp_return_path: false,
+            stamp_message_id: false,
+            log_format: LogFormat::Text,
+            transcripts: None,
+        }
+    }
+
+    /// Set the domain name that the server identifies itself with. See [`ServerConfig::hostname`].
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = hostname.into();
+        self
+    }
+
+    /// Set the text of the greeting after the domain name. See [`ServerConfig::banner`].
+    pub fn banner(mut self, banner: impl Into<String>) -> Self {
+        self.banner = banner.into();
+        self
+    }
+
+    /// Set the text of the reply to `QUIT`. See [`ServerConfig::quit_text`].
+    pub fn quit_text(mut self, text: impl Into<String>) -> Self {
+        self.quit_text = text.into();
+        self
+    }
+
+    /// Set the text of the reply that refuses a connection. See [`ServerConfig::refusal_text`].
+    pub fn refusal_text(mut self, text: impl Into<String>) -> Self {
+        self.refusal_text = text.into();
+        self
+    }
+
+    /// Set the text of the reply that closes a session early. See
+    /// [`ServerConfig::unavailable_text`].
+    pub fn unavailable_text(mut self, text: impl Into<String>) -> Self {
+        self.unavailable_text = text.into();
+        self
+    }
+
+    /// Set the maximum size of the data of a message in bytes. See
+    /// [`ServerConfig::max_message_size`].
+    pub const fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Set the maximum size of the header section of a message in bytes. See
+    /// [`ServerConfig::max_header_size`].
+    pub const fn max_header_size(mut self, size: usize) -> Self {
+        self.max_header_size = size;
+        self
+    }
+
+    /// Set the maximum number of header fields in a message. See
+    /// [`ServerConfig::max_header_fields`].
+    pub const fn max_header_fields(mut self, fields: usize) -> Self {
+        self.max_header_fields = fields;
+        self
+    }
+
+    /// Set the maximum length of a single header field in bytes. See
+    /// [`ServerConfig::max_header_field_length`].
+    pub const fn max_header_field_length(mut self, length: usize) -> Self {
+        self.max_header_field_length = length;
+        self
+    }
+
+    /// Set the maximum length of a command line in bytes. See [`ServerConfig::max_command_line`].
+    pub const fn max_command_line(mut self, length: usize) -> Self {
+        self.max_command_line = length;
+        self
+    }
+
+    /// Set the maximum length of a line of the data of a message in bytes. See
+    /// [`ServerConfig::max_text_line`].
+    pub const fn max_text_line(mut self, length: usize) -> Self {
+        self.max_text_line = length;
+        self
+    }
+
+    /// Set the maximum number of recipients accepted in one mail transaction. See
+    /// [`ServerConfig::max_recipients`].
+    pub const fn max_recipients(mut self, recipients: usize) -> Self {
+        self.max_recipients = recipients;
+        self
+    }
+
+    /// Set the maximum number of sessions at once. See [`ServerConfig::max_connections`].
+    pub const fn max_connections(mut self, connections: usize) -> Self {
+        self.max_connections = Some(connections);
+        self
+    }
+
+    /// Set what is done with connections past the maximum. See
+    /// [`ServerConfig::connection_overflow`].
+    pub const fn connection_overflow(mut self, overflow: ConnectionOverflow) -> Self {
+        self.connection_overflow = overflow;
+        self
+    }
+
+    /// Set the limits on the connections from each client address. See
+    /// [`ServerConfig::peer_limits`].
+    pub const fn peer_limits(mut self, limits: PeerLimits) -> Self {
+        self.peer_limits = limits;
+        self
+    }
+
+    /// Add limits on the connections from each client address in `network`, after the networks
+    /// that were already added. See [`ServerConfig::network_limits`].
+    pub fn network_limits(mut self, network: Network, limits: PeerLimits) -> Self {
+        self.network_limits.push((network, limits));
+        self
+    }
+
+    /// Decide what is done with connections from `network`, after the networks that were already
+    /// added. See [`ServerConfig::access_rules`].
+    pub fn access(mut self, network: Network, access: Access) -> Self {
+        self.access_rules.push((network, access));
+        self
+    }
+
+    /// Set what is done with connections from outside of the networks given to [`Self::access`],
+    /// such as [`Access::Reject`] to only allow particular networks. See
+    /// [`ServerConfig::default_access`].
+    pub const fn default_access(mut self, access: Access) -> Self {
+        self.default_access = access;
+        self
+    }
+
+    /// Set the limits on the commands of each session. See [`ServerConfig::command_limits`].
+    pub const fn command_limits(mut self, limits: CommandLimits) -> Self {
+        self.command_limits = limits;
+        self
+    }
+
+    /// Set the limits on failed authentication attempts. See [`ServerConfig::auth_limits`].
+    pub const fn auth_limits(mut self, limits: AuthLimits) -> Self {
+        self.auth_limits = limits;
+        self
+    }
+
+    /// Set the delays before the replies to suspicious clients. See [`ServerConfig::tarpit`].
+    pub const fn tarpit(mut self, tarpit: Tarpit) -> Self {
+        self.tarpit = tarpit;
+        self
+    }
+
+    /// Set how long to wait before greeting each client. See [`ServerConfig::greeting_delay`].
+    pub const fn greeting_delay(mut self, delay: Duration) -> Self {
+        self.greeting_delay = delay;
+        self
+    }
+
+    /// Set whether early talkers are rejected. See [`ServerConfig::reject_early_talkers`].
+    pub const fn reject_early_talkers(mut self, reject: bool) -> Self {
+        self.reject_early_talkers = reject;
+        self
+    }
+
+    /// Set the length of the prefix that IPv6 client addresses are grouped by. See
+    /// [`ServerConfig::ipv6_prefix_length`].
+    pub const fn ipv6_prefix_length(mut self, length: u8) -> Self {
+        self.ipv6_prefix_length = length;
+        self
+    }
+
+    /// Set the checks made on the identity that clients give in `HELO`. See
+    /// [`ServerConfig::helo_policy`].
+    pub const fn helo_policy(mut self, policy: HeloPolicy) -> Self {
+        self.helo_policy = policy;
+        self
+    }
+
+    /// Set the limits on mail transactions with the null reverse-path. See
+    /// [`ServerConfig::null_sender_policy`].
+    pub const fn null_sender_policy(mut self, policy: NullSenderPolicy) -> Self {
+        self.null_sender_policy = policy;
+        self
+    }
+
+    /// Set whether the names of clients are looked up, and what is done with the results. See
+    /// [`ServerConfig::reverse_dns_policy`].
+    pub const fn reverse_dns_policy(mut self, policy: ReverseDnsPolicy) -> Self {
+        self.reverse_dns_policy = policy;
+        self
+    }
+
+    /// Set whether the domains of senders are checked for existence, and what is done with the
+    /// results. See [`ServerConfig::sender_domain_policy`].
+    pub const fn sender_domain_policy(mut self, policy: SenderDomainPolicy) -> Self {
+        self.sender_domain_policy = policy;
+        self
+    }
+
+    /// Set whether SPF is checked, and what is done with the results. See
+    /// [`ServerConfig::spf_policy`].
+    #[cfg(feature = "spf")]
+    pub const fn spf_policy(mut self, policy: SpfPolicy) -> Self {
+        self.spf_policy = policy;
+        self
+    }
+
+    /// Evaluate DMARC for each message, doing what `policy` says with those that fail. See
+    /// [`ServerConfig::dmarc_policy`].
+    #[cfg(feature = "dmarc")]
+    pub const fn dmarc_policy(mut self, policy: DmarcPolicy) -> Self {
+        self.dmarc_policy = Some(policy);
+        self
+    }
+
+    /// Look up the address of each client in the DNS blocklists of `policy`. See
+    /// [`ServerConfig::dnsbl_policy`].
+    #[cfg(feature = "dnsbl")]
+    pub fn dnsbl_policy(mut self, policy: DnsblPolicy) -> Self {
+        self.dnsbl_policy = Some(policy);
+        self
+    }
+
+    /// Look up whether recipients have a mailbox with `verifier`. See
+    /// [`ServerConfig::address_verifier`].
+    #[cfg(feature = "directory")]
+    pub fn address_verifier(mut self, verifier: impl AddressVerifier) -> Self {
+        self.address_verifier = Some(SharedAddressVerifier::new(verifier));
+        self
+    }
+
+    /// Greylist recipients with `policy`. See [`ServerConfig::greylist_policy`].
+    #[cfg(feature = "greylist")]
+    pub fn greylist_policy(mut self, policy: GreylistPolicy) -> Self {
+        self.greylist_policy = Some(policy);
+        self
+    }
+
+    /// Scan messages with ClamAV as `policy` says. See [`ServerConfig::clamav_policy`].
+    #[cfg(feature = "clamav")]
+    pub fn clamav_policy(mut self, policy: ClamAvPolicy) -> Self {
+        self.clamav_policy = Some(policy);
+        self
+    }
+
+    /// Scan messages with Rspamd as `policy` says. See [`ServerConfig::rspamd_policy`].
+    #[cfg(feature = "rspamd")]
+    pub fn rspamd_policy(mut self, policy: RspamdPolicy) -> Self {
+        self.rspamd_policy = Some(policy);
+        self
+    }
+
+    /// Score messages with SpamAssassin as `policy` says. See
+    /// [`ServerConfig::spamassassin_policy`].
+    #[cfg(feature = "spamassassin")]
+    pub fn spamassassin_policy(mut self, policy: SpamAssassinPolicy) -> Self {
+        self.spamassassin_policy = Some(policy);
+        self
+    }
+
+    /// Keep accepted messages in `spool` until they are processed. See [`ServerConfig::spool`].
+    #[cfg(feature = "spool")]
+    pub fn spool(mut self, spool: Spool) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Check messages against `rule` after the rules that were already added. See
+    /// [`ServerConfig::filter_rules`].
+    #[cfg(feature = "filter")]
+    pub fn filter_rule(mut self, rule: FilterRule) -> Self {
+        self.filter_rules.push(rule);
+        self
+    }
+
+    /// Set which attachments are blocked. See [`ServerConfig::attachment_policy`].
+    #[cfg(feature = "attachment")]
+    pub fn attachment_policy(mut self, policy: AttachmentPolicy) -> Self {
+        self.attachment_policy = Some(policy);
+        self
+    }
+
+    /// Set whether the Authenticated Received Chain of each message is validated. See
+    /// [`ServerConfig::verify_arc`].
+    #[cfg(feature = "arc")]
+    pub const fn verify_arc(mut self, verify_arc: bool) -> Self {
+        self.verify_arc = verify_arc;
+        self
+    }
+
+    /// Seal each message with an ARC set on receipt. See [`ServerConfig::arc_sealer`].
+    #[cfg(feature = "arc")]
+    pub fn arc_sealer(mut self, sealer: ArcSealer) -> Self {
+        self.arc_sealer = Some(sealer);
+        self
+    }
+
+    /// Set the resolver that every DNS lookup is made with. See [`ServerConfig::resolver`].
+    pub fn resolver(mut self, resolver: impl Resolver) -> Self {
+        self.resolver = SharedResolver::new(resolver);
+        self
+    }
+
+    /// Offer `STARTTLS`, encrypting sessions with `tls`. See [`ServerConfig::tls`].
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Set whether `STARTTLS` is offered, and whether clients must use it before sending mail.
+    /// See [`ServerConfig::tls_policy`].
+    #[cfg(feature = "tls")]
+    pub const fn tls_policy(mut self, policy: TlsPolicy) -> Self {
+        self.tls_policy = policy;
+        self
+    }
+
+    /// Set how long the server waits on the client in each phase of the session. See
+    /// [`ServerConfig::timeouts`].
+    pub const fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Set whether to add a `Return-Path:` header to received messages. See
+    /// [`ServerConfig::stamp_return_path`].
+    pub const fn stamp_return_path(mut self, stamp: bool) -> Self {
+        self.stamp_return_path = stamp;
+        self
+    }
+
+    /// Set whether to add a `Message-ID:` header to received messages that lack one. See
+    /// [`ServerConfig::stamp_message_id`].
+    pub const fn stamp_message_id(mut self, stamp: bool) -> Self {
+        self.stamp_message_id = stamp;
+        self
+    }
+
+    /// Set how the opening and closing of each session is logged. See
+    /// [`ServerConfig::log_format`].
+    pub const fn log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = format;
+        self
+    }
+
+    /// Record the transcript of each session and keep it in `target`. See
+    /// [`ServerConfig::transcripts`].
+    pub fn transcripts(mut self, target: TranscriptTarget) -> Self {
+        self.transcripts = Some(target);
+        self
+    }
+
+    /// Check that the configuration is valid, as [`Self::build`] does.
+    ///
+    /// # Errors
+    ///
+    /// - [`ConfigError::InvalidHostname`] if the hostname is empty, longer than 255 characters, or
+    ///   not a domain name, see [`is_smtp_domain_name`].
+    /// - [`ConfigError::InvalidBanner`] if the banner is not printable ASCII on one line, or if the
+    ///   greeting would be longer than the 512 characters that a reply line is limited to.
+    /// - [`ConfigError::InvalidReplyText`] if any other reply text is invalid in the same way.
+    /// - [`ConfigError::InvalidPrefixLength`] if the IPv6 prefix length is longer than 128 bits.
+    /// - [`ConfigError::InvalidTimeout`] if any of the timeouts is zero.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.hostname.is_empty()
+            || self.hostname.len() > max_lengths::DOMAIN
+            || !is_smtp_domain_name(&self.hostname)
+        {
+            return Err(ConfigError::InvalidHostname);
+        }
+
+        if self.ipv6_prefix_length > 128 {
+            return Err(ConfigError::InvalidPrefixLength);
+        }
+
+        let timeouts = self.timeouts;
+        if [
+            timeouts.greeting(),
+            timeouts.server(),
+            timeouts.data_block(),
+            timeouts.data_termination(),
+        ]
+        .iter()
+        .any(Duration::is_zero)
+        {
+            return Err(ConfigError::InvalidTimeout);
+        }
+
+        // The greeting is `220 <hostname> <banner><CRLF>`, and so on for the other replies.
+        let with_hostname = self.hostname.len() + 7;
+        if !is_reply_text(&self.banner, with_hostname) {
+            return Err(ConfigError::InvalidBanner);
+        }
+        if !is_reply_text(&self.quit_text, 6)
+            || !is_reply_text(&self.refusal_text, with_hostname)
+            || !is_reply_text(&self.unavailable_text, with_hostname)
+        {
+            return Err(ConfigError::InvalidReplyText);
+        }
+
+        #[cfg(feature = "tls")]
+        if self.tls_policy == TlsPolicy::Required && self.tls.is_none() {
+            return Err(ConfigError::MissingTls);
+        }
+
+        #[cfg(feature = "dnsbl")]
+        if let Some(policy) = &self.dnsbl_policy {
+            if policy.timeout().is_zero() {
+                return Err(ConfigError::InvalidTimeout);
+            }
+            if policy.lists().iter().any(|list| {
+                list.zone().is_empty()
+                    || list.zone().len() > max_lengths::DOMAIN
+                    || !is_smtp_domain_name(list.zone())
+            }) {
+                return Err(ConfigError::InvalidDnsblZone);
+            }
+        }
+
+        #[cfg(feature = "greylist")]
+        if let Some(policy) = &self.greylist_policy {
+            if policy.initial_delay() >= policy.retry_window() {
+                return Err(ConfigError::InvalidGreylistWindow);
+            }
+        }
+
+        #[cfg(any(
+            feature = "attachment",
+            feature = "clamav",
+            feature = "filter",
+            feature = "rspamd",
+            feature = "spamassassin"
+        ))]
+        self.validate_message_checks()?;
+
+        Ok(())
+    }
+
+    /// Check that the policies of the checks made on each message once its data is received are
+    /// valid, as part of [`Self::validate`].
+    #[cfg(any(
+        feature = "attachment",
+        feature = "clamav",
+        feature = "filter",
+        feature = "rspamd",
+        feature = "spamassassin"
+    ))]
+    fn validate_message_checks(&self) -> Result<(), ConfigError> {
+        #[cfg(feature = "clamav")]
+        if let Some(policy) = &self.clamav_policy {
+            if policy.timeout().is_zero() {
+                return Err(ConfigError::InvalidTimeout);
+            }
+            if !policy.is_socket_path() && !is_host_and_port(policy.address()) {
+                return Err(ConfigError::InvalidScannerAddress);
+            }
+        }
+
+        #[cfg(feature = "rspamd")]
+        if let Some(policy) = &self.rspamd_policy {
+            if policy.timeout().is_zero() {
+                return Err(ConfigError::InvalidTimeout);
+            }
+            if !is_host_and_port(policy.address()) {
+                return Err(ConfigError::InvalidScannerAddress);
+            }
+        }
+
+        #[cfg(feature = "spamassassin")]
+        if let Some(policy) = &self.spamassassin_policy {
+            if policy.timeout().is_zero() {
+                return Err(ConfigError::InvalidTimeout);
+            }
+            if !is_host_and_port(policy.address()) {
+                return Err(ConfigError::InvalidScannerAddress);
+            }
+            if !policy.scores().all(f64::is_finite) {
+                return Err(ConfigError::InvalidSpamScore);
+            }
+        }
+
+        // Leaves room for the codes of the reply, such as `550 5.7.1 `.
+        #[cfg(feature = "filter")]
+        if self
+            .filter_rules
+            .iter()
+            .filter_map(FilterRule::text)
+            .any(|text| !is_reply_text(text, 10))
+        {
+            return Err(ConfigError::InvalidReplyText);
+        }
+
+        #[cfg(feature = "attachment")]
+        if let Some(policy) = &self.attachment_policy {
+            if !policy
+                .domains()
+                .all(|(domain, _)| is_smtp_domain_name(domain))
+            {
+                return Err(ConfigError::InvalidAttachmentDomain);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the [`ServerConfig`], validating it with [`Self::validate`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ConfigError`] if the configuration is invalid, see [`Self::validate`].
+    pub fn build(self) -> Result<ServerConfig, ConfigError> {
+        self.validate()?;
+
+        let reply_text = |text: String, error| SmtpString::new(&text).map_err(|_| error);
+        let banner = reply_text(self.banner, ConfigError::InvalidBanner)?;
+        let quit_text = reply_text(self.quit_text, ConfigError::InvalidReplyText)?;
+        let refusal_text = reply_text(self.refusal_text, ConfigError::InvalidReplyText)?;
+        let unavailable_text = reply_text(self.unavailable_text, ConfigError::InvalidReplyText)?;
+
+        Ok(ServerConfig {
+            hostname: self.hostname,
+            banner,
+            quit_text,
+            refusal_text,
+            unavailable_text,
+            max_message_size: self.max_message_size,
+            max_header_size: self.max_header_size,
+            max_header_fields: self.max_header_fields,
+            max_header_field_length: self.max_header_field_length,
+            max_command_line: self.max_command_line,
+            max_text_line: self.max_text_line,
+            max_recipients: self.max_recipients,
+            max_connections: self.max_connections,
+            connection_overflow: self.connection_overflow,
+            peer_limits: self.peer_limits,
+            network_limits: self.network_limits,
+            ipv6_prefix_length: self.ipv6_prefix_length,
+            access_rules: self.access_rules,
+            default_access: self.default_access,
+            command_limits: self.command_limits,
+            auth_limits: self.auth_limits,
+            tarpit: self.tarpit,
+            greeting_delay: self.greeting_delay,
+            reject_early_talkers: self.reject_early_talkers,
+            helo_policy: self.helo_policy,
+            null_sender_policy: self.null_sender_policy,
+            reverse_dns_policy: self.reverse_dns_policy,
+            sender_domain_policy: self.sender_domain_policy,
+            #[cfg(feature = "spf")]
+            spf_policy: self.spf_policy,
+            #[cfg(feature = "dmarc")]
+            dmarc_policy: self.dmarc_policy,
+            #[cfg(feature = "dnsbl")]
+            dnsbl_policy: self.dnsbl_policy,
+            #[cfg(feature = "directory")]
+            address_verifier: self.address_verifier,
+            #[cfg(feature = "greylist")]
+            greylist_policy: self.greylist_policy,
+            #[cfg(feature = "clamav")]
+            clamav_policy: self.clamav_policy,
+            #[cfg(feature = "rspamd")]
+            rspamd_policy: self.rspamd_policy,
+            #[cfg(feature = "spamassassin")]
+            spamassassin_policy: self.spamassassin_policy,
+            #[cfg(feature = "spool")]
+            spool: self.spool,
+            #[cfg(feature = "filter")]
+            filter_rules: self.filter_rules,
+            #[cfg(feature = "attachment")]
+            attachment_policy: self.attachment_policy,
+            #[cfg(feature = "arc")]
+            verify_arc: self.verify_arc,
+            #[cfg(feature = "arc")]
+            arc_sealer: self.arc_sealer,
+            resolver: self.resolver,
+            #[cfg(feature = "tls")]
+            tls: self.tls,
+            #[cfg(feature = "tls")]
+            tls_policy: self.tls_policy,
+            timeouts: self.timeouts,
+            stamp_return_path: self.stamp_return_path,
+            stamp_message_id: self.stamp_message_id,
+            log_format: self.log_format,
+            transcripts: self.transcripts,
+        })
+    }
+}
+
+/// Check whether `text` is printable ASCII on one line, and whether a reply line of `surrounding`
+/// more characters would fit in [`max_lengths::REPLY_LINE`].
+fn is_reply_text(text: &str, surrounding: usize) -> bool {
+    text.bytes().all(|byte| matches!(byte, b'\t' | 32..=126))
+        && text.len() + surrounding <= max_lengths::REPLY_LINE
+}
+
+/// Check whether `address` is a host and port, such as `127.0.0.1:783` or `[::1]:783`.
+#[cfg(any(feature = "clamav", feature = "rspamd", feature = "spamassassin"))]
+fn is_host_and_port(address: &str) -> bool {
+    address
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+}
+
+impl Default for ServerConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<ServerConfig> for ServerConfigBuilder {
+    fn from(config: ServerConfig) -> Self {
+        Self {
+            hostname: config.hostname,
+            banner: config.banner.to_string(),
+            quit_text: config.quit_text.to_string(),
+            refusal_text: config.refusal_text.to_string(),
+            unavailable_text: config.unavailable_text.to_string(),
+            max_message_size: config.max_message_size,
+            max_header_size: config.max_header_size,
+            max_header_fields: config.max_header_fields,
+            max_header_field_length: config.max_header_field_length,
+            max_command_line: config.max_command_line,
+            max_text_line: config.max_text_line,
+            max_recipients: config.max_recipients,
+            max_connections: config.max_connections,
+            connection_overflow: config.connection_overflow,
+            peer_limits: config.peer_limits,
+            network_limits: config.network_limits,
+            ipv6_prefix_length: config.ipv6_prefix_length,
+            access_rules: config.access_rules,
+            default_access: config.default_access,
+            command_limits: config.command_limits,
+            auth_limits: config.auth_limits,
+            tarpit: config.tarpit,
+            greeting_delay: config.greeting_delay,
+            reject_early_talkers: config.reject_early_talkers,
+            helo_policy: config.helo_policy,
+            null_sender_policy: config.null_sender_policy,
+            reverse_dns_policy: config.reverse_dns_policy,
+            sender_domain_policy: config.sender_domain_policy,
+            #[cfg(feature = "spf")]
+            spf_policy: config.spf_policy,
+            #[cfg(feature = "dmarc")]
+            dmarc_policy: config.dmarc_policy,
+            #[cfg(feature = "dnsbl")]
+            dnsbl_policy: config.dnsbl_policy,
+            #[cfg(feature = "directory")]
+            address_verifier: config.address_verifier,
+            #[cfg(feature = "greylist")]
+            greylist_policy: config.greylist_policy,
+            #[cfg(feature = "clamav")]
+            clamav_policy: config.clamav_policy,
+            #[cfg(feature = "rspamd")]
+            rspamd_policy: config.rspamd_policy,
+            #[cfg(feature = "spamassassin")]
+            spamassassin_policy: config.spamassassin_policy,
+            #[cfg(feature = "spool")]
+            spool: config.spool,
+            #[cfg(feature = "filter")]
+            filter_rules: config.filter_rules,
+            #[cfg(feature = "attachment")]
+            attachment_policy: config.attachment_policy,
+            #[cfg(feature = "arc")]
+            verify_arc: config.verify_arc,
+            #[cfg(feature = "arc")]
+            arc_sealer: config.arc_sealer,
+            resolver: config.resolver,
+            #[cfg(feature = "tls")]
+            tls: config.tls,
+            #[cfg(feature = "tls")]
+            tls_policy: config.tls_policy,
+            timeouts: config.timeouts,
+            stamp_return_path: config.stamp_return_path,
+            stamp_message_id: config.stamp_message_id,
+            log_format: config.log_format,
+            transcripts: config.transcripts,
+        }
+    }
+}
+
+impl TryFrom<ServerConfigBuilder> for ServerConfig {
+    type Error = ConfigError;
+
+    fn try_from(builder: ServerConfigBuilder) -> Result<Self, Self::Error> {
+        builder.build()
+    }
+}
+
+/// What is done with connections from a network, before a session is started or any limit is
+/// counted. See [`ServerConfig::access_rules`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Access {
+    /// Continue with the connection as usual.
+    Accept,
+    /// Reply with `554` in place of the greeting and close the connection.
+    ///
+    /// See [RFC 5321 section 3.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-3.1).
+    Reject,
+    /// Close the connection without replying.
+    Drop,
+}
+
+/// What is done with connections past [`ServerConfig::max_connections`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ConnectionOverflow {
+    /// Accept the connection, reply with `421`, and close it.
+    Refuse,
+    /// Stop accepting connections until a session closes, leaving new connections to wait in the
+    /// backlog of the listener.
+    ///
+    /// A connection that was accepted as the last session was taken, such as by another listener
+    /// of [`crate::listen_all`], waits for its greeting until a session closes.
+    Wait,
+}
+
+/// How the opening and closing of each session is logged, see [`ServerConfig::log_format`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum LogFormat {
+    /// Print a line of text as each session opens and another as it closes.
+    #[default]
+    Text,
+    /// Print one JSON object on one line as each session closes, for log pipelines to parse.
+    ///
+    /// The object holds the `session_id`, `local_addr`, `peer_addr`, `helo`, `authenticated_user`,
+    /// and `tls` of the session, the number of `commands` that the client sent, the number of
+    /// `messages_accepted`, the `close_reason`, and the `duration_secs` of the session.
+    #[cfg(feature = "json")]
+    Json,
+}
+
+/// Possible error states encountered when building a [`ServerConfig`] with
+/// [`ServerConfigBuilder`].
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum ConfigError {
+    /// The hostname is not a domain name.
+    InvalidHostname,
+    /// The banner is not printable ASCII on one line, or is too long.
+    InvalidBanner,
+    /// A reply text other than the banner is not printable ASCII on one line, or is too long.
+    InvalidReplyText,
+    /// The length of a network prefix is longer than its addresses.
+    InvalidPrefixLength,
+    /// A network is not an IP address, optionally followed by `/` and the length of its prefix.
+    InvalidNetwork,
+    /// A timeout is zero.
+    InvalidTimeout,
+    /// [`TlsPolicy::Required`] is set without [`ServerConfig::tls`].
+    #[cfg(feature = "tls")]
+    MissingTls,
+    /// The zone of a DNS blocklist is not a domain name.
+    #[cfg(feature = "dnsbl")]
+    InvalidDnsblZone,
+    /// The initial delay of greylisting is not shorter than its retry window.
+    #[cfg(feature = "greylist")]
+    InvalidGreylistWindow,
+    /// The address of a virus or spam scanner is not a host and port.
+    #[cfg(any(feature = "clamav", feature = "rspamd", feature = "spamassassin"))]
+    InvalidScannerAddress,
+    /// A spam score threshold is not a finite number.
+    #[cfg(feature = "spamassassin")]
+    InvalidSpamScore,
+    /// A domain with its own attachment rules is not a domain name.
+    #[cfg(feature = "attachment")]
+    InvalidAttachmentDomain,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::InvalidHostname => "hostname is not a valid domain name",
+            Self::InvalidBanner => "banner is not printable ASCII on one line, or is too long",
+            Self::InvalidReplyText => {
+                "reply text is not printable ASCII on one line, or is too long"
+            }
+            Self::InvalidPrefixLength => "network prefix is longer than its addresses",
+            Self::InvalidNetwork => "network is not an IP address and prefix length",
+            Self::InvalidTimeout => "timeout is zero",
+            #[cfg(feature = "tls")]
+            Self::MissingTls => "TLS is required but not configured",
+            #[cfg(feature = "dnsbl")]
+            Self::InvalidDnsblZone => "DNS blocklist zone is not a valid domain name",
+            #[cfg(feature = "greylist")]
+            Self::InvalidGreylistWindow => {
+                "greylisting initial delay is not shorter than its retry window"
+            }
+            #[cfg(any(feature = "clamav", feature = "rspamd", feature = "spamassassin"))]
+            Self::InvalidScannerAddress => "scanner address is not a host and port",
+            #[cfg(feature = "spamassassin")]
+            Self::InvalidSpamScore => "spam score threshold is not a finite number",
+            #[cfg(feature = "attachment")]
+            Self::InvalidAttachmentDomain => "attachment policy domain is not a valid domain name",
+        })
+    }
+}
+
+impl std::error::Error for ConfigError {}
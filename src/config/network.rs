@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Limits on the connections from each client address, and the networks that they apply to.
+//!
+//! See [`PeerLimits`] and [`Network`].
+
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+    time::Duration,
+};
+
+use super::ConfigError;
+
+/// Limits on the connections from one client address, past which connections are refused with
+/// `421`.
+///
+/// IPv6 addresses are grouped by [`super::ServerConfig::ipv6_prefix_length`], as one client often
+/// controls a whole prefix.
+///
+/// Each server counts only its own connections. With the `redis` feature, servers behind a load
+/// balancer can share a limit by counting in [`crate::SmtpHandler::on_connect`] with
+/// `RedisStore::count` in `greylist`.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use smtp_gateway::config::PeerLimits;
+/// #
+/// let limits = PeerLimits::unlimited()
+///     .with_max_connections(10)
+///     .with_max_rate(30, Duration::from_mins(1));
+///
+/// assert_eq!(limits.max_connections(), Some(10));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct PeerLimits {
+    /// The maximum number of sessions at once.
+    max_connections: Option<usize>,
+    /// The maximum number of connections in a period of time.
+    #[cfg_attr(feature = "serde", serde(with = "super::seconds::rate"))]
+    max_rate: Option<(usize, Duration)>,
+}
+
+impl PeerLimits {
+    /// Create a new [`Self`] without any limits.
+    #[must_use]
+    pub const fn unlimited() -> Self {
+        Self {
+            max_connections: None,
+            max_rate: None,
+        }
+    }
+
+    /// Get the maximum number of sessions at once, or `None` for no limit.
+    #[must_use]
+    pub const fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    /// Get the maximum number of connections in a period of time, or `None` for no limit.
+    ///
+    /// Every connection is counted, including those that were refused.
+    #[must_use]
+    pub const fn max_rate(&self) -> Option<(usize, Duration)> {
+        self.max_rate
+    }
+
+    /// Limit the number of sessions at once. See [`Self::max_connections`].
+    #[must_use]
+    pub const fn with_max_connections(mut self, connections: usize) -> Self {
+        self.max_connections = Some(connections);
+        self
+    }
+
+    /// Limit the number of connections in each `period`. See [`Self::max_rate`].
+    #[must_use]
+    pub const fn with_max_rate(mut self, connections: usize, period: Duration) -> Self {
+        self.max_rate = Some((connections, period));
+        self
+    }
+}
+
+/// A range of IP addresses, written as an address and the length of its prefix, such as
+/// `192.0.2.0/24`.
+///
+/// Parses from and formats as that notation with [`FromStr`] and [`Display`]. A bare address is
+/// parsed as a network of only that address.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::net::{IpAddr, Ipv4Addr};
+/// # use smtp_gateway::config::Network;
+/// #
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let network = Network::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)), 24)?;
+///
+/// assert!(network.contains(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+/// assert!(!network.contains(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1))));
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct Network {
+    /// The first address of the network.
+    address: IpAddr,
+    /// The number of leading bits that every address in the network shares.
+    prefix_length: u8,
+}
+
+impl Network {
+    /// Create a new [`Self`] of the addresses that share the first `prefix_length` bits of
+    /// `address`.
+    ///
+    /// # Errors
+    ///
+    /// - [`ConfigError::InvalidPrefixLength`] if `prefix_length` is longer than `address`.
+    pub fn new(address: IpAddr, prefix_length: u8) -> Result<Self, ConfigError> {
+        Ok(Self {
+            address: mask(address, prefix_length)?,
+            prefix_length,
+        })
+    }
+
+    /// Get the first address of the network.
+    #[must_use]
+    pub const fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    /// Get the number of leading bits that every address in the network shares.
+    #[must_use]
+    pub const fn prefix_length(&self) -> u8 {
+        self.prefix_length
+    }
+
+    /// Get whether `address` is in the network.
+    ///
+    /// IPv4 addresses mapped into IPv6 are treated as IPv4 addresses.
+    #[must_use]
+    pub fn contains(&self, address: IpAddr) -> bool {
+        mask(address.to_canonical(), self.prefix_length).is_ok_and(|masked| masked == self.address)
+    }
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+impl FromStr for Network {
+    type Err = ConfigError;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = match str.split_once('/') {
+            Some((address, prefix_length)) => (
+                address,
+                Some(
+                    prefix_length
+                        .parse()
+                        .map_err(|_| ConfigError::InvalidNetwork)?,
+                ),
+            ),
+            None => (str, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| ConfigError::InvalidNetwork)?;
+        let prefix_length = prefix_length.unwrap_or(match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        });
+
+        Self::new(address, prefix_length)
+    }
+}
+
+impl TryFrom<String> for Network {
+    type Error = ConfigError;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
+        string.parse()
+    }
+}
+
+impl From<Network> for String {
+    fn from(network: Network) -> Self {
+        network.to_string()
+    }
+}
+
+/// Keep the first `prefix_length` bits of `address`, zeroing the rest.
+///
+/// # Errors
+///
+/// - [`ConfigError::InvalidPrefixLength`] if `prefix_length` is longer than `address`.
+pub fn mask(address: IpAddr, prefix_length: u8) -> Result<IpAddr, ConfigError> {
+    let zeroed = |bits: u8| {
+        bits.checked_sub(prefix_length)
+            .ok_or(ConfigError::InvalidPrefixLength)
+    };
+
+    match address {
+        IpAddr::V4(address) => {
+            let mask = u32::MAX.checked_shl(zeroed(32)?.into()).unwrap_or(0);
+            Ok(IpAddr::V4(Ipv4Addr::from(u32::from(address) & mask)))
+        }
+        IpAddr::V6(address) => {
+            let mask = u128::MAX.checked_shl(zeroed(128)?.into()).unwrap_or(0);
+            Ok(IpAddr::V6(Ipv6Addr::from(u128::from(address) & mask)))
+        }
+    }
+}
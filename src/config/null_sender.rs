@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The limits on mail transactions with the null reverse-path, as used by bounces.
+//!
+//! See [`NullSenderPolicy`].
+
+/// The limits on mail transactions with the null reverse-path (`MAIL FROM:<>`), to restrict
+/// backscatter, the bounces of spam sent to forged senders.
+///
+/// The null reverse-path is accepted without limits by default, as [RFC 5321 section
+/// 4.5.5](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.5) requires that it is. The
+/// limits only defer what is past them with `4yz` replies, so legitimate notifications can still
+/// be delivered later. Handlers can make further decisions with
+/// [`crate::message::envelope::Envelope::is_null_sender`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::config::NullSenderPolicy;
+/// #
+/// // Notifications are only ever sent to one recipient.
+/// let policy = NullSenderPolicy::unlimited()
+///     .with_max_recipients(1)
+///     .with_max_transactions(5);
+///
+/// assert_eq!(policy.max_recipients(), Some(1));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct NullSenderPolicy {
+    /// The maximum number of recipients of each transaction.
+    max_recipients: Option<usize>,
+    /// The maximum number of transactions in each session.
+    max_transactions: Option<usize>,
+}
+
+impl NullSenderPolicy {
+    /// Create a new [`Self`] without any limits.
+    #[must_use]
+    pub const fn unlimited() -> Self {
+        Self {
+            max_recipients: None,
+            max_transactions: None,
+        }
+    }
+
+    /// Get the maximum number of recipients accepted in each transaction with the null
+    /// reverse-path, or `None` for only [`crate::ServerConfig::max_recipients`].
+    ///
+    /// Recipients past the limit are deferred with `452`, like those past
+    /// [`crate::ServerConfig::max_recipients`].
+    #[must_use]
+    pub const fn max_recipients(&self) -> Option<usize> {
+        self.max_recipients
+    }
+
+    /// Get the maximum number of transactions with the null reverse-path started in each session,
+    /// or `None` for no limit.
+    ///
+    /// `MAIL FROM:<>` past the limit is deferred with `450`.
+    #[must_use]
+    pub const fn max_transactions(&self) -> Option<usize> {
+        self.max_transactions
+    }
+
+    /// Limit the number of recipients of each transaction. See [`Self::max_recipients`].
+    #[must_use]
+    pub const fn with_max_recipients(mut self, recipients: usize) -> Self {
+        self.max_recipients = Some(recipients);
+        self
+    }
+
+    /// Limit the number of transactions in each session. See [`Self::max_transactions`].
+    #[must_use]
+    pub const fn with_max_transactions(mut self, transactions: usize) -> Self {
+        self.max_transactions = Some(transactions);
+        self
+    }
+}
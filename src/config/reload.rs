@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Replacing the configuration of a running server.
+//!
+//! See [`ConfigHandle`].
+
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use super::ServerConfig;
+
+/// A shared handle to the configuration of a running server, which can be replaced without
+/// restarting it.
+///
+/// Each session takes the configuration that is current when its connection is accepted, and
+/// keeps it until it closes, so a replacement only applies to the sessions after it. Limits on the
+/// server as a whole, such as [`ServerConfig::max_connections`], apply from the next connection.
+///
+/// Every way to start a server takes anything that converts into a [`Self`], so a
+/// [`ServerConfig`] can be given when it is not going to be replaced. Clones share the same
+/// configuration.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{config::ConfigHandle, handler::AcceptAll, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = ConfigHandle::new(ServerConfig::builder().hostname("mx.example.com").build()?);
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     config.clone(),
+///     |_| AcceptAll,
+///     Shutdown::new(),
+/// );
+///
+/// // Later, such as on `SIGHUP`.
+/// config.replace(
+///     ServerConfig::builder()
+///         .hostname("mx.example.com")
+///         .banner("ESMTP ready")
+///         .build()?,
+/// );
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConfigHandle {
+    /// The current configuration.
+    current: Arc<watch::Sender<Arc<ServerConfig>>>,
+}
+
+impl ConfigHandle {
+    /// Create a new [`Self`] that starts out with `config`.
+    #[must_use]
+    pub fn new(config: ServerConfig) -> Self {
+        Self {
+            current: Arc::new(watch::Sender::new(Arc::new(config))),
+        }
+    }
+
+    /// Get the current configuration.
+    #[must_use]
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.current.borrow().clone()
+    }
+
+    /// Replace the configuration with `config` for every session after this, returning the
+    /// previous configuration.
+    #[expect(
+        clippy::must_use_candidate,
+        reason = "the previous configuration is rarely needed"
+    )]
+    pub fn replace(&self, config: ServerConfig) -> Arc<ServerConfig> {
+        self.current.send_replace(Arc::new(config))
+    }
+}
+
+impl From<ServerConfig> for ConfigHandle {
+    fn from(config: ServerConfig) -> Self {
+        Self::new(config)
+    }
+}
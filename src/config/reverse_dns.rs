@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Whether the address of each client is checked for a forward-confirmed name.
+//!
+//! See [`ReverseDnsPolicy`].
+
+/// Whether the address of each client is checked for a name that resolves back to it when it
+/// connects, known as forward-confirmed reverse DNS, and what is done with the result.
+///
+/// The result is kept in [`crate::session::SessionInfo::reverse_dns`], where the handler can make
+/// its own decisions with it. Names are looked up with [`crate::resolver::Resolver::lookup_ptr`],
+/// which [`crate::resolver::SystemResolver`] does not support, so a resolver that does, such as
+/// the one enabled by the `hickory` feature, must be used.
+///
+/// [RFC 8601 section 3](https://www.rfc-editor.org/rfc/rfc8601.html#section-3).
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ReverseDnsPolicy {
+    /// Do not look up the name of the client.
+    #[default]
+    Off,
+    /// Look up the name of the client, and give it in the `Received:` header of each message, or
+    /// `unknown` if it could not be confirmed.
+    Annotate,
+    /// Look up and annotate the name of the client, and refuse the connection in place of the
+    /// greeting if it could not be confirmed: with `554 5.7.25` if the client has no such name, or
+    /// with `421 4.7.25` if a lookup failed. Meant for submission ports, as many legitimate
+    /// servers relaying mail lack one.
+    Reject,
+}
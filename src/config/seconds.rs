@@ -0,0 +1,148 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Serializes durations as a number of seconds, such as `300` or `0.5`, for configuration files.
+//!
+//! Used with `#[serde(with = "...")]`.
+
+use std::time::Duration;
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Serialize `duration` as a number of seconds.
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    duration.as_secs_f64().serialize(serializer)
+}
+
+/// Deserialize a duration from a non-negative number of seconds.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Duration::try_from_secs_f64(f64::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+/// Serializes a number of connections in a period of time, such as
+/// [`super::PeerLimits::max_rate`], as `{ connections, period }` with the period in seconds.
+pub mod rate {
+    use super::{Deserialize, Deserializer, Duration, Serialize, Serializer};
+
+    /// A number of connections in a period of time.
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Rate {
+        /// The number of connections.
+        connections: usize,
+        /// The period of time, in seconds.
+        #[serde(with = "super")]
+        period: Duration,
+    }
+
+    /// Serialize `rate` as `{ connections, period }`, or nothing for `None`.
+    #[expect(
+        clippy::ref_option,
+        reason = "`serde(with)` passes a reference to the field"
+    )]
+    pub fn serialize<S: Serializer>(
+        rate: &Option<(usize, Duration)>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        rate.map(|(connections, period)| Rate {
+            connections,
+            period,
+        })
+        .serialize(serializer)
+    }
+
+    /// Deserialize a rate from `{ connections, period }`, or nothing for `None`.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<(usize, Duration)>, D::Error> {
+        Ok(Option::<Rate>::deserialize(deserializer)?.map(|rate| (rate.connections, rate.period)))
+    }
+}
+
+/// Serializes a number of commands in a period of time, such as
+/// [`super::CommandLimits::max_command_rate`], as `{ commands, period }` with the period in seconds.
+pub mod command_rate {
+    use super::{Deserialize, Deserializer, Duration, Serialize, Serializer};
+
+    /// A number of commands in a period of time.
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Rate {
+        /// The number of commands.
+        commands: usize,
+        /// The period of time, in seconds.
+        #[serde(with = "super")]
+        period: Duration,
+    }
+
+    /// Serialize `rate` as `{ commands, period }`, or nothing for `None`.
+    #[expect(
+        clippy::ref_option,
+        reason = "`serde(with)` passes a reference to the field"
+    )]
+    pub fn serialize<S: Serializer>(
+        rate: &Option<(usize, Duration)>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        rate.map(|(commands, period)| Rate { commands, period })
+            .serialize(serializer)
+    }
+
+    /// Deserialize a rate from `{ commands, period }`, or nothing for `None`.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<(usize, Duration)>, D::Error> {
+        Ok(Option::<Rate>::deserialize(deserializer)?.map(|rate| (rate.commands, rate.period)))
+    }
+}
+
+/// Serializes a number of failed attempts in a period of time, such as
+/// [`super::AuthLimits::max_failure_rate`], as `{ failures, period }` with the period in seconds.
+pub mod failure_rate {
+    use super::{Deserialize, Deserializer, Duration, Serialize, Serializer};
+
+    /// A number of failed attempts in a period of time.
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Rate {
+        /// The number of failed attempts.
+        failures: usize,
+        /// The period of time, in seconds.
+        #[serde(with = "super")]
+        period: Duration,
+    }
+
+    /// Serialize `rate` as `{ failures, period }`, or nothing for `None`.
+    #[expect(
+        clippy::ref_option,
+        reason = "`serde(with)` passes a reference to the field"
+    )]
+    pub fn serialize<S: Serializer>(
+        rate: &Option<(usize, Duration)>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        rate.map(|(failures, period)| Rate { failures, period })
+            .serialize(serializer)
+    }
+
+    /// Deserialize a rate from `{ failures, period }`, or nothing for `None`.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<(usize, Duration)>, D::Error> {
+        Ok(Option::<Rate>::deserialize(deserializer)?.map(|rate| (rate.failures, rate.period)))
+    }
+}
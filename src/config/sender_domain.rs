@@ -0,0 +1,52 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Whether the domain of the reverse-path of each transaction is checked for existence.
+//!
+//! See [`SenderDomainPolicy`].
+
+/// Whether the domain of the reverse-path given in `MAIL FROM` is checked for `MX`, `A`, or `AAAA`
+/// records, and what is done when it has none.
+///
+/// Senders at domains that do not exist cannot be replied to or bounced to, so they are a cheap
+/// sign of forged mail. The null reverse-path is never checked. Domains with a null `MX` record,
+/// which declare that they do not send or receive mail, are rejected with `550 5.7.27` by either
+/// check, per [RFC 7505 section 4.2](https://www.rfc-editor.org/rfc/rfc7505.html#section-4.2),
+/// and transactions whose domain could not be looked up are deferred with `451 4.4.3`.
+///
+/// Domains are looked up with [`crate::resolver::Resolver::lookup_mx`] and
+/// [`crate::resolver::Resolver::lookup_ip`]. [`crate::resolver::SystemResolver`] cannot tell a
+/// domain without addresses from a failed lookup, so a resolver that can, such as the one enabled
+/// by the `hickory` feature, must be used for missing domains to be rejected rather than deferred.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum SenderDomainPolicy {
+    /// Do not check the domain of the sender.
+    #[default]
+    Off,
+    /// Check the domain of the sender, and defer the transaction with `450 4.1.8` if it does not
+    /// exist, so that senders who are only affected by a passing fault of their DNS can try
+    /// again.
+    Defer,
+    /// Check the domain of the sender, and reject the transaction with `550 5.1.8` if it does not
+    /// exist.
+    Reject,
+}
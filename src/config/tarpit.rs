@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The delays that slow down the replies to suspicious clients.
+//!
+//! See [`Tarpit`].
+
+use std::time::Duration;
+
+/// The delays before each reply to a client that was flagged as suspicious, such as by
+/// [`crate::handler::SessionContext::tarpit`].
+///
+/// The first reply after the client is flagged waits for [`Self::initial_delay`], and each reply
+/// after that waits twice as long as the last, up to [`Self::max_delay`]. Once the session has
+/// waited for [`Self::max_duration`] in total, it is closed with `421`, so that tarpitted sessions
+/// do not stay open forever.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use smtp_gateway::config::Tarpit;
+/// #
+/// let tarpit = Tarpit::default()
+///     .with_initial_delay(Duration::from_secs(5))
+///     .with_max_duration(Duration::from_mins(10));
+///
+/// assert_eq!(tarpit.max_delay(), Duration::from_secs(30));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Tarpit {
+    /// The delay before the first reply after the client is flagged.
+    #[cfg_attr(feature = "serde", serde(with = "super::seconds"))]
+    initial_delay: Duration,
+    /// The longest delay before a single reply.
+    #[cfg_attr(feature = "serde", serde(with = "super::seconds"))]
+    max_delay: Duration,
+    /// The longest total delay before the session is closed.
+    #[cfg_attr(feature = "serde", serde(with = "super::seconds"))]
+    max_duration: Duration,
+}
+
+impl Tarpit {
+    /// Get the delay before the first reply after the client is flagged, one second by default.
+    #[must_use]
+    pub const fn initial_delay(&self) -> Duration {
+        self.initial_delay
+    }
+
+    /// Get the longest delay before a single reply, 30 seconds by default.
+    ///
+    /// This should be kept below the timeouts of clients, which are five minutes for most commands
+    /// per [RFC 5321 section 4.5.3.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.2).
+    #[must_use]
+    pub const fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    /// Get the longest total delay before the session is closed with `421`, five minutes by
+    /// default.
+    #[must_use]
+    pub const fn max_duration(&self) -> Duration {
+        self.max_duration
+    }
+
+    /// Set the delay before the first reply. See [`Self::initial_delay`].
+    #[must_use]
+    pub const fn with_initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Set the longest delay before a single reply. See [`Self::max_delay`].
+    #[must_use]
+    pub const fn with_max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Set the longest total delay. See [`Self::max_duration`].
+    #[must_use]
+    pub const fn with_max_duration(mut self, duration: Duration) -> Self {
+        self.max_duration = duration;
+        self
+    }
+
+    /// Get the delay that follows `delay`, twice as long but no longer than [`Self::max_delay`].
+    #[must_use]
+    pub(crate) fn next_delay(&self, delay: Duration) -> Duration {
+        delay.saturating_mul(2).min(self.max_delay)
+    }
+}
+
+impl Default for Tarpit {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            max_duration: Duration::from_mins(5),
+        }
+    }
+}
@@ -0,0 +1,551 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use super::*;
+
+#[test]
+fn test_default() {
+    let config = ServerConfig::default();
+
+    assert_eq!(config.hostname(), "localhost");
+    assert_eq!(config.max_message_size(), 10 * 1024 * 1024);
+    assert_eq!(config.max_recipients(), 100);
+    assert_eq!(config.helo_policy(), HeloPolicy::accept_all());
+    assert_eq!(config.resolver(), &SharedResolver::default());
+    assert!(!config.stamp_return_path());
+    assert!(!config.stamp_message_id());
+}
+
+#[test]
+fn test_builder() -> Result<(), ConfigError> {
+    let config = ServerConfig::builder()
+        .hostname("mx.example.com")
+        .banner("ESMTP ready")
+        .quit_text("Goodbye")
+        .refusal_text("Go away")
+        .max_message_size(1024)
+        .max_recipients(5)
+        .stamp_message_id(true)
+        .build()?;
+
+    assert_eq!(config.hostname(), "mx.example.com");
+    assert_eq!(config.banner().to_string(), "ESMTP ready");
+    assert_eq!(config.quit_text().to_string(), "Goodbye");
+    assert_eq!(config.refusal_text().to_string(), "Go away");
+    assert_eq!(
+        config.unavailable_text().to_string(),
+        "Service not available, closing transmission channel"
+    );
+    assert_eq!(config.max_message_size(), 1024);
+    assert_eq!(config.max_recipients(), 5);
+    assert!(config.stamp_message_id());
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid() {
+    for hostname in ["", "exa mple.com", &"a".repeat(300)] {
+        assert_eq!(
+            ServerConfig::builder().hostname(hostname).build().err(),
+            Some(ConfigError::InvalidHostname),
+            "{hostname:?}"
+        );
+    }
+
+    for banner in ["ESMTP\r\nready", "ESMTP ✉", &"a".repeat(600)] {
+        assert_eq!(
+            ServerConfig::builder().banner(banner).build().err(),
+            Some(ConfigError::InvalidBanner),
+            "{banner:?}"
+        );
+    }
+
+    for text in ["Bye\r\n250 OK", &"a".repeat(600)] {
+        assert_eq!(
+            ServerConfig::builder().quit_text(text).build().err(),
+            Some(ConfigError::InvalidReplyText),
+            "{text:?}"
+        );
+        assert_eq!(
+            ServerConfig::builder().unavailable_text(text).build().err(),
+            Some(ConfigError::InvalidReplyText),
+            "{text:?}"
+        );
+    }
+
+    assert_eq!(
+        ServerConfig::builder()
+            .timeouts(Timeouts::rfc5321().with_server(Duration::ZERO))
+            .validate(),
+        Err(ConfigError::InvalidTimeout)
+    );
+    #[cfg(feature = "tls")]
+    assert_eq!(
+        ServerConfig::builder()
+            .tls_policy(crate::tls::TlsPolicy::Required)
+            .validate(),
+        Err(ConfigError::MissingTls)
+    );
+    assert_eq!(ServerConfig::builder().validate(), Ok(()));
+}
+
+#[test]
+fn test_network() -> Result<(), ConfigError> {
+    let v4 = Network::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 77)), 24)?;
+    assert_eq!(v4.address(), IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)));
+    assert!(v4.contains(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 255))));
+    assert!(!v4.contains(IpAddr::V4(Ipv4Addr::new(192, 0, 3, 0))));
+    // IPv4 addresses mapped into IPv6.
+    assert!(v4.contains(IpAddr::V6(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped())));
+
+    let v6 = Network::new(
+        IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+        64,
+    )?;
+    assert!(v6.contains(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 1, 2, 3, 4))));
+    assert!(!v6.contains(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1))));
+    assert!(!v6.contains(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+
+    // Tests the notation of networks.
+    assert_eq!("192.0.2.77/24".parse(), Ok(v4));
+    assert_eq!(v4.to_string(), "192.0.2.0/24");
+    assert_eq!(v6.to_string(), "2001:db8::/64");
+    assert_eq!("192.0.2.1".parse::<Network>()?.prefix_length(), 32);
+    for invalid in ["192.0.2.0/", "192.0.2/24", "example.com/24"] {
+        assert_eq!(
+            invalid.parse::<Network>(),
+            Err(ConfigError::InvalidNetwork),
+            "{invalid:?}"
+        );
+    }
+
+    let everything = Network::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)?;
+    assert!(everything.contains(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1))));
+
+    assert_eq!(
+        Network::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 33),
+        Err(ConfigError::InvalidPrefixLength)
+    );
+    assert_eq!(
+        ServerConfig::builder()
+            .ipv6_prefix_length(129)
+            .build()
+            .err(),
+        Some(ConfigError::InvalidPrefixLength)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_limits_for() -> Result<(), ConfigError> {
+    let strict = PeerLimits::unlimited().with_max_connections(1);
+    let trusted = PeerLimits::unlimited();
+    let config = ServerConfig::builder()
+        .peer_limits(strict)
+        .network_limits(
+            Network::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)), 24)?,
+            trusted,
+        )
+        .network_limits(
+            Network::new(IpAddr::V4(Ipv4Addr::new(192, 0, 0, 0)), 16)?,
+            strict.with_max_rate(10, Duration::from_mins(1)),
+        )
+        .build()?;
+
+    // Tests that the first network that contains an address decides its limits.
+    assert_eq!(
+        config.limits_for(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+        trusted
+    );
+    assert_eq!(
+        config
+            .limits_for(IpAddr::V4(Ipv4Addr::new(192, 0, 3, 1)))
+            .max_rate(),
+        Some((10, Duration::from_mins(1)))
+    );
+    assert_eq!(
+        config.limits_for(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1))),
+        strict
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_access_for() -> Result<(), ConfigError> {
+    let config = ServerConfig::builder()
+        .access(
+            Network::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 32)?,
+            Access::Accept,
+        )
+        .access(
+            Network::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)), 24)?,
+            Access::Drop,
+        )
+        .default_access(Access::Reject)
+        .build()?;
+
+    // Tests that the first network that contains an address decides its access.
+    assert_eq!(
+        config.access_for(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+        Access::Accept
+    );
+    assert_eq!(
+        config.access_for(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2))),
+        Access::Drop
+    );
+    assert_eq!(
+        config.access_for(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+        Access::Reject
+    );
+    assert_eq!(
+        ServerConfig::default().access_for(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+        Access::Accept
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() -> Result<(), Box<dyn std::error::Error>> {
+    let config: ServerConfig = serde_json::from_str(
+        r#"{
+            "hostname": "mx.example.com",
+            "max_recipients": 5,
+            "connection_overflow": "wait",
+            "network_limits": [
+                ["192.0.2.0/24", { "max_rate": { "connections": 10, "period": 60 } }]
+            ],
+            "access_rules": [["198.51.100.0/24", "drop"]],
+            "default_access": "reject",
+            "command_limits": {
+                "max_errors": 10,
+                "error_delay": 1,
+                "max_command_rate": { "commands": 100, "period": 60 }
+            },
+            "auth_limits": {
+                "max_failures": 3,
+                "max_failure_rate": { "failures": 10, "period": 600 },
+                "ban_duration": 3600
+            },
+            "tarpit": { "initial_delay": 5 },
+            "greeting_delay": 2,
+            "reject_early_talkers": true,
+            "helo_policy": { "syntax": "reject" },
+            "null_sender_policy": { "max_recipients": 1 },
+            "timeouts": { "server": 0.5 }
+        }"#,
+    )?;
+
+    assert_eq!(config.hostname(), "mx.example.com");
+    assert_eq!(config.max_recipients(), 5);
+    assert_eq!(config.max_message_size(), 10 * 1024 * 1024);
+    assert_eq!(config.connection_overflow(), ConnectionOverflow::Wait);
+    assert_eq!(
+        config
+            .limits_for(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .max_rate(),
+        Some((10, Duration::from_mins(1)))
+    );
+    assert_eq!(
+        config.access_for(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1))),
+        Access::Drop
+    );
+    assert_eq!(config.default_access(), Access::Reject);
+    assert_eq!(
+        config.command_limits(),
+        CommandLimits::unlimited()
+            .with_max_errors(10)
+            .with_error_delay(Duration::from_secs(1))
+            .with_max_command_rate(100, Duration::from_mins(1))
+    );
+    assert_eq!(
+        config.auth_limits(),
+        AuthLimits::unlimited()
+            .with_max_failures(3)
+            .with_max_failure_rate(10, Duration::from_mins(10))
+            .with_ban_duration(Duration::from_hours(1))
+    );
+    assert_eq!(
+        config.tarpit(),
+        Tarpit::default().with_initial_delay(Duration::from_secs(5))
+    );
+    assert_eq!(config.greeting_delay(), Duration::from_secs(2));
+    assert!(config.reject_early_talkers());
+    assert_eq!(config.helo_policy().syntax(), HeloAction::Reject);
+    assert_eq!(config.null_sender_policy().max_recipients(), Some(1));
+    assert_eq!(config.timeouts().server(), Duration::from_millis(500));
+    assert_eq!(config.timeouts().greeting(), Timeouts::rfc5321().greeting());
+
+    let json = serde_json::to_string(&config)?;
+    assert_eq!(serde_json::from_str::<ServerConfig>(&json)?, config);
+
+    // Tests that configurations are validated, and that unknown fields are refused.
+    for invalid in [
+        r#"{ "hostname": "exa mple.com" }"#,
+        r#"{ "timeouts": { "server": 0 } }"#,
+        r#"{ "timeouts": { "server": -1 } }"#,
+        r#"{ "network_limits": [["192.0.2.0/33", {}]] }"#,
+        r#"{ "max_recipient": 5 }"#,
+    ] {
+        assert!(
+            serde_json::from_str::<ServerConfig>(invalid).is_err(),
+            "{invalid}"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_policies() -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(
+        serde_json::from_str::<ServerConfig>(r#"{ "reverse_dns_policy": "annotate" }"#)?
+            .reverse_dns_policy(),
+        ReverseDnsPolicy::Annotate
+    );
+    assert_eq!(
+        serde_json::from_str::<ServerConfig>(r#"{ "sender_domain_policy": "defer" }"#)?
+            .sender_domain_policy(),
+        SenderDomainPolicy::Defer
+    );
+    #[cfg(feature = "spf")]
+    assert_eq!(
+        serde_json::from_str::<ServerConfig>(r#"{ "spf_policy": "reject" }"#)?.spf_policy(),
+        crate::spf::SpfPolicy::Reject
+    );
+    #[cfg(feature = "dmarc")]
+    assert_eq!(
+        serde_json::from_str::<ServerConfig>(r#"{ "dmarc_policy": { "reject": "tag" } }"#)?
+            .dmarc_policy(),
+        Some(crate::dmarc::DmarcPolicy::default().with_reject(crate::dmarc::DmarcAction::Tag))
+    );
+    #[cfg(feature = "arc")]
+    assert!(serde_json::from_str::<ServerConfig>(r#"{ "verify_arc": true }"#)?.verify_arc());
+    #[cfg(feature = "dnsbl")]
+    assert_eq!(
+        serde_json::from_str::<ServerConfig>(
+            r#"{ "dnsbl_policy": { "lists": [{ "zone": "bl.example", "action": "refuse" }] } }"#
+        )?
+        .dnsbl_policy(),
+        Some(
+            &crate::dnsbl::DnsblPolicy::default().with_list(
+                crate::dnsbl::Blocklist::new("bl.example")
+                    .with_action(crate::dnsbl::DnsblAction::Refuse)
+            )
+        )
+    );
+    #[cfg(feature = "greylist")]
+    assert_eq!(
+        serde_json::from_str::<ServerConfig>(r#"{ "greylist_policy": { "initial_delay": 60 } }"#)?
+            .greylist_policy(),
+        Some(
+            &crate::greylist::GreylistPolicy::default().with_initial_delay(Duration::from_mins(1))
+        )
+    );
+    #[cfg(feature = "clamav")]
+    assert_eq!(
+        serde_json::from_str::<ServerConfig>(
+            r#"{ "clamav_policy": { "address": "/run/clamav/clamd.ctl", "action": "quarantine" } }"#
+        )?
+        .clamav_policy(),
+        Some(
+            &crate::clamav::ClamAvPolicy::new("/run/clamav/clamd.ctl")
+                .with_action(crate::clamav::ClamAvAction::Quarantine)
+        )
+    );
+    #[cfg(feature = "attachment")]
+    assert_eq!(
+        serde_json::from_str::<ServerConfig>(
+            r#"{ "attachment_policy": { "domains": { "lab.example": { "extensions": ["iso"] } } } }"#
+        )?
+        .attachment_policy(),
+        Some(&crate::attachment::AttachmentPolicy::default().with_domain(
+            "lab.example",
+            crate::attachment::AttachmentRules::default().with_extensions(["iso"])
+        ))
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "dnsbl")]
+#[test]
+fn test_dnsbl_policy() {
+    use crate::dnsbl::{Blocklist, DnsblPolicy};
+
+    let with_list = |policy: DnsblPolicy| {
+        ServerConfig::builder()
+            .dnsbl_policy(policy.with_list(Blocklist::new("zen.spamhaus.org")))
+            .build()
+    };
+    assert!(with_list(DnsblPolicy::default()).is_ok());
+
+    // Tests that zones must be domain names, and that lookups must be given time.
+    assert_eq!(
+        with_list(DnsblPolicy::default().with_list(Blocklist::new("bl example"))).err(),
+        Some(ConfigError::InvalidDnsblZone)
+    );
+    assert_eq!(
+        with_list(DnsblPolicy::default().with_timeout(Duration::ZERO)).err(),
+        Some(ConfigError::InvalidTimeout)
+    );
+}
+
+#[cfg(feature = "greylist")]
+#[test]
+fn test_greylist_policy() {
+    use crate::greylist::GreylistPolicy;
+
+    let with_delay = |delay| {
+        ServerConfig::builder()
+            .greylist_policy(
+                GreylistPolicy::default()
+                    .with_initial_delay(delay)
+                    .with_retry_window(Duration::from_hours(1)),
+            )
+            .build()
+    };
+    assert!(with_delay(Duration::from_mins(5)).is_ok());
+
+    // Tests that retries must be accepted for some time after the initial delay.
+    assert_eq!(
+        with_delay(Duration::from_hours(1)).err(),
+        Some(ConfigError::InvalidGreylistWindow)
+    );
+}
+
+#[cfg(feature = "clamav")]
+#[test]
+fn test_clamav_policy() {
+    use crate::clamav::ClamAvPolicy;
+
+    let with_policy = |policy| ServerConfig::builder().clamav_policy(policy).build();
+    assert!(with_policy(ClamAvPolicy::default()).is_ok());
+    assert!(with_policy(ClamAvPolicy::new("/run/clamav/clamd.ctl")).is_ok());
+
+    // Tests that the address must be a host and port or a path, and that scans must be given time.
+    for address in ["clamd", "clamd:", "run/clamav/clamd.ctl"] {
+        assert_eq!(
+            with_policy(ClamAvPolicy::new(address)).err(),
+            Some(ConfigError::InvalidScannerAddress)
+        );
+    }
+    assert_eq!(
+        with_policy(ClamAvPolicy::default().with_timeout(Duration::ZERO)).err(),
+        Some(ConfigError::InvalidTimeout)
+    );
+}
+
+#[cfg(feature = "rspamd")]
+#[test]
+fn test_rspamd_policy() {
+    use crate::rspamd::RspamdPolicy;
+
+    let with_policy = |policy| ServerConfig::builder().rspamd_policy(policy).build();
+    assert!(with_policy(RspamdPolicy::default()).is_ok());
+    assert!(with_policy(RspamdPolicy::new("[::1]:11333")).is_ok());
+
+    // Tests that the address must have a port, and that scans must be given time.
+    for address in ["rspamd", "rspamd:", ":11333", "rspamd:port"] {
+        assert_eq!(
+            with_policy(RspamdPolicy::new(address)).err(),
+            Some(ConfigError::InvalidScannerAddress)
+        );
+    }
+    assert_eq!(
+        with_policy(RspamdPolicy::default().with_timeout(Duration::ZERO)).err(),
+        Some(ConfigError::InvalidTimeout)
+    );
+}
+
+#[cfg(feature = "filter")]
+#[test]
+fn test_filter_rules() {
+    use crate::filter::{FilterAction, FilterCondition, FilterRule};
+
+    let with_text = |text: &str| {
+        ServerConfig::builder()
+            .filter_rule(
+                FilterRule::new("a", FilterCondition::any([]), FilterAction::Reject)
+                    .with_text(text),
+            )
+            .build()
+    };
+    assert!(with_text("Not accepted here").is_ok());
+
+    // Tests that the text of replies must fit on one line.
+    assert_eq!(
+        with_text("Not\r\naccepted").err(),
+        Some(ConfigError::InvalidReplyText)
+    );
+}
+
+#[cfg(feature = "attachment")]
+#[test]
+fn test_attachment_policy() {
+    use crate::attachment::{AttachmentPolicy, AttachmentRules};
+
+    let with_domain = |domain| {
+        ServerConfig::builder()
+            .attachment_policy(
+                AttachmentPolicy::default().with_domain(domain, AttachmentRules::allow_all()),
+            )
+            .build()
+    };
+    assert!(with_domain("lab.example.com").is_ok());
+
+    // Tests that the domains with their own rules must be domain names.
+    assert_eq!(
+        with_domain("lab example").err(),
+        Some(ConfigError::InvalidAttachmentDomain)
+    );
+}
+
+#[cfg(feature = "spamassassin")]
+#[test]
+fn test_spamassassin_policy() {
+    use crate::spamassassin::SpamAssassinPolicy;
+
+    let with_policy = |policy| ServerConfig::builder().spamassassin_policy(policy).build();
+    assert!(with_policy(SpamAssassinPolicy::default().with_reject_score(10.0)).is_ok());
+
+    // Tests that thresholds must be numbers, and that the address must have a port.
+    assert_eq!(
+        with_policy(SpamAssassinPolicy::default().with_tag_score(f64::NAN)).err(),
+        Some(ConfigError::InvalidSpamScore)
+    );
+    assert_eq!(
+        with_policy(SpamAssassinPolicy::default().with_reject_score(f64::INFINITY)).err(),
+        Some(ConfigError::InvalidSpamScore)
+    );
+    assert_eq!(
+        with_policy(SpamAssassinPolicy::new("spamd")).err(),
+        Some(ConfigError::InvalidScannerAddress)
+    );
+}
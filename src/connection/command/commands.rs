@@ -19,29 +19,79 @@
 
 use std::io::Result;
 
-use ascii::{AsAsciiStr, AsciiStr};
-use tokio::{io::AsyncWriteExt, net::tcp::WriteHalf};
+use ascii::{AsAsciiStr, AsciiStr, AsciiString};
+use tokio::io::AsyncWriteExt;
 
 use super::{
-    super::{CloseReason, ShouldClose},
+    super::{transport::Writer, CloseReason, ShouldClose, Transaction},
+    helo, path,
+    sender_domain::{self, SenderDomain},
     Command,
 };
-use crate::{connection::DOMAIN, write_fmt_line, write_line};
+#[cfg(feature = "spf")]
+use crate::spf;
+#[cfg(any(feature = "tls", feature = "dnsbl"))]
+use crate::{audit::Audit, session::SessionInfo, ServerConfig};
+use crate::{
+    audit::RejectionSource,
+    config::{HeloCheck, SenderDomainPolicy},
+    event::SessionEvent,
+    handler::{Decision, SessionContext},
+    message::envelope::{self, Envelope, Notify, Recipient, RecipientStatus, Ret},
+    write_fmt_line, write_line, SmtpHandler,
+};
 
-/// Send a `"500 Syntax error - {}"` reply into `write_stream` and return with
-/// [`ShouldClose::Keep`].
+/// The maximum length of the value of the `ENVID` parameter.
+///
+/// [RFC 3461 section 4.4](https://www.rfc-editor.org/rfc/rfc3461.html#section-4.4).
+const ENVID_LENGTH: usize = 100;
+
+/// Send a `"500 Syntax error - {}"` reply into `write_stream`, count the error in `state`, and
+/// return with [`ShouldClose::Keep`].
 ///
 /// # Errors
 ///
 /// - Any errors that could come out of the supplied reader's `write_all` function.
 macro_rules! syntax_err_and_return {
-    ( $write_stream:expr, $error:expr ) => {{
+    ( $write_stream:expr, $state:expr, $error:expr ) => {{
+        $state.errors += 1;
         $crate::write_fmt_line!($write_stream, "500 Syntax error - {}", $error)?;
         return Ok(ShouldClose::Keep); // Should this close the connection?
     }};
 }
 
-/// Reply to an unrecognized command from a client.
+/// Send a `"501 Syntax error in parameters or arguments - {}"` reply into `write_stream`, count
+/// the error in `state`, and return with [`ShouldClose::Keep`].
+///
+/// # Errors
+///
+/// - Any errors that could come out of the supplied reader's `write_all` function.
+macro_rules! argument_err_and_return {
+    ( $write_stream:expr, $state:expr, $error:expr ) => {{
+        $state.errors += 1;
+        $crate::write_fmt_line!(
+            $write_stream,
+            "501 Syntax error in parameters or arguments - {}",
+            $error
+        )?;
+        return Ok(ShouldClose::Keep);
+    }};
+}
+
+/// Send a `"503 Bad sequence of commands"` reply into `write_stream` and return with
+/// [`ShouldClose::Keep`].
+///
+/// # Errors
+///
+/// - Any errors that could come out of the supplied reader's `write_all` function.
+macro_rules! sequence_err_and_return {
+    ( $write_stream:expr ) => {{
+        $crate::write_line!($write_stream, "503 Bad sequence of commands")?;
+        return Ok(ShouldClose::Keep);
+    }};
+}
+
+/// Reply to an unrecognized command from a client, counting it as an error.
 ///
 /// See [`not_implemented`] for commands that are recognized, but not implemented. See [RFC 5321
 /// section 4.2.4](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.2.4) for more details.
@@ -49,7 +99,13 @@ macro_rules! syntax_err_and_return {
 /// # Errors
 ///
 /// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
-pub async fn unrecognized(write_stream: &mut WriteHalf<'_>, _: Command) -> Result<ShouldClose> {
+pub async fn unrecognized<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    _: &mut H,
+    _: Command<'_>,
+) -> Result<ShouldClose> {
+    state.errors += 1;
     write_fmt_line!(write_stream, "500 Command not recognized")?;
 
     Ok(ShouldClose::Keep)
@@ -64,20 +120,102 @@ pub async fn unrecognized(write_stream: &mut WriteHalf<'_>, _: Command) -> Resul
 /// # Errors
 ///
 /// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
-pub async fn not_implemented(write_stream: &mut WriteHalf<'_>, _: Command) -> Result<ShouldClose> {
+pub async fn not_implemented<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    _: &mut SessionContext,
+    _: &mut H,
+    _: Command<'_>,
+) -> Result<ShouldClose> {
     write_fmt_line!(write_stream, "502 Command not implemented")?;
 
     Ok(ShouldClose::Keep)
 }
 
-/// Reply to the hello (`HELO`) command from a client.
+/// Reply to the verify (`VRFY`) command from a client, looking up whether the mailbox that it
+/// gives exists with [`crate::ServerConfig::address_verifier`].
+///
+/// Mailboxes that exist are replied to with `250`, those that do not with `550 5.1.1`, and those
+/// whose lookup fails with `451 4.3.0`. Arguments that are not a mailbox, such as a user name, are
+/// replied to with `252`, as they cannot be verified. Without a verifier, the command is not
+/// implemented, see [`not_implemented`].
+///
+/// [RFC 5321 section 3.5](https://www.rfc-editor.org/rfc/rfc5321.html#section-3.5).
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+#[cfg(feature = "directory")]
+pub async fn verify<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    handler: &mut H,
+    command: Command<'_>,
+) -> Result<ShouldClose> {
+    use crate::directory::{self, Verification};
+
+    let Some(verifier) = state.config.address_verifier() else {
+        return not_implemented(write_stream, state, handler, command).await;
+    };
+    let Some(text) = command.text() else {
+        argument_err_and_return!(write_stream, state, "missing mailbox");
+    };
+    let mailbox = text
+        .as_str()
+        .strip_prefix('<')
+        .and_then(|text| text.strip_suffix('>'))
+        .unwrap_or(text.as_str());
+
+    if !mailbox.as_ascii_str().is_ok_and(path::is_mailbox) {
+        write_line!(
+            write_stream,
+            "252 Cannot VRFY user, but will accept message and attempt delivery"
+        )?;
+        return Ok(ShouldClose::Keep);
+    }
+
+    match directory::check(verifier, mailbox).await {
+        Verification::Exists => write_fmt_line!(write_stream, "250 <{mailbox}>")?,
+        Verification::Unknown => {
+            state.audit.source(RejectionSource::Policy("directory"));
+            write_line!(write_stream, "550 5.1.1 User unknown")?;
+        }
+        Verification::Failed(error) => {
+            println!(
+                "[{}] Address verification failed: {error}",
+                state.session.id()
+            );
+            state.audit.source(RejectionSource::Policy("directory"));
+            write_line!(
+                write_stream,
+                "451 4.3.0 Address verification failed, please try again later"
+            )?;
+        }
+    }
+
+    Ok(ShouldClose::Keep)
+}
+
+/// Reply to the hello (`HELO`) or extended hello (`EHLO`) command from a client.
+///
+/// An accepted `EHLO` is replied to with the service extensions that the server supports, which
+/// are kept in [`SessionContext::extensions`]. Currently, this is only `SIZE`, with
+/// [`crate::ServerConfig::max_message_size`] as its parameter.
+///
+/// The identity is checked against [`crate::ServerConfig::helo_policy`] before it is given to the
+/// handler, with failed syntax rejected with `501` and other rejected checks with `550`.
 ///
 /// [RFC 5321 section 4.1.1.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.1).
+/// [RFC 1870 section 4](https://www.rfc-editor.org/rfc/rfc1870.html#section-4).
 ///
 /// # Errors
 ///
 /// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
-pub async fn hello(write_stream: &mut WriteHalf<'_>, command: Command) -> Result<ShouldClose> {
+pub async fn hello<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    handler: &mut H,
+    command: Command<'_>,
+) -> Result<ShouldClose> {
     /// Parse out the domain name or address literal from the start of the text of a command.
     ///
     /// [RFC 5321 section 4.1.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.2).
@@ -113,15 +251,738 @@ pub async fn hello(write_stream: &mut WriteHalf<'_>, command: Command) -> Result
         )
     }
 
-    let client = match command.text() {
+    let identity = match command.text() {
         Some(t) => match domain_or_literal(t) {
-            Ok(d) => d.as_str(),
-            Err(e) => syntax_err_and_return!(write_stream, e),
+            Ok(d) => Some(d),
+            Err(e) => syntax_err_and_return!(write_stream, state, e),
         },
-        None => "client",
+        None => None,
     };
+    let client = identity.map_or("client", AsciiStr::as_str);
+
+    // `HELO` implies the same clearing of state as `RSET`, even if the identity is rejected.
+    //
+    // <https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.4>
+    state.transaction = None;
+
+    let identity = identity.map(ToOwned::to_owned).unwrap_or_default();
+    // The policy is checked before the handler, so that it can see the annotated failures.
+    let peer = state.session.peer_addr.ip();
+    let config = &state.config;
+    match helo::verify(
+        identity.as_str(),
+        peer,
+        config.helo_policy(),
+        config.resolver(),
+    )
+    .await
+    {
+        Ok(failures) => state.session.helo_failures = failures,
+        Err(check) => {
+            state.session.helo = None;
+            state.session.extended = false;
+            state.session.helo_failures.clear();
+            state.extensions.clear();
+            if check == HeloCheck::Syntax {
+                argument_err_and_return!(
+                    write_stream,
+                    state,
+                    "invalid domain name or address literal"
+                );
+            }
+            state.audit.source(RejectionSource::Policy("helo_policy"));
+            write_fmt_line!(write_stream, "550 HELO identity {check}")?;
+            return Ok(ShouldClose::Keep);
+        }
+    }
+    #[cfg(feature = "spf")]
+    if !verify_spf_helo(write_stream, state, &identity).await? {
+        return Ok(ShouldClose::Keep);
+    }
+    let decision = decide!(write_stream, state, handler.on_ehlo(state, &identity).await);
+
+    state.session.helo = decision.is_accepted().then_some(identity);
+    state.session.extended = decision.is_accepted() && command.verb() == "EHLO";
+    if !decision.is_accepted() {
+        state.session.helo_failures.clear();
+    }
+    if let Some(helo) = &state.session.helo {
+        state.events.send(|| SessionEvent::Helo(helo.clone())).await;
+    }
+    state.extensions.clear();
+
+    let hostname = state.config.hostname();
+    if command.verb() == "EHLO" && decision.is_accepted() {
+        state
+            .extensions
+            .push(AsciiString::from_ascii("SIZE").expect("written in code as ASCII"));
+
+        write_fmt_line!(write_stream, "250-{hostname} greets {client}")?;
+        #[cfg(feature = "tls")]
+        if state.config.offers_tls() && !state.session.is_tls() {
+            state
+                .extensions
+                .push(AsciiString::from_ascii("STARTTLS").expect("written in code as ASCII"));
+            write_line!(write_stream, "250-STARTTLS")?;
+        }
+        write_fmt_line!(write_stream, "250 SIZE {}", state.config.max_message_size())?;
+        return Ok(ShouldClose::Keep);
+    }
+    reply!(
+        write_stream,
+        decision,
+        "250 {hostname} greets {client}",
+        "550 Requested action not taken",
+    );
+
+    Ok(ShouldClose::Keep)
+}
+
+/// Check SPF for the identity given in `HELO`, keeping the result in
+/// [`crate::session::SessionInfo::spf_helo`].
+///
+/// Returns `false` after rejecting the identity if [`crate::ServerConfig::spf_policy`] rejects it.
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+#[cfg(feature = "spf")]
+async fn verify_spf_helo(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    identity: &AsciiStr,
+) -> Result<bool> {
+    let peer = state.session.peer_addr.ip();
+    state.session.spf_helo = spf::verify_helo(&state.config, peer, identity.as_str()).await;
+
+    if state
+        .config
+        .spf_policy()
+        .rejects(state.session.spf_helo.as_ref())
+    {
+        state.session.helo = None;
+        state.session.extended = false;
+        state.session.helo_failures.clear();
+        state.session.spf_helo = None;
+        state.extensions.clear();
+        state.audit.source(RejectionSource::Policy("spf"));
+        write_line!(write_stream, "550 5.7.23 SPF validation failed")?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Check that the domain of the reverse-path of `envelope` exists, if
+/// [`crate::ServerConfig::sender_domain_policy`] asks.
+///
+/// The null reverse-path and address literals have no domain to look up, so they are not checked.
+///
+/// Returns `false` after rejecting or deferring the transaction if the domain does not exist or
+/// could not be looked up.
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+#[expect(
+    clippy::needless_pass_by_ref_mut,
+    reason = "a shared reference to the state is not `Send`, as the state is not `Sync`"
+)]
+async fn verify_sender_domain(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    envelope: &Envelope,
+) -> Result<bool> {
+    let policy = state.config.sender_domain_policy();
+    if policy == SenderDomainPolicy::Off {
+        return Ok(true);
+    }
+    let Some((_, domain)) = envelope
+        .reverse_path()
+        .and_then(|reverse_path| reverse_path.as_str().rsplit_once('@'))
+    else {
+        return Ok(true);
+    };
+    if domain.starts_with('[') {
+        return Ok(true);
+    }
+
+    let result = sender_domain::verify(state.config.resolver(), domain).await;
+    if result == SenderDomain::Exists {
+        return Ok(true);
+    }
+    println!(
+        "[{}] The domain of the sender {domain} could not be confirmed ({result:?})",
+        state.session.id
+    );
+
+    state
+        .audit
+        .source(RejectionSource::Policy("sender_domain_policy"));
+    match result {
+        SenderDomain::NullMx => write_line!(write_stream, "550 5.7.27 Sender address has null MX")?,
+        SenderDomain::Missing if policy == SenderDomainPolicy::Reject => write_line!(
+            write_stream,
+            "550 5.1.8 Sender address domain does not exist"
+        )?,
+        SenderDomain::Missing => write_line!(
+            write_stream,
+            "450 4.1.8 Sender address domain does not exist"
+        )?,
+        SenderDomain::Exists | SenderDomain::TempError => write_line!(
+            write_stream,
+            "451 4.4.3 Sender address domain could not be verified, try again later"
+        )?,
+    }
+
+    Ok(false)
+}
+
+/// Check SPF for the reverse-path of `envelope`, keeping the result in
+/// [`Envelope::spf`].
+///
+/// The null reverse-path has no domain of its own, so it is given the result for the identity
+/// given in `HELO`, per [RFC 7208 section
+/// 2.4](https://www.rfc-editor.org/rfc/rfc7208.html#section-2.4).
+///
+/// Returns `false` after rejecting the reverse-path if [`crate::ServerConfig::spf_policy`] rejects
+/// it.
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+#[cfg(feature = "spf")]
+#[expect(
+    clippy::needless_pass_by_ref_mut,
+    reason = "a shared reference to the state is not `Send`, as the state is not `Sync`"
+)]
+async fn verify_spf_mail_from(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    envelope: &mut Envelope,
+) -> Result<bool> {
+    let peer = state.session.peer_addr.ip();
+    envelope.spf = match &envelope.reverse_path {
+        Some(reverse_path) => {
+            let helo = state.session.helo().map_or("", AsciiStr::as_str);
+            spf::verify_mail_from(&state.config, peer, helo, reverse_path.as_str()).await
+        }
+        None => state.session.spf_helo.clone(),
+    };
+
+    if state.config.spf_policy().rejects(envelope.spf.as_ref()) {
+        state.audit.source(RejectionSource::Policy("spf"));
+        write_line!(write_stream, "550 5.7.23 SPF validation failed")?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Reply to `MAIL` with why the client cannot start mail transactions in this session, if it
+/// cannot, returning whether it replied.
+///
+/// With the `tls` feature, [`crate::tls::TlsPolicy::Required`] requires `STARTTLS` first, and with
+/// the `dnsbl` feature, clients listed with [`crate::dnsbl::DnsblAction::Reject`] are rejected.
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+#[cfg(any(feature = "tls", feature = "dnsbl"))]
+async fn refuse_transactions(
+    write_stream: &mut Writer,
+    session: &SessionInfo,
+    #[cfg_attr(
+        not(feature = "tls"),
+        expect(
+            unused_variables,
+            reason = "only `STARTTLS` is required by the configuration"
+        )
+    )]
+    config: &ServerConfig,
+    audit: &Audit,
+) -> Result<bool> {
+    // <https://www.rfc-editor.org/rfc/rfc3207.html#section-4>
+    #[cfg(feature = "tls")]
+    if config.tls_policy() == crate::tls::TlsPolicy::Required && !session.is_tls() {
+        audit.source(RejectionSource::Policy("tls_policy"));
+        write_line!(
+            write_stream,
+            "530 5.7.0 Must issue a STARTTLS command first"
+        )?;
+        return Ok(true);
+    }
+
+    #[cfg(feature = "dnsbl")]
+    if let Some(dnsbl) = session.dnsbl().filter(|dnsbl| dnsbl.rejects()) {
+        audit.source(RejectionSource::Policy("dnsbl"));
+        write_fmt_line!(
+            write_stream,
+            "550 5.7.1 Client host [{}] blocked using {}",
+            session.peer_addr.ip().to_canonical(),
+            dnsbl.zone()
+        )?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Defer `MAIL` with the null reverse-path in `envelope` once the session has started as many
+/// mail transactions with it as [`crate::ServerConfig::null_sender_policy`] allows, returning
+/// whether it was deferred.
+///
+/// Notifications must be accepted, but only need to be deferred to limit backscatter.
+///
+/// [RFC 5321 section 4.5.5](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.5).
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+#[expect(
+    clippy::needless_pass_by_ref_mut,
+    reason = "a shared reference to the state is not `Send`, as the state is not `Sync`"
+)]
+async fn defer_null_sender(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    envelope: &Envelope,
+) -> Result<bool> {
+    let max = state.config.null_sender_policy().max_transactions();
+    if !envelope.is_null_sender() || max.is_none_or(|max| state.null_senders < max) {
+        return Ok(false);
+    }
+
+    state
+        .audit
+        .source(RejectionSource::Limit("null_sender_policy"));
+    write_line!(
+        write_stream,
+        "450 4.7.1 Too many messages with a null reverse-path, try again later"
+    )?;
+    Ok(true)
+}
 
-    write_fmt_line!(write_stream, "250 {DOMAIN} greets {client}")?;
+/// Reply to the mail (`MAIL`) command from a client, starting a new mail transaction.
+///
+/// [RFC 5321 section 4.1.1.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.2).
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+pub async fn mail<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    handler: &mut H,
+    command: Command<'_>,
+) -> Result<ShouldClose> {
+    // A mail transaction can only be started after `HELO` and cannot be nested.
+    //
+    // <https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.4>
+    if state.session.helo.is_none() || state.transaction.is_some() {
+        sequence_err_and_return!(write_stream);
+    }
+
+    #[cfg(any(feature = "tls", feature = "dnsbl"))]
+    if refuse_transactions(write_stream, &state.session, &state.config, &state.audit).await? {
+        return Ok(ShouldClose::Keep);
+    }
+
+    // <https://www.rfc-editor.org/rfc/rfc4954.html#section-6>
+    if state.overrides.require_auth && state.session.authenticated_user.is_none() {
+        state.audit.source(RejectionSource::Policy("require_auth"));
+        write_line!(write_stream, "530 5.7.0 Authentication required")?;
+        return Ok(ShouldClose::Keep);
+    }
+
+    let Some(text) = command.text() else {
+        argument_err_and_return!(write_stream, state, "missing reverse-path");
+    };
+    let (reverse_path, parameters) = match path::parse(text, "FROM") {
+        Ok(parsed) => parsed,
+        Err(e) => argument_err_and_return!(write_stream, state, e),
+    };
+
+    let reverse_path = if reverse_path.is_empty() {
+        None
+    } else if path::is_mailbox(reverse_path) {
+        Some(reverse_path.to_owned())
+    } else {
+        argument_err_and_return!(write_stream, state, "reverse-path is not a mailbox");
+    };
+    let mut envelope = Envelope::new(reverse_path);
+
+    if defer_null_sender(write_stream, state, &envelope).await? {
+        return Ok(ShouldClose::Keep);
+    }
+
+    for parameter in parameters.as_str().split_whitespace() {
+        let (keyword, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+
+        // The size that the client expects the message to be.
+        //
+        // <https://www.rfc-editor.org/rfc/rfc1870.html#section-6>
+        if keyword.eq_ignore_ascii_case("SIZE") {
+            let Ok(size) = value.parse::<usize>() else {
+                argument_err_and_return!(write_stream, state, "invalid SIZE value");
+            };
+            if size > state.config.max_message_size() {
+                state
+                    .audit
+                    .source(RejectionSource::Limit("max_message_size"));
+                write_line!(
+                    write_stream,
+                    "552 Message size exceeds fixed maximum message size"
+                )?;
+                return Ok(ShouldClose::Keep);
+            }
+        // Which parts of the message to return in a failure notification.
+        //
+        // <https://www.rfc-editor.org/rfc/rfc3461.html#section-4.3>
+        } else if keyword.eq_ignore_ascii_case("RET") {
+            if envelope.ret.is_some() {
+                argument_err_and_return!(write_stream, state, "duplicate RET parameter");
+            }
+            let Some(ret) = Ret::parse(value) else {
+                argument_err_and_return!(write_stream, state, "invalid RET value");
+            };
+            envelope.ret = Some(ret);
+        // The identifier of the transaction, for use in notifications.
+        //
+        // <https://www.rfc-editor.org/rfc/rfc3461.html#section-4.4>
+        } else if keyword.eq_ignore_ascii_case("ENVID") {
+            if envelope.envid.is_some() {
+                argument_err_and_return!(write_stream, state, "duplicate ENVID parameter");
+            }
+            if value.is_empty() || value.len() > ENVID_LENGTH || !envelope::is_xtext(value) {
+                argument_err_and_return!(write_stream, state, "invalid ENVID value");
+            }
+            envelope.envid = value.as_ascii_str().ok().map(ToOwned::to_owned);
+        } else {
+            write_line!(
+                write_stream,
+                "555 MAIL FROM parameters not recognized or not implemented"
+            )?;
+            return Ok(ShouldClose::Keep);
+        }
+    }
+
+    if !verify_sender_domain(write_stream, state, &envelope).await? {
+        return Ok(ShouldClose::Keep);
+    }
+
+    #[cfg(feature = "spf")]
+    if !verify_spf_mail_from(write_stream, state, &mut envelope).await? {
+        return Ok(ShouldClose::Keep);
+    }
+
+    let decision = decide!(write_stream, state, handler.on_mail(state, &envelope).await);
+
+    if decision.is_accepted() {
+        if envelope.is_null_sender() {
+            state.null_senders += 1;
+        }
+        state
+            .events
+            .send(|| SessionEvent::MailFrom(envelope.clone()))
+            .await;
+        state.transaction = Some(Transaction::new(envelope));
+    }
+    reply!(
+        write_stream,
+        decision,
+        "250 OK",
+        "550 Requested action not taken: mailbox unavailable",
+    );
+
+    Ok(ShouldClose::Keep)
+}
+
+/// Reply to the recipient (`RCPT`) command from a client, adding a forward-path to the mail
+/// transaction.
+///
+/// [RFC 5321 section 4.1.1.3](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.3).
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+pub async fn recipient<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    handler: &mut H,
+    command: Command<'_>,
+) -> Result<ShouldClose> {
+    let Some(transaction) = state.transaction.as_ref() else {
+        sequence_err_and_return!(write_stream);
+    };
+    let max_recipients = match state.config.null_sender_policy().max_recipients() {
+        Some(max) if transaction.envelope.is_null_sender() => {
+            max.min(state.config.max_recipients())
+        }
+        _ => state.config.max_recipients(),
+    };
+    let is_over_limit = transaction.envelope.accepted().count() >= max_recipients;
+
+    let Some(text) = command.text() else {
+        argument_err_and_return!(write_stream, state, "missing forward-path");
+    };
+    let (forward_path, parameters) = match path::parse(text, "TO") {
+        Ok(parsed) => parsed,
+        Err(e) => argument_err_and_return!(write_stream, state, e),
+    };
+
+    if !(path::is_mailbox(forward_path) || path::is_postmaster(forward_path)) {
+        argument_err_and_return!(write_stream, state, "forward-path is not a mailbox");
+    }
+    let mut recipient = Recipient::new(forward_path.to_owned());
+
+    for parameter in parameters.as_str().split_whitespace() {
+        let (keyword, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+
+        // When to send notifications about delivery to this recipient.
+        //
+        // <https://www.rfc-editor.org/rfc/rfc3461.html#section-4.1>
+        if keyword.eq_ignore_ascii_case("NOTIFY") {
+            if recipient.notify.is_some() {
+                argument_err_and_return!(write_stream, state, "duplicate NOTIFY parameter");
+            }
+            let Some(notify) = Notify::parse(value) else {
+                argument_err_and_return!(write_stream, state, "invalid NOTIFY value");
+            };
+            recipient.notify = Some(notify);
+        // The recipient as originally given, before any forwarding.
+        //
+        // <https://www.rfc-editor.org/rfc/rfc3461.html#section-4.2>
+        } else if keyword.eq_ignore_ascii_case("ORCPT") {
+            if recipient.orcpt.is_some() {
+                argument_err_and_return!(write_stream, state, "duplicate ORCPT parameter");
+            }
+            if !envelope::is_orcpt(value) {
+                argument_err_and_return!(write_stream, state, "invalid ORCPT value");
+            }
+            recipient.orcpt = value.as_ascii_str().ok().map(ToOwned::to_owned);
+        } else {
+            write_line!(
+                write_stream,
+                "555 RCPT TO parameters not recognized or not implemented"
+            )?;
+            return Ok(ShouldClose::Keep);
+        }
+    }
+
+    // Clients must be able to try the recipients past the limit again in a later transaction.
+    //
+    // <https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.1.10>
+    if is_over_limit {
+        recipient.status = RecipientStatus::Deferred;
+        state.audit.source(RejectionSource::Limit(
+            if max_recipients < state.config.max_recipients() {
+                "null_sender_policy"
+            } else {
+                "max_recipients"
+            },
+        ));
+        write_line!(write_stream, "452 Too many recipients")?;
+    } else {
+        let decision = decide!(
+            write_stream,
+            state,
+            handler.on_rcpt(state, &recipient).await
+        );
+        #[cfg(any(feature = "directory", feature = "greylist"))]
+        let decision = verify_recipient(state, &recipient, decision).await;
+
+        recipient.status = match &decision {
+            Decision::Defer(_) => RecipientStatus::Deferred,
+            Decision::Reply(response) if response.is_transient_failure() => {
+                RecipientStatus::Deferred
+            }
+            decision if decision.is_accepted() => RecipientStatus::Accepted,
+            _ => RecipientStatus::Rejected,
+        };
+        reply!(
+            write_stream,
+            decision,
+            "250 OK",
+            "550 Requested action not taken: mailbox unavailable",
+        );
+    }
+
+    if recipient.status == RecipientStatus::Accepted {
+        state
+            .events
+            .send(|| SessionEvent::RcptTo(recipient.clone()))
+            .await;
+    }
+    let Some(transaction) = state.transaction.as_mut() else {
+        unreachable!("the handler cannot end the mail transaction")
+    };
+    transaction.envelope.recipients.push(recipient);
+
+    Ok(ShouldClose::Keep)
+}
+
+/// Check `recipient` against [`crate::ServerConfig::address_verifier`] and
+/// [`crate::ServerConfig::greylist_policy`] if the handler accepted it in `decision`, returning
+/// the decision to reply with.
+#[cfg(any(feature = "directory", feature = "greylist"))]
+#[expect(
+    clippy::needless_pass_by_ref_mut,
+    reason = "a shared reference to the state is not `Send`, as the state is not `Sync`"
+)]
+async fn verify_recipient(
+    state: &mut SessionContext,
+    recipient: &Recipient,
+    decision: Decision,
+) -> Decision {
+    #[cfg(feature = "directory")]
+    let decision = {
+        let accepted = decision.is_accepted();
+        let decision =
+            crate::directory::apply(&state.config, &state.session, recipient, decision).await;
+        if accepted && !decision.is_accepted() {
+            state.audit.source(RejectionSource::Policy("directory"));
+        }
+        decision
+    };
+    #[cfg(feature = "greylist")]
+    let decision = {
+        let accepted = decision.is_accepted();
+        let decision = crate::greylist::apply(
+            &state.config,
+            &state.session,
+            state
+                .transaction
+                .as_ref()
+                .map(|transaction| &transaction.envelope),
+            recipient,
+            decision,
+        )
+        .await;
+        if accepted && !decision.is_accepted() {
+            state.audit.source(RejectionSource::Policy("greylist"));
+        }
+        decision
+    };
+
+    decision
+}
+
+/// Reply to the data (`DATA`) command from a client, signaling that the following lines will be
+/// the data of the message.
+///
+/// The data itself is read by the session after this returns, see
+/// [`crate::connection::data::receive`].
+///
+/// [RFC 5321 section 4.1.1.4](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.4).
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+pub async fn data<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    handler: &mut H,
+    command: Command<'_>,
+) -> Result<ShouldClose> {
+    if command.text().is_some() {
+        argument_err_and_return!(write_stream, state, "DATA does not take arguments");
+    }
+
+    let Some(transaction) = state.transaction.as_ref() else {
+        sequence_err_and_return!(write_stream);
+    };
+    if transaction.envelope.accepted().next().is_none() {
+        write_line!(write_stream, "554 No valid recipients")?;
+        return Ok(ShouldClose::Keep);
+    }
+
+    let decision = decide!(write_stream, state, handler.on_data_start(state).await);
+
+    state.awaiting_data = decision.is_accepted();
+    reply!(
+        write_stream,
+        decision,
+        "354 Start mail input; end with <CRLF>.<CRLF>",
+        "554 Transaction failed",
+    );
+
+    Ok(ShouldClose::Keep)
+}
+
+/// Reply to the reset (`RSET`) command from a client, aborting the current mail transaction.
+///
+/// [RFC 5321 section 4.1.1.5](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.5).
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+pub async fn reset<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    _: &mut H,
+    command: Command<'_>,
+) -> Result<ShouldClose> {
+    if command.text().is_some() {
+        argument_err_and_return!(write_stream, state, "RSET does not take arguments");
+    }
+
+    state.transaction = None;
+    write_line!(write_stream, "250 OK")?;
+
+    Ok(ShouldClose::Keep)
+}
+
+/// Reply to the start TLS (`STARTTLS`) command from a client, after which the connection is
+/// encrypted if it is offered, see [`crate::ServerConfig::tls_policy`].
+///
+/// The handshake itself is made by [`crate::connection::handle`] once this returns.
+///
+/// [RFC 3207 section 4](https://www.rfc-editor.org/rfc/rfc3207.html#section-4).
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+#[cfg(feature = "tls")]
+pub async fn start_tls<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    _: &mut H,
+    command: Command<'_>,
+) -> Result<ShouldClose> {
+    if !state.config.offers_tls() {
+        write_line!(write_stream, "502 Command not implemented")?;
+        return Ok(ShouldClose::Keep);
+    }
+    if command.text().is_some() {
+        argument_err_and_return!(write_stream, state, "STARTTLS does not take arguments");
+    }
+    if state.session.is_tls() {
+        sequence_err_and_return!(write_stream);
+    }
+
+    write_line!(write_stream, "220 2.0.0 Ready to start TLS")?;
+    state.starting_tls = true;
+
+    Ok(ShouldClose::Keep)
+}
+
+/// Reply to the no-op (`NOOP`) command from a client.
+///
+/// Any text given with the command is ignored.
+///
+/// [RFC 5321 section 4.1.1.9](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.9).
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+pub async fn noop<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    _: &mut SessionContext,
+    _: &mut H,
+    _: Command<'_>,
+) -> Result<ShouldClose> {
+    write_line!(write_stream, "250 OK")?;
 
     Ok(ShouldClose::Keep)
 }
@@ -133,7 +994,16 @@ pub async fn hello(write_stream: &mut WriteHalf<'_>, command: Command) -> Result
 /// # Errors
 ///
 /// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
-pub async fn quit(write_stream: &mut WriteHalf<'_>, _: Command) -> Result<ShouldClose> {
-    write_line!(write_stream, "221 Bye")?;
+#[expect(
+    clippy::needless_pass_by_ref_mut,
+    reason = "every command takes the same parameters"
+)]
+pub async fn quit<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    _: &mut H,
+    _: Command<'_>,
+) -> Result<ShouldClose> {
+    write_fmt_line!(write_stream, "221 {}", state.config.quit_text())?;
     Ok(ShouldClose::Close(CloseReason::Quit))
 }
@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Checks the identity that clients give in `HELO` against [`HeloPolicy`].
+//!
+//! See [`verify`].
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::{
+    config::{HeloAction, HeloCheck, HeloPolicy},
+    resolver::Resolver,
+    str::max_lengths,
+};
+
+/// The identity given in `HELO`, as far as it could be parsed.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Identity<'a> {
+    /// A domain name, such as `mail.example.com`.
+    Domain(&'a str),
+    /// An address literal, such as `[192.0.2.1]`, or `None` for a general address literal that
+    /// is not an IP address.
+    Literal(Option<IpAddr>),
+    /// Neither a domain name nor an address literal.
+    Invalid,
+}
+
+impl<'a> Identity<'a> {
+    /// Parse the identity given in `HELO`.
+    ///
+    /// [RFC 5321 section 4.1.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.2).
+    /// [RFC 5321 section 4.1.3](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.3).
+    pub fn parse(identity: &'a str) -> Self {
+        let Some(literal) = identity
+            .strip_prefix('[')
+            .and_then(|literal| literal.strip_suffix(']'))
+        else {
+            return if is_domain(identity) {
+                Self::Domain(identity)
+            } else {
+                Self::Invalid
+            };
+        };
+
+        if literal
+            .get(..5)
+            .is_some_and(|tag| tag.eq_ignore_ascii_case("IPv6:"))
+        {
+            return literal[5..]
+                .parse::<Ipv6Addr>()
+                .map_or(Self::Invalid, |address| Self::Literal(Some(address.into())));
+        }
+        if let Ok(address) = literal.parse::<Ipv4Addr>() {
+            return Self::Literal(Some(address.into()));
+        }
+
+        // `General-address-literal = Standardized-tag ":" 1*dcontent`
+        match literal.split_once(':') {
+            Some((tag, content))
+                if is_label(tag)
+                    && !content.is_empty()
+                    && content
+                        .bytes()
+                        .all(|byte| matches!(byte, 33..=90 | 94..=126)) =>
+            {
+                Self::Literal(None)
+            }
+            _ => Self::Invalid,
+        }
+    }
+}
+
+/// Check whether `domain` is a domain name of dot-separated labels.
+pub fn is_domain(domain: &str) -> bool {
+    !domain.is_empty() && domain.len() <= max_lengths::DOMAIN && domain.split('.').all(is_label)
+}
+
+/// Check whether `label` is letters, digits, and hyphens, starting and ending with a letter or
+/// digit.
+fn is_label(label: &str) -> bool {
+    let bytes = label.as_bytes();
+
+    bytes.first().is_some_and(u8::is_ascii_alphanumeric)
+        && bytes.last().is_some_and(u8::is_ascii_alphanumeric)
+        && bytes
+            .iter()
+            .all(|byte| byte.is_ascii_alphanumeric() || *byte == b'-')
+}
+
+/// Make the checks of `policy` on the identity given in `HELO` by the client at `peer`, looking up
+/// domain names with `resolver`.
+///
+/// Checks that are not asked for are skipped, as is everything but the syntax check if that
+/// fails.
+///
+/// # Errors
+///
+/// - The first check that failed with [`HeloAction::Reject`].
+pub async fn verify(
+    identity: &str,
+    peer: IpAddr,
+    policy: HeloPolicy,
+    resolver: &impl Resolver,
+) -> Result<Vec<HeloCheck>, HeloCheck> {
+    let mut failures = Vec::new();
+    let mut fail = |check| match policy.action(check) {
+        HeloAction::Accept => Ok(()),
+        HeloAction::Annotate => {
+            failures.push(check);
+            Ok(())
+        }
+        HeloAction::Reject => Err(check),
+    };
+
+    match Identity::parse(identity) {
+        Identity::Invalid => fail(HeloCheck::Syntax)?,
+        Identity::Domain(domain) => {
+            if policy.resolves() != HeloAction::Accept && !resolves(resolver, domain).await {
+                fail(HeloCheck::Resolves)?;
+            }
+        }
+        Identity::Literal(address) => {
+            if address.is_none_or(|address| address.to_canonical() != peer.to_canonical()) {
+                fail(HeloCheck::MatchesPeer)?;
+            }
+        }
+    }
+
+    Ok(failures)
+}
+
+/// Check whether `domain` resolves to at least one address with `resolver`.
+async fn resolves(resolver: &impl Resolver, domain: &str) -> bool {
+    resolver
+        .lookup_ip(domain)
+        .await
+        .is_ok_and(|addresses| !addresses.is_empty())
+}
@@ -20,40 +20,229 @@
 //! See [`handle`].
 
 use std::{
+    borrow::Cow,
     fmt::{Debug, Display},
     ops::Range,
+    time::Instant,
 };
 
-use ascii::{AsciiStr, AsciiString, IntoAsciiString};
+use ascii::{AsMutAsciiStr, AsciiStr};
 use tokio::io::AsyncWriteExt;
 
-use super::ShouldClose;
-use crate::str::CRLF;
+use super::{line::Line, transport::Writer, CloseReason, ShouldClose};
+use crate::{
+    audit::RejectionSource,
+    handler::{Decision, SessionContext},
+    redact::{self, REDACTED},
+    server::SessionGuard,
+    str::CRLF,
+    write_fmt_line, write_line, SmtpHandler,
+};
 
 #[macro_use]
 mod commands;
+mod helo;
+mod path;
+mod sender_domain;
+
+pub use helo::is_domain;
 #[cfg(test)]
 mod test;
 
-/// Reply to a line from the client in an SMTP session.
+/// The reply code that tells the client that its credentials were not accepted.
+///
+/// [RFC 4954 section 6](https://www.rfc-editor.org/rfc/rfc4954.html#section-6).
+const AUTH_FAILED: u16 = 535;
+
+/// Reply to a line from the client in an SMTP session, holding the client to
+/// [`crate::config::CommandLimits`] and [`crate::config::AuthLimits`].
+///
+/// Once the client makes [`crate::config::CommandLimits::max_errors`] errors in a row, or fails to
+/// authenticate too often, it is told so with `421` and the session is closed.
 ///
 /// # Errors
 ///
 /// [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
-pub async fn handle(
-    write_stream: &mut tokio::net::tcp::WriteHalf<'_>,
-    line: String,
+pub async fn handle<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    handler: &mut H,
+    line: Line<'_>,
+    server: &SessionGuard,
 ) -> std::io::Result<ShouldClose> {
-    if line.trim().is_empty() {
+    if matches!(&line, Line::Complete(line) if line.trim_ascii().is_empty()) {
         return Ok(ShouldClose::Keep);
     }
+    state.audit.command(match &line {
+        Line::Complete(line) => Some(line),
+        Line::TooLong => None,
+    });
+
+    if let ShouldClose::Close(reason) = pace(write_stream, state).await? {
+        return Ok(ShouldClose::Close(reason));
+    }
+
+    let errors = state.errors;
+    let auth_failures = state.auth_failures;
+    let should_close = match line {
+        Line::Complete(line) => reply(write_stream, state, handler, line).await?,
+        // The rest of the line was discarded, so the client is still in sync.
+        Line::TooLong => {
+            state.errors += 1;
+            state
+                .audit
+                .source(RejectionSource::Limit("max_command_line"));
+            write_line!(write_stream, "500 Line too long")?;
+            ShouldClose::Keep
+        }
+    };
+
+    if state.auth_failures > auth_failures {
+        if let ShouldClose::Close(reason) = limit_auth(write_stream, state, server).await? {
+            return Ok(ShouldClose::Close(reason));
+        }
+    }
+
+    if state.errors == errors {
+        state.errors = 0;
+        return Ok(should_close);
+    }
+    state.total_errors += 1;
+    if state
+        .config
+        .command_limits()
+        .max_errors()
+        .is_some_and(|max| state.errors >= max)
+    {
+        state.audit.source(RejectionSource::Limit("command_limits"));
+        write_fmt_line!(
+            write_stream,
+            "421 4.7.0 {} Too many errors",
+            state.config.hostname()
+        )?;
+        return Ok(ShouldClose::Close(CloseReason::TooManyErrors));
+    }
+
+    Ok(should_close)
+}
+
+/// Hold the client to [`crate::config::CommandLimits`] and [`crate::config::Tarpit`] before
+/// replying to its command.
+///
+/// Past [`crate::config::CommandLimits::max_command_rate`] or
+/// [`crate::config::Tarpit::max_duration`], the client is told so with `421` and this returns with
+/// [`ShouldClose::Close`]. Otherwise, this waits for the next delay of the tarpit if the client was
+/// flagged, or for [`crate::config::CommandLimits::error_delay`] if it made an error in its last
+/// command.
+///
+/// # Errors
+///
+/// [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+async fn pace(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+) -> std::io::Result<ShouldClose> {
+    let limits = state.config.command_limits();
+
+    if let Some((max, period)) = limits.max_command_rate() {
+        let now = Instant::now();
+        while state
+            .recent_commands
+            .front()
+            .is_some_and(|&then| now.duration_since(then) > period)
+        {
+            state.recent_commands.pop_front();
+        }
+        state.recent_commands.push_back(now);
+
+        if state.recent_commands.len() > max {
+            state.audit.source(RejectionSource::Limit("command_limits"));
+            write_fmt_line!(
+                write_stream,
+                "421 4.7.0 {} Too many commands",
+                state.config.hostname()
+            )?;
+            return Ok(ShouldClose::Close(CloseReason::TooManyCommands));
+        }
+    }
+
+    if let Some(delay) = state.tarpit_delay {
+        let tarpit = state.config.tarpit();
+        if state.tarpitted_for >= tarpit.max_duration() {
+            state.audit.source(RejectionSource::Policy("tarpit"));
+            write_fmt_line!(
+                write_stream,
+                "421 4.7.0 {} {}",
+                state.config.hostname(),
+                state.config.unavailable_text()
+            )?;
+            return Ok(ShouldClose::Close(CloseReason::Tarpitted));
+        }
+
+        tokio::time::sleep(delay).await;
+        state.tarpitted_for += delay;
+        state.tarpit_delay = Some(tarpit.next_delay(delay));
+    } else if state.errors > 0 && !limits.error_delay().is_zero() {
+        tokio::time::sleep(limits.error_delay()).await;
+    }
+
+    Ok(ShouldClose::Keep)
+}
+
+/// Hold the client to [`crate::config::AuthLimits`] after its credentials were not accepted.
+///
+/// Past [`crate::config::AuthLimits::max_failures`] for the session or
+/// [`crate::config::AuthLimits::max_failure_rate`] for its address, the client is told so with `421`
+/// and this returns with [`ShouldClose::Close`]. Otherwise, this waits for
+/// [`crate::config::AuthLimits::failure_delay_after`] the failures of the session.
+///
+/// # Errors
+///
+/// [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+#[expect(
+    clippy::needless_pass_by_ref_mut,
+    reason = "a shared reference to the state is not `Send`, as the state is not `Sync`"
+)]
+async fn limit_auth(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    server: &SessionGuard,
+) -> std::io::Result<ShouldClose> {
+    let limits = state.config.auth_limits();
+    let failures = state.auth_failures;
+    let is_banned = server.fail_auth(limits);
+
+    if is_banned || limits.max_failures().is_some_and(|max| failures >= max) {
+        state.audit.source(RejectionSource::Limit("auth_limits"));
+        write_fmt_line!(
+            write_stream,
+            "421 4.7.0 {} Too many failed authentication attempts",
+            state.config.hostname()
+        )?;
+        return Ok(ShouldClose::Close(CloseReason::TooManyAuthFailures));
+    }
 
+    tokio::time::sleep(limits.failure_delay_after(failures)).await;
+    Ok(ShouldClose::Keep)
+}
+
+/// Reply to a command line from the client.
+///
+/// # Errors
+///
+/// [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+async fn reply<H: SmtpHandler>(
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    handler: &mut H,
+    line: &mut [u8],
+) -> std::io::Result<ShouldClose> {
     // RFC 5321 section 2.3.8 specifies that lines ending with anything other than `CRLF` must not
     // be recognized.
     //
     // <https://www.rfc-editor.org/rfc/rfc5321.html#section-2.3.8>
-    if !line.ends_with(CRLF) {
-        syntax_err_and_return!(write_stream, "no trailing CRLF");
+    if !line.ends_with(CRLF.as_bytes()) {
+        syntax_err_and_return!(write_stream, state, "no trailing CRLF");
     }
 
     // RFC 5321 uses US-ASCII, specifically ANSI X3.4-1968 (reference 6).
@@ -61,35 +250,69 @@ pub async fn handle(
     // for the purposes of this library.
     //
     // <https://www.rfc-editor.org/rfc/rfc5321.html#ref-6>
-    let Ok(line) = line.into_ascii_string() else {
-        syntax_err_and_return!(write_stream, "invalid character encoding");
+    let Ok(line) = line.as_mut_ascii_str() else {
+        syntax_err_and_return!(write_stream, state, "invalid character encoding");
     };
 
-    let command = match parse(line) {
+    let mut command = match parse(line) {
         Ok(c) => c,
-        Err(e) => syntax_err_and_return!(write_stream, e),
+        Err(e) => syntax_err_and_return!(write_stream, state, e),
     };
+    command.credential = state.audit.awaits_credential();
+    state.commands += 1;
+    // Times the command until it is replied to, at the end of this function.
+    #[cfg(feature = "metrics")]
+    let _timer = crate::metrics::command(command.verb().as_str());
+
+    // Anything but accepting is replied with in place of the command.
+    let decision = decide!(
+        write_stream,
+        state,
+        handler.on_command(state, &command).await
+    );
+    if decision != Decision::Accept {
+        if matches!(&decision, Decision::Reply(response) if response.code() == AUTH_FAILED) {
+            state.auth_failures += 1;
+        }
+        reply!(
+            write_stream,
+            decision,
+            "250 OK",
+            "550 Requested action not taken",
+        );
+        return Ok(ShouldClose::Keep);
+    }
 
     macro_rules! command {
         ($command:ident) => {
-            commands::$command(write_stream, command).await
+            commands::$command(write_stream, state, handler, command).await
         };
     }
 
     // Currently targeting section the minimum implementation set of [RFC 5321 section
     // 4.5.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.1).
     match command.verb().as_str() {
-        "HELO" => command!(hello),
+        "HELO" | "EHLO" => command!(hello),
+        "MAIL" => command!(mail),
+        "RCPT" => command!(recipient),
+        "DATA" => command!(data),
+        "RSET" => command!(reset),
+        "NOOP" => command!(noop),
         "QUIT" => command!(quit),
-        "EHLO" | "MAIL" | "RCPT" | "DATA" | "RSET" | "NOOP" | "VRFY" => {
-            command!(not_implemented)
-        }
+        #[cfg(feature = "tls")]
+        "STARTTLS" => command!(start_tls),
+        #[cfg(feature = "directory")]
+        "VRFY" => command!(verify),
+        #[cfg(not(feature = "directory"))]
+        "VRFY" => command!(not_implemented),
         _ => command!(unrecognized),
     }
 }
 
 /// Parse a line as a command.
-fn parse(mut line: AsciiString) -> Result<Command, CommandError> {
+///
+/// The verb is set to uppercase in place, and the command borrows the rest of `line` as it is.
+fn parse(line: &mut AsciiStr) -> Result<Command<'_>, CommandError> {
     /// Trim the line of leading and trailing whitespace.
     ///
     /// RFC 5321 section 4.1.1 recommends to allow for trailing whitespace.
@@ -147,7 +370,7 @@ fn parse(mut line: AsciiString) -> Result<Command, CommandError> {
     }
 
     // Will not error because of emptiness, as this was already checked above.
-    let trimmed = trim(&line).ok_or(CommandError::OnlyWhitespace)?;
+    let trimmed = trim(line).ok_or(CommandError::OnlyWhitespace)?;
     let trimmed_str = &line[trimmed.clone()];
 
     let (verb, text, multiline) = split_command(trimmed_str);
@@ -168,23 +391,30 @@ fn parse(mut line: AsciiString) -> Result<Command, CommandError> {
     //
     // Note that the mailbox-local part of an email address (ex. `smith` in `smith@example.com`) is
     // the only case-sensitive part of an SMTP command, so `text` is not be set to uppercase.
-    let verb_str: &mut AsciiStr = line[verb.clone()].as_mut();
-    verb_str.make_ascii_uppercase();
+    line[verb.clone()].make_ascii_uppercase();
 
     Ok(Command {
-        line,
+        line: &*line,
         trimmed,
         verb,
         text,
         multiline,
+        credential: false,
     })
 }
 
-/// One line of an SMTP command.
+/// One line of an SMTP command, as parsed from a line sent by the client.
+///
+/// The command borrows the buffer that the line was read into, which is reused for the next line,
+/// so anything that is kept past the command has to be copied out of it.
+///
+/// See [`crate::handler::SmtpHandler::on_command`].
 #[derive(PartialEq, Eq, Clone)]
-struct Command {
+pub struct Command<'a> {
     /// The entire line, unmodified except for the [`Self::verb`] range being set to uppercase.
-    line: AsciiString,
+    ///
+    /// Borrowed from the buffer that the line was read into, rather than copied out of it.
+    line: &'a AsciiStr,
     /// The range over [`Self::line`] without leading and trailing whitespace.
     trimmed: Range<usize>,
     /// The range over [`Self::line`] containing the verb of the command.
@@ -195,48 +425,79 @@ struct Command {
     ///
     /// Derived from the character that [`Self::verb`] and [`Self::text`] were split by.
     multiline: MultiLine,
+    /// Whether the line was sent in reply to a `334` challenge, so it carries credentials.
+    credential: bool,
 }
 
 // Consuming implementation is not complete
-impl Command {
-    /// Get the entire line as a string slice, unmodified unmodified except for the [`Self::verb`]
+impl<'a> Command<'a> {
+    /// Get the entire line as a string slice, unmodified except for the [`Self::verb`]
     /// range being set to uppercase.
-    pub fn line(&self) -> &AsciiStr {
-        self.line.as_ref()
+    #[must_use]
+    pub const fn line(&self) -> &'a AsciiStr {
+        self.line
     }
 
     /// Get the line with leading and trailing whitespace stripped as a string slice.
-    pub fn trimmed(&self) -> &AsciiStr {
+    #[must_use]
+    pub fn trimmed(&self) -> &'a AsciiStr {
         self.get(&self.trimmed)
     }
 
     /// Get the verb of the command as an uppercase string slice.
-    pub fn verb(&self) -> &AsciiStr {
+    #[must_use]
+    pub fn verb(&self) -> &'a AsciiStr {
         self.get(&self.verb)
     }
 
     /// Get the text of the command as a string slice.
-    pub fn text(&self) -> Option<&AsciiStr> {
+    #[must_use]
+    pub fn text(&self) -> Option<&'a AsciiStr> {
         let range = self.text.as_ref()?;
 
         Some(self.get(range))
     }
 
+    /// Get whether the line was sent in reply to a `334` challenge, so it carries credentials
+    /// rather than a command.
+    ///
+    /// [RFC 4954 section 4](https://www.rfc-editor.org/rfc/rfc4954.html#section-4).
+    #[must_use]
+    pub const fn is_credential(&self) -> bool {
+        self.credential
+    }
+
     /// Get the [`MultiLine`] type of the command.
     ///
     /// Derived from the character that [`Self::verb`] and [`Self::text`] were split by.
-    pub const fn multiline(&self) -> MultiLine {
+    const fn multiline(&self) -> MultiLine {
         self.multiline
     }
 
-    /// Get a range of the internal [`AsciiString`] as a string slice.
-    fn get(&self, range: &Range<usize>) -> &AsciiStr {
+    /// Get a range of [`Self::line`] as a string slice.
+    fn get(&self, range: &Range<usize>) -> &'a AsciiStr {
         &self.line[range.clone()]
     }
 }
 
-impl Debug for Command {
+impl Debug for Command<'_> {
+    /// Formats without credentials, leaving out everything but the verb of `AUTH` with an initial
+    /// response, and everything of [`Self::is_credential`].
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.credential {
+            return f
+                .debug_struct("Command")
+                .field("line", &REDACTED)
+                .finish_non_exhaustive();
+        }
+        if let Cow::Owned(line) = redact::command(self.line.as_str()) {
+            return f
+                .debug_struct("Command")
+                .field("line", &line)
+                .field("verb()", &self.verb())
+                .finish_non_exhaustive();
+        }
+
         f.debug_struct("Command")
             .field("line", &self.line)
             .field("line()", &self.line())
@@ -248,6 +509,7 @@ impl Debug for Command {
             .field("text()", &self.text())
             .field("multiline", &self.multiline)
             .field("multiline()", &self.multiline())
+            .field("credential", &self.credential)
             .finish()
     }
 }
@@ -302,7 +564,7 @@ impl std::error::Error for CommandError {
         None
     }
 
-    fn description(&self) -> &str {
+    fn description(&self) -> &'static str {
         "description() is deprecated; use Display"
     }
 
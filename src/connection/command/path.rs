@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Parses the paths given as arguments to the `MAIL` and `RCPT` commands.
+//!
+//! See [`parse`].
+
+use ascii::{AsAsciiStr, AsciiChar, AsciiStr};
+
+use crate::str::max_lengths;
+
+/// Parse a path out of the text of a `MAIL` or `RCPT` command, such as `FROM:<smith@example.com>`.
+///
+/// `keyword` is the part before the colon (`"FROM"` or `"TO"`), which is matched
+/// case-insensitively. Per [RFC 5321 section
+/// 4.1.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.2), source routes (such as
+/// `<@relay.example.com:smith@example.com>`) are accepted but ignored.
+///
+/// Returns the path without the angle brackets (which is empty for the null path, `<>`) and the
+/// parameters after it (which is empty if there are none).
+///
+/// # Errors
+///
+/// - A description of the syntax error encountered.
+pub fn parse<'a>(
+    text: &'a AsciiStr,
+    keyword: &str,
+) -> Result<(&'a AsciiStr, &'a AsciiStr), &'static str> {
+    let as_str = text.as_str();
+
+    let Some((given_keyword, rest)) = as_str.split_once(':') else {
+        return Err("missing ':' after keyword");
+    };
+    if !given_keyword.eq_ignore_ascii_case(keyword) {
+        return Err("unexpected keyword");
+    }
+
+    // Some clients add a space after the colon, which is not allowed but is harmless.
+    let rest = rest.trim_start();
+
+    let Some(rest) = rest.strip_prefix('<') else {
+        return Err("path must start with '<'");
+    };
+    let Some((path, parameters)) = rest.split_once('>') else {
+        return Err("unterminated '<' in path");
+    };
+
+    // Includes the angle brackets, as RFC 5321 section 4.5.3.1.3 does.
+    if path.len() + 2 > max_lengths::PATH {
+        return Err("path too long");
+    }
+
+    // Strip the source route, if any.
+    let path = match path.strip_prefix('@') {
+        Some(route) => match route.split_once(':') {
+            Some((_, mailbox)) => mailbox,
+            None => return Err("source route without mailbox"),
+        },
+        None => path,
+    };
+
+    if path
+        .chars()
+        .any(|c| c.is_ascii_whitespace() || c.is_ascii_control())
+    {
+        return Err("invalid character in path");
+    }
+
+    let as_ascii = |str: &'a str| {
+        str.as_ascii_str()
+            .expect("`as_str` is derived from an `&AsciiStr`.")
+    };
+
+    Ok((as_ascii(path), as_ascii(parameters.trim())))
+}
+
+/// Check whether a path is a mailbox, in the form of `local-part@domain`.
+///
+/// This is only a loose check that the path has a non-empty local-part and domain.
+pub fn is_mailbox(path: &AsciiStr) -> bool {
+    let slice = path.as_slice();
+
+    slice
+        .iter()
+        .rposition(|&char| char == AsciiChar::At)
+        .is_some_and(|at| at != 0 && at + 1 != slice.len())
+}
+
+/// Check whether a path is the special `postmaster` mailbox, which may be given without a domain.
+///
+/// [RFC 5321 section 4.1.1.3](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.3).
+pub fn is_postmaster(path: &AsciiStr) -> bool {
+    path.as_str().eq_ignore_ascii_case("postmaster")
+}
@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Checks that the domain of the reverse-path exists, per [`SenderDomainPolicy`].
+//!
+//! See [`verify`].
+//!
+//! [`SenderDomainPolicy`]: crate::config::SenderDomainPolicy
+
+use std::io;
+
+use crate::resolver::Resolver;
+
+/// Whether the domain of a reverse-path exists, as far as DNS can tell.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SenderDomain {
+    /// The domain has `MX`, `A`, or `AAAA` records.
+    Exists,
+    /// The domain has a null `MX` record, declaring that it does not send or receive mail.
+    ///
+    /// [RFC 7505 section 3](https://www.rfc-editor.org/rfc/rfc7505.html#section-3).
+    NullMx,
+    /// The domain has none of the records.
+    Missing,
+    /// A lookup failed, so whether the domain exists is unknown.
+    TempError,
+}
+
+/// Look up whether `domain` has `MX`, `A`, or `AAAA` records with `resolver`.
+///
+/// Addresses are only looked up if the domain has no `MX` records or they could not be looked up,
+/// as a domain without them still receives mail at its own address.
+///
+/// [RFC 5321 section 5.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-5.1).
+pub async fn verify(resolver: &impl Resolver, domain: &str) -> SenderDomain {
+    let mx_failed = match resolver.lookup_mx(domain).await {
+        // `MX 0 .` is the only record of a domain with a null `MX`.
+        Ok(exchangers) if exchangers.len() == 1 && exchangers[0].is_empty() => {
+            return SenderDomain::NullMx;
+        }
+        Ok(exchangers) if !exchangers.is_empty() => return SenderDomain::Exists,
+        Ok(_) => false,
+        Err(error) => error.kind() != io::ErrorKind::Unsupported,
+    };
+
+    match resolver.lookup_ip(domain).await {
+        Ok(addresses) if !addresses.is_empty() => SenderDomain::Exists,
+        Ok(_) if !mx_failed => SenderDomain::Missing,
+        Ok(_) | Err(_) => SenderDomain::TempError,
+    }
+}
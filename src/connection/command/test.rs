@@ -17,7 +17,7 @@
 
 //! Tests for [`super`].
 
-use ascii::AsAsciiStr;
+use ascii::{AsAsciiStr, IntoAsciiString};
 
 use super::*;
 
@@ -25,17 +25,20 @@ type Result = std::result::Result<(), Box<dyn std::error::Error>>;
 
 #[test]
 fn test_command_parsing() -> Result {
-    let command = parse("  foo bar baz bim  \r\n".into_ascii_string()?)?;
+    let mut line = "  foo bar baz bim  \r\n".into_ascii_string()?;
+    let pointer = line.as_ptr();
+    let command = parse(&mut line)?;
 
     // Tests that it constructs the right object.
     assert_eq!(
         command,
         Command {
-            line: "  FOO bar baz bim  \r\n".into_ascii_string()?,
+            line: "  FOO bar baz bim  \r\n".as_ascii_str()?,
             trimmed: 2..17,    // `"FOO bar baz bim"`.
             verb: 2..5,        // "`FOO`".
             text: Some(6..17), // "`bar baz bim`".
             multiline: MultiLine::LastLine,
+            credential: false,
         }
     );
 
@@ -45,35 +48,272 @@ fn test_command_parsing() -> Result {
     assert_eq!(command.verb(), "FOO".as_ascii_str()?);
     assert_eq!(command.text(), Some("bar baz bim".as_ascii_str()?));
 
+    // Tests that it borrows the line rather than copying it.
+    assert_eq!(command.line().as_ptr(), pointer);
+
     // Tests that it does not perform any `CRLF` checks.
     assert_eq!(
-        parse("foo bar\n".into_ascii_string()?)?.line(),
+        parse(&mut "foo bar\n".into_ascii_string()?)?.line(),
         "FOO bar\n".as_ascii_str()?
     );
 
     // Test for handling of no text.
     assert_eq!(
-        parse("foo\r\n".into_ascii_string()?)?,
+        parse(&mut "foo\r\n".into_ascii_string()?)?,
         Command {
-            line: "FOO\r\n".into_ascii_string()?,
+            line: "FOO\r\n".as_ascii_str()?,
             trimmed: 0..3,
             verb: 0..3,
             text: None,
             multiline: MultiLine::LastLine,
+            credential: false,
         }
     );
 
     // Test that having a space but no text after the verb still counts as no text.
     assert_eq!(
-        parse("foo \r\n".into_ascii_string()?)?,
+        parse(&mut "foo \r\n".into_ascii_string()?)?,
         Command {
-            line: "FOO \r\n".into_ascii_string()?,
+            line: "FOO \r\n".as_ascii_str()?,
             trimmed: 0..3,
             verb: 0..3,
             text: None,
             multiline: MultiLine::LastLine,
+            credential: false,
+        }
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_path_parsing() -> Result {
+    // Tests that it strips the angle brackets and splits off parameters.
+    assert_eq!(
+        path::parse("FROM:<smith@example.com> SIZE=100".as_ascii_str()?, "FROM"),
+        Ok((
+            "smith@example.com".as_ascii_str()?,
+            "SIZE=100".as_ascii_str()?
+        ))
+    );
+
+    // Tests that the keyword is case-insensitive and that a space after the colon is allowed.
+    assert_eq!(
+        path::parse("to: <jones@example.com>".as_ascii_str()?, "TO"),
+        Ok(("jones@example.com".as_ascii_str()?, "".as_ascii_str()?))
+    );
+
+    // Tests that the null path is accepted.
+    assert_eq!(
+        path::parse("FROM:<>".as_ascii_str()?, "FROM"),
+        Ok(("".as_ascii_str()?, "".as_ascii_str()?))
+    );
+
+    // Tests that source routes are ignored.
+    assert_eq!(
+        path::parse(
+            "TO:<@a.example,@b.example:jones@example.com>".as_ascii_str()?,
+            "TO"
+        ),
+        Ok(("jones@example.com".as_ascii_str()?, "".as_ascii_str()?))
+    );
+
+    // Tests for syntax errors.
+    assert!(path::parse("FROM:smith@example.com".as_ascii_str()?, "FROM").is_err());
+    assert!(path::parse("FROM:<smith@example.com".as_ascii_str()?, "FROM").is_err());
+    assert!(path::parse("TO:<smith@example.com>".as_ascii_str()?, "FROM").is_err());
+    assert!(path::parse("FROM <smith@example.com>".as_ascii_str()?, "FROM").is_err());
+
+    assert!(path::is_mailbox("smith@example.com".as_ascii_str()?));
+    assert!(!path::is_mailbox("smith@".as_ascii_str()?));
+    assert!(!path::is_mailbox("@example.com".as_ascii_str()?));
+    assert!(path::is_postmaster("PostMaster".as_ascii_str()?));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_helo_verification() {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use helo::Identity;
+
+    use crate::{
+        config::{HeloAction, HeloCheck, HeloPolicy},
+        resolver::Resolver,
+    };
+
+    /// Only resolves `mail.example.com`.
+    struct Stub;
+
+    impl Resolver for Stub {
+        async fn lookup_ip(&self, name: &str) -> std::io::Result<Vec<IpAddr>> {
+            Ok(if name == "mail.example.com" {
+                vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]
+            } else {
+                Vec::new()
+            })
+        }
+    }
+
+    let peer = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    assert_eq!(
+        Identity::parse("mail.example.com"),
+        Identity::Domain("mail.example.com")
+    );
+    assert_eq!(
+        Identity::parse("[192.0.2.1]"),
+        Identity::Literal(Some(peer))
+    );
+    assert_eq!(
+        Identity::parse("[IPv6:2001:db8::1]"),
+        Identity::Literal(Some(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into()))
+    );
+    assert_eq!(Identity::parse("[x-tag:content]"), Identity::Literal(None));
+
+    // Tests for syntax errors.
+    for invalid in [
+        "",
+        "-example.com",
+        "example-.com",
+        "example..com",
+        "example.com.",
+        "plus+.com",
+        "[192.0.2]",
+        "[IPv6:192.0.2.1]",
+        "[192.0.2.1",
+    ] {
+        assert_eq!(Identity::parse(invalid), Identity::Invalid, "{invalid:?}");
+    }
+
+    // Tests that checks are skipped by default.
+    assert_eq!(
+        helo::verify("", peer, HeloPolicy::accept_all(), &Stub).await,
+        Ok(vec![])
+    );
+
+    let policy = HeloPolicy::accept_all()
+        .with_syntax(HeloAction::Reject)
+        .with_resolves(HeloAction::Annotate)
+        .with_matches_peer(HeloAction::Annotate);
+    assert_eq!(
+        helo::verify("plus+.com", peer, policy, &Stub).await,
+        Err(HeloCheck::Syntax)
+    );
+    assert_eq!(
+        helo::verify("[192.0.2.1]", peer, policy, &Stub).await,
+        Ok(vec![])
+    );
+    assert_eq!(
+        helo::verify("[192.0.2.2]", peer, policy, &Stub).await,
+        Ok(vec![HeloCheck::MatchesPeer])
+    );
+    // Tests that IPv4 clients mapped into IPv6 match their literals.
+    assert_eq!(
+        helo::verify(
+            "[192.0.2.1]",
+            Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped().into(),
+            policy.with_matches_peer(HeloAction::Reject),
+            &Stub
+        )
+        .await,
+        Ok(vec![])
+    );
+    assert_eq!(
+        helo::verify("mail.example.com", peer, policy, &Stub).await,
+        Ok(vec![])
+    );
+    assert_eq!(
+        helo::verify("unknown.example.com", peer, policy, &Stub).await,
+        Ok(vec![HeloCheck::Resolves])
+    );
+}
+
+#[tokio::test]
+async fn test_sender_domain_verification() {
+    use std::{
+        io,
+        net::{IpAddr, Ipv4Addr},
+    };
+
+    use sender_domain::SenderDomain;
+
+    use crate::resolver::Resolver;
+
+    /// Gives `mx.example.com` an `MX` record, `a.example.com` an address, and `null.example.com`
+    /// a null `MX` record, failing lookups of `fail.example.com`.
+    struct Stub;
+
+    impl Resolver for Stub {
+        async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+            match name {
+                "a.example.com" => Ok(vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]),
+                "fail.example.com" => Err(io::ErrorKind::TimedOut.into()),
+                _ => Ok(Vec::new()),
+            }
+        }
+
+        async fn lookup_mx(&self, name: &str) -> io::Result<Vec<String>> {
+            match name {
+                "mx.example.com" => Ok(vec!["mail.example.com".to_owned()]),
+                "null.example.com" => Ok(vec![String::new()]),
+                "fail.example.com" => Err(io::ErrorKind::TimedOut.into()),
+                _ => Ok(Vec::new()),
+            }
         }
+    }
+
+    /// Only looks up addresses, like [`crate::resolver::SystemResolver`].
+    struct AddressesOnly;
+
+    impl Resolver for AddressesOnly {
+        async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+            Stub.lookup_ip(name).await
+        }
+    }
+
+    for (domain, result) in [
+        ("mx.example.com", SenderDomain::Exists),
+        ("a.example.com", SenderDomain::Exists),
+        ("null.example.com", SenderDomain::NullMx),
+        ("missing.example.com", SenderDomain::Missing),
+        ("fail.example.com", SenderDomain::TempError),
+    ] {
+        assert_eq!(
+            sender_domain::verify(&Stub, domain).await,
+            result,
+            "{domain}"
+        );
+    }
+
+    // Tests that resolvers without `MX` lookups fall back to addresses.
+    assert_eq!(
+        sender_domain::verify(&AddressesOnly, "a.example.com").await,
+        SenderDomain::Exists
     );
+    assert_eq!(
+        sender_domain::verify(&AddressesOnly, "missing.example.com").await,
+        SenderDomain::Missing
+    );
+}
+
+#[test]
+fn test_command_debug() -> Result {
+    let mut line = "MAIL FROM:<smith@example.com>\r\n".into_ascii_string()?;
+    let command = parse(&mut line)?;
+    assert!(format!("{command:?}").contains("smith@example.com"));
+
+    let mut line = "AUTH PLAIN AHVzZXIAcGFzc3dvcmQ=\r\n".into_ascii_string()?;
+    let command = parse(&mut line)?;
+    let debug = format!("{command:?}");
+    assert!(debug.contains("AUTH PLAIN [redacted]"));
+    assert!(!debug.contains("AHVzZXIAcGFzc3dvcmQ="));
+
+    let mut line = "cGFzc3dvcmQ=\r\n".into_ascii_string()?;
+    let mut command = parse(&mut line)?;
+    command.credential = true;
+    assert!(!format!("{command:?}").contains("cGFzc3dvcmQ="));
 
     Ok(())
 }
@@ -0,0 +1,729 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Handles receiving the data of a mail transaction after the `DATA` command.
+//!
+//! See [`handle`].
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt},
+    sync::mpsc,
+};
+
+use super::{
+    line::{Line, LineReader},
+    transport::Writer,
+    CloseReason, Delivery, ShouldClose,
+};
+use crate::{
+    audit::{Audit, RejectionSource},
+    event::{EventSender, SessionEvent},
+    handler::{Decision, Defer, SessionContext},
+    message::{headers, id, stream::StreamingMessage, trace, ContentHash, Message, Size},
+    server::SessionGuard,
+    write_fmt_line, write_line, ServerConfig, SmtpHandler,
+};
+
+/// The line that terminates the data of a message.
+///
+/// [RFC 5321 section 4.1.1.4](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.4).
+const END_OF_DATA: &[u8] = b".\r\n";
+
+/// Receive the data of the mail transaction in progress, deliver it according to `delivery`, and
+/// send the final reply to the client.
+///
+/// With [`Delivery::Buffered`], the final reply is decided by [`SmtpHandler::on_message`], and the
+/// client is told to try again later if that takes longer than
+/// [`crate::timeouts::Timeouts::data_termination`]. While the message waits for the consumer, it
+/// is counted in the backlog of [`crate::server::Health`] through `server`.
+///
+/// If the data exceeds any of the [`Limits`] of the [`crate::ServerConfig`], the rest of it is read
+/// and discarded, and the mail transaction is aborted with a `552` reply.
+///
+/// # Errors
+///
+/// - I/O errors from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+/// - Any errors that could come out of the supplied reader's `read_line` function.
+pub async fn handle<R: AsyncBufReadExt + Unpin, H: SmtpHandler>(
+    reader: &mut LineReader<R>,
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    handler: &mut H,
+    delivery: &Delivery,
+    server: &SessionGuard,
+) -> std::io::Result<ShouldClose> {
+    #[cfg(feature = "metrics")]
+    let _timer = crate::metrics::DataTimer::start();
+    let limits = Limits::from(state.config.as_ref());
+
+    match delivery {
+        Delivery::Buffered => {
+            let mut destination = Destination::Buffer(Vec::new());
+
+            let Reception {
+                size,
+                hash,
+                exceeded,
+            } = match receive(reader, &mut destination, &limits, &mut state.events).await? {
+                Ok(reception) => reception,
+                Err(reason) => return Ok(ShouldClose::Close(reason)),
+            };
+            if let Some(exceeded) = exceeded {
+                state.transaction = None;
+                exceeded.reply(write_stream, &state.audit).await?;
+                return Ok(ShouldClose::Keep);
+            }
+            let Destination::Buffer(data) = destination else {
+                unreachable!("`destination` was constructed as a buffer")
+            };
+
+            let Some(message) = state.finish_transaction(Bytes::from(data), size, hash) else {
+                unreachable!("`DATA` is only accepted during a mail transaction")
+            };
+            // The message is checked as the client sent it, as adding to it can break signatures.
+            #[cfg(any(
+                feature = "dmarc",
+                feature = "arc",
+                feature = "attachment",
+                feature = "clamav",
+                feature = "filter",
+                feature = "rspamd",
+                feature = "spamassassin"
+            ))]
+            let Some(message) = check(write_stream, &state.config, &state.audit, message).await?
+            else {
+                return Ok(ShouldClose::Keep);
+            };
+            let message = stamp(message, &state.config);
+
+            #[cfg(feature = "spool")]
+            let message = match crate::spool::apply(&state.config, message).await {
+                Ok(message) => message,
+                Err(response) => {
+                    state.audit.source(RejectionSource::Server);
+                    write_fmt_line!(write_stream, "{}", response)?;
+                    return Ok(ShouldClose::Keep);
+                }
+            };
+
+            let id = message.session().id();
+            println!(
+                "[{id}] Message received from {} for {} recipient(s) ({} bytes)",
+                message.session().peer_addr(),
+                message.envelope().accepted().count(),
+                size.total()
+            );
+
+            state
+                .events
+                .send(|| SessionEvent::MessageComplete(Box::new(message.clone())))
+                .await;
+
+            #[cfg(feature = "spool")]
+            let spool_id = message.spool_id().cloned();
+            // The client is only told that the message was received once the handler has taken
+            // responsibility for it, and is told to try again later if that takes too long.
+            let timeout = state.config.timeouts().data_termination();
+            let hand_off = server.activity().hand_off();
+            let result = tokio::time::timeout(timeout, handler.on_message(state, message))
+                .await
+                .unwrap_or_else(|_| {
+                    println!("[{id}] The handler did not accept the message within {timeout:?}");
+                    Ok(Decision::Defer(Defer::LocalError))
+                });
+            drop(hand_off);
+            // Only messages that the consumer took responsibility for are kept.
+            #[cfg(feature = "spool")]
+            if !result.as_ref().is_ok_and(Decision::is_accepted) {
+                crate::spool::discard(&state.config, &state.session, spool_id.as_ref()).await;
+            }
+            let decision = decide!(write_stream, state, result);
+            if decision.is_accepted() {
+                state.messages_accepted += 1;
+                server.counters().message();
+            }
+            reply!(
+                write_stream,
+                decision,
+                "250 2.0.0 Ok: queued as {id}",
+                "554 Transaction failed",
+            );
+        }
+        Delivery::Streaming(messages) => {
+            return stream(reader, write_stream, state, &limits, messages, server).await;
+        }
+    }
+
+    Ok(ShouldClose::Keep)
+}
+
+/// Receive the data of the mail transaction in progress for [`Delivery::Streaming`], sending the
+/// message to `messages` as soon as its data starts and replying once it is accepted.
+///
+/// # Errors
+///
+/// - The same as [`handle`].
+async fn stream<R: AsyncBufReadExt + Unpin>(
+    reader: &mut LineReader<R>,
+    write_stream: &mut Writer,
+    state: &mut SessionContext,
+    limits: &Limits,
+    messages: &mpsc::Sender<StreamingMessage>,
+    server: &SessionGuard,
+) -> std::io::Result<ShouldClose> {
+    let Some(envelope) =
+        state.finish_transaction(Bytes::new(), Size::default(), ContentHash::default())
+    else {
+        unreachable!("`DATA` is only accepted during a mail transaction")
+    };
+    let trace_fields = trace_fields(&envelope, &state.config);
+    let (message, body, acceptance) = StreamingMessage::new(envelope);
+
+    // If the consumer is gone, the data is still received so that the session stays in
+    // sync, and the missing acceptance tells the client to try again later.
+    let _ = messages.send(message).await;
+
+    let mut destination = Destination::Stream(Some(body));
+    destination.write(&trace_fields).await;
+    let exceeded = match receive(reader, &mut destination, limits, &mut state.events).await? {
+        Ok(reception) => reception.exceeded,
+        Err(reason) => {
+            destination
+                .send(Err(std::io::ErrorKind::UnexpectedEof.into()))
+                .await;
+            return Ok(ShouldClose::Close(reason));
+        }
+    };
+    // Ends the [`crate::message::stream::Body`].
+    drop(destination);
+
+    if let Some(exceeded) = exceeded {
+        exceeded.reply(write_stream, &state.audit).await?;
+        return Ok(ShouldClose::Keep);
+    }
+
+    let id = state.session.id;
+    let hand_off = server.activity().hand_off();
+    let acceptance =
+        tokio::time::timeout(state.config.timeouts().data_termination(), acceptance).await;
+    drop(hand_off);
+    match acceptance {
+        Ok(Ok(true)) => {
+            state.messages_accepted += 1;
+            server.counters().message();
+            write_fmt_line!(write_stream, "250 2.0.0 Ok: queued as {id}")?;
+        }
+        Ok(Ok(false)) => {
+            state.audit.source(RejectionSource::Handler);
+            write_line!(write_stream, "554 Transaction failed")?;
+        }
+        Ok(Err(_)) | Err(_) => {
+            state.audit.source(RejectionSource::Server);
+            write_line!(
+                write_stream,
+                "451 Requested action aborted: local error in processing"
+            )?;
+        }
+    }
+
+    Ok(ShouldClose::Keep)
+}
+
+/// Evaluate DMARC, validate ARC, check the attachments of `message` and check it against the
+/// filter rules, and scan it with ClamAV, Rspamd, and SpamAssassin as `config` asks, before it is
+/// handed to the handler.
+///
+/// Returns `None` after replying to the client if the message is rejected or deferred.
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+#[cfg(any(
+    feature = "dmarc",
+    feature = "arc",
+    feature = "attachment",
+    feature = "clamav",
+    feature = "filter",
+    feature = "rspamd",
+    feature = "spamassassin"
+))]
+#[cfg_attr(
+    not(any(
+        feature = "attachment",
+        feature = "clamav",
+        feature = "dmarc",
+        feature = "filter",
+        feature = "rspamd",
+        feature = "spamassassin"
+    )),
+    expect(
+        clippy::needless_pass_by_ref_mut,
+        reason = "ARC never replies to the client"
+    )
+)]
+async fn check(
+    #[cfg_attr(
+        not(any(
+            feature = "attachment",
+            feature = "clamav",
+            feature = "dmarc",
+            feature = "filter",
+            feature = "rspamd",
+            feature = "spamassassin"
+        )),
+        expect(unused_variables, reason = "ARC never replies to the client")
+    )]
+    write_stream: &mut Writer,
+    config: &ServerConfig,
+    #[cfg_attr(
+        not(any(
+            feature = "attachment",
+            feature = "clamav",
+            feature = "dmarc",
+            feature = "filter",
+            feature = "rspamd",
+            feature = "spamassassin"
+        )),
+        expect(unused_variables, reason = "ARC never replies to the client")
+    )]
+    audit: &Audit,
+    message: Message,
+) -> std::io::Result<Option<Message>> {
+    #[cfg(feature = "dmarc")]
+    let Some(message) = verify_dmarc(write_stream, config, audit, message).await?
+    else {
+        return Ok(None);
+    };
+    #[cfg(feature = "arc")]
+    let message = crate::arc::apply(config, message).await;
+    #[cfg(feature = "attachment")]
+    let message = match crate::attachment::apply(config, message) {
+        Ok(message) => message,
+        Err(response) => {
+            audit.source(RejectionSource::Policy("attachment"));
+            write_fmt_line!(write_stream, "{}", response)?;
+            return Ok(None);
+        }
+    };
+    #[cfg(feature = "filter")]
+    let message = match crate::filter::apply(config, message) {
+        Ok(message) => message,
+        Err(response) => {
+            audit.source(RejectionSource::Policy("filter"));
+            write_fmt_line!(write_stream, "{}", response)?;
+            return Ok(None);
+        }
+    };
+    #[cfg(feature = "clamav")]
+    let message = match crate::clamav::apply(config, message).await {
+        Ok(message) => message,
+        Err(response) => {
+            audit.source(RejectionSource::Policy("clamav"));
+            write_fmt_line!(write_stream, "{}", response)?;
+            return Ok(None);
+        }
+    };
+    #[cfg(feature = "rspamd")]
+    let message = match crate::rspamd::apply(config, message).await {
+        Ok(message) => message,
+        Err(response) => {
+            audit.source(RejectionSource::Policy("rspamd"));
+            write_fmt_line!(write_stream, "{}", response)?;
+            return Ok(None);
+        }
+    };
+    #[cfg(feature = "spamassassin")]
+    let message = match crate::spamassassin::apply(config, message).await {
+        Ok(message) => message,
+        Err(response) => {
+            audit.source(RejectionSource::Policy("spamassassin"));
+            write_fmt_line!(write_stream, "{}", response)?;
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(message))
+}
+
+/// Evaluate DMARC for `message` if [`ServerConfig::dmarc_policy`] is set, keeping the verdict in
+/// it and adding an `Authentication-Results:` header to it.
+///
+/// Returns `None` after rejecting the message with `550 5.7.1` if the policy rejects it.
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+#[cfg(feature = "dmarc")]
+async fn verify_dmarc(
+    write_stream: &mut Writer,
+    config: &ServerConfig,
+    audit: &Audit,
+    mut message: Message,
+) -> std::io::Result<Option<Message>> {
+    use crate::dmarc::{self, DmarcDisposition};
+
+    let Some(verdict) = dmarc::verify(config, &message).await else {
+        return Ok(Some(message));
+    };
+
+    if verdict.disposition() == DmarcDisposition::Reject {
+        let domain = verdict.domain().unwrap_or_default();
+        println!(
+            "[{}] Message rejected by the DMARC policy of {domain}",
+            message.session().id()
+        );
+        audit.source(RejectionSource::Policy("dmarc"));
+        write_fmt_line!(
+            write_stream,
+            "550 5.7.1 Message rejected by the DMARC policy of {domain}"
+        )?;
+        return Ok(None);
+    }
+
+    message = message.with_dmarc(verdict);
+    if let Some(header) = trace::authentication_results(&message, config.hostname()) {
+        message.prepend(header.as_bytes());
+    }
+
+    Ok(Some(message))
+}
+
+/// Add the `Message-ID:` header to `message` if [`ServerConfig::stamp_message_id`] is set and it
+/// has none, and then the trace header fields, see [`trace_fields`], returning it.
+fn stamp(mut message: Message, config: &ServerConfig) -> Message {
+    if config.stamp_message_id() && !message.headers().contains("Message-ID") {
+        let id = id::generate(config.hostname());
+        message.prepend(format!("Message-ID: {id}\r\n").as_bytes());
+    }
+    let fields = trace_fields(&message, config);
+    message.prepend(&fields);
+    message
+}
+
+/// Create the trace header fields that the server adds to the start of a message when accepting
+/// it, including their line endings.
+///
+/// These are the `Received:` header and, if [`ServerConfig::stamp_return_path`] is set, the
+/// `Return-Path:` header above it. With the `spf` feature, the `Received-SPF:` header is added
+/// between them if SPF was checked for the message.
+fn trace_fields(message: &Message, config: &ServerConfig) -> Vec<u8> {
+    let mut fields = Vec::new();
+
+    if config.stamp_return_path() {
+        fields.extend_from_slice(trace::return_path(message).as_bytes());
+    }
+    #[cfg(feature = "spf")]
+    if let Some(received_spf) = trace::received_spf(message, config.hostname()) {
+        fields.extend_from_slice(received_spf.as_bytes());
+    }
+    fields.extend_from_slice(
+        trace::received(
+            message,
+            config.hostname(),
+            Some(&message.session().id().to_string()),
+        )
+        .as_bytes(),
+    );
+
+    fields
+}
+
+/// Where the data of a message is written to as it is received.
+pub(super) enum Destination {
+    /// Collect the data into memory.
+    Buffer(Vec<u8>),
+    /// Send the data to a [`crate::message::stream::Body`], or discard it if the receiver was
+    /// dropped.
+    Stream(Option<mpsc::Sender<std::io::Result<Bytes>>>),
+}
+
+impl Destination {
+    /// Write a line of data.
+    async fn write(&mut self, line: &[u8]) {
+        match self {
+            Self::Buffer(buffer) => buffer.extend_from_slice(line),
+            Self::Stream(_) => self.send(Ok(Bytes::copy_from_slice(line))).await,
+        }
+    }
+
+    /// Send an item to the [`crate::message::stream::Body`], if this is [`Self::Stream`].
+    async fn send(&mut self, item: std::io::Result<Bytes>) {
+        if let Self::Stream(sender) = self {
+            if let Some(channel) = sender {
+                if channel.send(item).await.is_err() {
+                    *sender = None;
+                }
+            }
+        }
+    }
+
+    /// Discard the data written so far and stop accepting more, because the data exceeds a limit.
+    async fn overflow(&mut self) {
+        match self {
+            Self::Buffer(buffer) => *buffer = Vec::new(),
+            Self::Stream(_) => {
+                self.send(Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "message exceeds a size limit",
+                )))
+                .await;
+
+                *self = Self::Stream(None);
+            }
+        }
+    }
+}
+
+/// Limits on the data of a message, past which it is refused.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Limits {
+    /// The maximum size of the data in bytes.
+    pub message_size: usize,
+    /// The maximum size of the header section in bytes.
+    pub header_size: usize,
+    /// The maximum number of header fields.
+    pub header_fields: usize,
+    /// The maximum length of a single header field in bytes, including its folded lines.
+    pub header_field_length: usize,
+    /// The maximum length of a line in bytes, including its line ending.
+    pub line_length: usize,
+    /// The maximum time to wait for each line.
+    pub data_block: Duration,
+}
+
+impl From<&ServerConfig> for Limits {
+    fn from(config: &ServerConfig) -> Self {
+        Self {
+            message_size: config.max_message_size(),
+            header_size: config.max_header_size(),
+            header_fields: config.max_header_fields(),
+            header_field_length: config.max_header_field_length(),
+            line_length: config.max_text_line(),
+            data_block: config.timeouts().data_block(),
+        }
+    }
+}
+
+/// Which of the [`Limits`] the data of a message exceeded, or how else it was malformed.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub(super) enum Exceeded {
+    /// [`Limits::message_size`].
+    MessageSize,
+    /// [`Limits::header_size`].
+    HeaderSize,
+    /// [`Limits::header_fields`].
+    HeaderFields,
+    /// [`Limits::header_field_length`].
+    HeaderFieldLength,
+    /// [`Limits::line_length`].
+    LineLength,
+    /// A line ended in a bare `\n`, or held a bare `\r`, which [RFC 5321 section
+    /// 2.3.8](https://www.rfc-editor.org/rfc/rfc5321.html#section-2.3.8) does not allow.
+    ///
+    /// Servers that disagree about where such data ends can be made to deliver a message smuggled
+    /// inside of another, so it is rejected rather than passed on.
+    BareLineEnding,
+}
+
+impl Exceeded {
+    /// Get what triggers the rejection: the setting of [`ServerConfig`] that the limit comes from,
+    /// or the protocol.
+    const fn source(self) -> RejectionSource {
+        RejectionSource::Limit(match self {
+            Self::MessageSize => "max_message_size",
+            Self::HeaderSize => "max_header_size",
+            Self::HeaderFields => "max_header_fields",
+            Self::HeaderFieldLength => "max_header_field_length",
+            Self::LineLength => "max_text_line",
+            Self::BareLineEnding => return RejectionSource::Protocol,
+        })
+    }
+
+    /// Send the reply that aborts the mail transaction because of this limit, `552`, or `554` for
+    /// [`Self::BareLineEnding`], noting it in `audit`.
+    ///
+    /// # Errors
+    ///
+    /// - I/O errors from [`AsyncWriteExt::write_all`] on [`tokio::net::TcpStream`].
+    async fn reply(self, write_stream: &mut Writer, audit: &Audit) -> std::io::Result<()> {
+        audit.source(self.source());
+        match self {
+            Self::MessageSize => write_line!(
+                write_stream,
+                "552 Message size exceeds fixed maximum message size"
+            ),
+            Self::HeaderSize => write_line!(
+                write_stream,
+                "552 Header section exceeds fixed maximum size"
+            ),
+            Self::HeaderFields => write_line!(write_stream, "552 Too many header fields"),
+            Self::HeaderFieldLength => write_line!(
+                write_stream,
+                "552 Header field exceeds fixed maximum length"
+            ),
+            Self::LineLength => write_line!(write_stream, "552 Line too long"),
+            Self::BareLineEnding => write_line!(
+                write_stream,
+                "554 5.6.0 Bare CR or LF is not allowed in the data"
+            ),
+        }
+    }
+}
+
+/// The result of receiving the data of a message.
+#[derive(Debug)]
+pub(super) struct Reception {
+    /// The size of the data, counted even past where it was discarded, except for lines that
+    /// exceeded [`Limits::line_length`].
+    pub size: Size,
+    /// The digest of the data, computed even past where it was discarded, except for lines that
+    /// exceeded [`Limits::line_length`].
+    pub hash: ContentHash,
+    /// The limit that the data exceeded, if any, after which it was discarded.
+    pub exceeded: Option<Exceeded>,
+}
+
+/// Read the data of a message out of `reader` into `destination`, until the terminating
+/// `<CRLF>.<CRLF>`, and count its [`Size`] and [`ContentHash`].
+///
+/// Removes the leading period that clients add to any line starting with a period, per [RFC 5321
+/// section 4.5.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.2).
+///
+/// Once the data exceeds any of the `limits`, [`Destination::overflow`] is called and the rest of
+/// the data is only counted, so that the session stays in sync with the client.
+///
+/// Every line that is written to `destination` is also sent to `events`.
+///
+/// Returns [`CloseReason`] if the session ended before the data was finished.
+///
+/// # Errors
+///
+/// - Any errors that could come out of the supplied reader's `read_line` function.
+pub(super) async fn receive<R: AsyncBufReadExt + Unpin>(
+    reader: &mut LineReader<R>,
+    destination: &mut Destination,
+    limits: &Limits,
+    events: &mut EventSender,
+) -> std::io::Result<Result<Reception, CloseReason>> {
+    let mut size = Size::default();
+    let mut hasher = Sha256::new();
+    let mut exceeded = None;
+    let mut is_header = true;
+    let mut header_fields = 0;
+    let mut header_field_length = 0;
+    // The data starts after the `CRLF` that ended the `DATA` command.
+    let mut after_crlf = true;
+
+    loop {
+        let line =
+            match tokio::time::timeout(limits.data_block, reader.read(limits.line_length)).await {
+                Ok(Ok(Line::Complete(line))) => line,
+                // The line was never buffered, so it is not counted.
+                Ok(Ok(Line::TooLong)) => {
+                    after_crlf = reader.ends_in_crlf();
+                    if exceeded.is_none() {
+                        exceeded = Some(Exceeded::LineLength);
+                        destination.overflow().await;
+                    }
+                    continue;
+                }
+                Ok(Err(err)) if err.kind() == std::io::ErrorKind::ConnectionAborted => {
+                    return Ok(Err(CloseReason::ClosedByClient));
+                }
+                Ok(Err(err)) => return Err(err),
+                Err(elapsed) => return Ok(Err(CloseReason::TimedOut(elapsed))),
+            };
+
+        // Only `<CRLF>.<CRLF>` ends the data, so that a period after a bare line ending cannot end
+        // it earlier than another server would.
+        if after_crlf && line == END_OF_DATA {
+            #[cfg(feature = "metrics")]
+            if exceeded.is_none() {
+                crate::metrics::message_received(size.total());
+            }
+
+            return Ok(Ok(Reception {
+                size,
+                hash: hasher.into(),
+                exceeded,
+            }));
+        }
+
+        after_crlf = line.ends_with(b"\r\n");
+        if exceeded.is_none() && has_bare_line_ending(line) {
+            exceeded = Some(Exceeded::BareLineEnding);
+            destination.overflow().await;
+        }
+
+        let line = line.strip_prefix(b".").unwrap_or(line);
+
+        // The empty line that ends the header section is counted as part of it.
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        let is_header_line = is_header && (content.is_empty() || headers::is_header_line(content));
+        is_header = is_header_line && !content.is_empty();
+
+        size.add(line.len(), is_header_line);
+        hasher.update(line);
+
+        if is_header {
+            // Folded lines continue the field before them.
+            if matches!(content.first(), Some(b' ' | b'\t')) {
+                header_field_length += line.len();
+            } else {
+                header_fields += 1;
+                header_field_length = line.len();
+            }
+        }
+
+        if exceeded.is_some() {
+            continue;
+        }
+
+        exceeded = if size.total() > limits.message_size {
+            Some(Exceeded::MessageSize)
+        } else if size.header() > limits.header_size {
+            Some(Exceeded::HeaderSize)
+        } else if header_fields > limits.header_fields {
+            Some(Exceeded::HeaderFields)
+        } else if header_field_length > limits.header_field_length {
+            Some(Exceeded::HeaderFieldLength)
+        } else {
+            None
+        };
+
+        if exceeded.is_some() {
+            destination.overflow().await;
+        } else {
+            destination.write(line).await;
+            events
+                .send(|| SessionEvent::DataChunk(Bytes::copy_from_slice(line)))
+                .await;
+        }
+    }
+}
+
+/// Get whether `line` ends in a bare `\n`, or holds a `\r` or `\n` anywhere but in the `\r\n`
+/// that ends it.
+fn has_bare_line_ending(line: &[u8]) -> bool {
+    let content = line.strip_suffix(b"\r\n").unwrap_or(line);
+    content.iter().any(|&byte| matches!(byte, b'\r' | b'\n'))
+}
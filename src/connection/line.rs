@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Reads lines from SMTP clients without buffering past a maximum length.
+//!
+//! See [`LineReader`].
+
+use tokio::io::AsyncBufReadExt;
+
+/// A line read by [`LineReader::read`].
+#[derive(PartialEq, Eq, Debug)]
+pub(super) enum Line<'a> {
+    /// The bytes of the line, including its line ending if it had one.
+    ///
+    /// They are not validated, so that the data of a message can carry 8-bit text, and are left
+    /// for the command parser to reject if they are not ASCII. They are lent mutably, so that the
+    /// parser can set the verb of a command to uppercase in place rather than copying the line.
+    Complete(&'a mut [u8]),
+    /// The line was longer than the limit, and was read to its end and discarded.
+    TooLong,
+}
+
+/// Reads lines out of a reader into one buffer that is reused for every line, so that reading a
+/// line does not allocate once the buffer has grown to fit the longest line allowed.
+#[derive(Debug)]
+pub(super) struct LineReader<R> {
+    /// The reader that lines are read out of.
+    reader: R,
+    /// The line being read, cleared before each line.
+    buffer: Vec<u8>,
+    /// Whether the last line read ended in `\r\n`, even if it was too long to keep.
+    ends_in_crlf: bool,
+}
+
+impl<R: AsyncBufReadExt + Unpin> LineReader<R> {
+    /// Create a new [`Self`] that reads lines out of `reader`.
+    pub(super) const fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+            ends_in_crlf: false,
+        }
+    }
+
+    /// Get the reader that lines are read out of, dropping the buffer.
+    #[cfg(feature = "tls")]
+    pub(super) fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Get whether the last line read ended in `\r\n`, rather than a bare `\n` or the end of the
+    /// stream, even if it was [`Line::TooLong`].
+    pub(super) const fn ends_in_crlf(&self) -> bool {
+        self.ends_in_crlf
+    }
+
+    /// Read a line, up to and including the next `\n` or the end of the stream.
+    ///
+    /// Once the line is longer than `limit` bytes, including its line ending, it stops being
+    /// buffered, and the rest of it is read and discarded so that the next read starts on the
+    /// next line. The buffer therefore never grows past `limit` bytes.
+    ///
+    /// # Errors
+    ///
+    /// - [`std::io::ErrorKind::ConnectionAborted`] if the end of the stream is reached before any
+    ///   bytes are read, like [`crate::read_line`].
+    /// - Any errors that could come out of the supplied reader's `fill_buf` function.
+    pub(super) async fn read(&mut self, limit: usize) -> std::io::Result<Line<'_>> {
+        self.buffer.clear();
+        let mut is_empty = true;
+        let mut is_too_long = false;
+        // The last two bytes of the line, which are kept even once it is too long.
+        let mut ending = [0; 2];
+
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                if is_empty {
+                    return Err(std::io::ErrorKind::ConnectionAborted.into());
+                }
+                break;
+            }
+            is_empty = false;
+
+            let end = available.iter().position(|&byte| byte == b'\n');
+            let length = end.map_or(available.len(), |end| end + 1);
+            let chunk = &available[..length];
+            let is_end = end.is_some();
+            ending = match *chunk {
+                [.., second_last, last] => [second_last, last],
+                [last] => [ending[1], last],
+                [] => ending,
+            };
+
+            if !is_too_long {
+                if self.buffer.len() + length > limit {
+                    is_too_long = true;
+                    self.buffer.clear();
+                } else {
+                    self.buffer.extend_from_slice(chunk);
+                }
+            }
+            self.reader.consume(length);
+
+            if is_end {
+                break;
+            }
+        }
+
+        self.ends_in_crlf = ending == *b"\r\n";
+        Ok(if is_too_long {
+            Line::TooLong
+        } else {
+            Line::Complete(&mut self.buffer)
+        })
+    }
+}
@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Logs the opening and closing of SMTP sessions according to
+//! [`crate::ServerConfig::log_format`].
+//!
+//! See [`opened`] and [`closed`].
+
+use super::CloseReason;
+use crate::{config::LogFormat, handler::SessionContext};
+
+/// Log that the session of `state` was opened.
+///
+/// With [`LogFormat::Json`], nothing is logged until the session closes.
+pub(super) fn opened(state: &SessionContext) {
+    let session = &state.session;
+
+    match state.config.log_format() {
+        LogFormat::Text => println!(
+            "[{}] Connection opened on {} by {}",
+            session.id(),
+            session.local_addr(),
+            session.peer_addr()
+        ),
+        #[cfg(feature = "json")]
+        LogFormat::Json => (),
+    }
+}
+
+/// Log that the session of `state` was closed because of `reason`.
+pub(super) fn closed(state: &SessionContext, reason: &CloseReason) {
+    let session = &state.session;
+
+    match state.config.log_format() {
+        LogFormat::Text => println!(
+            "[{}] Connection on {} with {} closed ({reason:?})",
+            session.id(),
+            session.local_addr(),
+            session.peer_addr()
+        ),
+        #[cfg(feature = "json")]
+        LogFormat::Json => println!("{}", record(state, reason)),
+    }
+}
+
+/// Get the JSON record of the session of `state`, which was closed because of `reason`. See
+/// [`LogFormat::Json`].
+#[cfg(feature = "json")]
+pub(super) fn record(state: &SessionContext, reason: &CloseReason) -> serde_json::Value {
+    let session = &state.session;
+
+    serde_json::json!({
+        "session_id": session.id().to_string(),
+        "local_addr": session.local_addr().to_string(),
+        "peer_addr": session.peer_addr().to_string(),
+        "helo": session.helo().map(ToString::to_string),
+        "authenticated_user": session.authenticated_user(),
+        "tls": session.is_tls(),
+        "commands": state.commands,
+        "messages_accepted": state.messages_accepted,
+        "close_reason": reason.as_str(),
+        "duration_secs": state.opened_at.elapsed().as_secs_f64(),
+    })
+}
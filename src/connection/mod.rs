@@ -19,52 +19,163 @@
 //!
 //! See [`handle`].
 
+/// Send the reply for a [`crate::handler::Decision`] into `write_stream`: `$accept` if it accepts,
+/// `$reject` if it rejects, or the reply for deferring or the consumer's own reply.
+///
+/// `$accept` and `$reject` are format strings, see [`crate::write_fmt_line`].
+///
+/// Returns with [`ShouldClose::Close`] if the reply tells the client that the session is being
+/// closed.
+///
+/// # Errors
+///
+/// - Any errors that could come out of the supplied writer's `write_all` function.
+macro_rules! reply {
+    ( $write_stream:expr, $decision:expr, $accept:expr, $reject:expr $(,)? ) => {{
+        let response = match $decision {
+            $crate::handler::Decision::Accept => {
+                $crate::write_fmt_line!($write_stream, $accept)?;
+                None
+            }
+            $crate::handler::Decision::Reject => {
+                $crate::write_fmt_line!($write_stream, $reject)?;
+                None
+            }
+            $crate::handler::Decision::Defer(defer) => Some(defer.response()),
+            $crate::handler::Decision::Reply(response) => Some(response),
+        };
+
+        if let Some(response) = response {
+            $crate::write_fmt_line!($write_stream, "{}", response)?;
+            if response.closes_session() {
+                return Ok($crate::connection::ShouldClose::Close(
+                    $crate::connection::CloseReason::ServiceUnavailable,
+                ));
+            }
+        }
+    }};
+}
+
+/// Get the [`crate::handler::Decision`] out of a [`crate::handler::HandlerResult`], or log and
+/// reply to the [`crate::handler::HandlerError`] that the handler failed with and return.
+///
+/// The client is told to try again later with `451 4.3.0`, or with `421 4.3.0` if the error closes
+/// the session, in which case this returns with [`ShouldClose::Close`].
+///
+/// # Errors
+///
+/// - Any errors that could come out of the supplied writer's `write_all` function.
+macro_rules! decide {
+    ( $write_stream:expr, $state:expr, $result:expr $(,)? ) => {
+        match $result {
+            Ok(decision) => {
+                if !decision.is_accepted() {
+                    $state.audit.source($crate::audit::RejectionSource::Handler);
+                }
+                decision
+            }
+            Err(error) => {
+                eprintln!("[{}] Handler failed: {error}", $state.session.id);
+                $state.audit.source($crate::audit::RejectionSource::Handler);
+
+                if error.closes_session() {
+                    $crate::write_fmt_line!(
+                        $write_stream,
+                        "421 4.3.0 {} {}",
+                        $state.config.hostname(),
+                        $state.config.unavailable_text()
+                    )?;
+                    return Ok($crate::connection::ShouldClose::Close(
+                        $crate::connection::CloseReason::HandlerError,
+                    ));
+                }
+
+                $crate::write_line!(
+                    $write_stream,
+                    "451 4.3.0 Requested action aborted: local error"
+                )?;
+                return Ok($crate::connection::ShouldClose::Keep);
+            }
+        }
+    };
+}
+
 mod command;
+mod data;
+mod line;
+mod log;
+mod reverse_dns;
+mod transport;
 
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::TcpStream,
-    time::error::Elapsed,
-};
+pub use command::Command;
+#[cfg(test)]
+mod test;
 
-use crate::write_fmt_line;
+use std::{sync::Arc, time::SystemTime};
 
-const DOMAIN: &str = "example.com";
+use tokio::{io::AsyncWriteExt, net::TcpStream, sync::mpsc};
 
-/// Handle a TCP connection as an SMTP session.
+use self::transport::{Metered, Transport};
+use crate::{
+    audit::RejectionSource,
+    config::ReverseDnsPolicy,
+    event::{SessionEvent, SessionEvents},
+    handler::{ConnectDecision, SessionContext},
+    listener::Overrides,
+    message::{envelope::Envelope, stream::StreamingMessage},
+    server::SessionGuard,
+    session::{CloseReason, ReverseDns, SessionInfo, SessionSummary},
+    write_fmt_line, HandlerFactory, ServerConfig, SmtpHandler,
+};
+
+/// Handle a TCP connection as an SMTP session following `config`, consulting a handler created by
+/// `factory` for every decision and handing off received messages according to `delivery`.
+///
+/// The session follows `overrides` over `config`, see [`crate::Listener`]. If `events` is given,
+/// the [`SessionEvents`] of the session are sent through it as it starts.
+///
+/// The session is counted by `server` until it closes, and is closed with `421` when the server
+/// shuts down, see [`crate::ServerHandle`]. Returns how the session ended, see [`SessionSummary`].
 ///
 /// # Errors
 ///
 /// This function will return [`std::io::Error`] from a variety of sources:
 ///
 /// - I/O errors from [`AsyncWriteExt::write_all`] on [`TcpStream`].
-/// - I/O and UTF-8 errors from [`AsyncBufReadExt::read_line`] on [`BufReader<TcpStream>`].
+/// - I/O errors from [`line::LineReader::read`] on [`TcpStream`].
 /// - I/O errors encountered in [`TcpStream::local_addr`] amd [`TcpStream::peer_addr`].
 ///     - On POSIX, these come from `getsockname` and `getpeername` from the C standard library.
 ///       If these return explicit errors or malformed output, this will be bubbled up through
 ///       [`std::io::Error`]. For more details, see the source code for this function.
-pub async fn handle(mut stream: TcpStream) -> std::io::Result<()> {
-    /// Read a line out of `reader` or break with [`CloseReason`].
+pub async fn handle<F: HandlerFactory>(
+    stream: TcpStream,
+    config: Arc<ServerConfig>,
+    overrides: Overrides,
+    factory: Arc<F>,
+    delivery: Delivery,
+    events: Option<mpsc::Sender<SessionEvents>>,
+    mut server: SessionGuard,
+) -> std::io::Result<SessionSummary> {
+    /// Read a line of at most `limit` bytes out of `reader` within `timeout` or break with
+    /// [`CloseReason`], unless `closing` resolves first.
     ///
     /// Implicitly calls `.await`.
     ///
     /// # Breaks
     ///
     /// If `read_line` reads zero bytes, `break` with [`CloseReason::ClosedByClient`].
-    /// If `read_line` takes more than [`timeouts::SERVER_TIMEOUT`], break with
-    /// [`CloseReason::TimedOut`].
+    /// If `read_line` takes more than `timeout`, break with [`CloseReason::TimedOut`].
+    /// If `closing` resolves first, break with [`CloseReason::Shutdown`].
     ///
     /// # Errors
     ///
     /// - Any errors that could come out of the supplied reader's `read_line` function.
     macro_rules! read_line_or_break {
-        ($reader:expr) => {
-            match ::tokio::time::timeout(
-                $crate::timeouts::SERVER_TIMEOUT,
-                $crate::read_line!($reader),
-            )
-            .await
-            {
+        ($reader:expr, $limit:expr, $timeout:expr, $closing:expr) => {
+            match ::tokio::select! {
+                result = ::tokio::time::timeout($timeout, $reader.read($limit)) => result,
+                () = $closing => break CloseReason::Shutdown,
+            } {
                 Ok(result) => match result {
                     Ok(line) => Ok(line),
                     Err(err) => match err.kind() {
@@ -99,24 +210,456 @@ pub async fn handle(mut stream: TcpStream) -> std::io::Result<()> {
     // - <https://pubs.opengroup.org/onlinepubs/9799919799.2024edition/functions/getpeername.html>
     let local_socket = stream.local_addr()?;
     let client_socket = stream.peer_addr()?;
-    println!("Connection opened on {local_socket} by {client_socket}");
+    let mut state = SessionContext::new(
+        SessionInfo::new(local_socket, client_socket),
+        config,
+        server.rejection_sender().clone(),
+    );
+    state.overrides = overrides;
+    #[cfg(feature = "tls")]
+    let id = state.session.id;
+    log::opened(&state);
 
-    let (read_stream, mut write_stream) = stream.split();
-    let mut reader = BufReader::new(read_stream);
+    let Some(stream) = screen(stream, &mut state, factory.as_ref()).await else {
+        return Ok(SessionSummary::new(&state, CloseReason::Refused));
+    };
+    #[cfg(feature = "metrics")]
+    let _active = crate::metrics::ActiveSession::start();
 
-    write_fmt_line!(write_stream, "220 {DOMAIN} SMTP testing service ready")?;
+    if let Some(events) = events {
+        let (receiver, sender) = SessionEvents::new();
+        if events.send(receiver).await.is_ok() {
+            state.events = sender;
+        }
+    }
+    state
+        .events
+        .send(|| SessionEvent::Connected(Box::new(state.session.clone())))
+        .await;
 
+    let stream = Metered::new(stream, server.start(&state.session));
+    let transport = Transport::new(stream, &state, server.counters().clone());
+    let Some((mut reader, mut write_stream)) = greet(transport, &mut state).await? else {
+        return Ok(SessionSummary::new(&state, CloseReason::TlsFailed));
+    };
+
+    let mut handler = factory.create(state.session.clone());
+
+    // Only the first command is held to the greeting timeout.
+    let mut timeout = state.config.timeouts().greeting();
     let close_reason = loop {
-        let line = read_line_or_break!(reader)?;
+        let line = read_line_or_break!(
+            reader,
+            state.config.max_command_line(),
+            timeout,
+            server.closing(state.phase())
+        )?;
+        timeout = state.config.timeouts().server();
 
-        match command::handle(&mut write_stream, line).await? {
+        let should_close =
+            command::handle(&mut write_stream, &mut state, &mut handler, line, &server).await?;
+        server.activity().set_phase(state.phase());
+        match should_close {
             ShouldClose::Close(reason) => break reason,
             ShouldClose::Keep => (),
         }
+
+        // The `STARTTLS` command was accepted, so the handshake starts on the next byte.
+        #[cfg(feature = "tls")]
+        if state.starting_tls {
+            match start_tls(Transport::unsplit(reader, write_stream), &mut state).await {
+                Ok(halves) => (reader, write_stream) = halves,
+                Err(error) => {
+                    println!("[{id}] TLS handshake with {client_socket} failed: {error}");
+                    return Ok(SessionSummary::new(&state, CloseReason::TlsFailed));
+                }
+            }
+        }
+
+        // The `DATA` command was accepted, so the following lines are the data of the message.
+        if state.awaiting_data {
+            let should_close = data::handle(
+                &mut reader,
+                &mut write_stream,
+                &mut state,
+                &mut handler,
+                &delivery,
+                &server,
+            )
+            .await?;
+            state.awaiting_data = false;
+
+            match should_close {
+                ShouldClose::Close(reason) => break reason,
+                ShouldClose::Keep => (),
+            }
+        }
     };
 
-    println!("Connection on {local_socket} with {client_socket} closed ({close_reason:?})");
-    Ok(())
+    close(&mut write_stream, &mut state, &mut handler, close_reason).await
+}
+
+/// Greet the client through `transport`, first starting TLS if the session is on a listener with
+/// [`crate::Listener::with_implicit_tls`], returning the halves of the connection, or `None` if the
+/// handshake failed.
+///
+/// With implicit TLS, the client starts the handshake as soon as it connects, so a refusal in
+/// place of the greeting is not understood, but closes the connection all the same.
+///
+/// # Errors
+///
+/// - I/O errors from [`AsyncWriteExt::write_all`] on [`TcpStream`].
+#[cfg_attr(
+    not(feature = "tls"),
+    expect(
+        clippy::needless_pass_by_ref_mut,
+        reason = "the state is only changed by starting TLS"
+    )
+)]
+async fn greet(
+    transport: Transport,
+    state: &mut SessionContext,
+) -> std::io::Result<Option<(transport::Reader, transport::Writer)>> {
+    #[cfg(feature = "tls")]
+    let (reader, mut write_stream) = if state.overrides.implicit_tls {
+        match start_tls(transport, state).await {
+            Ok(halves) => halves,
+            Err(error) => {
+                println!(
+                    "[{}] TLS handshake with {} failed: {error}",
+                    state.session.id, state.session.peer_addr
+                );
+                return Ok(None);
+            }
+        }
+    } else {
+        transport.split()
+    };
+    #[cfg(not(feature = "tls"))]
+    let (reader, mut write_stream) = transport.split();
+
+    write_fmt_line!(
+        write_stream,
+        "220 {} {}",
+        state.config.hostname(),
+        state.config.banner()
+    )?;
+
+    Ok(Some((reader, write_stream)))
+}
+
+/// Close the session of `state` because of `reason`, telling the client if the server is shutting
+/// down, then giving `handler` and [`ServerConfig::transcripts`] their last look at the session
+/// and returning its summary.
+///
+/// # Errors
+///
+/// - I/O errors from [`AsyncWriteExt::write_all`] on [`TcpStream`].
+async fn close(
+    write_stream: &mut transport::Writer,
+    state: &mut SessionContext,
+    handler: &mut impl SmtpHandler,
+    reason: CloseReason,
+) -> std::io::Result<SessionSummary> {
+    if matches!(reason, CloseReason::Shutdown) {
+        state.audit.source(RejectionSource::Server);
+        write_fmt_line!(
+            write_stream,
+            "421 4.3.2 {} {}",
+            state.config.hostname(),
+            state.config.unavailable_text()
+        )?;
+    }
+
+    state.events.send(|| SessionEvent::Closed).await;
+    crate::transcript::save(state).await;
+    handler.on_close(state).await;
+
+    log::closed(state, &reason);
+    Ok(SessionSummary::new(state, reason))
+}
+
+/// Check the client before greeting it, returning `stream` to greet it through, or refusing the
+/// connection and returning `None`.
+///
+/// [`HandlerFactory::on_connect`] decides first, then clients that talk before the greeting are
+/// checked for with [`delay_greeting`], their names are looked up per
+/// [`ServerConfig::reverse_dns_policy`], and with the `dnsbl` feature, they are looked up in
+/// [`ServerConfig::dnsbl_policy`].
+async fn screen<F: HandlerFactory>(
+    stream: TcpStream,
+    state: &mut SessionContext,
+    factory: &F,
+) -> Option<TcpStream> {
+    match factory.on_connect(state.session.peer_addr).await {
+        ConnectDecision::Accept => (),
+        ConnectDecision::Drop => return None,
+        ConnectDecision::Reject => {
+            let reply = refuse(stream, &state.config).await;
+            state.audit.refused(&reply, RejectionSource::Handler);
+            return None;
+        }
+        ConnectDecision::Tarpit(delay) => tokio::time::sleep(delay).await,
+    }
+
+    if delay_greeting(&stream, state).await {
+        let reply = refuse(stream, &state.config).await;
+        state
+            .audit
+            .refused(&reply, RejectionSource::Policy("reject_early_talkers"));
+        return None;
+    }
+
+    let policy = state.config.reverse_dns_policy();
+    if policy != ReverseDnsPolicy::Off {
+        let ip = state.session.peer_addr.ip().to_canonical();
+        let reverse_dns = reverse_dns::verify(state.config.resolver(), ip).await;
+        let verified = matches!(reverse_dns, ReverseDns::Verified(_));
+        if !verified {
+            println!(
+                "[{}] The name of {ip} could not be confirmed ({reverse_dns:?})",
+                state.session.id
+            );
+        }
+        if !verified && policy == ReverseDnsPolicy::Reject {
+            let reply = refuse_unnamed(stream, &state.config, ip, &reverse_dns).await;
+            state
+                .audit
+                .refused(&reply, RejectionSource::Policy("reverse_dns_policy"));
+            return None;
+        }
+        state.session.reverse_dns = Some(reverse_dns);
+    }
+
+    #[cfg(feature = "dnsbl")]
+    {
+        state.session.dnsbl = crate::dnsbl::check(&state.config, &state.session).await;
+        if let Some(dnsbl) = state.session.dnsbl().filter(|dnsbl| dnsbl.refuses()) {
+            let reply =
+                refuse_listed(stream, &state.config, state.session.peer_addr, dnsbl.zone()).await;
+            state
+                .audit
+                .refused(&reply, RejectionSource::Policy("dnsbl"));
+            return None;
+        }
+    }
+
+    Some(stream)
+}
+
+/// Wait for [`ServerConfig::greeting_delay`] before greeting the client, noting whether it sent
+/// anything during the wait in [`SessionInfo::is_early_talker`].
+///
+/// Anything that the client sent is left to be read as its first command. Returns whether the
+/// client should be refused, per [`ServerConfig::reject_early_talkers`].
+async fn delay_greeting(stream: &TcpStream, state: &mut SessionContext) -> bool {
+    let delay = state.config.greeting_delay();
+    if delay.is_zero() {
+        return false;
+    }
+
+    let deadline = tokio::time::Instant::now() + delay;
+    if let Ok(Ok(read)) = tokio::time::timeout_at(deadline, stream.peek(&mut [0])).await {
+        state.session.early_talker = read > 0;
+    }
+    tokio::time::sleep_until(deadline).await;
+
+    if state.session.early_talker {
+        println!(
+            "[{}] {} talked before the greeting",
+            state.session.id, state.session.peer_addr
+        );
+    }
+    state.session.early_talker && state.config.reject_early_talkers()
+}
+
+/// Encrypt `transport` with TLS after `STARTTLS`, or before the greeting with
+/// [`crate::Listener::with_implicit_tls`], returning the halves of the encrypted connection.
+///
+/// Everything learned from the client before the handshake is forgotten, so the client must greet
+/// the server again.
+///
+/// [RFC 3207 section 4.2](https://www.rfc-editor.org/rfc/rfc3207.html#section-4.2).
+///
+/// # Errors
+///
+/// - I/O and TLS errors from the handshake, or [`std::io::ErrorKind::TimedOut`] if it takes longer
+///   than [`crate::timeouts::Timeouts::server`].
+#[cfg(feature = "tls")]
+async fn start_tls(
+    transport: Transport,
+    state: &mut SessionContext,
+) -> std::io::Result<(transport::Reader, transport::Writer)> {
+    use crate::tls::rustls::ProtocolVersion;
+
+    state.starting_tls = false;
+    let (
+        Transport {
+            stream: transport::Stream::Tcp(stream),
+            transcript,
+            counters,
+            audit,
+        },
+        Some(tls),
+    ) = (transport, state.config.tls())
+    else {
+        return Err(std::io::Error::other(
+            "TLS is already started or not offered",
+        ));
+    };
+
+    let stream = tokio::time::timeout(
+        state.config.timeouts().server(),
+        tls.acceptor().accept(stream),
+    )
+    .await??;
+
+    let (_, connection) = stream.get_ref();
+    let mut info = crate::session::TlsInfo::new(
+        match connection.protocol_version() {
+            Some(ProtocolVersion::TLSv1_2) => "TLSv1.2",
+            Some(ProtocolVersion::TLSv1_3) => "TLSv1.3",
+            version => version
+                .and_then(|version| version.as_str())
+                .unwrap_or_default(),
+        },
+        connection
+            .negotiated_cipher_suite()
+            .and_then(|suite| suite.suite().as_str())
+            .unwrap_or_default(),
+    )
+    .with_peer_certificates(
+        connection
+            .peer_certificates()
+            .unwrap_or_default()
+            .iter()
+            .map(|certificate| certificate.to_vec())
+            .collect(),
+    );
+    if let Some(name) = connection.server_name() {
+        info = info.with_server_name(name);
+    }
+
+    state.events.send(|| SessionEvent::Tls(info.clone())).await;
+    state.session.tls = Some(info);
+    state.session.helo = None;
+    state.session.extended = false;
+    state.session.helo_failures.clear();
+    state.transaction = None;
+    state.extensions.clear();
+
+    Ok(Transport {
+        stream: transport::Stream::Tls(Box::new(stream)),
+        transcript,
+        counters,
+        audit,
+    }
+    .split())
+}
+
+/// Refuse a TCP connection by replying with `554` in place of the greeting, then closing it,
+/// returning the reply.
+///
+/// Errors are ignored, as the connection is being closed either way.
+///
+/// [RFC 5321 section 3.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-3.1).
+pub async fn refuse(stream: TcpStream, config: &ServerConfig) -> String {
+    let reply = format!("554 {} {}", config.hostname(), config.refusal_text());
+    send_refusal(stream, reply).await
+}
+
+/// Refuse a TCP connection from a client at `ip` whose name could not be confirmed by replying in
+/// place of the greeting, then closing it, returning the reply. See [`ReverseDnsPolicy::Reject`].
+///
+/// Errors are ignored, as the connection is being closed either way.
+async fn refuse_unnamed(
+    stream: TcpStream,
+    config: &ServerConfig,
+    ip: std::net::IpAddr,
+    reverse_dns: &ReverseDns,
+) -> String {
+    let reply = if *reverse_dns == ReverseDns::TempError {
+        format!(
+            "421 4.7.25 {} Reverse DNS lookup of [{ip}] failed, try again later",
+            config.hostname()
+        )
+    } else {
+        format!(
+            "554 5.7.25 {} Reverse DNS validation failed for [{ip}]",
+            config.hostname()
+        )
+    };
+    send_refusal(stream, reply).await
+}
+
+/// Refuse a TCP connection from a client that is listed on the DNS blocklist `zone` by replying
+/// with `554 5.7.1` in place of the greeting, then closing it, returning the reply. See
+/// [`crate::dnsbl::DnsblAction::Refuse`].
+///
+/// Errors are ignored, as the connection is being closed either way.
+#[cfg(feature = "dnsbl")]
+async fn refuse_listed(
+    stream: TcpStream,
+    config: &ServerConfig,
+    client_socket: std::net::SocketAddr,
+    zone: &str,
+) -> String {
+    let reply = format!(
+        "554 5.7.1 {} Client host [{}] blocked using {zone}",
+        config.hostname(),
+        client_socket.ip().to_canonical()
+    );
+    send_refusal(stream, reply).await
+}
+
+/// Turn away a TCP connection past [`ServerConfig::max_connections`] or
+/// [`ServerConfig::peer_limits`] by replying with `421` and `text` in place of the greeting, then
+/// closing it, returning the reply.
+///
+/// Errors are ignored, as the connection is being closed either way.
+pub async fn overflow(stream: TcpStream, config: &ServerConfig, text: &str) -> String {
+    let reply = format!("421 4.3.2 {} {text}", config.hostname());
+    send_refusal(stream, reply).await
+}
+
+/// Reply with `reply` in place of the greeting, then close `stream`, returning `reply`.
+///
+/// Errors are ignored, as the connection is being closed either way.
+async fn send_refusal(mut stream: TcpStream, reply: String) -> String {
+    let _ = write_fmt_line!(stream, "{reply}");
+    let _ = stream.shutdown().await;
+    reply
+}
+
+/// How the messages received in an SMTP session are handed off to the consumer.
+#[derive(Debug, Clone)]
+pub enum Delivery {
+    /// Receive the data of each message into memory, then hand it to
+    /// [`SmtpHandler::on_message`].
+    Buffered,
+    /// Send each message as soon as its data starts, streaming the data as it is received.
+    Streaming(mpsc::Sender<StreamingMessage>),
+}
+
+/// A mail transaction in progress, started by `MAIL FROM`.
+///
+/// [RFC 5321 section 3.3](https://www.rfc-editor.org/rfc/rfc5321.html#section-3.3).
+#[derive(Debug)]
+pub struct Transaction {
+    /// The envelope from `MAIL FROM` and every accepted `RCPT TO`.
+    pub envelope: Envelope,
+    /// When `MAIL FROM` was accepted.
+    pub started_at: SystemTime,
+}
+
+impl Transaction {
+    /// Start a new [`Self`] with the envelope from `MAIL FROM`.
+    pub fn new(envelope: Envelope) -> Self {
+        Self {
+            envelope,
+            started_at: SystemTime::now(),
+        }
+    }
 }
 
 /// Indicates if and why a TCP connection should be closed.
@@ -127,17 +670,3 @@ enum ShouldClose {
     /// The TCP connection should be closed because [`CloseReason`].
     Close(CloseReason),
 }
-
-/// Indicates why a TCP connection should be closed.
-#[derive(PartialEq, Eq, Debug)]
-#[expect(dead_code)]
-enum CloseReason {
-    /// The SMTP client requested to quit the session.
-    Quit,
-    /// An error occurred in the implementation.
-    Error,
-    /// More time [`Elapsed`] than [`crate::timeouts::SERVER_TIMEOUT`] specifies.
-    TimedOut(Elapsed),
-    /// The TCP connection was forcefully ended by the client.
-    ClosedByClient,
-}
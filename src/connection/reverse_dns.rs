@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Looks up the name of the address of each client, confirming that it resolves back to the
+//! address, per [`crate::config::ReverseDnsPolicy`].
+//!
+//! See [`verify`].
+
+use std::net::IpAddr;
+
+use super::command::is_domain;
+use crate::{resolver::Resolver, session::ReverseDns};
+
+/// The most names of the address that are resolved to confirm it.
+///
+/// [RFC 7208 section 5.5](https://www.rfc-editor.org/rfc/rfc7208.html#section-5.5).
+const MAX_NAMES: usize = 10;
+
+/// Look up the names of `ip` with `resolver`, returning the first one that resolves back to `ip`.
+///
+/// Names that are not ASCII domain names are skipped, as they could not be given in a header.
+///
+/// Fails with [`ReverseDns::TempError`] if no name was confirmed and a lookup failed, as the name
+/// could have been confirmed otherwise.
+pub async fn verify(resolver: &impl Resolver, ip: IpAddr) -> ReverseDns {
+    let ip = ip.to_canonical();
+    let Ok(names) = resolver.lookup_ptr(ip).await else {
+        return ReverseDns::TempError;
+    };
+
+    let mut failed = false;
+    for name in names
+        .into_iter()
+        .take(MAX_NAMES)
+        .filter(|name| is_domain(name))
+    {
+        match resolver.lookup_ip(&name).await {
+            Ok(addresses) if addresses.iter().any(|address| address.to_canonical() == ip) => {
+                return ReverseDns::Verified(name);
+            }
+            Ok(_) => (),
+            Err(_) => failed = true,
+        }
+    }
+
+    if failed {
+        ReverseDns::TempError
+    } else {
+        ReverseDns::Unknown
+    }
+}
@@ -0,0 +1,380 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use super::{
+    data::{receive, Destination, Exceeded, Limits, Reception},
+    line::{Line, LineReader},
+    reverse_dns,
+};
+use crate::{event::EventSender, message::ContentHash, resolver::Resolver, session::ReverseDns};
+
+type Result = std::result::Result<(), Box<dyn std::error::Error>>;
+
+/// [`Limits`] that no test data comes close to.
+const UNLIMITED: Limits = Limits {
+    message_size: usize::MAX,
+    header_size: usize::MAX,
+    header_fields: usize::MAX,
+    header_field_length: usize::MAX,
+    line_length: usize::MAX,
+    data_block: Duration::MAX,
+};
+
+#[tokio::test]
+async fn test_data_size() -> Result {
+    const DATA: &[u8] = b"Subject: test\r\n\tfolded\r\n\r\n..body\r\n.\r\n";
+
+    let mut destination = Destination::Buffer(Vec::new());
+    let Reception { size, hash, .. } = receive(
+        &mut LineReader::new(DATA),
+        &mut destination,
+        &UNLIMITED,
+        &mut EventSender::default(),
+    )
+    .await?
+    .expect("the data is complete");
+
+    assert_eq!(size.header(), 26);
+    assert_eq!(size.body(), 7);
+
+    let Destination::Buffer(buffer) = destination else {
+        unreachable!()
+    };
+    assert_eq!(buffer, b"Subject: test\r\n\tfolded\r\n\r\n.body\r\n");
+    // Tests that the digest is computed over the data without dot-stuffing.
+    assert_eq!(hash, ContentHash::of(&buffer));
+
+    // Tests that the data is discarded, but still counted, once it exceeds the limit.
+    let mut destination = Destination::Buffer(Vec::new());
+    let limits = Limits {
+        message_size: 20,
+        ..UNLIMITED
+    };
+    let reception = receive(
+        &mut LineReader::new(DATA),
+        &mut destination,
+        &limits,
+        &mut EventSender::default(),
+    )
+    .await?
+    .expect("the data is complete");
+
+    assert_eq!(reception.size.total(), 33);
+    assert_eq!(reception.exceeded, Some(Exceeded::MessageSize));
+
+    let Destination::Buffer(buffer) = destination else {
+        unreachable!()
+    };
+    assert!(buffer.is_empty());
+
+    // Tests that a line that is not a header field ends the header section.
+    let mut destination = Destination::Buffer(Vec::new());
+    let Reception { size, .. } = receive(
+        &mut LineReader::new(&b"Subject: test\r\nbody\r\n.\r\n"[..]),
+        &mut destination,
+        &UNLIMITED,
+        &mut EventSender::default(),
+    )
+    .await?
+    .expect("the data is complete");
+
+    assert_eq!(size.header(), 15);
+    assert_eq!(size.body(), 6);
+
+    // Tests that 8-bit data is kept as it was sent.
+    let mut destination = Destination::Buffer(Vec::new());
+    receive(
+        &mut LineReader::new(&b"Subject: caf\xe9\r\n\r\n\xff\xfe\r\n.\r\n"[..]),
+        &mut destination,
+        &UNLIMITED,
+        &mut EventSender::default(),
+    )
+    .await?
+    .expect("the data is complete");
+
+    let Destination::Buffer(buffer) = destination else {
+        unreachable!()
+    };
+    assert_eq!(buffer, b"Subject: caf\xe9\r\n\r\n\xff\xfe\r\n");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_header_limits() -> Result {
+    const DATA: &[u8] = b"To: jones@example.com\r\n\
+        Subject: test\r\n\
+        \tfolded\r\n\
+        \r\n\
+        body that is much longer than the header fields\r\n\
+        .\r\n";
+
+    for (limits, expected) in [
+        (
+            Limits {
+                header_size: 40,
+                ..UNLIMITED
+            },
+            Some(Exceeded::HeaderSize),
+        ),
+        (
+            Limits {
+                header_fields: 1,
+                ..UNLIMITED
+            },
+            Some(Exceeded::HeaderFields),
+        ),
+        (
+            Limits {
+                header_field_length: 23,
+                ..UNLIMITED
+            },
+            Some(Exceeded::HeaderFieldLength),
+        ),
+        // Tests that the body is not counted towards the header limits.
+        (
+            Limits {
+                header_size: 49,
+                header_fields: 2,
+                header_field_length: 24,
+                ..UNLIMITED
+            },
+            None,
+        ),
+    ] {
+        let mut destination = Destination::Buffer(Vec::new());
+        let reception = receive(
+            &mut LineReader::new(DATA),
+            &mut destination,
+            &limits,
+            &mut EventSender::default(),
+        )
+        .await?
+        .expect("the data is complete");
+
+        assert_eq!(reception.exceeded, expected);
+
+        let Destination::Buffer(buffer) = destination else {
+            unreachable!()
+        };
+        assert_eq!(buffer.is_empty(), expected.is_some());
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_line_length() -> Result {
+    const DATA: &[u8] = b"Subject: test\r\n\r\nbody that is longer than the header\r\n.\r\n";
+
+    // Tests that a line exactly at the limit is kept, and that the reader resynchronizes to the
+    // line after one that is too long.
+    let mut reader = LineReader::new(&b"NOOP\r\nNOOP NOOP\r\nQUIT"[..]);
+    let Line::Complete(line) = reader.read(6).await? else {
+        unreachable!()
+    };
+    assert_eq!(line, b"NOOP\r\n");
+    assert_eq!(reader.read(6).await?, Line::TooLong);
+    let Line::Complete(line) = reader.read(6).await? else {
+        unreachable!()
+    };
+    assert_eq!(line, b"QUIT");
+    assert_eq!(
+        reader.read(6).await.map_err(|err| err.kind()),
+        Err(std::io::ErrorKind::ConnectionAborted)
+    );
+
+    // Tests that the buffer is reused for each line rather than allocated again.
+    let mut reader = LineReader::new(&b"NOOP\r\nQUIT\r\n"[..]);
+    let Line::Complete(first) = reader.read(6).await? else {
+        unreachable!()
+    };
+    let first = first.as_ptr();
+    let Line::Complete(second) = reader.read(6).await? else {
+        unreachable!()
+    };
+    assert_eq!(second, b"QUIT\r\n");
+    assert_eq!(second.as_ptr(), first);
+
+    for (line_length, expected) in [(37, None), (36, Some(Exceeded::LineLength))] {
+        let limits = Limits {
+            line_length,
+            ..UNLIMITED
+        };
+        let mut destination = Destination::Buffer(Vec::new());
+        let reception = receive(
+            &mut LineReader::new(DATA),
+            &mut destination,
+            &limits,
+            &mut EventSender::default(),
+        )
+        .await?
+        .expect("the data is complete");
+
+        assert_eq!(reception.exceeded, expected);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bare_line_endings() -> Result {
+    // Tests that a period after a bare line ending does not end the data, so that what follows it
+    // cannot be smuggled in as commands or another message, and that the message is rejected.
+    for (data, expected) in [
+        (
+            &b"Subject: test\r\n\r\nbody\n.\r\nMAIL FROM:<smith@example.com>\r\n.\r\n"[..],
+            Exceeded::BareLineEnding,
+        ),
+        (
+            b"Subject: test\r\n\r\nbody\r.\r\nMAIL FROM:<smith@example.com>\r\n.\r\n",
+            Exceeded::BareLineEnding,
+        ),
+        (
+            b"Subject: test\r\n\r\nbody that is too long\n.\r\nRSET\r\n.\r\n",
+            Exceeded::LineLength,
+        ),
+    ] {
+        let mut reader = LineReader::new(data);
+        let mut destination = Destination::Buffer(Vec::new());
+        let limits = Limits {
+            line_length: 20,
+            ..UNLIMITED
+        };
+        let reception = receive(
+            &mut reader,
+            &mut destination,
+            &limits,
+            &mut EventSender::default(),
+        )
+        .await?
+        .expect("the data is complete");
+
+        assert_eq!(reception.exceeded, Some(expected));
+        // Tests that everything up to the real end of the data was read.
+        assert_eq!(
+            reader.read(usize::MAX).await.map_err(|err| err.kind()),
+            Err(io::ErrorKind::ConnectionAborted)
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reverse_dns() {
+    /// Names `192.0.2.1` `mail.example.com`, which resolves back to it, `192.0.2.2`
+    /// `spoofed.example.com`, which does not, `192.0.2.3` `fail.example.com`, which fails to
+    /// resolve, and `192.0.2.5` names that are not ASCII domain names, which resolve back to it.
+    struct Stub;
+
+    impl Resolver for Stub {
+        async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+            match name {
+                "mail.example.com" => Ok(vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]),
+                "fail.example.com" => Err(io::Error::other("lookup failed")),
+                "bücher.example" | "bad_name.example" => {
+                    Ok(vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 5))])
+                }
+                _ => Ok(vec![IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1))]),
+            }
+        }
+
+        async fn lookup_ptr(&self, address: IpAddr) -> io::Result<Vec<String>> {
+            Ok(match address {
+                IpAddr::V4(address) if address.octets()[3] == 1 => {
+                    vec!["mail.example.com".to_owned()]
+                }
+                IpAddr::V4(address) if address.octets()[3] == 2 => {
+                    vec!["spoofed.example.com".to_owned()]
+                }
+                IpAddr::V4(address) if address.octets()[3] == 3 => {
+                    vec!["fail.example.com".to_owned()]
+                }
+                IpAddr::V4(address) if address.octets()[3] == 5 => {
+                    vec!["bücher.example".to_owned(), "bad_name.example".to_owned()]
+                }
+                _ => Vec::new(),
+            })
+        }
+    }
+
+    let verify = |last| reverse_dns::verify(&Stub, IpAddr::V4(Ipv4Addr::new(192, 0, 2, last)));
+    assert_eq!(
+        verify(1).await,
+        ReverseDns::Verified("mail.example.com".to_owned())
+    );
+    // Tests that a name that does not resolve back to the address is not confirmed.
+    assert_eq!(verify(2).await, ReverseDns::Unknown);
+    assert_eq!(verify(3).await, ReverseDns::TempError);
+    assert_eq!(verify(4).await, ReverseDns::Unknown);
+    // Tests that names that are not ASCII domain names are not confirmed.
+    assert_eq!(verify(5).await, ReverseDns::Unknown);
+
+    // Tests that a resolver that cannot look up names fails temporarily.
+    assert_eq!(
+        reverse_dns::verify(
+            &crate::resolver::SystemResolver,
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        )
+        .await,
+        ReverseDns::TempError
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_log_record() {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use tokio::sync::broadcast;
+
+    use super::{log, CloseReason};
+    use crate::{handler::SessionContext, session::SessionInfo, ServerConfig};
+
+    let local = SocketAddr::from((Ipv4Addr::LOCALHOST, 25));
+    let peer = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 40000));
+    let mut state = SessionContext::new(
+        SessionInfo::new(local, peer),
+        Arc::new(ServerConfig::default()),
+        broadcast::channel(1).0,
+    );
+    state.session.helo = Some("client.example.com".parse().unwrap());
+    state.authenticate("smith");
+    state.commands = 6;
+    state.messages_accepted = 1;
+
+    let record = log::record(&state, &CloseReason::Quit);
+    assert_eq!(record["session_id"], state.session.id.to_string());
+    assert_eq!(record["local_addr"], "127.0.0.1:25");
+    assert_eq!(record["peer_addr"], "192.0.2.1:40000");
+    assert_eq!(record["helo"], "client.example.com");
+    assert_eq!(record["authenticated_user"], "smith");
+    assert_eq!(record["tls"], false);
+    assert_eq!(record["commands"], 6);
+    assert_eq!(record["messages_accepted"], 1);
+    assert_eq!(record["close_reason"], "quit");
+    assert!(record["duration_secs"].as_f64().is_some());
+}
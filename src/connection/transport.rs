@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The byte streams that SMTP sessions are carried over.
+//!
+//! See [`Transport`].
+
+use std::{
+    io::IoSlice,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, BufReader, ReadBuf, ReadHalf, WriteHalf},
+    net::TcpStream,
+};
+
+use super::line::LineReader;
+use crate::{
+    audit::Audit, handler::SessionContext, session::Activity, stats::Counters, transcript::Recorder,
+};
+
+/// Reads the lines sent by the client in an SMTP session.
+pub type Reader = LineReader<BufReader<ReadHalf<Transport>>>;
+
+/// Writes the replies to the client in an SMTP session.
+pub type Writer = WriteHalf<Transport>;
+
+/// The connection that an SMTP session is carried over, which is encrypted after `STARTTLS`.
+///
+/// With [`crate::ServerConfig::transcripts`], every byte read and written is recorded as it is
+/// seen inside of TLS.
+#[derive(Debug)]
+pub struct Transport {
+    /// The byte stream of the connection.
+    pub(super) stream: Stream,
+    /// Records the transcript of the session, if it is recorded.
+    pub(super) transcript: Option<Arc<Recorder>>,
+    /// Counts the replies that reject or defer for [`crate::ServerHandle::stats`].
+    pub(super) counters: Arc<Counters>,
+    /// Turns the replies that reject or defer into [`crate::audit::Rejection`]s.
+    pub(super) audit: Arc<Audit>,
+}
+
+/// The byte stream of a [`Transport`].
+#[derive(Debug)]
+pub enum Stream {
+    /// A plain TCP connection.
+    Tcp(Metered<TcpStream>),
+    /// A TCP connection encrypted with TLS.
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::server::TlsStream<Metered<TcpStream>>>),
+}
+
+impl Transport {
+    /// Create a new [`Self`] over the plain TCP connection `stream` of the session of `state`,
+    /// counting what it sends with `counters`.
+    pub fn new(
+        stream: Metered<TcpStream>,
+        state: &SessionContext,
+        counters: Arc<Counters>,
+    ) -> Self {
+        Self {
+            stream: Stream::Tcp(stream),
+            transcript: state.transcript.clone(),
+            counters,
+            audit: state.audit.clone(),
+        }
+    }
+
+    /// Split `self` into a [`Reader`] and [`Writer`].
+    pub fn split(self) -> (Reader, Writer) {
+        let (read, write) = tokio::io::split(self);
+        (LineReader::new(BufReader::new(read)), write)
+    }
+
+    /// Join a [`Reader`] and [`Writer`] from [`Self::split`] back into [`Self`].
+    ///
+    /// Anything buffered by the reader is discarded, so that nothing sent before `STARTTLS` is
+    /// read as if it were sent after it.
+    ///
+    /// [RFC 3207 section 6](https://www.rfc-editor.org/rfc/rfc3207.html#section-6).
+    #[cfg(feature = "tls")]
+    pub fn unsplit(reader: Reader, writer: Writer) -> Self {
+        reader.into_inner().into_inner().unsplit(writer)
+    }
+}
+
+impl AsyncRead for Transport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+
+        let poll = match &mut this.stream {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        };
+
+        if let (Poll::Ready(Ok(())), Some(transcript)) = (&poll, &this.transcript) {
+            transcript.received(&buf.filled()[before..]);
+        }
+
+        poll
+    }
+}
+
+// Vectored writes are left to their default of writing one buffer at a time through
+// `poll_write`, so that every write is seen there.
+impl AsyncWrite for Transport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+
+        let poll = match &mut this.stream {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        };
+
+        if let Poll::Ready(Ok(written)) = poll {
+            // Replies are small enough to be written at once, so the written bytes start on a
+            // line.
+            #[cfg(feature = "metrics")]
+            crate::metrics::replies(&buf[..written]);
+            this.counters.replies(&buf[..written]);
+            this.audit.replied(&buf[..written]);
+            if let Some(transcript) = &this.transcript {
+                transcript.sent(&buf[..written]);
+            }
+        }
+
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match &mut self.get_mut().stream {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match &mut self.get_mut().stream {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// A byte stream that counts the bytes that go through it into the [`Activity`] of its session.
+#[derive(Debug)]
+pub struct Metered<S> {
+    /// The byte stream that is counted.
+    stream: S,
+    /// Where the bytes are counted.
+    activity: Arc<Activity>,
+}
+
+impl<S> Metered<S> {
+    /// Create a new [`Self`] that counts the bytes of `stream` into `activity`.
+    pub const fn new(stream: S, activity: Arc<Activity>) -> Self {
+        Self { stream, activity }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+
+        let poll = Pin::new(&mut this.stream).poll_read(cx, buf);
+        if matches!(poll, Poll::Ready(Ok(()))) {
+            this.activity.add_received(buf.filled().len() - before);
+        }
+
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+
+        let poll = Pin::new(&mut this.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.activity.add_sent(written);
+        }
+
+        poll
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+
+        let poll = Pin::new(&mut this.stream).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.activity.add_sent(written);
+        }
+
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
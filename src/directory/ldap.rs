@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Verifies addresses against an LDAP directory, see [`LdapVerifier`].
+
+use std::{fmt::Debug, io, time::Duration};
+
+use ldap3::{ldap_escape, LdapConnAsync, LdapConnSettings, Scope};
+
+use super::AddressVerifier;
+use crate::redact;
+
+/// The filter that entries are searched for with by default, matching the `mail` attribute.
+const DEFAULT_FILTER: &str = "(mail={address})";
+
+/// Verifies addresses by searching an LDAP directory for entries that match them.
+///
+/// Each lookup connects to [`Self::url`], binds as [`Self::bind_dn`] if there is one, and searches
+/// the subtree under [`Self::base`] with [`Self::filter`], in which `{address}` is replaced with
+/// the escaped address. An address has a mailbox if any entry matches. Only `ldap://` and
+/// `ldapi://` URLs are supported.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use smtp_gateway::directory::LdapVerifier;
+/// #
+/// let verifier = LdapVerifier::new("ldap://127.0.0.1:389", "ou=people,dc=example,dc=com")
+///     .with_filter("(|(mail={address})(mailAlternateAddress={address}))")
+///     .with_bind("cn=smtp,dc=example,dc=com", "secret")
+///     .with_timeout(Duration::from_secs(5));
+///
+/// assert_eq!(verifier.bind_dn(), Some("cn=smtp,dc=example,dc=com"));
+/// ```
+#[derive(Clone)]
+pub struct LdapVerifier {
+    /// The URL of the directory server.
+    url: String,
+    /// The DN that entries are searched under.
+    base: String,
+    /// The filter that entries are searched for with.
+    filter: String,
+    /// The DN and password to bind as, or `None` to search anonymously.
+    bind: Option<(String, String)>,
+    /// How long each lookup may take.
+    timeout: Duration,
+}
+
+impl LdapVerifier {
+    /// Create a new [`Self`] that searches the directory at `url` under `base`.
+    #[must_use]
+    pub fn new(url: &str, base: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            base: base.to_owned(),
+            filter: DEFAULT_FILTER.to_owned(),
+            bind: None,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Get the URL of the directory server, such as `ldap://127.0.0.1:389`.
+    #[must_use]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Get the DN that entries are searched under, such as `ou=people,dc=example,dc=com`.
+    #[must_use]
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// Get the filter that entries are searched for with, `(mail={address})` by default.
+    #[must_use]
+    pub fn filter(&self) -> &str {
+        &self.filter
+    }
+
+    /// Get the DN that is bound as before searching, or `None` if searches are anonymous.
+    #[must_use]
+    pub fn bind_dn(&self) -> Option<&str> {
+        self.bind.as_ref().map(|(dn, _)| dn.as_str())
+    }
+
+    /// Get how long each lookup may take, ten seconds by default.
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Search for entries with `filter`, in which `{address}` is replaced with the address. See
+    /// [`Self::filter`].
+    #[must_use]
+    pub fn with_filter(mut self, filter: &str) -> Self {
+        filter.clone_into(&mut self.filter);
+        self
+    }
+
+    /// Bind as `dn` with `password` before searching. See [`Self::bind_dn`].
+    #[must_use]
+    pub fn with_bind(mut self, dn: &str, password: &str) -> Self {
+        self.bind = Some((dn.to_owned(), password.to_owned()));
+        self
+    }
+
+    /// Set how long each lookup may take. See [`Self::timeout`].
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Get the filter that `address` is searched for with.
+    #[expect(
+        clippy::literal_string_with_formatting_args,
+        reason = "`{address}` is the placeholder of the filter, not a format argument"
+    )]
+    pub(super) fn filter_for(&self, address: &str) -> String {
+        self.filter.replace("{address}", &ldap_escape(address))
+    }
+
+    /// Search the directory for `address`, returning whether any entry matched.
+    async fn search(&self, address: &str) -> Result<bool, ldap3::LdapError> {
+        let settings = LdapConnSettings::new().set_conn_timeout(self.timeout);
+        let (connection, mut ldap) = LdapConnAsync::with_settings(settings, &self.url).await?;
+        ldap3::drive!(connection);
+
+        if let Some((dn, password)) = &self.bind {
+            ldap.simple_bind(dn, password).await?.success()?;
+        }
+        // `1.1` requests no attributes, as only whether an entry matches is needed.
+        let (entries, _) = ldap
+            .search(
+                &self.base,
+                Scope::Subtree,
+                &self.filter_for(address),
+                ["1.1"],
+            )
+            .await?
+            .success()?;
+        ldap.unbind().await?;
+
+        Ok(!entries.is_empty())
+    }
+}
+
+impl Debug for LdapVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LdapVerifier")
+            .field("url", &redact::url(&self.url))
+            .field("base", &self.base)
+            .field("filter", &self.filter)
+            .field("bind_dn", &self.bind_dn())
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AddressVerifier for LdapVerifier {
+    async fn verify(&self, address: &str) -> io::Result<bool> {
+        tokio::time::timeout(self.timeout, self.search(address))
+            .await?
+            .map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,245 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Recipient validation against directories of addresses, so that mail to users that do not exist
+//! is rejected during the session instead of accepted and bounced later.
+//!
+//! See [`AddressVerifier`], and [`crate::ServerConfig::address_verifier`].
+
+#[cfg(feature = "ldap")]
+mod ldap;
+#[cfg(feature = "sql")]
+mod sql;
+#[cfg(test)]
+mod test;
+
+use std::{collections::HashSet, fmt::Debug, future::Future, io, sync::Arc};
+
+use futures_util::{future::BoxFuture, FutureExt};
+
+#[cfg(feature = "ldap")]
+pub use self::ldap::LdapVerifier;
+#[cfg(feature = "sql")]
+pub use self::sql::SqlVerifier;
+use crate::{
+    handler::{Decision, Response},
+    message::envelope::Recipient,
+    session::SessionInfo,
+    ServerConfig,
+};
+
+/// Looks up whether an address has a mailbox, such as [`StaticVerifier`], or with the `ldap` and
+/// `sql` features, `LdapVerifier` and `SqlVerifier`.
+///
+/// Addresses are given lowercased, in the form of `local-part@domain`, without angle brackets.
+pub trait AddressVerifier: Send + Sync + 'static {
+    /// Get whether `address` has a mailbox.
+    fn verify(&self, address: &str) -> impl Future<Output = io::Result<bool>> + Send;
+}
+
+/// Verifies addresses against a fixed set of mailboxes and domains, kept in memory.
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::directory::{AddressVerifier, StaticVerifier};
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> std::io::Result<()> {
+/// let verifier = StaticVerifier::new()
+///     .with_address("Smith@example.com")
+///     .with_domain("lists.example.com");
+///
+/// assert!(verifier.verify("smith@example.com").await?);
+/// assert!(verifier.verify("any@lists.example.com").await?);
+/// assert!(!verifier.verify("jones@example.com").await?);
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct StaticVerifier {
+    /// The lowercased addresses that have a mailbox.
+    addresses: HashSet<String>,
+    /// The lowercased domains where every address has a mailbox.
+    domains: HashSet<String>,
+}
+
+impl StaticVerifier {
+    /// Create a new [`Self`] without any mailboxes.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the lowercased addresses that have a mailbox.
+    #[must_use]
+    pub const fn addresses(&self) -> &HashSet<String> {
+        &self.addresses
+    }
+
+    /// Get the lowercased domains where every address has a mailbox.
+    #[must_use]
+    pub const fn domains(&self) -> &HashSet<String> {
+        &self.domains
+    }
+
+    /// Add a mailbox for `address`, which is not case-sensitive.
+    #[must_use]
+    pub fn with_address(mut self, address: &str) -> Self {
+        self.addresses.insert(address.to_ascii_lowercase());
+        self
+    }
+
+    /// Accept every address at `domain`, which is not case-sensitive, such as one that is handled
+    /// by a catch-all mailbox.
+    #[must_use]
+    pub fn with_domain(mut self, domain: &str) -> Self {
+        self.domains.insert(domain.to_ascii_lowercase());
+        self
+    }
+
+    /// Get whether `address` has a mailbox, ignoring case.
+    fn contains(&self, address: &str) -> bool {
+        let address = address.to_ascii_lowercase();
+        let domain = address.rsplit_once('@').map(|(_, domain)| domain);
+
+        self.addresses.contains(&address) || domain.is_some_and(|d| self.domains.contains(d))
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for StaticVerifier {
+    /// Collect a [`Self`] with a mailbox for each address.
+    fn from_iter<T: IntoIterator<Item = S>>(iter: T) -> Self {
+        iter.into_iter().fold(Self::new(), |verifier, address| {
+            verifier.with_address(address.as_ref())
+        })
+    }
+}
+
+impl AddressVerifier for StaticVerifier {
+    async fn verify(&self, address: &str) -> io::Result<bool> {
+        Ok(self.contains(address))
+    }
+}
+
+/// An [`AddressVerifier`] that can be shared between sessions and kept in a
+/// [`crate::ServerConfig`].
+///
+/// Compares equal to clones of itself.
+#[derive(Clone)]
+pub struct SharedAddressVerifier {
+    /// The verifier that addresses are looked up with.
+    verifier: Arc<dyn DynAddressVerifier>,
+}
+
+impl SharedAddressVerifier {
+    /// Create a new [`Self`] that looks up addresses with `verifier`.
+    pub fn new(verifier: impl AddressVerifier) -> Self {
+        Self {
+            verifier: Arc::new(verifier),
+        }
+    }
+}
+
+impl PartialEq for SharedAddressVerifier {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.verifier, &other.verifier)
+    }
+}
+
+impl Eq for SharedAddressVerifier {}
+
+impl Debug for SharedAddressVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedAddressVerifier")
+            .finish_non_exhaustive()
+    }
+}
+
+impl AddressVerifier for SharedAddressVerifier {
+    async fn verify(&self, address: &str) -> io::Result<bool> {
+        self.verifier.verify_boxed(address).await
+    }
+}
+
+/// An [`AddressVerifier`] with its method boxed, so that it can be used as a trait object.
+trait DynAddressVerifier: Send + Sync {
+    /// See [`AddressVerifier::verify`].
+    fn verify_boxed<'a>(&'a self, address: &'a str) -> BoxFuture<'a, io::Result<bool>>;
+}
+
+impl<V: AddressVerifier> DynAddressVerifier for V {
+    fn verify_boxed<'a>(&'a self, address: &'a str) -> BoxFuture<'a, io::Result<bool>> {
+        AddressVerifier::verify(self, address).boxed()
+    }
+}
+
+/// What [`check`] found for an address.
+pub(crate) enum Verification {
+    /// The address has a mailbox.
+    Exists,
+    /// The address does not have a mailbox.
+    Unknown,
+    /// The lookup failed.
+    Failed(io::Error),
+}
+
+/// Look up `address` with `verifier`, lowercasing it first.
+pub(crate) async fn check(verifier: &SharedAddressVerifier, address: &str) -> Verification {
+    match verifier.verify(&address.to_ascii_lowercase()).await {
+        Ok(true) => Verification::Exists,
+        Ok(false) => Verification::Unknown,
+        Err(error) => Verification::Failed(error),
+    }
+}
+
+/// Reject `decision` to accept `recipient` with `550 5.1.1` if it does not have a mailbox according
+/// to [`ServerConfig::address_verifier`], or defer it with `451 4.3.0` if the lookup failed.
+///
+/// Decisions that do not accept the recipient are left as they are, as is `postmaster`, which
+/// must always be accepted.
+pub(crate) async fn apply(
+    config: &ServerConfig,
+    session: &SessionInfo,
+    recipient: &Recipient,
+    decision: Decision,
+) -> Decision {
+    let Some(verifier) = config.address_verifier() else {
+        return decision;
+    };
+    let forward_path = recipient.forward_path().as_str();
+    let local_part = forward_path
+        .rsplit_once('@')
+        .map_or(forward_path, |(local_part, _)| local_part);
+    if !decision.is_accepted() || local_part.eq_ignore_ascii_case("postmaster") {
+        return decision;
+    }
+
+    let reply = match check(verifier, forward_path).await {
+        Verification::Exists => return decision,
+        Verification::Unknown => {
+            println!("[{}] Unknown recipient <{forward_path}>", session.id());
+            "550 5.1.1 User unknown"
+        }
+        Verification::Failed(error) => {
+            println!("[{}] Address verification failed: {error}", session.id());
+            "451 4.3.0 Address verification failed, please try again later"
+        }
+    };
+
+    Decision::Reply(Response::parse(reply).expect("written in code as a valid reply"))
+}
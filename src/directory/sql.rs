@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Verifies addresses against an SQL database, see [`SqlVerifier`].
+
+use std::io;
+
+use sqlx::AnyPool;
+
+use super::AddressVerifier;
+
+/// Verifies addresses by querying an SQL database for rows that match them.
+///
+/// [`Self::query`] is run with the address bound as its only parameter, written as `$1` for
+/// PostgreSQL and SQLite or `?` for MySQL, and an address has a mailbox if it returns any row.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::directory::SqlVerifier;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), sqlx::Error> {
+/// let verifier = SqlVerifier::connect(
+///     "postgres://smtp@localhost/mail",
+///     "SELECT 1 FROM mailboxes WHERE address = $1",
+/// )
+/// .await?;
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SqlVerifier {
+    /// The connections to the database.
+    pool: AnyPool,
+    /// The query that addresses are looked up with.
+    query: String,
+}
+
+impl SqlVerifier {
+    /// Create a new [`Self`] that runs `query` on the connections of `pool`.
+    #[must_use]
+    pub fn new(pool: AnyPool, query: &str) -> Self {
+        Self {
+            pool,
+            query: query.to_owned(),
+        }
+    }
+
+    /// Connect to the database at `url`, such as `postgres://`, `mysql://`, or `sqlite://`, and
+    /// create a new [`Self`] that runs `query` on it.
+    ///
+    /// # Errors
+    ///
+    /// - [`sqlx::Error`] if the database cannot be connected to.
+    pub async fn connect(url: &str, query: &str) -> Result<Self, sqlx::Error> {
+        sqlx::any::install_default_drivers();
+
+        Ok(Self::new(AnyPool::connect(url).await?, query))
+    }
+
+    /// Get the connections to the database.
+    #[must_use]
+    pub const fn pool(&self) -> &AnyPool {
+        &self.pool
+    }
+
+    /// Get the query that addresses are looked up with.
+    #[must_use]
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+}
+
+impl AddressVerifier for SqlVerifier {
+    async fn verify(&self, address: &str) -> io::Result<bool> {
+        sqlx::query(&self.query)
+            .bind(address)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|row| row.is_some())
+            .map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use super::*;
+
+#[tokio::test]
+async fn test_static() -> io::Result<()> {
+    let verifier = ["Smith@Example.com", "jones@example.com"]
+        .into_iter()
+        .collect::<StaticVerifier>()
+        .with_domain("Lists.Example.com");
+
+    assert!(verifier.verify("smith@example.com").await?);
+    assert!(verifier.verify("JONES@EXAMPLE.COM").await?);
+    assert!(verifier.verify("anyone@lists.example.com").await?);
+    assert!(!verifier.verify("green@example.com").await?);
+    // Tests that a domain only matches after the last `@`, not as a suffix.
+    assert!(!verifier.verify("anyone@sub.lists.example.com").await?);
+    assert!(!verifier.verify("lists.example.com").await?);
+
+    // Tests that a shared verifier compares equal to its clones, and looks up with the inner one.
+    let shared = SharedAddressVerifier::new(verifier.clone());
+    assert_eq!(shared, shared.clone());
+    assert_ne!(shared, SharedAddressVerifier::new(verifier));
+    assert!(matches!(
+        check(&shared, "Smith@Example.com").await,
+        Verification::Exists
+    ));
+    assert!(matches!(
+        check(&shared, "green@example.com").await,
+        Verification::Unknown
+    ));
+
+    Ok(())
+}
+
+#[cfg(feature = "ldap")]
+#[test]
+fn test_ldap_filter() {
+    let verifier = LdapVerifier::new("ldap://127.0.0.1", "dc=example,dc=com");
+    assert_eq!(
+        verifier.filter_for("smith@example.com"),
+        "(mail=smith@example.com)"
+    );
+    // Tests that filter syntax in addresses is escaped.
+    assert_eq!(
+        verifier
+            .with_filter("(uid={address})")
+            .filter_for("*)(uid=*"),
+        r"(uid=\2a\29\28uid=\2a)"
+    );
+}
+
+#[cfg(feature = "sql")]
+#[tokio::test]
+async fn test_sql() -> Result<(), sqlx::Error> {
+    let path = std::env::temp_dir().join("smtp_gateway-directory-test.sqlite");
+    let _ = std::fs::remove_file(&path);
+
+    let verifier = SqlVerifier::connect(
+        &format!("sqlite://{}?mode=rwc", path.display()),
+        "SELECT 1 FROM mailboxes WHERE address = $1",
+    )
+    .await?;
+    sqlx::query("CREATE TABLE mailboxes (address TEXT NOT NULL)")
+        .execute(verifier.pool())
+        .await?;
+    sqlx::query("INSERT INTO mailboxes VALUES ('smith@example.com')")
+        .execute(verifier.pool())
+        .await?;
+
+    assert!(verifier.verify("smith@example.com").await?);
+    assert!(!verifier.verify("jones@example.com").await?);
+
+    verifier.pool().close().await;
+    let _ = std::fs::remove_file(&path);
+
+    Ok(())
+}
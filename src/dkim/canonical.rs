@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Splits messages into their header fields and body, and canonicalizes them for signing.
+//!
+//! [RFC 6376 section 3.4](https://www.rfc-editor.org/rfc/rfc6376.html#section-3.4).
+
+/// A header field of a message, as it appears in the data.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct RawField<'a> {
+    /// The name of the field, before the colon.
+    pub name: &'a [u8],
+    /// The whole field, including its name, folded lines, and the trailing line ending.
+    pub raw: &'a [u8],
+}
+
+impl<'a> RawField<'a> {
+    /// Check whether the field is named `name`, ignoring case.
+    pub const fn is(&self, name: &str) -> bool {
+        self.name.trim_ascii().eq_ignore_ascii_case(name.as_bytes())
+    }
+
+    /// Get the value of the field after the colon, including folded lines but without the
+    /// trailing line ending.
+    pub fn value(&self) -> &'a [u8] {
+        let value = &self.raw[self.name.len() + 1..];
+        value.strip_suffix(b"\r\n").unwrap_or(value)
+    }
+}
+
+/// The ways that a header field or body can be canonicalized.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Canonicalization {
+    /// Keep the data as it is, besides empty lines at the end of the body.
+    Simple,
+    /// Lowercase header names and compress whitespace, so that common changes in transit do not
+    /// break signatures.
+    Relaxed,
+}
+
+impl Canonicalization {
+    /// Parse the value of a `c=` tag into the canonicalizations of the header and body.
+    ///
+    /// Returns `None` if either is unknown.
+    pub fn parse_pair(value: &str) -> Option<(Self, Self)> {
+        let parse = |value: &str| match value {
+            "simple" => Some(Self::Simple),
+            "relaxed" => Some(Self::Relaxed),
+            _ => None,
+        };
+
+        match value.split_once('/') {
+            Some((header, body)) => Some((parse(header)?, parse(body)?)),
+            None => Some((parse(value)?, Self::Simple)),
+        }
+    }
+
+    /// Canonicalize a header field onto the end of `output`, including its line ending.
+    ///
+    /// [RFC 6376 section 3.4.1](https://www.rfc-editor.org/rfc/rfc6376.html#section-3.4.1).
+    /// [RFC 6376 section 3.4.2](https://www.rfc-editor.org/rfc/rfc6376.html#section-3.4.2).
+    pub fn header(self, field: &[u8], output: &mut Vec<u8>) {
+        match self {
+            Self::Simple => output.extend_from_slice(field),
+            Self::Relaxed => {
+                let Some(colon) = field.iter().position(|byte| *byte == b':') else {
+                    return;
+                };
+                let (name, value) = field.split_at(colon);
+
+                output.extend(name.trim_ascii().iter().map(u8::to_ascii_lowercase));
+                output.push(b':');
+                compress_whitespace(&value[1..], true, output);
+                output.extend_from_slice(b"\r\n");
+            }
+        }
+    }
+
+    /// Canonicalize the body of a message.
+    ///
+    /// [RFC 6376 section 3.4.3](https://www.rfc-editor.org/rfc/rfc6376.html#section-3.4.3).
+    /// [RFC 6376 section 3.4.4](https://www.rfc-editor.org/rfc/rfc6376.html#section-3.4.4).
+    pub fn body(self, body: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(body.len());
+
+        for line in body.split_inclusive(|byte| *byte == b'\n') {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            match self {
+                Self::Simple => output.extend_from_slice(line),
+                Self::Relaxed => compress_whitespace(line, false, &mut output),
+            }
+            output.extend_from_slice(b"\r\n");
+        }
+
+        // Empty lines at the end of the body are ignored.
+        while output.ends_with(b"\r\n\r\n") {
+            output.truncate(output.len() - 2);
+        }
+        if output == b"\r\n" {
+            output.clear();
+        }
+        if self == Self::Simple && output.is_empty() {
+            output.extend_from_slice(b"\r\n");
+        }
+
+        output
+    }
+}
+
+/// Split the data of a message into its header fields and its body.
+pub fn split(data: &[u8]) -> (Vec<RawField<'_>>, &[u8]) {
+    let mut fields: Vec<RawField<'_>> = Vec::new();
+    // Where the field in progress starts, and where its name ends.
+    let mut field: Option<(usize, usize)> = None;
+    let mut start = 0;
+
+    while start < data.len() {
+        let end = data[start..]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(data.len(), |index| start + index + 1);
+        let line = &data[start..end];
+
+        // A folded line continues the field before it.
+        if !matches!(line.first(), Some(b' ' | b'\t')) {
+            if let Some((field_start, name_end)) = field.take() {
+                fields.push(RawField {
+                    name: &data[field_start..name_end],
+                    raw: &data[field_start..start],
+                });
+            }
+            if line == b"\r\n" || line == b"\n" {
+                return (fields, &data[end..]);
+            }
+            field = line
+                .iter()
+                .position(|byte| *byte == b':')
+                .map(|colon| (start, start + colon));
+        }
+
+        start = end;
+    }
+
+    if let Some((field_start, name_end)) = field {
+        fields.push(RawField {
+            name: &data[field_start..name_end],
+            raw: &data[field_start..],
+        });
+    }
+    (fields, &[])
+}
+
+/// Replace every run of whitespace in `value` with a single space, removing folding and the
+/// whitespace at its end, onto the end of `output`.
+///
+/// The whitespace at the start is removed too if `trim_start`.
+fn compress_whitespace(value: &[u8], trim_start: bool, output: &mut Vec<u8>) {
+    let start = output.len();
+    let mut in_whitespace = false;
+
+    for byte in value {
+        match byte {
+            b'\r' | b'\n' => (),
+            b' ' | b'\t' => in_whitespace = true,
+            _ => {
+                if in_whitespace && (!trim_start || output.len() > start) {
+                    output.push(b' ');
+                }
+                in_whitespace = false;
+                output.push(*byte);
+            }
+        }
+    }
+}
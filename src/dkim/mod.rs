@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Verifying the DKIM signatures of messages, with the canonicalization and keys that ARC shares
+//! with it.
+//!
+//! See [`verify`].
+//!
+//! [RFC 6376](https://www.rfc-editor.org/rfc/rfc6376.html).
+
+pub mod canonical;
+pub mod signature;
+#[cfg(all(test, feature = "dmarc"))]
+mod test;
+
+use std::collections::HashMap;
+#[cfg(feature = "dmarc")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use self::canonical::{Canonicalization, RawField};
+use crate::resolver::Resolver;
+
+/// The name of the header field of a DKIM signature.
+#[cfg(feature = "dmarc")]
+const DKIM_SIGNATURE: &str = "DKIM-Signature";
+
+/// The most DKIM signatures of a message that are verified, to bound the lookups made for it.
+#[cfg(feature = "dmarc")]
+const MAX_SIGNATURES: usize = 8;
+
+/// Verify the `DKIM-Signature:` header fields of a message with the data `data`, looking up their
+/// keys with `resolver`, and get the domains (`d=` tags) of the valid ones, in lowercase.
+///
+/// Only RSA-SHA256 signatures that cover the `From:` header field and have not expired are
+/// verified, and only the first [`MAX_SIGNATURES`] of them. Lookups that fail are treated as
+/// invalid signatures.
+///
+/// [RFC 6376 section 6.1](https://www.rfc-editor.org/rfc/rfc6376.html#section-6.1).
+#[cfg(feature = "dmarc")]
+pub async fn verify(resolver: &impl Resolver, data: &[u8]) -> Vec<String> {
+    let (fields, body) = canonical::split(data);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+
+    let mut domains = Vec::new();
+    let signatures = fields.iter().filter(|field| field.is(DKIM_SIGNATURE));
+    for field in signatures.take(MAX_SIGNATURES) {
+        let Some(tags) = std::str::from_utf8(field.value())
+            .ok()
+            .and_then(signature::tags)
+        else {
+            continue;
+        };
+        let (Some("1"), Some(domain), Some(signed)) =
+            (tags.get("v").copied(), tags.get("d"), tags.get("h"))
+        else {
+            continue;
+        };
+        let is_from_signed = signed
+            .split(':')
+            .any(|name| name.trim().eq_ignore_ascii_case("from"));
+        let is_expired = tags
+            .get("x")
+            .is_some_and(|expiry| expiry.parse::<u64>().map_or(true, |expiry| expiry < now));
+        if !is_from_signed || is_expired {
+            continue;
+        }
+
+        if verify_field(resolver, &fields, body, *field, &tags).await {
+            domains.push(domain.to_ascii_lowercase());
+        }
+    }
+
+    domains
+}
+
+/// Verify the signature header field `field` with the tags `tags`, such as a `DKIM-Signature:` or
+/// `ARC-Message-Signature:`, over the header fields `fields` and the body `body` of a message.
+///
+/// [RFC 6376 section 6.1.3](https://www.rfc-editor.org/rfc/rfc6376.html#section-6.1.3).
+pub async fn verify_field(
+    resolver: &impl Resolver,
+    fields: &[RawField<'_>],
+    body: &[u8],
+    field: RawField<'_>,
+    tags: &HashMap<&str, &str>,
+) -> bool {
+    let (Some("rsa-sha256"), Some(domain), Some(selector), Some(signed), Some(body_hash)) = (
+        tags.get("a").copied(),
+        tags.get("d"),
+        tags.get("s"),
+        tags.get("h"),
+        tags.get("bh").and_then(|hash| signature::decode(hash)),
+    ) else {
+        return false;
+    };
+    let Some((header_canonicalization, body_canonicalization)) =
+        Canonicalization::parse_pair(tags.get("c").copied().unwrap_or("simple/simple"))
+    else {
+        return false;
+    };
+
+    let mut canonical_body = body_canonicalization.body(body);
+    if let Some(length) = tags.get("l") {
+        // A body shorter than the length that was signed fails, rather than being hashed whole.
+        let Some(length) = length
+            .parse::<usize>()
+            .ok()
+            .filter(|length| *length <= canonical_body.len())
+        else {
+            return false;
+        };
+        canonical_body.truncate(length);
+    }
+    if Sha256::digest(&canonical_body)[..] != body_hash[..] {
+        return false;
+    }
+
+    let signed: Vec<_> = signed
+        .split(':')
+        .map(|name| name.trim().to_owned())
+        .collect();
+    let mut input = header_hash_input(fields, &signed, header_canonicalization);
+    let mut unsigned = Vec::new();
+    header_canonicalization.header(&signature::without_signature(field.raw), &mut unsigned);
+    input.extend_from_slice(unsigned.strip_suffix(b"\r\n").unwrap_or(&unsigned));
+
+    verify_signature(resolver, tags, selector, domain, &input).await
+}
+
+/// Verify the `b=` tag of a signature in `tags` over `input`, with the key of `selector` and
+/// `domain`.
+pub async fn verify_signature(
+    resolver: &impl Resolver,
+    tags: &HashMap<&str, &str>,
+    selector: &str,
+    domain: &str,
+    input: &[u8],
+) -> bool {
+    let Some(signature) = tags
+        .get("b")
+        .and_then(|signature| signature::decode(signature))
+    else {
+        return false;
+    };
+    let Ok(key) = signature::public_key(resolver, selector, domain).await else {
+        return false;
+    };
+
+    signature::verify(&key, input, &signature)
+}
+
+/// Canonicalize the header fields named by `signed` in order, each from the last of its name
+/// that was not already used, as a signature covers them.
+///
+/// [RFC 6376 section 5.4.2](https://www.rfc-editor.org/rfc/rfc6376.html#section-5.4.2).
+pub fn header_hash_input(
+    fields: &[RawField<'_>],
+    signed: &[String],
+    canonicalization: Canonicalization,
+) -> Vec<u8> {
+    let mut used = vec![false; fields.len()];
+    let mut input = Vec::new();
+
+    for name in signed {
+        let next = fields
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(index, field)| !used[*index] && field.is(name));
+        if let Some((index, field)) = next {
+            used[index] = true;
+            canonicalization.header(field.raw, &mut input);
+        }
+    }
+
+    input
+}
@@ -0,0 +1,199 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Parses the tag lists of DKIM and ARC header fields, and verifies them with the keys published
+//! for DKIM.
+//!
+//! [RFC 6376 section 3.2](https://www.rfc-editor.org/rfc/rfc6376.html#section-3.2).
+
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::signature::{UnparsedPublicKey, RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY};
+
+use crate::resolver::Resolver;
+
+/// Parse a tag list, such as `i=1; a=rsa-sha256; d=example.com`, by the names of its tags.
+///
+/// Returns `None` if a tag is given twice, or if a tag has no `=`.
+pub fn tags(value: &str) -> Option<HashMap<&str, &str>> {
+    let mut tags = HashMap::new();
+
+    for tag in value.split(';') {
+        if tag.trim().is_empty() {
+            continue;
+        }
+        let (name, value) = tag.split_once('=')?;
+        if tags.insert(name.trim(), value.trim()).is_some() {
+            return None;
+        }
+    }
+
+    Some(tags)
+}
+
+/// Decode a Base64 tag value, ignoring the whitespace that it may be folded with.
+pub fn decode(value: &str) -> Option<Vec<u8>> {
+    let value: String = value
+        .chars()
+        .filter(|char| !char.is_ascii_whitespace())
+        .collect();
+
+    STANDARD.decode(value).ok()
+}
+
+/// Encode a tag value in Base64.
+#[cfg(any(feature = "arc", test))]
+pub fn encode(value: &[u8]) -> String {
+    STANDARD.encode(value)
+}
+
+/// Remove the value of the `b=` tag from a header field, which is how the field is signed.
+///
+/// [RFC 6376 section 3.7](https://www.rfc-editor.org/rfc/rfc6376.html#section-3.7).
+pub fn without_signature(field: &[u8]) -> Vec<u8> {
+    let Some(colon) = field.iter().position(|byte| *byte == b':') else {
+        return field.to_vec();
+    };
+    let mut output = field[..=colon].to_vec();
+
+    let mut first = true;
+    for tag in field[colon + 1..].split(|byte| *byte == b';') {
+        if !first {
+            output.push(b';');
+        }
+        first = false;
+
+        let equals = tag.iter().position(|byte| *byte == b'=');
+        match equals {
+            Some(equals) if tag[..equals].trim_ascii() == b"b" => {
+                output.extend_from_slice(&tag[..=equals]);
+                // A line ending after the value is kept, as it ends the field.
+                if tag.ends_with(b"\r\n") {
+                    output.extend_from_slice(b"\r\n");
+                }
+            }
+            _ => output.extend_from_slice(tag),
+        }
+    }
+
+    output
+}
+
+/// Why the public key of a signature could not be found.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum KeyError {
+    /// The lookup failed, so it can be tried again later.
+    Temporary,
+    /// There is no valid key, or it was revoked.
+    Permanent,
+}
+
+/// Look up the RSA public key that `domain` publishes under `selector`, as a DER-encoded
+/// `RSAPublicKey`.
+///
+/// # Errors
+///
+/// - [`KeyError`] if there is no usable key.
+///
+/// [RFC 6376 section 3.6.2](https://www.rfc-editor.org/rfc/rfc6376.html#section-3.6.2).
+pub async fn public_key(
+    resolver: &impl Resolver,
+    selector: &str,
+    domain: &str,
+) -> Result<Vec<u8>, KeyError> {
+    let records = resolver
+        .lookup_txt(&format!("{selector}._domainkey.{domain}"))
+        .await
+        .map_err(|_| KeyError::Temporary)?;
+
+    for record in &records {
+        let Some(tags) = tags(record) else {
+            continue;
+        };
+        if tags.get("v").is_some_and(|version| *version != "DKIM1")
+            || tags.get("k").is_some_and(|kind| *kind != "rsa")
+        {
+            continue;
+        }
+        // An empty key is a revoked key.
+        let key = tags
+            .get("p")
+            .and_then(|key| decode(key))
+            .filter(|key| !key.is_empty())
+            .ok_or(KeyError::Permanent)?;
+
+        return Ok(subject_public_key(&key).unwrap_or(&key).to_vec());
+    }
+
+    Err(KeyError::Permanent)
+}
+
+/// Check whether `signature` is a valid RSA-SHA256 signature of `data` by `key`, a DER-encoded
+/// `RSAPublicKey`.
+///
+/// Keys of 1024 bits are still accepted, as [RFC 8301 section
+/// 3.2](https://www.rfc-editor.org/rfc/rfc8301.html#section-3.2) asks of verifiers.
+pub fn verify(key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    UnparsedPublicKey::new(&RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY, key)
+        .verify(data, signature)
+        .is_ok()
+}
+
+/// Get the `RSAPublicKey` out of a DER-encoded `SubjectPublicKeyInfo`, which is how keys are
+/// usually published, or `None` if `der` is not one.
+///
+/// [RFC 5280 section 4.1](https://www.rfc-editor.org/rfc/rfc5280.html#section-4.1).
+fn subject_public_key(der: &[u8]) -> Option<&[u8]> {
+    /// Split a DER element with the tag `expected` off of the start of `der`, returning its
+    /// contents and what follows it.
+    fn element(der: &[u8], expected: u8) -> Option<(&[u8], &[u8])> {
+        let (&tag, rest) = der.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        if tag != expected {
+            return None;
+        }
+
+        let (length, rest) = if first < 0x80 {
+            (usize::from(first), rest)
+        } else {
+            let octets = usize::from(first & 0x7f);
+            if octets > size_of::<usize>() || rest.len() < octets {
+                return None;
+            }
+            let (length, rest) = rest.split_at(octets);
+            let length = length
+                .iter()
+                .fold(0, |length, octet| (length << 8) | usize::from(*octet));
+            (length, rest)
+        };
+
+        (rest.len() >= length).then(|| rest.split_at(length))
+    }
+
+    /// `SEQUENCE`.
+    const SEQUENCE: u8 = 0x30;
+    /// `BIT STRING`.
+    const BIT_STRING: u8 = 0x03;
+
+    let (info, _) = element(der, SEQUENCE)?;
+    let (_, rest) = element(info, SEQUENCE)?;
+    let (key, _) = element(rest, BIT_STRING)?;
+
+    // The first octet of a bit string is the number of unused bits, which is zero for keys.
+    key.strip_prefix(&[0])
+}
@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::io;
+
+use ring::{rand::SystemRandom, signature::RsaKeyPair};
+
+use super::*;
+
+/// The private key that test messages are signed with.
+const KEY: &str = include_str!("../test/certs/arc.key");
+
+/// The public key of [`KEY`], as published in DNS.
+const PUBLIC_KEY: &str = include_str!("../test/certs/arc.pub");
+
+/// The message that is signed, from `smith@example.com`.
+const MESSAGE: &str = "From: <smith@example.com>\r\nSubject: test\r\n\r\nbody\r\n";
+
+/// Publishes [`PUBLIC_KEY`] under `dkim._domainkey.example.com`.
+struct Stub;
+
+impl Resolver for Stub {
+    async fn lookup_ip(&self, _: &str) -> io::Result<Vec<std::net::IpAddr>> {
+        Ok(Vec::new())
+    }
+
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+        Ok(match name {
+            "dkim._domainkey.example.com" => {
+                vec![format!("v=DKIM1; k=rsa; p={}", PUBLIC_KEY.trim())]
+            }
+            _ => Vec::new(),
+        })
+    }
+}
+
+/// Sign [`MESSAGE`] with [`KEY`], with a `DKIM-Signature:` header that has the tags `tags` before
+/// its body hash and signature, returning the signed message.
+fn signed(tags: &str) -> String {
+    let pem: String = KEY
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let key = signature::decode(&pem)
+        .and_then(|der| RsaKeyPair::from_pkcs8(&der).ok())
+        .expect("the test key is valid");
+
+    let (fields, body) = canonical::split(MESSAGE.as_bytes());
+    let body_hash = Sha256::digest(Canonicalization::Relaxed.body(body));
+    let header = format!(
+        "{DKIM_SIGNATURE}: {tags}; bh={}; b=",
+        signature::encode(&body_hash)
+    );
+    let signed: Vec<_> = signature::tags(&header[DKIM_SIGNATURE.len() + 1..])
+        .and_then(|tags| tags.get("h").copied())
+        .unwrap_or_default()
+        .split(':')
+        .map(str::to_owned)
+        .collect();
+    let mut input = header_hash_input(&fields, &signed, Canonicalization::Relaxed);
+    let mut unsigned = Vec::new();
+    Canonicalization::Relaxed.header(header.as_bytes(), &mut unsigned);
+    input.extend_from_slice(unsigned.strip_suffix(b"\r\n").unwrap_or(&unsigned));
+
+    let mut signature = vec![0; key.public().modulus_len()];
+    key.sign(
+        &ring::signature::RSA_PKCS1_SHA256,
+        &SystemRandom::new(),
+        &input,
+        &mut signature,
+    )
+    .expect("the test key signs");
+
+    format!("{header}{}\r\n{MESSAGE}", signature::encode(&signature))
+}
+
+#[tokio::test]
+async fn test_verify() {
+    const TAGS: &str =
+        "v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com; s=dkim; h=from:subject";
+
+    assert_eq!(
+        verify(&Stub, signed(TAGS).as_bytes()).await,
+        ["example.com"]
+    );
+    assert!(verify(&Stub, MESSAGE.as_bytes()).await.is_empty());
+
+    // Tests that modifying the signed message breaks the signature.
+    for modified in [
+        signed(TAGS).replace("body", "modified"),
+        signed(TAGS).replace("Subject: test", "Subject: modified"),
+    ] {
+        assert!(verify(&Stub, modified.as_bytes()).await.is_empty());
+    }
+
+    // Tests that only current signatures of the `From:` header field are accepted.
+    for tags in [
+        "a=rsa-sha256; c=relaxed/relaxed; d=example.com; s=dkim; h=from:subject",
+        "v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com; s=dkim; h=subject",
+        "v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com; s=dkim; h=from; x=1",
+        "v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com; s=unknown; h=from",
+    ] {
+        assert!(
+            verify(&Stub, signed(tags).as_bytes()).await.is_empty(),
+            "{tags}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_verify_length() {
+    const TAGS: &str =
+        "v=1; a=rsa-sha256; c=relaxed/relaxed; d=example.com; s=dkim; h=from:subject";
+
+    let (_, body) = canonical::split(MESSAGE.as_bytes());
+    let length = Canonicalization::Relaxed.body(body).len();
+    assert_eq!(
+        verify(&Stub, signed(&format!("{TAGS}; l={length}")).as_bytes()).await,
+        ["example.com"]
+    );
+
+    // Tests that a length past the end of the body fails.
+    let length = length + 1;
+    assert!(
+        verify(&Stub, signed(&format!("{TAGS}; l={length}")).as_bytes())
+            .await
+            .is_empty()
+    );
+}
@@ -0,0 +1,549 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Applying the policies that domains publish for mail that claims to be from them, with
+//! Domain-based Message Authentication, Reporting, and Conformance (DMARC).
+//!
+//! See [`evaluate`] and [`DmarcPolicy`].
+//!
+//! [RFC 7489](https://www.rfc-editor.org/rfc/rfc7489.html).
+
+mod record;
+#[cfg(test)]
+mod test;
+
+use std::{
+    fmt::Display,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use self::record::Record;
+use crate::{
+    dkim,
+    resolver::Resolver,
+    spf::{SpfResult, SpfVerdict},
+    Message, ServerConfig,
+};
+
+/// What the server does with messages that fail DMARC, by the disposition that the domain in
+/// their `From:` header asks for.
+///
+/// Every evaluated message is given an `Authentication-Results:` header and keeps its
+/// [`DmarcVerdict`] in [`Message::dmarc`].
+///
+/// Messages pass DMARC with a valid `DKIM-Signature:` header, or an SPF pass when
+/// [`crate::ServerConfig::spf_policy`] is set, for a domain aligned with the domain in their
+/// `From:` header.
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::dmarc::{DmarcAction, DmarcPolicy};
+/// #
+/// let policy = DmarcPolicy::default().with_reject(DmarcAction::Tag);
+///
+/// assert_eq!(policy.quarantine(), DmarcAction::Tag);
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct DmarcPolicy {
+    /// What to do with messages from domains that ask for them to be quarantined.
+    quarantine: DmarcAction,
+    /// What to do with messages from domains that ask for them to be rejected.
+    reject: DmarcAction,
+}
+
+impl DmarcPolicy {
+    /// Create a new [`Self`] that only annotates messages, whatever their domains ask for.
+    #[must_use]
+    pub const fn annotate_only() -> Self {
+        Self {
+            quarantine: DmarcAction::Annotate,
+            reject: DmarcAction::Annotate,
+        }
+    }
+
+    /// Get what is done with failing messages from domains that publish `p=quarantine`.
+    /// Defaults to [`DmarcAction::Tag`].
+    #[must_use]
+    pub const fn quarantine(&self) -> DmarcAction {
+        self.quarantine
+    }
+
+    /// Get what is done with failing messages from domains that publish `p=reject`. Defaults to
+    /// [`DmarcAction::Reject`].
+    #[must_use]
+    pub const fn reject(&self) -> DmarcAction {
+        self.reject
+    }
+
+    /// Get the action for messages that their domain asks to be handled with `disposition`.
+    #[must_use]
+    pub const fn action(&self, disposition: DmarcDisposition) -> DmarcAction {
+        match disposition {
+            DmarcDisposition::None => DmarcAction::Annotate,
+            DmarcDisposition::Quarantine => self.quarantine,
+            DmarcDisposition::Reject => self.reject,
+        }
+    }
+
+    /// Set what is done with failing messages from domains that publish `p=quarantine`. See
+    /// [`Self::quarantine`].
+    #[must_use]
+    pub const fn with_quarantine(mut self, action: DmarcAction) -> Self {
+        self.quarantine = action;
+        self
+    }
+
+    /// Set what is done with failing messages from domains that publish `p=reject`. See
+    /// [`Self::reject`].
+    #[must_use]
+    pub const fn with_reject(mut self, action: DmarcAction) -> Self {
+        self.reject = action;
+        self
+    }
+}
+
+impl Default for DmarcPolicy {
+    /// Honor what domains ask for, tagging messages to be quarantined and rejecting messages to
+    /// be rejected.
+    fn default() -> Self {
+        Self {
+            quarantine: DmarcAction::Tag,
+            reject: DmarcAction::Reject,
+        }
+    }
+}
+
+/// What is done with a message that fails DMARC, see [`DmarcPolicy`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum DmarcAction {
+    /// Accept the message, noting the result in its `Authentication-Results:` header.
+    Annotate,
+    /// Accept the message and mark it to be quarantined, with a disposition of
+    /// [`DmarcDisposition::Quarantine`] in [`DmarcVerdict::disposition`] and its
+    /// `Authentication-Results:` header, for the consumer to act on.
+    Tag,
+    /// Reject the message with `550 5.7.1`.
+    Reject,
+}
+
+/// What a domain asks for to be done with messages that fail DMARC, and what was done with them.
+///
+/// [RFC 7489 section 6.3](https://www.rfc-editor.org/rfc/rfc7489.html#section-6.3).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum DmarcDisposition {
+    /// Deliver the message as usual.
+    None,
+    /// Treat the message as suspicious, such as by delivering it to a spam folder.
+    Quarantine,
+    /// Reject the message.
+    Reject,
+}
+
+impl DmarcDisposition {
+    /// Parse the value of the `p` or `sp` tag of a DMARC record.
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Self::None),
+            "quarantine" => Some(Self::Quarantine),
+            "reject" => Some(Self::Reject),
+            _ => None,
+        }
+    }
+
+    /// Get the disposition that is applied instead of `self` to messages that are not sampled
+    /// by the `pct` tag of a DMARC record.
+    ///
+    /// [RFC 7489 section 6.6.4](https://www.rfc-editor.org/rfc/rfc7489.html#section-6.6.4).
+    const fn unsampled(self) -> Self {
+        match self {
+            Self::None | Self::Quarantine => Self::None,
+            Self::Reject => Self::Quarantine,
+        }
+    }
+}
+
+impl Display for DmarcDisposition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Quarantine => "quarantine",
+            Self::Reject => "reject",
+        })
+    }
+}
+
+/// How closely an authenticated domain must match the domain in the `From:` header of a message.
+///
+/// [RFC 7489 section 3.1](https://www.rfc-editor.org/rfc/rfc7489.html#section-3.1).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Alignment {
+    /// The domains must have the same organizational domain.
+    Relaxed,
+    /// The domains must be the same.
+    Strict,
+}
+
+impl Alignment {
+    /// Parse the value of the `adkim` or `aspf` tag of a DMARC record.
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "r" => Some(Self::Relaxed),
+            "s" => Some(Self::Strict),
+            _ => None,
+        }
+    }
+
+    /// Check whether `authenticated` is aligned with the domain `from`, whose organizational
+    /// domain is `organization`, looking up the organizational domain of `authenticated` with
+    /// `resolver` if needed.
+    ///
+    /// # Errors
+    ///
+    /// - [`DmarcResult::TempError`] if a lookup failed.
+    async fn aligns(
+        self,
+        resolver: &impl Resolver,
+        authenticated: &str,
+        from: &str,
+        organization: &str,
+    ) -> Result<bool, DmarcResult> {
+        let authenticated = authenticated.strip_suffix('.').unwrap_or(authenticated);
+        let authenticated = authenticated.to_ascii_lowercase();
+        if authenticated.eq_ignore_ascii_case(from) {
+            return Ok(true);
+        }
+
+        match self {
+            Self::Relaxed => {
+                // Only domains under `organization` can have it as their organizational domain.
+                let organization = organization.to_ascii_lowercase();
+                let is_under = authenticated
+                    .strip_suffix(organization.as_str())
+                    .is_some_and(|subdomain| subdomain.is_empty() || subdomain.ends_with('.'));
+                if !is_under {
+                    return Ok(false);
+                }
+
+                let records = walk(resolver, &authenticated).await?;
+                Ok(organizational_domain(&authenticated, &records) == organization)
+            }
+            Self::Strict => Ok(false),
+        }
+    }
+}
+
+/// The result of evaluating DMARC for a message.
+///
+/// [RFC 7489 section 11.2](https://www.rfc-editor.org/rfc/rfc7489.html#section-11.2).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum DmarcResult {
+    /// The domain in the `From:` header does not publish a DMARC record.
+    None,
+    /// SPF or DKIM passed for a domain aligned with the domain in the `From:` header.
+    Pass,
+    /// Neither SPF nor DKIM passed for an aligned domain.
+    Fail,
+    /// A lookup failed, so the evaluation can be tried again later.
+    TempError,
+    /// The message does not have exactly one domain in its `From:` header.
+    PermError,
+}
+
+impl Display for DmarcResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::TempError => "temperror",
+            Self::PermError => "permerror",
+        })
+    }
+}
+
+/// The result of evaluating DMARC for a message, and what was done with it.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmarcVerdict {
+    /// The result of the evaluation.
+    result: DmarcResult,
+    /// The domain in the `From:` header.
+    domain: Option<String>,
+    /// What the domain publishes to be done with failing messages, if it publishes a record.
+    policy: Option<DmarcDisposition>,
+    /// What is done with the message.
+    disposition: DmarcDisposition,
+}
+
+impl DmarcVerdict {
+    /// Get the result of the evaluation.
+    #[must_use]
+    pub const fn result(&self) -> DmarcResult {
+        self.result
+    }
+
+    /// Get the domain in the `From:` header of the message, or `None` if it does not have exactly
+    /// one.
+    #[must_use]
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    /// Get what the domain publishes to be done with messages that fail DMARC, from the `p` tag
+    /// of its record (or the `sp` tag for subdomains), or `None` if it does not publish a record.
+    #[must_use]
+    pub const fn policy(&self) -> Option<DmarcDisposition> {
+        self.policy
+    }
+
+    /// Get what is done with the message.
+    ///
+    /// This is [`DmarcDisposition::None`] unless the message failed, and otherwise depends on
+    /// [`Self::policy`], the percentage of messages that the domain asks for it to be applied
+    /// to, and the [`DmarcPolicy`] that the verdict was given under.
+    #[must_use]
+    pub const fn disposition(&self) -> DmarcDisposition {
+        self.disposition
+    }
+}
+
+/// Evaluate DMARC for a message from the domain `from` (in its `From:` header), making DNS lookups
+/// with `resolver`.
+///
+/// `spf` is the result of checking SPF for the message, see
+/// [`crate::message::envelope::Envelope::spf`], and `dkim_domains` are the domains (`d=` tags) of
+/// the DKIM signatures of the message that were verified. The disposition of the returned verdict
+/// is what the domain asks for, without any [`DmarcPolicy`].
+///
+/// The record that applies to `from` and its organizational domain are found with the DNS tree
+/// walk of [DMARCbis section 4.10](https://datatracker.ietf.org/doc/html/draft-ietf-dmarc-dmarcbis#section-4.10),
+/// which needs no copy of the public suffix list, so that domains under public suffixes like
+/// `co.uk` are never aligned with each other.
+pub async fn evaluate(
+    resolver: &impl Resolver,
+    from: &str,
+    spf: Option<&SpfVerdict>,
+    dkim_domains: &[&str],
+) -> DmarcVerdict {
+    let mut verdict = DmarcVerdict {
+        result: DmarcResult::None,
+        domain: Some(from.to_owned()),
+        policy: None,
+        disposition: DmarcDisposition::None,
+    };
+    let from = from.strip_suffix('.').unwrap_or(from);
+
+    // The closest record to the domain applies to it, with the `sp` tag if it is of a parent.
+    //
+    // <https://www.rfc-editor.org/rfc/rfc7489.html#section-6.6.3>
+    let records = match walk(resolver, from).await {
+        Ok(records) => records,
+        Err(result) => return DmarcVerdict { result, ..verdict },
+    };
+    let Some((name, record)) = records.first() else {
+        return verdict;
+    };
+    let policy = if name.eq_ignore_ascii_case(from) {
+        record.policy
+    } else {
+        record.subdomain_policy.unwrap_or(record.policy)
+    };
+    verdict.policy = Some(policy);
+
+    let organization = organizational_domain(from, &records);
+    let spf_aligned = match spf {
+        Some(spf) if spf.result() == SpfResult::Pass => {
+            record
+                .spf_alignment
+                .aligns(resolver, spf.domain(), from, organization)
+                .await
+        }
+        _ => Ok(false),
+    };
+    let mut aligned = spf_aligned;
+    for domain in dkim_domains {
+        if aligned != Ok(false) {
+            break;
+        }
+        aligned = record
+            .dkim_alignment
+            .aligns(resolver, domain, from, organization)
+            .await;
+    }
+
+    match aligned {
+        Ok(true) => {
+            verdict.result = DmarcResult::Pass;
+            return verdict;
+        }
+        Ok(false) => (),
+        Err(result) => return DmarcVerdict { result, ..verdict },
+    }
+
+    verdict.result = DmarcResult::Fail;
+    verdict.disposition = if is_sampled(record.percentage) {
+        policy
+    } else {
+        policy.unsampled()
+    };
+
+    verdict
+}
+
+/// Evaluate DMARC for `message` if `config` asks for it, applying [`ServerConfig::dmarc_policy`]
+/// to the disposition of the verdict.
+///
+/// Returns `None` if DMARC is not evaluated.
+pub(crate) async fn verify(config: &ServerConfig, message: &Message) -> Option<DmarcVerdict> {
+    let policy = config.dmarc_policy()?;
+
+    // Messages must have exactly one author domain.
+    //
+    // <https://www.rfc-editor.org/rfc/rfc7489.html#section-6.6.1>
+    let mut domains: Vec<_> = message
+        .headers()
+        .from()
+        .iter()
+        .filter_map(|mailbox| mailbox.address().rsplit_once('@'))
+        .map(|(_, domain)| domain.to_ascii_lowercase())
+        .collect();
+    domains.sort_unstable();
+    domains.dedup();
+    let [domain] = domains.as_slice() else {
+        return Some(DmarcVerdict {
+            result: DmarcResult::PermError,
+            domain: None,
+            policy: None,
+            disposition: DmarcDisposition::None,
+        });
+    };
+
+    let dkim_domains = dkim::verify(config.resolver(), message.data()).await;
+    let dkim_domains: Vec<_> = dkim_domains.iter().map(String::as_str).collect();
+    let mut verdict = evaluate(
+        config.resolver(),
+        domain,
+        message.envelope().spf(),
+        &dkim_domains,
+    )
+    .await;
+    verdict.disposition = match policy.action(verdict.disposition) {
+        DmarcAction::Annotate => DmarcDisposition::None,
+        DmarcAction::Tag => DmarcDisposition::Quarantine,
+        DmarcAction::Reject => DmarcDisposition::Reject,
+    };
+
+    Some(verdict)
+}
+
+/// Look up the DMARC record of `domain`, or `None` if it has none or more than one.
+///
+/// # Errors
+///
+/// - [`DmarcResult::TempError`] if the lookup failed.
+async fn lookup(resolver: &impl Resolver, domain: &str) -> Result<Option<Record>, DmarcResult> {
+    let records = resolver
+        .lookup_txt(&format!("_dmarc.{domain}"))
+        .await
+        .map_err(|_| DmarcResult::TempError)?;
+
+    let mut records = records.iter().filter(|text| Record::is_dmarc(text));
+    Ok(match (records.next(), records.next()) {
+        (Some(record), None) => Record::parse(record),
+        _ => None,
+    })
+}
+
+/// The most DNS lookups made by a tree walk.
+///
+/// [DMARCbis section 4.10](https://datatracker.ietf.org/doc/html/draft-ietf-dmarc-dmarcbis#section-4.10).
+const MAX_WALK: usize = 8;
+
+/// Look up the DMARC records of `domain` and the domains above it, closest first, skipping all but
+/// the [`MAX_WALK`] closest to the root after `domain` itself, to bound the number of lookups.
+///
+/// # Errors
+///
+/// - [`DmarcResult::TempError`] if a lookup failed.
+async fn walk<'a>(
+    resolver: &impl Resolver,
+    domain: &'a str,
+) -> Result<Vec<(&'a str, Record)>, DmarcResult> {
+    let parents = domain
+        .match_indices('.')
+        .map(|(index, _)| &domain[index + 1..]);
+    let skipped = parents.clone().count().saturating_sub(MAX_WALK - 1);
+
+    let mut records = Vec::new();
+    for name in std::iter::once(domain).chain(parents.skip(skipped)) {
+        if let Some(record) = lookup(resolver, name).await? {
+            records.push((name, record));
+        }
+    }
+
+    Ok(records)
+}
+
+/// Get the organizational domain of `domain`, from the `records` found on a [`walk`] from it.
+///
+/// This is the closest domain to `domain` whose record says it is not a public suffix, else the
+/// domain below the closest one that says it is, else the furthest domain with a record, else
+/// `domain` itself.
+///
+/// [DMARCbis section 4.10.2](https://datatracker.ietf.org/doc/html/draft-ietf-dmarc-dmarcbis#section-4.10.2).
+fn organizational_domain<'a>(domain: &'a str, records: &[(&'a str, Record)]) -> &'a str {
+    let mut organization = domain;
+
+    for (name, record) in records {
+        match record.public_suffix {
+            Some(false) => return name,
+            Some(true) => {
+                let Some(subdomain) = domain.strip_suffix(name).and_then(|s| s.strip_suffix('.'))
+                else {
+                    return domain;
+                };
+                let label = subdomain.rsplit('.').next().unwrap_or(subdomain);
+                return &domain[subdomain.len() - label.len()..];
+            }
+            None => organization = name,
+        }
+    }
+
+    organization
+}
+
+/// Decide whether a failing message is sampled by a `pct` tag of `percentage`, so that the policy
+/// of its domain is applied to it.
+fn is_sampled(percentage: u8) -> bool {
+    if percentage >= 100 {
+        return true;
+    }
+
+    let sample = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.subsec_nanos());
+    sample % 100 < u32::from(percentage)
+}
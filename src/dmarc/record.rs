@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Parses DMARC records into their tags.
+//!
+//! See [`Record`].
+
+use super::{Alignment, DmarcDisposition};
+
+/// A DMARC record, as published in a `TXT` record of `_dmarc.` a domain that starts with
+/// `v=DMARC1`.
+///
+/// [RFC 7489 section 6.3](https://www.rfc-editor.org/rfc/rfc7489.html#section-6.3).
+#[derive(PartialEq, Eq, Debug)]
+pub struct Record {
+    /// The policy for the domain itself, from the `p` tag.
+    pub policy: DmarcDisposition,
+    /// The policy for the subdomains of the domain, from the `sp` tag, or `None` to use
+    /// [`Self::policy`].
+    pub subdomain_policy: Option<DmarcDisposition>,
+    /// How closely the domains of DKIM signatures must match, from the `adkim` tag.
+    pub dkim_alignment: Alignment,
+    /// How closely the domain checked by SPF must match, from the `aspf` tag.
+    pub spf_alignment: Alignment,
+    /// The percentage of failing messages that the policy is applied to, from the `pct` tag.
+    pub percentage: u8,
+    /// Whether the domain is a public suffix, such as `co.uk`, from the `psd` tag, or `None` if
+    /// the record does not say.
+    pub public_suffix: Option<bool>,
+}
+
+impl Record {
+    /// Check whether the text of a `TXT` record is a DMARC record.
+    ///
+    /// [RFC 7489 section 6.6.3](https://www.rfc-editor.org/rfc/rfc7489.html#section-6.6.3).
+    pub fn is_dmarc(text: &str) -> bool {
+        text.split(';').next().is_some_and(|version| {
+            version
+                .split_once('=')
+                .is_some_and(|(name, value)| name.trim() == "v" && value.trim() == "DMARC1")
+        })
+    }
+
+    /// Parse a DMARC record, which must start with `v=DMARC1`, see [`Self::is_dmarc`].
+    ///
+    /// Unknown tags are ignored, as are the reporting tags, which this server does not act on.
+    ///
+    /// Returns `None` if the record has no valid policy, and so must be ignored. A record that
+    /// asks for reports but has an invalid policy is read as `p=none`, as it still asks for
+    /// reports.
+    ///
+    /// [RFC 7489 section 6.6.3](https://www.rfc-editor.org/rfc/rfc7489.html#section-6.6.3).
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut policy = None;
+        let mut subdomain_policy = Ok(None);
+        let mut has_reports = false;
+        let mut record = Self {
+            policy: DmarcDisposition::None,
+            subdomain_policy: None,
+            dkim_alignment: Alignment::Relaxed,
+            spf_alignment: Alignment::Relaxed,
+            percentage: 100,
+            public_suffix: None,
+        };
+
+        for tag in text.split(';').skip(1) {
+            let Some((name, value)) = tag.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match name.trim() {
+                "p" => policy = DmarcDisposition::parse(value),
+                "sp" => subdomain_policy = DmarcDisposition::parse(value).map(Some).ok_or(()),
+                "adkim" => record.dkim_alignment = Alignment::parse(value)?,
+                "aspf" => record.spf_alignment = Alignment::parse(value)?,
+                "pct" => {
+                    record.percentage =
+                        value.parse().ok().filter(|percentage| *percentage <= 100)?;
+                }
+                "psd" => {
+                    record.public_suffix = match value {
+                        "y" => Some(true),
+                        "n" => Some(false),
+                        _ => None,
+                    };
+                }
+                "rua" => has_reports = value.split(',').any(|uri| uri.trim().contains(':')),
+                _ => (),
+            }
+        }
+
+        match (policy, subdomain_policy) {
+            (Some(policy), Ok(subdomain_policy)) => {
+                record.policy = policy;
+                record.subdomain_policy = subdomain_policy;
+            }
+            _ if has_reports => (),
+            _ => return None,
+        }
+
+        Some(record)
+    }
+}
@@ -0,0 +1,282 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::{collections::HashMap, io};
+
+use super::*;
+use crate::spf::SpfIdentity;
+
+/// Resolves `TXT` records from a table, failing lookups of names under `fail.example.com`.
+struct Stub(HashMap<&'static str, &'static str>);
+
+impl Resolver for Stub {
+    async fn lookup_ip(&self, _: &str) -> io::Result<Vec<std::net::IpAddr>> {
+        Ok(Vec::new())
+    }
+
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+        if name.ends_with("fail.example.com") {
+            return Err(io::Error::other("lookup failed"));
+        }
+        Ok(self
+            .0
+            .get(name)
+            .map(|record| vec![(*record).to_owned()])
+            .unwrap_or_default())
+    }
+}
+
+#[test]
+fn test_record_parsing() {
+    assert!(Record::is_dmarc("v=DMARC1; p=none"));
+    assert!(Record::is_dmarc("v = DMARC1"));
+    assert!(!Record::is_dmarc("v=spf1 -all"));
+    assert!(!Record::is_dmarc("p=reject; v=DMARC1"));
+
+    assert_eq!(
+        Record::parse("v=DMARC1; p=quarantine; sp=reject; adkim=s; pct=50; foo=bar"),
+        Some(Record {
+            policy: DmarcDisposition::Quarantine,
+            subdomain_policy: Some(DmarcDisposition::Reject),
+            dkim_alignment: Alignment::Strict,
+            spf_alignment: Alignment::Relaxed,
+            percentage: 50,
+            public_suffix: None,
+        })
+    );
+
+    // Tests that a record that asks for reports is read as `p=none` without a valid policy.
+    assert_eq!(
+        Record::parse("v=DMARC1; p=none; psd=y").and_then(|record| record.public_suffix),
+        Some(true)
+    );
+    assert_eq!(
+        Record::parse("v=DMARC1; p=bogus; rua=mailto:dmarc@example.com")
+            .map(|record| record.policy),
+        Some(DmarcDisposition::None)
+    );
+
+    for invalid in [
+        "v=DMARC1",
+        "v=DMARC1; p=bogus",
+        "v=DMARC1; p=reject; sp=bogus",
+        "v=DMARC1; p=reject; aspf=x",
+        "v=DMARC1; p=reject; pct=101",
+    ] {
+        assert_eq!(Record::parse(invalid), None, "{invalid:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_alignment() {
+    let resolver = Stub(HashMap::from([
+        ("_dmarc.example.com", "v=DMARC1; p=reject"),
+        ("_dmarc.victim.co.uk", "v=DMARC1; p=reject"),
+        ("_dmarc.attacker.co.uk", "v=DMARC1; p=none"),
+        ("_dmarc.example", "v=DMARC1; p=reject; psd=y"),
+        ("_dmarc.team.org.example", "v=DMARC1; p=reject; psd=n"),
+    ]));
+
+    for (domain, organization) in [
+        ("mail.example.com", "example.com"),
+        ("example.com", "example.com"),
+        ("com", "com"),
+        // Tests that domains under public suffixes are their own organizational domains.
+        ("mail.victim.co.uk", "victim.co.uk"),
+        ("attacker.co.uk", "attacker.co.uk"),
+        ("unknown.co.uk", "unknown.co.uk"),
+        // Tests that the domain below a public suffix domain is the organizational domain.
+        ("a.b.example", "b.example"),
+        ("example", "example"),
+        // Tests that a domain that says it is not a public suffix is the organizational domain.
+        ("mail.team.org.example", "team.org.example"),
+    ] {
+        let records = walk(&resolver, domain).await.expect("no lookup fails");
+        assert_eq!(
+            organizational_domain(domain, &records),
+            organization,
+            "{domain}"
+        );
+    }
+
+    for (alignment, authenticated, from, aligned) in [
+        (Alignment::Relaxed, "mail.example.com", "example.com", true),
+        (Alignment::Relaxed, "example.net", "example.com", false),
+        (Alignment::Relaxed, "attacker.co.uk", "victim.co.uk", false),
+        (Alignment::Strict, "EXAMPLE.com", "example.com", true),
+        (Alignment::Strict, "mail.example.com", "example.com", false),
+    ] {
+        let records = walk(&resolver, from).await.expect("no lookup fails");
+        let organization = organizational_domain(from, &records);
+        assert_eq!(
+            alignment
+                .aligns(&resolver, authenticated, from, organization)
+                .await,
+            Ok(aligned),
+            "{authenticated} {from}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_walk() {
+    /// Counts the lookups it makes, finding no records.
+    struct Counter(std::sync::Mutex<Vec<String>>);
+
+    impl Resolver for Counter {
+        async fn lookup_ip(&self, _: &str) -> io::Result<Vec<std::net::IpAddr>> {
+            Ok(Vec::new())
+        }
+
+        async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+            self.0
+                .lock()
+                .expect("the lock is never held across a panic")
+                .push(name.to_owned());
+            Ok(Vec::new())
+        }
+    }
+
+    let resolver = Counter(std::sync::Mutex::default());
+    let records = walk(&resolver, "a.b.c.d.e.f.g.h.i.j.example.com")
+        .await
+        .expect("no lookup fails");
+    assert!(records.is_empty());
+    assert_eq!(
+        resolver
+            .0
+            .into_inner()
+            .expect("the lock is never held across a panic"),
+        [
+            "_dmarc.a.b.c.d.e.f.g.h.i.j.example.com",
+            "_dmarc.f.g.h.i.j.example.com",
+            "_dmarc.g.h.i.j.example.com",
+            "_dmarc.h.i.j.example.com",
+            "_dmarc.i.j.example.com",
+            "_dmarc.j.example.com",
+            "_dmarc.example.com",
+            "_dmarc.com",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_evaluate() {
+    let resolver = Stub(HashMap::from([
+        ("_dmarc.example.com", "v=DMARC1; p=reject; sp=quarantine"),
+        ("_dmarc.strict.example.com", "v=DMARC1; p=reject; aspf=s"),
+        ("_dmarc.none.example.org", "v=DMARC1; p=none"),
+        ("_dmarc.sampled.example.org", "v=DMARC1; p=reject; pct=0"),
+    ]));
+    let spf = |result, sender| SpfVerdict::new(result, SpfIdentity::MailFrom, sender);
+
+    for (from, spf, dkim, result, policy, disposition) in [
+        // Tests that an aligned SPF pass passes.
+        (
+            "example.com",
+            Some(spf(SpfResult::Pass, "smith@mail.example.com")),
+            &[][..],
+            DmarcResult::Pass,
+            Some(DmarcDisposition::Reject),
+            DmarcDisposition::None,
+        ),
+        // Tests that SPF must pass, and must be aligned.
+        (
+            "example.com",
+            Some(spf(SpfResult::Fail, "smith@example.com")),
+            &[],
+            DmarcResult::Fail,
+            Some(DmarcDisposition::Reject),
+            DmarcDisposition::Reject,
+        ),
+        (
+            "example.com",
+            Some(spf(SpfResult::Pass, "smith@example.net")),
+            &[],
+            DmarcResult::Fail,
+            Some(DmarcDisposition::Reject),
+            DmarcDisposition::Reject,
+        ),
+        // Tests that an aligned DKIM signature passes.
+        (
+            "example.com",
+            None,
+            &["example.com"],
+            DmarcResult::Pass,
+            Some(DmarcDisposition::Reject),
+            DmarcDisposition::None,
+        ),
+        // Tests that subdomains use the record of their organizational domain.
+        (
+            "mail.example.com",
+            None,
+            &[],
+            DmarcResult::Fail,
+            Some(DmarcDisposition::Quarantine),
+            DmarcDisposition::Quarantine,
+        ),
+        // Tests strict alignment.
+        (
+            "strict.example.com",
+            Some(spf(SpfResult::Pass, "smith@mail.strict.example.com")),
+            &[],
+            DmarcResult::Fail,
+            Some(DmarcDisposition::Reject),
+            DmarcDisposition::Reject,
+        ),
+        (
+            "none.example.org",
+            None,
+            &[],
+            DmarcResult::Fail,
+            Some(DmarcDisposition::None),
+            DmarcDisposition::None,
+        ),
+        // Tests that unsampled messages are quarantined rather than rejected.
+        (
+            "sampled.example.org",
+            None,
+            &[],
+            DmarcResult::Fail,
+            Some(DmarcDisposition::Reject),
+            DmarcDisposition::Quarantine,
+        ),
+        (
+            "example.net",
+            None,
+            &[],
+            DmarcResult::None,
+            None,
+            DmarcDisposition::None,
+        ),
+        (
+            "fail.example.com",
+            None,
+            &[],
+            DmarcResult::TempError,
+            None,
+            DmarcDisposition::None,
+        ),
+    ] {
+        let verdict = evaluate(&resolver, from, spf.as_ref(), dkim).await;
+        assert_eq!(verdict.result(), result, "{from}");
+        assert_eq!(verdict.policy(), policy, "{from}");
+        assert_eq!(verdict.disposition(), disposition, "{from}");
+    }
+}
@@ -0,0 +1,585 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Looking up the address of each client in DNS blocklists (DNSBLs) when it connects.
+//!
+//! See [`DnsblPolicy`] and [`lookup`].
+//!
+//! [RFC 5782](https://www.rfc-editor.org/rfc/rfc5782.html).
+
+#[cfg(test)]
+mod test;
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fmt::Debug,
+    io,
+    net::{IpAddr, Ipv4Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{resolver::Resolver, session::SessionInfo, ServerConfig};
+
+/// The most lookups that [`DnsblPolicy`] keeps cached before it clears out the expired ones.
+const MAX_CACHED: usize = 4096;
+
+/// The DNS blocklists that the address of each client is looked up in when it connects, and what
+/// is done when it is listed.
+///
+/// Each list that the client is listed on adds its [`Blocklist::weight`] to the score of the
+/// client, and the strongest of the [`Blocklist::action`] of those lists and the actions whose
+/// score thresholds were reached is taken. Listings are kept in
+/// [`crate::session::SessionInfo::dnsbl`] for the handler to make its own decisions with.
+///
+/// Lookups that fail or take longer than [`Self::timeout`] count as not listed, so that a broken
+/// list does not turn every client away. A name that is not listed cannot be told apart from a
+/// failed lookup by [`crate::resolver::SystemResolver`], so a resolver that can, such as the one
+/// enabled by the `hickory` feature, should be used.
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::dnsbl::{Blocklist, DnsblAction, DnsblPolicy};
+/// #
+/// let policy = DnsblPolicy::default()
+///     .with_list(Blocklist::new("zen.spamhaus.org").with_action(DnsblAction::Refuse))
+///     .with_list(Blocklist::new("bl.spamcop.net").with_weight(2))
+///     .with_list(Blocklist::new("b.barracudacentral.org").with_weight(2))
+///     .with_reject_score(4);
+///
+/// assert_eq!(policy.lists().len(), 3);
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct DnsblPolicy {
+    /// The lists that clients are looked up in.
+    lists: Vec<Blocklist>,
+    /// The score at which `MAIL` is rejected, or `None` if the score does not reject it.
+    reject_score: Option<u32>,
+    /// The score at which the connection is refused, or `None` if the score does not refuse it.
+    refuse_score: Option<u32>,
+    /// How long to wait on each lookup.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    timeout: Duration,
+    /// How long the result of each lookup is kept.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    cache_ttl: Duration,
+    /// The results of recent lookups, shared by every clone of the policy.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    cache: Cache,
+}
+
+impl DnsblPolicy {
+    /// Get the lists that clients are looked up in, none by default.
+    #[must_use]
+    pub fn lists(&self) -> &[Blocklist] {
+        &self.lists
+    }
+
+    /// Get the score at which `MAIL` is rejected with `550 5.7.1`, or `None` if the score does not
+    /// reject it, which is the default.
+    #[must_use]
+    pub const fn reject_score(&self) -> Option<u32> {
+        self.reject_score
+    }
+
+    /// Get the score at which the connection is refused with `554 5.7.1` in place of the greeting,
+    /// or `None` if the score does not refuse it, which is the default.
+    #[must_use]
+    pub const fn refuse_score(&self) -> Option<u32> {
+        self.refuse_score
+    }
+
+    /// Get how long to wait on each lookup before counting the client as not listed, two seconds
+    /// by default.
+    ///
+    /// Every list is looked up at once before the client is greeted, so this is about how long
+    /// the greeting can be held up.
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Get how long the result of each lookup is kept for other connections from the same
+    /// address, five minutes by default. Failed lookups are not kept.
+    #[must_use]
+    pub const fn cache_ttl(&self) -> Duration {
+        self.cache_ttl
+    }
+
+    /// Add a list that clients are looked up in. See [`Self::lists`].
+    #[must_use]
+    pub fn with_list(mut self, list: Blocklist) -> Self {
+        self.lists.push(list);
+        self
+    }
+
+    /// Set the score at which `MAIL` is rejected. See [`Self::reject_score`].
+    #[must_use]
+    pub const fn with_reject_score(mut self, score: u32) -> Self {
+        self.reject_score = Some(score);
+        self
+    }
+
+    /// Set the score at which the connection is refused. See [`Self::refuse_score`].
+    #[must_use]
+    pub const fn with_refuse_score(mut self, score: u32) -> Self {
+        self.refuse_score = Some(score);
+        self
+    }
+
+    /// Set how long to wait on each lookup. See [`Self::timeout`].
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how long the result of each lookup is kept, or zero to not keep them. See
+    /// [`Self::cache_ttl`].
+    #[must_use]
+    pub const fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Get the action for a client with `listings`, or `None` if it is not listed.
+    fn action(&self, listings: &[DnsblListing]) -> Option<DnsblAction> {
+        let score = score(listings);
+        let by_score = [
+            (self.refuse_score, DnsblAction::Refuse),
+            (self.reject_score, DnsblAction::Reject),
+        ]
+        .into_iter()
+        .find_map(|(threshold, action)| threshold.is_some_and(|t| score >= t).then_some(action));
+
+        listings
+            .iter()
+            .map(DnsblListing::action)
+            .chain(by_score)
+            .max()
+    }
+}
+
+impl Default for DnsblPolicy {
+    fn default() -> Self {
+        Self {
+            lists: Vec::new(),
+            reject_score: None,
+            refuse_score: None,
+            timeout: Duration::from_secs(2),
+            cache_ttl: Duration::from_mins(5),
+            cache: Cache::default(),
+        }
+    }
+}
+
+/// A DNS blocklist that the address of each client is looked up in, and what is done when it is
+/// listed.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Blocklist {
+    /// The zone that addresses are looked up under.
+    zone: String,
+    /// How much a listing adds to the score of the client.
+    weight: u32,
+    /// What is done when the client is listed.
+    action: DnsblAction,
+    /// The answers that count as a listing, or empty if any of them do.
+    codes: Vec<Ipv4Addr>,
+}
+
+impl Blocklist {
+    /// Create a new [`Self`] that looks up addresses under `zone`, such as `zen.spamhaus.org`,
+    /// with a weight of one that only annotates listed clients.
+    #[must_use]
+    pub fn new(zone: impl Into<String>) -> Self {
+        Self {
+            zone: zone.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Get the zone that addresses are looked up under, such as `zen.spamhaus.org`.
+    #[must_use]
+    pub fn zone(&self) -> &str {
+        &self.zone
+    }
+
+    /// Get how much a listing adds to the score of the client, see [`DnsblPolicy`].
+    #[must_use]
+    pub const fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    /// Get what is done when the client is listed, regardless of its score.
+    #[must_use]
+    pub const fn action(&self) -> DnsblAction {
+        self.action
+    }
+
+    /// Get the answers that count as a listing, such as `127.0.0.2` for the spam sources of a list
+    /// that also lists other kinds of addresses, or empty if any answer does, which is the
+    /// default.
+    ///
+    /// Answers outside of `127.0.0.0/8`, and those in `127.255.255.0/24` that some lists use to
+    /// report errors, never count.
+    #[must_use]
+    pub fn codes(&self) -> &[Ipv4Addr] {
+        &self.codes
+    }
+
+    /// Set how much a listing adds to the score of the client. See [`Self::weight`].
+    #[must_use]
+    pub const fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Set what is done when the client is listed. See [`Self::action`].
+    #[must_use]
+    pub const fn with_action(mut self, action: DnsblAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Set the answers that count as a listing. See [`Self::codes`].
+    #[must_use]
+    pub fn with_codes(mut self, codes: impl IntoIterator<Item = Ipv4Addr>) -> Self {
+        self.codes = codes.into_iter().collect();
+        self
+    }
+
+    /// Get whether `answer` counts as a listing.
+    fn counts(&self, answer: Ipv4Addr) -> bool {
+        let [first, second, third, _] = answer.octets();
+
+        first == 127
+            && (second, third) != (255, 255)
+            && (self.codes.is_empty() || self.codes.contains(&answer))
+    }
+}
+
+impl Default for Blocklist {
+    fn default() -> Self {
+        Self {
+            zone: String::new(),
+            weight: 1,
+            action: DnsblAction::default(),
+            codes: Vec::new(),
+        }
+    }
+}
+
+/// What is done with a client that is listed on a DNS blocklist.
+///
+/// Ordered from weakest to strongest.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum DnsblAction {
+    /// Accept the client, noting the listing in the `Received:` header of each message.
+    #[default]
+    Annotate,
+    /// Greet the client and annotate its messages, but reject each `MAIL` with `550 5.7.1`, so
+    /// that it learns why in the reply.
+    Reject,
+    /// Refuse the connection with `554 5.7.1` in place of the greeting, then close it.
+    Refuse,
+}
+
+/// The result of looking up a client in the DNS blocklists of a [`DnsblPolicy`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DnsblVerdict {
+    /// The lists that the client is listed on, strongest first.
+    listings: Vec<DnsblListing>,
+    /// What is done with the client, or `None` if it is not listed.
+    action: Option<DnsblAction>,
+}
+
+impl DnsblVerdict {
+    /// Get the lists that the client is listed on, those with the strongest action and then the
+    /// highest weight first. Empty if it is not listed on any.
+    #[must_use]
+    pub fn listings(&self) -> &[DnsblListing] {
+        &self.listings
+    }
+
+    /// Get the sum of the weights of the lists that the client is listed on.
+    #[must_use]
+    pub fn score(&self) -> u32 {
+        score(&self.listings)
+    }
+
+    /// Get what is done with the client, or `None` if it is not listed.
+    #[must_use]
+    pub const fn action(&self) -> Option<DnsblAction> {
+        self.action
+    }
+
+    /// Get whether the client is listed on any list.
+    #[must_use]
+    pub const fn is_listed(&self) -> bool {
+        !self.listings.is_empty()
+    }
+
+    /// Get whether `MAIL` is rejected for the client.
+    pub(crate) fn rejects(&self) -> bool {
+        self.action >= Some(DnsblAction::Reject)
+    }
+
+    /// Get whether the connection of the client is refused.
+    pub(crate) fn refuses(&self) -> bool {
+        self.action == Some(DnsblAction::Refuse)
+    }
+
+    /// Get the zone of the list to name when rejecting the client, the strongest one.
+    pub(crate) fn zone(&self) -> &str {
+        self.listings.first().map_or("", DnsblListing::zone)
+    }
+}
+
+/// A DNS blocklist that a client is listed on.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DnsblListing {
+    /// The zone of the list, such as `zen.spamhaus.org`.
+    zone: String,
+    /// The answer of the list, which says why the client is listed.
+    code: Ipv4Addr,
+    /// How much the listing adds to the score of the client.
+    weight: u32,
+    /// What the list does with the client.
+    action: DnsblAction,
+}
+
+impl DnsblListing {
+    /// Get the zone of the list, such as `zen.spamhaus.org`.
+    #[must_use]
+    pub fn zone(&self) -> &str {
+        &self.zone
+    }
+
+    /// Get the answer of the list, such as `127.0.0.2`, which says why the client is listed.
+    #[must_use]
+    pub const fn code(&self) -> Ipv4Addr {
+        self.code
+    }
+
+    /// Get how much the listing adds to the score of the client, see [`Blocklist::weight`].
+    #[must_use]
+    pub const fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    /// Get what the list does with the client, see [`Blocklist::action`].
+    #[must_use]
+    pub const fn action(&self) -> DnsblAction {
+        self.action
+    }
+}
+
+/// The results of recent lookups, by the name that was looked up.
+///
+/// Every cache compares equal, as it is not part of the configuration.
+#[derive(Clone, Default)]
+struct Cache {
+    /// The result of looking up each name.
+    entries: Arc<Mutex<HashMap<String, Cached>>>,
+}
+
+/// The result of a lookup kept in [`Cache`].
+#[derive(Clone, Copy)]
+struct Cached {
+    /// When the name was looked up.
+    at: Instant,
+    /// The answer that counts as a listing, or `None` if the address is not listed.
+    answer: Option<Ipv4Addr>,
+}
+
+impl Cache {
+    /// Get the result for `name` if it was looked up less than `ttl` ago.
+    fn get(&self, name: &str, ttl: Duration) -> Option<Cached> {
+        let cached = *self.entries.lock().ok()?.get(name)?;
+
+        (cached.at.elapsed() < ttl).then_some(cached)
+    }
+
+    /// Keep `answer` for `name`, clearing out the results older than `ttl` if there are too many.
+    fn insert(&self, name: String, answer: Option<Ipv4Addr>, ttl: Duration) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+
+        if entries.len() >= MAX_CACHED {
+            entries.retain(|_, cached| cached.at.elapsed() < ttl);
+        }
+        if entries.len() < MAX_CACHED {
+            let at = Instant::now();
+            entries.insert(name, Cached { at, answer });
+        }
+    }
+}
+
+impl PartialEq for Cache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Cache {}
+
+impl Debug for Cache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cache").finish_non_exhaustive()
+    }
+}
+
+/// Look up `ip` in every list of `policy` at once with `resolver`, returning what is done with it.
+///
+/// Lookups that fail count as not listed, and are returned alongside the verdict with the zone of
+/// their list.
+pub async fn lookup(
+    resolver: &impl Resolver,
+    policy: &DnsblPolicy,
+    ip: IpAddr,
+) -> (DnsblVerdict, Vec<(String, io::Error)>) {
+    let reversed = reverse(ip);
+    let lookups = policy.lists.iter().map(|list| {
+        let name = format!("{reversed}.{}", list.zone);
+        async move { (list, query(resolver, policy, list, name).await) }
+    });
+
+    let mut listings = Vec::new();
+    let mut errors = Vec::new();
+    for (list, result) in futures_util::future::join_all(lookups).await {
+        match result {
+            Ok(Some(code)) => listings.push(DnsblListing {
+                zone: list.zone.clone(),
+                code,
+                weight: list.weight,
+                action: list.action,
+            }),
+            Ok(None) => (),
+            Err(error) => errors.push((list.zone.clone(), error)),
+        }
+    }
+    listings.sort_by_key(|listing| Reverse((listing.action, listing.weight)));
+
+    let action = policy.action(&listings);
+    (DnsblVerdict { listings, action }, errors)
+}
+
+/// Look up the client of `session` in the lists of [`ServerConfig::dnsbl_policy`], logging the
+/// lookups that failed.
+///
+/// Returns `None` if no lists are set.
+pub(crate) async fn check(config: &ServerConfig, session: &SessionInfo) -> Option<DnsblVerdict> {
+    let policy = config
+        .dnsbl_policy()
+        .filter(|policy| !policy.lists.is_empty())?;
+
+    let ip = session.peer_addr().ip().to_canonical();
+    let (verdict, errors) = lookup(config.resolver(), policy, ip).await;
+    for (zone, error) in errors {
+        println!(
+            "[{}] DNSBL lookup of {ip} in {zone} failed: {error}",
+            session.id()
+        );
+    }
+    if verdict.is_listed() {
+        let zones: Vec<_> = verdict.listings.iter().map(DnsblListing::zone).collect();
+        println!(
+            "[{}] {ip} is listed by {} (score {})",
+            session.id(),
+            zones.join(", "),
+            verdict.score()
+        );
+    }
+
+    Some(verdict)
+}
+
+/// Look up `name` for `list`, returning the answer if it counts as a listing.
+///
+/// # Errors
+///
+/// - [`io::Error`] from `resolver`, or [`io::ErrorKind::TimedOut`] if it took longer than
+///   [`DnsblPolicy::timeout`].
+async fn query(
+    resolver: &impl Resolver,
+    policy: &DnsblPolicy,
+    list: &Blocklist,
+    name: String,
+) -> io::Result<Option<Ipv4Addr>> {
+    if let Some(cached) = policy.cache.get(&name, policy.cache_ttl) {
+        return Ok(cached.answer);
+    }
+
+    let answers = tokio::time::timeout(policy.timeout, resolver.lookup_ip(&name))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let answer = answers.into_iter().find_map(|answer| match answer {
+        IpAddr::V4(answer) if list.counts(answer) => Some(answer),
+        _ => None,
+    });
+
+    if !policy.cache_ttl.is_zero() {
+        policy.cache.insert(name, answer, policy.cache_ttl);
+    }
+    Ok(answer)
+}
+
+/// Get the sum of the weights of `listings`.
+fn score(listings: &[DnsblListing]) -> u32 {
+    listings
+        .iter()
+        .map(DnsblListing::weight)
+        .fold(0, u32::saturating_add)
+}
+
+/// Get the name that `ip` is looked up as under a zone: the octets of an IPv4 address or the
+/// nibbles of an IPv6 address in reverse order, separated by dots.
+///
+/// [RFC 5782 section 2.1](https://www.rfc-editor.org/rfc/rfc5782.html#section-2.1) and [section
+/// 2.4](https://www.rfc-editor.org/rfc/rfc5782.html#section-2.4).
+fn reverse(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}")
+        }
+        IpAddr::V6(ip) => {
+            let nibbles: Vec<_> = ip
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|byte| [byte & 0xf, byte >> 4])
+                .map(|nibble| format!("{nibble:x}"))
+                .collect();
+            nibbles.join(".")
+        }
+    }
+}
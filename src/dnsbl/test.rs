@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::{
+    collections::HashMap,
+    net::Ipv6Addr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::*;
+
+/// Resolves names from a table, failing lookups of names under `fail.example` and never answering
+/// those under `slow.example`, and counts the lookups made.
+#[derive(Default)]
+struct Stub {
+    /// The addresses of each name.
+    ip: HashMap<&'static str, Vec<IpAddr>>,
+    /// How many lookups were made.
+    lookups: AtomicUsize,
+}
+
+impl Stub {
+    /// Give `name` the address `ip`.
+    fn ip(mut self, name: &'static str, ip: Ipv4Addr) -> Self {
+        self.ip.entry(name).or_default().push(ip.into());
+        self
+    }
+}
+
+impl Resolver for Stub {
+    async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        if name.ends_with(".fail.example") {
+            return Err(io::Error::other("lookup failed"));
+        }
+        if name.ends_with(".slow.example") {
+            std::future::pending::<()>().await;
+        }
+        Ok(self.ip.get(name).cloned().unwrap_or_default())
+    }
+}
+
+/// The address that is looked up in each test, `192.0.2.99`.
+const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 99));
+
+#[test]
+fn test_reverse() {
+    assert_eq!(reverse(CLIENT), "99.2.0.192");
+    // Tests that IPv4-mapped addresses are looked up as IPv4.
+    assert_eq!(
+        reverse(IpAddr::V6(Ipv4Addr::new(192, 0, 2, 99).to_ipv6_mapped())),
+        "99.2.0.192"
+    );
+    // Tests the example from RFC 5782 section 2.4.
+    assert_eq!(
+        reverse(IpAddr::V6(Ipv6Addr::new(
+            0x2001, 0xdb8, 0x1234, 0, 0, 0, 0, 0x567
+        ))),
+        "7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.4.3.2.1.8.b.d.0.1.0.0.2"
+    );
+}
+
+#[test]
+fn test_codes() {
+    let any = Blocklist::new("bl.example");
+    assert!(any.counts(Ipv4Addr::new(127, 0, 0, 2)));
+    assert!(any.counts(Ipv4Addr::new(127, 0, 0, 10)));
+    // Tests that answers outside of the loopback range, and error codes, do not count.
+    assert!(!any.counts(Ipv4Addr::new(192, 0, 2, 1)));
+    assert!(!any.counts(Ipv4Addr::new(127, 255, 255, 254)));
+
+    let specific = any.with_codes([Ipv4Addr::new(127, 0, 0, 2)]);
+    assert!(specific.counts(Ipv4Addr::new(127, 0, 0, 2)));
+    assert!(!specific.counts(Ipv4Addr::new(127, 0, 0, 10)));
+}
+
+#[tokio::test]
+async fn test_scoring() {
+    let stub = Stub::default()
+        .ip("99.2.0.192.a.example", Ipv4Addr::new(127, 0, 0, 2))
+        .ip("99.2.0.192.b.example", Ipv4Addr::new(127, 0, 0, 3))
+        .ip("99.2.0.192.c.example", Ipv4Addr::new(127, 0, 0, 4));
+    let policy = DnsblPolicy::default()
+        .with_list(Blocklist::new("a.example"))
+        .with_list(Blocklist::new("b.example").with_weight(2))
+        .with_list(Blocklist::new("unlisted.example").with_weight(10))
+        .with_list(Blocklist::new("fail.example").with_weight(10));
+
+    // Tests that listings only annotate the client below every threshold.
+    let (verdict, errors) = lookup(&stub, &policy, CLIENT).await;
+    assert_eq!(verdict.score(), 3);
+    assert_eq!(verdict.action(), Some(DnsblAction::Annotate));
+    assert_eq!(verdict.zone(), "b.example");
+    assert_eq!(verdict.listings()[0].code(), Ipv4Addr::new(127, 0, 0, 3));
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, "fail.example");
+
+    // Tests that the score reaches the thresholds.
+    let (verdict, _) = lookup(&stub, &policy.clone().with_reject_score(3), CLIENT).await;
+    assert!(verdict.rejects() && !verdict.refuses());
+    let (verdict, _) = lookup(&stub, &policy.clone().with_refuse_score(3), CLIENT).await;
+    assert!(verdict.refuses());
+
+    // Tests that the action of a list applies whatever the score, and that it is named first.
+    let policy = policy.with_list(Blocklist::new("c.example").with_action(DnsblAction::Reject));
+    let (verdict, _) = lookup(&stub, &policy, CLIENT).await;
+    assert_eq!(verdict.action(), Some(DnsblAction::Reject));
+    assert_eq!(verdict.zone(), "c.example");
+
+    // Tests that a client that is not listed has no action.
+    let unlisted = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    let (verdict, _) = lookup(&stub, &policy, unlisted).await;
+    assert!(!verdict.is_listed());
+    assert_eq!(verdict.action(), None);
+}
+
+#[tokio::test]
+async fn test_cache_and_timeout() {
+    let stub = Stub::default().ip("99.2.0.192.a.example", Ipv4Addr::new(127, 0, 0, 2));
+    let policy = DnsblPolicy::default()
+        .with_list(Blocklist::new("a.example"))
+        .with_list(Blocklist::new("slow.example").with_action(DnsblAction::Refuse))
+        .with_timeout(Duration::from_millis(50));
+
+    // Tests that a lookup that takes too long counts as not listed.
+    let (verdict, errors) = lookup(&stub, &policy, CLIENT).await;
+    assert_eq!(verdict.action(), Some(DnsblAction::Annotate));
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].1.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(stub.lookups.load(Ordering::Relaxed), 2);
+
+    // Tests that the answer is kept by every clone of the policy, but not the failed lookup.
+    let (verdict, _) = lookup(&stub, &policy.clone(), CLIENT).await;
+    assert!(verdict.is_listed());
+    assert_eq!(stub.lookups.load(Ordering::Relaxed), 3);
+
+    // Tests that answers are not kept without a time to live.
+    let policy = policy.with_cache_ttl(Duration::ZERO);
+    lookup(&stub, &policy, CLIENT).await;
+    lookup(&stub, &policy, CLIENT).await;
+    assert_eq!(stub.lookups.load(Ordering::Relaxed), 7);
+}
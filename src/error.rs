@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Errors from serving SMTP sessions.
+//!
+//! See [`SmtpError`].
+
+use std::fmt::Display;
+
+use tokio::task::JoinError;
+
+/// Possible errors encountered while serving SMTP sessions with [`crate::serve`].
+#[derive(Debug)]
+pub enum SmtpError {
+    /// Accepting a TCP connection failed, after which no more connections are accepted.
+    ///
+    /// See [`tokio::net::TcpListener::accept`].
+    Accept(std::io::Error),
+    /// A session ended with an I/O error.
+    ///
+    /// See [`crate::Session`].
+    Session(std::io::Error),
+    /// The task handling a session panicked or was cancelled.
+    Task(JoinError),
+}
+
+impl Display for SmtpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Accept(e) => write!(f, "failed to accept a connection: {e}"),
+            Self::Session(e) => write!(f, "session ended with an error: {e}"),
+            Self::Task(e) => write!(f, "session task failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SmtpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Accept(e) | Self::Session(e) => Some(e),
+            Self::Task(e) => Some(e),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The events of SMTP sessions, for consumers that follow the protocol flow as it happens.
+//!
+//! See [`crate::listen_events`] and [`SessionEvent`].
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use ascii::AsciiString;
+use bytes::Bytes;
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+use crate::{
+    message::envelope::{Envelope, Recipient},
+    session::{SessionInfo, TlsInfo},
+    Message,
+};
+
+/// How many events of a session can be waiting for the consumer before the session waits to send
+/// more.
+const BUFFERED_EVENTS: usize = 64;
+
+/// Something that happened during an SMTP session, in the order that it happened.
+///
+/// Only what the server accepted is reported, so the events of a session describe its progress
+/// through the protocol. Every decision is still made by the session's [`crate::SmtpHandler`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SessionEvent {
+    /// The session started. This is always the first event.
+    Connected(Box<SessionInfo>),
+    /// The connection was encrypted after `STARTTLS`, forgetting the identity given in `HELO`.
+    Tls(TlsInfo),
+    /// The identity that the client gave in `HELO` was accepted.
+    Helo(AsciiString),
+    /// A mail transaction was started by `MAIL FROM`, with an envelope that has no recipients yet.
+    MailFrom(Envelope),
+    /// A recipient given in `RCPT TO` was accepted.
+    RcptTo(Recipient),
+    /// A line of the data of the message, with the transparency dot-stuffing removed.
+    ///
+    /// Lines past a limit on the size of the data are not reported, see
+    /// [`crate::message::Size`].
+    DataChunk(Bytes),
+    /// The data of the message was received in full, before [`crate::SmtpHandler::on_message`]
+    /// decides whether to accept it.
+    MessageComplete(Box<Message>),
+    /// The session ended. This is the last event, unless the session ended because of an I/O
+    /// error, in which case the events end without it.
+    Closed,
+}
+
+/// The events of one SMTP session, as a [`Stream`] of [`SessionEvent`].
+///
+/// The session waits for the consumer to receive its events once too many are waiting, so every
+/// [`Self`] should be polled until it ends or dropped. Once dropped, the session continues without
+/// sending events.
+#[derive(Debug)]
+pub struct SessionEvents {
+    /// Receives the events from the session.
+    receiver: mpsc::Receiver<SessionEvent>,
+}
+
+impl SessionEvents {
+    /// Create a new [`Self`], returning it alongside the sender for the session to use.
+    pub(crate) fn new() -> (Self, EventSender) {
+        let (sender, receiver) = mpsc::channel(BUFFERED_EVENTS);
+
+        (Self { receiver }, EventSender(Some(sender)))
+    }
+}
+
+impl Stream for SessionEvents {
+    type Item = SessionEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Sends the events of a session to its [`SessionEvents`], if it has one.
+#[derive(Debug, Default)]
+pub(crate) struct EventSender(Option<mpsc::Sender<SessionEvent>>);
+
+impl EventSender {
+    /// Send the event created by `event`, if the consumer is receiving events.
+    ///
+    /// `event` is only called if it will be sent, so that events are not created needlessly.
+    pub(crate) async fn send(&mut self, event: impl FnOnce() -> SessionEvent) {
+        let Some(sender) = &self.0 else {
+            return;
+        };
+
+        if sender.send(event()).await.is_err() {
+            self.0 = None;
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Writes files durably, without replacing the files that are already there.
+//!
+//! See [`write_new`].
+
+use std::{io, path::Path};
+
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+/// Write `contents` to a new file at `temporary` and sync it to disk, then move it to `path`, so
+/// that the file at `path` is either complete or not there at all.
+///
+/// The file is linked into place rather than renamed, as a rename would silently replace a file
+/// that is already at `path`.
+///
+/// # Errors
+///
+/// - [`io::ErrorKind::AlreadyExists`] if there is already a file at `temporary` or `path`.
+/// - [`io::Error`] if the file cannot be written or moved.
+pub async fn write_new(temporary: &Path, path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temporary)
+        .await?;
+    let written = async {
+        file.write_all(contents).await?;
+        file.sync_all().await
+    }
+    .await;
+    drop(file);
+
+    let linked = match written {
+        Ok(()) => fs::hard_link(temporary, path).await,
+        Err(error) => Err(error),
+    };
+    fs::remove_file(temporary).await?;
+    linked?;
+
+    // The link is only durable once the directory that holds it is synced.
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        File::open(parent).await?.sync_all().await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,485 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Filtering messages with rules that match their headers, body, and envelope.
+//!
+//! See [`FilterRule`].
+
+#[cfg(test)]
+mod test;
+
+use std::fmt::Display;
+
+use regex::Regex;
+
+use crate::{handler::Response, message::Message, ServerConfig};
+
+/// The longest pattern that a rule may compile to, in bytes, so that rules from configuration
+/// files cannot use too much memory.
+const MAX_REGEX_SIZE: usize = 1024 * 1024;
+
+/// A rule that does something with the messages that match its condition, such as rejecting
+/// them.
+///
+/// Every rule of [`crate::ServerConfig::filter_rules`] is checked in order against each message
+/// once its data is received, before it is handed to [`crate::SmtpHandler::on_message`]. The first
+/// rule that matches with [`FilterAction::Reject`] or [`FilterAction::Defer`] replies to the
+/// client, and the rest add to the [`FilterVerdict`] kept in [`Message::filter`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::filter::{FilterAction, FilterCondition, FilterPattern, FilterRule};
+/// #
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// // Rejects invoices that are not from example.com.
+/// let rule = FilterRule::new(
+///     "foreign-invoices",
+///     FilterCondition::all([
+///         FilterCondition::header("Subject", FilterPattern::contains("invoice")),
+///         !FilterCondition::sender(FilterPattern::regex(r"@example\.com$")?),
+///     ]),
+///     FilterAction::Reject,
+/// )
+/// .with_text("Invoices are only accepted from example.com");
+///
+/// assert_eq!(rule.name(), "foreign-invoices");
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct FilterRule {
+    /// The name of the rule, for logs and tags.
+    name: String,
+    /// What messages must match.
+    condition: FilterCondition,
+    /// What is done with messages that match.
+    action: FilterAction,
+    /// The text of the reply to messages that are rejected or deferred, if not the usual one.
+    #[cfg_attr(feature = "serde", serde(default))]
+    text: Option<String>,
+}
+
+impl FilterRule {
+    /// Create a new [`Self`] named `name` that does `action` with messages that match
+    /// `condition`.
+    #[must_use]
+    pub fn new(name: impl Into<String>, condition: FilterCondition, action: FilterAction) -> Self {
+        Self {
+            name: name.into(),
+            condition,
+            action,
+            text: None,
+        }
+    }
+
+    /// Get the name of the rule, which is logged when it matches and added by
+    /// [`FilterAction::Tag`].
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get what messages must match.
+    #[must_use]
+    pub const fn condition(&self) -> &FilterCondition {
+        &self.condition
+    }
+
+    /// Get what is done with messages that match.
+    #[must_use]
+    pub const fn action(&self) -> &FilterAction {
+        &self.action
+    }
+
+    /// Get the text of the reply to messages that are rejected or deferred, or `None` for the
+    /// usual text, which is the default.
+    #[must_use]
+    pub fn text(&self) -> Option<&str> {
+        self.text.as_deref()
+    }
+
+    /// Set the text of the reply to messages that are rejected or deferred. See [`Self::text`].
+    #[must_use]
+    pub fn with_text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    /// Get the reply to messages that match, or `None` if they are accepted.
+    fn response(&self) -> Option<Response> {
+        let (code, text) = match self.action {
+            FilterAction::Reject => ("550 5.7.1", "Message rejected by policy"),
+            FilterAction::Defer => ("451 4.7.1", "Message deferred by policy, try again later"),
+            FilterAction::Tag | FilterAction::Route(_) => return None,
+        };
+
+        self.text
+            .as_deref()
+            .and_then(|text| Response::parse(&format!("{code} {text}")).ok())
+            .or_else(|| Response::parse(&format!("{code} {text}")).ok())
+    }
+}
+
+/// What messages must match for a [`FilterRule`] to apply to them.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum FilterCondition {
+    /// Match messages that match every one of the conditions, or every message if there are none.
+    All(Vec<Self>),
+    /// Match messages that match any of the conditions, or no message if there are none.
+    Any(Vec<Self>),
+    /// Match messages that do not match the condition.
+    Not(Box<Self>),
+    /// Match messages with a part that matches a pattern.
+    Match {
+        /// The part of the message that is matched.
+        field: FilterField,
+        /// What the part must match.
+        pattern: FilterPattern,
+    },
+}
+
+impl FilterCondition {
+    /// Match messages that match every one of `conditions`.
+    pub fn all(conditions: impl IntoIterator<Item = Self>) -> Self {
+        Self::All(conditions.into_iter().collect())
+    }
+
+    /// Match messages that match any of `conditions`.
+    pub fn any(conditions: impl IntoIterator<Item = Self>) -> Self {
+        Self::Any(conditions.into_iter().collect())
+    }
+
+    /// Match messages with a header named `name` whose value matches `pattern`.
+    #[must_use]
+    pub fn header(name: impl Into<String>, pattern: FilterPattern) -> Self {
+        Self::Match {
+            field: FilterField::Header(name.into()),
+            pattern,
+        }
+    }
+
+    /// Match messages whose body matches `pattern`.
+    #[must_use]
+    pub const fn body(pattern: FilterPattern) -> Self {
+        Self::Match {
+            field: FilterField::Body,
+            pattern,
+        }
+    }
+
+    /// Match messages whose reverse-path matches `pattern`.
+    #[must_use]
+    pub const fn sender(pattern: FilterPattern) -> Self {
+        Self::Match {
+            field: FilterField::Sender,
+            pattern,
+        }
+    }
+
+    /// Match messages with an accepted recipient that matches `pattern`.
+    #[must_use]
+    pub const fn recipient(pattern: FilterPattern) -> Self {
+        Self::Match {
+            field: FilterField::Recipient,
+            pattern,
+        }
+    }
+
+    /// Check whether `message` matches [`Self`].
+    #[must_use]
+    pub fn matches(&self, message: &Message) -> bool {
+        match self {
+            Self::All(conditions) => conditions
+                .iter()
+                .all(|condition| condition.matches(message)),
+            Self::Any(conditions) => conditions
+                .iter()
+                .any(|condition| condition.matches(message)),
+            Self::Not(condition) => !condition.matches(message),
+            Self::Match { field, pattern } => field
+                .values(message)
+                .iter()
+                .any(|value| pattern.matches(value)),
+        }
+    }
+}
+
+/// Match messages that do not match the condition.
+impl std::ops::Not for FilterCondition {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self::Not(Box::new(self))
+    }
+}
+
+/// The part of a message that a [`FilterCondition::Match`] matches.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum FilterField {
+    /// The value of each header with the name, unfolded.
+    Header(String),
+    /// The body, with any bytes that are not UTF-8 replaced.
+    Body,
+    /// The reverse-path of the envelope, empty for the null reverse-path.
+    Sender,
+    /// Each forward-path of the envelope that was accepted.
+    Recipient,
+    /// The IP address of the client.
+    Client,
+    /// The name that the client gave in `HELO` or `EHLO`.
+    Helo,
+}
+
+impl FilterField {
+    /// Get the values of the field of `message`.
+    fn values(&self, message: &Message) -> Vec<String> {
+        let envelope = message.envelope();
+        let session = message.session();
+
+        match self {
+            Self::Header(name) => message
+                .headers()
+                .get_all(name)
+                .map(ToOwned::to_owned)
+                .collect(),
+            Self::Body => vec![String::from_utf8_lossy(&message.body()).into_owned()],
+            Self::Sender => vec![envelope
+                .reverse_path()
+                .map(ToString::to_string)
+                .unwrap_or_default()],
+            Self::Recipient => envelope
+                .accepted()
+                .map(|recipient| recipient.forward_path().to_string())
+                .collect(),
+            Self::Client => vec![session.peer_addr().ip().to_canonical().to_string()],
+            Self::Helo => session
+                .helo()
+                .map(ToString::to_string)
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+/// What a part of a message must match.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum FilterPattern {
+    /// Match text that contains the string, ignoring ASCII case.
+    Contains(String),
+    /// Match text that the regular expression matches anywhere in.
+    Regex(#[cfg_attr(feature = "serde", serde(with = "regex_string"))] Regex),
+}
+
+impl FilterPattern {
+    /// Match text that contains `text`, ignoring ASCII case.
+    #[must_use]
+    pub fn contains(text: impl Into<String>) -> Self {
+        Self::Contains(text.into())
+    }
+
+    /// Match text that the regular expression `pattern` matches anywhere in, such as
+    /// `(?i)^urgent`.
+    ///
+    /// # Errors
+    ///
+    /// - [`regex::Error`] if `pattern` is not a valid regular expression, or would compile to be
+    ///   too large.
+    pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        compile(pattern).map(Self::Regex)
+    }
+
+    /// Check whether `text` matches [`Self`].
+    #[must_use]
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::Contains(needle) => text
+                .to_ascii_lowercase()
+                .contains(&needle.to_ascii_lowercase()),
+            Self::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+impl PartialEq for FilterPattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Contains(a), Self::Contains(b)) => a.eq_ignore_ascii_case(b),
+            (Self::Regex(a), Self::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for FilterPattern {}
+
+/// Compile the regular expression `pattern`, limiting its size.
+fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    regex::RegexBuilder::new(pattern)
+        .size_limit(MAX_REGEX_SIZE)
+        .build()
+}
+
+/// (De)serialize a [`Regex`] as its pattern.
+#[cfg(feature = "serde")]
+mod regex_string {
+    use regex::Regex;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    /// Serialize `regex` as its pattern.
+    pub fn serialize<S: Serializer>(regex: &Regex, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(regex.as_str())
+    }
+
+    /// Deserialize a [`Regex`] from its pattern, failing if it is not valid.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+        super::compile(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+/// What is done with a message that matches a [`FilterRule`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum FilterAction {
+    /// Reject the message with `550 5.7.1`.
+    Reject,
+    /// Defer the message with `451 4.7.1`, so that the client tries again later.
+    Defer,
+    /// Accept the message, adding the name of the rule to [`FilterVerdict::tags`] and to an
+    /// `X-Filter-Tag:` header.
+    Tag,
+    /// Accept the message, giving it the destination for consumers to route it to, see
+    /// [`FilterVerdict::route`].
+    Route(String),
+}
+
+impl Display for FilterAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reject => f.write_str("reject"),
+            Self::Defer => f.write_str("defer"),
+            Self::Tag => f.write_str("tag"),
+            Self::Route(destination) => write!(f, "route to {destination}"),
+        }
+    }
+}
+
+/// The rules that matched an accepted message, see [`FilterRule`].
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FilterVerdict {
+    /// The names of the rules that matched, in order.
+    matched: Vec<String>,
+    /// The names of the rules that tagged the message, in order.
+    tags: Vec<String>,
+    /// The destination of the first rule that routed the message.
+    route: Option<String>,
+}
+
+impl FilterVerdict {
+    /// Get the names of the rules that matched the message, in order.
+    #[must_use]
+    pub fn matched(&self) -> &[String] {
+        &self.matched
+    }
+
+    /// Get the names of the rules that matched the message with [`FilterAction::Tag`], in order.
+    #[must_use]
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Get the destination of the first rule that matched the message with
+    /// [`FilterAction::Route`], or `None` if none did.
+    #[must_use]
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+}
+
+/// Check `message` against `rules` in order, returning the rule that rejects or defers it, or
+/// the [`FilterVerdict`] of the rules that matched it.
+///
+/// # Errors
+///
+/// - The first [`FilterRule`] that matches with [`FilterAction::Reject`] or
+///   [`FilterAction::Defer`].
+pub fn evaluate<'a>(
+    rules: &'a [FilterRule],
+    message: &Message,
+) -> Result<FilterVerdict, &'a FilterRule> {
+    let mut verdict = FilterVerdict::default();
+
+    for rule in rules.iter().filter(|rule| rule.condition.matches(message)) {
+        match &rule.action {
+            FilterAction::Reject | FilterAction::Defer => return Err(rule),
+            FilterAction::Tag => verdict.tags.push(rule.name.clone()),
+            FilterAction::Route(destination) => {
+                verdict.route.get_or_insert_with(|| destination.clone());
+            }
+        }
+        verdict.matched.push(rule.name.clone());
+    }
+
+    Ok(verdict)
+}
+
+/// Check `message` against [`ServerConfig::filter_rules`], keeping the verdict in it and tagging
+/// it as the rules say.
+///
+/// # Errors
+///
+/// - The [`Response`] to reply with if a rule rejects or defers the message.
+pub(crate) fn apply(config: &ServerConfig, mut message: Message) -> Result<Message, Response> {
+    let rules = config.filter_rules();
+    if rules.is_empty() {
+        return Ok(message);
+    }
+    let id = message.session().id();
+
+    let verdict = match evaluate(rules, &message) {
+        Ok(verdict) => verdict,
+        Err(rule) => {
+            println!(
+                "[{id}] Message matched filter rule {} ({})",
+                rule.name, rule.action
+            );
+            return rule.response().map_or(Ok(message), Err);
+        }
+    };
+    for name in &verdict.matched {
+        println!("[{id}] Message matched filter rule {name}");
+    }
+
+    message.remove_header("X-Filter-Tag");
+    for tag in &verdict.tags {
+        if let Err(error) = message.add_header("X-Filter-Tag", tag) {
+            println!("[{id}] Failed to add X-Filter-Tag header: {error}");
+        }
+    }
+
+    Ok(message.with_filter(verdict))
+}
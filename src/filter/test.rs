@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use super::*;
+
+/// Create the message that the rules of each test are checked against.
+fn message() -> Message {
+    Message::builder()
+        .reverse_path("smith@example.com")
+        .forward_path("jones@example.net")
+        .forward_path("green@example.org")
+        .helo("mail.example.com")
+        .header("Subject", "Your INVOICE is attached")
+        .header("X-Mailer", "Mass Mailer 2.0")
+        .body("Pay now at http://pay.example/\r\n")
+        .build()
+        .expect("the message is valid")
+}
+
+#[test]
+fn test_patterns() -> Result<(), regex::Error> {
+    let message = message();
+
+    // Tests that substrings ignore case, and that regular expressions match anywhere.
+    assert!(
+        FilterCondition::header("subject", FilterPattern::contains("invoice")).matches(&message)
+    );
+    assert!(FilterCondition::body(FilterPattern::regex(r"https?://pay\.")?).matches(&message));
+    assert!(!FilterCondition::body(FilterPattern::regex("^https?://")?).matches(&message));
+    assert!(!FilterCondition::header("Missing", FilterPattern::contains("")).matches(&message));
+
+    // Tests that each envelope field is matched.
+    assert!(FilterCondition::sender(FilterPattern::regex("@example\\.com$")?).matches(&message));
+    assert!(FilterCondition::recipient(FilterPattern::contains("green@")).matches(&message));
+    assert!(FilterCondition::Match {
+        field: FilterField::Helo,
+        pattern: FilterPattern::contains("mail."),
+    }
+    .matches(&message));
+    assert!(FilterCondition::Match {
+        field: FilterField::Client,
+        pattern: FilterPattern::contains("."),
+    }
+    .matches(&message));
+
+    Ok(())
+}
+
+#[test]
+fn test_combinators() {
+    let message = message();
+    let yes = || FilterCondition::header("X-Mailer", FilterPattern::contains("mass"));
+    let no = || FilterCondition::header("X-Mailer", FilterPattern::contains("outlook"));
+
+    assert!(FilterCondition::all([yes(), yes()]).matches(&message));
+    assert!(!FilterCondition::all([yes(), no()]).matches(&message));
+    assert!(FilterCondition::any([no(), yes()]).matches(&message));
+    assert!(!FilterCondition::any([no(), no()]).matches(&message));
+    assert!(!no().matches(&message));
+    // Tests the conditions without any conditions in them.
+    assert!(FilterCondition::all([]).matches(&message));
+    assert!(!FilterCondition::any([]).matches(&message));
+}
+
+#[test]
+fn test_evaluate() {
+    let message = message();
+    let invoice = || FilterCondition::header("Subject", FilterPattern::contains("invoice"));
+    let rules = [
+        FilterRule::new("never", FilterCondition::any([]), FilterAction::Reject),
+        FilterRule::new("invoices", invoice(), FilterAction::Tag),
+        FilterRule::new(
+            "billing",
+            invoice(),
+            FilterAction::Route("billing".to_owned()),
+        ),
+        FilterRule::new("later", invoice(), FilterAction::Route("other".to_owned())),
+    ];
+
+    // Tests that tags add up, and that the first route is kept.
+    let verdict = evaluate(&rules, &message).expect("no rule rejects");
+    assert_eq!(verdict.matched(), ["invoices", "billing", "later"]);
+    assert_eq!(verdict.tags(), ["invoices"]);
+    assert_eq!(verdict.route(), Some("billing"));
+
+    // Tests that the first rule that rejects or defers ends the checks.
+    let rules = [
+        FilterRule::new("defer", invoice(), FilterAction::Defer).with_text("Slow down"),
+        FilterRule::new("reject", invoice(), FilterAction::Reject),
+    ];
+    let rule = evaluate(&rules, &message).expect_err("a rule defers");
+    assert_eq!(rule.name(), "defer");
+    assert_eq!(
+        rule.response().map(|response| response.to_string()),
+        Some("451 4.7.1 Slow down".to_owned())
+    );
+    assert_eq!(
+        rules[1].response().map(|response| response.to_string()),
+        Some("550 5.7.1 Message rejected by policy".to_owned())
+    );
+}
+
+#[test]
+fn test_regex_limits() {
+    assert!(FilterPattern::regex("(unclosed").is_err());
+    assert!(FilterPattern::regex("a{1000}{1000}").is_err());
+}
@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! A [`GreylistStore`] kept in a file.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use tokio::io::AsyncWriteExt;
+
+use super::{poisoned, GreylistEntry, GreylistStore, MAX_ENTRIES};
+use crate::time::{from_millis, millis};
+
+/// The fewest lines that the file is compacted at, see [`FileStore`].
+const MIN_COMPACTED_LINES: usize = 1024;
+
+/// Keeps triplets in a file, so that they are remembered across restarts.
+///
+/// Triplets are also kept in memory, and each one that is set is appended to the file as a line,
+/// so that later lines replace earlier ones for the same key. Once most of the lines have been
+/// replaced or have expired, the file is rewritten with only the current ones. Each line holds
+/// when the entry expires, in milliseconds since the Unix epoch, the entry as
+/// [`GreylistEntry::encode`] formats it, and the key.
+#[derive(Debug)]
+pub struct FileStore {
+    /// The file that triplets are kept in.
+    path: PathBuf,
+    /// Each entry, and when it expires.
+    entries: Mutex<HashMap<String, (GreylistEntry, SystemTime)>>,
+    /// Writes to the file, one entry at a time, so that its lines are in the order that the
+    /// entries were set.
+    log: tokio::sync::Mutex<Log>,
+}
+
+/// The file of a [`FileStore`], behind its lock.
+#[derive(Debug)]
+struct Log {
+    /// The file, opened for appending once the first entry is set.
+    file: Option<tokio::fs::File>,
+    /// The number of lines in the file, including those that were replaced or expired since.
+    lines: usize,
+}
+
+impl FileStore {
+    /// Open the store kept in the file at `path`, which is created when the first entry is set if
+    /// it does not exist. Malformed and expired lines are skipped.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the file exists but cannot be read.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+
+        let now = SystemTime::now();
+        let entries = text
+            .lines()
+            .filter_map(parse_line)
+            .filter(|(_, (_, expires))| *expires > now)
+            .collect();
+
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+            log: tokio::sync::Mutex::new(Log {
+                file: None,
+                lines: text.lines().count(),
+            }),
+        })
+    }
+
+    /// Get the path of the file that triplets are kept in.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replace the file with the entries that have not expired, all at once so that it is never
+    /// left half written.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the file cannot be written.
+    async fn compact(&self, log: &mut Log) -> io::Result<()> {
+        let mut text = String::new();
+        let lines = {
+            let mut entries = self.entries.lock().map_err(|_| poisoned())?;
+            let now = SystemTime::now();
+            entries.retain(|_, (_, expires)| *expires > now);
+            for (key, (entry, expires)) in entries.iter() {
+                text.push_str(&line(key, *entry, *expires));
+            }
+            entries.len()
+        };
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        // The next line is appended to the new file.
+        log.file = None;
+        tokio::fs::write(&temporary, text).await?;
+        tokio::fs::rename(&temporary, &self.path).await?;
+        log.lines = lines;
+
+        Ok(())
+    }
+}
+
+impl GreylistStore for FileStore {
+    async fn get(&self, key: &str) -> io::Result<Option<GreylistEntry>> {
+        let entries = self.entries.lock().map_err(|_| poisoned())?;
+
+        Ok(entries
+            .get(key)
+            .filter(|(_, expires)| *expires > SystemTime::now())
+            .map(|(entry, _)| *entry))
+    }
+
+    async fn set(&self, key: &str, entry: GreylistEntry, ttl: Duration) -> io::Result<()> {
+        // Held until the line is written, so that an older set cannot be written after.
+        let mut log = self.log.lock().await;
+
+        let expires = SystemTime::now() + ttl;
+        let current = {
+            let mut entries = self.entries.lock().map_err(|_| poisoned())?;
+            if entries.len() >= MAX_ENTRIES && !entries.contains_key(key) {
+                let now = SystemTime::now();
+                entries.retain(|_, (_, expires)| *expires > now);
+                if entries.len() >= MAX_ENTRIES {
+                    return Err(io::Error::other("greylist store full"));
+                }
+            }
+            entries.insert(key.to_owned(), (entry, expires));
+            entries.len()
+        };
+
+        if log.lines >= MIN_COMPACTED_LINES.max(current * 2) {
+            return self.compact(&mut log).await;
+        }
+
+        let file = match &mut log.file {
+            Some(file) => file,
+            file @ None => file.insert(
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .await?,
+            ),
+        };
+        file.write_all(line(key, entry, expires).as_bytes()).await?;
+        file.flush().await?;
+        log.lines += 1;
+        drop(log);
+
+        Ok(())
+    }
+}
+
+/// Format a line of the file for `key`, holding `entry` until `expires`.
+fn line(key: &str, entry: GreylistEntry, expires: SystemTime) -> String {
+    format!("{} {} {key}\n", millis(expires), entry.encode())
+}
+
+/// Parse a line of the file into its key, entry, and when it expires.
+fn parse_line(line: &str) -> Option<(String, (GreylistEntry, SystemTime))> {
+    let (expires, rest) = line.split_once(' ')?;
+    let (first_seen, rest) = rest.split_once(' ')?;
+    let (passed, rest) = rest.split_once(' ')?;
+    let (last_seen, key) = rest.split_once(' ')?;
+    let entry = GreylistEntry::decode(&format!("{first_seen} {passed} {last_seen}"))?;
+
+    Some((key.to_owned(), (entry, from_millis(expires)?)))
+}
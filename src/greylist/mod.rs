@@ -0,0 +1,515 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Greylisting, which defers mail from unknown senders until they try again, as legitimate servers
+//! do and most spam software does not.
+//!
+//! See [`GreylistPolicy`] and [`GreylistStore`].
+//!
+//! [RFC 6647](https://www.rfc-editor.org/rfc/rfc6647.html).
+
+mod file;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(test)]
+mod test;
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use ascii::AsciiStr;
+use futures_util::{future::BoxFuture, FutureExt};
+
+pub use self::file::FileStore;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisStore;
+use crate::{
+    handler::{Decision, Response},
+    message::envelope::{Envelope, Recipient},
+    session::SessionInfo,
+    time::{from_millis, millis},
+    ServerConfig,
+};
+
+/// The most entries that [`MemoryStore`] keeps before it clears out the expired ones.
+const MAX_ENTRIES: usize = 65536;
+
+/// How many times a passed triplet can be remembered for longer in [`GreylistPolicy::pass_ttl`],
+/// so that it is not set again on every use, but about once a day by default.
+const REFRESHES_PER_TTL: u32 = 32;
+
+/// How long mail is deferred from senders that have not been seen before, and how long they are
+/// remembered.
+///
+/// Each recipient is keyed on the network of the client, its reverse-path, and the recipient,
+/// known as a triplet. The network is the `/24` of an IPv4 address or the `/64` of an IPv6 one, as
+/// large senders retry from other servers of the same pool. A recipient with a new triplet is
+/// deferred with `450 4.7.1`, and accepted if the client tries again after
+/// [`Self::initial_delay`] but within [`Self::retry_window`], after which its triplet is remembered
+/// as passed for [`Self::pass_ttl`] since it was last used. As that is only set again once a
+/// small part of [`Self::pass_ttl`] has passed, the triplet may be forgotten slightly sooner.
+///
+/// Recipients are only greylisted once the handler accepted them, so that recipients that do not
+/// exist are rejected at once, and never for authenticated clients. Triplets are kept in a
+/// [`GreylistStore`], in memory by default, and lookups that fail let the recipient through.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use smtp_gateway::greylist::{FileStore, GreylistPolicy};
+/// #
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let path = std::env::temp_dir().join("smtp_gateway-greylist-doc");
+/// let policy = GreylistPolicy::default()
+///     .with_initial_delay(Duration::from_mins(1))
+///     .with_store(FileStore::open(&path)?);
+///
+/// assert_eq!(policy.retry_window(), Duration::from_hours(24));
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct GreylistPolicy {
+    /// How long a new triplet is deferred for.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    initial_delay: Duration,
+    /// How long after a new triplet was first seen a retry is accepted.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    retry_window: Duration,
+    /// How long a passed triplet is remembered since it was last used.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    pass_ttl: Duration,
+    /// Where triplets are kept.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    store: SharedGreylistStore,
+}
+
+impl GreylistPolicy {
+    /// Get how long a new triplet is deferred for, five minutes by default.
+    #[must_use]
+    pub const fn initial_delay(&self) -> Duration {
+        self.initial_delay
+    }
+
+    /// Get how long after a new triplet was first seen a retry is accepted, 24 hours by default.
+    /// Retries after this are treated as a new triplet.
+    #[must_use]
+    pub const fn retry_window(&self) -> Duration {
+        self.retry_window
+    }
+
+    /// Get how long a passed triplet is remembered since it was last used, 36 days by default so
+    /// that monthly mail is not deferred again.
+    #[must_use]
+    pub const fn pass_ttl(&self) -> Duration {
+        self.pass_ttl
+    }
+
+    /// Get where triplets are kept, in memory by default.
+    #[must_use]
+    pub const fn store(&self) -> &SharedGreylistStore {
+        &self.store
+    }
+
+    /// Set how long a new triplet is deferred for. See [`Self::initial_delay`].
+    #[must_use]
+    pub const fn with_initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Set how long after a new triplet was first seen a retry is accepted. See
+    /// [`Self::retry_window`].
+    #[must_use]
+    pub const fn with_retry_window(mut self, window: Duration) -> Self {
+        self.retry_window = window;
+        self
+    }
+
+    /// Set how long a passed triplet is remembered. See [`Self::pass_ttl`].
+    #[must_use]
+    pub const fn with_pass_ttl(mut self, ttl: Duration) -> Self {
+        self.pass_ttl = ttl;
+        self
+    }
+
+    /// Keep triplets in `store`, such as a [`FileStore`] to remember them across restarts. See
+    /// [`Self::store`].
+    #[must_use]
+    pub fn with_store(mut self, store: impl GreylistStore) -> Self {
+        self.store = SharedGreylistStore::new(store);
+        self
+    }
+
+    /// Check the triplet of a client at `ip` sending from `reverse_path`, which is empty for the
+    /// null reverse-path, to `recipient`, returning whether it passes, and recording it if it does
+    /// not.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] from the [`GreylistStore`].
+    pub async fn check(&self, ip: IpAddr, reverse_path: &str, recipient: &str) -> io::Result<bool> {
+        self.check_at(&key(ip, reverse_path, recipient), SystemTime::now())
+            .await
+    }
+
+    /// Check the triplet `key` as if it were `now`. See [`Self::check`].
+    async fn check_at(&self, key: &str, now: SystemTime) -> io::Result<bool> {
+        let entry = self.store.get(key).await?;
+        let age = |entry: &GreylistEntry| now.duration_since(entry.first_seen).unwrap_or_default();
+
+        match entry {
+            Some(entry) if entry.passed => {
+                let unused = now.duration_since(entry.last_seen).unwrap_or_default();
+                if unused >= self.pass_ttl / REFRESHES_PER_TTL {
+                    let entry = entry.with_last_seen(now);
+                    self.store.set(key, entry, self.pass_ttl).await?;
+                }
+                Ok(true)
+            }
+            Some(entry) if age(&entry) < self.initial_delay => Ok(false),
+            Some(entry) if age(&entry) <= self.retry_window => {
+                let entry = GreylistEntry::new(entry.first_seen, true).with_last_seen(now);
+                self.store.set(key, entry, self.pass_ttl).await?;
+                Ok(true)
+            }
+            _ => {
+                let entry = GreylistEntry::new(now, false);
+                self.store.set(key, entry, self.retry_window).await?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl Default for GreylistPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_mins(5),
+            retry_window: Duration::from_hours(24),
+            pass_ttl: Duration::from_hours(36 * 24),
+            store: SharedGreylistStore::default(),
+        }
+    }
+}
+
+/// What a [`GreylistStore`] keeps for a triplet.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct GreylistEntry {
+    /// When the triplet was first seen.
+    first_seen: SystemTime,
+    /// Whether the client tried again in time.
+    passed: bool,
+    /// When the entry was last set for the triplet.
+    last_seen: SystemTime,
+}
+
+impl GreylistEntry {
+    /// Create a new [`Self`] for a triplet first seen at `first_seen`, and whether it passed.
+    #[must_use]
+    pub const fn new(first_seen: SystemTime, passed: bool) -> Self {
+        Self {
+            first_seen,
+            passed,
+            last_seen: first_seen,
+        }
+    }
+
+    /// Get when the triplet was first seen.
+    #[must_use]
+    pub const fn first_seen(&self) -> SystemTime {
+        self.first_seen
+    }
+
+    /// Get whether the client tried again in time, so that the triplet is no longer deferred.
+    #[must_use]
+    pub const fn passed(&self) -> bool {
+        self.passed
+    }
+
+    /// Get when the entry was last set for the triplet, which is when it was first seen unless it
+    /// passed since.
+    #[must_use]
+    pub const fn last_seen(&self) -> SystemTime {
+        self.last_seen
+    }
+
+    /// Set when the entry was last set for the triplet. See [`Self::last_seen`].
+    #[must_use]
+    pub const fn with_last_seen(mut self, last_seen: SystemTime) -> Self {
+        self.last_seen = last_seen;
+        self
+    }
+
+    /// Format [`Self`] as the milliseconds since the Unix epoch when it was first seen, a space,
+    /// `1` if it passed or `0` if not, a space, and the milliseconds since the Unix epoch when it
+    /// was last seen, for stores that keep text.
+    #[must_use]
+    pub fn encode(&self) -> String {
+        format!(
+            "{} {} {}",
+            millis(self.first_seen),
+            u8::from(self.passed),
+            millis(self.last_seen)
+        )
+    }
+
+    /// Parse [`Self`] from the format of [`Self::encode`], or `None` if it is malformed.
+    ///
+    /// Entries without when they were last seen are read as last seen when they were first seen.
+    #[must_use]
+    pub fn decode(text: &str) -> Option<Self> {
+        let (first_seen, rest) = text.split_once(' ')?;
+        let (passed, last_seen) = match rest.split_once(' ') {
+            Some((passed, last_seen)) => (passed, Some(last_seen)),
+            None => (rest, None),
+        };
+        let first_seen = from_millis(first_seen)?;
+
+        Some(Self {
+            first_seen,
+            passed: match passed {
+                "0" => false,
+                "1" => true,
+                _ => return None,
+            },
+            last_seen: match last_seen {
+                Some(last_seen) => from_millis(last_seen)?,
+                None => first_seen,
+            },
+        })
+    }
+}
+
+/// Keeps the triplets of [`GreylistPolicy`], such as [`MemoryStore`], [`FileStore`], or with the
+/// `redis` feature, `RedisStore` to share them between servers.
+///
+/// Entries are keyed on text that identifies the triplet, and given a time to live, after which
+/// the store should forget them.
+pub trait GreylistStore: Send + Sync + 'static {
+    /// Get the entry for `key`, or `None` if there is none or it expired.
+    fn get(&self, key: &str) -> impl Future<Output = io::Result<Option<GreylistEntry>>> + Send;
+
+    /// Keep `entry` for `key` until `ttl` passes, replacing any entry for it.
+    fn set(
+        &self,
+        key: &str,
+        entry: GreylistEntry,
+        ttl: Duration,
+    ) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// Keeps triplets in memory, forgetting them when the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    /// Each entry, and when it expires.
+    entries: Mutex<HashMap<String, (GreylistEntry, Instant)>>,
+}
+
+impl MemoryStore {
+    /// Create a new empty [`Self`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl GreylistStore for MemoryStore {
+    async fn get(&self, key: &str) -> io::Result<Option<GreylistEntry>> {
+        let entries = self.entries.lock().map_err(|_| poisoned())?;
+
+        Ok(entries
+            .get(key)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(entry, _)| *entry))
+    }
+
+    async fn set(&self, key: &str, entry: GreylistEntry, ttl: Duration) -> io::Result<()> {
+        let mut entries = self.entries.lock().map_err(|_| poisoned())?;
+
+        let now = Instant::now();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, (_, expires)| *expires > now);
+        }
+        entries.insert(key.to_owned(), (entry, now + ttl));
+        drop(entries);
+
+        Ok(())
+    }
+}
+
+/// The error for a store whose lock was poisoned by a panic.
+fn poisoned() -> io::Error {
+    io::Error::other("greylist store poisoned")
+}
+
+/// A [`GreylistStore`] that can be shared between sessions and kept in a [`GreylistPolicy`].
+///
+/// Compares equal to clones of itself. Defaults to a [`MemoryStore`] that every default shares,
+/// so that triplets are kept when the configuration is reloaded.
+#[derive(Clone)]
+pub struct SharedGreylistStore {
+    /// The store that triplets are kept in.
+    store: Arc<dyn DynGreylistStore>,
+}
+
+impl SharedGreylistStore {
+    /// Create a new [`Self`] that keeps triplets in `store`.
+    pub fn new(store: impl GreylistStore) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+}
+
+impl Default for SharedGreylistStore {
+    fn default() -> Self {
+        /// The store that every default shares, so that default configurations compare equal.
+        static MEMORY: LazyLock<SharedGreylistStore> =
+            LazyLock::new(|| SharedGreylistStore::new(MemoryStore::new()));
+
+        MEMORY.clone()
+    }
+}
+
+impl PartialEq for SharedGreylistStore {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.store, &other.store)
+    }
+}
+
+impl Eq for SharedGreylistStore {}
+
+impl Debug for SharedGreylistStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedGreylistStore")
+            .finish_non_exhaustive()
+    }
+}
+
+impl GreylistStore for SharedGreylistStore {
+    async fn get(&self, key: &str) -> io::Result<Option<GreylistEntry>> {
+        self.store.get_boxed(key).await
+    }
+
+    async fn set(&self, key: &str, entry: GreylistEntry, ttl: Duration) -> io::Result<()> {
+        self.store.set_boxed(key, entry, ttl).await
+    }
+}
+
+/// A [`GreylistStore`] with its methods boxed, so that it can be used as a trait object.
+trait DynGreylistStore: Send + Sync {
+    /// See [`GreylistStore::get`].
+    fn get_boxed<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Option<GreylistEntry>>>;
+
+    /// See [`GreylistStore::set`].
+    fn set_boxed<'a>(
+        &'a self,
+        key: &'a str,
+        entry: GreylistEntry,
+        ttl: Duration,
+    ) -> BoxFuture<'a, io::Result<()>>;
+}
+
+impl<S: GreylistStore> DynGreylistStore for S {
+    fn get_boxed<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Option<GreylistEntry>>> {
+        GreylistStore::get(self, key).boxed()
+    }
+
+    fn set_boxed<'a>(
+        &'a self,
+        key: &'a str,
+        entry: GreylistEntry,
+        ttl: Duration,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        GreylistStore::set(self, key, entry, ttl).boxed()
+    }
+}
+
+/// Get the key of the triplet of a client at `ip` sending from `reverse_path` to `recipient`,
+/// such as `192.0.2.0/24 <smith@example.com> <jones@example.com>`.
+///
+/// The paths are lowercased, as the domains of addresses are not case-sensitive and few servers
+/// treat local parts as though they are.
+fn key(ip: IpAddr, reverse_path: &str, recipient: &str) -> String {
+    let network = match ip.to_canonical() {
+        IpAddr::V4(ip) => format!("{}/24", Ipv4Addr::from(u32::from(ip) & !0xff)),
+        IpAddr::V6(ip) => format!(
+            "{}/64",
+            Ipv6Addr::from(u128::from(ip) & !u128::from(u64::MAX))
+        ),
+    };
+
+    format!(
+        "{network} <{}> <{}>",
+        reverse_path.to_ascii_lowercase(),
+        recipient.to_ascii_lowercase()
+    )
+}
+
+/// Defer `decision` to accept `recipient` of the transaction with `envelope` if its triplet is
+/// greylisted by [`ServerConfig::greylist_policy`], replying with `450 4.7.1`.
+///
+/// Decisions that do not accept the recipient, and recipients of authenticated clients, are left
+/// as they are. Lookups that fail are logged, and let the recipient through.
+pub(crate) async fn apply(
+    config: &ServerConfig,
+    session: &SessionInfo,
+    envelope: Option<&Envelope>,
+    recipient: &Recipient,
+    decision: Decision,
+) -> Decision {
+    let Some(policy) = config.greylist_policy() else {
+        return decision;
+    };
+    if !decision.is_accepted() || session.authenticated_user().is_some() {
+        return decision;
+    }
+
+    let ip = session.peer_addr().ip();
+    let reverse_path = envelope
+        .and_then(Envelope::reverse_path)
+        .map_or("", AsciiStr::as_str);
+    let forward_path = recipient.forward_path().as_str();
+    match policy.check(ip, reverse_path, forward_path).await {
+        Ok(true) => decision,
+        Ok(false) => {
+            println!(
+                "[{}] Greylisted {ip} sending from <{reverse_path}> to <{forward_path}>",
+                session.id()
+            );
+            Decision::Reply(
+                Response::parse("450 4.7.1 Greylisted, please try again later")
+                    .expect("written in code as a valid reply"),
+            )
+        }
+        Err(error) => {
+            println!("[{}] Greylisting failed: {error}", session.id());
+            decision
+        }
+    }
+}
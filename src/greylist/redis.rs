@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! A [`GreylistStore`] kept in Redis, which also keeps counters for rate limits.
+
+use std::{io, time::Duration};
+
+use redis::{
+    aio::MultiplexedConnection, AsyncCommands, Client, ExistenceCheck, RedisError, SetExpiry,
+    SetOptions,
+};
+
+use super::{GreylistEntry, GreylistStore};
+
+/// The prefix of the key of each triplet, so that the database can be shared.
+const PREFIX: &str = "greylist:";
+/// The prefix of the key of each counter of [`RedisStore::count`].
+const RATE_PREFIX: &str = "rate:";
+
+/// Keeps triplets in Redis, so that they can be shared between servers and are remembered across
+/// restarts.
+///
+/// Each triplet is kept as a string key with the prefix `greylist:`, holding the entry as
+/// [`GreylistEntry::encode`] formats it, and expiring along with it.
+///
+/// Servers that share a [`Self`] can also share rate limits with [`Self::count`], and queue
+/// messages in the same Redis server with `RedisSink::from_store` in `crate::sink`.
+#[derive(Clone)]
+pub struct RedisStore {
+    /// The connection to the server, shared between sessions.
+    connection: MultiplexedConnection,
+}
+
+impl RedisStore {
+    /// Connect to the Redis server at `url`, such as `redis://127.0.0.1/`.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if `url` is malformed or the server cannot be connected to.
+    pub async fn connect(url: &str) -> io::Result<Self> {
+        let client = Client::open(url).map_err(into_io)?;
+        let connection = client
+            .get_multiplexed_tokio_connection()
+            .await
+            .map_err(into_io)?;
+
+        Ok(Self { connection })
+    }
+
+    /// Count an event for `key`, such as a message from a sender, returning how many events have
+    /// been counted for it in the current window of `period`, including this one.
+    ///
+    /// The window starts with the first event counted for `key`, and the count starts over once
+    /// it passes. Every server counting on the same Redis server shares the count, so a handler can
+    /// hold clients to a limit across all of them.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::time::Duration;
+    /// # use smtp_gateway::greylist::RedisStore;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let store = RedisStore::connect("redis://127.0.0.1/").await?;
+    /// if store.count("sender:smith@example.com", Duration::from_hours(1)).await? > 100 {
+    ///     // Defer the message.
+    /// }
+    /// #     Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the server cannot be reached.
+    pub async fn count(&self, key: &str, period: Duration) -> io::Result<u64> {
+        let mut connection = self.connection.clone();
+        let key = format!("{RATE_PREFIX}{key}");
+        let period = u64::try_from(period.as_millis()).unwrap_or(u64::MAX).max(1);
+
+        // The counter is created with its expiry in the same transaction that increments it, so it
+        // cannot be left without one.
+        let (count,): (u64,) = redis::pipe()
+            .atomic()
+            .set_options(
+                &key,
+                0,
+                SetOptions::default()
+                    .conditional_set(ExistenceCheck::NX)
+                    .with_expiration(SetExpiry::PX(period)),
+            )
+            .ignore()
+            .incr(&key, 1)
+            .query_async(&mut connection)
+            .await
+            .map_err(into_io)?;
+
+        Ok(count)
+    }
+
+    /// Get the connection to the server, such as to share it with a sink.
+    pub(crate) const fn connection(&self) -> &MultiplexedConnection {
+        &self.connection
+    }
+}
+
+impl std::fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStore").finish_non_exhaustive()
+    }
+}
+
+impl GreylistStore for RedisStore {
+    async fn get(&self, key: &str) -> io::Result<Option<GreylistEntry>> {
+        let mut connection = self.connection.clone();
+        let value: Option<String> = connection
+            .get(format!("{PREFIX}{key}"))
+            .await
+            .map_err(into_io)?;
+
+        Ok(value.as_deref().and_then(GreylistEntry::decode))
+    }
+
+    async fn set(&self, key: &str, entry: GreylistEntry, ttl: Duration) -> io::Result<()> {
+        let mut connection = self.connection.clone();
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX).max(1);
+
+        connection
+            .pset_ex(format!("{PREFIX}{key}"), entry.encode(), ttl)
+            .await
+            .map_err(into_io)
+    }
+}
+
+/// Convert `error` into an [`io::Error`].
+fn into_io(error: RedisError) -> io::Error {
+    io::Error::other(error)
+}
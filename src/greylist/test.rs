@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::time::UNIX_EPOCH;
+
+use super::*;
+
+/// The client whose triplets are checked in each test.
+const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 99));
+
+#[test]
+fn test_key() {
+    assert_eq!(
+        key(CLIENT, "Smith@Example.com", "jones@example.com"),
+        "192.0.2.0/24 <smith@example.com> <jones@example.com>"
+    );
+    // Tests that IPv4-mapped addresses are keyed as IPv4, and IPv6 on the /64.
+    assert_eq!(
+        key(
+            IpAddr::V6(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped()),
+            "",
+            "jones@example.com"
+        ),
+        "192.0.2.0/24 <> <jones@example.com>"
+    );
+    assert_eq!(
+        key(
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 1, 2, 3, 4, 5, 6)),
+            "",
+            "jones@example.com"
+        ),
+        "2001:db8:1:2::/64 <> <jones@example.com>"
+    );
+}
+
+#[test]
+fn test_encoding() {
+    let entry = GreylistEntry::new(UNIX_EPOCH + Duration::from_millis(1234), true);
+    assert_eq!(entry.encode(), "1234 1 1234");
+    assert_eq!(GreylistEntry::decode("1234 1 1234"), Some(entry));
+    assert_eq!(GreylistEntry::decode("1234 1"), Some(entry));
+    let entry = entry.with_last_seen(UNIX_EPOCH + Duration::from_millis(5678));
+    assert_eq!(entry.encode(), "1234 1 5678");
+    assert_eq!(GreylistEntry::decode("1234 1 5678"), Some(entry));
+    assert_eq!(GreylistEntry::decode("1234 2"), None);
+    assert_eq!(GreylistEntry::decode("1234 1 x"), None);
+    assert_eq!(GreylistEntry::decode("1234"), None);
+}
+
+#[tokio::test]
+async fn test_windows() -> io::Result<()> {
+    let policy = GreylistPolicy::default().with_store(MemoryStore::new());
+    let triplet = key(CLIENT, "smith@example.com", "jones@example.com");
+    let start = SystemTime::now();
+
+    // Tests that a new triplet is deferred, and still is before the initial delay passes.
+    assert!(!policy.check_at(&triplet, start).await?);
+    assert!(
+        !policy
+            .check_at(&triplet, start + Duration::from_mins(4))
+            .await?
+    );
+    // Tests that a retry within the window passes, and that the triplet then keeps passing.
+    assert!(
+        policy
+            .check_at(&triplet, start + Duration::from_mins(6))
+            .await?
+    );
+    assert!(
+        policy
+            .check_at(&triplet, start + Duration::from_hours(48))
+            .await?
+    );
+    assert!(policy
+        .store()
+        .get(&triplet)
+        .await?
+        .is_some_and(|entry| entry.passed() && entry.first_seen() == start));
+
+    // Tests that a passed triplet is only remembered for longer once a while has passed since it
+    // last was, rather than on every use.
+    let last_seen = || async {
+        policy
+            .store()
+            .get(&triplet)
+            .await
+            .map(|entry| entry.map(|entry| entry.last_seen()))
+    };
+    assert_eq!(last_seen().await?, Some(start + Duration::from_hours(48)));
+    assert!(
+        policy
+            .check_at(&triplet, start + Duration::from_hours(49))
+            .await?
+    );
+    assert_eq!(last_seen().await?, Some(start + Duration::from_hours(48)));
+    assert!(
+        policy
+            .check_at(&triplet, start + Duration::from_hours(96))
+            .await?
+    );
+    assert_eq!(last_seen().await?, Some(start + Duration::from_hours(96)));
+
+    // Tests that a retry after the window is treated as a new triplet.
+    let triplet = key(CLIENT, "", "jones@example.com");
+    assert!(!policy.check_at(&triplet, start).await?);
+    let late = start + Duration::from_hours(25);
+    assert!(!policy.check_at(&triplet, late).await?);
+    assert!(
+        !policy
+            .check_at(&triplet, late + Duration::from_mins(1))
+            .await?
+    );
+    assert!(
+        policy
+            .check_at(&triplet, late + Duration::from_mins(5))
+            .await?
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_memory_store() -> io::Result<()> {
+    let store = MemoryStore::new();
+    let entry = GreylistEntry::new(SystemTime::now(), false);
+
+    store.set("a", entry, Duration::from_mins(1)).await?;
+    store.set("b", entry, Duration::ZERO).await?;
+    assert_eq!(store.get("a").await?, Some(entry));
+    // Tests that expired entries are forgotten.
+    assert_eq!(store.get("b").await?, None);
+    assert_eq!(store.get("c").await?, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_file_store() -> io::Result<()> {
+    let directory = std::env::temp_dir().join("smtp_gateway_test_greylist");
+    std::fs::create_dir_all(&directory)?;
+    let path = directory.join("triplets");
+    let _ = std::fs::remove_file(&path);
+
+    let entry = GreylistEntry::new(UNIX_EPOCH + Duration::from_secs(1), true);
+    let store = FileStore::open(&path)?;
+    assert_eq!(store.get("a b").await?, None);
+    store.set("a b", entry, Duration::from_mins(1)).await?;
+    store.set("c", entry, Duration::ZERO).await?;
+
+    // Tests that entries are read back with their keys, that expired ones are skipped, and that
+    // malformed lines are ignored.
+    std::fs::write(
+        &path,
+        std::fs::read_to_string(&path)? + "malformed\n1 2 3 4\n",
+    )?;
+    let store = FileStore::open(&path)?;
+    assert_eq!(store.get("a b").await?, Some(entry));
+    assert_eq!(store.get("c").await?, None);
+
+    // Tests that entries set again replace the earlier ones when the file is read back, and that
+    // the file is compacted once most of its lines are stale.
+    let later = entry.with_last_seen(UNIX_EPOCH + Duration::from_secs(2));
+    for _ in 0..1024 {
+        store.set("a b", entry, Duration::from_mins(1)).await?;
+    }
+    store.set("a b", later, Duration::from_mins(1)).await?;
+    assert!(std::fs::read_to_string(&path)?.lines().count() < 1024);
+    let store = FileStore::open(&path)?;
+    assert_eq!(store.get("a b").await?, Some(later));
+
+    Ok(())
+}
+
+#[test]
+fn test_default_store() {
+    // Tests that defaults share a store, so that they compare equal.
+    assert_eq!(GreylistPolicy::default(), GreylistPolicy::default());
+    assert_ne!(
+        GreylistPolicy::default(),
+        GreylistPolicy::default().with_store(MemoryStore::new())
+    );
+}
@@ -0,0 +1,360 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The state of an SMTP session, as shared with the consumer's handler.
+//!
+//! See [`SessionContext`].
+
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ascii::AsciiString;
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+use crate::{
+    audit::{Audit, Rejection},
+    connection::Transaction,
+    event::EventSender,
+    listener::Overrides,
+    message::{envelope::Envelope, ContentHash, Size},
+    session::SessionInfo,
+    transcript::{Recorder, Transcript},
+    Message, ServerConfig,
+};
+
+/// The state of an SMTP session that persists between commands, passed to every method of
+/// [`super::SmtpHandler`].
+///
+/// Along with the state of the session, this holds values of any type for the handler to keep
+/// between its methods, one for each type. See [`Self::insert`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::{
+/// #     handler::{Decision, HandlerResult, SessionContext, SmtpHandler},
+/// #     message::envelope::Recipient,
+/// # };
+/// #
+/// /// Whether a recipient matched a known user.
+/// struct KnownUser;
+///
+/// /// Only accepts data for transactions with a known user as a recipient.
+/// struct KnownUsersOnly;
+///
+/// impl SmtpHandler for KnownUsersOnly {
+///     async fn on_rcpt(
+///         &mut self,
+///         context: &mut SessionContext,
+///         recipient: &Recipient,
+///     ) -> HandlerResult {
+///         if recipient.forward_path().as_str() == "postmaster@example.com" {
+///             context.insert(KnownUser);
+///         }
+///
+///         Ok(Decision::Accept)
+///     }
+///
+///     async fn on_data_start(&mut self, context: &mut SessionContext) -> HandlerResult {
+///         if context.remove::<KnownUser>().is_some() {
+///             Ok(Decision::Accept)
+///         } else {
+///             Ok(Decision::Reject)
+///         }
+///     }
+/// }
+/// ```
+pub struct SessionContext {
+    /// The details of the session, including the identity given by the client in `HELO`.
+    pub(crate) session: SessionInfo,
+    /// The mail transaction in progress, if any.
+    pub(crate) transaction: Option<Transaction>,
+    /// Whether the `DATA` command was accepted, meaning that the next lines are data.
+    pub(crate) awaiting_data: bool,
+    /// Whether `STARTTLS` was accepted, meaning that the handshake comes next.
+    pub(crate) starting_tls: bool,
+    /// The service extensions advertised to the client in reply to `EHLO`.
+    pub(crate) extensions: Vec<AsciiString>,
+    /// The number of errors that the client made in a row, see
+    /// [`crate::config::CommandLimits::max_errors`].
+    pub(crate) errors: usize,
+    /// The number of failed authentication attempts of the client, see
+    /// [`crate::config::AuthLimits`].
+    pub(crate) auth_failures: usize,
+    /// The number of commands that the client made an error in, see
+    /// [`crate::session::SessionSummary::errors`].
+    pub(crate) total_errors: usize,
+    /// When the commands in the current period of
+    /// [`crate::config::CommandLimits::max_command_rate`] were received.
+    pub(crate) recent_commands: VecDeque<Instant>,
+    /// The delay before the next reply if the client was flagged as suspicious, see
+    /// [`crate::config::Tarpit`].
+    pub(crate) tarpit_delay: Option<Duration>,
+    /// How long the replies to the client were delayed in total since it was flagged.
+    pub(crate) tarpitted_for: Duration,
+    /// The number of mail transactions with the null reverse-path started in the session, see
+    /// [`crate::config::NullSenderPolicy::max_transactions`].
+    pub(crate) null_senders: usize,
+    /// When the session was opened.
+    pub(crate) opened_at: Instant,
+    /// The number of commands that the client sent in the session.
+    pub(crate) commands: usize,
+    /// The number of messages accepted in the session.
+    pub(crate) messages_accepted: usize,
+    /// Records the transcript of the session, if [`ServerConfig::transcripts`] is set.
+    pub(crate) transcript: Option<Arc<Recorder>>,
+    /// Notes what is being replied to and why, for [`crate::ServerHandle::rejections`].
+    pub(crate) audit: Arc<Audit>,
+    /// The values kept by the handler, by their type.
+    values: HashMap<TypeId, Box<dyn Any + Send>>,
+    /// Sends the events of the session, if the consumer is receiving them.
+    pub(crate) events: EventSender,
+    /// The configuration of the server.
+    pub(crate) config: Arc<ServerConfig>,
+    /// The settings of the listener that the session was accepted on, which apply over
+    /// [`Self::config`].
+    pub(crate) overrides: Overrides,
+}
+
+impl SessionContext {
+    /// Create a new [`Self`] for a session that has not yet started a mail transaction, sending its
+    /// rejections through `rejections`.
+    pub(crate) fn new(
+        session: SessionInfo,
+        config: Arc<ServerConfig>,
+        rejections: broadcast::Sender<Rejection>,
+    ) -> Self {
+        Self {
+            audit: Arc::new(Audit::new(&session, rejections)),
+            session,
+            transaction: None,
+            awaiting_data: false,
+            starting_tls: false,
+            extensions: Vec::new(),
+            errors: 0,
+            auth_failures: 0,
+            total_errors: 0,
+            recent_commands: VecDeque::new(),
+            tarpit_delay: None,
+            tarpitted_for: Duration::ZERO,
+            null_senders: 0,
+            opened_at: Instant::now(),
+            commands: 0,
+            messages_accepted: 0,
+            transcript: config
+                .transcripts()
+                .is_some()
+                .then(|| Arc::new(Recorder::new())),
+            values: HashMap::new(),
+            events: EventSender::default(),
+            config,
+            overrides: Overrides::default(),
+        }
+    }
+
+    /// Get the details of the session, such as the address of the client.
+    ///
+    /// See [`SessionInfo`].
+    #[must_use]
+    pub const fn session(&self) -> &SessionInfo {
+        &self.session
+    }
+
+    /// Get the configuration of the server that the session is on.
+    ///
+    /// See [`ServerConfig`].
+    #[must_use]
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Get how far the session has progressed.
+    ///
+    /// See [`Phase`].
+    #[must_use]
+    pub fn phase(&self) -> Phase {
+        match &self.transaction {
+            _ if self.awaiting_data => Phase::Data,
+            Some(transaction) if transaction.envelope.accepted().next().is_some() => {
+                Phase::Recipient
+            }
+            Some(_) => Phase::Mail,
+            None if self.session.helo.is_some() => Phase::Greeted,
+            None => Phase::Connected,
+        }
+    }
+
+    /// Get the envelope of the mail transaction in progress, holding every recipient given so far.
+    ///
+    /// Returns `None` if there is no mail transaction in progress, including while the handler is
+    /// given a received message.
+    #[must_use]
+    pub fn envelope(&self) -> Option<&Envelope> {
+        self.transaction
+            .as_ref()
+            .map(|transaction| &transaction.envelope)
+    }
+
+    /// Get the transcript of the session so far, if [`ServerConfig::transcripts`] is set.
+    ///
+    /// Credentials sent for `AUTH` are redacted.
+    #[must_use]
+    pub fn transcript(&self) -> Option<Transcript> {
+        self.transcript
+            .as_ref()
+            .map(|recorder| recorder.transcript())
+    }
+
+    /// Get the service extensions that were advertised to the client in reply to `EHLO`, by their
+    /// keywords.
+    ///
+    /// This is empty if the client greeted the server with `HELO`.
+    #[must_use]
+    pub fn extensions(&self) -> &[AsciiString] {
+        &self.extensions
+    }
+
+    /// Treat the client as authenticated as `user` for the rest of the session, such as when it
+    /// gave a trusted certificate, see [`crate::session::TlsInfo::peer_certificates`].
+    ///
+    /// The identity is kept in [`SessionInfo::authenticated_user`], so it is carried by every
+    /// message received afterwards.
+    pub fn authenticate(&mut self, user: impl Into<String>) {
+        self.session.authenticated_user = Some(user.into());
+    }
+
+    /// Flag the client as suspicious, such as when it is listed in a DNS blocklist, so that every
+    /// reply for the rest of the session is delayed according to [`ServerConfig::tarpit`].
+    ///
+    /// Flagging a client that is already flagged does nothing.
+    pub fn tarpit(&mut self) {
+        if self.tarpit_delay.is_none() {
+            self.tarpit_delay = Some(self.config.tarpit().initial_delay());
+        }
+    }
+
+    /// Get whether the client was flagged as suspicious with [`Self::tarpit`].
+    #[must_use]
+    pub const fn is_tarpitted(&self) -> bool {
+        self.tarpit_delay.is_some()
+    }
+
+    /// Keep a value for the rest of the session, returning the value of the same type that was
+    /// kept before, if any.
+    pub fn insert<T: Send + 'static>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    /// Get the value of type `T` that was kept, if any.
+    #[must_use]
+    pub fn get<T: Send + 'static>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// Get the value of type `T` that was kept, if any, to modify it.
+    #[must_use]
+    pub fn get_mut<T: Send + 'static>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    /// Stop keeping the value of type `T`, returning it if there was one.
+    pub fn remove<T: Send + 'static>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())?
+            .downcast()
+            .ok()
+            .map(|value| *value)
+    }
+
+    /// End the mail transaction in progress with its data, creating a [`Message`] out of it.
+    ///
+    /// Returns `None` if there is no mail transaction in progress.
+    pub(crate) fn finish_transaction(
+        &mut self,
+        data: Bytes,
+        size: Size,
+        hash: ContentHash,
+    ) -> Option<Message> {
+        let transaction = self.transaction.take()?;
+
+        Some(
+            Message::new(
+                transaction.envelope,
+                self.session.clone(),
+                transaction.started_at,
+                data,
+            )
+            .with_size(size)
+            .with_hash(hash),
+        )
+    }
+}
+
+impl Debug for SessionContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionContext")
+            .field("session", &self.session)
+            .field("transaction", &self.transaction)
+            .field("awaiting_data", &self.awaiting_data)
+            .field("starting_tls", &self.starting_tls)
+            .field("extensions", &self.extensions)
+            .field("errors", &self.errors)
+            .field("auth_failures", &self.auth_failures)
+            .field("total_errors", &self.total_errors)
+            .field("recent_commands", &self.recent_commands.len())
+            .field("tarpit_delay", &self.tarpit_delay)
+            .field("tarpitted_for", &self.tarpitted_for)
+            .field("null_senders", &self.null_senders)
+            .field("opened_at", &self.opened_at)
+            .field("commands", &self.commands)
+            .field("messages_accepted", &self.messages_accepted)
+            .field("transcript", &self.transcript.is_some())
+            .field("audit", &self.audit)
+            .field("values", &self.values.len())
+            .field("events", &self.events)
+            .field("config", &self.config)
+            .field("overrides", &self.overrides)
+            .finish()
+    }
+}
+
+/// How far an SMTP session has progressed.
+///
+/// See [RFC 5321 section 3](https://www.rfc-editor.org/rfc/rfc5321.html#section-3).
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum Phase {
+    /// The client has not yet greeted the server with `HELO` or `EHLO`.
+    Connected,
+    /// The client has greeted the server, but has not started a mail transaction.
+    Greeted,
+    /// The client has started a mail transaction with `MAIL FROM`, but no recipient has been
+    /// accepted yet.
+    Mail,
+    /// At least one recipient has been accepted in the mail transaction.
+    Recipient,
+    /// The server is receiving the data of the mail transaction after `DATA`.
+    Data,
+}
@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The errors that an [`super::SmtpHandler`] can fail with.
+//!
+//! See [`HandlerError`].
+
+use std::{error::Error, fmt::Display};
+
+use super::Decision;
+
+/// What an [`super::SmtpHandler`] returns: its [`Decision`], or the error that kept it from
+/// deciding.
+pub type HandlerResult = Result<Decision, HandlerError>;
+
+/// An error that kept an [`super::SmtpHandler`] from deciding, such as a database being unavailable.
+///
+/// The client is told `451 4.3.0 Requested action aborted: local error` and may try again later,
+/// so nothing that the client asked for is lost. The session is kept open unless
+/// [`Self::close_session`] was called, in which case the client is told that the service is not
+/// available and the session is closed.
+///
+/// Any [`Error`] converts into [`Self`], so handlers can use `?`.
+#[derive(Debug)]
+pub struct HandlerError {
+    /// The error that the handler failed with.
+    source: Box<dyn Error + Send + Sync>,
+    /// Whether the session should be closed after telling the client about the error.
+    closes_session: bool,
+}
+
+impl HandlerError {
+    /// Create a new [`Self`] from an error or a message, keeping the session open.
+    pub fn new(source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        Self {
+            source: source.into(),
+            closes_session: false,
+        }
+    }
+
+    /// Close the session after telling the client about the error, such as for errors that later
+    /// commands in the same session would run into again.
+    #[must_use]
+    pub const fn close_session(mut self) -> Self {
+        self.closes_session = true;
+        self
+    }
+
+    /// Get whether the session will be closed after telling the client about the error.
+    #[must_use]
+    pub const fn closes_session(&self) -> bool {
+        self.closes_session
+    }
+
+    /// Get the error that the handler failed with.
+    #[must_use]
+    pub fn inner(&self) -> &(dyn Error + Send + Sync + 'static) {
+        self.source.as_ref()
+    }
+
+    /// Consume [`Self`] to get the error that the handler failed with.
+    #[must_use]
+    pub fn into_inner(self) -> Box<dyn Error + Send + Sync> {
+        self.source
+    }
+}
+
+impl<E: Error + Send + Sync + 'static> From<E> for HandlerError {
+    fn from(source: E) -> Self {
+        Self::new(source)
+    }
+}
+
+impl Display for HandlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "handler error: {}", self.source)
+    }
+}
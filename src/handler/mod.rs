@@ -0,0 +1,384 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The policy decisions made by the consumer during SMTP sessions.
+//!
+//! See [`SmtpHandler`].
+
+use std::{future::Future, net::SocketAddr, time::Duration};
+
+use ascii::AsciiStr;
+use tokio::sync::mpsc;
+
+use crate::{
+    message::{
+        envelope::{Envelope, Recipient},
+        pending::PendingMessage,
+    },
+    session::SessionInfo,
+    Message,
+};
+
+mod context;
+mod error;
+mod response;
+#[cfg(test)]
+mod test;
+
+pub use crate::connection::Command;
+pub use context::{Phase, SessionContext};
+pub use error::{HandlerError, HandlerResult};
+pub use response::{Defer, EnhancedCode, Refuse, Response, ResponseError};
+
+/// Decides whether to accept what a client asks for during an SMTP session.
+///
+/// Every method is called at the point in the session where the server would reply to the client,
+/// and the reply depends on the returned [`Decision`]. [`Decision::Accept`] and
+/// [`Decision::Reject`] send the server's usual replies, [`Decision::Defer`] tells the client to try
+/// again later, and [`Decision::Reply`] sends the consumer's own. Every method accepts by default,
+/// so a consumer only needs to implement the decisions that it cares about.
+///
+/// Every method can also fail with a [`HandlerError`], such as when a database that the decision
+/// depends on is unavailable, which tells the client to try again later.
+///
+/// Every method is given the [`SessionContext`] of the session, which holds the state of the
+/// session and can keep values for the handler between its methods.
+///
+/// Every session gets its own handler, created by the [`HandlerFactory`] given to [`crate::listen`]
+/// with the [`SessionInfo`] of the new session. A handler can keep state about its session
+/// in itself, and only needs to share state that is about more than one session.
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::{
+/// #     handler::{Decision, HandlerResult, SessionContext, SmtpHandler},
+/// #     message::envelope::Recipient,
+/// # };
+/// #
+/// /// Only accepts mail for one domain.
+/// struct OneDomain;
+///
+/// impl SmtpHandler for OneDomain {
+///     async fn on_rcpt(
+///         &mut self,
+///         _: &mut SessionContext,
+///         recipient: &Recipient,
+///     ) -> HandlerResult {
+///         if recipient.forward_path().as_str().ends_with("@example.com") {
+///             Ok(Decision::Accept)
+///         } else {
+///             Ok(Decision::Reject)
+///         }
+///     }
+/// }
+/// ```
+pub trait SmtpHandler: Send + 'static {
+    /// Observe a command from the client before it is acted on, deciding whether to act on it.
+    ///
+    /// This is called for every command that can be parsed, including unrecognized ones, which
+    /// makes it the place for policy that applies to every command, such as rate limiting or audit
+    /// logging. Anything other than [`Decision::Accept`] is replied with in place of the command,
+    /// which is otherwise ignored.
+    ///
+    /// As the server does not implement `AUTH`, a handler can implement it here. Replying with
+    /// `535` counts as a failed authentication attempt, which [`crate::config::AuthLimits`] limits.
+    fn on_command(
+        &mut self,
+        _context: &mut SessionContext,
+        _command: &Command<'_>,
+    ) -> impl Future<Output = HandlerResult> + Send {
+        async { Ok(Decision::Accept) }
+    }
+
+    /// Decide whether to accept the identity that the client gave in `HELO` or `EHLO`.
+    ///
+    /// The client cannot start a mail transaction until this accepts.
+    fn on_ehlo(
+        &mut self,
+        _context: &mut SessionContext,
+        _identity: &AsciiStr,
+    ) -> impl Future<Output = HandlerResult> + Send {
+        async { Ok(Decision::Accept) }
+    }
+
+    /// Decide whether to start a mail transaction for the reverse-path and parameters given in
+    /// `MAIL FROM`.
+    ///
+    /// `envelope` does not have any recipients yet.
+    fn on_mail(
+        &mut self,
+        _context: &mut SessionContext,
+        _envelope: &Envelope,
+    ) -> impl Future<Output = HandlerResult> + Send {
+        async { Ok(Decision::Accept) }
+    }
+
+    /// Decide whether to accept a recipient given in `RCPT TO`.
+    ///
+    /// The envelope in `context` holds the recipients given so far, not including `recipient`. This
+    /// is not called for recipients past the recipient limit, which are always deferred.
+    ///
+    /// The client is replied to for each recipient, so a recipient can be refused with a specific
+    /// reason (see [`Refuse`]) before the client sends the data. The recipient is recorded on the
+    /// envelope with a [`crate::message::envelope::RecipientStatus`] according to the decision,
+    /// where [`Decision::Defer`] or a [`Decision::Reply`] with a transient failure defers it.
+    fn on_rcpt(
+        &mut self,
+        _context: &mut SessionContext,
+        _recipient: &Recipient,
+    ) -> impl Future<Output = HandlerResult> + Send {
+        async { Ok(Decision::Accept) }
+    }
+
+    /// Decide whether to accept the data of the mail transaction when the client sends `DATA`.
+    ///
+    /// This is only called when at least one recipient was accepted. A [`Decision::Reply`] that
+    /// accepts should use the `354` reply code.
+    fn on_data_start(
+        &mut self,
+        _context: &mut SessionContext,
+    ) -> impl Future<Output = HandlerResult> + Send {
+        async { Ok(Decision::Accept) }
+    }
+
+    /// Take a received message, deciding whether to tell the client that it was accepted.
+    ///
+    /// This is called after the end of the data is received but before the client is replied to,
+    /// which makes it the place to inspect the content of the message, such as to scan it for spam
+    /// or viruses. The client waits for the reply for as long as this takes, up to
+    /// [`crate::timeouts::Timeouts::data_termination`], after which it is told to try again later.
+    ///
+    /// Accepting tells the client that the server has taken responsibility for delivering the
+    /// message, so this should only accept once the message is stored durably or handed on.
+    /// Otherwise, the message can be rejected (such as with [`Refuse::PolicyViolation`]) or
+    /// deferred (such as with [`Defer::LocalError`]) for the client to try again later.
+    ///
+    /// The header fields of the message can be edited before it is handed on, such as to add
+    /// routing headers, see [`Message::add_header`].
+    ///
+    /// This is not called for messages received through [`crate::listen_streaming`], which are
+    /// accepted or rejected through [`crate::StreamingMessage`] instead.
+    fn on_message(
+        &mut self,
+        _context: &mut SessionContext,
+        _message: Message,
+    ) -> impl Future<Output = HandlerResult> + Send {
+        async { Ok(Decision::Accept) }
+    }
+
+    /// Observe the end of the session, such as to retrieve its transcript with
+    /// [`SessionContext::transcript`].
+    ///
+    /// This is called once the session closes, after any `221` or `421` reply is sent, but not if
+    /// the connection fails.
+    fn on_close(&mut self, _context: &mut SessionContext) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+/// Creates an [`SmtpHandler`] for each session, and decides whether to start a session at all.
+///
+/// This is implemented for every function or closure that creates a handler from the
+/// [`SessionInfo`] of a new session, which accepts every connection.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::net::SocketAddr;
+/// # use smtp_gateway::{
+/// #     handler::{AcceptAll, ConnectDecision, HandlerFactory},
+/// #     session::SessionInfo,
+/// # };
+/// #
+/// /// Refuses connections from outside of the local machine.
+/// struct LocalOnly;
+///
+/// impl HandlerFactory for LocalOnly {
+///     type Handler = AcceptAll;
+///
+///     async fn on_connect(&self, peer: SocketAddr) -> ConnectDecision {
+///         if peer.ip().is_loopback() {
+///             ConnectDecision::Accept
+///         } else {
+///             ConnectDecision::Reject
+///         }
+///     }
+///
+///     fn create(&self, _: SessionInfo) -> AcceptAll {
+///         AcceptAll
+///     }
+/// }
+/// ```
+pub trait HandlerFactory: Send + Sync + 'static {
+    /// The handler created for each session.
+    type Handler: SmtpHandler;
+
+    /// Decide what to do with a new connection from `peer`, before a session is started for it.
+    ///
+    /// This is called in the task of each connection as it starts, so a slow decision only holds
+    /// up the connection that it is for.
+    fn on_connect(&self, _peer: SocketAddr) -> impl Future<Output = ConnectDecision> + Send {
+        async { ConnectDecision::Accept }
+    }
+
+    /// Create the handler for a new session.
+    fn create(&self, session: SessionInfo) -> Self::Handler;
+}
+
+impl<H, F> HandlerFactory for F
+where
+    H: SmtpHandler,
+    F: Fn(SessionInfo) -> H + Send + Sync + 'static,
+{
+    type Handler = H;
+
+    fn create(&self, session: SessionInfo) -> H {
+        self(session)
+    }
+}
+
+/// What to do with a new connection, before a session is started for it.
+///
+/// See [`HandlerFactory::on_connect`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ConnectDecision {
+    /// Start a session for the connection.
+    Accept,
+    /// Close the connection without replying.
+    Drop,
+    /// Reply with `554` in place of the greeting and close the connection.
+    ///
+    /// See [RFC 5321 section 3.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-3.1).
+    Reject,
+    /// Start a session for the connection, but wait before greeting the client.
+    ///
+    /// Clients that send commands before the greeting are likely to be spammers.
+    Tarpit(Duration),
+}
+
+/// Whether to accept what the client asked for.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Decision {
+    /// Reply to the client with the server's usual success reply.
+    Accept,
+    /// Reply to the client with the server's usual permanent failure reply.
+    Reject,
+    /// Reply to the client with a transient failure, so that it tries again later.
+    ///
+    /// [`Defer::ServiceUnavailable`] also closes the session.
+    Defer(Defer),
+    /// Reply to the client with a specific reply, which accepts unless it is a failure.
+    ///
+    /// See [`Response::is_failure`].
+    Reply(Response),
+}
+
+impl Decision {
+    /// Whether the client's request is accepted.
+    #[must_use]
+    pub const fn is_accepted(&self) -> bool {
+        match self {
+            Self::Accept => true,
+            Self::Reject | Self::Defer(_) => false,
+            Self::Reply(response) => !response.is_failure(),
+        }
+    }
+}
+
+impl From<Defer> for Decision {
+    fn from(defer: Defer) -> Self {
+        Self::Defer(defer)
+    }
+}
+
+impl From<Refuse> for Decision {
+    fn from(refuse: Refuse) -> Self {
+        Self::Reply(refuse.response())
+    }
+}
+
+impl From<Response> for Decision {
+    fn from(response: Response) -> Self {
+        Self::Reply(response)
+    }
+}
+
+/// An [`SmtpHandler`] that accepts everything and discards every received message.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct AcceptAll;
+
+impl SmtpHandler for AcceptAll {}
+
+/// An [`SmtpHandler`] that accepts everything and sends every received message through a channel.
+///
+/// See [`crate::listen_channel`].
+#[derive(Debug, Clone)]
+pub(crate) struct Forward {
+    /// Where received messages are sent.
+    messages: mpsc::Sender<Message>,
+}
+
+impl Forward {
+    /// Create a new [`Self`] that sends every received message into `messages`.
+    pub(crate) const fn new(messages: mpsc::Sender<Message>) -> Self {
+        Self { messages }
+    }
+}
+
+impl SmtpHandler for Forward {
+    async fn on_message(&mut self, _: &mut SessionContext, message: Message) -> HandlerResult {
+        match self.messages.send(message).await {
+            Ok(()) => Ok(Decision::Accept),
+            // The consumer is gone, so nobody has taken responsibility for the message.
+            Err(_) => Ok(Decision::Defer(Defer::LocalError)),
+        }
+    }
+}
+
+/// An [`SmtpHandler`] that accepts everything, sends every received message through a channel, and
+/// only accepts each message once the consumer does.
+///
+/// See [`crate::listen_acknowledged`].
+#[derive(Debug, Clone)]
+pub(crate) struct Acknowledge {
+    /// Where received messages are sent.
+    messages: mpsc::Sender<PendingMessage>,
+}
+
+impl Acknowledge {
+    /// Create a new [`Self`] that sends every received message into `messages`.
+    pub(crate) const fn new(messages: mpsc::Sender<PendingMessage>) -> Self {
+        Self { messages }
+    }
+}
+
+impl SmtpHandler for Acknowledge {
+    async fn on_message(&mut self, _: &mut SessionContext, message: Message) -> HandlerResult {
+        let (message, acceptance) = PendingMessage::new(message);
+        if self.messages.send(message).await.is_err() {
+            return Ok(Decision::Defer(Defer::LocalError));
+        }
+
+        match acceptance.await {
+            Ok(true) => Ok(Decision::Accept),
+            Ok(false) => Ok(Decision::Reject),
+            // The consumer dropped the message without taking responsibility for it.
+            Err(_) => Ok(Decision::Defer(Defer::LocalError)),
+        }
+    }
+}
@@ -0,0 +1,512 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Replies that a consumer can send to the client in place of the server's own.
+//!
+//! See [`Response`].
+
+use std::fmt::{Debug, Display};
+
+use crate::str::{max_lengths, SmtpString};
+
+/// A reply to the client, made up of a reply code, an optional enhanced status code, and text.
+///
+/// The reply is validated as it is created, so it is always a single valid reply line.
+///
+/// See [RFC 5321 section 4.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.2) and [RFC
+/// 3463](https://www.rfc-editor.org/rfc/rfc3463.html).
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::handler::Response;
+/// # use std::error::Error;
+/// #
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let response = Response::parse("550 5.7.1 Blocked by policy")?;
+///
+/// assert_eq!(response.code(), 550);
+/// assert_eq!(response.enhanced_code().map(|code| code.to_string()).as_deref(), Some("5.7.1"));
+/// assert!(response.is_permanent_failure());
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Response {
+    /// The three digit reply code.
+    code: u16,
+    /// The enhanced status code, which has the same class as [`Self::code`].
+    enhanced_code: Option<EnhancedCode>,
+    /// The text after the codes, without a line ending.
+    text: SmtpString,
+}
+
+impl Response {
+    /// Create a new [`Self`] from a reply code and text.
+    ///
+    /// # Errors
+    ///
+    /// - [`ResponseError::InvalidCode`] if `code` is not a reply code from `200` to `559`.
+    /// - [`ResponseError::InvalidText`] if `text` contains anything other than printable ASCII,
+    ///   spaces, and tabs.
+    /// - [`ResponseError::TooLong`] if the reply line would be longer than the maximum length of a
+    ///   reply line.
+    pub fn new(code: u16, text: &str) -> Result<Self, ResponseError> {
+        let response = Self {
+            code,
+            enhanced_code: None,
+            text: text_from(text)?,
+        };
+
+        response.validate()
+    }
+
+    /// Add an enhanced status code to [`Self`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ResponseError::MismatchedClass`] if the class of `enhanced_code` is not the same as the
+    ///   first digit of the reply code.
+    /// - [`ResponseError::TooLong`] if the reply line would be longer than the maximum length of a
+    ///   reply line.
+    pub fn with_enhanced_code(
+        mut self,
+        enhanced_code: EnhancedCode,
+    ) -> Result<Self, ResponseError> {
+        self.enhanced_code = Some(enhanced_code);
+
+        self.validate()
+    }
+
+    /// Parse a reply line (without its line ending), such as `"550 5.7.1 Blocked by policy"`.
+    ///
+    /// The enhanced status code is optional, and is recognized if the first word of the text is a
+    /// valid one.
+    ///
+    /// # Errors
+    ///
+    /// - [`ResponseError::InvalidCode`] if the line does not start with a valid reply code.
+    /// - Any error from [`Self::new`] and [`Self::with_enhanced_code`].
+    pub fn parse(line: &str) -> Result<Self, ResponseError> {
+        let (code, text) = line.split_once(' ').unwrap_or((line, ""));
+        if code.len() != 3 {
+            return Err(ResponseError::InvalidCode);
+        }
+        let code = code.parse().map_err(|_| ResponseError::InvalidCode)?;
+
+        let (enhanced_code, rest) = text.split_once(' ').unwrap_or((text, ""));
+        match EnhancedCode::parse(enhanced_code) {
+            Ok(enhanced_code) => Self::new(code, rest)?.with_enhanced_code(enhanced_code),
+            Err(_) => Self::new(code, text),
+        }
+    }
+
+    /// Get the three digit reply code.
+    #[must_use]
+    pub const fn code(&self) -> u16 {
+        self.code
+    }
+
+    /// Get the enhanced status code, if any.
+    #[must_use]
+    pub const fn enhanced_code(&self) -> Option<EnhancedCode> {
+        self.enhanced_code
+    }
+
+    /// Get the text after the codes.
+    #[must_use]
+    pub const fn text(&self) -> &SmtpString {
+        &self.text
+    }
+
+    /// Whether the reply is a failure, either transient or permanent.
+    #[must_use]
+    pub const fn is_failure(&self) -> bool {
+        self.code >= 400
+    }
+
+    /// Whether the reply is a transient failure (`4yz`), which the client may try again later.
+    #[must_use]
+    pub const fn is_transient_failure(&self) -> bool {
+        self.code / 100 == 4
+    }
+
+    /// Whether the reply is a permanent failure (`5yz`), which the client should not try again.
+    #[must_use]
+    pub const fn is_permanent_failure(&self) -> bool {
+        self.code / 100 == 5
+    }
+
+    /// Whether the reply tells the client that the session is being closed (`421`).
+    #[must_use]
+    pub const fn closes_session(&self) -> bool {
+        self.code == 421
+    }
+
+    /// Check that [`Self`] is a valid reply line.
+    fn validate(self) -> Result<Self, ResponseError> {
+        // The first digit is the class and the second is the category, which only go up to 5.
+        //
+        // <https://www.rfc-editor.org/rfc/rfc5321.html#section-4.2.1>
+        if !(200..600).contains(&self.code) || (self.code / 10) % 10 > 5 {
+            return Err(ResponseError::InvalidCode);
+        }
+
+        if let Some(enhanced_code) = self.enhanced_code {
+            if u16::from(enhanced_code.class) != self.code / 100 {
+                return Err(ResponseError::MismatchedClass);
+            }
+        }
+
+        // Including the line ending.
+        if self.to_string().len() + 2 > max_lengths::REPLY_LINE {
+            return Err(ResponseError::TooLong);
+        }
+
+        Ok(self)
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code)?;
+        if let Some(enhanced_code) = self.enhanced_code {
+            write!(f, " {enhanced_code}")?;
+        }
+        if !self.text.as_inner().is_empty() {
+            write!(f, " {}", self.text)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A transient failure, telling the client to try again later, with the usual reply for its
+/// reason.
+///
+/// Use [`Self::with_enhanced_code`] for a different enhanced status code than the usual one.
+///
+/// See [RFC 5321 section 4.2.3](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.2.3).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Defer {
+    /// The service is not available, such as when the server is overloaded, and the session is
+    /// closed (`421 4.3.2`).
+    ServiceUnavailable,
+    /// The mailbox is temporarily unavailable, such as when greylisting (`450 4.2.0`).
+    MailboxUnavailable,
+    /// The request failed because of a local error, such as a database that is down (`451 4.3.0`).
+    LocalError,
+    /// There is not enough storage to accept the request, such as when a queue is full (`452
+    /// 4.3.1`).
+    InsufficientStorage,
+}
+
+impl Defer {
+    /// Get the reply code.
+    #[must_use]
+    pub const fn code(self) -> u16 {
+        match self {
+            Self::ServiceUnavailable => 421,
+            Self::MailboxUnavailable => 450,
+            Self::LocalError => 451,
+            Self::InsufficientStorage => 452,
+        }
+    }
+
+    /// Get the usual enhanced status code.
+    ///
+    /// See [RFC 3463 section 3](https://www.rfc-editor.org/rfc/rfc3463.html#section-3).
+    #[must_use]
+    pub const fn enhanced_code(self) -> EnhancedCode {
+        let (subject, detail) = match self {
+            Self::ServiceUnavailable => (3, 2),
+            Self::MailboxUnavailable => (2, 0),
+            Self::LocalError => (3, 0),
+            Self::InsufficientStorage => (3, 1),
+        };
+
+        EnhancedCode {
+            class: 4,
+            subject,
+            detail,
+        }
+    }
+
+    /// Get the text of the reply.
+    const fn text(self) -> &'static str {
+        match self {
+            Self::ServiceUnavailable => "Service not available, closing transmission channel",
+            Self::MailboxUnavailable => "Requested mail action not taken: mailbox unavailable",
+            Self::LocalError => "Requested action aborted: local error in processing",
+            Self::InsufficientStorage => "Requested action not taken: insufficient system storage",
+        }
+    }
+
+    /// Get the reply, with the usual enhanced status code.
+    #[expect(
+        clippy::missing_panics_doc,
+        reason = "every reply is written in code as a valid reply"
+    )]
+    #[must_use]
+    pub fn response(self) -> Response {
+        self.with_enhanced_code(self.enhanced_code())
+            .expect("written in code as a valid reply")
+    }
+
+    /// Get the reply with a different enhanced status code, such as `4.7.1` for greylisting.
+    ///
+    /// # Errors
+    ///
+    /// - [`ResponseError::MismatchedClass`] if `enhanced_code` is not a transient failure.
+    pub fn with_enhanced_code(
+        self,
+        enhanced_code: EnhancedCode,
+    ) -> Result<Response, ResponseError> {
+        Response::new(self.code(), self.text())?.with_enhanced_code(enhanced_code)
+    }
+}
+
+/// A permanent failure, telling the client not to try again, with the usual reply for its reason.
+///
+/// Use [`Self::with_enhanced_code`] for a different enhanced status code than the usual one.
+///
+/// See [RFC 5321 section 4.2.3](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.2.3).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Refuse {
+    /// The recipient does not exist (`550 5.1.1`).
+    UnknownUser,
+    /// The mailbox of the recipient is full (`552 5.2.2`).
+    MailboxFull,
+    /// The mailbox name is not allowed, such as when it is malformed (`553 5.1.3`).
+    MailboxNotAllowed,
+    /// The request is not allowed by the policy of the server (`550 5.7.1`).
+    PolicyViolation,
+}
+
+impl Refuse {
+    /// Get the reply code.
+    #[must_use]
+    pub const fn code(self) -> u16 {
+        match self {
+            Self::UnknownUser | Self::PolicyViolation => 550,
+            Self::MailboxFull => 552,
+            Self::MailboxNotAllowed => 553,
+        }
+    }
+
+    /// Get the usual enhanced status code.
+    ///
+    /// See [RFC 3463 section 3](https://www.rfc-editor.org/rfc/rfc3463.html#section-3).
+    #[must_use]
+    pub const fn enhanced_code(self) -> EnhancedCode {
+        let (subject, detail) = match self {
+            Self::UnknownUser => (1, 1),
+            Self::MailboxFull => (2, 2),
+            Self::MailboxNotAllowed => (1, 3),
+            Self::PolicyViolation => (7, 1),
+        };
+
+        EnhancedCode {
+            class: 5,
+            subject,
+            detail,
+        }
+    }
+
+    /// Get the text of the reply.
+    const fn text(self) -> &'static str {
+        match self {
+            Self::UnknownUser => "Requested action not taken: no such user",
+            Self::MailboxFull => "Requested mail action aborted: exceeded storage allocation",
+            Self::MailboxNotAllowed => "Requested action not taken: mailbox name not allowed",
+            Self::PolicyViolation => "Requested action not taken: rejected by policy",
+        }
+    }
+
+    /// Get the reply, with the usual enhanced status code.
+    #[expect(
+        clippy::missing_panics_doc,
+        reason = "every reply is written in code as a valid reply"
+    )]
+    #[must_use]
+    pub fn response(self) -> Response {
+        self.with_enhanced_code(self.enhanced_code())
+            .expect("written in code as a valid reply")
+    }
+
+    /// Get the reply with a different enhanced status code.
+    ///
+    /// # Errors
+    ///
+    /// - [`ResponseError::MismatchedClass`] if `enhanced_code` is not a permanent failure.
+    pub fn with_enhanced_code(
+        self,
+        enhanced_code: EnhancedCode,
+    ) -> Result<Response, ResponseError> {
+        Response::new(self.code(), self.text())?.with_enhanced_code(enhanced_code)
+    }
+}
+
+/// Validate the text of a reply line, which may only contain printable ASCII, spaces, and tabs.
+///
+/// [RFC 5321 section 4.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.2).
+fn text_from(text: &str) -> Result<SmtpString, ResponseError> {
+    if text
+        .bytes()
+        .any(|byte| !(byte == b'\t' || (b' '..=b'~').contains(&byte)))
+    {
+        return Err(ResponseError::InvalidText);
+    }
+
+    SmtpString::new(text).map_err(|_| ResponseError::InvalidText)
+}
+
+/// An enhanced status code, such as `5.7.1`, which gives more detail than a reply code.
+///
+/// See [RFC 3463 section 2](https://www.rfc-editor.org/rfc/rfc3463.html#section-2).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct EnhancedCode {
+    /// Whether the status is success (`2`), a transient failure (`4`), or a permanent failure
+    /// (`5`).
+    class: u8,
+    /// The broad category of the status.
+    subject: u16,
+    /// The specific status within [`Self::subject`].
+    detail: u16,
+}
+
+impl EnhancedCode {
+    /// Create a new [`Self`] from its three parts.
+    ///
+    /// # Errors
+    ///
+    /// - [`ResponseError::InvalidEnhancedCode`] if `class` is not `2`, `4`, or `5`, or if `subject`
+    ///   or `detail` are more than three digits long.
+    pub const fn new(class: u8, subject: u16, detail: u16) -> Result<Self, ResponseError> {
+        if !matches!(class, 2 | 4 | 5) || subject > 999 || detail > 999 {
+            return Err(ResponseError::InvalidEnhancedCode);
+        }
+
+        Ok(Self {
+            class,
+            subject,
+            detail,
+        })
+    }
+
+    /// Parse a [`Self`] out of its textual form, such as `"5.7.1"`.
+    ///
+    /// # Errors
+    ///
+    /// - [`ResponseError::InvalidEnhancedCode`] if `code` is not three numbers separated by
+    ///   periods, or if any of them are invalid as described in [`Self::new`].
+    pub fn parse(code: &str) -> Result<Self, ResponseError> {
+        /// Parse one part of the code, which is one to three digits.
+        fn number<T: std::str::FromStr>(part: Option<&str>) -> Result<T, ResponseError> {
+            part.filter(|part| {
+                (1..=3).contains(&part.len()) && part.bytes().all(|b| b.is_ascii_digit())
+            })
+            .and_then(|part| part.parse().ok())
+            .ok_or(ResponseError::InvalidEnhancedCode)
+        }
+
+        let mut parts = code.split('.');
+        let code = Self::new(
+            number(parts.next())?,
+            number(parts.next())?,
+            number(parts.next())?,
+        )?;
+
+        if parts.next().is_some() {
+            return Err(ResponseError::InvalidEnhancedCode);
+        }
+
+        Ok(code)
+    }
+
+    /// Get whether the status is success (`2`), a transient failure (`4`), or a permanent failure
+    /// (`5`).
+    #[must_use]
+    pub const fn class(&self) -> u8 {
+        self.class
+    }
+
+    /// Get the broad category of the status.
+    #[must_use]
+    pub const fn subject(&self) -> u16 {
+        self.subject
+    }
+
+    /// Get the specific status within [`Self::subject`].
+    #[must_use]
+    pub const fn detail(&self) -> u16 {
+        self.detail
+    }
+}
+
+impl Display for EnhancedCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.class, self.subject, self.detail)
+    }
+}
+
+/// Possible error states encountered when trying to create a [`Response`] or [`EnhancedCode`].
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub enum ResponseError {
+    /// The reply code is not three digits from `200` to `559`.
+    InvalidCode,
+    /// The enhanced status code is not valid.
+    InvalidEnhancedCode,
+    /// The class of the enhanced status code does not match the reply code.
+    MismatchedClass,
+    /// The text contains characters not allowed in a reply, such as line breaks.
+    InvalidText,
+    /// The reply line is longer than the maximum length of a reply line.
+    TooLong,
+}
+
+impl Display for ResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::InvalidCode => "invalid reply code",
+            Self::InvalidEnhancedCode => "invalid enhanced status code",
+            Self::MismatchedClass => "enhanced status code does not match the reply code",
+            Self::InvalidText => "invalid character in reply text",
+            Self::TooLong => "reply line is too long",
+        })
+    }
+}
+
+impl Debug for ResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self} at {} {}", file!(), line!())
+    }
+}
+
+impl std::error::Error for ResponseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+
+    fn description(&self) -> &'static str {
+        "description() is deprecated; use Display"
+    }
+
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        self.source()
+    }
+}
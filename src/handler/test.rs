@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use ascii::{AsciiString, IntoAsciiString};
+use tokio::sync::broadcast;
+
+use super::*;
+use crate::{
+    connection::Transaction, message::envelope::RecipientStatus, session::SessionInfo, ServerConfig,
+};
+
+type Result = std::result::Result<(), Box<dyn std::error::Error>>;
+
+#[test]
+fn test_response() -> Result {
+    let response = Response::parse("550 5.7.1 Blocked by policy")?;
+    assert_eq!(response.code(), 550);
+    assert_eq!(response.enhanced_code(), Some(EnhancedCode::new(5, 7, 1)?));
+    assert_eq!(response.text().to_string(), "Blocked by policy");
+    assert_eq!(response.to_string(), "550 5.7.1 Blocked by policy");
+    assert!(response.is_permanent_failure());
+    assert!(!Decision::from(response).is_accepted());
+
+    // The enhanced status code is optional.
+    let response = Response::parse("451 Try again later")?;
+    assert_eq!(response.enhanced_code(), None);
+    assert_eq!(response.text().to_string(), "Try again later");
+    assert!(response.is_transient_failure());
+
+    let response =
+        Response::new(250, "Queued")?.with_enhanced_code(EnhancedCode::parse("2.0.0")?)?;
+    assert_eq!(response.to_string(), "250 2.0.0 Queued");
+    assert!(Decision::Reply(response).is_accepted());
+    assert_eq!(Response::new(250, "")?.to_string(), "250");
+
+    assert_eq!(Response::new(199, "OK"), Err(ResponseError::InvalidCode));
+    assert_eq!(Response::new(600, "OK"), Err(ResponseError::InvalidCode));
+    assert_eq!(Response::new(560, "OK"), Err(ResponseError::InvalidCode));
+    assert_eq!(Response::parse("5500 OK"), Err(ResponseError::InvalidCode));
+    assert_eq!(
+        Response::new(550, "two\r\nlines"),
+        Err(ResponseError::InvalidText)
+    );
+    assert_eq!(Response::new(550, "🦀"), Err(ResponseError::InvalidText));
+    assert_eq!(
+        Response::new(550, &"a".repeat(507)),
+        Err(ResponseError::TooLong)
+    );
+    assert!(Response::new(550, &"a".repeat(506)).is_ok());
+    assert_eq!(
+        Response::parse("550 4.7.1 Blocked by policy"),
+        Err(ResponseError::MismatchedClass)
+    );
+
+    assert_eq!(
+        EnhancedCode::new(3, 0, 0),
+        Err(ResponseError::InvalidEnhancedCode)
+    );
+    assert_eq!(
+        EnhancedCode::parse("5.1000.0"),
+        Err(ResponseError::InvalidEnhancedCode)
+    );
+    assert_eq!(
+        EnhancedCode::parse("5.1"),
+        Err(ResponseError::InvalidEnhancedCode)
+    );
+    assert_eq!(
+        EnhancedCode::parse("5.1.1.1"),
+        Err(ResponseError::InvalidEnhancedCode)
+    );
+    assert_eq!(
+        EnhancedCode::parse("5.+1.1"),
+        Err(ResponseError::InvalidEnhancedCode)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_defer() -> Result {
+    assert_eq!(
+        Defer::ServiceUnavailable.response().to_string(),
+        "421 4.3.2 Service not available, closing transmission channel"
+    );
+    assert!(Defer::ServiceUnavailable.response().closes_session());
+    assert_eq!(
+        Defer::LocalError.response().to_string(),
+        "451 4.3.0 Requested action aborted: local error in processing"
+    );
+
+    for defer in [
+        Defer::ServiceUnavailable,
+        Defer::MailboxUnavailable,
+        Defer::LocalError,
+        Defer::InsufficientStorage,
+    ] {
+        assert!(defer.response().is_transient_failure());
+        assert!(!Decision::from(defer).is_accepted());
+    }
+
+    let greylisted = Defer::MailboxUnavailable.with_enhanced_code(EnhancedCode::new(4, 7, 1)?)?;
+    assert_eq!(
+        greylisted.to_string(),
+        "450 4.7.1 Requested mail action not taken: mailbox unavailable"
+    );
+    assert!(!greylisted.closes_session());
+    assert_eq!(
+        Defer::LocalError.with_enhanced_code(EnhancedCode::new(5, 3, 0)?),
+        Err(ResponseError::MismatchedClass)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_refuse() -> Result {
+    assert_eq!(
+        Refuse::UnknownUser.response().to_string(),
+        "550 5.1.1 Requested action not taken: no such user"
+    );
+    assert_eq!(
+        Refuse::MailboxFull.response().to_string(),
+        "552 5.2.2 Requested mail action aborted: exceeded storage allocation"
+    );
+
+    for refuse in [
+        Refuse::UnknownUser,
+        Refuse::MailboxFull,
+        Refuse::MailboxNotAllowed,
+        Refuse::PolicyViolation,
+    ] {
+        assert!(refuse.response().is_permanent_failure());
+        assert!(!Decision::from(refuse).is_accepted());
+    }
+
+    assert_eq!(
+        Refuse::UnknownUser.with_enhanced_code(EnhancedCode::new(4, 1, 1)?),
+        Err(ResponseError::MismatchedClass)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_session_context() {
+    let address = "127.0.0.1:25".parse().unwrap();
+    let mut context = SessionContext::new(
+        SessionInfo::new(address, address),
+        Arc::new(ServerConfig::default()),
+        broadcast::channel(1).0,
+    );
+    assert_eq!(context.phase(), Phase::Connected);
+    assert!(context.envelope().is_none());
+    assert!(context.extensions().is_empty());
+
+    context.session.helo = Some(AsciiString::new());
+    assert_eq!(context.phase(), Phase::Greeted);
+
+    context.transaction = Some(Transaction::new(Envelope::new(None)));
+    assert_eq!(context.phase(), Phase::Mail);
+    assert_eq!(context.envelope().map(Envelope::recipients), Some(&[][..]));
+
+    let forward_path = "jones@example.com".into_ascii_string().unwrap();
+    let mut rejected = Recipient::new(forward_path.clone());
+    rejected.status = RecipientStatus::Rejected;
+    context
+        .transaction
+        .as_mut()
+        .unwrap()
+        .envelope
+        .recipients
+        .push(rejected);
+    assert_eq!(context.phase(), Phase::Mail);
+    context
+        .transaction
+        .as_mut()
+        .unwrap()
+        .envelope
+        .recipients
+        .push(Recipient::new(forward_path));
+    assert_eq!(context.phase(), Phase::Recipient);
+
+    context.awaiting_data = true;
+    assert_eq!(context.phase(), Phase::Data);
+
+    // Values are kept by their type.
+    assert_eq!(context.insert(1_u8), None);
+    assert_eq!(context.insert(2_u8), Some(1));
+    assert_eq!(context.insert("known user"), None);
+    *context.get_mut::<u8>().unwrap() += 1;
+    assert_eq!(context.get::<u8>(), Some(&3));
+    assert_eq!(context.get::<u16>(), None);
+    assert_eq!(context.remove::<&str>(), Some("known user"));
+    assert_eq!(context.get::<&str>(), None);
+}
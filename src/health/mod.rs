@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! A tiny HTTP endpoint that reports the [`Health`] of the servers, for the liveness and readiness
+//! probes of Kubernetes and the health checks of load balancers.
+//!
+//! See [`serve`].
+//!
+//! `GET /live` always replies with `200 OK` while the endpoint runs, and `GET /ready` replies with
+//! `200 OK` while [`Health::is_ready`], or `503 Service Unavailable` otherwise. Both carry the
+//! [`Health`] in their plain text body. Load balancers that only check whether a TCP connection can
+//! be opened can be pointed at the endpoint as well.
+
+#[cfg(test)]
+mod test;
+
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+use crate::{server::Health, ServerHandle};
+
+/// The longest request that is read, in bytes.
+const MAX_REQUEST: u64 = 8 * 1024;
+
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Accept HTTP requests on `listener` and reply to each with the [`Health`] of the servers that
+/// `server` is given to, see [`ServerHandle::health`].
+///
+/// This runs until accepting a connection fails, so it is usually spawned as a task. It keeps
+/// running while the servers shut down, so that readiness probes see them draining.
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`tokio::net::TcpListener::accept`].
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{handler::AcceptAll, ServerConfig, ServerHandle};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let server = ServerHandle::new();
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("0.0.0.0:25").await?,
+///     ServerConfig::default(),
+///     |_| AcceptAll,
+///     server.clone(),
+/// );
+/// tokio::spawn(smtp_gateway::health::serve(
+///     TcpListener::bind("0.0.0.0:8080").await?,
+///     server,
+/// ));
+/// #     Ok(())
+/// # }
+/// ```
+pub async fn serve(listener: TcpListener, server: ServerHandle) -> std::io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let server = server.clone();
+
+        tokio::spawn(async move {
+            // The client is gone either way, so there is no one to report errors to.
+            let _ = tokio::time::timeout(REQUEST_TIMEOUT, respond(stream, &server)).await;
+        });
+    }
+}
+
+/// Read one HTTP request from `stream` and reply to it with the [`Health`] of `server`.
+///
+/// # Errors
+///
+/// - I/O errors from reading the request or writing the reply.
+pub(crate) async fn respond(
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    server: &ServerHandle,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream).take(MAX_REQUEST);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    // The header fields are read and ignored, so that the client is not reset while sending them.
+    let mut line = String::new();
+    while reader.read_line(&mut line).await? > 0 && !matches!(line.as_str(), "\r\n" | "\n") {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_ascii_whitespace();
+    let reply = reply(
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+        &server.health(),
+    );
+
+    let stream = reader.get_mut().get_mut();
+    stream.write_all(reply.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Get the HTTP response to a request for `path` with `method`, given the `health` of the servers.
+pub(crate) fn reply(method: &str, path: &str, health: &Health) -> String {
+    let (status, body) = match (method, path) {
+        ("GET" | "HEAD", "/live") => ("200 OK", health.to_string()),
+        ("GET" | "HEAD", "/ready") if health.is_ready() => ("200 OK", health.to_string()),
+        ("GET" | "HEAD", "/ready") => ("503 Service Unavailable", health.to_string()),
+        ("GET" | "HEAD", _) => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+
+    let mut reply = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    if method != "HEAD" {
+        reply.push_str(&body);
+    }
+
+    reply
+}
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::*;
+
+#[test]
+fn test_reply() {
+    let server = ServerHandle::new();
+
+    // Tests that a server is live but not ready before it listens.
+    let health = server.health();
+    let live = reply("GET", "/live", &health);
+    assert!(live.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(live.ends_with("\r\n\r\nlistener: stopped\nactive_sessions: 0\nbacklog: 0\n"));
+    assert!(reply("GET", "/ready", &health).starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+    // Tests that it is ready while it listens.
+    let listening = server.listening();
+    let ready = reply("HEAD", "/ready", &server.health());
+    assert!(ready.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(ready.contains("Content-Length: 50\r\n"));
+    assert!(ready.ends_with("\r\n\r\n"));
+    drop(listening);
+
+    assert!(reply("GET", "/", &health).starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert!(reply("POST", "/ready", &health).starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+}
+
+#[tokio::test]
+async fn test_respond() -> std::io::Result<()> {
+    let server = ServerHandle::new();
+    let (mut client, stream) = tokio::io::duplex(1024);
+
+    client
+        .write_all(b"GET /live HTTP/1.1\r\nHost: localhost\r\nUser-Agent: kube-probe/1.30\r\n\r\n")
+        .await?;
+    respond(stream, &server).await?;
+
+    let mut response = String::new();
+    client.read_to_string(&mut response).await?;
+    assert_eq!(response, reply("GET", "/live", &server.health()));
+
+    Ok(())
+}
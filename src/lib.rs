@@ -22,8 +22,9 @@
 //! # How It Works
 //!
 //! [`crate::listen`] accepts any incoming TCP connection and spawns a new task to handle it as an
-//! SMTP session. When an SMTP session finishes with a received message, it is passed to the
-//! consumer to handle.
+//! SMTP session. Every decision about what to accept during a session is made by the consumer's
+//! [`SmtpHandler`], and when an SMTP session finishes with a received message, it is passed to the
+//! handler.
 //!
 //! smtp_gateway accepts messages but it cannot send or relay messages. An SMTP gateway receives
 //! messages in SMTP and transform them for retransmission. smtp_gateway exists to handle the first
@@ -34,6 +35,67 @@
 //!
 #![doc = concat!('<', env!("CARGO_PKG_REPOSITORY"), '>')]
 //!
+//! # Features
+//!
+//! - `amqp`: publish received messages to RabbitMQ or another AMQP broker, see `AmqpSink` in
+//!   `sink`.
+//! - `arc`: validate the Authenticated Received Chain of messages and seal them, see `arc`.
+//!   Enables `spf`.
+//! - `attachment`: reject messages with attachments of dangerous types before they are handed to
+//!   the handler, see `attachment`. Enables `mime`.
+//! - `clamav`: scan messages for viruses with ClamAV before they are handed to the handler, see
+//!   `clamav`.
+//! - `directory`: reject recipients that are not in a directory of addresses, and answer `VRFY`
+//!   from it, see `directory`.
+//! - `dmarc`: apply the DMARC policies of the domains that messages claim to be from, see
+//!   `dmarc`. Enables `spf`, and verifies DKIM signatures for it.
+//! - `dnsbl`: look up the address of each client in DNS blocklists when it connects, see `dnsbl`.
+//! - `encoding`: decode text in every common character set, see [`message::decode::charset`].
+//! - `filter`: check messages against rules that match their headers, body, and envelope before
+//!   they are handed to the handler, see `filter`.
+//! - `greylist`: defer mail from senders that have not been seen before until they try again, see
+//!   `greylist`.
+//! - `health`: serve the health of the server over HTTP for liveness and readiness probes, see
+//!   `health`.
+//! - `hickory`: make every kind of DNS lookup with `hickory-resolver`, see `HickoryResolver` in
+//!   [`resolver`].
+//! - `json`: serialize messages as JSON with `Message::to_json`, see [`message::export`], and log
+//!   one JSON record per session, see `LogFormat::Json` in [`config`].
+//! - `kafka`: produce received messages to Apache Kafka, see `KafkaSink` in `sink`.
+//! - `ldap`: look up recipients in an LDAP directory, see `LdapVerifier` in `directory`. Enables
+//!   `directory`.
+//! - `lettre`: convert a [`Message`] into a `lettre` envelope, see [`message::convert`].
+//! - `lmtp`: re-deliver received messages to an LMTP server such as Dovecot, see `LmtpSink` in
+//!   `sink`.
+//! - `mail-parser`: convert a [`Message`] into a `mail-parser` message, see
+//!   [`message::convert`].
+//! - `metrics`: record counters and histograms of connections, commands, replies, and messages
+//!   through the `metrics` facade for Prometheus or another recorder, see `metrics`.
+//! - `mime`: parse the MIME structure of messages with `Message::parts`, `Message::attachments`,
+//!   and `Message::text_body`.
+//! - `nats`: publish received messages to NATS JetStream, see `NatsSink` in `sink`.
+//! - `redis`: keep greylisting triplets and rate limit counters in Redis, see `RedisStore` in
+//!   `greylist`, and queue received messages in it, see `RedisSink` in `sink`. Enables
+//!   `greylist`.
+//! - `rspamd`: scan messages for spam with Rspamd before they are handed to the handler, see
+//!   `rspamd`.
+//! - `s3`: upload received messages to an S3-compatible bucket as their data is received, see
+//!   `S3Sink` in `sink`.
+//! - `serde`: implement `Serialize` and `Deserialize` for [`Message`] and the types it is made of,
+//!   and for [`ServerConfig`] to load it from configuration files.
+//! - `spamassassin`: score messages for spam with SpamAssassin before they are handed to the
+//!   handler, see `spamassassin`.
+//! - `spf`: check whether clients are allowed to send mail for the domains they give in `HELO`
+//!   and `MAIL FROM` with SPF, see `spf`.
+//! - `spool`: keep every accepted message on disk until the consumer has processed it, see
+//!   `spool`.
+//! - `sql`: look up recipients in an SQL database, see `SqlVerifier` in `directory`. Enables
+//!   `directory`.
+//! - `sqlite`: store received messages in SQLite and look them up again, see `SqliteStore` in
+//!   `sink`.
+//! - `tls`: offer `STARTTLS` with `rustls`, optionally verifying client certificates, see `tls`.
+//! - `webhook`: post received messages to an HTTP endpoint, see `WebhookSink` in `sink`.
+//!
 //! # Terminology
 //!
 //! smtp_gateway uses specific terminology (such as "client" and "server") as defined by [RFC 5321
@@ -43,37 +105,560 @@
 #![warn(clippy::nursery, clippy::pedantic)]
 #![cfg_attr(debug_assertions, allow(clippy::missing_errors_doc))]
 
-use std::io::Result;
+use std::{future::Future, io::Result, net::SocketAddr, sync::Arc};
 
-use async_stream::try_stream;
+use async_stream::{stream, try_stream};
+use audit::RejectionSource;
+use config::{Access, ConfigHandle, ConnectionOverflow};
+use connection::Delivery;
 use futures_core::stream::Stream;
-use tokio::{net::TcpListener, task::JoinHandle};
+use futures_util::StreamExt;
+use listener::Overrides;
+use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle};
 
+#[cfg(feature = "arc")]
+pub mod arc;
+#[cfg(feature = "attachment")]
+pub mod attachment;
+pub mod audit;
+#[cfg(feature = "clamav")]
+pub mod clamav;
+pub mod config;
 mod connection;
-mod message;
+#[cfg(feature = "directory")]
+pub mod directory;
+#[cfg(any(feature = "arc", feature = "dmarc"))]
+mod dkim;
+#[cfg(feature = "dmarc")]
+pub mod dmarc;
+#[cfg(feature = "dnsbl")]
+pub mod dnsbl;
+pub mod error;
+pub mod event;
+mod file;
+#[cfg(feature = "filter")]
+pub mod filter;
+#[cfg(feature = "greylist")]
+pub mod greylist;
+pub mod handler;
+#[cfg(feature = "health")]
+pub mod health;
+pub mod listener;
+pub mod message;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod peers;
+mod permits;
+mod redact;
+pub mod resolver;
+#[cfg(feature = "rspamd")]
+pub mod rspamd;
+pub mod server;
+pub mod session;
+pub mod shutdown;
+pub mod sink;
+#[cfg(feature = "spamassassin")]
+pub mod spamassassin;
+#[cfg(feature = "spf")]
+pub mod spf;
+#[cfg(feature = "spool")]
+pub mod spool;
+pub mod stats;
 pub mod str;
 #[cfg(test)]
 mod test;
+#[cfg(any(feature = "greylist", feature = "spool"))]
+mod time;
 pub mod timeouts;
-pub use message::Message;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transcript;
+pub use config::ServerConfig;
+pub use error::SmtpError;
+pub use handler::{HandlerFactory, SmtpHandler};
+pub use listener::Listener;
+pub use message::{pending::PendingMessage, stream::StreamingMessage, Message};
+pub use server::ServerHandle;
+pub use shutdown::Shutdown;
+
+/// An SMTP session running in the background, which can be joined for how it ended, see
+/// [`session::SessionSummary`].
+pub type Session = JoinHandle<Result<session::SessionSummary>>;
+
+/// How many messages can be waiting for the consumer before sessions wait to send more.
+const BUFFERED_MESSAGES: usize = 16;
+
+/// Listen on a port for incoming TCP connections and handle them as SMTP sessions, consulting a
+/// handler for every decision and handing it every received message.
+///
+/// Every session follows `config`, see [`ServerConfig`], which can be replaced while the server
+/// runs by giving a [`config::ConfigHandle`]. `factory` decides whether to start a
+/// session for each connection, and creates the handler for each session as it starts, from the
+/// details of the session. This can be a closure, such as `|_| AcceptAll`. See [`HandlerFactory`]
+/// and [`SmtpHandler`].
+///
+/// The server is looked into and shut down through `server`, see [`ServerHandle`], which can be
+/// just a [`Shutdown`]. Once [`Shutdown::shutdown`] is called, the returned stream ends, and each
+/// session is closed with `421` once it is safe to.
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`tokio::net::TcpListener::accept`].
+/// - For I/O errors from a [`Session`], see [`connection::handle`].
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{handler::AcceptAll, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = ServerConfig::builder().hostname("mx.example.com").build()?;
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     config,
+///     |_| AcceptAll,
+///     Shutdown::new(),
+/// );
+/// #     Ok(())
+/// # }
+/// ```
+pub fn listen<F: HandlerFactory>(
+    listener: TcpListener,
+    config: impl Into<ConfigHandle>,
+    factory: F,
+    server: impl Into<ServerHandle>,
+) -> impl Stream<Item = Result<Session>> {
+    accept(
+        listener,
+        config.into(),
+        Overrides::default(),
+        Arc::new(factory),
+        Delivery::Buffered,
+        None,
+        server.into(),
+    )
+}
+
+/// Listen on several ports for incoming TCP connections and handle them as SMTP sessions like
+/// [`listen`], merging the sessions from every listener into one stream.
+///
+/// Sessions on each [`Listener`] follow its own configuration if it has one, or `config` if not.
+/// Every listener shares the handlers that `factory` creates, and the sessions of every listener
+/// count towards [`ServerConfig::max_connections`] together.
+///
+/// Once [`Shutdown::shutdown`] is called on `server`, the returned stream ends after every listener
+/// has stopped accepting, and each session is closed with `421` once it is safe to.
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`tokio::net::TcpListener::accept`], after which no more connections
+///   are accepted on that listener.
+/// - For I/O errors from a [`Session`], see [`connection::handle`].
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{handler::AcceptAll, Listener, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = ServerConfig::builder().hostname("mx.example.com").build()?;
+/// let sessions = smtp_gateway::listen_all(
+///     [
+///         Listener::new(TcpListener::bind("0.0.0.0:25").await?),
+///         Listener::new(TcpListener::bind("0.0.0.0:587").await?)
+///             .with_config(ServerConfig::builder().hostname("submission.example.com").build()?),
+///     ],
+///     config,
+///     |_| AcceptAll,
+///     Shutdown::new(),
+/// );
+/// #     Ok(())
+/// # }
+/// ```
+pub fn listen_all<F: HandlerFactory>(
+    listeners: impl IntoIterator<Item = Listener>,
+    config: impl Into<ConfigHandle>,
+    factory: F,
+    server: impl Into<ServerHandle>,
+) -> impl Stream<Item = Result<Session>> {
+    let config = config.into();
+    let factory = Arc::new(factory);
+    let server = server.into();
 
-pub type Session = JoinHandle<Result<()>>;
+    futures_util::stream::select_all(listeners.into_iter().map(move |listener| {
+        let config = listener.config.unwrap_or_else(|| config.clone());
+        Box::pin(accept(
+            listener.socket,
+            config,
+            listener.overrides,
+            factory.clone(),
+            Delivery::Buffered,
+            None,
+            server.clone(),
+        ))
+    }))
+}
 
-/// Listen on a port for incoming TCP connections and handle them as SMTP sessions.
+/// Listen on a port for incoming TCP connections and handle them as SMTP sessions, sending every
+/// received message to the consumer through the returned receiver.
+///
+/// Every session accepts everything that [`handler::AcceptAll`] does. The client is only told that
+/// a message was received once it is in the channel, and is told to try again later if the
+/// receiver was dropped. The server is looked into and shut down through `server`, like [`listen`].
+///
+/// With the `spool` feature, the messages left in [`ServerConfig::spool`] from before the server
+/// started are sent through the receiver as well, see [`spool::Spool::replay`].
 ///
 /// # Errors
 ///
 /// - [`std::io::Error`] from [`tokio::net::TcpListener::accept`].
 /// - For I/O errors from a [`Session`], see [`connection::handle`].
-pub fn listen(listener: TcpListener) -> impl Stream<Item = Result<Session>> {
+pub fn listen_channel(
+    listener: TcpListener,
+    config: impl Into<ConfigHandle>,
+    server: impl Into<ServerHandle>,
+) -> (impl Stream<Item = Result<Session>>, mpsc::Receiver<Message>) {
+    let (sender, receiver) = mpsc::channel(BUFFERED_MESSAGES);
+    let config = config.into();
+
+    #[cfg(feature = "spool")]
+    if let Some(spool) = config.current().spool().cloned() {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            match spool.replay(&sender).await {
+                Ok(count) => println!("Replayed {count} spooled message(s)"),
+                Err(error) => println!("Replaying the spool failed: {error}"),
+            }
+        });
+    }
+
+    (
+        listen(
+            listener,
+            config,
+            move |_| handler::Forward::new(sender.clone()),
+            server,
+        ),
+        receiver,
+    )
+}
+
+/// Listen on a port for incoming TCP connections and handle them as SMTP sessions, yielding every
+/// received message along with every error from accepting connections or from sessions.
+///
+/// This is [`listen_channel`] with the sessions managed in the background, for consumers that only
+/// care about the messages. Connections are accepted while the returned stream is polled, and
+/// sessions that are in progress when it is dropped are left to finish. Once the server is shut
+/// down through `server`, the stream ends after the last session does.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use futures_util::{pin_mut, StreamExt};
+/// # use smtp_gateway::{ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> std::io::Result<()> {
+/// let messages = smtp_gateway::serve(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     Shutdown::new(),
+/// );
+/// pin_mut!(messages);
+///
+/// while let Some(message) = messages.next().await {
+///     match message {
+///         Ok(message) => println!("{} bytes received", message.size().total()),
+///         Err(e) => eprintln!("{e}"),
+///     }
+/// }
+/// #     Ok(())
+/// # }
+/// ```
+pub fn serve(
+    listener: TcpListener,
+    config: impl Into<ConfigHandle>,
+    server: impl Into<ServerHandle>,
+) -> impl Stream<Item = std::result::Result<Message, SmtpError>> {
+    let (sessions, mut messages) = listen_channel(listener, config, server);
+
+    stream! {
+        // Dropped once no more connections are accepted, so that the stream ends after the last
+        // session does.
+        let (error_sender, mut errors) = mpsc::channel(BUFFERED_MESSAGES);
+        let mut error_sender = Some(error_sender);
+        // Dropped once no more connections are accepted, along with the handler factory that
+        // holds the sender of the messages.
+        let mut sessions = Some(Box::pin(sessions));
+
+        loop {
+            tokio::select! {
+                session = async { sessions.as_mut()?.next().await }, if sessions.is_some() => {
+                    match session {
+                        // Wait for the session in the background to report how it ended.
+                        Some(Ok(session)) => {
+                            let errors = error_sender.clone();
+                            tokio::spawn(async move {
+                                let error = match session.await {
+                                    Ok(Ok(_)) => return,
+                                    Ok(Err(e)) => SmtpError::Session(e),
+                                    Err(e) => SmtpError::Task(e),
+                                };
+                                if let Some(errors) = errors {
+                                    let _ = errors.send(error).await;
+                                }
+                            });
+                        }
+                        Some(Err(e)) => yield Err(SmtpError::Accept(e)),
+                        None => (sessions, error_sender) = (None, None),
+                    }
+                }
+                Some(message) = messages.recv() => yield Ok(message),
+                Some(error) = errors.recv() => yield Err(error),
+                else => break,
+            }
+        }
+    }
+}
+
+/// Listen on a port for incoming TCP connections and handle them as SMTP sessions, sending every
+/// received message to the consumer through the returned receiver and waiting for it to be
+/// acknowledged.
+///
+/// This is [`listen_channel`] for consumers that need at-least-once delivery: the client is only
+/// told that a message was received once the consumer calls [`PendingMessage::accept`], which it
+/// should do once the message is stored durably or handed on. If the consumer drops the message, or
+/// takes longer than [`timeouts::Timeouts::data_termination`] to decide, the client is told to try
+/// again later.
+///
+/// Unlike [`listen_channel`], the messages left in [`ServerConfig::spool`] with the `spool`
+/// feature are not replayed, see [`spool::Spool::pending`]. The server is looked into and shut down
+/// through `server`, like [`listen`].
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`tokio::net::TcpListener::accept`].
+/// - For I/O errors from a [`Session`], see [`connection::handle`].
+pub fn listen_acknowledged(
+    listener: TcpListener,
+    config: impl Into<ConfigHandle>,
+    server: impl Into<ServerHandle>,
+) -> (
+    impl Stream<Item = Result<Session>>,
+    mpsc::Receiver<PendingMessage>,
+) {
+    let (sender, receiver) = mpsc::channel(BUFFERED_MESSAGES);
+
+    (
+        listen(
+            listener,
+            config,
+            move |_| handler::Acknowledge::new(sender.clone()),
+            server,
+        ),
+        receiver,
+    )
+}
+
+/// Listen on a port for incoming TCP connections and handle them as SMTP sessions, streaming the
+/// data of each received message to the consumer as it arrives.
+///
+/// Each [`StreamingMessage`] is sent through the returned receiver as soon as the client starts
+/// sending its data. The client is only told that the message was received once the consumer calls
+/// [`StreamingMessage::accept`] after reading the data, or [`message::stream::Acceptance::accept`]
+/// after splitting it with [`StreamingMessage::into_parts`]. Every other decision is made by the
+/// handler that `factory` creates for each session, except for [`SmtpHandler::on_message`], which
+/// is not called. The server is looked into and shut down through `server`, like [`listen`].
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`tokio::net::TcpListener::accept`].
+/// - For I/O errors from a [`Session`], see [`connection::handle`].
+pub fn listen_streaming<F: HandlerFactory>(
+    listener: TcpListener,
+    config: impl Into<ConfigHandle>,
+    factory: F,
+    server: impl Into<ServerHandle>,
+) -> (
+    impl Stream<Item = Result<Session>>,
+    mpsc::Receiver<StreamingMessage>,
+) {
+    let (sender, receiver) = mpsc::channel(BUFFERED_MESSAGES);
+
+    (
+        accept(
+            listener,
+            config.into(),
+            Overrides::default(),
+            Arc::new(factory),
+            Delivery::Streaming(sender),
+            None,
+            server.into(),
+        ),
+        receiver,
+    )
+}
+
+/// Listen on a port for incoming TCP connections and handle them as SMTP sessions like [`listen`],
+/// sending the events of each session to the consumer through the returned receiver as it starts.
+///
+/// Each [`event::SessionEvents`] is a stream of the [`event::SessionEvent`]s of one session, from
+/// [`event::SessionEvent::Connected`] to [`event::SessionEvent::Closed`], for consumers that build
+/// their own state machines or live views on top of the protocol flow. Every decision is still made
+/// by the handler that `factory` creates for each session. The server is looked into and shut down
+/// through `server`, like [`listen`].
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`tokio::net::TcpListener::accept`].
+/// - For I/O errors from a [`Session`], see [`connection::handle`].
+pub fn listen_events<F: HandlerFactory>(
+    listener: TcpListener,
+    config: impl Into<ConfigHandle>,
+    factory: F,
+    server: impl Into<ServerHandle>,
+) -> (
+    impl Stream<Item = Result<Session>>,
+    mpsc::Receiver<event::SessionEvents>,
+) {
+    let (sender, receiver) = mpsc::channel(BUFFERED_MESSAGES);
+
+    (
+        accept(
+            listener,
+            config.into(),
+            Overrides::default(),
+            Arc::new(factory),
+            Delivery::Buffered,
+            Some(sender),
+            server.into(),
+        ),
+        receiver,
+    )
+}
+
+/// Accept incoming TCP connections and spawn a task to handle each as an SMTP session, until
+/// the server is shut down through `server`.
+///
+/// See [`connection::handle`] for `overrides`, `delivery`, and `events`.
+///
+/// # Errors
+///
+/// - [`std::io::Error`] from [`tokio::net::TcpListener::accept`].
+#[expect(
+    clippy::significant_drop_tightening,
+    reason = "the permit of each session is moved into its task rather than dropped by the loop"
+)]
+fn accept<F: HandlerFactory>(
+    listener: TcpListener,
+    config: ConfigHandle,
+    overrides: Overrides,
+    factory: Arc<F>,
+    delivery: Delivery,
+    events: Option<mpsc::Sender<event::SessionEvents>>,
+    server: ServerHandle,
+) -> impl Stream<Item = Result<Session>> {
     try_stream! {
+        let _listening = server.listening();
         loop {
-            let (stream, _) = listener.accept().await?;
-            yield tokio::spawn(connection::handle(stream));
+            let current = config.current();
+            if current.connection_overflow() == ConnectionOverflow::Wait {
+                tokio::select! {
+                    () = server.below(current.max_connections().unwrap_or(usize::MAX)) => (),
+                    () = server.shutdown().stopped() => break,
+                }
+            }
+
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                () = server.shutdown().stopped() => break,
+            };
+            let (stream, peer) = accepted?;
+            server.counters().connection();
+            #[cfg(feature = "metrics")]
+            metrics::connection_accepted();
+            // The session follows the configuration as it is when its connection is accepted.
+            let config = config.current();
+            match config.access_for(peer.ip()) {
+                Access::Accept => (),
+                Access::Reject => {
+                    let config = config.clone();
+                    turn_away(&server, peer, RejectionSource::Policy("access"), async move {
+                        connection::refuse(stream, &config).await
+                    });
+                    continue;
+                }
+                Access::Drop => {
+                    drop(stream);
+                    continue;
+                }
+            }
+            // Another listener of the same server can take the last session while this one is
+            // accepting, so the connection holds a permit of its own or waits for one.
+            let permit = if config.connection_overflow() == ConnectionOverflow::Wait {
+                tokio::select! {
+                    permit = server.acquire(&config) => Some(permit),
+                    () = server.shutdown().stopped() => break,
+                }
+            } else {
+                server.try_acquire(&config)
+            };
+            let Some(permit) = permit else {
+                let config = config.clone();
+                turn_away(&server, peer, RejectionSource::Limit("max_connections"), async move {
+                    let text = "Too many connections, try again later";
+                    connection::overflow(stream, &config, text).await
+                });
+                continue;
+            };
+            let guard = match server.admit(peer.ip(), &config, permit) {
+                Ok(guard) => guard,
+                Err(excess) => {
+                    let config = config.clone();
+                    turn_away(&server, peer, RejectionSource::Limit(excess.setting()), async move {
+                        connection::overflow(stream, &config, excess.text()).await
+                    });
+                    continue;
+                }
+            };
+
+            let activity = guard.activity().clone();
+            let session = tokio::spawn(connection::handle(
+                stream,
+                config,
+                overrides,
+                factory.clone(),
+                delivery.clone(),
+                events.clone(),
+                guard,
+            ));
+            activity.set_abort(session.abort_handle());
+            yield session;
         }
     }
 }
 
+/// Send a connection that is turned away its `refusal` in a task of its own, so that a slow client
+/// does not hold up the accepting of other connections, then note the reply for
+/// [`ServerHandle::rejections`].
+fn turn_away(
+    server: &ServerHandle,
+    peer: SocketAddr,
+    source: RejectionSource,
+    refusal: impl Future<Output = String> + Send + 'static,
+) {
+    let server = server.clone();
+    tokio::spawn(async move {
+        let reply = refusal.await;
+        server.refused(peer, &reply, source);
+    });
+}
+
 /// Tests whether a string is a domain name as considered by SMTP ([RFC 5321, section
 /// 2.3.5](https://www.rfc-editor.org/rfc/rfc5321.html#section-2.3.5)).
 ///
@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The addresses that one server listens on.
+//!
+//! See [`Listener`] and [`crate::listen_all`].
+
+#[cfg(unix)]
+use std::{
+    env,
+    os::fd::{FromRawFd, RawFd},
+};
+
+use tokio::net::TcpListener;
+
+use crate::config::ConfigHandle;
+
+/// The first file descriptor that systemd passes sockets as, see `sd_listen_fds(3)`.
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+/// A bound [`TcpListener`] that the server accepts connections on.
+///
+/// A listener can have its own [`crate::ServerConfig`] in place of the server's, such as for a submission
+/// port with a different hostname or lower limits. Settings that only differ between ports, such
+/// as [`Self::with_implicit_tls`] and [`Self::with_required_auth`], apply over whichever
+/// configuration the listener follows.
+///
+/// Given to [`crate::listen_all`].
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{Listener, ServerConfig};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let relay = Listener::new(TcpListener::bind("0.0.0.0:25").await?);
+/// let submission = Listener::new(TcpListener::bind("0.0.0.0:587").await?)
+///     .with_config(
+///         ServerConfig::builder()
+///             .hostname("submission.example.com")
+///             .build()?,
+///     )
+///     .with_required_auth();
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Listener {
+    /// The bound socket that connections are accepted on.
+    pub(crate) socket: TcpListener,
+    /// The configuration of sessions on this listener, or `None` for the server's.
+    pub(crate) config: Option<ConfigHandle>,
+    /// The settings that apply over the configuration of sessions on this listener.
+    pub(crate) overrides: Overrides,
+}
+
+impl Listener {
+    /// Create a new [`Self`] that follows the configuration of the server.
+    #[must_use]
+    pub const fn new(listener: TcpListener) -> Self {
+        Self {
+            socket: listener,
+            config: None,
+            overrides: Overrides {
+                #[cfg(feature = "tls")]
+                implicit_tls: false,
+                require_auth: false,
+            },
+        }
+    }
+
+    /// Create a new [`Self`] from a bound [`std::net::TcpListener`], such as one inherited from
+    /// the process that started this one, putting it into nonblocking mode as Tokio requires.
+    ///
+    /// This must be called from within a Tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// - [`std::io::Error`] if `listener` is not a bound TCP socket, or from
+    ///   [`std::net::TcpListener::set_nonblocking`].
+    pub fn from_std(listener: std::net::TcpListener) -> std::io::Result<Self> {
+        // Fails for anything but an IP socket, such as a Unix socket.
+        listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self::new(TcpListener::from_std(listener)?))
+    }
+
+    /// Take the listening sockets that systemd passed to this process with socket activation, in
+    /// the order of the `ListenStream=` lines of the socket unit.
+    ///
+    /// This follows `sd_listen_fds(3)`, so the server can be started by systemd on its first
+    /// connection and can listen on port 25 without running with privileges. Returns an empty list
+    /// if the process was not started with socket activation. The `LISTEN_*` environment variables
+    /// are removed so that the sockets are not taken twice or passed on to child processes, so
+    /// this should be called before any other threads read the environment.
+    ///
+    /// This must be called from within a Tokio runtime. Give the listeners to
+    /// [`crate::listen_all`].
+    ///
+    /// # Errors
+    ///
+    /// - [`std::io::Error`] if any of the passed sockets is not a bound TCP socket, see
+    ///   [`Self::from_std`].
+    #[cfg(unix)]
+    pub fn from_systemd() -> std::io::Result<Vec<Self>> {
+        let is_for_this_process = env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            == Some(std::process::id());
+        let count = env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|count| count.parse::<RawFd>().ok());
+        for variable in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(variable);
+        }
+
+        let Some(count) = count.filter(|_| is_for_this_process) else {
+            return Ok(Vec::new());
+        };
+
+        (LISTEN_FDS_START..LISTEN_FDS_START.saturating_add(count))
+            .map(|fd| {
+                // SAFETY: systemd passes these descriptors to this process to own, and they
+                // cannot be taken again now that the variables that name them are removed.
+                let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+                Self::from_std(listener)
+            })
+            .collect()
+    }
+
+    /// Follow `config` for sessions on this listener, in place of the configuration of the server.
+    #[must_use]
+    pub fn with_config(mut self, config: impl Into<ConfigHandle>) -> Self {
+        self.config = Some(config.into());
+        self
+    }
+
+    /// Get the configuration of sessions on this listener, or `None` if it follows the
+    /// configuration of the server.
+    #[must_use]
+    pub const fn config(&self) -> Option<&ConfigHandle> {
+        self.config.as_ref()
+    }
+
+    /// Start TLS as soon as each client connects, before the greeting, as on port 465 for
+    /// submission.
+    ///
+    /// Sessions are encrypted with [`crate::ServerConfig::tls`] of the configuration that they
+    /// follow, and are closed if it is not set. `STARTTLS` is not offered, as the connection is
+    /// already encrypted.
+    ///
+    /// [RFC 8314 section 3.3](https://www.rfc-editor.org/rfc/rfc8314.html#section-3.3).
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub const fn with_implicit_tls(mut self) -> Self {
+        self.overrides.implicit_tls = true;
+        self
+    }
+
+    /// Get whether TLS is started before the greeting, see [`Self::with_implicit_tls`].
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub const fn implicit_tls(&self) -> bool {
+        self.overrides.implicit_tls
+    }
+
+    /// Require clients to authenticate before sending mail, as on port 587 for submission,
+    /// rejecting `MAIL` with `530` until they have.
+    ///
+    /// A client is authenticated once the handler calls
+    /// [`crate::handler::SessionContext::authenticate`], such as when it implements `AUTH` in
+    /// [`crate::SmtpHandler::on_command`].
+    ///
+    /// [RFC 4954 section 6](https://www.rfc-editor.org/rfc/rfc4954.html#section-6).
+    #[must_use]
+    pub const fn with_required_auth(mut self) -> Self {
+        self.overrides.require_auth = true;
+        self
+    }
+
+    /// Get whether clients must authenticate before sending mail, see
+    /// [`Self::with_required_auth`].
+    #[must_use]
+    pub const fn requires_auth(&self) -> bool {
+        self.overrides.require_auth
+    }
+}
+
+/// The settings of a [`Listener`] that apply over the configuration of its sessions.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) struct Overrides {
+    /// Whether TLS is started before the greeting, see [`Listener::with_implicit_tls`].
+    #[cfg(feature = "tls")]
+    pub(crate) implicit_tls: bool,
+    /// Whether clients must authenticate before sending mail, see
+    /// [`Listener::with_required_auth`].
+    pub(crate) require_auth: bool,
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Self {
+        Self::new(listener)
+    }
+}
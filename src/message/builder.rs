@@ -0,0 +1,298 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Constructs messages programmatically, without an SMTP session.
+//!
+//! See [`MessageBuilder`].
+
+use std::{fmt::Display, net::SocketAddr, time::SystemTime};
+
+use ascii::AsciiString;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::{
+    envelope::{Envelope, Recipient},
+    headers, ContentHash, Message, Size,
+};
+use crate::{
+    session::{SessionInfo, TlsInfo},
+    str::CRLF,
+};
+
+/// Builds a [`Message`] out of an envelope, header fields, and a body.
+///
+/// Intended for testing consumers of [`Message`] without a live SMTP session. Created by
+/// [`Message::builder`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::Message;
+/// #
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let message = Message::builder()
+///     .reverse_path("smith@example.com")
+///     .forward_path("jones@example.com")
+///     .header("Subject", "Hello")
+///     .body("Hi there!\r\n")
+///     .build()?;
+///
+/// assert_eq!(message.headers().subject(), Some("Hello"));
+/// assert_eq!(message.body(), "Hi there!\r\n".as_bytes());
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct MessageBuilder {
+    /// The reverse-path, or `None` for the null reverse-path.
+    reverse_path: Option<String>,
+    /// The forward-paths.
+    forward_paths: Vec<String>,
+    /// The identity given in `HELO`, or `None` if `HELO` was not sent.
+    helo: Option<String>,
+    /// Whether the identity was given in `EHLO` rather than `HELO`.
+    extended: bool,
+    /// The address of the server.
+    local_addr: SocketAddr,
+    /// The address of the client.
+    peer_addr: SocketAddr,
+    /// When the mail transaction was started, or `None` for when [`Self::build`] is called.
+    started_at: Option<SystemTime>,
+    /// When the end of the data was received, or `None` for when [`Self::build`] is called.
+    received_at: Option<SystemTime>,
+    /// The details of the encryption of the connection, or `None` if it was not encrypted.
+    tls: Option<TlsInfo>,
+    /// The identity the client authenticated as, if any.
+    authenticated_user: Option<String>,
+    /// The header fields, in order.
+    headers: Vec<(String, String)>,
+    /// The body.
+    body: Bytes,
+}
+
+impl MessageBuilder {
+    /// Create a new [`Self`] with an empty envelope, no header fields, and an empty body.
+    ///
+    /// The server and client both default to `127.0.0.1:0` and the times default to when
+    /// [`Self::build`] is called.
+    pub fn new() -> Self {
+        Self {
+            reverse_path: None,
+            forward_paths: Vec::new(),
+            helo: None,
+            extended: false,
+            local_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            peer_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            started_at: None,
+            received_at: None,
+            tls: None,
+            authenticated_user: None,
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    /// Set the reverse-path, without angle brackets. See [`Envelope::reverse_path`].
+    pub fn reverse_path(mut self, path: impl Into<String>) -> Self {
+        self.reverse_path = Some(path.into());
+        self
+    }
+
+    /// Set the reverse-path to the null reverse-path (`<>`). See [`Envelope::reverse_path`].
+    pub fn null_reverse_path(mut self) -> Self {
+        self.reverse_path = None;
+        self
+    }
+
+    /// Add a recipient by its forward-path, without angle brackets. See [`Envelope::recipients`].
+    pub fn forward_path(mut self, path: impl Into<String>) -> Self {
+        self.forward_paths.push(path.into());
+        self
+    }
+
+    /// Set the identity given in `HELO`. See [`SessionInfo::helo`].
+    pub fn helo(mut self, helo: impl Into<String>) -> Self {
+        self.helo = Some(helo.into());
+        self.extended = false;
+        self
+    }
+
+    /// Set the identity given in `EHLO`. See [`SessionInfo::helo`] and
+    /// [`SessionInfo::is_extended`].
+    pub fn ehlo(mut self, ehlo: impl Into<String>) -> Self {
+        self.helo = Some(ehlo.into());
+        self.extended = true;
+        self
+    }
+
+    /// Set the address of the server. See [`SessionInfo::local_addr`].
+    pub const fn local_addr(mut self, addr: SocketAddr) -> Self {
+        self.local_addr = addr;
+        self
+    }
+
+    /// Set the address of the client. See [`SessionInfo::peer_addr`].
+    pub const fn peer_addr(mut self, addr: SocketAddr) -> Self {
+        self.peer_addr = addr;
+        self
+    }
+
+    /// Set when the mail transaction was started. See [`Message::started_at`].
+    pub const fn started_at(mut self, time: SystemTime) -> Self {
+        self.started_at = Some(time);
+        self
+    }
+
+    /// Set when the end of the data was received. See [`Message::received_at`].
+    pub const fn received_at(mut self, time: SystemTime) -> Self {
+        self.received_at = Some(time);
+        self
+    }
+
+    /// Set the details of the encryption of the connection. See [`SessionInfo::tls`].
+    pub fn tls(mut self, tls: TlsInfo) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Set the identity the client authenticated as. See [`SessionInfo::authenticated_user`].
+    pub fn authenticated_user(mut self, user: impl Into<String>) -> Self {
+        self.authenticated_user = Some(user.into());
+        self
+    }
+
+    /// Add a header field. Fields are written in the order they are added.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set the body, which is written after the header section as-is.
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Build the [`Message`], writing the header fields and the body into its data.
+    ///
+    /// # Errors
+    ///
+    /// - [`BuildError::NonAsciiPath`] if a path or the `HELO` identity is not ASCII.
+    /// - [`BuildError::InvalidHeader`] if a header field name is empty or not printable ASCII
+    ///   without colons, or if a value contains a line ending.
+    pub fn build(self) -> Result<Message, BuildError> {
+        let ascii =
+            |path: String| AsciiString::from_ascii(path).map_err(|_| BuildError::NonAsciiPath);
+
+        let reverse_path = self.reverse_path.map(ascii).transpose()?;
+        let recipients = self
+            .forward_paths
+            .into_iter()
+            .map(|path| ascii(path).map(Recipient::new))
+            .collect::<Result<Vec<_>, _>>()?;
+        let helo = self.helo.map(ascii).transpose()?;
+
+        let mut data = BytesMut::new();
+        for (name, value) in &self.headers {
+            let is_valid_name = !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|byte| (33..=126).contains(&byte) && byte != b':');
+            if !is_valid_name || value.contains(['\r', '\n']) {
+                return Err(BuildError::InvalidHeader);
+            }
+
+            data.put_slice(name.as_bytes());
+            data.put_slice(b": ");
+            data.put_slice(value.as_bytes());
+            data.put_slice(CRLF.as_bytes());
+        }
+        if !self.headers.is_empty() {
+            data.put_slice(CRLF.as_bytes());
+        }
+        data.put_slice(&self.body);
+        let data = data.freeze();
+
+        let body = headers::parse(&data).1.len();
+        let size = Size {
+            header: data.len() - body,
+            body,
+        };
+
+        let now = SystemTime::now();
+
+        Ok(Message {
+            envelope: Envelope {
+                recipients,
+                ..Envelope::new(reverse_path)
+            },
+            session: SessionInfo {
+                helo,
+                extended: self.extended,
+                tls: self.tls,
+                authenticated_user: self.authenticated_user,
+                ..SessionInfo::new(self.local_addr, self.peer_addr)
+            },
+            started_at: self.started_at.unwrap_or(now),
+            received_at: self.received_at.unwrap_or(now),
+            hash: ContentHash::of(&data),
+            data,
+            size,
+            #[cfg(feature = "dmarc")]
+            dmarc: None,
+            #[cfg(feature = "arc")]
+            arc: None,
+            #[cfg(feature = "filter")]
+            filter: None,
+            #[cfg(feature = "clamav")]
+            clamav: None,
+            #[cfg(feature = "rspamd")]
+            rspamd: None,
+            #[cfg(feature = "spamassassin")]
+            spamassassin: None,
+            #[cfg(feature = "spool")]
+            spool_id: None,
+        })
+    }
+}
+
+impl Default for MessageBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Possible error states encountered when building a [`Message`] with [`MessageBuilder`].
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum BuildError {
+    /// A path or the `HELO` identity contains characters that are not ASCII.
+    NonAsciiPath,
+    /// A header field has an invalid name or a value that contains a line ending.
+    InvalidHeader,
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NonAsciiPath => "path or HELO identity contains non-ASCII characters",
+            Self::InvalidHeader => "header field has an invalid name or a multi-line value",
+        })
+    }
+}
+
+impl std::error::Error for BuildError {}
@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Conversions from [`Message`] into the types of other mail crates.
+//!
+//! - With the `lettre` feature, into [`lettre::address::Envelope`], for relaying a message with
+//!   a `lettre` transport's `send_raw` and [`Message::data`].
+//! - With the `mail-parser` feature, into [`mail_parser::Message`].
+
+use std::fmt::Display;
+
+use super::Message;
+
+/// Possible error states encountered when converting a [`Message`] into the type of another mail
+/// crate.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConversionError {
+    /// A path in the envelope is not a valid address.
+    #[cfg(feature = "lettre")]
+    Address(lettre::address::AddressError),
+    /// The envelope was rejected, such as for having no recipients.
+    #[cfg(feature = "lettre")]
+    Envelope(lettre::error::Error),
+    /// The data could not be parsed as a message.
+    #[cfg(feature = "mail-parser")]
+    Unparsable,
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "lettre")]
+            Self::Address(err) => write!(f, "invalid address in envelope: {err}"),
+            #[cfg(feature = "lettre")]
+            Self::Envelope(err) => write!(f, "invalid envelope: {err}"),
+            #[cfg(feature = "mail-parser")]
+            Self::Unparsable => f.write_str("data could not be parsed as a message"),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "lettre")]
+            Self::Address(err) => Some(err),
+            #[cfg(feature = "lettre")]
+            Self::Envelope(err) => Some(err),
+            #[cfg(feature = "mail-parser")]
+            Self::Unparsable => None,
+        }
+    }
+}
+
+#[cfg(feature = "lettre")]
+impl TryFrom<&Message> for lettre::address::Envelope {
+    type Error = ConversionError;
+
+    /// Convert the envelope of a message, for relaying it with `send_raw`.
+    ///
+    /// The null reverse-path becomes no sender.
+    fn try_from(message: &Message) -> Result<Self, Self::Error> {
+        let address = |path: &ascii::AsciiStr| {
+            path.as_str()
+                .parse::<lettre::Address>()
+                .map_err(ConversionError::Address)
+        };
+
+        let from = message.envelope().reverse_path().map(address).transpose()?;
+        let to = message
+            .envelope()
+            .forward_paths()
+            .map(address)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::new(from, to).map_err(ConversionError::Envelope)
+    }
+}
+
+#[cfg(feature = "lettre")]
+impl TryFrom<Message> for lettre::address::Envelope {
+    type Error = ConversionError;
+
+    /// Convert the envelope of a message, for relaying it with `send_raw`.
+    ///
+    /// See the implementation for `&Message`.
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        Self::try_from(&message)
+    }
+}
+
+#[cfg(feature = "mail-parser")]
+impl<'a> TryFrom<&'a Message> for mail_parser::Message<'a> {
+    type Error = ConversionError;
+
+    /// Parse the data of a message, borrowing from it.
+    fn try_from(message: &'a Message) -> Result<Self, Self::Error> {
+        mail_parser::MessageParser::default()
+            .parse(message.data().as_ref())
+            .ok_or(ConversionError::Unparsable)
+    }
+}
+
+#[cfg(feature = "mail-parser")]
+impl TryFrom<Message> for mail_parser::Message<'static> {
+    type Error = ConversionError;
+
+    /// Parse the data of a message.
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        mail_parser::Message::try_from(&message).map(mail_parser::Message::into_owned)
+    }
+}
@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Dates as used in message headers.
+//!
+//! See [RFC 5322 section 3.3](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.3).
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The abbreviated names of the days of the week, starting from Sunday.
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// The abbreviated names of the months of the year, starting from January.
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Format a time as an RFC 5322 date, such as `Thu, 17 Oct 2024 13:05:09 +0000`.
+///
+/// The time is always given in UTC. Times before the Unix epoch are clamped to it.
+///
+/// [RFC 5322 section 3.3](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.3).
+#[must_use]
+#[expect(
+    clippy::cast_possible_truncation,
+    reason = "the indices are always less than 12"
+)]
+pub fn format(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    let days = seconds / 86_400;
+    let seconds_of_day = seconds % 86_400;
+    let (year, month, day) = civil_from_days(days);
+
+    // The Unix epoch was a Thursday.
+    let weekday = DAYS[((days + 4) % 7) as usize];
+    let month = MONTHS[(month - 1) as usize];
+
+    format!(
+        "{weekday}, {day} {month} {year} {:02}:{:02}:{:02} +0000",
+        seconds_of_day / 3_600,
+        seconds_of_day % 3_600 / 60,
+        seconds_of_day % 60,
+    )
+}
+
+/// Parse an RFC 5322 date, such as `Thu, 17 Oct 2024 13:05:09 +0000`.
+///
+/// The day of the week and the seconds are optional, as the specification allows. Comments,
+/// obsolete two-digit years, and obsolete named time zones (such as `GMT` or `EST`) are accepted
+/// per [RFC 5322 section 4.3](https://www.rfc-editor.org/rfc/rfc5322.html#section-4.3).
+///
+/// Returns `None` if the date is malformed, before the Unix epoch, or after the year 9999.
+///
+/// [RFC 5322 section 3.3](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.3).
+#[must_use]
+pub fn parse(date: &str) -> Option<SystemTime> {
+    // Drop any trailing comment, such as in `+0000 (UTC)`.
+    let date = date.split_once('(').map_or(date, |(date, _)| date);
+
+    let mut parts = date.split_whitespace().peekable();
+
+    // The day of the week is redundant, so it is skipped.
+    if parts.peek()?.ends_with(',') {
+        parts.next();
+    }
+
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(month))?
+        + 1;
+    let year: u64 = match parts.next()? {
+        // Obsolete two-digit years, per RFC 5322 section 4.3.
+        year if year.len() == 2 => match year.parse::<u64>().ok()? {
+            year @ 0..=49 => year + 2000,
+            year => year + 1900,
+        },
+        year if year.len() == 3 => year.parse::<u64>().ok()? + 1900,
+        year => year.parse().ok()?,
+    };
+
+    let mut time = parts.next()?.split(':');
+    let hour: u64 = time.next()?.parse().ok()?;
+    let minute: u64 = time.next()?.parse().ok()?;
+    let second: u64 = time.next().map_or(Some(0), |second| second.parse().ok())?;
+
+    // Missing zones are treated as UTC, as they are in practice.
+    let offset = parts.next().map_or(Some(0), zone_offset)?;
+
+    // Years past 9999 are refused, so that the arithmetic below cannot overflow on hostile input.
+    if !(1970..=9999).contains(&year)
+        || day == 0
+        || day > 31
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let days = days_from_civil(year, month as u64, day)?;
+    let seconds = days
+        .checked_mul(86_400)?
+        .checked_add(hour * 3_600 + minute * 60 + second)?
+        .checked_add_signed(-offset)?;
+
+    UNIX_EPOCH.checked_add(Duration::from_secs(seconds))
+}
+
+/// Parse a time zone into its offset from UTC, in seconds.
+///
+/// Accepts `+hhmm` and `-hhmm` offsets, as well as the obsolete names of RFC 5322 section 4.3.
+/// Military zones are treated as UTC, as the specification recommends.
+fn zone_offset(zone: &str) -> Option<i64> {
+    let hours = match zone.to_ascii_uppercase().as_str() {
+        "UT" | "GMT" | "Z" => 0,
+        "EDT" => -4,
+        "EST" | "CDT" => -5,
+        "CST" | "MDT" => -6,
+        "MST" | "PDT" => -7,
+        "PST" => -8,
+        zone if zone.len() == 1 && zone.chars().all(|c| c.is_ascii_alphabetic()) => 0,
+        _ => {
+            let (sign, digits) = match zone.split_at_checked(1)? {
+                ("+", digits) => (1, digits),
+                ("-", digits) => (-1, digits),
+                _ => return None,
+            };
+            if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+
+            let hours: i64 = digits[..2].parse().ok()?;
+            let minutes: i64 = digits[2..].parse().ok()?;
+
+            return Some(sign * (hours * 3_600 + minutes * 60));
+        }
+    };
+
+    Some(hours * 3_600)
+}
+
+/// Convert a year, month (1 to 12), and day of the month (1 to 31) of the proleptic Gregorian
+/// calendar into a number of days since the Unix epoch.
+///
+/// Returns `None` for dates before the Unix epoch.
+///
+/// See Howard Hinnant's `days_from_civil`:
+/// <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+const fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+    // Shift the epoch to 0000-03-01, so that leap days are at the end of each "year."
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_from_march = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    (era * 146_097 + day_of_era).checked_sub(719_468)
+}
+
+/// Convert a number of days since the Unix epoch into a year, month (1 to 12), and day of the
+/// month (1 to 31) of the proleptic Gregorian calendar.
+///
+/// See Howard Hinnant's `civil_from_days`:
+/// <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+pub(crate) const fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Shift the epoch to 0000-03-01, so that leap days are at the end of each "year."
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Decodes the data of messages into the bytes and text that they represent.
+//!
+//! See [`transfer_encoding`] and [`charset`].
+
+use std::borrow::Cow;
+
+use bytes::Bytes;
+
+/// Decode data according to the value of its `Content-Transfer-Encoding:` field.
+///
+/// `base64` and `quoted-printable` are decoded, see [`base64`] and [`quoted_printable`]. Anything
+/// else (including no encoding) is returned as-is, without copying `data`.
+///
+/// [RFC 2045 section 6](https://www.rfc-editor.org/rfc/rfc2045.html#section-6).
+#[must_use]
+pub fn transfer_encoding(data: &Bytes, encoding: Option<&str>) -> Bytes {
+    match encoding.map(str::trim) {
+        Some(encoding) if encoding.eq_ignore_ascii_case("base64") => Bytes::from(base64(data)),
+        Some(encoding) if encoding.eq_ignore_ascii_case("quoted-printable") => {
+            Bytes::from(quoted_printable(data))
+        }
+        // `7bit`, `8bit`, and `binary` are not encodings, just descriptions of the data.
+        _ => data.clone(),
+    }
+}
+
+/// Decode text in the character set named by `charset` (such as the `charset` parameter of a
+/// `Content-Type:` field) into UTF-8.
+///
+/// UTF-8, US-ASCII, and ISO-8859-1 are always supported, and every other character set in the
+/// [WHATWG Encoding Standard](https://encoding.spec.whatwg.org/) is supported with the `encoding`
+/// feature. Unsupported character sets (or no character set at all) are decoded as UTF-8.
+///
+/// Invalid sequences are replaced with `U+FFFD REPLACEMENT CHARACTER`.
+#[must_use]
+pub fn charset<'a>(data: &'a [u8], charset: Option<&str>) -> Cow<'a, str> {
+    let charset = charset.map(str::trim).unwrap_or_default();
+
+    if ["utf-8", "utf8", "us-ascii", "ascii", ""]
+        .iter()
+        .any(|name| charset.eq_ignore_ascii_case(name))
+    {
+        return String::from_utf8_lossy(data);
+    }
+
+    #[cfg(feature = "encoding")]
+    if let Some(encoding) = encoding_rs::Encoding::for_label(charset.as_bytes()) {
+        return encoding.decode_without_bom_handling(data).0;
+    }
+
+    if ["iso-8859-1", "latin1"]
+        .iter()
+        .any(|name| charset.eq_ignore_ascii_case(name))
+    {
+        return data.iter().copied().map(char::from).collect();
+    }
+
+    String::from_utf8_lossy(data)
+}
+
+/// Decode `base64` data, skipping any characters outside of the alphabet (such as line endings).
+///
+/// [RFC 2045 section 6.8](https://www.rfc-editor.org/rfc/rfc2045.html#section-6.8).
+#[must_use]
+pub fn base64(data: &[u8]) -> Vec<u8> {
+    /// Get the 6-bit value of a character of the alphabet.
+    const fn value(byte: u8) -> Option<u8> {
+        match byte {
+            b'A'..=b'Z' => Some(byte - b'A'),
+            b'a'..=b'z' => Some(byte - b'a' + 26),
+            b'0'..=b'9' => Some(byte - b'0' + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let mut output = Vec::with_capacity(data.len() / 4 * 3);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in data {
+        // Padding marks the end of the data.
+        if byte == b'=' {
+            break;
+        }
+        let Some(value) = value(byte) else {
+            continue;
+        };
+
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            output.push(((buffer >> bits) & 0xFF) as u8);
+        }
+    }
+
+    output
+}
+
+/// Decode `quoted-printable` data.
+///
+/// Malformed escape sequences are kept as-is, as the specification recommends.
+///
+/// [RFC 2045 section 6.7](https://www.rfc-editor.org/rfc/rfc2045.html#section-6.7).
+#[must_use]
+pub fn quoted_printable(data: &[u8]) -> Vec<u8> {
+    /// Get the value of a hexadecimal digit.
+    const fn hex(byte: u8) -> Option<u8> {
+        match byte {
+            b'0'..=b'9' => Some(byte - b'0'),
+            b'A'..=b'F' => Some(byte - b'A' + 10),
+            b'a'..=b'f' => Some(byte - b'a' + 10),
+            _ => None,
+        }
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+
+    for line in data.split_inclusive(|&byte| byte == b'\n') {
+        // Everything after the last non-whitespace character is the line ending or whitespace
+        // that was added in transport, so it is removed.
+        let content = line.trim_ascii_end();
+        let ending = &line[content.len()..];
+        let ending: &[u8] = if ending.ends_with(b"\r\n") {
+            b"\r\n"
+        } else if ending.ends_with(b"\n") {
+            b"\n"
+        } else {
+            b""
+        };
+
+        // A trailing `=` is a soft line break, which joins this line with the next.
+        let (content, ending) = content
+            .strip_suffix(b"=")
+            .map_or((content, ending), |content| (content, b""));
+
+        let mut index = 0;
+        while index < content.len() {
+            let byte = content[index];
+
+            if byte == b'=' {
+                if let (Some(&high), Some(&low)) = (content.get(index + 1), content.get(index + 2))
+                {
+                    if let (Some(high), Some(low)) = (hex(high), hex(low)) {
+                        output.push(high << 4 | low);
+                        index += 3;
+                        continue;
+                    }
+                }
+            }
+
+            output.push(byte);
+            index += 1;
+        }
+
+        output.extend_from_slice(ending);
+    }
+
+    output
+}
@@ -0,0 +1,374 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The envelope of a message, as given by the client in `MAIL` and `RCPT`.
+//!
+//! See [`Envelope`].
+
+use std::fmt::Display;
+
+use ascii::{AsciiStr, AsciiString};
+
+#[cfg(feature = "spf")]
+use crate::spf::SpfVerdict;
+
+/// The envelope of a message, which is everything about the mail transaction that is needed to
+/// send the message on to its recipients.
+///
+/// Includes the parameters of the delivery status notification (DSN) extension, so that a relay
+/// can reconstruct the `MAIL` and `RCPT` commands it received.
+///
+/// See [RFC 5321 section 2.3.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-2.3.1) and
+/// [RFC 3461 section 4](https://www.rfc-editor.org/rfc/rfc3461.html#section-4).
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+    /// The reverse-path from `MAIL FROM`, or `None` for the null reverse-path (`<>`).
+    pub(crate) reverse_path: Option<AsciiString>,
+    /// The recipients from every `RCPT TO` with a valid forward-path, in the order they were
+    /// received.
+    pub(crate) recipients: Vec<Recipient>,
+    /// The `RET` parameter from `MAIL FROM`.
+    pub(crate) ret: Option<Ret>,
+    /// The `ENVID` parameter from `MAIL FROM`, still encoded as `xtext`.
+    pub(crate) envid: Option<AsciiString>,
+    /// The result of checking SPF for the reverse-path, or `None` if it was not checked.
+    #[cfg(feature = "spf")]
+    pub(crate) spf: Option<SpfVerdict>,
+}
+
+impl Envelope {
+    /// Create a new [`Self`] with no recipients or parameters.
+    pub(crate) const fn new(reverse_path: Option<AsciiString>) -> Self {
+        Self {
+            reverse_path,
+            recipients: Vec::new(),
+            ret: None,
+            envid: None,
+            #[cfg(feature = "spf")]
+            spf: None,
+        }
+    }
+
+    /// Get the reverse-path given by the client in `MAIL FROM`, without the angle brackets.
+    ///
+    /// Returns `None` for the null reverse-path (`MAIL FROM:<>`), which is used by notification
+    /// messages such as bounces. See [RFC 5321 section
+    /// 4.5.5](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.5).
+    #[must_use]
+    pub fn reverse_path(&self) -> Option<&AsciiStr> {
+        self.reverse_path.as_deref()
+    }
+
+    /// Get whether the reverse-path is the null reverse-path (`MAIL FROM:<>`), as used by
+    /// notification messages such as bounces.
+    #[must_use]
+    pub const fn is_null_sender(&self) -> bool {
+        self.reverse_path.is_none()
+    }
+
+    /// Get every recipient given by the client in `RCPT TO`, in the order they were received,
+    /// including those that were not accepted.
+    ///
+    /// See [`Recipient::status`] and [`Self::accepted`].
+    #[must_use]
+    pub fn recipients(&self) -> &[Recipient] {
+        &self.recipients
+    }
+
+    /// Get the recipients that were accepted, which are the ones that the message must be
+    /// delivered to.
+    ///
+    /// There is always at least one accepted recipient for a received message, as a mail
+    /// transaction cannot reach `DATA` without one.
+    pub fn accepted(&self) -> impl Iterator<Item = &Recipient> {
+        self.recipients
+            .iter()
+            .filter(|recipient| recipient.status == RecipientStatus::Accepted)
+    }
+
+    /// Get the forward-path of every accepted recipient, without the angle brackets.
+    ///
+    /// See [`Self::accepted`].
+    pub fn forward_paths(&self) -> impl Iterator<Item = &AsciiStr> {
+        self.accepted().map(Recipient::forward_path)
+    }
+
+    /// Get which parts of the message should be returned in a failure notification, as given by
+    /// the `RET` parameter of `MAIL FROM`.
+    ///
+    /// [RFC 3461 section 4.3](https://www.rfc-editor.org/rfc/rfc3461.html#section-4.3).
+    #[must_use]
+    pub const fn ret(&self) -> Option<Ret> {
+        self.ret
+    }
+
+    /// Get the identifier the client gave to the transaction in the `ENVID` parameter of `MAIL
+    /// FROM`.
+    ///
+    /// This is still encoded as `xtext`, exactly as it was given.
+    ///
+    /// [RFC 3461 section 4.4](https://www.rfc-editor.org/rfc/rfc3461.html#section-4.4).
+    #[must_use]
+    pub fn envid(&self) -> Option<&AsciiStr> {
+        self.envid.as_deref()
+    }
+
+    /// Get the result of checking SPF for the reverse-path, or `None` if
+    /// [`crate::ServerConfig::spf_policy`] is [`crate::spf::SpfPolicy::Off`].
+    ///
+    /// For the null reverse-path, this is the result for the identity given in `HELO`, as it is
+    /// the only identity of the sender.
+    ///
+    /// [RFC 7208 section 2.4](https://www.rfc-editor.org/rfc/rfc7208.html#section-2.4).
+    #[cfg(feature = "spf")]
+    #[must_use]
+    pub const fn spf(&self) -> Option<&SpfVerdict> {
+        self.spf.as_ref()
+    }
+}
+
+/// A recipient of a message, as given by the client in `RCPT TO`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Recipient {
+    /// The forward-path, without the angle brackets.
+    pub(crate) forward_path: AsciiString,
+    /// The `NOTIFY` parameter.
+    pub(crate) notify: Option<Notify>,
+    /// The `ORCPT` parameter, still encoded as `xtext`.
+    pub(crate) orcpt: Option<AsciiString>,
+    /// Whether the server took responsibility for delivering to the recipient.
+    pub(crate) status: RecipientStatus,
+}
+
+impl Recipient {
+    /// Create a new, accepted [`Self`] with no parameters.
+    pub(crate) const fn new(forward_path: AsciiString) -> Self {
+        Self {
+            forward_path,
+            notify: None,
+            orcpt: None,
+            status: RecipientStatus::Accepted,
+        }
+    }
+
+    /// Get the forward-path, without the angle brackets.
+    #[must_use]
+    pub fn forward_path(&self) -> &AsciiStr {
+        &self.forward_path
+    }
+
+    /// Get when the client wants to be notified about delivery to this recipient, as given by the
+    /// `NOTIFY` parameter.
+    ///
+    /// [RFC 3461 section 4.1](https://www.rfc-editor.org/rfc/rfc3461.html#section-4.1).
+    #[must_use]
+    pub const fn notify(&self) -> Option<Notify> {
+        self.notify
+    }
+
+    /// Get the original recipient given by the `ORCPT` parameter, such as
+    /// `rfc822;jones@example.com`.
+    ///
+    /// This is still encoded as `xtext`, exactly as it was given.
+    ///
+    /// [RFC 3461 section 4.2](https://www.rfc-editor.org/rfc/rfc3461.html#section-4.2).
+    #[must_use]
+    pub fn orcpt(&self) -> Option<&AsciiStr> {
+        self.orcpt.as_deref()
+    }
+
+    /// Get how the server replied to the `RCPT TO` that gave this recipient.
+    #[must_use]
+    pub const fn status(&self) -> RecipientStatus {
+        self.status
+    }
+}
+
+/// How the server replied to a `RCPT TO`, which decides whether the message is delivered to that
+/// recipient.
+///
+/// A sink that hands the message on, such as `LmtpSink`, can replace it with how the next server
+/// replied.
+///
+/// [RFC 5321 section 4.1.1.3](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.3).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RecipientStatus {
+    /// The recipient was accepted with a `250` reply, so the server is responsible for delivering
+    /// the message to it.
+    Accepted,
+    /// The recipient was refused with a `4yz` reply, so the client may try it again later.
+    Deferred,
+    /// The recipient was refused with a `5yz` reply, so the client must not try it again.
+    Rejected,
+}
+
+/// When a delivery status notification should be sent for a recipient, as given by the `NOTIFY`
+/// parameter of `RCPT TO`.
+///
+/// A notification is never sent if none of the conditions are set (`NOTIFY=NEVER`).
+///
+/// [RFC 3461 section 4.1](https://www.rfc-editor.org/rfc/rfc3461.html#section-4.1).
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Notify {
+    /// Notify on successful delivery.
+    pub success: bool,
+    /// Notify on failed delivery.
+    pub failure: bool,
+    /// Notify if delivery is delayed.
+    pub delay: bool,
+}
+
+impl Notify {
+    /// Parse the value of a `NOTIFY` parameter, which is either `NEVER` or a comma-separated list
+    /// of `SUCCESS`, `FAILURE`, and `DELAY`, case-insensitively.
+    ///
+    /// Returns `None` if the value is invalid.
+    #[must_use]
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.eq_ignore_ascii_case("NEVER") {
+            return Some(Self::default());
+        }
+
+        let mut notify = Self::default();
+        for condition in value.split(',') {
+            let flag = match condition.to_ascii_uppercase().as_str() {
+                "SUCCESS" => &mut notify.success,
+                "FAILURE" => &mut notify.failure,
+                "DELAY" => &mut notify.delay,
+                _ => return None,
+            };
+            // Each condition may only be given once.
+            if *flag {
+                return None;
+            }
+            *flag = true;
+        }
+
+        Some(notify)
+    }
+
+    /// Get whether a notification should never be sent (`NOTIFY=NEVER`).
+    #[must_use]
+    pub const fn is_never(&self) -> bool {
+        !(self.success || self.failure || self.delay)
+    }
+}
+
+impl Display for Notify {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_never() {
+            return f.write_str("NEVER");
+        }
+
+        let conditions = [
+            (self.success, "SUCCESS"),
+            (self.failure, "FAILURE"),
+            (self.delay, "DELAY"),
+        ];
+        let conditions: Vec<&str> = conditions
+            .into_iter()
+            .filter_map(|(is_set, name)| is_set.then_some(name))
+            .collect();
+
+        f.write_str(&conditions.join(","))
+    }
+}
+
+/// Which parts of a message should be returned in a failure notification, as given by the `RET`
+/// parameter of `MAIL FROM`.
+///
+/// [RFC 3461 section 4.3](https://www.rfc-editor.org/rfc/rfc3461.html#section-4.3).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Ret {
+    /// Return the entire message (`RET=FULL`).
+    Full,
+    /// Return only the header section of the message (`RET=HDRS`).
+    Headers,
+}
+
+impl Ret {
+    /// Parse the value of a `RET` parameter, which is `FULL` or `HDRS`, case-insensitively.
+    ///
+    /// Returns `None` if the value is invalid.
+    #[must_use]
+    pub const fn parse(value: &str) -> Option<Self> {
+        if value.eq_ignore_ascii_case("FULL") {
+            Some(Self::Full)
+        } else if value.eq_ignore_ascii_case("HDRS") {
+            Some(Self::Headers)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for Ret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Full => "FULL",
+            Self::Headers => "HDRS",
+        })
+    }
+}
+
+/// Check whether `str` is valid `xtext`, the encoding used by the values of the DSN parameters.
+///
+/// [RFC 3461 section 4](https://www.rfc-editor.org/rfc/rfc3461.html#section-4).
+pub(crate) fn is_xtext(str: &str) -> bool {
+    let mut bytes = str.bytes();
+
+    while let Some(byte) = bytes.next() {
+        match byte {
+            // A `+` encodes a character as two uppercase hexadecimal digits.
+            b'+' => {
+                let is_hex = |byte: Option<u8>| {
+                    byte.is_some_and(|byte| matches!(byte, b'0'..=b'9' | b'A'..=b'F'))
+                };
+                if !(is_hex(bytes.next()) && is_hex(bytes.next())) {
+                    return false;
+                }
+            }
+            b'=' => return false,
+            33..=126 => (),
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// Check whether `str` is a valid value for the `ORCPT` parameter, which is an address type and
+/// an address encoded as `xtext`, separated by a semicolon.
+///
+/// [RFC 3461 section 4.2](https://www.rfc-editor.org/rfc/rfc3461.html#section-4.2).
+pub(crate) fn is_orcpt(str: &str) -> bool {
+    let Some((addr_type, address)) = str.split_once(';') else {
+        return false;
+    };
+
+    !addr_type.is_empty()
+        && addr_type
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+        && !address.is_empty()
+        && is_xtext(address)
+}
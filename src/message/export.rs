@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Serialize messages for storing them or handing them to other systems, see
+//! [`Message::to_eml_bytes`] and [`Message::to_json`].
+
+#[cfg(feature = "json")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "json")]
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+
+use super::{headers, trace, Message};
+
+/// Serialize `message` as an RFC 5322 message, such as for an `.eml` file or a mailbox.
+///
+/// This is the data of the message, which starts with the `Received:` header that the server
+/// added, with a `Return-Path:` header holding the reverse-path added above it if there is none.
+/// Bare `LF` line endings are replaced with `CRLF`, and the data ends with a line ending.
+///
+/// [RFC 5322 section 2.1](https://www.rfc-editor.org/rfc/rfc5322.html#section-2.1).
+#[must_use]
+pub fn eml(message: &Message) -> Vec<u8> {
+    let data = message.data();
+    let mut eml = Vec::with_capacity(data.len() + 64);
+
+    if !headers::parse(data).0.contains("Return-Path") {
+        eml.extend_from_slice(trace::return_path(message).as_bytes());
+    }
+
+    let mut previous = None;
+    for &byte in data {
+        if byte == b'\n' && previous != Some(b'\r') {
+            eml.push(b'\r');
+        }
+        eml.push(byte);
+        previous = Some(byte);
+    }
+    if !data.is_empty() && previous != Some(b'\n') {
+        eml.extend_from_slice(b"\r\n");
+    }
+
+    eml
+}
+
+/// Get the envelope of `message`, the details of its session, and a summary of its header as a
+/// JSON object, with its data in base64 as `data`.
+///
+/// Times are in milliseconds since the Unix epoch, and the `message_id` and `subject` are `null`
+/// if the message does not have them.
+///
+/// ```json
+/// {
+///   "session_id": "00000000075bcd15",
+///   "peer_addr": "192.0.2.1:49152",
+///   "helo": "client.example.com",
+///   "authenticated_user": null,
+///   "tls": false,
+///   "reverse_path": "smith@example.com",
+///   "recipients": ["jones@example.com"],
+///   "started_at": 1729170309000,
+///   "received_at": 1729170309250,
+///   "size": 1024,
+///   "hash": "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+///   "message_id": "<1234@client.example.com>",
+///   "subject": "Hello",
+///   "data": "UmVjZWl2ZWQ6IGZyb20g..."
+/// }
+/// ```
+#[cfg(feature = "json")]
+#[must_use]
+pub fn json(message: &Message) -> Value {
+    let mut json = metadata(message);
+    json["data"] = Value::String(STANDARD.encode(message.data()));
+
+    json
+}
+
+/// Get the object of [`json`] without `data`.
+#[cfg(feature = "json")]
+pub(crate) fn metadata(message: &Message) -> Value {
+    let session = message.session();
+    let envelope = message.envelope();
+    let headers = message.headers();
+    let millis = |time: SystemTime| {
+        let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        u64::try_from(since.as_millis()).unwrap_or(u64::MAX)
+    };
+
+    json!({
+        "session_id": session.id().to_string(),
+        "peer_addr": session.peer_addr().to_string(),
+        "helo": session.helo().map(ToString::to_string),
+        "authenticated_user": session.authenticated_user(),
+        "tls": session.is_tls(),
+        "reverse_path": envelope.reverse_path().map(ToString::to_string),
+        "recipients": envelope.forward_paths().map(ToString::to_string).collect::<Vec<_>>(),
+        "started_at": millis(message.started_at()),
+        "received_at": millis(message.received_at()),
+        "size": message.data().len(),
+        "hash": message.hash().to_string(),
+        "message_id": headers.message_id(),
+        "subject": headers.subject(),
+    })
+}
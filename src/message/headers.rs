@@ -0,0 +1,514 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Parses the header section of a message.
+//!
+//! See [`parse`] and [`Headers`].
+//!
+//! [RFC 5322 section 2.2](https://www.rfc-editor.org/rfc/rfc5322.html#section-2.2).
+
+use std::{fmt::Display, ops::Range, time::SystemTime};
+
+use super::date;
+use crate::str::{max_lengths, SmtpString, CRLF};
+
+/// Split the data of a message into its header fields and its body.
+///
+/// The header section ends at the first empty line, which is not part of either. If a line that
+/// is neither a header field nor the continuation of one is found first, the header section is
+/// considered to end there instead, and that line is the start of the body.
+///
+/// Field values are unfolded (see [`Field::value`]). Fields that are not valid UTF-8 are decoded
+/// lossily.
+///
+/// [RFC 5322 section 2.1](https://www.rfc-editor.org/rfc/rfc5322.html#section-2.1).
+#[must_use]
+pub fn parse(data: &[u8]) -> (Headers, &[u8]) {
+    let mut fields: Vec<Field> = Vec::new();
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let line_end = rest
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(rest.len(), |index| index + 1);
+        let (line, next) = rest.split_at(line_end);
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+
+        // The empty line that separates the header section from the body.
+        if content.is_empty() {
+            return (Headers { fields }, next);
+        }
+
+        // A folded continuation of the previous field.
+        //
+        // <https://www.rfc-editor.org/rfc/rfc5322.html#section-2.2.3>
+        if matches!(content.first(), Some(b' ' | b'\t')) {
+            if let Some(field) = fields.last_mut() {
+                field.value.push_str(&String::from_utf8_lossy(content));
+                rest = next;
+                continue;
+            }
+        }
+
+        let Some((name, value)) = split_field(content) else {
+            break;
+        };
+        fields.push(Field {
+            name: String::from_utf8_lossy(name).into_owned(),
+            value: String::from_utf8_lossy(value).into_owned(),
+        });
+
+        rest = next;
+    }
+
+    (Headers { fields }, rest)
+}
+
+/// Get whether a line (without its line ending) can be part of the header section, either as a
+/// field or as the continuation of a folded one.
+///
+/// The header section ends at the first line that cannot, see [`parse`].
+pub(crate) fn is_header_line(line: &[u8]) -> bool {
+    matches!(line.first(), Some(b' ' | b'\t')) || split_field(line).is_some()
+}
+
+/// Find every occurrence of the field with the given name in the data of a message, as the ranges
+/// of bytes that they span, including their folded lines and line endings.
+///
+/// The header section is walked the same way as [`parse`].
+pub(crate) fn find(data: &[u8], name: &str) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    // Whether the last field seen has the given name, for its folded continuations.
+    let mut last: Option<bool> = None;
+    let mut start = 0;
+
+    while start < data.len() {
+        let end = data[start..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(data.len(), |index| start + index + 1);
+        let line = &data[start..end];
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+
+        if content.is_empty() {
+            break;
+        }
+
+        match (matches!(content.first(), Some(b' ' | b'\t')), last) {
+            (true, Some(true)) => {
+                if let Some(range) = ranges.last_mut() {
+                    range.end = end;
+                }
+            }
+            (true, Some(false)) => (),
+            _ => {
+                let Some((field_name, _)) = split_field(content) else {
+                    break;
+                };
+                let is_match = field_name.eq_ignore_ascii_case(name.as_bytes());
+
+                if is_match {
+                    ranges.push(start..end);
+                }
+                last = Some(is_match);
+            }
+        }
+
+        start = end;
+    }
+
+    ranges
+}
+
+/// Create a header field from its name and value, including its line ending.
+///
+/// `value` may be folded onto multiple lines, as long as every line after the first starts with
+/// whitespace. Line endings in `value` are converted to `CRLF`, see [`SmtpString::new`].
+///
+/// # Errors
+///
+/// - [`HeaderError::InvalidName`] if `name` is empty or not printable ASCII without colons.
+/// - [`HeaderError::InvalidValue`] if `value` is not ASCII, or has a line that is empty or does
+///   not start with whitespace after the first.
+/// - [`HeaderError::TooLong`] if a line of the field is longer than 998 characters, per [RFC 5322
+///   section 2.1.1](https://www.rfc-editor.org/rfc/rfc5322.html#section-2.1.1).
+pub(crate) fn field(name: &str, value: &str) -> Result<SmtpString, HeaderError> {
+    let is_valid_name = !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| (33..=126).contains(&byte) && byte != b':');
+    if !is_valid_name {
+        return Err(HeaderError::InvalidName);
+    }
+
+    let field = SmtpString::new(&format!("{name}: {value}{CRLF}"))
+        .map_err(|_| HeaderError::InvalidValue)?;
+    let field_str = field.as_inner().as_str();
+    let mut lines = field_str
+        .strip_suffix(CRLF)
+        .unwrap_or(field_str)
+        .split(CRLF);
+
+    if lines
+        .clone()
+        .any(|line| line.len() + CRLF.len() > max_lengths::TEXT_LINE)
+    {
+        return Err(HeaderError::TooLong);
+    }
+    // Skips the first line, which starts with the name.
+    lines.next();
+    if !lines.all(|line| line.starts_with([' ', '\t']) && !line.trim().is_empty()) {
+        return Err(HeaderError::InvalidValue);
+    }
+
+    Ok(field)
+}
+
+/// Split a header field into its name and its value, if it is one.
+///
+/// Field names are printable ASCII characters other than the colon.
+///
+/// [RFC 5322 section 2.2](https://www.rfc-editor.org/rfc/rfc5322.html#section-2.2).
+fn split_field(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let colon = line.iter().position(|&byte| byte == b':')?;
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+
+    // Some old software puts whitespace between the name and the colon.
+    //
+    // <https://www.rfc-editor.org/rfc/rfc5322.html#section-4.5>
+    let name = name.trim_ascii_end();
+
+    if name.is_empty() || !name.iter().all(|&byte| (33..=126).contains(&byte)) {
+        return None;
+    }
+
+    Some((name, value))
+}
+
+/// The header fields of a message, in the order they appear.
+///
+/// Fields can appear more than once (such as `Received:`), so every occurrence is kept. Lookups by
+/// name are case-insensitive.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Headers {
+    /// Every field, in the order they appear.
+    fields: Vec<Field>,
+}
+
+impl Headers {
+    /// Get every field, in the order they appear.
+    #[must_use]
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Get the value of the first field with the given name, if any.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+            .map(Field::value)
+    }
+
+    /// Get the values of every field with the given name, in the order they appear.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |field| field.name.eq_ignore_ascii_case(name))
+            .map(Field::value)
+    }
+
+    /// Get whether there is at least one field with the given name.
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Get the number of fields.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Get whether there are no fields.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Get the authors of the message from the `From:` field.
+    ///
+    /// [RFC 5322 section 3.6.2](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.6.2).
+    #[must_use]
+    pub fn from(&self) -> Vec<Mailbox> {
+        self.get("From")
+            .map(Mailbox::parse_list)
+            .unwrap_or_default()
+    }
+
+    /// Get the primary recipients of the message from every `To:` field.
+    ///
+    /// [RFC 5322 section 3.6.3](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.6.3).
+    #[must_use]
+    pub fn to(&self) -> Vec<Mailbox> {
+        self.get_all("To").flat_map(Mailbox::parse_list).collect()
+    }
+
+    /// Get the subject of the message from the `Subject:` field.
+    ///
+    /// [RFC 5322 section 3.6.5](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.6.5).
+    #[must_use]
+    pub fn subject(&self) -> Option<&str> {
+        self.get("Subject")
+    }
+
+    /// Get when the author considered the message ready to send from the `Date:` field.
+    ///
+    /// Returns `None` if there is no `Date:` field or if it is malformed, see [`date::parse`].
+    ///
+    /// [RFC 5322 section 3.6.1](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.6.1).
+    #[must_use]
+    pub fn date(&self) -> Option<SystemTime> {
+        self.get("Date").and_then(date::parse)
+    }
+
+    /// Get the unique identifier of the message from the `Message-ID:` field, without the angle
+    /// brackets.
+    ///
+    /// [RFC 5322 section 3.6.4](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.6.4).
+    #[must_use]
+    pub fn message_id(&self) -> Option<&str> {
+        let id = self.get("Message-ID")?;
+
+        Some(
+            id.strip_prefix('<')
+                .and_then(|id| id.strip_suffix('>'))
+                .unwrap_or(id),
+        )
+    }
+}
+
+/// A header field of a message.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Field {
+    /// The name of the field, as it was given.
+    name: String,
+    /// The unfolded value of the field, as it was given.
+    value: String,
+}
+
+impl Field {
+    /// Get the name of the field, with its case preserved.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the value of the field, unfolded and trimmed of surrounding whitespace.
+    ///
+    /// Unfolding removes the line endings that were inserted to break long fields onto multiple
+    /// lines, per [RFC 5322 section
+    /// 2.2.3](https://www.rfc-editor.org/rfc/rfc5322.html#section-2.2.3).
+    #[must_use]
+    pub fn value(&self) -> &str {
+        self.value.trim()
+    }
+}
+
+/// A mailbox from an address field, such as `Jane Smith <smith@example.com>`.
+///
+/// [RFC 5322 section 3.4](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.4).
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mailbox {
+    /// The display name, if any, with quotes removed.
+    name: Option<String>,
+    /// The address, in the form of `local-part@domain`.
+    address: String,
+}
+
+impl Mailbox {
+    /// Get the display name of the mailbox, if any, with quotes removed.
+    #[must_use]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Get the address of the mailbox, in the form of `local-part@domain`.
+    #[must_use]
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Parse a comma-separated list of mailboxes, such as the value of a `To:` field.
+    ///
+    /// Group names are discarded in favor of their members. Comments are removed. Malformed
+    /// entries are skipped.
+    ///
+    /// [RFC 5322 section 3.4](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.4).
+    #[must_use]
+    pub fn parse_list(list: &str) -> Vec<Self> {
+        split_outside_quotes(&remove_comments(list), &[',', ':', ';'])
+            .into_iter()
+            .filter_map(|entry| Self::parse(&entry))
+            .collect()
+    }
+
+    /// Parse a single mailbox, either in the form of `name <address>` or of a bare address.
+    fn parse(mailbox: &str) -> Option<Self> {
+        let mailbox = mailbox.trim();
+
+        let (name, address) = match mailbox.rfind('<') {
+            Some(start) => {
+                let address = mailbox[start + 1..].strip_suffix('>')?;
+                let name = unquote(mailbox[..start].trim());
+
+                (Some(name).filter(|name| !name.is_empty()), address.trim())
+            }
+            // Without angle brackets, this is only an address if it looks like one, so that the
+            // names of groups are skipped.
+            None if mailbox.contains('@') => (None, mailbox),
+            None => return None,
+        };
+
+        if address.is_empty() || address.contains(char::is_whitespace) {
+            return None;
+        }
+
+        Some(Self {
+            name,
+            address: address.to_owned(),
+        })
+    }
+}
+
+/// Remove the comments (text in parentheses, which may be nested) outside of quoted strings.
+///
+/// [RFC 5322 section 3.2.2](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.2.2).
+fn remove_comments(str: &str) -> String {
+    let mut output = String::with_capacity(str.len());
+    let mut depth = 0_usize;
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for char in str.chars() {
+        if escaped {
+            escaped = false;
+        } else if char == '\\' {
+            escaped = true;
+        } else if char == '"' && depth == 0 {
+            quoted = !quoted;
+        } else if char == '(' && !quoted {
+            depth += 1;
+            continue;
+        } else if char == ')' && !quoted && depth > 0 {
+            depth -= 1;
+            continue;
+        }
+
+        if depth == 0 {
+            output.push(char);
+        }
+    }
+
+    output
+}
+
+/// Split a string on any of `separators` that are outside of quoted strings and angle brackets.
+fn split_outside_quotes(str: &str, separators: &[char]) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut bracketed = false;
+    let mut escaped = false;
+
+    for char in str.chars() {
+        if escaped {
+            escaped = false;
+        } else if char == '\\' {
+            escaped = true;
+        } else if char == '"' {
+            quoted = !quoted;
+        } else if !quoted && char == '<' {
+            bracketed = true;
+        } else if !quoted && char == '>' {
+            bracketed = false;
+        } else if !quoted && !bracketed && separators.contains(&char) {
+            parts.push(std::mem::take(&mut current));
+            continue;
+        }
+
+        current.push(char);
+    }
+    parts.push(current);
+
+    parts
+}
+
+/// Remove the quotes and escapes from a quoted string, or return it as-is if it is not quoted.
+///
+/// [RFC 5322 section 3.2.4](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.2.4).
+pub(crate) fn unquote(str: &str) -> String {
+    let Some(inner) = str.strip_prefix('"').and_then(|str| str.strip_suffix('"')) else {
+        return str.to_owned();
+    };
+
+    let mut output = String::with_capacity(inner.len());
+    let mut escaped = false;
+
+    for char in inner.chars() {
+        if char == '\\' && !escaped {
+            escaped = true;
+            continue;
+        }
+
+        escaped = false;
+        output.push(char);
+    }
+
+    output
+}
+
+/// Possible error states encountered when editing the header fields of a [`super::Message`].
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum HeaderError {
+    /// The name of the field is empty, or is not printable ASCII without colons.
+    InvalidName,
+    /// The value of the field is not ASCII, or has a line after the first that is empty or does not
+    /// start with whitespace.
+    InvalidValue,
+    /// A line of the field is longer than 998 characters.
+    TooLong,
+}
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::InvalidName => "header field has an invalid name",
+            Self::InvalidValue => "header field has a non-ASCII or improperly folded value",
+            Self::TooLong => "header field has a line longer than 998 characters",
+        })
+    }
+}
+
+impl std::error::Error for HeaderError {}
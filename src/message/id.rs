@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Generating and validating message identifiers for the `Message-ID:` header.
+//!
+//! See [RFC 5322 section 3.6.4](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.6.4).
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Distinguishes identifiers generated by this process within the same instant.
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Generate a new, unique message identifier on `domain`, including the angle brackets, such as
+/// `<18003c8a3f0c2a40.1f2e.0@mx.example.com>`.
+///
+/// The left side is made from the current time, the process ID, and a counter, so identifiers are
+/// unique as long as `domain` is unique to the host. `domain` is not validated, see [`is_valid`].
+///
+/// [RFC 5322 section 3.6.4](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.6.4).
+#[must_use]
+pub fn generate(domain: &str) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    format!("<{time:x}.{:x}.{count:x}@{domain}>", std::process::id())
+}
+
+/// Check whether `id` is a valid message identifier, including the angle brackets, such as the
+/// value of a `Message-ID:` header.
+///
+/// Only the strict syntax is accepted, where both sides are a `dot-atom-text` or the right side is
+/// a domain literal. Surrounding whitespace is ignored, but comments are not allowed.
+///
+/// [RFC 5322 section 3.6.4](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.6.4).
+#[must_use]
+pub fn is_valid(id: &str) -> bool {
+    let Some(id) = id
+        .trim()
+        .strip_prefix('<')
+        .and_then(|id| id.strip_suffix('>'))
+    else {
+        return false;
+    };
+    let Some((left, right)) = id.rsplit_once('@') else {
+        return false;
+    };
+
+    let is_literal = right
+        .strip_prefix('[')
+        .and_then(|literal| literal.strip_suffix(']'))
+        .is_some_and(|literal| {
+            literal
+                .bytes()
+                .all(|byte| matches!(byte, 33..=90 | 94..=126))
+        });
+
+    is_dot_atom_text(left) && (is_literal || is_dot_atom_text(right))
+}
+
+/// Check whether `str` is a `dot-atom-text`, which is runs of `atext` separated by single periods.
+///
+/// [RFC 5322 section 3.2.3](https://www.rfc-editor.org/rfc/rfc5322.html#section-3.2.3).
+fn is_dot_atom_text(str: &str) -> bool {
+    str.split('.').all(|atom| {
+        !atom.is_empty()
+            && atom
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-/=?^_`{|}~".contains(&byte))
+    })
+}
@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Splits the body of a message into lines.
+//!
+//! See [`Lines`].
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_core::Stream;
+
+/// The lines of the body of a message, without their line endings.
+///
+/// `<CRLF>`, a bare `<LF>`, and a bare `<CR>` all end a line, so lines are the same no matter
+/// which line endings the client used. The transparency dot-stuffing was already removed when the
+/// data was received.
+///
+/// This is a [`Stream`] that is always ready, so it can be consumed the same way as the
+/// [`super::stream::Body`] of a [`super::stream::StreamingMessage`]. Each line is a slice of the
+/// data of the message, so it does not copy the data.
+///
+/// Created by [`super::Message::lines`].
+#[derive(Debug, Clone)]
+pub struct Lines {
+    /// The rest of the body that has not yet been split.
+    rest: Bytes,
+}
+
+impl Lines {
+    /// Create a new [`Self`] over `body`.
+    pub(crate) const fn new(body: Bytes) -> Self {
+        Self { rest: body }
+    }
+
+    /// Split the next line off of [`Self::rest`].
+    fn split_line(&mut self) -> Option<Bytes> {
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let Some(end) = self
+            .rest
+            .iter()
+            .position(|&byte| matches!(byte, b'\r' | b'\n'))
+        else {
+            // The last line has no line ending.
+            return Some(std::mem::take(&mut self.rest));
+        };
+        let ending = if self.rest[end..].starts_with(b"\r\n") {
+            2
+        } else {
+            1
+        };
+
+        let line = self.rest.split_to(end);
+        let _ = self.rest.split_to(ending);
+
+        Some(line)
+    }
+}
+
+impl Stream for Lines {
+    type Item = Bytes;
+
+    fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.split_line())
+    }
+}
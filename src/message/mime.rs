@@ -0,0 +1,423 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Parses the MIME structure of a message into its parts.
+//!
+//! See [`parts`].
+//!
+//! [RFC 2045](https://www.rfc-editor.org/rfc/rfc2045.html) and [RFC
+//! 2046](https://www.rfc-editor.org/rfc/rfc2046.html).
+
+use bytes::Bytes;
+
+use super::{
+    decode,
+    headers::{self, unquote, Headers},
+};
+
+/// How many multipart entities can be nested inside each other before the rest are treated as
+/// opaque data.
+///
+/// Guards against messages crafted to exhaust the stack.
+const MAX_DEPTH: usize = 16;
+
+/// Walk the MIME structure of the data of a message, returning every part that is not itself a
+/// multipart entity, in the order they appear.
+///
+/// Nested multipart entities are flattened, and the preamble and epilogue of each are discarded. A
+/// message that is not multipart is returned as a single part.
+///
+/// The data of each part is decoded according to its `Content-Transfer-Encoding:` field, see
+/// [`Part::data`]. Parts that do not need decoding are slices of `data`, so they do not copy it.
+#[must_use]
+pub fn parts(data: &Bytes) -> Vec<Part> {
+    let (headers, body) = headers::parse(data);
+
+    let mut parts = Vec::new();
+    walk(data, headers, body, 0, &mut parts);
+
+    parts
+}
+
+/// Add the parts of an entity to `parts`, descending into it if it is multipart.
+///
+/// `body` must be a slice of `data`.
+fn walk(data: &Bytes, headers: Headers, body: &[u8], depth: usize, parts: &mut Vec<Part>) {
+    let content_type = headers
+        .get("Content-Type")
+        .map(ContentType::parse)
+        .unwrap_or_default();
+
+    if content_type.is_multipart() && depth < MAX_DEPTH {
+        if let Some(boundary) = content_type.parameter("boundary") {
+            for entity in split_multipart(body, boundary.as_bytes()) {
+                let (headers, body) = headers::parse(entity);
+                walk(data, headers, body, depth + 1, parts);
+            }
+
+            return;
+        }
+    }
+
+    let decoded = decode::transfer_encoding(
+        &data.slice_ref(body),
+        headers.get("Content-Transfer-Encoding"),
+    );
+
+    parts.push(Part {
+        headers,
+        content_type,
+        data: decoded,
+    });
+}
+
+/// Split the body of a multipart entity into the entities between its boundaries.
+///
+/// The line ending before each delimiter line belongs to the delimiter, not the entity before it.
+///
+/// [RFC 2046 section 5.1.1](https://www.rfc-editor.org/rfc/rfc2046.html#section-5.1.1).
+fn split_multipart<'a>(body: &'a [u8], boundary: &[u8]) -> Vec<&'a [u8]> {
+    let mut entities = Vec::new();
+    // Where the current entity started, or `None` if still in the preamble.
+    let mut start = None;
+    let mut offset = 0;
+
+    for line in body.split_inclusive(|&byte| byte == b'\n') {
+        let line_start = offset;
+        offset += line.len();
+
+        let Some(rest) = line
+            .strip_prefix(b"--")
+            .and_then(|line| line.strip_prefix(boundary))
+        else {
+            continue;
+        };
+        let is_closing = rest.starts_with(b"--");
+        let rest = if is_closing { &rest[2..] } else { rest };
+        // Delimiter lines may have trailing whitespace, but nothing else.
+        if !rest.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+
+        if let Some(start) = start {
+            let entity = &body[start..line_start];
+            let entity = entity.strip_suffix(b"\n").unwrap_or(entity);
+            let entity = entity.strip_suffix(b"\r").unwrap_or(entity);
+            entities.push(entity);
+        }
+
+        if is_closing {
+            return entities;
+        }
+        start = Some(offset);
+    }
+
+    // Tolerate a missing closing delimiter by treating the rest as the last entity.
+    if let Some(start) = start {
+        entities.push(&body[start..]);
+    }
+
+    entities
+}
+
+/// A part of a MIME message, which is not itself multipart.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Part {
+    /// The header fields of the part.
+    headers: Headers,
+    /// The parsed `Content-Type:` field of the part.
+    content_type: ContentType,
+    /// The data of the part, decoded according to its `Content-Transfer-Encoding:` field.
+    data: Bytes,
+}
+
+impl Part {
+    /// Get the header fields of the part.
+    #[must_use]
+    pub const fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Get the type of the data of the part.
+    ///
+    /// Defaults to `text/plain; charset=us-ascii` if the part does not have a `Content-Type:`
+    /// field, per [RFC 2045 section 5.2](https://www.rfc-editor.org/rfc/rfc2045.html#section-5.2).
+    #[must_use]
+    pub const fn content_type(&self) -> &ContentType {
+        &self.content_type
+    }
+
+    /// Get the data of the part, decoded according to its `Content-Transfer-Encoding:` field.
+    ///
+    /// `base64` and `quoted-printable` are decoded. Anything else is returned as-is, as a slice
+    /// of the data of the message.
+    ///
+    /// [RFC 2045 section 6](https://www.rfc-editor.org/rfc/rfc2045.html#section-6).
+    #[must_use]
+    pub const fn data(&self) -> &Bytes {
+        &self.data
+    }
+
+    /// Get the data of the part as UTF-8 text, decoded according to the `charset` parameter of its
+    /// `Content-Type:` field.
+    ///
+    /// Returns `None` if the part is not a `text/*` type. See [`decode::charset`].
+    #[must_use]
+    pub fn text(&self) -> Option<String> {
+        if !self.content_type.mime_type().starts_with("text/") {
+            return None;
+        }
+
+        Some(decode::charset(&self.data, self.content_type.parameter("charset")).into_owned())
+    }
+
+    /// Get the name of the file that the part contains, if any.
+    ///
+    /// Taken from the `filename` parameter of the `Content-Disposition:` field (including the
+    /// percent-encoded `filename*` form of [RFC 2231](https://www.rfc-editor.org/rfc/rfc2231.html)
+    /// in UTF-8 or US-ASCII), or else the `name` parameter of the `Content-Type:` field.
+    ///
+    /// [RFC 2183 section 2.3](https://www.rfc-editor.org/rfc/rfc2183.html#section-2.3).
+    #[must_use]
+    pub fn filename(&self) -> Option<String> {
+        let (_, parameters) = self
+            .headers
+            .get("Content-Disposition")
+            .map(parse_parameters)
+            .unwrap_or_default();
+        let parameter = |name: &str| {
+            parameters
+                .iter()
+                .find(|(parameter, _)| parameter == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        parameter("filename*")
+            .and_then(decode_extended_value)
+            .or_else(|| parameter("filename").map(ToOwned::to_owned))
+            .or_else(|| self.content_type.parameter("name").map(ToOwned::to_owned))
+    }
+
+    /// Get whether the part is an attachment, rather than part of the text of the message.
+    ///
+    /// A part is an attachment if its `Content-Disposition:` field is `attachment`, or if it has
+    /// no `Content-Disposition:` field but does have a [`Self::filename`].
+    ///
+    /// [RFC 2183 section 2](https://www.rfc-editor.org/rfc/rfc2183.html#section-2).
+    #[must_use]
+    pub fn is_attachment(&self) -> bool {
+        self.headers.get("Content-Disposition").map_or_else(
+            || self.filename().is_some(),
+            |disposition| parse_parameters(disposition).0 == "attachment",
+        )
+    }
+}
+
+/// Get every part of the data of a message that is an attachment, in the order they appear.
+///
+/// See [`parts`] and [`Part::is_attachment`].
+#[must_use]
+pub fn attachments(data: &Bytes) -> Vec<Attachment> {
+    parts(data)
+        .into_iter()
+        .filter(Part::is_attachment)
+        .map(|part| Attachment {
+            filename: part.filename(),
+            content_type: part.content_type,
+            data: part.data,
+        })
+        .collect()
+}
+
+/// A part of a MIME message that is a file attached to it, rather than part of its text.
+///
+/// See [`attachments`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Attachment {
+    /// The name of the file, if given.
+    filename: Option<String>,
+    /// The parsed `Content-Type:` field of the part.
+    content_type: ContentType,
+    /// The data of the file, decoded according to its `Content-Transfer-Encoding:` field.
+    data: Bytes,
+}
+
+impl Attachment {
+    /// Get the name of the file, if the sender gave one.
+    ///
+    /// This comes from the client, so it must not be trusted as a path. See [`Part::filename`].
+    #[must_use]
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// Get the type of the file, as declared by the sender.
+    ///
+    /// See [`Part::content_type`].
+    #[must_use]
+    pub const fn content_type(&self) -> &ContentType {
+        &self.content_type
+    }
+
+    /// Get the size of the file in bytes, after decoding.
+    #[must_use]
+    pub const fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Get the data of the file, decoded according to its `Content-Transfer-Encoding:` field.
+    ///
+    /// See [`Part::data`].
+    #[must_use]
+    pub const fn data(&self) -> &Bytes {
+        &self.data
+    }
+}
+
+/// The parsed value of a `Content-Type:` field, such as `text/plain; charset=utf-8`.
+///
+/// [RFC 2045 section 5](https://www.rfc-editor.org/rfc/rfc2045.html#section-5).
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContentType {
+    /// The type and subtype, in lowercase, such as `text/plain`.
+    mime_type: String,
+    /// The parameters, with names in lowercase and values unquoted.
+    parameters: Vec<(String, String)>,
+}
+
+impl ContentType {
+    /// Parse the value of a `Content-Type:` field.
+    ///
+    /// Falls back to [`Self::default`] if the type is malformed.
+    #[must_use]
+    pub fn parse(value: &str) -> Self {
+        let (mime_type, parameters) = parse_parameters(value);
+
+        let Some((kind, subtype)) = mime_type.split_once('/') else {
+            return Self::default();
+        };
+        if kind.is_empty() || subtype.is_empty() {
+            return Self::default();
+        }
+
+        Self {
+            mime_type,
+            parameters,
+        }
+    }
+
+    /// Get the type and subtype, in lowercase, such as `text/plain`.
+    #[must_use]
+    pub fn mime_type(&self) -> &str {
+        &self.mime_type
+    }
+
+    /// Get the value of a parameter by its case-insensitive name, such as `charset`.
+    #[must_use]
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(parameter, _)| parameter.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Get whether this is a `multipart/*` type.
+    #[must_use]
+    pub fn is_multipart(&self) -> bool {
+        self.mime_type.starts_with("multipart/")
+    }
+}
+
+impl Default for ContentType {
+    /// `text/plain; charset=us-ascii`, per [RFC 2045 section
+    /// 5.2](https://www.rfc-editor.org/rfc/rfc2045.html#section-5.2).
+    fn default() -> Self {
+        Self {
+            mime_type: "text/plain".to_owned(),
+            parameters: vec![("charset".to_owned(), "us-ascii".to_owned())],
+        }
+    }
+}
+
+/// Parse the value of a field made of a value followed by parameters, such as `Content-Type:` or
+/// `Content-Disposition:`.
+///
+/// Returns the value in lowercase, and the parameters with names in lowercase and values unquoted.
+fn parse_parameters(value: &str) -> (String, Vec<(String, String)>) {
+    let mut items = split_parameters(value).into_iter();
+
+    let value = items.next().unwrap_or_default().trim().to_ascii_lowercase();
+    let parameters = items
+        .filter_map(|parameter| {
+            let (name, value) = parameter.split_once('=')?;
+            Some((name.trim().to_ascii_lowercase(), unquote(value.trim())))
+        })
+        .collect();
+
+    (value, parameters)
+}
+
+/// Decode an extended parameter value, such as `utf-8'en'%E2%82%AC%20rates.txt`.
+///
+/// Returns `None` if the value is malformed or its character set is not UTF-8 or US-ASCII.
+///
+/// [RFC 2231 section 4](https://www.rfc-editor.org/rfc/rfc2231.html#section-4).
+fn decode_extended_value(value: &str) -> Option<String> {
+    let mut items = value.splitn(3, '\'');
+    let (charset, _language, encoded) = (items.next()?, items.next()?, items.next()?);
+    if !(charset.eq_ignore_ascii_case("utf-8") || charset.eq_ignore_ascii_case("us-ascii")) {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let high = char::from(bytes.next()?).to_digit(16)?;
+            let low = char::from(bytes.next()?).to_digit(16)?;
+            decoded.push(u8::try_from(high << 4 | low).ok()?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Split the value of a field on semicolons outside of quoted strings.
+fn split_parameters(value: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+
+    for (index, char) in value.char_indices() {
+        match char {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                items.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => (),
+        }
+    }
+    items.push(&value[start..]);
+
+    items
+}
@@ -0,0 +1,634 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The messages received through SMTP sessions.
+//!
+//! See [`Message`].
+
+use std::{fmt::Display, ops::Range, time::SystemTime};
+
+use bytes::{Bytes, BytesMut};
+use envelope::Envelope;
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "arc")]
+use crate::arc::ArcResult;
+#[cfg(feature = "clamav")]
+use crate::clamav::ClamAvVerdict;
+#[cfg(feature = "dmarc")]
+use crate::dmarc::DmarcVerdict;
+#[cfg(feature = "filter")]
+use crate::filter::FilterVerdict;
+#[cfg(feature = "rspamd")]
+use crate::rspamd::RspamdVerdict;
+use crate::session::SessionInfo;
+#[cfg(feature = "spamassassin")]
+use crate::spamassassin::SpamAssassinVerdict;
+#[cfg(feature = "spool")]
+use crate::spool::SpoolId;
+
+pub mod builder;
+#[cfg(any(feature = "lettre", feature = "mail-parser"))]
+pub mod convert;
+pub mod date;
+pub mod decode;
+pub mod envelope;
+pub mod export;
+pub mod headers;
+pub mod id;
+pub mod lines;
+#[cfg(feature = "mime")]
+pub mod mime;
+pub mod pending;
+pub mod stream;
+#[cfg(test)]
+mod test;
+pub mod trace;
+
+/// An SMTP message, as received at the end of a mail transaction.
+///
+/// Holds the envelope (from the `MAIL` and `RCPT` commands), the data (from the `DATA` command),
+/// and details about the SMTP session that it was received through.
+///
+/// The data is stored as [`Bytes`], so cloning a [`Self`] (such as to hand it to more than one
+/// consumer) does not copy the data, and views into it such as [`Self::body`] are cheap slices of
+/// the same allocation.
+///
+/// See [RFC 5321 section 2.3.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-2.3.1) and
+/// [section 3.3](https://www.rfc-editor.org/rfc/rfc5321.html#section-3.3).
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Message {
+    /// The envelope from `MAIL FROM` and every accepted `RCPT TO`.
+    envelope: Envelope,
+    /// The details of the session that the message was received through.
+    session: SessionInfo,
+    /// When the mail transaction was started by `MAIL FROM`.
+    started_at: SystemTime,
+    /// When the end of the data was received.
+    received_at: SystemTime,
+    /// The data of the message, with transparency dot-stuffing removed.
+    data: Bytes,
+    /// The size of the data as received from the client.
+    size: Size,
+    /// The SHA-256 digest of the data as received from the client.
+    hash: ContentHash,
+    /// The result of evaluating DMARC for the message, or `None` if it was not evaluated.
+    #[cfg(feature = "dmarc")]
+    dmarc: Option<DmarcVerdict>,
+    /// The result of validating the ARC of the message, or `None` if it was not validated.
+    #[cfg(feature = "arc")]
+    arc: Option<ArcResult>,
+    /// The rules that matched the message, or `None` if there are none to check.
+    #[cfg(feature = "filter")]
+    filter: Option<FilterVerdict>,
+    /// The result of scanning the message with ClamAV, or `None` if it was not scanned.
+    #[cfg(feature = "clamav")]
+    clamav: Option<ClamAvVerdict>,
+    /// The result of scanning the message with Rspamd, or `None` if it was not scanned.
+    #[cfg(feature = "rspamd")]
+    rspamd: Option<RspamdVerdict>,
+    /// The result of scoring the message with SpamAssassin, or `None` if it was not scored.
+    #[cfg(feature = "spamassassin")]
+    spamassassin: Option<SpamAssassinVerdict>,
+    /// The entry that the message is kept under in the spool, or `None` if it was not spooled.
+    #[cfg(feature = "spool")]
+    spool_id: Option<SpoolId>,
+}
+
+impl Message {
+    /// Create a new [`Self`] from a completed mail transaction, marking it as received now.
+    pub(crate) fn new(
+        envelope: Envelope,
+        session: SessionInfo,
+        started_at: SystemTime,
+        data: Bytes,
+    ) -> Self {
+        Self {
+            envelope,
+            session,
+            started_at,
+            received_at: SystemTime::now(),
+            size: Size {
+                header: 0,
+                body: data.len(),
+            },
+            hash: ContentHash::default(),
+            data,
+            #[cfg(feature = "dmarc")]
+            dmarc: None,
+            #[cfg(feature = "arc")]
+            arc: None,
+            #[cfg(feature = "filter")]
+            filter: None,
+            #[cfg(feature = "clamav")]
+            clamav: None,
+            #[cfg(feature = "rspamd")]
+            rspamd: None,
+            #[cfg(feature = "spamassassin")]
+            spamassassin: None,
+            #[cfg(feature = "spool")]
+            spool_id: None,
+        }
+    }
+
+    /// Set the digest of the data as it was computed during reception.
+    pub(crate) const fn with_hash(mut self, hash: ContentHash) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// Set the result of evaluating DMARC for the message.
+    #[cfg(feature = "dmarc")]
+    pub(crate) fn with_dmarc(mut self, verdict: DmarcVerdict) -> Self {
+        self.dmarc = Some(verdict);
+        self
+    }
+
+    /// Set the result of validating the ARC of the message.
+    #[cfg(feature = "arc")]
+    pub(crate) const fn with_arc(mut self, result: ArcResult) -> Self {
+        self.arc = Some(result);
+        self
+    }
+
+    /// Set the rules that matched the message.
+    #[cfg(feature = "filter")]
+    pub(crate) fn with_filter(mut self, verdict: FilterVerdict) -> Self {
+        self.filter = Some(verdict);
+        self
+    }
+
+    /// Set the result of scanning the message with ClamAV.
+    #[cfg(feature = "clamav")]
+    pub(crate) fn with_clamav(mut self, verdict: ClamAvVerdict) -> Self {
+        self.clamav = Some(verdict);
+        self
+    }
+
+    /// Set the result of scanning the message with Rspamd.
+    #[cfg(feature = "rspamd")]
+    pub(crate) fn with_rspamd(mut self, verdict: RspamdVerdict) -> Self {
+        self.rspamd = Some(verdict);
+        self
+    }
+
+    /// Set the result of scoring the message with SpamAssassin.
+    #[cfg(feature = "spamassassin")]
+    pub(crate) fn with_spamassassin(mut self, verdict: SpamAssassinVerdict) -> Self {
+        self.spamassassin = Some(verdict);
+        self
+    }
+
+    /// Set the entry that the message is kept under in the spool.
+    #[cfg(feature = "spool")]
+    pub(crate) fn with_spool_id(mut self, id: SpoolId) -> Self {
+        self.spool_id = Some(id);
+        self
+    }
+
+    /// Set when the end of the data was received, such as when reading the message back from a
+    /// spool.
+    #[cfg(feature = "spool")]
+    pub(crate) const fn with_received_at(mut self, time: SystemTime) -> Self {
+        self.received_at = time;
+        self
+    }
+
+    /// Get the envelope mutably, such as to record how a downstream server replied to each
+    /// recipient.
+    #[cfg(feature = "lmtp")]
+    pub(crate) const fn envelope_mut(&mut self) -> &mut Envelope {
+        &mut self.envelope
+    }
+
+    /// Create a [`builder::MessageBuilder`] to construct a [`Self`] without an SMTP session, such as
+    /// for testing.
+    pub fn builder() -> builder::MessageBuilder {
+        builder::MessageBuilder::new()
+    }
+
+    /// Set the size of the data as it was counted during reception.
+    pub(crate) const fn with_size(mut self, size: Size) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Get the envelope given by the client in `MAIL FROM` and `RCPT TO`.
+    ///
+    /// See [`Envelope`].
+    #[must_use]
+    pub const fn envelope(&self) -> &Envelope {
+        &self.envelope
+    }
+
+    /// Get the details of the session that the message was received through, such as the
+    /// address of the client.
+    ///
+    /// See [`SessionInfo`].
+    #[must_use]
+    pub const fn session(&self) -> &SessionInfo {
+        &self.session
+    }
+
+    /// Get when the mail transaction was started by `MAIL FROM`.
+    #[must_use]
+    pub const fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+
+    /// Get when the end of the data was received.
+    #[must_use]
+    pub const fn received_at(&self) -> SystemTime {
+        self.received_at
+    }
+
+    /// Get the data of the message as raw bytes.
+    ///
+    /// This is the content as sent after `DATA`, including headers, with the transparency
+    /// dot-stuffing of [RFC 5321 section
+    /// 4.5.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.2) removed and without the
+    /// terminating `<CRLF>.<CRLF>`. The server's `Received:` header (see [`trace::received`]) is
+    /// added to the start.
+    #[must_use]
+    pub const fn data(&self) -> &Bytes {
+        &self.data
+    }
+
+    /// Get the size of the data of the message as it was received from the client.
+    ///
+    /// This is counted as the data is received, after the transparency dot-stuffing is removed,
+    /// and does not include the `Received:` header that the server adds to the start of
+    /// [`Self::data`].
+    #[must_use]
+    pub const fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Get the SHA-256 digest of the data of the message as it was received from the client.
+    ///
+    /// This is computed as the data is received, over the same data as [`Self::size`], so it does
+    /// not include the `Received:` header that the server adds to the start of [`Self::data`].
+    #[must_use]
+    pub const fn hash(&self) -> ContentHash {
+        self.hash
+    }
+
+    /// Get the result of evaluating DMARC for the message, or `None` if
+    /// [`crate::ServerConfig::dmarc_policy`] is not set.
+    ///
+    /// Messages that are tagged to be quarantined have a
+    /// [`crate::dmarc::DmarcVerdict::disposition`] of
+    /// [`crate::dmarc::DmarcDisposition::Quarantine`].
+    #[cfg(feature = "dmarc")]
+    #[must_use]
+    pub const fn dmarc(&self) -> Option<&DmarcVerdict> {
+        self.dmarc.as_ref()
+    }
+
+    /// Get the result of validating the Authenticated Received Chain of the message, or `None` if
+    /// it was not validated, see [`crate::ServerConfig::verify_arc`].
+    #[cfg(feature = "arc")]
+    #[must_use]
+    pub const fn arc(&self) -> Option<ArcResult> {
+        self.arc
+    }
+
+    /// Get the rules that matched the message, such as to route it by [`FilterVerdict::route`], or
+    /// `None` if there are none to check, see [`crate::ServerConfig::filter_rules`].
+    #[cfg(feature = "filter")]
+    #[must_use]
+    pub const fn filter(&self) -> Option<&FilterVerdict> {
+        self.filter.as_ref()
+    }
+
+    /// Get the result of scanning the message with ClamAV, or `None` if it was not scanned, see
+    /// [`crate::ServerConfig::clamav_policy`].
+    #[cfg(feature = "clamav")]
+    #[must_use]
+    pub const fn clamav(&self) -> Option<&ClamAvVerdict> {
+        self.clamav.as_ref()
+    }
+
+    /// Get the result of scanning the message with Rspamd, or `None` if it was not scanned, see
+    /// [`crate::ServerConfig::rspamd_policy`].
+    #[cfg(feature = "rspamd")]
+    #[must_use]
+    pub const fn rspamd(&self) -> Option<&RspamdVerdict> {
+        self.rspamd.as_ref()
+    }
+
+    /// Get the result of scoring the message with SpamAssassin, or `None` if it was not scored,
+    /// see [`crate::ServerConfig::spamassassin_policy`].
+    #[cfg(feature = "spamassassin")]
+    #[must_use]
+    pub const fn spamassassin(&self) -> Option<&SpamAssassinVerdict> {
+        self.spamassassin.as_ref()
+    }
+
+    /// Get the entry that the message is kept under in the spool, or `None` if it was not
+    /// spooled, see [`crate::ServerConfig::spool`].
+    ///
+    /// The consumer takes the message out of the spool with [`crate::spool::Spool::remove`] once
+    /// it has processed it.
+    #[cfg(feature = "spool")]
+    #[must_use]
+    pub const fn spool_id(&self) -> Option<&SpoolId> {
+        self.spool_id.as_ref()
+    }
+
+    /// Parse the header fields of the message.
+    ///
+    /// The data is parsed each time this is called, so consider keeping the result.
+    ///
+    /// See [`headers::parse`].
+    #[must_use]
+    pub fn headers(&self) -> headers::Headers {
+        headers::parse(&self.data).0
+    }
+
+    /// Get the body of the message, which is everything after the header section.
+    ///
+    /// This is a slice of [`Self::data`], so it does not copy the data.
+    ///
+    /// See [`headers::parse`].
+    #[must_use]
+    pub fn body(&self) -> Bytes {
+        self.data.slice_ref(headers::parse(&self.data).1)
+    }
+
+    /// Get the lines of the body of the message, without their line endings.
+    ///
+    /// See [`lines::Lines`].
+    #[must_use]
+    pub fn lines(&self) -> lines::Lines {
+        lines::Lines::new(self.body())
+    }
+
+    /// Walk the MIME structure of the message, returning every part that is not itself a
+    /// multipart entity with its data decoded.
+    ///
+    /// The data is parsed each time this is called, so consider keeping the result.
+    ///
+    /// See [`mime::parts`].
+    #[cfg(feature = "mime")]
+    #[must_use]
+    pub fn parts(&self) -> Vec<mime::Part> {
+        mime::parts(&self.data)
+    }
+
+    /// Get the text of the message as UTF-8, decoded according to its `Content-Transfer-Encoding:`
+    /// field and character set.
+    ///
+    /// For a multipart message, this is the first `text/plain` part that is not an attachment, or
+    /// else the first `text/*` part that is not an attachment. Returns `None` if there is no such
+    /// part.
+    ///
+    /// See [`mime::Part::text`].
+    #[cfg(feature = "mime")]
+    #[must_use]
+    pub fn text_body(&self) -> Option<String> {
+        let parts = self.parts();
+        let is_text = |part: &&mime::Part| {
+            part.content_type().mime_type().starts_with("text/") && !part.is_attachment()
+        };
+
+        parts
+            .iter()
+            .filter(is_text)
+            .find(|part| part.content_type().mime_type() == "text/plain")
+            .or_else(|| parts.iter().find(is_text))
+            .and_then(mime::Part::text)
+    }
+
+    /// Get every attachment of the message, with its data decoded.
+    ///
+    /// The data is parsed each time this is called, so consider keeping the result.
+    ///
+    /// See [`mime::attachments`].
+    #[cfg(feature = "mime")]
+    #[must_use]
+    pub fn attachments(&self) -> Vec<mime::Attachment> {
+        mime::attachments(&self.data)
+    }
+
+    /// Add a header field to the start of the header section, such as `X-Original-To:` or a spam
+    /// score, before the message is handed on.
+    ///
+    /// [`Self::size`] and [`Self::hash`] are not changed, as they describe the data as it was
+    /// received from the client.
+    ///
+    /// # Errors
+    ///
+    /// [`headers::HeaderError`] if the name or value is invalid, in which case the message is not
+    /// changed.
+    pub fn add_header(&mut self, name: &str, value: &str) -> Result<(), headers::HeaderError> {
+        let field = headers::field(name, value)?;
+        self.prepend(field.as_bytes());
+
+        Ok(())
+    }
+
+    /// Remove every header field with the given name, returning how many were removed.
+    ///
+    /// Names are compared case-insensitively.
+    pub fn remove_header(&mut self, name: &str) -> usize {
+        let ranges = headers::find(&self.data, name);
+        self.splice(&ranges, &[]);
+
+        ranges.len()
+    }
+
+    /// Replace the first header field with the given name and remove the rest, or add it to the
+    /// start of the header section if there is none. See [`Self::add_header`].
+    ///
+    /// Names are compared case-insensitively.
+    ///
+    /// # Errors
+    ///
+    /// [`headers::HeaderError`] if the name or value is invalid, in which case the message is not
+    /// changed.
+    pub fn replace_header(&mut self, name: &str, value: &str) -> Result<(), headers::HeaderError> {
+        let field = headers::field(name, value)?;
+        let ranges = headers::find(&self.data, name);
+
+        if ranges.is_empty() {
+            self.prepend(field.as_bytes());
+        } else {
+            self.splice(&ranges, field.as_bytes());
+        }
+
+        Ok(())
+    }
+
+    /// Replace the first of `ranges` over the data of the message with `replacement`, and remove
+    /// the rest.
+    ///
+    /// `ranges` must be in order and must not overlap.
+    fn splice(&mut self, ranges: &[Range<usize>], replacement: &[u8]) {
+        if ranges.is_empty() {
+            return;
+        }
+
+        let mut data = BytesMut::with_capacity(self.data.len() + replacement.len());
+        let mut start = 0;
+        for (index, range) in ranges.iter().enumerate() {
+            data.extend_from_slice(&self.data[start..range.start]);
+            if index == 0 {
+                data.extend_from_slice(replacement);
+            }
+            start = range.end;
+        }
+        data.extend_from_slice(&self.data[start..]);
+
+        self.data = data.freeze();
+    }
+
+    /// Add a line (such as a trace header) to the start of the data of the message.
+    ///
+    /// `line` must include its line ending.
+    pub(crate) fn prepend(&mut self, line: &[u8]) {
+        let mut data = BytesMut::with_capacity(line.len() + self.data.len());
+        data.extend_from_slice(line);
+        data.extend_from_slice(&self.data);
+
+        self.data = data.freeze();
+    }
+
+    /// Serialize the message as an RFC 5322 message, such as for an `.eml` file, with a
+    /// `Return-Path:` header and `CRLF` line endings.
+    ///
+    /// See [`export::eml`].
+    #[must_use]
+    pub fn to_eml_bytes(&self) -> Vec<u8> {
+        export::eml(self)
+    }
+
+    /// Serialize the envelope of the message, the details of its session, and its data in base64
+    /// as a JSON object.
+    ///
+    /// See [`export::json`].
+    #[cfg(feature = "json")]
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        export::json(self).to_string()
+    }
+
+    /// Consume [`Self`] to get the data of the message as raw bytes.
+    ///
+    /// See [`Self::data`].
+    #[must_use]
+    pub fn into_data(self) -> Bytes {
+        self.data
+    }
+}
+
+/// The size of the data of a message, in bytes.
+///
+/// The header section includes the empty line that ends it. See [`headers::parse`] for where the
+/// header section is considered to end.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Size {
+    /// The size of the header section, including the empty line that ends it.
+    header: usize,
+    /// The size of the body.
+    body: usize,
+}
+
+impl Size {
+    /// Create a new [`Self`] from the sizes of the header section and the body.
+    #[cfg(feature = "spool")]
+    pub(crate) const fn new(header: usize, body: usize) -> Self {
+        Self { header, body }
+    }
+
+    /// Get the size of the header section, including the empty line that ends it.
+    #[must_use]
+    pub const fn header(&self) -> usize {
+        self.header
+    }
+
+    /// Get the size of the body.
+    #[must_use]
+    pub const fn body(&self) -> usize {
+        self.body
+    }
+
+    /// Get the size of the entire data.
+    #[must_use]
+    pub const fn total(&self) -> usize {
+        self.header + self.body
+    }
+
+    /// Count a line of data, given whether it is part of the header section.
+    pub(crate) const fn add(&mut self, line: usize, is_header: bool) {
+        if is_header {
+            self.header += line;
+        } else {
+            self.body += line;
+        }
+    }
+}
+
+/// The SHA-256 digest of the data of a message, for identifying messages by their content.
+///
+/// Formats as lowercase hexadecimal with [`Display`].
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    /// Compute the digest of `data` all at once.
+    pub(crate) fn of(data: &[u8]) -> Self {
+        Self(Sha256::digest(data).into())
+    }
+
+    /// Parse a digest from the lowercase hexadecimal that [`Display`] formats it as, or `None` if
+    /// it is malformed.
+    #[cfg(feature = "spool")]
+    pub(crate) fn parse(hex: &str) -> Option<Self> {
+        if hex.len() != 64 {
+            return None;
+        }
+
+        let mut bytes = [0; 32];
+        for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+
+        Some(Self(bytes))
+    }
+
+    /// Get the digest as raw bytes.
+    #[must_use]
+    pub const fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<Sha256> for ContentHash {
+    fn from(hasher: Sha256) -> Self {
+        Self(hasher.finalize().into())
+    }
+}
+
+impl Display for ContentHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
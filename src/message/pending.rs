@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! A received message that the client is not replied to for until the consumer acknowledges it.
+//!
+//! See [`PendingMessage`], and [`crate::listen_acknowledged`].
+
+use tokio::sync::oneshot;
+
+use super::Message;
+
+/// An SMTP message that the server has received but not yet told the client about.
+///
+/// The server will not send the final reply to the client until the consumer calls
+/// [`Self::accept`] or [`Self::reject`], so accepting only once the message is stored durably or
+/// handed on gives at-least-once delivery from the client to the consumer. If [`Self`] is dropped
+/// without either, or the consumer takes longer than [`crate::timeouts::Timeouts::data_termination`],
+/// the client is told to try again later, and may send the message again.
+#[derive(Debug)]
+pub struct PendingMessage {
+    /// The received message.
+    message: Message,
+    /// Reports whether the consumer accepted responsibility for the message.
+    acceptance: oneshot::Sender<bool>,
+}
+
+impl PendingMessage {
+    /// Create a new [`Self`], returning it alongside the channel used to receive whether it was
+    /// accepted.
+    pub(crate) fn new(message: Message) -> (Self, oneshot::Receiver<bool>) {
+        let (acceptance, acceptance_receiver) = oneshot::channel();
+
+        (
+            Self {
+                message,
+                acceptance,
+            },
+            acceptance_receiver,
+        )
+    }
+
+    /// Get the received message.
+    #[must_use]
+    pub const fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Accept responsibility for the message, telling the client that it was received.
+    pub fn accept(self) {
+        // If the session already ended, there's nobody left to tell.
+        let _ = self.acceptance.send(true);
+    }
+
+    /// Refuse responsibility for the message, telling the client that the transaction failed.
+    pub fn reject(self) {
+        // If the session already ended, there's nobody left to tell.
+        let _ = self.acceptance.send(false);
+    }
+}
@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Messages whose data is delivered as it is received, rather than after the end of the data.
+//!
+//! See [`StreamingMessage`].
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use bytes::Bytes;
+use futures_core::Stream;
+use tokio::sync::{mpsc, oneshot};
+
+use super::{envelope::Envelope, Message};
+use crate::session::SessionInfo;
+
+/// An SMTP message whose data is still being received.
+///
+/// Created as soon as the server accepts the `DATA` command, so that the data can be consumed
+/// through [`Self::body_mut`] as it arrives instead of being buffered in memory first.
+///
+/// The server will not send the final reply to the client until the consumer calls
+/// [`Self::accept`] or [`Self::reject`]. If [`Self`] is dropped without either, the client is
+/// told that the message could not be processed and that it should try again later.
+///
+/// To read the [`Body`] in one task and decide in another, such as accepting once a copy of the
+/// data is stored elsewhere, split it with [`Self::into_parts`].
+#[derive(Debug)]
+pub struct StreamingMessage {
+    /// The details of the message, with empty data.
+    message: Message,
+    /// The data of the message, as it is received.
+    body: Body,
+    /// Reports whether the consumer accepted responsibility for the message.
+    acceptance: Acceptance,
+}
+
+impl StreamingMessage {
+    /// Create a new [`Self`], returning it alongside the channels used to send its data and
+    /// receive whether it was accepted.
+    pub(crate) fn new(
+        message: Message,
+    ) -> (
+        Self,
+        mpsc::Sender<std::io::Result<Bytes>>,
+        oneshot::Receiver<bool>,
+    ) {
+        /// How many chunks of data can be received before the session waits for the consumer to
+        /// catch up.
+        const BUFFERED_CHUNKS: usize = 64;
+
+        let (body_sender, body_receiver) = mpsc::channel(BUFFERED_CHUNKS);
+        let (acceptance_sender, acceptance_receiver) = oneshot::channel();
+
+        let message = Self {
+            message,
+            body: Body {
+                receiver: body_receiver,
+                is_finished: false,
+                has_failed: false,
+            },
+            acceptance: Acceptance {
+                sender: acceptance_sender,
+            },
+        };
+
+        (message, body_sender, acceptance_receiver)
+    }
+
+    /// Get the envelope given by the client in `MAIL FROM` and `RCPT TO`.
+    ///
+    /// See [`Message::envelope`].
+    #[must_use]
+    pub const fn envelope(&self) -> &Envelope {
+        self.message.envelope()
+    }
+
+    /// Get the details of the session that the message is being received through.
+    ///
+    /// See [`Message::session`].
+    #[must_use]
+    pub const fn session(&self) -> &SessionInfo {
+        self.message.session()
+    }
+
+    /// Get when the mail transaction was started by `MAIL FROM`.
+    #[must_use]
+    pub const fn started_at(&self) -> SystemTime {
+        self.message.started_at()
+    }
+
+    /// Get the data of the message as a [`Stream`] of chunks, as they are received.
+    ///
+    /// See [`Body`].
+    pub const fn body_mut(&mut self) -> &mut Body {
+        &mut self.body
+    }
+
+    /// Split `self` into the details of the message with empty data, its [`Body`], and the
+    /// [`Acceptance`] that decides whether it is accepted, so that the data can be read apart from
+    /// the decision.
+    #[must_use]
+    pub fn into_parts(self) -> (Message, Body, Acceptance) {
+        (self.message, self.body, self.acceptance)
+    }
+
+    /// Accept responsibility for the message, telling the client that it was received.
+    ///
+    /// This drops the [`Body`], so it must only be called once the [`Body`] has ended. If it has
+    /// not, the rest of the data would be lost, so the client is told to try again later instead,
+    /// as if [`Self`] were dropped. See [`Self::into_parts`] to accept while the data is still
+    /// being read.
+    pub fn accept(self) {
+        if self.body.is_finished() {
+            self.acceptance.accept();
+        }
+    }
+
+    /// Refuse responsibility for the message, telling the client that the transaction failed.
+    ///
+    /// The reply is sent once the end of the data is received, so this can be called before the
+    /// [`Body`] has ended, and the rest of the data is discarded.
+    pub fn reject(self) {
+        self.acceptance.reject();
+    }
+}
+
+/// Decides whether the consumer accepts responsibility for a [`StreamingMessage`] apart from its
+/// [`Body`], see [`StreamingMessage::into_parts`].
+///
+/// If [`Self`] is dropped without calling [`Self::accept`] or [`Self::reject`], the client is told
+/// to try again later.
+#[derive(Debug)]
+pub struct Acceptance {
+    /// Reports whether the consumer accepted responsibility for the message.
+    sender: oneshot::Sender<bool>,
+}
+
+impl Acceptance {
+    /// Accept responsibility for the message, telling the client that it was received.
+    ///
+    /// The reply is sent once the end of the data is received, so this can be called while the
+    /// [`Body`] is still being read, such as once the data is known to be stored as it arrives.
+    pub fn accept(self) {
+        // If the session already ended, there's nobody left to tell.
+        let _ = self.sender.send(true);
+    }
+
+    /// Refuse responsibility for the message, telling the client that the transaction failed.
+    ///
+    /// The reply is sent once the end of the data is received, so this can be called while the
+    /// [`Body`] is still being read.
+    pub fn reject(self) {
+        // If the session already ended, there's nobody left to tell.
+        let _ = self.sender.send(false);
+    }
+}
+
+/// The data of a [`StreamingMessage`], as it is received.
+///
+/// Yields each line of the data (including its line ending) with the transparency dot-stuffing of
+/// [RFC 5321 section 4.5.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.2) removed,
+/// and ends once the terminating `<CRLF>.<CRLF>` is received. The first item is the server's
+/// `Received:` header (see [`super::trace::received`]).
+///
+/// If the session ends before the data is finished, the last item is an [`std::io::Error`] with
+/// [`std::io::ErrorKind::UnexpectedEof`].
+#[derive(Debug)]
+pub struct Body {
+    /// Receives the data from the session.
+    receiver: mpsc::Receiver<std::io::Result<Bytes>>,
+    /// Whether the data ended with the terminating `<CRLF>.<CRLF>`.
+    is_finished: bool,
+    /// Whether the session ended before the data did.
+    has_failed: bool,
+}
+
+impl Body {
+    /// Check whether the whole data was received, meaning that [`Self`] ended without an error.
+    #[must_use]
+    pub const fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+}
+
+impl Stream for Body {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.receiver.poll_recv(cx);
+        match &poll {
+            Poll::Ready(None) => self.is_finished = !self.has_failed,
+            Poll::Ready(Some(Err(_))) => self.has_failed = true,
+            Poll::Ready(Some(Ok(_))) | Poll::Pending => (),
+        }
+
+        poll
+    }
+}
@@ -0,0 +1,757 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ascii::{AsciiStr, IntoAsciiString};
+use bytes::Bytes;
+
+use super::*;
+use crate::{
+    config::HeloCheck,
+    session::{ReverseDns, SessionId, TlsInfo},
+};
+
+type Result = std::result::Result<(), Box<dyn std::error::Error>>;
+
+/// Create a [`Message`] from `client.example.com` at `192.0.2.1`, received at `date`.
+fn message(
+    forward_paths: &[&str],
+    date: SystemTime,
+) -> std::result::Result<Message, Box<dyn std::error::Error>> {
+    let builder = forward_paths
+        .iter()
+        .fold(Message::builder(), |builder, path| {
+            builder.forward_path(*path)
+        });
+
+    Ok(builder
+        .reverse_path("smith@example.com")
+        .helo("client.example.com")
+        .peer_addr(SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 25))
+        .started_at(date)
+        .received_at(date)
+        .header("Subject", "test")
+        .body("body\r\n")
+        .build()?)
+}
+
+#[test]
+fn test_date_format() {
+    assert_eq!(date::format(UNIX_EPOCH), "Thu, 1 Jan 1970 00:00:00 +0000");
+    assert_eq!(
+        date::format(UNIX_EPOCH + Duration::from_hours(264_384)),
+        "Tue, 29 Feb 2000 00:00:00 +0000"
+    );
+    assert_eq!(
+        date::format(UNIX_EPOCH + Duration::from_secs(1_729_170_309)),
+        "Thu, 17 Oct 2024 13:05:09 +0000"
+    );
+}
+
+#[test]
+fn test_date_parse() {
+    let time = UNIX_EPOCH + Duration::from_secs(1_729_170_309);
+    let expected = Some(time);
+
+    assert_eq!(date::parse("Thu, 17 Oct 2024 13:05:09 +0000"), expected);
+    assert_eq!(date::parse("17 Oct 2024 13:05:09 +0000 (UTC)"), expected);
+    assert_eq!(date::parse("Thu, 17 Oct 2024 09:05:09 -0400"), expected);
+    assert_eq!(date::parse("Thu, 17 Oct 24 09:05:09 EDT"), expected);
+    assert_eq!(
+        date::parse("Thu, 17 Oct 2024 13:05 GMT"),
+        Some(time - Duration::from_secs(9))
+    );
+
+    // Tests that it round-trips with [`date::format`].
+    assert_eq!(date::parse(&date::format(time)), expected);
+
+    assert_eq!(date::parse("Thu, 17 Oct 2024"), None);
+    assert_eq!(date::parse("Thu, 17 Foo 2024 13:05:09 +0000"), None);
+    assert_eq!(date::parse("Thu, 32 Oct 2024 13:05:09 +0000"), None);
+    assert_eq!(date::parse("Thu, 17 Oct 2024 13:05:09 +00"), None);
+
+    // Tests that huge years are refused rather than overflowing.
+    assert!(date::parse("Fri, 31 Dec 9999 23:59:59 +0000").is_some());
+    assert_eq!(date::parse("Sat, 1 Jan 10000 00:00:00 +0000"), None);
+    assert_eq!(
+        date::parse("Thu, 17 Oct 18446744073709551615 13:05:09 +0000"),
+        None
+    );
+    assert_eq!(
+        date::parse("Thu, 17 Oct 99999999999999999 13:05:09 -2359"),
+        None
+    );
+}
+
+#[test]
+fn test_decode() {
+    let data = Bytes::from_static(b"caf=C3=A9");
+    assert_eq!(
+        decode::transfer_encoding(&data, Some("Quoted-Printable")),
+        "café".as_bytes()
+    );
+    assert_eq!(
+        decode::transfer_encoding(&data, Some("8bit")).as_ptr(),
+        data.as_ptr()
+    );
+    assert_eq!(
+        decode::transfer_encoding(&Bytes::from_static(b"Y2Fmw6k="), Some("base64")),
+        "café".as_bytes()
+    );
+
+    assert_eq!(decode::charset("café".as_bytes(), None), "café");
+    assert_eq!(decode::charset(b"caf\xE9", Some("ISO-8859-1")), "café");
+    assert_eq!(decode::charset(b"caf\xE9", Some("utf-8")), "caf\u{FFFD}");
+
+    #[cfg(feature = "encoding")]
+    assert_eq!(
+        decode::charset(b"\xA4 rates", Some("iso-8859-15")),
+        "€ rates"
+    );
+}
+
+#[test]
+fn test_headers() {
+    let data = b"Received: from a.example\r\n\
+        \tby b.example; Thu, 17 Oct 2024 13:05:09 +0000\r\n\
+        Received: from c.example by a.example; Thu, 17 Oct 2024 13:00:00 +0000\r\n\
+        From: \"Smith, Jane\" <smith@example.com>\r\n\
+        to: jones@example.com, Bob (the builder) <bob@example.com>,\r\n  \
+        Friends: green@example.com, \"Gray\" <gray@example.com>;\r\n\
+        Subject: Hello,\r\n world\r\n\
+        Date: Thu, 17 Oct 2024 13:05:09 +0000\r\n\
+        Message-ID: <1234@example.com>\r\n\
+        \r\n\
+        Body: not a header\r\n";
+
+    let (headers, body) = headers::parse(data);
+
+    assert_eq!(body, b"Body: not a header\r\n");
+    assert_eq!(headers.len(), 7);
+
+    // Tests that duplicates are kept in order and that folded fields are unfolded.
+    assert_eq!(
+        headers.get_all("received").collect::<Vec<_>>(),
+        [
+            "from a.example\tby b.example; Thu, 17 Oct 2024 13:05:09 +0000",
+            "from c.example by a.example; Thu, 17 Oct 2024 13:00:00 +0000",
+        ]
+    );
+    assert_eq!(headers.subject(), Some("Hello, world"));
+    assert_eq!(headers.message_id(), Some("1234@example.com"));
+    assert_eq!(
+        headers.date(),
+        Some(UNIX_EPOCH + Duration::from_secs(1_729_170_309))
+    );
+
+    let from = headers.from();
+    assert_eq!(from.len(), 1);
+    assert_eq!(from[0].name(), Some("Smith, Jane"));
+    assert_eq!(from[0].address(), "smith@example.com");
+
+    // Tests that comments are removed and that groups are flattened.
+    let to = headers.to();
+    assert_eq!(
+        to.iter().map(headers::Mailbox::address).collect::<Vec<_>>(),
+        [
+            "jones@example.com",
+            "bob@example.com",
+            "green@example.com",
+            "gray@example.com",
+        ]
+    );
+    assert_eq!(to[1].name(), Some("Bob"));
+    assert_eq!(to[3].name(), Some("Gray"));
+
+    // Tests that a line that is not a field ends the header section.
+    let (headers, body) = headers::parse(b"Subject: test\r\nnot a field\r\n");
+    assert_eq!(headers.len(), 1);
+    assert_eq!(body, b"not a field\r\n");
+}
+
+#[test]
+fn test_header_edit() -> Result {
+    let mut message = Message::builder()
+        .header("Received", "from a.example by b.example")
+        .header("X-Spam-Score", "1.0")
+        .header("Subject", "test")
+        .header("x-spam-score", "2.0")
+        .body("X-Spam-Score: not a header\r\n")
+        .build()?;
+    let size = message.size();
+
+    message.add_header("X-Original-To", "jones@example.com")?;
+    message.replace_header("X-Spam-Score", "5.0\r\n\t(high)")?;
+
+    assert_eq!(
+        message.data(),
+        &b"X-Original-To: jones@example.com\r\n\
+        Received: from a.example by b.example\r\n\
+        X-Spam-Score: 5.0\r\n\t(high)\r\n\
+        Subject: test\r\n\
+        \r\n\
+        X-Spam-Score: not a header\r\n"[..]
+    );
+    assert_eq!(message.headers().get("X-Spam-Score"), Some("5.0\t(high)"));
+    assert_eq!(message.size(), size);
+
+    // Tests that folded lines are removed along with their field.
+    assert_eq!(message.remove_header("x-spam-score"), 1);
+    assert_eq!(message.remove_header("X-Spam-Score"), 0);
+    assert!(!message.headers().contains("X-Spam-Score"));
+    assert_eq!(message.headers().len(), 3);
+
+    message.replace_header("Comments", "none")?;
+    assert_eq!(message.headers().fields()[0].name(), "Comments");
+
+    for (name, value, error) in [
+        ("", "value", headers::HeaderError::InvalidName),
+        ("X-Bad:", "value", headers::HeaderError::InvalidName),
+        ("X-Bad", "caf\u{e9}", headers::HeaderError::InvalidValue),
+        ("X-Bad", "one\r\ntwo", headers::HeaderError::InvalidValue),
+        ("X-Bad", "one\r\n\r\n", headers::HeaderError::InvalidValue),
+        ("X-Bad", &"a".repeat(992), headers::HeaderError::TooLong),
+    ] {
+        assert_eq!(message.add_header(name, value), Err(error));
+    }
+    // 998 characters, the longest allowed line.
+    message.add_header("X-Long", &"a".repeat(990))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_envelope_parameters() {
+    use envelope::{Notify, Ret};
+
+    let notify = Notify::parse("success,DELAY");
+    assert_eq!(
+        notify,
+        Some(Notify {
+            success: true,
+            failure: false,
+            delay: true,
+        })
+    );
+    assert_eq!(
+        notify.map(|notify| notify.to_string()).as_deref(),
+        Some("SUCCESS,DELAY")
+    );
+    assert!(Notify::parse("NEVER").is_some_and(|notify| notify.is_never()));
+    assert_eq!(Notify::parse("NEVER,SUCCESS"), None);
+    assert_eq!(Notify::parse("SUCCESS,SUCCESS"), None);
+    assert_eq!(Notify::parse(""), None);
+
+    assert_eq!(Ret::parse("hdrs"), Some(Ret::Headers));
+    assert_eq!(
+        Ret::parse("FULL").map(|ret| ret.to_string()).as_deref(),
+        Some("FULL")
+    );
+    assert_eq!(Ret::parse("BODY"), None);
+
+    assert!(envelope::is_xtext("QQ+2B1234"));
+    assert!(!envelope::is_xtext("QQ+2b1234"));
+    assert!(!envelope::is_xtext("QQ+2"));
+    assert!(!envelope::is_xtext("a=b"));
+    assert!(envelope::is_orcpt("rfc822;jones@example.com"));
+    assert!(!envelope::is_orcpt("jones@example.com"));
+    assert!(!envelope::is_orcpt("rfc822;"));
+}
+
+#[test]
+fn test_message_id() {
+    let first = id::generate("mx.example.com");
+    assert!(id::is_valid(&first));
+    assert!(first.ends_with("@mx.example.com>"));
+    assert_ne!(first, id::generate("mx.example.com"));
+
+    assert!(id::is_valid("<1234.5678@example.com>"));
+    assert!(id::is_valid(" <a.b+c@[192.0.2.1]>\r\n"));
+    assert!(!id::is_valid("1234.5678@example.com"));
+    assert!(!id::is_valid("<1234..5678@example.com>"));
+    assert!(!id::is_valid("<.1234@example.com>"));
+    assert!(!id::is_valid("<1234 5678@example.com>"));
+    assert!(!id::is_valid("<example.com>"));
+    assert!(!id::is_valid("<1234@>"));
+    assert!(!id::is_valid("<1234@[a[b]>"));
+}
+
+#[test]
+fn test_body_is_slice() -> Result {
+    let message = message(&["jones@example.com"], UNIX_EPOCH)?;
+    let body = message.body();
+
+    assert_eq!(body, &b"body\r\n"[..]);
+    // Tests that the body points into the same allocation as the data.
+    assert_eq!(
+        body.as_ptr(),
+        message.data()[message.data().len() - body.len()..].as_ptr()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_builder() -> Result {
+    let message = message(&["jones@example.com"], UNIX_EPOCH)?;
+
+    assert_eq!(
+        message.envelope().reverse_path(),
+        Some("smith@example.com".into_ascii_string()?.as_ref())
+    );
+    assert_eq!(
+        message
+            .envelope()
+            .forward_paths()
+            .map(AsciiStr::as_str)
+            .collect::<Vec<_>>(),
+        ["jones@example.com"]
+    );
+    assert_eq!(message.data(), &b"Subject: test\r\n\r\nbody\r\n"[..]);
+    assert_eq!(message.size().header(), 17);
+    assert_eq!(message.size().body(), 6);
+    assert_eq!(message.headers().subject(), Some("test"));
+    assert_eq!(
+        message.hash().to_string(),
+        "b90d321eb4093d94ef83adf130f8cec8dd19ba3b8d53d6503d1d010877c14881"
+    );
+
+    assert_eq!(
+        message.session().helo(),
+        Some("client.example.com".into_ascii_string()?.as_ref())
+    );
+    assert_eq!(message.session().peer_addr().port(), 25);
+    assert!(!message.session().is_tls());
+
+    let message = Message::builder()
+        .null_reverse_path()
+        .tls(TlsInfo::new("TLSv1.3", "TLS13_AES_256_GCM_SHA384"))
+        .authenticated_user("smith")
+        .build()?;
+    assert_eq!(message.envelope().reverse_path(), None);
+    assert_eq!(message.session().helo(), None);
+    assert_eq!(
+        message.session().tls().map(TlsInfo::protocol),
+        Some("TLSv1.3")
+    );
+    assert_eq!(message.session().authenticated_user(), Some("smith"));
+    assert!(message.data().is_empty());
+
+    assert_eq!(
+        Message::builder().header("Bad Name", "value").build(),
+        Err(builder::BuildError::InvalidHeader)
+    );
+    assert_eq!(
+        Message::builder().header("Subject", "two\r\nlines").build(),
+        Err(builder::BuildError::InvalidHeader)
+    );
+    assert_eq!(
+        Message::builder().forward_path("jöns@example.com").build(),
+        Err(builder::BuildError::NonAsciiPath)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_session_id() {
+    let first = SessionId::generate();
+    let second = SessionId::generate();
+
+    assert_ne!(first, second);
+    assert_eq!(first.to_string().len(), 16);
+}
+
+#[tokio::test]
+async fn test_lines() -> Result {
+    use futures_util::StreamExt;
+
+    let message = Message::builder()
+        .header("Subject", "test")
+        .body("first\r\nsecond\nthird\r\r\nlast")
+        .build()?;
+
+    let lines: Vec<Bytes> = message.lines().collect().await;
+    assert_eq!(lines, ["first", "second", "third", "", "last"]);
+
+    // Tests that the lines are slices of the data.
+    let first = message.lines().next().await.unwrap_or_default();
+    assert_eq!(first.as_ptr(), message.body().as_ptr());
+
+    assert_eq!(Message::builder().build()?.lines().count().await, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_streaming_acceptance() -> Result {
+    use futures_util::StreamExt;
+    use stream::StreamingMessage;
+
+    let streaming =
+        || message(&["jones@example.com"], SystemTime::now()).map(StreamingMessage::new);
+
+    // Tests that a message is only accepted once its data has ended, as the rest would be lost.
+    let (mut message, data, acceptance) = streaming()?;
+    data.send(Ok(Bytes::from_static(b"Subject: test\r\n")))
+        .await?;
+    assert!(message.body_mut().next().await.is_some());
+    message.accept();
+    assert!(acceptance.await.is_err());
+
+    let (mut message, data, acceptance) = streaming()?;
+    data.send(Ok(Bytes::from_static(b"Subject: test\r\n")))
+        .await?;
+    drop(data);
+    while message.body_mut().next().await.is_some() {}
+    assert!(message.body_mut().is_finished());
+    message.accept();
+    assert!(acceptance.await?);
+
+    // Tests that data that ended with an error is not finished.
+    let (mut message, data, _acceptance) = streaming()?;
+    data.send(Err(std::io::ErrorKind::UnexpectedEof.into()))
+        .await?;
+    drop(data);
+    while message.body_mut().next().await.is_some() {}
+    assert!(!message.body_mut().is_finished());
+
+    // Tests that the parts can accept the message while its data is still being read.
+    let (message, data, acceptance) = streaming()?;
+    let (message, mut body, decision) = message.into_parts();
+    assert_eq!(message.envelope().accepted().count(), 1);
+    decision.accept();
+    assert!(acceptance.await?);
+    data.send(Ok(Bytes::from_static(b"Subject: test\r\n")))
+        .await?;
+    assert!(body.next().await.is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_received_header() -> Result {
+    let date = UNIX_EPOCH + Duration::from_secs(1_729_170_309);
+
+    assert_eq!(
+        trace::received(
+            &message(&["jones@example.com"], date)?,
+            "mx.example.com",
+            Some("1a2b3c")
+        )
+        .to_string(),
+        "Received: from client.example.com (client.example.com [192.0.2.1])\r\n\
+         \tby mx.example.com via TCP with SMTP id 1a2b3c for <jones@example.com>;\r\n\
+         \tThu, 17 Oct 2024 13:05:09 +0000\r\n"
+    );
+
+    // Tests that other recipients are not disclosed.
+    assert_eq!(
+        trace::received(
+            &message(&["jones@example.com", "green@example.com"], date)?,
+            "mx.example.com",
+            None
+        )
+        .to_string(),
+        "Received: from client.example.com (client.example.com [192.0.2.1])\r\n\
+         \tby mx.example.com via TCP with SMTP; Thu, 17 Oct 2024 13:05:09 +0000\r\n"
+    );
+
+    // Tests that failed checks of the identity given in `HELO` are noted.
+    let mut annotated = message(&["jones@example.com"], date)?;
+    annotated.session.helo_failures = vec![HeloCheck::Resolves, HeloCheck::MatchesPeer];
+    assert_eq!(
+        trace::received(&annotated, "mx.example.com", None).to_string(),
+        "Received: from client.example.com (client.example.com [192.0.2.1])\r\n\
+         \t(HELO does not resolve, does not match the client address)\r\n\
+         \tby mx.example.com via TCP with SMTP for <jones@example.com>;\r\n\
+         \tThu, 17 Oct 2024 13:05:09 +0000\r\n"
+    );
+
+    // Tests that the confirmed name of the client is given, or `unknown` if there is none.
+    let mut named = message(&["jones@example.com"], date)?;
+    named.session.reverse_dns = Some(ReverseDns::Verified("mail.example.com".to_owned()));
+    assert_eq!(
+        trace::received(&named, "mx.example.com", None).to_string(),
+        "Received: from client.example.com (mail.example.com [192.0.2.1])\r\n\
+         \tby mx.example.com via TCP with SMTP for <jones@example.com>;\r\n\
+         \tThu, 17 Oct 2024 13:05:09 +0000\r\n"
+    );
+    named.session.reverse_dns = Some(ReverseDns::Unknown);
+    assert!(trace::received(&named, "mx.example.com", None)
+        .to_string()
+        .starts_with("Received: from client.example.com (unknown [192.0.2.1])"));
+    // Tests that a name that is not ASCII is written lossily.
+    named.session.reverse_dns = Some(ReverseDns::Verified("bücher.example".to_owned()));
+    assert!(trace::received(&named, "mx.example.com", None)
+        .to_string()
+        .starts_with("Received: from client.example.com (b?cher.example [192.0.2.1])"));
+
+    // Tests that sessions that greeted with `EHLO` are noted as ESMTP.
+    named.session.extended = true;
+    assert!(trace::received(&named, "mx.example.com", None)
+        .to_string()
+        .contains(" with ESMTP for <jones@example.com>;"));
+
+    // Tests that the encryption of the session and client certificates are noted.
+    let mut encrypted = message(&["jones@example.com"], date)?;
+    encrypted.session.tls = Some(TlsInfo::new("TLSv1.3", "TLS13_AES_256_GCM_SHA384"));
+    assert_eq!(
+        trace::received(&encrypted, "mx.example.com", None).to_string(),
+        "Received: from client.example.com (client.example.com [192.0.2.1])\r\n\
+         \tby mx.example.com via TCP with ESMTPS\r\n\
+         \t(TLSv1.3, TLS13_AES_256_GCM_SHA384) for <jones@example.com>;\r\n\
+         \tThu, 17 Oct 2024 13:05:09 +0000\r\n"
+    );
+    encrypted.session.tls = Some(
+        TlsInfo::new("TLSv1.3", "TLS13_AES_256_GCM_SHA384")
+            .with_peer_certificates(vec![b"certificate".to_vec()]),
+    );
+    encrypted.session.authenticated_user = Some("relay.example.com".to_owned());
+    assert_eq!(
+        trace::received(&encrypted, "mx.example.com", None).to_string(),
+        "Received: from client.example.com (client.example.com [192.0.2.1])\r\n\
+         \tby mx.example.com via TCP with ESMTPSA\r\n\
+         \t(TLSv1.3, TLS13_AES_256_GCM_SHA384, verified client certificate)\r\n\
+         \tfor <jones@example.com>; Thu, 17 Oct 2024 13:05:09 +0000\r\n"
+    );
+
+    assert_eq!(
+        trace::return_path(&message(&["jones@example.com"], date)?).to_string(),
+        "Return-Path: <smith@example.com>\r\n"
+    );
+    assert_eq!(
+        trace::return_path(&Message::builder().build()?).to_string(),
+        "Return-Path: <>\r\n"
+    );
+
+    assert_eq!(
+        trace::address_literal(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into()),
+        "[IPv6:2001:db8::1]"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_eml() -> Result {
+    let message = Message::builder()
+        .reverse_path("smith@example.com")
+        .forward_path("jones@example.com")
+        .header("Subject", "test")
+        .body("bare\nline endings")
+        .build()?;
+
+    // Tests that a `Return-Path:` header is added, and that line endings are made `CRLF`.
+    let eml = message.to_eml_bytes();
+    assert!(eml.starts_with(b"Return-Path: <smith@example.com>\r\nSubject: test\r\n"));
+    assert!(eml.ends_with(b"\r\n\r\nbare\r\nline endings\r\n"));
+
+    // Tests that a `Return-Path:` header that is already there is kept as it is.
+    let stamped = Message::builder()
+        .null_reverse_path()
+        .forward_path("jones@example.com")
+        .header("Return-Path", "<>")
+        .body("body\r\n")
+        .build()?;
+    assert_eq!(stamped.to_eml_bytes(), stamped.data().to_vec());
+
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json() -> Result {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    let message = message(&["jones@example.com"], UNIX_EPOCH + Duration::from_secs(1))?;
+    let json: serde_json::Value = serde_json::from_str(&message.to_json())?;
+
+    assert_eq!(json["reverse_path"], "smith@example.com");
+    assert_eq!(json["recipients"], serde_json::json!(["jones@example.com"]));
+    assert_eq!(json["helo"], "client.example.com");
+    assert_eq!(json["subject"], "test");
+    assert_eq!(json["received_at"], 1000);
+    assert_eq!(json["hash"], message.hash().to_string());
+    assert_eq!(
+        STANDARD.decode(json["data"].as_str().unwrap_or_default())?,
+        message.data().to_vec()
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "mime")]
+#[test]
+fn test_mime_parts() {
+    let data = b"From: smith@example.com\r\n\
+        Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+        \r\n\
+        This is the preamble.\r\n\
+        --outer\r\n\
+        Content-Type: multipart/alternative; boundary=inner\r\n\
+        \r\n\
+        --inner\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        Content-Transfer-Encoding: quoted-printable\r\n\
+        \r\n\
+        caf=C3=A9 with a soft =\r\n\
+        line break\r\n\
+        --inner\r\n\
+        Content-Type: text/html\r\n\
+        \r\n\
+        <p>caf\xC3\xA9</p>\r\n\
+        --inner--\r\n\
+        --outer\r\n\
+        Content-Type: application/octet-stream; name=\"a.bin\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        AAEC\r\n\
+        /w==\r\n\
+        --outer--  \r\n\
+        This is the epilogue.\r\n";
+
+    let parts = mime::parts(&Bytes::from_static(data));
+
+    assert_eq!(
+        parts
+            .iter()
+            .map(|part| part.content_type().mime_type())
+            .collect::<Vec<_>>(),
+        ["text/plain", "text/html", "application/octet-stream"]
+    );
+
+    assert_eq!(parts[0].content_type().parameter("CHARSET"), Some("utf-8"));
+    assert_eq!(parts[0].data(), "café with a soft line break".as_bytes());
+    assert_eq!(parts[1].data(), "<p>café</p>".as_bytes());
+    assert_eq!(parts[2].content_type().parameter("name"), Some("a.bin"));
+    assert_eq!(parts[2].data(), &[0, 1, 2, 255][..]);
+    assert_eq!(parts[2].text(), None);
+
+    let message = Message::builder()
+        .body(Bytes::from_static(data))
+        .build()
+        .unwrap();
+    assert_eq!(
+        message.text_body().as_deref(),
+        Some("café with a soft line break")
+    );
+
+    // Tests that a message without MIME structure is a single plain text part.
+    let parts = mime::parts(&Bytes::from_static(b"Subject: test\r\n\r\nbody\r\n"));
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].content_type(), &mime::ContentType::default());
+    assert_eq!(parts[0].data(), &b"body\r\n"[..]);
+}
+
+#[cfg(feature = "mime")]
+#[test]
+fn test_mime_attachments() {
+    let data = b"Content-Type: multipart/mixed; boundary=b\r\n\
+        \r\n\
+        --b\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Disposition: inline\r\n\
+        \r\n\
+        See attached.\r\n\
+        --b\r\n\
+        Content-Type: text/csv\r\n\
+        Content-Disposition: attachment; filename=\"rates.csv\";\r\n\
+        \tfilename*=utf-8''%E2%82%AC%20rates.csv\r\n\
+        \r\n\
+        a,b\r\n\
+        --b\r\n\
+        Content-Type: image/png; name=\"logo.png\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        iVBO\r\n\
+        --b\r\n\
+        Content-Type: application/octet-stream\r\n\
+        Content-Disposition: attachment\r\n\
+        \r\n\
+        data\r\n\
+        --b--\r\n";
+
+    let attachments = mime::attachments(&Bytes::from_static(data));
+
+    assert_eq!(
+        attachments
+            .iter()
+            .map(mime::Attachment::filename)
+            .collect::<Vec<_>>(),
+        [Some("€ rates.csv"), Some("logo.png"), None]
+    );
+    assert_eq!(attachments[0].content_type().mime_type(), "text/csv");
+    assert_eq!(attachments[0].data(), &b"a,b"[..]);
+    assert_eq!(attachments[1].size(), 3);
+    assert_eq!(attachments[1].data(), &[0x89, b'P', b'N'][..]);
+    assert_eq!(attachments[2].size(), 4);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() -> Result {
+    let message = message(&["jones@example.com"], UNIX_EPOCH)?;
+
+    let json = serde_json::to_string(&message)?;
+    assert_eq!(serde_json::from_str::<Message>(&json)?, message);
+
+    let headers = message.headers();
+    let json = serde_json::to_string(&headers)?;
+    assert_eq!(serde_json::from_str::<headers::Headers>(&json)?, headers);
+
+    Ok(())
+}
+
+#[cfg(feature = "lettre")]
+#[test]
+fn test_lettre_envelope() -> Result {
+    let message = message(&["jones@example.com", "green@example.com"], UNIX_EPOCH)?;
+    let envelope = lettre::address::Envelope::try_from(&message)?;
+
+    assert_eq!(
+        envelope.from().map(ToString::to_string).as_deref(),
+        Some("smith@example.com")
+    );
+    assert_eq!(envelope.to().len(), 2);
+
+    let message = Message::builder().forward_path("not an address").build()?;
+    assert!(lettre::address::Envelope::try_from(message).is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "mail-parser")]
+#[test]
+fn test_mail_parser_message() -> Result {
+    let message = message(&["jones@example.com"], UNIX_EPOCH)?;
+    let parsed = mail_parser::Message::try_from(&message)?;
+
+    assert_eq!(parsed.subject(), Some("test"));
+    assert_eq!(parsed.body_text(0).as_deref(), Some("body\r\n"));
+
+    let parsed: mail_parser::Message<'static> = message.try_into()?;
+    assert_eq!(parsed.subject(), Some("test"));
+
+    Ok(())
+}
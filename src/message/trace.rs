@@ -0,0 +1,302 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Trace information added to messages as they pass through the server.
+//!
+//! See [RFC 5321 section 4.4](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.4).
+
+use std::net::IpAddr;
+
+use ascii::AsciiStr;
+
+use super::{date, Message};
+use crate::{session::ReverseDns, str::SmtpString};
+
+/// The length that header lines are folded to fit within, excluding the line ending.
+///
+/// [RFC 5322 section 2.1.1](https://www.rfc-editor.org/rfc/rfc5322.html#section-2.1.1).
+const FOLD_LENGTH: usize = 78;
+
+/// Create the `Received:` header that the server adds to a message when accepting it, including
+/// the trailing line ending.
+///
+/// `by` is the domain name of the server, and `id` is an identifier for the mail transaction, if
+/// any. The name of the client is given in the `from` clause if it was confirmed, or `unknown` if
+/// it could not be, see [`crate::session::SessionInfo::reverse_dns`]. The `for` clause is only
+/// included if the message has exactly one accepted recipient, so as to not disclose the other
+/// recipients. Checks of the identity given in `HELO` that failed are noted in a
+/// comment after the `from` clause, such as `(HELO does not resolve)`, see
+/// [`crate::session::SessionInfo::helo_failures`], followed by the DNS blocklists that the client
+/// is listed on, such as `(listed by zen.spamhaus.org)`. The version of TLS and cipher suite of an
+/// encrypted session are noted in a comment after the `with` clause, along with whether the client
+/// gave a verified certificate, see [`crate::session::TlsInfo`]. Any character that is not ASCII,
+/// such as in a name restored from a spool, is written as `?`.
+///
+/// The header is folded to fit within 78 characters per line where possible, such as:
+///
+/// ```text
+/// Received: from client.example.com (client.example.com [192.0.2.1])
+///         by mx.example.com via TCP with SMTP id 1a2b3c for <smith@example.com>;
+///         Thu, 17 Oct 2024 13:05:09 +0000
+/// ```
+///
+/// [RFC 5321 section 4.4](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.4).
+#[must_use]
+pub fn received(message: &Message, by: &str, id: Option<&str>) -> SmtpString {
+    let helo = message.session().helo().map_or("", AsciiStr::as_str);
+    let literal = address_literal(message.session().peer_addr().ip());
+
+    let mut clauses = Vec::with_capacity(8);
+
+    // An empty identity would be a syntax error, so fall back to the address literal. The name
+    // of the client is only given as confirmed if it was looked up.
+    let from = if helo.is_empty() { &literal } else { helo };
+    clauses.push(match message.session().reverse_dns() {
+        Some(ReverseDns::Verified(name)) => format!("from {from} ({name} {literal})"),
+        Some(_) => format!("from {from} (unknown {literal})"),
+        None if helo.is_empty() => format!("from {literal}"),
+        None => format!("from {helo} ({helo} {literal})"),
+    });
+    // Checks of the identity that failed without rejecting it are noted after the `from` clause.
+    let failures = message.session().helo_failures();
+    if !failures.is_empty() {
+        let failures: Vec<_> = failures.iter().map(ToString::to_string).collect();
+        clauses.push(format!("(HELO {})", failures.join(", ")));
+    }
+    #[cfg(feature = "dnsbl")]
+    if let Some(dnsbl) = message.session().dnsbl().filter(|dnsbl| dnsbl.is_listed()) {
+        let zones: Vec<_> = dnsbl
+            .listings()
+            .iter()
+            .map(crate::dnsbl::DnsblListing::zone)
+            .collect();
+        clauses.push(format!("(listed by {})", zones.join(", ")));
+    }
+    clauses.push(format!("by {by}"));
+    clauses.push("via TCP".to_owned());
+    clauses.push(format!("with {}", protocol(message)));
+    if let Some(tls) = message.session().tls() {
+        clauses.push(if tls.peer_certificates().is_empty() {
+            format!("({}, {})", tls.protocol(), tls.cipher())
+        } else {
+            format!(
+                "({}, {}, verified client certificate)",
+                tls.protocol(),
+                tls.cipher()
+            )
+        });
+    }
+    if let Some(id) = id {
+        clauses.push(format!("id {id}"));
+    }
+    let mut recipients = message.envelope().forward_paths();
+    if let (Some(recipient), None) = (recipients.next(), recipients.next()) {
+        clauses.push(format!("for <{recipient}>"));
+    }
+
+    // The date is separated from the clauses by a semicolon.
+    if let Some(last) = clauses.last_mut() {
+        last.push(';');
+    }
+    clauses.push(date::format(message.received_at()));
+
+    let header = fold("Received:", &clauses);
+
+    SmtpString::new_lossy(&header)
+}
+
+/// Create the `Return-Path:` header that the server adds to a message when it is the final
+/// delivery hop, including the trailing line ending.
+///
+/// The path is taken from the reverse-path of the message, and is `<>` for the null reverse-path.
+///
+/// [RFC 5321 section 4.4](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.4).
+#[must_use]
+#[expect(
+    clippy::missing_panics_doc,
+    reason = "every part of the header is ASCII"
+)]
+pub fn return_path(message: &Message) -> SmtpString {
+    let path = message
+        .envelope()
+        .reverse_path()
+        .map_or("", |path| path.as_str());
+
+    SmtpString::new(&format!("Return-Path: <{path}>\r\n"))
+        .expect("every part of the header is ASCII")
+}
+
+/// Create the `Received-SPF:` header with the result of checking SPF for the reverse-path of a
+/// message, including the trailing line ending, or `None` if SPF was not checked.
+///
+/// `by` is the domain name of the server, which is given as the receiver. See
+/// [`crate::message::envelope::Envelope::spf`].
+///
+/// The header is folded like [`received`], such as:
+///
+/// ```text
+/// Received-SPF: pass (mx.example.com: domain of smith@example.com designates 192.0.2.1 as
+///         permitted sender) client-ip=192.0.2.1; envelope-from="smith@example.com";
+///         helo=client.example.com; receiver=mx.example.com; identity=mailfrom;
+/// ```
+///
+/// [RFC 7208 section 9.1](https://www.rfc-editor.org/rfc/rfc7208.html#section-9.1).
+#[cfg(feature = "spf")]
+#[must_use]
+#[expect(
+    clippy::missing_panics_doc,
+    reason = "every part of the header is ASCII"
+)]
+pub fn received_spf(message: &Message, by: &str) -> Option<SmtpString> {
+    use crate::spf::SpfResult;
+
+    let verdict = message.envelope().spf()?;
+    let ip = message.session().peer_addr().ip().to_canonical();
+    let sender = verdict.sender();
+    let domain = verdict.domain();
+
+    let comment = match verdict.result() {
+        SpfResult::Pass => format!("domain of {sender} designates {ip} as permitted sender"),
+        SpfResult::Fail | SpfResult::SoftFail => {
+            format!("domain of {sender} does not designate {ip} as permitted sender")
+        }
+        SpfResult::Neutral => format!("{ip} is neither permitted nor denied by domain of {sender}"),
+        SpfResult::None => format!("domain of {domain} does not publish an SPF record"),
+        SpfResult::TempError => format!("error in processing during lookup of {domain}"),
+        SpfResult::PermError => format!("domain of {domain} has an invalid SPF record"),
+    };
+
+    let mut parts: Vec<_> = format!("{} ({by}: {comment})", verdict.result())
+        .split(' ')
+        .map(ToOwned::to_owned)
+        .collect();
+    parts.push(format!("client-ip={ip};"));
+    if !message.envelope().is_null_sender() {
+        parts.push(format!("envelope-from=\"{sender}\";"));
+    }
+    if let Some(helo) = message.session().helo().filter(|helo| !helo.is_empty()) {
+        parts.push(format!("helo={helo};"));
+    }
+    parts.push(format!("receiver={by};"));
+    parts.push(format!("identity={};", verdict.identity()));
+
+    let header = fold("Received-SPF:", &parts);
+
+    Some(SmtpString::new(&header).expect("every part of the header is ASCII"))
+}
+
+/// Create the `Authentication-Results:` header with the results of checking SPF and evaluating
+/// DMARC for a message, including the trailing line ending.
+///
+/// Returns `None` if DMARC was not evaluated, or if the domain in the `From:` header is not ASCII.
+/// `by` is the domain name of the server, which identifies who made the checks. See
+/// [`Message::dmarc`].
+///
+/// The header is folded like [`received`], such as:
+///
+/// ```text
+/// Authentication-Results: mx.example.com; spf=pass smtp.mailfrom=smith@example.com;
+///         dmarc=fail (p=reject dis=quarantine) header.from=example.com
+/// ```
+///
+/// [RFC 8601 section 2.2](https://www.rfc-editor.org/rfc/rfc8601.html#section-2.2).
+/// [RFC 7489 section 11.2](https://www.rfc-editor.org/rfc/rfc7489.html#section-11.2).
+#[cfg(feature = "dmarc")]
+#[must_use]
+pub fn authentication_results(message: &Message, by: &str) -> Option<SmtpString> {
+    let verdict = message.dmarc()?;
+
+    let mut parts = vec![format!("{by};")];
+    if let Some(spf) = message.envelope().spf() {
+        parts.push(format!("spf={}", spf.result()));
+        parts.push(format!("smtp.{}={};", spf.identity(), spf.sender()));
+    }
+    parts.push(format!("dmarc={}", verdict.result()));
+    if let Some(policy) = verdict.policy() {
+        parts.push(format!("(p={policy}"));
+        parts.push(format!("dis={})", verdict.disposition()));
+    }
+    if let Some(domain) = verdict.domain() {
+        parts.push(format!("header.from={domain}"));
+    }
+
+    let header = fold("Authentication-Results:", &parts);
+
+    SmtpString::new(&header).ok()
+}
+
+/// Get the protocol that a message was received with, as registered by [RFC
+/// 3848](https://www.rfc-editor.org/rfc/rfc3848.html).
+///
+/// Sessions that greeted with `HELO` are `SMTP`, and those that greeted with `EHLO` are `ESMTP`.
+/// Encryption is only started with `STARTTLS` and authentication with `AUTH`, both after `EHLO`,
+/// so encrypted and authenticated sessions are always extended.
+fn protocol(message: &Message) -> &'static str {
+    let session = message.session();
+    match (session.is_tls(), session.authenticated_user().is_some()) {
+        (true, true) => "ESMTPSA",
+        (true, false) => "ESMTPS",
+        (false, true) => "ESMTPA",
+        (false, false) if session.is_extended() => "ESMTP",
+        (false, false) => "SMTP",
+    }
+}
+
+/// Format an IP address as an SMTP address literal, such as `[192.0.2.1]` or `[IPv6:2001:db8::1]`.
+///
+/// [RFC 5321 section 4.1.3](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.3).
+pub(crate) fn address_literal(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => format!("[{ip}]"),
+        IpAddr::V6(ip) => ip
+            .to_ipv4_mapped()
+            .map_or_else(|| format!("[IPv6:{ip}]"), |ip| format!("[{ip}]")),
+    }
+}
+
+/// Join the name of a header field and its parts into a header line, folding it onto new lines
+/// (indented with a tab) between parts that would go past [`FOLD_LENGTH`].
+///
+/// Includes the trailing line ending.
+///
+/// [RFC 5322 section 2.2.3](https://www.rfc-editor.org/rfc/rfc5322.html#section-2.2.3).
+pub(crate) fn fold(name: &str, parts: &[String]) -> String {
+    /// The width that a tab is assumed to take up when counting the length of a line.
+    const TAB_WIDTH: usize = 8;
+
+    let mut header = name.to_owned();
+    let mut line_length = header.len();
+    // Folding before the first part of a line would only create an empty line.
+    let mut is_line_start = true;
+
+    for part in parts {
+        if line_length + 1 + part.len() > FOLD_LENGTH && !is_line_start {
+            header.push_str("\r\n\t");
+            line_length = TAB_WIDTH;
+        } else {
+            header.push(' ');
+            line_length += 1;
+        }
+
+        header.push_str(part);
+        line_length += part.len();
+        is_line_start = false;
+    }
+
+    header.push_str("\r\n");
+    header
+}
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Records what the server does as metrics through the [`metrics`] facade.
+//!
+//! Nothing is recorded until the consumer installs a recorder, such as the Prometheus exporter of
+//! `metrics-exporter-prometheus`, which serves the metrics for Prometheus to scrape. [`describe`]
+//! gives each metric its description and unit once a recorder is installed.
+//!
+//! The metrics are:
+//!
+//! - [`CONNECTIONS_ACCEPTED`]: a counter of the TCP connections accepted by the server, including
+//!   those that are turned away before the greeting.
+//! - [`ACTIVE_SESSIONS`]: a gauge of the SMTP sessions in progress.
+//! - [`COMMANDS`]: a counter of the commands received, labeled by their `verb`. Verbs that the
+//!   server does not know are counted as `UNKNOWN`, so that clients cannot create new labels.
+//! - [`COMMAND_DURATION`]: a histogram of the seconds from receiving each command until its reply
+//!   is written, labeled by `verb` like [`COMMANDS`]. This includes the time taken by the handler
+//!   and by checks such as DNS lookups, so slow ones stand out before clients time out.
+//! - [`REPLIES`]: a counter of the replies sent in SMTP sessions, labeled by their `code`.
+//! - [`MESSAGES_RECEIVED`]: a counter of the messages whose data was received in full.
+//! - [`RECEIVED_BYTES`]: a counter of the bytes of data of those messages.
+//! - [`DATA_DURATION`]: a histogram of the seconds from the `354` reply to `DATA` until the final
+//!   reply, including the time taken to deliver the message.
+
+#[cfg(test)]
+mod test;
+
+use std::time::Instant;
+
+use metrics::Unit;
+
+/// The name of the counter of accepted TCP connections.
+pub const CONNECTIONS_ACCEPTED: &str = "smtp_connections_accepted_total";
+/// The name of the gauge of SMTP sessions in progress.
+pub const ACTIVE_SESSIONS: &str = "smtp_sessions_active";
+/// The name of the counter of received commands, labeled by `verb`.
+pub const COMMANDS: &str = "smtp_commands_total";
+/// The name of the histogram of the seconds taken by each command, labeled by `verb`.
+pub const COMMAND_DURATION: &str = "smtp_command_duration_seconds";
+/// The name of the counter of sent replies, labeled by `code`.
+pub const REPLIES: &str = "smtp_replies_total";
+/// The name of the counter of received messages.
+pub const MESSAGES_RECEIVED: &str = "smtp_messages_received_total";
+/// The name of the counter of the bytes of data of received messages.
+pub const RECEIVED_BYTES: &str = "smtp_received_bytes_total";
+/// The name of the histogram of the seconds taken by the data of each message.
+pub const DATA_DURATION: &str = "smtp_data_duration_seconds";
+
+/// The verbs that [`COMMANDS`] is labeled with as they are, in uppercase.
+const VERBS: &[&str] = &[
+    "AUTH", "BDAT", "DATA", "EHLO", "EXPN", "HELO", "HELP", "MAIL", "NOOP", "QUIT", "RCPT", "RSET",
+    "STARTTLS", "VRFY",
+];
+
+/// Describe every metric to the installed recorder.
+///
+/// Call this after installing a recorder, as descriptions given before then are lost.
+pub fn describe() {
+    metrics::describe_counter!(
+        CONNECTIONS_ACCEPTED,
+        Unit::Count,
+        "TCP connections accepted by the server"
+    );
+    metrics::describe_gauge!(ACTIVE_SESSIONS, Unit::Count, "SMTP sessions in progress");
+    metrics::describe_counter!(COMMANDS, Unit::Count, "Commands received, by verb");
+    metrics::describe_histogram!(
+        COMMAND_DURATION,
+        Unit::Seconds,
+        "Time from receiving a command until its reply is written, by verb"
+    );
+    metrics::describe_counter!(REPLIES, Unit::Count, "Replies sent, by code");
+    metrics::describe_counter!(
+        MESSAGES_RECEIVED,
+        Unit::Count,
+        "Messages whose data was received in full"
+    );
+    metrics::describe_counter!(
+        RECEIVED_BYTES,
+        Unit::Bytes,
+        "Bytes of data of received messages"
+    );
+    metrics::describe_histogram!(
+        DATA_DURATION,
+        Unit::Seconds,
+        "Time from the start of the data of a message until the final reply"
+    );
+}
+
+/// Count a TCP connection accepted by the server.
+pub(crate) fn connection_accepted() {
+    metrics::counter!(CONNECTIONS_ACCEPTED).increment(1);
+}
+
+/// Counts an SMTP session as in progress for as long as it is held.
+#[derive(Debug)]
+pub(crate) struct ActiveSession(());
+
+impl ActiveSession {
+    /// Start counting an SMTP session as in progress.
+    pub(crate) fn start() -> Self {
+        metrics::gauge!(ACTIVE_SESSIONS).increment(1);
+        Self(())
+    }
+}
+
+impl Drop for ActiveSession {
+    fn drop(&mut self) {
+        metrics::gauge!(ACTIVE_SESSIONS).decrement(1);
+    }
+}
+
+/// Count a command with the uppercase verb `verb`, timing it until the returned [`CommandTimer`]
+/// is dropped once the command is replied to.
+pub(crate) fn command(verb: &str) -> CommandTimer {
+    let verb = verb_label(verb);
+    metrics::counter!(COMMANDS, "verb" => verb).increment(1);
+
+    CommandTimer {
+        verb,
+        started: Instant::now(),
+    }
+}
+
+/// Get the label that [`COMMANDS`] counts `verb` under.
+fn verb_label(verb: &str) -> &'static str {
+    VERBS
+        .iter()
+        .find(|&&known| known == verb)
+        .copied()
+        .unwrap_or("UNKNOWN")
+}
+
+/// Records how long a command takes into [`COMMAND_DURATION`] once it is dropped, see [`command`].
+#[derive(Debug)]
+pub(crate) struct CommandTimer {
+    /// The label of the verb of the command.
+    verb: &'static str,
+    /// When the command was received.
+    started: Instant,
+}
+
+impl Drop for CommandTimer {
+    fn drop(&mut self) {
+        metrics::histogram!(COMMAND_DURATION, "verb" => self.verb)
+            .record(self.started.elapsed().as_secs_f64());
+    }
+}
+
+/// Count the replies written to the client in `written`.
+///
+/// Only the last line of each reply is counted, so that each multiline reply counts once.
+pub(crate) fn replies(written: &[u8]) {
+    for code in crate::stats::reply_codes(written) {
+        metrics::counter!(REPLIES, "code" => code.to_string()).increment(1);
+    }
+}
+
+/// Records how long the data of a message takes into [`DATA_DURATION`] once it is dropped.
+#[derive(Debug)]
+pub(crate) struct DataTimer(Instant);
+
+impl DataTimer {
+    /// Start timing the data of a message.
+    pub(crate) fn start() -> Self {
+        Self(Instant::now())
+    }
+}
+
+impl Drop for DataTimer {
+    fn drop(&mut self) {
+        metrics::histogram!(DATA_DURATION).record(self.0.elapsed().as_secs_f64());
+    }
+}
+
+/// Count a message whose data of `size` bytes was received in full.
+pub(crate) fn message_received(size: usize) {
+    metrics::counter!(MESSAGES_RECEIVED).increment(1);
+    metrics::counter!(RECEIVED_BYTES).increment(size as u64);
+}
@@ -15,10 +15,14 @@
 // You should have received a copy of the GNU Affero General Public License along with
 // smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
 
-/// An SMTP message.
-///
-/// This will be expanded as the implementation progresses.
-#[allow(dead_code)]
-pub struct Message {
-    data: String,
+//! Tests for [`super`].
+
+use super::*;
+
+#[test]
+fn test_verb_label() {
+    assert_eq!(verb_label("EHLO"), "EHLO");
+    assert_eq!(verb_label("STARTTLS"), "STARTTLS");
+    assert_eq!(verb_label("ehlo"), "UNKNOWN");
+    assert_eq!(verb_label("GET"), "UNKNOWN");
 }
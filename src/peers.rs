@@ -0,0 +1,262 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Counts the connections and failed authentication attempts from each client address to enforce
+//! [`PeerLimits`] and [`AuthLimits::max_failure_rate`].
+//!
+//! See [`Peers`].
+
+use std::{
+    collections::{HashMap, VecDeque},
+    iter,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{self, AuthLimits, PeerLimits},
+    ServerConfig,
+};
+
+/// How many addresses are tracked before those without recent connections are forgotten.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Which of the [`PeerLimits`] a connection exceeded, or whether its address is banned by
+/// [`AuthLimits::ban_duration`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Excess {
+    /// [`PeerLimits::max_connections`].
+    Connections,
+    /// [`PeerLimits::max_rate`].
+    Rate,
+    /// [`AuthLimits::max_failure_rate`], until [`AuthLimits::ban_duration`] passes.
+    Banned,
+}
+
+impl Excess {
+    /// Get the text of the `421` reply that refuses the connection.
+    pub const fn text(self) -> &'static str {
+        match self {
+            Self::Connections => "Too many connections from your address",
+            Self::Rate => "Too many connections from your address recently, slow down",
+            Self::Banned => "Too many failed authentication attempts from your address",
+        }
+    }
+
+    /// Get the name of the setting that refused the connection, for
+    /// [`crate::audit::RejectionSource::Limit`].
+    pub const fn setting(self) -> &'static str {
+        match self {
+            Self::Connections | Self::Rate => "peer_limits",
+            Self::Banned => "auth_limits",
+        }
+    }
+}
+
+/// The connections from each client address, grouped as [`ServerConfig::ipv6_prefix_length`]
+/// describes.
+#[derive(Debug)]
+pub struct Peers {
+    /// The connections from each group of addresses.
+    table: Mutex<Table>,
+}
+
+/// The state of [`Peers`] behind its lock.
+#[derive(Debug)]
+struct Table {
+    /// The connections from each group of addresses.
+    peers: HashMap<IpAddr, Peer>,
+    /// How many groups of addresses are tracked before pruning.
+    prune_at: usize,
+}
+
+/// The connections from one group of addresses.
+#[derive(Debug, Default)]
+struct Peer {
+    /// The number of sessions that have not yet closed.
+    active: usize,
+    /// When each connection within the longest rate limit period was made, oldest first.
+    recent: VecDeque<Instant>,
+    /// When each failed authentication attempt within [`AuthLimits::max_failure_rate`] was made,
+    /// oldest first.
+    failures: VecDeque<Instant>,
+    /// When connections stop being refused after [`AuthLimits::max_failure_rate`] was exceeded.
+    banned_until: Option<Instant>,
+}
+
+impl Peer {
+    /// Check whether `self` has anything worth remembering at `now`, such as sessions, or
+    /// connections or failures within `longest`.
+    fn is_remembered(&self, longest: Duration, now: Instant) -> bool {
+        let is_recent = |then: &Instant| now.duration_since(*then) <= longest;
+
+        self.active > 0
+            || self.recent.back().is_some_and(is_recent)
+            || self.failures.back().is_some_and(is_recent)
+            || self.banned_until.is_some_and(|until| now < until)
+    }
+}
+
+impl Peers {
+    /// Create a new [`Self`] without any connections.
+    pub fn new() -> Self {
+        Self {
+            table: Mutex::new(Table {
+                peers: HashMap::new(),
+                prune_at: PRUNE_THRESHOLD,
+            }),
+        }
+    }
+
+    /// Count a connection from `address`, returning the key that it is counted under until it is
+    /// passed to [`Self::release`], or [`Excess`] if it exceeds the limits for `address` in
+    /// `config`.
+    pub fn admit(&self, address: IpAddr, config: &ServerConfig) -> Result<IpAddr, Excess> {
+        let address = address.to_canonical();
+        let limits = config.limits_for(address);
+        let key = match address {
+            IpAddr::V4(_) => address,
+            IpAddr::V6(_) => config::mask(address, config.ipv6_prefix_length())
+                .expect("the prefix length was validated when the config was built"),
+        };
+        let longest = iter::once(config.peer_limits())
+            .chain(config.network_limits().iter().map(|&(_, limits)| limits))
+            .filter_map(|limits| limits.max_rate())
+            .chain(config.auth_limits().max_failure_rate())
+            .map(|(_, period)| period)
+            .max()
+            .unwrap_or(Duration::ZERO);
+
+        self.table
+            .lock()
+            .expect("the lock is never held across a panic")
+            .admit(key, limits, longest, Instant::now())?;
+        Ok(key)
+    }
+
+    /// Count a failed authentication attempt of a session that was counted under `key` by
+    /// [`Self::admit`], returning whether it exceeds [`AuthLimits::max_failure_rate`], in which
+    /// case connections from `key` are refused for [`AuthLimits::ban_duration`].
+    pub fn fail_auth(&self, key: IpAddr, limits: AuthLimits) -> bool {
+        self.table
+            .lock()
+            .expect("the lock is never held across a panic")
+            .fail_auth(key, limits, Instant::now())
+    }
+
+    /// Stop counting a session that was counted under `key` by [`Self::admit`].
+    pub fn release(&self, key: IpAddr) {
+        let mut table = self
+            .table
+            .lock()
+            .expect("the lock is never held across a panic");
+        if let Some(peer) = table.peers.get_mut(&key) {
+            peer.active = peer.active.saturating_sub(1);
+            if peer.active == 0
+                && peer.recent.is_empty()
+                && peer.failures.is_empty()
+                && peer.banned_until.is_none()
+            {
+                table.peers.remove(&key);
+            }
+        }
+    }
+}
+
+impl Table {
+    /// Count a connection from the group of addresses `key` at `now`, unless it exceeds `limits`.
+    ///
+    /// Groups without sessions, bans, or connections or failures in the last `longest` are
+    /// forgotten once there are enough of them.
+    fn admit(
+        &mut self,
+        key: IpAddr,
+        limits: PeerLimits,
+        longest: Duration,
+        now: Instant,
+    ) -> Result<(), Excess> {
+        if self.peers.len() >= self.prune_at {
+            self.peers
+                .retain(|_, peer| peer.is_remembered(longest, now));
+            self.prune_at = (self.peers.len() * 2).max(PRUNE_THRESHOLD);
+        }
+        let peer = self.peers.entry(key).or_default();
+
+        if peer.banned_until.is_some_and(|until| now < until) {
+            return Err(Excess::Banned);
+        }
+        peer.banned_until = None;
+
+        if let Some((_, period)) = limits.max_rate() {
+            while peer
+                .recent
+                .front()
+                .is_some_and(|&then| now.duration_since(then) > period)
+            {
+                peer.recent.pop_front();
+            }
+            peer.recent.push_back(now);
+        } else {
+            peer.recent.clear();
+        }
+
+        if limits
+            .max_connections()
+            .is_some_and(|max| peer.active >= max)
+        {
+            return Err(Excess::Connections);
+        }
+        if limits
+            .max_rate()
+            .is_some_and(|(max, _)| peer.recent.len() > max)
+        {
+            return Err(Excess::Rate);
+        }
+
+        peer.active += 1;
+        Ok(())
+    }
+
+    /// Count a failed authentication attempt from the group of addresses `key` at `now`,
+    /// returning whether it exceeds [`AuthLimits::max_failure_rate`] of `limits`, in which case the
+    /// group is banned for [`AuthLimits::ban_duration`].
+    fn fail_auth(&mut self, key: IpAddr, limits: AuthLimits, now: Instant) -> bool {
+        let Some((max, period)) = limits.max_failure_rate() else {
+            return false;
+        };
+        let peer = self.peers.entry(key).or_default();
+
+        while peer
+            .failures
+            .front()
+            .is_some_and(|&then| now.duration_since(then) > period)
+        {
+            peer.failures.pop_front();
+        }
+        peer.failures.push_back(now);
+
+        if peer.failures.len() <= max {
+            return false;
+        }
+        if !limits.ban_duration().is_zero() {
+            peer.banned_until = now.checked_add(limits.ban_duration());
+            peer.failures.clear();
+        }
+        true
+    }
+}
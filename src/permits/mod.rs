@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Hands out a permit to each session to enforce [`ServerConfig::max_connections`].
+//!
+//! See [`Permits`].
+
+#[cfg(test)]
+mod test;
+
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::ServerConfig;
+
+/// The permits for the sessions of the servers, one of which is held by each session until it
+/// closes.
+///
+/// The number of permits follows [`ServerConfig::max_connections`] of the configuration that each
+/// permit is asked for with, so that a reloaded limit applies from the next connection.
+#[derive(Debug)]
+pub struct Permits {
+    /// The permits that are not held by a session.
+    semaphore: Arc<Semaphore>,
+    /// The number of permits, behind its lock.
+    limit: Mutex<Limit>,
+}
+
+/// The number of permits of [`Permits`].
+#[derive(Debug, Default)]
+struct Limit {
+    /// The number of permits, whether held or not.
+    max: usize,
+    /// The number of held permits that are forgotten rather than returned as their sessions close,
+    /// because the limit was lowered below the number of sessions.
+    debt: usize,
+}
+
+impl Permits {
+    /// Create a new [`Self`] without any permits, which are added by the first configuration that
+    /// one is asked for with.
+    pub fn new() -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(0)),
+            limit: Mutex::default(),
+        }
+    }
+
+    /// Wait for a permit for a session within [`ServerConfig::max_connections`] of `config`.
+    pub async fn acquire(&self, config: &ServerConfig) -> OwnedSemaphorePermit {
+        self.resize(config);
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed")
+    }
+
+    /// Get a permit for a session within [`ServerConfig::max_connections`] of `config`, or `None`
+    /// if every permit is held.
+    pub fn try_acquire(&self, config: &ServerConfig) -> Option<OwnedSemaphorePermit> {
+        self.resize(config);
+        self.semaphore.clone().try_acquire_owned().ok()
+    }
+
+    /// Return the permit of a session that closed, unless the limit was lowered since.
+    pub fn release(&self, permit: OwnedSemaphorePermit) {
+        let mut limit = self.lock();
+        let is_owed = limit.debt > 0;
+        limit.debt = limit.debt.saturating_sub(1);
+        drop(limit);
+
+        if is_owed {
+            permit.forget();
+        }
+    }
+
+    /// Add or remove permits to match [`ServerConfig::max_connections`] of `config`.
+    ///
+    /// Permits that are held cannot be removed, so they are forgotten as their sessions close.
+    fn resize(&self, config: &ServerConfig) {
+        let max = config
+            .max_connections()
+            .unwrap_or(Semaphore::MAX_PERMITS)
+            .min(Semaphore::MAX_PERMITS);
+        let mut limit = self.lock();
+
+        if max > limit.max {
+            let added = max - limit.max;
+            let repaid = added.min(limit.debt);
+            limit.debt -= repaid;
+            self.semaphore.add_permits(added - repaid);
+        } else {
+            let removed = limit.max - max;
+            limit.debt += removed - self.semaphore.forget_permits(removed);
+        }
+        limit.max = max;
+    }
+
+    /// Lock the number of permits.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Limit> {
+        self.limit
+            .lock()
+            .expect("the lock is never held across a panic")
+    }
+}
@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::time::Duration;
+
+use super::*;
+use crate::config::ConfigError;
+
+#[tokio::test]
+#[expect(
+    clippy::significant_drop_tightening,
+    reason = "the permits are held while the limit changes, then released"
+)]
+async fn test_resize() -> Result<(), ConfigError> {
+    let with_max = |max| {
+        ServerConfig::builder()
+            .hostname("mx.example.com")
+            .max_connections(max)
+            .build()
+    };
+    let (one, two) = (with_max(1)?, with_max(2)?);
+    let permits = Permits::new();
+
+    // Tests that permits are only given within the limit, and that `acquire` waits for one.
+    let first = permits.try_acquire(&two);
+    let second = permits.try_acquire(&two);
+    assert!(first.is_some() && second.is_some());
+    assert!(permits.try_acquire(&two).is_none());
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), permits.acquire(&two))
+            .await
+            .is_err()
+    );
+
+    // Tests that a lowered limit forgets the permits of the sessions past it as they close.
+    assert!(permits.try_acquire(&one).is_none());
+    permits.release(first.expect("the permit was given"));
+    assert!(permits.try_acquire(&one).is_none());
+    permits.release(second.expect("the permit was given"));
+    let third = permits.try_acquire(&one);
+    assert!(third.is_some());
+    assert!(permits.try_acquire(&one).is_none());
+
+    // Tests that a raised limit adds permits at once.
+    let unlimited = ServerConfig::builder().hostname("mx.example.com").build()?;
+    assert!(permits.try_acquire(&unlimited).is_some());
+    assert!(permits.try_acquire(&unlimited).is_some());
+
+    Ok(())
+}
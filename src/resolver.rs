@@ -0,0 +1,301 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The DNS lookups that the server makes, such as to verify the identity given in `HELO`.
+//!
+//! See [`Resolver`].
+
+use std::{
+    fmt::Debug,
+    future::Future,
+    io,
+    net::IpAddr,
+    sync::{Arc, LazyLock},
+};
+
+use futures_util::{future::BoxFuture, FutureExt};
+
+/// Makes the DNS lookups that the server needs.
+///
+/// Every lookup made by the server goes through the resolver in
+/// [`crate::ServerConfig::resolver`], so that tests can answer lookups themselves and operators
+/// can choose which servers to ask. Defaults to [`SystemResolver`], or with the `hickory` feature,
+/// [`HickoryResolver`] can be used to make every kind of lookup.
+///
+/// Each lookup returns an empty list if the name has no records of that kind, and fails if the
+/// lookup itself failed, such as when no server answered. Names are returned in ASCII, with the
+/// labels of internationalized names in punycode such as `xn--bcher-kva.example`, as they are
+/// written into headers and compared with the names that clients give.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::{io, net::{IpAddr, Ipv4Addr}};
+/// # use smtp_gateway::{resolver::Resolver, ServerConfig};
+/// #
+/// /// Resolves every name to the same address.
+/// struct Fixed;
+///
+/// impl Resolver for Fixed {
+///     async fn lookup_ip(&self, _: &str) -> io::Result<Vec<IpAddr>> {
+///         Ok(vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))])
+///     }
+/// }
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = ServerConfig::builder().resolver(Fixed).build()?;
+/// #     Ok(())
+/// # }
+/// ```
+pub trait Resolver: Send + Sync + 'static {
+    /// Look up the IP addresses of `name`, from its `A` and `AAAA` records.
+    fn lookup_ip(&self, name: &str) -> impl Future<Output = io::Result<Vec<IpAddr>>> + Send;
+
+    /// Look up the `TXT` records of `name`, each with its strings joined together.
+    ///
+    /// Unsupported by default.
+    fn lookup_txt(&self, _name: &str) -> impl Future<Output = io::Result<Vec<String>>> + Send {
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
+    /// Look up the domain names of `address`, from its `PTR` records.
+    ///
+    /// Unsupported by default.
+    fn lookup_ptr(&self, _address: IpAddr) -> impl Future<Output = io::Result<Vec<String>>> + Send {
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
+    /// Look up the mail exchangers of `name`, from its `MX` records, most preferred first.
+    ///
+    /// Unsupported by default.
+    fn lookup_mx(&self, _name: &str) -> impl Future<Output = io::Result<Vec<String>>> + Send {
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+}
+
+/// Resolves names with the resolver of the operating system, through [`tokio::net::lookup_host`].
+///
+/// This can only look up addresses, and cannot tell a name without addresses from a failed
+/// lookup, so both fail.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        Ok(tokio::net::lookup_host((name, 0))
+            .await?
+            .map(|address| address.ip())
+            .collect())
+    }
+}
+
+/// Resolves names with [`hickory_resolver`], which can make every kind of lookup.
+///
+/// Created with the configuration of the operating system by [`Self::system`], or from a
+/// resolver with its own configuration, such as one that asks particular servers.
+#[cfg(feature = "hickory")]
+#[derive(Clone)]
+pub struct HickoryResolver {
+    /// The resolver that lookups are made with.
+    resolver: hickory_resolver::TokioResolver,
+}
+
+#[cfg(feature = "hickory")]
+impl HickoryResolver {
+    /// Create a new [`Self`] with the configuration of the operating system, such as from
+    /// `/etc/resolv.conf`.
+    ///
+    /// # Errors
+    ///
+    /// - [`std::io::Error`] if the configuration of the operating system could not be read.
+    pub fn system() -> io::Result<Self> {
+        Ok(Self {
+            resolver: hickory_resolver::TokioResolver::builder_tokio()
+                .map_err(io::Error::other)?
+                .build(),
+        })
+    }
+}
+
+#[cfg(feature = "hickory")]
+impl From<hickory_resolver::TokioResolver> for HickoryResolver {
+    fn from(resolver: hickory_resolver::TokioResolver) -> Self {
+        Self { resolver }
+    }
+}
+
+#[cfg(feature = "hickory")]
+impl Debug for HickoryResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HickoryResolver").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "hickory")]
+impl Resolver for HickoryResolver {
+    async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        hickory_result(self.resolver.lookup_ip(name).await, |lookup| {
+            lookup.iter().collect()
+        })
+    }
+
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+        hickory_result(self.resolver.txt_lookup(name).await, |lookup| {
+            lookup
+                .iter()
+                .map(|txt| {
+                    txt.txt_data()
+                        .iter()
+                        .map(|data| String::from_utf8_lossy(data))
+                        .collect()
+                })
+                .collect()
+        })
+    }
+
+    async fn lookup_ptr(&self, address: IpAddr) -> io::Result<Vec<String>> {
+        hickory_result(self.resolver.reverse_lookup(address).await, |lookup| {
+            lookup.iter().map(|name| hickory_name(name)).collect()
+        })
+    }
+
+    async fn lookup_mx(&self, name: &str) -> io::Result<Vec<String>> {
+        hickory_result(self.resolver.mx_lookup(name).await, |lookup| {
+            let mut exchangers: Vec<_> = lookup.iter().collect();
+            exchangers.sort_by_key(|mx| mx.preference());
+
+            exchangers
+                .into_iter()
+                .map(|mx| hickory_name(mx.exchange()))
+                .collect()
+        })
+    }
+}
+
+/// Convert the result of a lookup with [`hickory_resolver`], treating a name without records of
+/// the kind as an empty list.
+#[cfg(feature = "hickory")]
+fn hickory_result<L, T>(
+    result: Result<L, hickory_resolver::ResolveError>,
+    convert: impl FnOnce(L) -> Vec<T>,
+) -> io::Result<Vec<T>> {
+    match result {
+        Ok(lookup) => Ok(convert(lookup)),
+        Err(e) if e.is_no_records_found() => Ok(Vec::new()),
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+/// Get `name` from a lookup with [`hickory_resolver`] in ASCII, without its trailing dot.
+#[cfg(feature = "hickory")]
+pub(crate) fn hickory_name(name: &hickory_resolver::Name) -> String {
+    name.to_ascii().trim_end_matches('.').to_owned()
+}
+
+/// A [`Resolver`] that can be shared between sessions and kept in a [`crate::ServerConfig`].
+///
+/// Compares equal to clones of itself. Defaults to a [`SystemResolver`] that every default
+/// shares.
+#[derive(Clone)]
+pub struct SharedResolver {
+    /// The resolver that lookups are made with.
+    resolver: Arc<dyn DynResolver>,
+}
+
+impl SharedResolver {
+    /// Create a new [`Self`] that makes lookups with `resolver`.
+    pub fn new(resolver: impl Resolver) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+        }
+    }
+}
+
+impl Default for SharedResolver {
+    fn default() -> Self {
+        /// The resolver that every default shares, so that default configurations compare equal.
+        static SYSTEM: LazyLock<SharedResolver> =
+            LazyLock::new(|| SharedResolver::new(SystemResolver));
+
+        SYSTEM.clone()
+    }
+}
+
+impl PartialEq for SharedResolver {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.resolver, &other.resolver)
+    }
+}
+
+impl Eq for SharedResolver {}
+
+impl Debug for SharedResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedResolver").finish_non_exhaustive()
+    }
+}
+
+impl Resolver for SharedResolver {
+    async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        self.resolver.lookup_ip(name).await
+    }
+
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+        self.resolver.lookup_txt(name).await
+    }
+
+    async fn lookup_ptr(&self, address: IpAddr) -> io::Result<Vec<String>> {
+        self.resolver.lookup_ptr(address).await
+    }
+
+    async fn lookup_mx(&self, name: &str) -> io::Result<Vec<String>> {
+        self.resolver.lookup_mx(name).await
+    }
+}
+
+/// A [`Resolver`] with its lookups boxed, so that it can be used as a trait object.
+trait DynResolver: Send + Sync {
+    /// See [`Resolver::lookup_ip`].
+    fn lookup_ip<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Vec<IpAddr>>>;
+
+    /// See [`Resolver::lookup_txt`].
+    fn lookup_txt<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Vec<String>>>;
+
+    /// See [`Resolver::lookup_ptr`].
+    fn lookup_ptr(&self, address: IpAddr) -> BoxFuture<'_, io::Result<Vec<String>>>;
+
+    /// See [`Resolver::lookup_mx`].
+    fn lookup_mx<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Vec<String>>>;
+}
+
+impl<R: Resolver> DynResolver for R {
+    fn lookup_ip<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Vec<IpAddr>>> {
+        Resolver::lookup_ip(self, name).boxed()
+    }
+
+    fn lookup_txt<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Vec<String>>> {
+        Resolver::lookup_txt(self, name).boxed()
+    }
+
+    fn lookup_ptr(&self, address: IpAddr) -> BoxFuture<'_, io::Result<Vec<String>>> {
+        Resolver::lookup_ptr(self, address).boxed()
+    }
+
+    fn lookup_mx<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Vec<String>>> {
+        Resolver::lookup_mx(self, name).boxed()
+    }
+}
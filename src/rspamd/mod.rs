@@ -0,0 +1,601 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Scanning messages for spam with [Rspamd](https://rspamd.com/).
+//!
+//! See [`RspamdPolicy`] and [`scan`].
+//!
+//! The message is submitted to the `/checkv2` endpoint of the normal worker of Rspamd, along with
+//! what the server knows about the session, as described by the [protocol of
+//! Rspamd](https://docs.rspamd.com/developers/protocol).
+
+#[cfg(test)]
+mod test;
+
+use std::{collections::BTreeMap, fmt::Debug, io, time::Duration};
+
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    handler::{Defer, Response},
+    message::Message,
+    redact::REDACTED,
+    ServerConfig,
+};
+
+/// The longest response that is read from Rspamd, in bytes.
+const MAX_RESPONSE: u64 = 1024 * 1024;
+
+/// Where messages are scanned by Rspamd, and what is done when it cannot be reached.
+///
+/// Each message is scanned once its data is received, before it is handed to
+/// [`crate::SmtpHandler::on_message`], and the action that Rspamd returns decides what happens to
+/// it:
+///
+/// - [`RspamdAction::Reject`] rejects the message with `554 5.7.1`.
+/// - [`RspamdAction::SoftReject`] and [`RspamdAction::Greylist`] defer it with `451 4.7.1`.
+/// - [`RspamdAction::RewriteSubject`] and [`RspamdAction::AddHeader`] accept it with an
+///   `X-Spam: Yes` header, and the former with the subject that Rspamd rewrote.
+/// - [`RspamdAction::Accept`] accepts it.
+///
+/// Any `X-Spam:` header that the client sent is removed, and the headers that Rspamd asks to add
+/// or remove are applied to every accepted message, which keeps its [`RspamdVerdict`] in
+/// [`Message::rspamd`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use smtp_gateway::rspamd::RspamdPolicy;
+/// #
+/// let policy = RspamdPolicy::new("rspamd.internal:11333").with_timeout(Duration::from_secs(10));
+///
+/// assert!(!policy.defer_on_error());
+/// ```
+#[derive(PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct RspamdPolicy {
+    /// The host and port of the normal worker of Rspamd.
+    address: String,
+    /// How long to wait for a scan.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    timeout: Duration,
+    /// The password sent to Rspamd, if any.
+    password: Option<String>,
+    /// Whether messages are deferred when they cannot be scanned.
+    defer_on_error: bool,
+}
+
+impl RspamdPolicy {
+    /// Create a new [`Self`] that scans messages with the normal worker of Rspamd at `address`,
+    /// a host and port such as `127.0.0.1:11333`.
+    #[must_use]
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Get the host and port of the normal worker of Rspamd, `127.0.0.1:11333` by default.
+    #[must_use]
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Get how long to wait for a scan, including connecting to Rspamd, 20 seconds by default.
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Get the password sent to Rspamd in the `Password` header, or `None` if there is none,
+    /// which is the default.
+    #[must_use]
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+
+    /// Get whether messages are deferred with `451 4.3.0` when they cannot be scanned, or
+    /// accepted without a verdict, which is the default.
+    #[must_use]
+    pub const fn defer_on_error(&self) -> bool {
+        self.defer_on_error
+    }
+
+    /// Set how long to wait for a scan. See [`Self::timeout`].
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the password sent to Rspamd. See [`Self::password`].
+    #[must_use]
+    pub fn with_password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Set whether messages are deferred when they cannot be scanned. See
+    /// [`Self::defer_on_error`].
+    #[must_use]
+    pub const fn with_defer_on_error(mut self, defer: bool) -> Self {
+        self.defer_on_error = defer;
+        self
+    }
+}
+
+impl Default for RspamdPolicy {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:11333".to_owned(),
+            timeout: Duration::from_secs(20),
+            password: None,
+            defer_on_error: false,
+        }
+    }
+}
+
+impl Debug for RspamdPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RspamdPolicy")
+            .field("address", &self.address)
+            .field("timeout", &self.timeout)
+            .field("password", &self.password.as_ref().map(|_| REDACTED))
+            .field("defer_on_error", &self.defer_on_error)
+            .finish()
+    }
+}
+
+/// The action that Rspamd recommends for a message, from the least to the most severe.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum RspamdAction {
+    /// Accept the message (`no action`).
+    Accept,
+    /// Defer the message so that the client tries again later (`greylist`).
+    Greylist,
+    /// Accept the message, marking it as spam with a header (`add header`).
+    AddHeader,
+    /// Accept the message, marking it as spam in its subject (`rewrite subject`).
+    RewriteSubject,
+    /// Defer the message, such as when a rate limit is reached (`soft reject`).
+    SoftReject,
+    /// Reject the message (`reject`).
+    Reject,
+}
+
+impl RspamdAction {
+    /// Parse the name that Rspamd gives an action, such as `add header`.
+    #[must_use]
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "no action" => Self::Accept,
+            "greylist" => Self::Greylist,
+            "add header" => Self::AddHeader,
+            "rewrite subject" => Self::RewriteSubject,
+            "soft reject" => Self::SoftReject,
+            "reject" => Self::Reject,
+            _ => return None,
+        })
+    }
+
+    /// Get the name that Rspamd gives the action.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Accept => "no action",
+            Self::Greylist => "greylist",
+            Self::AddHeader => "add header",
+            Self::RewriteSubject => "rewrite subject",
+            Self::SoftReject => "soft reject",
+            Self::Reject => "reject",
+        }
+    }
+
+    /// Whether the action marks the message as spam but accepts it.
+    const fn is_spam(self) -> bool {
+        matches!(self, Self::AddHeader | Self::RewriteSubject)
+    }
+}
+
+/// The result of scanning a message with Rspamd, see [`scan`].
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RspamdVerdict {
+    /// The recommended action.
+    action: RspamdAction,
+    /// The score of the message.
+    score: f64,
+    /// The score at which the message is rejected.
+    required_score: f64,
+    /// The names of the symbols that matched, in order.
+    symbols: Vec<String>,
+    /// The text that Rspamd gives for the reply, if any.
+    message: Option<String>,
+    /// The rewritten subject, if any.
+    subject: Option<String>,
+    /// The headers to add, in order.
+    add_headers: Vec<(String, String)>,
+    /// The names of the headers to remove.
+    remove_headers: Vec<String>,
+}
+
+// Scores are parsed from JSON, which cannot express NaN.
+impl Eq for RspamdVerdict {}
+
+impl RspamdVerdict {
+    /// Get the action that Rspamd recommends.
+    #[must_use]
+    pub const fn action(&self) -> RspamdAction {
+        self.action
+    }
+
+    /// Get the score of the message.
+    #[must_use]
+    pub const fn score(&self) -> f64 {
+        self.score
+    }
+
+    /// Get the score at which Rspamd recommends rejecting the message.
+    #[must_use]
+    pub const fn required_score(&self) -> f64 {
+        self.required_score
+    }
+
+    /// Get the names of the symbols that matched the message, such as `R_SPF_ALLOW`, in order.
+    #[must_use]
+    pub fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    /// Get the text that Rspamd gives for the reply to the client, if any.
+    #[must_use]
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Get the subject that Rspamd rewrote for [`RspamdAction::RewriteSubject`], if any.
+    #[must_use]
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    /// Get the names and values of the headers that Rspamd asks to add to the message.
+    #[must_use]
+    pub fn add_headers(&self) -> &[(String, String)] {
+        &self.add_headers
+    }
+
+    /// Get the names of the headers that Rspamd asks to remove from the message.
+    #[must_use]
+    pub fn remove_headers(&self) -> &[String] {
+        &self.remove_headers
+    }
+
+    /// Get the reply that rejects or defers the message, or `None` if it is accepted.
+    fn response(&self) -> Option<Response> {
+        let (code, enhanced_code, text) = match self.action {
+            RspamdAction::Reject => (554, "5.7.1", "Spam message rejected"),
+            RspamdAction::SoftReject => (451, "4.7.1", "Try again later"),
+            RspamdAction::Greylist => (451, "4.7.1", "Greylisted, please try again later"),
+            _ => return None,
+        };
+
+        self.message
+            .as_deref()
+            .and_then(|message| Response::parse(&format!("{code} {enhanced_code} {message}")).ok())
+            .or_else(|| Response::parse(&format!("{code} {enhanced_code} {text}")).ok())
+    }
+}
+
+/// The response of Rspamd to `/checkv2`, with the fields that the server uses.
+#[derive(serde::Deserialize)]
+struct Reply {
+    /// The name of the recommended action.
+    action: String,
+    /// The score of the message.
+    #[serde(default)]
+    score: f64,
+    /// The score at which the message is rejected.
+    #[serde(default)]
+    required_score: f64,
+    /// The symbols that matched, by name.
+    #[serde(default)]
+    symbols: BTreeMap<String, Value>,
+    /// Messages for the server, such as the text of the reply.
+    #[serde(default)]
+    messages: BTreeMap<String, Value>,
+    /// The rewritten subject.
+    subject: Option<String>,
+    /// Changes to the headers of the message.
+    #[serde(default)]
+    milter: Milter,
+}
+
+/// The changes that Rspamd asks for in the headers of the message.
+#[derive(serde::Deserialize, Default)]
+struct Milter {
+    /// The headers to add, by name.
+    #[serde(default)]
+    add_headers: BTreeMap<String, AddHeader>,
+    /// The headers to remove, by name.
+    #[serde(default)]
+    remove_headers: BTreeMap<String, Value>,
+}
+
+/// The value of a header that Rspamd asks to add, in any of the forms that it uses.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum AddHeader {
+    /// A bare value.
+    Value(String),
+    /// A value with the position to add it at.
+    Ordered {
+        /// The value.
+        value: String,
+    },
+    /// More than one value.
+    Many(Vec<Self>),
+}
+
+impl AddHeader {
+    /// Add each value of the header to `values`.
+    fn collect(self, name: &str, values: &mut Vec<(String, String)>) {
+        match self {
+            Self::Value(value) | Self::Ordered { value } => values.push((name.to_owned(), value)),
+            Self::Many(headers) => {
+                for header in headers {
+                    header.collect(name, values);
+                }
+            }
+        }
+    }
+}
+
+/// Scan `message` with the Rspamd of `policy`, giving it the details of the session that the
+/// message was received through.
+///
+/// # Errors
+///
+/// - [`io::Error`] if Rspamd cannot be reached in time, or its response is not a valid verdict.
+pub async fn scan(policy: &RspamdPolicy, message: &Message) -> io::Result<RspamdVerdict> {
+    let request = request(policy, message);
+
+    let response = tokio::time::timeout(policy.timeout, async {
+        let mut stream = TcpStream::connect(&policy.address).await?;
+        stream.write_all(&request).await?;
+        stream.write_all(message.data()).await?;
+
+        let mut response = Vec::new();
+        stream.take(MAX_RESPONSE).read_to_end(&mut response).await?;
+        io::Result::Ok(response)
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Rspamd did not respond in time"))??;
+
+    parse(&body(&response)?)
+}
+
+/// Create the head of the HTTP request that submits `message` to Rspamd.
+///
+/// Values that cannot be sent in a header are left out.
+fn request(policy: &RspamdPolicy, message: &Message) -> Vec<u8> {
+    let session = message.session();
+    let mut headers = vec![
+        ("Host", policy.address.clone()),
+        ("Connection", "close".to_owned()),
+        ("Content-Length", message.data().len().to_string()),
+        ("IP", session.peer_addr().ip().to_canonical().to_string()),
+        ("Queue-Id", session.id().to_string()),
+        (
+            "From",
+            message
+                .envelope()
+                .reverse_path()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+        ),
+    ];
+    if let Some(helo) = session.helo() {
+        headers.push(("Helo", helo.to_string()));
+    }
+    if let Some(hostname) = session.peer_hostname() {
+        headers.push(("Hostname", hostname.to_owned()));
+    }
+    if let Some(user) = session.authenticated_user() {
+        headers.push(("User", user.to_owned()));
+    }
+    if let Some(password) = &policy.password {
+        headers.push(("Password", password.clone()));
+    }
+    for recipient in message.envelope().accepted() {
+        headers.push(("Rcpt", recipient.forward_path().to_string()));
+    }
+
+    let mut request = b"POST /checkv2 HTTP/1.1\r\n".to_vec();
+    for (name, value) in headers {
+        if value
+            .bytes()
+            .all(|byte| byte == b'\t' || !byte.is_ascii_control())
+        {
+            request.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+        }
+    }
+    request.extend_from_slice(b"\r\n");
+
+    request
+}
+
+/// Get the body of the HTTP `response` of Rspamd, decoding it if it is chunked.
+///
+/// # Errors
+///
+/// - [`io::Error`] if `response` is malformed or is not a success.
+fn body(response: &[u8]) -> io::Result<Vec<u8>> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed response from Rspamd");
+
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let head = std::str::from_utf8(&response[..end]).map_err(|_| malformed())?;
+    let body = &response[end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or_default();
+    let code = status.split(' ').nth(1).unwrap_or_default();
+    if code != "200" {
+        return Err(io::Error::other(format!("Rspamd responded with {status}")));
+    }
+
+    let chunked = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.eq_ignore_ascii_case("Transfer-Encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+    if !chunked {
+        return Ok(body.to_vec());
+    }
+
+    let mut decoded = Vec::new();
+    let mut rest = body;
+    loop {
+        let line_end = rest
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or_else(malformed)?;
+        let size = std::str::from_utf8(&rest[..line_end]).map_err(|_| malformed())?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| malformed())?;
+        if size == 0 {
+            return Ok(decoded);
+        }
+
+        let chunk = rest
+            .get(line_end + 2..line_end + 2 + size)
+            .ok_or_else(malformed)?;
+        decoded.extend_from_slice(chunk);
+        rest = rest.get(line_end + 4 + size..).ok_or_else(malformed)?;
+    }
+}
+
+/// Parse the JSON `body` of the response of Rspamd into a [`RspamdVerdict`].
+///
+/// # Errors
+///
+/// - [`io::Error`] if `body` is not a valid response.
+fn parse(body: &[u8]) -> io::Result<RspamdVerdict> {
+    let reply: Reply = serde_json::from_slice(body)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    let action = RspamdAction::parse(&reply.action).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown Rspamd action {}", reply.action),
+        )
+    })?;
+
+    let mut add_headers = Vec::new();
+    for (name, header) in reply.milter.add_headers {
+        header.collect(&name, &mut add_headers);
+    }
+
+    Ok(RspamdVerdict {
+        action,
+        score: reply.score,
+        required_score: reply.required_score,
+        symbols: reply.symbols.into_keys().collect(),
+        message: reply
+            .messages
+            .get("smtp_message")
+            .and_then(Value::as_str)
+            .map(ToOwned::to_owned),
+        subject: reply.subject,
+        add_headers,
+        remove_headers: reply.milter.remove_headers.into_keys().collect(),
+    })
+}
+
+/// Scan `message` if [`ServerConfig::rspamd_policy`] is set, keeping the verdict in it and
+/// changing its headers as the verdict asks.
+///
+/// # Errors
+///
+/// - The [`Response`] to reply with if the message is rejected or deferred.
+pub(crate) async fn apply(
+    config: &ServerConfig,
+    mut message: Message,
+) -> Result<Message, Response> {
+    let Some(policy) = config.rspamd_policy() else {
+        return Ok(message);
+    };
+    let id = message.session().id();
+
+    let verdict = match scan(policy, &message).await {
+        Ok(verdict) => verdict,
+        Err(error) => {
+            println!("[{id}] Failed to scan message with Rspamd: {error}");
+            if policy.defer_on_error {
+                return Err(Defer::LocalError.response());
+            }
+            return Ok(message);
+        }
+    };
+    println!(
+        "[{id}] Rspamd scored message {:.2}/{:.2} ({})",
+        verdict.score,
+        verdict.required_score,
+        verdict.action.as_str()
+    );
+    if let Some(response) = verdict.response() {
+        return Err(response);
+    }
+
+    message.remove_header("X-Spam");
+    for name in &verdict.remove_headers {
+        message.remove_header(name);
+    }
+    let mut headers = verdict.add_headers.clone();
+    if verdict.action.is_spam() {
+        headers.push(("X-Spam".to_owned(), "Yes".to_owned()));
+    }
+    if let Some(subject) = verdict.subject.as_deref() {
+        if verdict.action == RspamdAction::RewriteSubject {
+            headers.push(("Subject".to_owned(), subject.to_owned()));
+        }
+    }
+    for (name, value) in headers {
+        let result = if name.eq_ignore_ascii_case("Subject") {
+            message.replace_header(&name, &value)
+        } else {
+            message.add_header(&name, &value)
+        };
+        if let Err(error) = result {
+            println!("[{id}] Failed to add {name} header from Rspamd: {error}");
+        }
+    }
+
+    Ok(message.with_rspamd(verdict))
+}
@@ -0,0 +1,161 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use super::*;
+
+/// A response of Rspamd to `/checkv2`, as given by its documentation.
+const REPLY: &str = r#"{
+    "is_skipped": false,
+    "score": 7.5,
+    "required_score": 15,
+    "action": "rewrite subject",
+    "symbols": {
+        "R_SPF_ALLOW": { "name": "R_SPF_ALLOW", "score": -0.2 },
+        "BAYES_SPAM": { "name": "BAYES_SPAM", "score": 5.1 }
+    },
+    "messages": { "smtp_message": "Looks like spam" },
+    "subject": "***SPAM*** Hello",
+    "milter": {
+        "add_headers": {
+            "X-Spamd-Bar": { "value": "+++++++", "order": 0 },
+            "X-Rspamd-Server": "scanner",
+            "X-Extra": [{ "value": "a", "order": 0 }, { "value": "b", "order": 1 }]
+        },
+        "remove_headers": { "X-Spamd-Bar": 0 }
+    }
+}"#;
+
+#[test]
+fn test_parse() -> io::Result<()> {
+    let verdict = parse(REPLY.as_bytes())?;
+
+    assert_eq!(verdict.action(), RspamdAction::RewriteSubject);
+    assert!((verdict.score() - 7.5).abs() < f64::EPSILON);
+    assert!((verdict.required_score() - 15.0).abs() < f64::EPSILON);
+    assert_eq!(verdict.symbols(), ["BAYES_SPAM", "R_SPF_ALLOW"]);
+    assert_eq!(verdict.message(), Some("Looks like spam"));
+    assert_eq!(verdict.subject(), Some("***SPAM*** Hello"));
+    assert_eq!(verdict.remove_headers(), ["X-Spamd-Bar"]);
+    assert_eq!(verdict.add_headers().len(), 4);
+    assert!(verdict
+        .add_headers()
+        .contains(&("X-Extra".to_owned(), "b".to_owned())));
+
+    // Tests that unknown actions and malformed responses are errors.
+    assert!(parse(br#"{ "action": "quarantine" }"#).is_err());
+    assert!(parse(b"not json").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_actions() -> io::Result<()> {
+    for action in [
+        RspamdAction::Accept,
+        RspamdAction::Greylist,
+        RspamdAction::AddHeader,
+        RspamdAction::RewriteSubject,
+        RspamdAction::SoftReject,
+        RspamdAction::Reject,
+    ] {
+        assert_eq!(RspamdAction::parse(action.as_str()), Some(action));
+    }
+
+    let reply = |action: &str| parse(format!(r#"{{ "action": "{action}" }}"#).as_bytes());
+    assert_eq!(reply("no action")?.response(), None);
+    assert_eq!(reply("add header")?.response(), None);
+    assert_eq!(
+        reply("reject")?
+            .response()
+            .map(|response| response.to_string()),
+        Some("554 5.7.1 Spam message rejected".to_owned())
+    );
+    assert_eq!(
+        reply("greylist")?
+            .response()
+            .map(|response| response.code()),
+        Some(451)
+    );
+    // Tests that the text of Rspamd is used for the reply.
+    let verdict = parse(
+        br#"{ "action": "soft reject", "messages": { "smtp_message": "Rate limit exceeded" } }"#,
+    )?;
+    assert_eq!(
+        verdict.response().map(|response| response.to_string()),
+        Some("451 4.7.1 Rate limit exceeded".to_owned())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_body() -> io::Result<()> {
+    assert_eq!(
+        body(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}")?,
+        b"{}"
+    );
+    assert_eq!(
+        body(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n{\"a\r\n4\r\n\":1}\r\n0\r\n\r\n")?,
+        b"{\"a\":1}"
+    );
+    assert!(body(b"HTTP/1.1 500 Internal Server Error\r\n\r\n").is_err());
+    assert!(body(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n9\r\nab").is_err());
+    assert!(body(b"garbage").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_request() -> Result<(), Box<dyn std::error::Error>> {
+    let message = Message::builder()
+        .reverse_path("smith@example.com")
+        .forward_path("jones@example.com")
+        .forward_path("green@example.com")
+        .helo("mail.example.com")
+        .authenticated_user("smith\r\nInjected: yes")
+        .body("Hello\r\n")
+        .build()?;
+    let policy = RspamdPolicy::default().with_password("secret");
+
+    let request = String::from_utf8(request(&policy, &message))?;
+    assert!(request.starts_with("POST /checkv2 HTTP/1.1\r\nHost: 127.0.0.1:11333\r\n"));
+    assert!(request.ends_with("\r\n\r\n"));
+    for header in [
+        "From: smith@example.com\r\n",
+        "Rcpt: jones@example.com\r\n",
+        "Rcpt: green@example.com\r\n",
+        "Helo: mail.example.com\r\n",
+        "Password: secret\r\n",
+        &format!("Content-Length: {}\r\n", message.data().len()),
+        &format!("Queue-Id: {}\r\n", message.session().id()),
+    ] {
+        assert!(request.contains(header), "missing {header:?}");
+    }
+    // Tests that values that would break the request are left out.
+    assert!(!request.contains("Injected"));
+
+    Ok(())
+}
+
+#[test]
+fn test_debug() {
+    let debug = format!("{:?}", RspamdPolicy::default().with_password("secret"));
+    assert!(debug.contains("[redacted]"));
+    assert!(!debug.contains("secret"));
+}
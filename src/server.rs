@@ -0,0 +1,470 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Looking into a server while it runs, through its sessions, health, statistics, and rejections.
+//!
+//! See [`ServerHandle`].
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::sync::{broadcast, watch, OwnedSemaphorePermit};
+
+use crate::{
+    audit::{self, Rejection, RejectionSource, BUFFERED_REJECTIONS},
+    config::AuthLimits,
+    handler,
+    peers::{Excess, Peers},
+    permits::Permits,
+    session::{Activity, SessionId, SessionInfo, SessionStatus},
+    shutdown::ShutdownGuard,
+    stats::{Counters, ServerStats},
+    ServerConfig, Shutdown,
+};
+
+/// A handle to the servers that it is given to, such as with [`crate::listen`], to look into them
+/// while they run and to shut them down.
+///
+/// The sessions of the servers can be looked into and closed, such as for an admin endpoint, see
+/// [`Self::sessions`], and their totals can be rendered on a dashboard, see [`Self::stats`]. Why
+/// they rejected mail can be followed as it happens, see [`Self::rejections`]. The servers are
+/// shut down through [`Self::shutdown`].
+///
+/// The servers that are given the same [`Self`] share [`ServerConfig::max_connections`], the
+/// limits for each client address in [`ServerConfig::peer_limits`], and the bans of
+/// [`AuthLimits`]. A [`Shutdown`] that is given to a server in place of a [`Self`] creates a new
+/// [`Self`] for it, see [`Self::from`].
+///
+/// Cloning a [`Self`] creates another handle to the same servers.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// # use smtp_gateway::{handler::AcceptAll, ServerConfig, ServerHandle};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let server = ServerHandle::new();
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     |_| AcceptAll,
+///     server.clone(),
+/// );
+///
+/// // ...
+///
+/// println!("{} sessions are open", server.active_sessions());
+/// server.shutdown().shutdown(Duration::from_secs(30)).await;
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ServerHandle {
+    /// The state shared by every handle and session.
+    inner: Arc<Inner>,
+}
+
+/// The state of a [`ServerHandle`], shared by every handle and session.
+#[derive(Debug)]
+struct Inner {
+    /// Shuts down the servers.
+    shutdown: Shutdown,
+    /// The number of sessions that have not yet closed.
+    sessions: watch::Sender<usize>,
+    /// The sessions from each client address.
+    peers: Peers,
+    /// The permits for sessions within [`ServerConfig::max_connections`].
+    permits: Permits,
+    /// What each session that has not yet closed is doing, by the key of its [`SessionGuard`].
+    activities: Mutex<HashMap<u64, Arc<Activity>>>,
+    /// The key of the next [`SessionGuard`].
+    next_key: AtomicU64,
+    /// The number of listeners that are accepting connections.
+    listeners: AtomicUsize,
+    /// Counts what the servers do.
+    counters: Arc<Counters>,
+    /// Sends every rejection to the receivers from [`ServerHandle::rejections`].
+    rejections: broadcast::Sender<Rejection>,
+}
+
+impl Inner {
+    /// Create a new [`Self`] for servers that are shut down with `shutdown`.
+    fn new(shutdown: Shutdown) -> Self {
+        Self {
+            shutdown,
+            sessions: watch::Sender::new(0),
+            peers: Peers::new(),
+            permits: Permits::new(),
+            activities: Mutex::new(HashMap::new()),
+            next_key: AtomicU64::new(0),
+            listeners: AtomicUsize::new(0),
+            counters: Arc::new(Counters::new()),
+            rejections: broadcast::channel(BUFFERED_REJECTIONS).0,
+        }
+    }
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Self::new(Shutdown::new())
+    }
+}
+
+impl ServerHandle {
+    /// Create a new [`Self`] for servers that are not yet running.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the handle that shuts down the servers.
+    ///
+    /// See [`Shutdown`].
+    #[must_use]
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.inner.shutdown
+    }
+
+    /// Get the number of sessions that have not yet closed.
+    #[must_use]
+    pub fn active_sessions(&self) -> usize {
+        *self.inner.sessions.borrow()
+    }
+
+    /// Get what each session that has not yet closed is doing, in no particular order.
+    ///
+    /// Sessions that are waiting out a [`crate::handler::ConnectDecision::Tarpit`] before they
+    /// start are not listed.
+    #[must_use]
+    pub fn sessions(&self) -> Vec<SessionStatus> {
+        self.activities()
+            .values()
+            .filter_map(|activity| activity.status())
+            .collect()
+    }
+
+    /// Close the session `id` at once, returning whether it was found.
+    ///
+    /// Unlike [`Shutdown::shutdown`], the connection is dropped without a reply, so the client is
+    /// not told that any message that it was sending was received, and the task of the session
+    /// ends as cancelled.
+    #[must_use]
+    pub fn close_session(&self, id: SessionId) -> bool {
+        self.activities()
+            .values()
+            .find(|activity| activity.id() == Some(id))
+            .is_some_and(|activity| activity.abort())
+    }
+
+    /// Get whether the servers are accepting connections, how many sessions they have, and how
+    /// many messages are waiting for the consumer, such as for a readiness probe.
+    ///
+    /// See [`Health`], and `health` with the `health` feature for an HTTP endpoint that reports it.
+    #[must_use]
+    pub fn health(&self) -> Health {
+        let listening = self.inner.listeners.load(Ordering::Relaxed) > 0;
+        let is_shutting_down = self.inner.shutdown.is_shutting_down();
+        let active_sessions = self.active_sessions();
+
+        Health {
+            listener: match () {
+                () if listening && !is_shutting_down => ListenerStatus::Listening,
+                () if is_shutting_down && active_sessions > 0 => ListenerStatus::Draining,
+                () => ListenerStatus::Stopped,
+            },
+            active_sessions,
+            backlog: self
+                .activities()
+                .values()
+                .filter(|activity| activity.is_handing_off())
+                .count(),
+        }
+    }
+
+    /// Get the totals of what the servers did since [`Self`] was created, such as the number of
+    /// connections and of messages accepted.
+    ///
+    /// See [`ServerStats`].
+    #[must_use]
+    pub fn stats(&self) -> ServerStats {
+        self.inner.counters.snapshot()
+    }
+
+    /// Receive every `4xx` and `5xx` reply that the servers send from now on, along with the
+    /// session, the command, and what triggered it, such as to log why mail was rejected.
+    ///
+    /// Nothing is recorded while nothing receives the rejections. A receiver that falls more than
+    /// 1,024 rejections behind skips the oldest, see [`broadcast::error::RecvError::Lagged`].
+    ///
+    /// See [`Rejection`].
+    #[must_use]
+    pub fn rejections(&self) -> broadcast::Receiver<Rejection> {
+        self.inner.rejections.subscribe()
+    }
+
+    /// Send a [`Rejection`] for a connection from `peer_addr` that was refused with `reply` before
+    /// its session started because of `source`, see [`Self::rejections`].
+    pub(crate) fn refused(&self, peer_addr: SocketAddr, reply: &str, source: RejectionSource) {
+        audit::refused(&self.inner.rejections, peer_addr, reply, source);
+    }
+
+    /// Get what the servers count for [`Self::stats`].
+    pub(crate) fn counters(&self) -> &Counters {
+        &self.inner.counters
+    }
+
+    /// Lock the activities of the sessions.
+    fn activities(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Arc<Activity>>> {
+        self.inner
+            .activities
+            .lock()
+            .expect("the lock is never held across a panic")
+    }
+
+    /// Count a listener as accepting connections until the returned [`ListenerGuard`] is dropped.
+    pub(crate) fn listening(&self) -> ListenerGuard {
+        self.inner.listeners.fetch_add(1, Ordering::Relaxed);
+        ListenerGuard(self.inner.clone())
+    }
+
+    /// Resolve once fewer than `limit` sessions are active.
+    pub(crate) async fn below(&self, limit: usize) {
+        let _ = self
+            .inner
+            .sessions
+            .subscribe()
+            .wait_for(|&count| count < limit)
+            .await;
+    }
+
+    /// Wait for a permit for a new session within [`ServerConfig::max_connections`] of `config`,
+    /// for [`crate::config::ConnectionOverflow::Wait`].
+    pub(crate) async fn acquire(&self, config: &ServerConfig) -> OwnedSemaphorePermit {
+        self.inner.permits.acquire(config).await
+    }
+
+    /// Get a permit for a new session within [`ServerConfig::max_connections`] of `config`, or
+    /// `None` if the servers are full, for [`crate::config::ConnectionOverflow::Refuse`].
+    pub(crate) fn try_acquire(&self, config: &ServerConfig) -> Option<OwnedSemaphorePermit> {
+        self.inner.permits.try_acquire(config)
+    }
+
+    /// Count a new session from `address` that holds `permit` until the returned [`SessionGuard`]
+    /// is dropped, or return [`Excess`] if it exceeds the limits for `address` in `config`.
+    pub(crate) fn admit(
+        &self,
+        address: IpAddr,
+        config: &ServerConfig,
+        permit: OwnedSemaphorePermit,
+    ) -> Result<SessionGuard, Excess> {
+        let peer = self.inner.peers.admit(address, config)?;
+        self.inner.sessions.send_modify(|count| *count += 1);
+
+        let key = self.inner.next_key.fetch_add(1, Ordering::Relaxed);
+        let activity = Arc::new(Activity::new());
+        self.activities().insert(key, activity.clone());
+
+        Ok(SessionGuard {
+            shutdown: self.inner.shutdown.admit(),
+            permit: Some(permit),
+            peer,
+            key,
+            activity,
+            inner: self.inner.clone(),
+        })
+    }
+}
+
+impl From<Shutdown> for ServerHandle {
+    /// Create a new [`Self`] for servers that are shut down with `shutdown`.
+    fn from(shutdown: Shutdown) -> Self {
+        Self {
+            inner: Arc::new(Inner::new(shutdown)),
+        }
+    }
+}
+
+/// Counts a session as active in a [`ServerHandle`] until it is dropped.
+#[derive(Debug)]
+pub(crate) struct SessionGuard {
+    /// Counts the session until the servers are shut down.
+    shutdown: ShutdownGuard,
+    /// The permit of the session from [`Permits`], which is only taken as it is dropped.
+    permit: Option<OwnedSemaphorePermit>,
+    /// The key that the session is counted under in [`Peers`].
+    peer: IpAddr,
+    /// The key that the activity of the session is kept under.
+    key: u64,
+    /// What the session is doing.
+    activity: Arc<Activity>,
+    /// The state that the session is counted in.
+    inner: Arc<Inner>,
+}
+
+impl SessionGuard {
+    /// Get what sends every rejection for [`ServerHandle::rejections`].
+    pub(crate) fn rejection_sender(&self) -> &broadcast::Sender<Rejection> {
+        &self.inner.rejections
+    }
+
+    /// Get what the servers count for [`ServerHandle::stats`], to count what the session does.
+    pub(crate) fn counters(&self) -> &Arc<Counters> {
+        &self.inner.counters
+    }
+
+    /// Get what the session is doing, to be updated as it progresses.
+    pub(crate) const fn activity(&self) -> &Arc<Activity> {
+        &self.activity
+    }
+
+    /// Note that the session of `info` started, returning its activity to count the bytes of its
+    /// connection with.
+    pub(crate) fn start(&self, info: &SessionInfo) -> Arc<Activity> {
+        self.activity.start(info);
+        self.activity.clone()
+    }
+
+    /// Note that the session is waiting for a command in `phase`, then resolve once the session
+    /// should be closed as the servers shut down, which is sooner if it is outside of a mail
+    /// transaction.
+    pub(crate) async fn closing(&mut self, phase: handler::Phase) {
+        self.activity.set_phase(phase);
+        self.shutdown
+            .closing(phase <= handler::Phase::Greeted)
+            .await;
+    }
+
+    /// Count a failed authentication attempt of the session, returning whether its address
+    /// exceeded [`AuthLimits::max_failure_rate`] and is banned for [`AuthLimits::ban_duration`].
+    pub(crate) fn fail_auth(&self, limits: AuthLimits) -> bool {
+        self.inner.peers.fail_auth(self.peer, limits)
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.inner.sessions.send_modify(|count| *count -= 1);
+        self.inner.peers.release(self.peer);
+        if let Some(permit) = self.permit.take() {
+            self.inner.permits.release(permit);
+        }
+        self.inner
+            .activities
+            .lock()
+            .expect("the lock is never held across a panic")
+            .remove(&self.key);
+    }
+}
+
+/// Counts a listener as accepting connections in a [`ServerHandle`] until it is dropped.
+#[derive(Debug)]
+pub(crate) struct ListenerGuard(Arc<Inner>);
+
+impl Drop for ListenerGuard {
+    fn drop(&mut self) {
+        self.0.listeners.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The health of the servers that a [`ServerHandle`] is given to, see [`ServerHandle::health`].
+///
+/// Formats with [`Display`] as one `key: value` line for each field, such as for the body of a
+/// health check.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Health {
+    /// Whether the servers are accepting connections.
+    listener: ListenerStatus,
+    /// The number of sessions that have not yet closed.
+    active_sessions: usize,
+    /// The number of received messages that are waiting for the consumer.
+    backlog: usize,
+}
+
+impl Health {
+    /// Get whether the servers are accepting connections.
+    #[must_use]
+    pub const fn listener(&self) -> ListenerStatus {
+        self.listener
+    }
+
+    /// Get the number of sessions that have not yet closed, see [`ServerHandle::active_sessions`].
+    #[must_use]
+    pub const fn active_sessions(&self) -> usize {
+        self.active_sessions
+    }
+
+    /// Get the number of received messages that are waiting for the consumer to take them, through
+    /// [`crate::SmtpHandler::on_message`] or a [`crate::StreamingMessage`].
+    ///
+    /// A backlog that keeps growing means that the consumer cannot keep up.
+    #[must_use]
+    pub const fn backlog(&self) -> usize {
+        self.backlog
+    }
+
+    /// Get whether the servers are ready for new connections, meaning that they are
+    /// [`ListenerStatus::Listening`].
+    #[must_use]
+    pub const fn is_ready(&self) -> bool {
+        matches!(self.listener, ListenerStatus::Listening)
+    }
+}
+
+impl Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "listener: {}", self.listener)?;
+        writeln!(f, "active_sessions: {}", self.active_sessions)?;
+        writeln!(f, "backlog: {}", self.backlog)
+    }
+}
+
+/// Whether the servers that a [`ServerHandle`] is given to are accepting connections, see
+/// [`Health::listener`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ListenerStatus {
+    /// At least one server is accepting connections.
+    ///
+    /// Connections are only accepted while the stream of sessions from a server, such as from
+    /// [`crate::listen`], is polled.
+    Listening,
+    /// The servers are shutting down, and are waiting for their sessions to close, see
+    /// [`Shutdown::shutdown`].
+    Draining,
+    /// No server is accepting connections, and none is waiting for sessions to close after
+    /// shutting down.
+    Stopped,
+}
+
+impl Display for ListenerStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Listening => "listening",
+            Self::Draining => "draining",
+            Self::Stopped => "stopped",
+        })
+    }
+}
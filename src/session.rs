@@ -0,0 +1,697 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Details about SMTP sessions, shared by everything received through them.
+//!
+//! See [`SessionInfo`], [`SessionStatus`] for the sessions in progress, and [`SessionSummary`] for
+//! the sessions that ended.
+
+use std::{
+    fmt::Display,
+    hash::{BuildHasher, Hasher, RandomState},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        LazyLock, Mutex, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use ascii::{AsciiStr, AsciiString};
+use tokio::{task::AbortHandle, time::error::Elapsed};
+
+#[cfg(feature = "dnsbl")]
+use crate::dnsbl::DnsblVerdict;
+#[cfg(feature = "spf")]
+use crate::spf::SpfVerdict;
+use crate::{
+    config::HeloCheck,
+    handler::{Phase, SessionContext},
+    message::ContentHash,
+};
+
+/// Details about an SMTP session, for logging and policy decisions.
+///
+/// Every [`crate::Message`] carries the details of the session it was received through, as they
+/// were when its data was received.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionInfo {
+    /// The unique identifier of the session.
+    pub(crate) id: SessionId,
+    /// The address of the server that the client connected to.
+    pub(crate) local_addr: SocketAddr,
+    /// The address of the client.
+    pub(crate) peer_addr: SocketAddr,
+    /// The identity the client gave in `HELO`, or `None` if it has not yet sent `HELO`.
+    pub(crate) helo: Option<AsciiString>,
+    /// Whether the identity was given in `EHLO` rather than `HELO`.
+    pub(crate) extended: bool,
+    /// The checks of [`crate::config::HeloPolicy`] that the identity failed and was annotated for.
+    pub(crate) helo_failures: Vec<HeloCheck>,
+    /// The details of the encryption of the connection, or `None` if it is not encrypted.
+    pub(crate) tls: Option<TlsInfo>,
+    /// The identity the client authenticated as, or `None` if it has not authenticated.
+    pub(crate) authenticated_user: Option<String>,
+    /// Whether the client talked before it was greeted.
+    pub(crate) early_talker: bool,
+    /// The forward-confirmed name of the client, or `None` if it was not looked up.
+    pub(crate) reverse_dns: Option<ReverseDns>,
+    /// The result of looking up the client in DNS blocklists.
+    #[cfg(feature = "dnsbl")]
+    pub(crate) dnsbl: Option<DnsblVerdict>,
+    /// The result of checking SPF for the identity given in `HELO`.
+    #[cfg(feature = "spf")]
+    pub(crate) spf_helo: Option<SpfVerdict>,
+}
+
+impl SessionInfo {
+    /// Create a new [`Self`] for a session that just started, with a new [`SessionId`].
+    pub(crate) fn new(local_addr: SocketAddr, peer_addr: SocketAddr) -> Self {
+        Self {
+            id: SessionId::generate(),
+            local_addr,
+            peer_addr,
+            helo: None,
+            extended: false,
+            helo_failures: Vec::new(),
+            tls: None,
+            authenticated_user: None,
+            early_talker: false,
+            reverse_dns: None,
+            #[cfg(feature = "dnsbl")]
+            dnsbl: None,
+            #[cfg(feature = "spf")]
+            spf_helo: None,
+        }
+    }
+
+    /// Get the unique identifier of the session.
+    ///
+    /// This is what the client is told a message was queued as when it is accepted, is the `id`
+    /// of the `Received:` header added to the message, and starts the log lines of the server
+    /// about the session, so the three can be matched up.
+    #[must_use]
+    pub const fn id(&self) -> SessionId {
+        self.id
+    }
+
+    /// Get the address of the server that the client connected to.
+    #[must_use]
+    pub const fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Get the address of the client.
+    #[must_use]
+    pub const fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Get the identity given by the client in `HELO`.
+    ///
+    /// This is the domain or address literal that the client claims to be. It is only verified as
+    /// far as [`crate::config::HeloPolicy`] asks, and is empty if the client sent `HELO` without one. Returns `None` if the client has not
+    /// sent `HELO`.
+    #[must_use]
+    pub fn helo(&self) -> Option<&AsciiStr> {
+        self.helo.as_deref()
+    }
+
+    /// Get whether the client gave its identity in `EHLO` rather than `HELO`, and so uses the
+    /// service extensions of ESMTP. Returns `false` if the client has not sent either.
+    #[must_use]
+    pub const fn is_extended(&self) -> bool {
+        self.extended
+    }
+
+    /// Get the checks of [`crate::config::HeloPolicy`] that the identity given in `HELO` failed,
+    /// for those set to [`crate::config::HeloAction::Annotate`].
+    ///
+    /// Empty if every check passed, or if the client has not sent `HELO`.
+    #[must_use]
+    pub fn helo_failures(&self) -> &[HeloCheck] {
+        &self.helo_failures
+    }
+
+    /// Get the details of the encryption of the connection, or `None` if it is not encrypted.
+    #[must_use]
+    pub const fn tls(&self) -> Option<&TlsInfo> {
+        self.tls.as_ref()
+    }
+
+    /// Get whether the connection is encrypted.
+    #[must_use]
+    pub const fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+
+    /// Get the identity the client authenticated as, or `None` if it has not authenticated.
+    #[must_use]
+    pub fn authenticated_user(&self) -> Option<&str> {
+        self.authenticated_user.as_deref()
+    }
+
+    /// Get whether the client sent anything before it was greeted, during
+    /// [`crate::ServerConfig::greeting_delay`].
+    ///
+    /// Always `false` if there is no delay before the greeting.
+    #[must_use]
+    pub const fn is_early_talker(&self) -> bool {
+        self.early_talker
+    }
+
+    /// Get the result of looking up the name of the client when it connected, per
+    /// [`crate::ServerConfig::reverse_dns_policy`], or `None` if it was not looked up.
+    #[must_use]
+    pub const fn reverse_dns(&self) -> Option<&ReverseDns> {
+        self.reverse_dns.as_ref()
+    }
+
+    /// Get the name of the client that resolves back to its address, or `None` if it has none or
+    /// it was not looked up. See [`Self::reverse_dns`].
+    #[must_use]
+    pub fn peer_hostname(&self) -> Option<&str> {
+        match &self.reverse_dns {
+            Some(ReverseDns::Verified(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Get the result of looking up the client in the DNS blocklists of
+    /// [`crate::ServerConfig::dnsbl_policy`] when it connected.
+    ///
+    /// Returns `None` if no blocklists are set.
+    #[cfg(feature = "dnsbl")]
+    #[must_use]
+    pub const fn dnsbl(&self) -> Option<&DnsblVerdict> {
+        self.dnsbl.as_ref()
+    }
+
+    /// Get the result of checking SPF for the identity given in `HELO`.
+    ///
+    /// Returns `None` if [`crate::ServerConfig::spf_policy`] is [`crate::spf::SpfPolicy::Off`],
+    /// if the client has not sent `HELO`, or if its identity is an address literal rather than a
+    /// domain name.
+    #[cfg(feature = "spf")]
+    #[must_use]
+    pub const fn spf_helo(&self) -> Option<&SpfVerdict> {
+        self.spf_helo.as_ref()
+    }
+}
+
+/// The result of looking up the name of the address of a client and confirming that it resolves
+/// back to the address, known as forward-confirmed reverse DNS.
+///
+/// See [`crate::config::ReverseDnsPolicy`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ReverseDns {
+    /// The address has this name, which resolves back to the address.
+    Verified(String),
+    /// The address has no name that resolves back to it.
+    Unknown,
+    /// A lookup failed, so the name could not be confirmed.
+    TempError,
+}
+
+/// Details about the encryption of a connection.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TlsInfo {
+    /// The version of the protocol, such as `TLSv1.3`.
+    pub(crate) protocol: String,
+    /// The negotiated cipher suite, such as `TLS13_AES_256_GCM_SHA384`.
+    pub(crate) cipher: String,
+    /// The name that the client asked for with Server Name Indication, if any.
+    pub(crate) server_name: Option<String>,
+    /// The verified certificate chain of the client in DER, starting with its own, or empty if it
+    /// did not give one.
+    pub(crate) peer_certificates: Vec<Vec<u8>>,
+}
+
+impl TlsInfo {
+    /// Create a new [`Self`] from the negotiated protocol version and cipher suite.
+    #[must_use]
+    pub fn new(protocol: impl Into<String>, cipher: impl Into<String>) -> Self {
+        Self {
+            protocol: protocol.into(),
+            cipher: cipher.into(),
+            server_name: None,
+            peer_certificates: Vec::new(),
+        }
+    }
+
+    /// Set the name that the client asked for with Server Name Indication.
+    #[must_use]
+    pub fn with_server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Set the verified certificate chain of the client in DER, starting with its own.
+    #[must_use]
+    pub fn with_peer_certificates(mut self, certificates: Vec<Vec<u8>>) -> Self {
+        self.peer_certificates = certificates;
+        self
+    }
+
+    /// Get the version of the protocol, such as `TLSv1.3`.
+    #[must_use]
+    pub fn protocol(&self) -> &str {
+        &self.protocol
+    }
+
+    /// Get the negotiated cipher suite, such as `TLS13_AES_256_GCM_SHA384`.
+    #[must_use]
+    pub fn cipher(&self) -> &str {
+        &self.cipher
+    }
+
+    /// Get the name that the client asked for with Server Name Indication, which chose the
+    /// certificate presented to it, or `None` if it did not ask for one.
+    #[must_use]
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Get the certificate chain that the client gave in DER, starting with its own.
+    ///
+    /// The chain was verified against the certificate authorities of [`crate::tls::TlsConfig`]
+    /// during the handshake, so a handler can treat it as proof of the identity of the client. It
+    /// is empty if the client did not give a certificate.
+    #[must_use]
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    /// Get the SHA-256 fingerprint of the certificate of the client as lowercase hexadecimal, or
+    /// `None` if it did not give one.
+    #[must_use]
+    pub fn peer_fingerprint(&self) -> Option<String> {
+        self.peer_certificates
+            .first()
+            .map(|certificate| ContentHash::of(certificate).to_string())
+    }
+}
+
+/// A short identifier for an SMTP session, unique within the process and unlikely to repeat
+/// across restarts.
+///
+/// Formats as 16 lowercase hexadecimal digits with [`Display`], such as `67110a4500000001`.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionId(u64);
+
+impl SessionId {
+    /// Generate a new [`Self`] from the current time and a counter of the sessions started by
+    /// this process.
+    pub(crate) fn generate() -> Self {
+        /// Distinguishes the sessions started within the same second.
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        Self((seconds << 32) | u64::from(count))
+    }
+
+    /// Generate a new name for a message received through this session, for the file that it is
+    /// or object that it is kept in, such as `67110a4500000001-5c1d2e3f4a5b6c7d-00000003`.
+    ///
+    /// Session identifiers repeat when the server is restarted within the same second, so the
+    /// name also has a value that is random for each process, and a counter of the names that it
+    /// generated.
+    pub(crate) fn message_name(self) -> String {
+        /// Distinguishes this process from the others that could have the same session IDs.
+        static PROCESS: LazyLock<u64> = LazyLock::new(|| {
+            // The keys of `RandomState` are random for each process.
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u32(std::process::id());
+            hasher.finish()
+        });
+        /// Distinguishes the names generated by this process.
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        format!("{self}-{:016x}-{count:08x}", *PROCESS)
+    }
+
+    /// Get the identifier as a number.
+    #[must_use]
+    pub const fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    /// Create a [`Self`] from the number that [`Self::as_u64`] gives, such as when reading it back
+    /// from a spool.
+    #[cfg(any(feature = "spool", feature = "sqlite"))]
+    pub(crate) const fn from_u64(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+impl Display for SessionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// What an SMTP session in progress is doing, as of when it was looked up with
+/// [`crate::ServerHandle::sessions`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct SessionStatus {
+    /// The unique identifier of the session.
+    id: SessionId,
+    /// The address of the server that the client connected to.
+    local_addr: SocketAddr,
+    /// The address of the client.
+    peer_addr: SocketAddr,
+    /// How far the session has progressed.
+    phase: Phase,
+    /// How long the session has been open.
+    age: Duration,
+    /// How long since anything was received from or sent to the client.
+    idle: Duration,
+    /// The number of bytes received from the client.
+    received: u64,
+    /// The number of bytes sent to the client.
+    sent: u64,
+}
+
+impl SessionStatus {
+    /// Get the unique identifier of the session, which [`crate::ServerHandle::close_session`] takes.
+    #[must_use]
+    pub const fn id(&self) -> SessionId {
+        self.id
+    }
+
+    /// Get the address of the server that the client connected to.
+    #[must_use]
+    pub const fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Get the address of the client.
+    #[must_use]
+    pub const fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Get how far the session has progressed, as of the last command from the client.
+    #[must_use]
+    pub const fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Get how long the session has been open.
+    #[must_use]
+    pub const fn age(&self) -> Duration {
+        self.age
+    }
+
+    /// Get how long it has been since anything was received from or sent to the client.
+    #[must_use]
+    pub const fn idle(&self) -> Duration {
+        self.idle
+    }
+
+    /// Get the number of bytes received from the client, including the overhead of TLS.
+    #[must_use]
+    pub const fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Get the number of bytes sent to the client, including the overhead of TLS.
+    #[must_use]
+    pub const fn sent(&self) -> u64 {
+        self.sent
+    }
+}
+
+/// How an SMTP session ended, given by joining its [`crate::Session`].
+#[derive(PartialEq, Eq, Debug)]
+pub struct SessionSummary {
+    /// The unique identifier of the session.
+    id: SessionId,
+    /// Why the session was closed.
+    close_reason: CloseReason,
+    /// The number of messages that the client was told were accepted.
+    messages_accepted: usize,
+    /// The number of commands that the client made an error in.
+    errors: usize,
+}
+
+impl SessionSummary {
+    /// Summarize the session of `state`, which was closed because of `close_reason`.
+    pub(crate) const fn new(state: &SessionContext, close_reason: CloseReason) -> Self {
+        Self {
+            id: state.session.id,
+            close_reason,
+            messages_accepted: state.messages_accepted,
+            errors: state.total_errors,
+        }
+    }
+
+    /// Get the unique identifier of the session.
+    #[must_use]
+    pub const fn id(&self) -> SessionId {
+        self.id
+    }
+
+    /// Get why the session was closed.
+    #[must_use]
+    pub const fn close_reason(&self) -> &CloseReason {
+        &self.close_reason
+    }
+
+    /// Get the number of messages that the client was told were accepted.
+    #[must_use]
+    pub const fn messages_accepted(&self) -> usize {
+        self.messages_accepted
+    }
+
+    /// Get the number of commands that the client made an error in, such as a syntax error or a
+    /// command out of sequence, including those that were not in a row.
+    #[must_use]
+    pub const fn errors(&self) -> usize {
+        self.errors
+    }
+}
+
+/// Why an SMTP session was closed, see [`SessionSummary::close_reason`].
+#[derive(PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum CloseReason {
+    /// The SMTP client requested to quit the session.
+    Quit,
+    /// More time [`Elapsed`] than [`crate::timeouts::Timeouts`] allows.
+    TimedOut(Elapsed),
+    /// The TCP connection was forcefully ended by the client.
+    ClosedByClient,
+    /// The client was refused or dropped in place of the greeting, such as by
+    /// [`crate::HandlerFactory::on_connect`] or [`crate::ServerConfig::reverse_dns_policy`].
+    Refused,
+    /// The TLS handshake after `STARTTLS` failed.
+    TlsFailed,
+    /// The consumer's handler told the client that the service is not available.
+    ServiceUnavailable,
+    /// The consumer's handler failed with an error that closes the session.
+    HandlerError,
+    /// The server is shutting down.
+    Shutdown,
+    /// The client made more errors in a row than [`crate::config::CommandLimits::max_errors`].
+    TooManyErrors,
+    /// The client sent commands faster than [`crate::config::CommandLimits::max_command_rate`].
+    TooManyCommands,
+    /// The client failed to authenticate more times than [`crate::config::AuthLimits`] allow.
+    TooManyAuthFailures,
+    /// The client was delayed for longer than [`crate::config::Tarpit::max_duration`].
+    Tarpitted,
+}
+
+impl CloseReason {
+    /// Get the name of `self` in `snake_case`, such as `timed_out`, as logged with
+    /// [`crate::config::LogFormat::Json`].
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Quit => "quit",
+            Self::TimedOut(_) => "timed_out",
+            Self::ClosedByClient => "closed_by_client",
+            Self::Refused => "refused",
+            Self::TlsFailed => "tls_failed",
+            Self::ServiceUnavailable => "service_unavailable",
+            Self::HandlerError => "handler_error",
+            Self::Shutdown => "shutdown",
+            Self::TooManyErrors => "too_many_errors",
+            Self::TooManyCommands => "too_many_commands",
+            Self::TooManyAuthFailures => "too_many_auth_failures",
+            Self::Tarpitted => "tarpitted",
+        }
+    }
+}
+
+impl Display for CloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Tracks what an SMTP session is doing for [`SessionStatus`], shared between the session and the
+/// [`crate::ServerHandle`] that it is counted in.
+#[derive(Debug)]
+pub(crate) struct Activity {
+    /// When the connection was accepted.
+    opened_at: Instant,
+    /// The identifier and addresses of the session, once it has started.
+    identity: OnceLock<(SessionId, SocketAddr, SocketAddr)>,
+    /// How far the session has progressed.
+    phase: Mutex<Phase>,
+    /// When anything was last received from or sent to the client.
+    last_active: Mutex<Instant>,
+    /// The number of bytes received from the client.
+    received: AtomicU64,
+    /// The number of bytes sent to the client.
+    sent: AtomicU64,
+    /// Aborts the task of the session, once it is spawned.
+    abort: OnceLock<AbortHandle>,
+    /// Whether a message of the session is waiting for the consumer to take it.
+    handing_off: AtomicBool,
+}
+
+impl Activity {
+    /// Create a new [`Self`] for a connection that was just accepted.
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+
+        Self {
+            opened_at: now,
+            identity: OnceLock::new(),
+            phase: Mutex::new(Phase::Connected),
+            last_active: Mutex::new(now),
+            received: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            abort: OnceLock::new(),
+            handing_off: AtomicBool::new(false),
+        }
+    }
+
+    /// Note that the session of `info` started, so that it is listed from now on.
+    pub(crate) fn start(&self, info: &SessionInfo) {
+        let _ = self
+            .identity
+            .set((info.id, info.local_addr, info.peer_addr));
+    }
+
+    /// Note that the session progressed to `phase`.
+    pub(crate) fn set_phase(&self, phase: Phase) {
+        *self
+            .phase
+            .lock()
+            .expect("the lock is never held across a panic") = phase;
+    }
+
+    /// Count `bytes` received from the client.
+    pub(crate) fn add_received(&self, bytes: usize) {
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Count `bytes` sent to the client.
+    pub(crate) fn add_sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Note that something was just received from or sent to the client.
+    fn touch(&self) {
+        *self
+            .last_active
+            .lock()
+            .expect("the lock is never held across a panic") = Instant::now();
+    }
+
+    /// Let [`Self::abort`] abort the task of the session through `handle`.
+    pub(crate) fn set_abort(&self, handle: AbortHandle) {
+        let _ = self.abort.set(handle);
+    }
+
+    /// Abort the task of the session, closing its connection at once, returning whether it could
+    /// be.
+    pub(crate) fn abort(&self) -> bool {
+        self.abort.get().inspect(|abort| abort.abort()).is_some()
+    }
+
+    /// Note that a message of the session is waiting for the consumer to take it, until the
+    /// returned [`HandOff`] is dropped.
+    pub(crate) fn hand_off(&self) -> HandOff<'_> {
+        self.handing_off.store(true, Ordering::Relaxed);
+        HandOff(self)
+    }
+
+    /// Get whether a message of the session is waiting for the consumer to take it.
+    pub(crate) fn is_handing_off(&self) -> bool {
+        self.handing_off.load(Ordering::Relaxed)
+    }
+
+    /// Get the identifier of the session, or `None` if it has not yet started.
+    pub(crate) fn id(&self) -> Option<SessionId> {
+        self.identity.get().map(|&(id, ..)| id)
+    }
+
+    /// Get what the session is doing, or `None` if it has not yet started.
+    pub(crate) fn status(&self) -> Option<SessionStatus> {
+        let &(id, local_addr, peer_addr) = self.identity.get()?;
+
+        Some(SessionStatus {
+            id,
+            local_addr,
+            peer_addr,
+            phase: *self
+                .phase
+                .lock()
+                .expect("the lock is never held across a panic"),
+            age: self.opened_at.elapsed(),
+            idle: self
+                .last_active
+                .lock()
+                .expect("the lock is never held across a panic")
+                .elapsed(),
+            received: self.received.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Marks a message of a session as waiting for the consumer to take it until it is dropped, see
+/// [`Activity::hand_off`].
+#[derive(Debug)]
+pub(crate) struct HandOff<'a>(&'a Activity);
+
+impl Drop for HandOff<'_> {
+    fn drop(&mut self) {
+        self.0.handing_off.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Gracefully shutting down a server.
+//!
+//! See [`Shutdown`].
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::watch;
+
+/// A handle to gracefully shut down the servers that it is given to, such as with
+/// [`crate::listen`].
+///
+/// A [`Self`] can be given to a server in place of a [`crate::ServerHandle`] when nothing else
+/// about the server needs to be looked into, see [`crate::ServerHandle::shutdown`].
+///
+/// Cloning a [`Self`] creates another handle to the same servers.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// # use smtp_gateway::{handler::AcceptAll, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let shutdown = Shutdown::new();
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     |_| AcceptAll,
+///     shutdown.clone(),
+/// );
+///
+/// // ...
+///
+/// shutdown.shutdown(Duration::from_secs(30)).await;
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    /// The state shared by every handle and session.
+    inner: Arc<Inner>,
+}
+
+/// The state of a [`Shutdown`], shared by every handle and session.
+#[derive(Debug)]
+struct Inner {
+    /// How far the shutdown has progressed.
+    phase: watch::Sender<Phase>,
+    /// The number of sessions that have not yet closed.
+    sessions: watch::Sender<usize>,
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Self {
+            phase: watch::Sender::new(Phase::Running),
+            sessions: watch::Sender::new(0),
+        }
+    }
+}
+
+/// How far a [`Shutdown`] has progressed.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+enum Phase {
+    /// New connections are accepted.
+    Running,
+    /// New connections are not accepted, and idle sessions are closed.
+    Draining,
+    /// Every session is closed, whether it is idle or not.
+    Closing,
+}
+
+impl Shutdown {
+    /// Create a new [`Self`] for servers that are not yet shutting down.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shut down the servers, resolving once every session has closed.
+    ///
+    /// New connections stop being accepted, ending the streams of sessions, and each session is
+    /// closed with `421` as soon as it is waiting for a command outside of a mail transaction.
+    /// Once `deadline` has passed, sessions are closed with `421` as soon as they are waiting for
+    /// a command, even in the middle of a mail transaction. Sessions that are receiving the data of
+    /// a message are closed once the data ends or times out.
+    pub async fn shutdown(&self, deadline: Duration) {
+        self.advance(Phase::Draining);
+
+        let mut sessions = self.inner.sessions.subscribe();
+        if tokio::time::timeout(deadline, sessions.wait_for(|&count| count == 0))
+            .await
+            .is_err()
+        {
+            self.advance(Phase::Closing);
+            let _ = sessions.wait_for(|&count| count == 0).await;
+        }
+    }
+
+    /// Get whether [`Self::shutdown`] was called.
+    #[must_use]
+    pub fn is_shutting_down(&self) -> bool {
+        *self.inner.phase.borrow() > Phase::Running
+    }
+
+    /// Move the shutdown to `phase`, unless it is already further along.
+    fn advance(&self, phase: Phase) {
+        self.inner.phase.send_if_modified(|current| {
+            let is_later = phase > *current;
+            if is_later {
+                *current = phase;
+            }
+            is_later
+        });
+    }
+
+    /// Resolve once new connections should no longer be accepted.
+    pub(crate) async fn stopped(&self) {
+        let _ = self
+            .inner
+            .phase
+            .subscribe()
+            .wait_for(|&phase| phase > Phase::Running)
+            .await;
+    }
+
+    /// Count a new session until the returned [`ShutdownGuard`] is dropped, so that
+    /// [`Self::shutdown`] waits for it to close.
+    pub(crate) fn admit(&self) -> ShutdownGuard {
+        self.inner.sessions.send_modify(|count| *count += 1);
+
+        ShutdownGuard {
+            phase: self.inner.phase.subscribe(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Counts a session in a [`Shutdown`] until it is dropped.
+#[derive(Debug)]
+pub(crate) struct ShutdownGuard {
+    /// Watches how far the shutdown has progressed.
+    phase: watch::Receiver<Phase>,
+    /// The state that the session is counted in.
+    inner: Arc<Inner>,
+}
+
+impl ShutdownGuard {
+    /// Resolve once the session should be closed, which is sooner if it `is_idle`, meaning that it
+    /// is outside of a mail transaction.
+    pub(crate) async fn closing(&mut self, is_idle: bool) {
+        let _ = self
+            .phase
+            .wait_for(|&phase| phase == Phase::Closing || (is_idle && phase == Phase::Draining))
+            .await;
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        self.inner.sessions.send_modify(|count| *count -= 1);
+    }
+}
@@ -0,0 +1,300 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Sinks made of other sinks, see [`super::MessageSink::fanout`],
+//! [`super::MessageSink::fallback`], [`super::MessageSink::filter`], and
+//! [`super::MessageSink::dead_letter`].
+
+use std::{
+    fmt::Debug,
+    time::{Duration, SystemTime},
+};
+
+use super::{DeliveryResult, MessageSink};
+use crate::{message::date, Message};
+
+/// Delivers each message to two sinks at once, succeeding once both of them do.
+///
+/// If either of them fails, so does [`Self`], with the error that may be tried again if only one
+/// of them may be, so that the client sends the message again. The sink that succeeded then gets
+/// the message again, so sinks that are fanned out to should tolerate duplicates.
+///
+/// Created with [`MessageSink::fanout`], and nested for more than two sinks.
+#[derive(Debug, Clone)]
+pub struct Fanout<A, B> {
+    /// The first sink.
+    first: A,
+    /// The second sink.
+    second: B,
+}
+
+impl<A, B> Fanout<A, B> {
+    /// Create a new [`Self`] that delivers to `first` and `second`.
+    pub(super) const fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    /// Get the first sink.
+    #[must_use]
+    pub const fn first(&self) -> &A {
+        &self.first
+    }
+
+    /// Get the second sink.
+    #[must_use]
+    pub const fn second(&self) -> &B {
+        &self.second
+    }
+}
+
+impl<A: MessageSink, B: MessageSink> MessageSink for Fanout<A, B> {
+    async fn deliver(&self, message: Message) -> DeliveryResult {
+        match tokio::join!(
+            self.first.deliver(message.clone()),
+            self.second.deliver(message)
+        ) {
+            (Ok(()), Ok(())) => Ok(()),
+            (Err(error), Ok(())) | (Ok(()), Err(error)) => Err(error),
+            (Err(first), Err(second)) => {
+                if !first.is_temporary() && second.is_temporary() {
+                    Err(second)
+                } else {
+                    Err(first)
+                }
+            }
+        }
+    }
+}
+
+/// Delivers each message to a primary sink, and to a fallback sink if the primary one fails for a
+/// reason that is temporary, such as a broker that cannot be reached.
+///
+/// A message that the primary sink refuses for good is not given to the fallback sink, as the
+/// refusal is about the message, not the sink.
+///
+/// Created with [`MessageSink::fallback`].
+#[derive(Debug, Clone)]
+pub struct Fallback<A, B> {
+    /// The sink that each message is delivered to first.
+    primary: A,
+    /// The sink that each message is delivered to if the primary sink fails.
+    fallback: B,
+}
+
+impl<A, B> Fallback<A, B> {
+    /// Create a new [`Self`] that delivers to `primary`, or else to `fallback`.
+    pub(super) const fn new(primary: A, fallback: B) -> Self {
+        Self { primary, fallback }
+    }
+
+    /// Get the sink that each message is delivered to first.
+    #[must_use]
+    pub const fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    /// Get the sink that each message is delivered to if the primary sink fails.
+    #[must_use]
+    pub const fn fallback(&self) -> &B {
+        &self.fallback
+    }
+}
+
+impl<A: MessageSink, B: MessageSink> MessageSink for Fallback<A, B> {
+    async fn deliver(&self, message: Message) -> DeliveryResult {
+        match self.primary.deliver(message.clone()).await {
+            Err(error) if error.is_temporary() => {
+                let id = message.session().id();
+                println!("[{id}] Delivering to the fallback sink: {error}");
+
+                self.fallback.deliver(message).await
+            }
+            result => result,
+        }
+    }
+}
+
+/// Delivers only the messages that a predicate returns `true` for to a sink, skipping every other
+/// one as if it was delivered, such as to archive only the messages to one domain.
+///
+/// Created with [`MessageSink::filter`].
+#[derive(Clone)]
+pub struct Filter<S, F> {
+    /// The sink that matching messages are delivered to.
+    sink: S,
+    /// Whether a message is delivered.
+    predicate: F,
+}
+
+impl<S, F> Filter<S, F> {
+    /// Create a new [`Self`] that delivers the messages that `predicate` matches to `sink`.
+    pub(super) const fn new(sink: S, predicate: F) -> Self {
+        Self { sink, predicate }
+    }
+
+    /// Get the sink that matching messages are delivered to.
+    #[must_use]
+    pub const fn sink(&self) -> &S {
+        &self.sink
+    }
+}
+
+impl<S: Debug, F> Debug for Filter<S, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Filter")
+            .field("sink", &self.sink)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, F> MessageSink for Filter<S, F>
+where
+    S: MessageSink,
+    F: Fn(&Message) -> bool + Send + Sync + 'static,
+{
+    async fn deliver(&self, message: Message) -> DeliveryResult {
+        if (self.predicate)(&message) {
+            self.sink.deliver(message).await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Retries each message that a sink fails to deliver, and then delivers it to a dead-letter sink.
+///
+/// Only failures that are temporary are retried. Once there are no retries left, the message is
+/// delivered to the dead-letter sink, such as a [`super::DirectorySink`], so that it is kept
+/// rather than deferred again and again.
+///
+/// Before it is delivered to the dead-letter sink, these header fields are added to the message:
+///
+/// - `X-Dead-Letter-Reason:` why the last attempt failed.
+/// - `X-Dead-Letter-Attempts:` how many times delivery was attempted.
+/// - `X-Dead-Letter-Date:` when delivery was given up on.
+///
+/// A message is only deferred if the dead-letter sink fails as well, and a message that the sink
+/// refuses for good is refused as it is, as the client is told about it.
+///
+/// Retries wait [`Self::backoff`] before the first one, and twice as long before each one after.
+///
+/// Created with [`MessageSink::dead_letter`].
+#[derive(Debug, Clone)]
+pub struct DeadLetter<S, D> {
+    /// The sink that each message is delivered to.
+    sink: S,
+    /// The sink that the messages that could not be delivered are delivered to.
+    letters: D,
+    /// How many times delivery is retried.
+    retries: u32,
+    /// How long to wait before the first retry.
+    backoff: Duration,
+}
+
+impl<S, D> DeadLetter<S, D> {
+    /// Create a new [`Self`] that delivers to `sink`, or else to `dead_letter`.
+    pub(super) const fn new(sink: S, dead_letter: D) -> Self {
+        Self {
+            sink,
+            letters: dead_letter,
+            retries: 3,
+            backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Get the sink that each message is delivered to.
+    #[must_use]
+    pub const fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Get the sink that the messages that could not be delivered are delivered to.
+    #[must_use]
+    pub const fn dead_letter(&self) -> &D {
+        &self.letters
+    }
+
+    /// Get how many times delivery is retried before a message is delivered to the dead-letter
+    /// sink, 3 by default.
+    #[must_use]
+    pub const fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Get how long to wait before the first retry, which doubles for each one after, 1 second by
+    /// default.
+    #[must_use]
+    pub const fn backoff(&self) -> Duration {
+        self.backoff
+    }
+
+    /// Set how many times delivery is retried. See [`Self::retries`].
+    #[must_use]
+    pub const fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Set how long to wait before the first retry. See [`Self::backoff`].
+    #[must_use]
+    pub const fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+}
+
+impl<S: MessageSink, D: MessageSink> MessageSink for DeadLetter<S, D> {
+    async fn deliver(&self, message: Message) -> DeliveryResult {
+        let mut backoff = self.backoff;
+        let mut attempts = 0;
+        let error = loop {
+            attempts += 1;
+            match self.sink.deliver(message.clone()).await {
+                Err(error) if error.is_temporary() && attempts <= self.retries => {
+                    tokio::time::sleep(backoff).await;
+                    backoff = backoff.saturating_mul(2);
+                }
+                Err(error) if error.is_temporary() => break error,
+                result => return result,
+            }
+        };
+
+        // Header fields must be printable ASCII on a line of their own.
+        let reason: String = error
+            .to_string()
+            .chars()
+            .take(900)
+            .map(|char| if char.is_ascii_graphic() { char } else { ' ' })
+            .collect();
+        let mut dead = message;
+        let _ = dead.add_header("X-Dead-Letter-Date", &date::format(SystemTime::now()));
+        let _ = dead.add_header("X-Dead-Letter-Attempts", &attempts.to_string());
+        let _ = dead.add_header("X-Dead-Letter-Reason", &reason);
+
+        let id = dead.session().id();
+        match self.letters.deliver(dead).await {
+            Ok(()) => {
+                println!("[{id}] Moved message to dead letters after {attempts} attempts: {error}");
+                Ok(())
+            }
+            Err(dead_error) => {
+                println!("[{id}] Failed to move message to dead letters: {dead_error}");
+                Err(error)
+            }
+        }
+    }
+}
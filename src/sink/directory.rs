@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Writes messages to files in a directory, see [`DirectorySink`].
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{DeliveryResult, MessageSink};
+use crate::{file, Message};
+
+/// The directory that files are written into before they are complete.
+const TEMPORARY: &str = "tmp";
+
+/// Writes each message to an `.eml` file in a directory, as [`Message::to_eml_bytes`] serializes
+/// it, such as to keep the messages that could not be delivered elsewhere, see
+/// [`MessageSink::dead_letter`].
+///
+/// Each file is named after the session that its message was received through, a value that is
+/// random for each process, and a counter of the messages named by it, such as
+/// `67110a4500000001-5c1d2e3f4a5b6c7d-00000003.eml`. Files are written into the `tmp` directory
+/// inside it and moved into place once they are synced, so a file is either complete or not there
+/// at all, and never replace a file that is already there.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{sink::DirectorySink, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let sink = DirectorySink::open("/var/mail/inbound")?;
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     move |_| sink.clone(),
+///     Shutdown::new(),
+/// );
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DirectorySink {
+    /// The directory that files are written to.
+    path: PathBuf,
+}
+
+impl DirectorySink {
+    /// Open the directory at `path` to write files to, creating it if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the directory cannot be created.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(path.join(TEMPORARY))?;
+
+        Ok(Self { path })
+    }
+
+    /// Get the directory that files are written to.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `message` to a new file and sync it to disk, returning the path of the file.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the file cannot be written, or if there is already a file with the same
+    ///   name.
+    pub async fn write(&self, message: &Message) -> io::Result<PathBuf> {
+        let name = format!("{}.eml", message.session().id().message_name());
+        let temporary = self.path.join(TEMPORARY).join(&name);
+        let path = self.path.join(name);
+
+        file::write_new(&temporary, &path, &message.to_eml_bytes()).await?;
+
+        Ok(path)
+    }
+}
+
+impl MessageSink for DirectorySink {
+    async fn deliver(&self, message: Message) -> DeliveryResult {
+        self.write(&message).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The HTTP/1.1 client of the sinks that deliver messages over HTTP, see [`Endpoint`].
+//!
+//! Each request is sent on a new connection that the server closes after its response, so that
+//! responses are read to the end of the connection rather than framed.
+
+use std::{io, time::Duration};
+
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::redact;
+
+/// The longest response that is read, in bytes.
+const MAX_RESPONSE: u64 = 1024 * 1024;
+
+/// An `http://` or `https://` URL that requests are sent to.
+#[derive(Clone)]
+pub(super) struct Endpoint {
+    /// The URL as it was given.
+    url: String,
+    /// The host and port, as sent in the `Host` header.
+    host: String,
+    /// The host and port that are connected to.
+    address: String,
+    /// The path and query, `/` if the URL has none.
+    path: String,
+    /// Whether the server is reached over TLS.
+    #[cfg(feature = "tls")]
+    secure: bool,
+    /// How the server is reached over TLS.
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<crate::tls::rustls::ClientConfig>>,
+}
+
+impl Endpoint {
+    /// Parse `url`.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] with [`io::ErrorKind::InvalidInput`] if `url` is not an `http://` URL, or
+    ///   an `https://` URL with the `tls` feature.
+    pub(super) fn parse(url: &str) -> io::Result<Self> {
+        let invalid = |reason| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{reason}: {}", redact::url(url)),
+            )
+        };
+
+        let (secure, rest) = if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else if let Some(rest) = url.strip_prefix("https://") {
+            if cfg!(not(feature = "tls")) {
+                return Err(invalid("HTTPS URLs need the `tls` feature"));
+            }
+            (true, rest)
+        } else {
+            return Err(invalid("unsupported URL"));
+        };
+        let (host, path) = rest
+            .find(['/', '?'])
+            .map_or((rest, "/"), |end| (&rest[..end], &rest[end..]));
+        if host.is_empty() || host.contains(['@', '#', ' ']) {
+            return Err(invalid("unsupported URL"));
+        }
+
+        let has_port = host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.contains(']'));
+        let address = if has_port {
+            host.to_owned()
+        } else {
+            format!("{host}:{}", if secure { 443 } else { 80 })
+        };
+        let path = if path.starts_with('?') {
+            format!("/{path}")
+        } else {
+            path.to_owned()
+        };
+
+        Ok(Self {
+            url: url.to_owned(),
+            host: host.to_owned(),
+            address,
+            path,
+            #[cfg(feature = "tls")]
+            secure,
+            #[cfg(feature = "tls")]
+            tls: None,
+        })
+    }
+
+    /// Get the URL as it was given.
+    pub(super) fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Get the host and port, as sent in the `Host` header.
+    pub(super) fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Get the path and query, `/` if the URL has none.
+    pub(super) fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Set how the server is reached over TLS, which `https://` URLs need.
+    #[cfg(feature = "tls")]
+    pub(super) fn set_tls(&mut self, config: Arc<crate::tls::rustls::ClientConfig>) {
+        self.tls = Some(config);
+    }
+
+    /// Send a request made of `head`, which must ask the server to close the connection, and
+    /// `body`, and read its response, waiting for up to `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the server cannot be reached in time, or its response is malformed.
+    pub(super) async fn send(
+        &self,
+        head: &[u8],
+        body: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Response> {
+        let response = tokio::time::timeout(timeout, self.exchange(head, body))
+            .await
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{} did not respond in time", self.host),
+                )
+            })??;
+
+        Response::parse(&response)
+    }
+
+    /// Connect to the server, send a request made of `head` and `body`, and read its response.
+    async fn exchange(&self, head: &[u8], body: &[u8]) -> io::Result<Vec<u8>> {
+        let stream = TcpStream::connect(&self.address).await?;
+
+        #[cfg(feature = "tls")]
+        if self.secure {
+            use crate::tls::rustls::pki_types::ServerName;
+
+            let config = self.tls.clone().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "HTTPS URLs need a TLS configuration",
+                )
+            })?;
+            let hostname = self.address.rsplit_once(':').map_or("", |(host, _)| host);
+            let name = ServerName::try_from(hostname.trim_matches(['[', ']']).to_owned())
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+            let stream = tokio_rustls::TlsConnector::from(config)
+                .connect(name, stream)
+                .await?;
+
+            return exchange(stream, head, body).await;
+        }
+
+        exchange(stream, head, body).await
+    }
+}
+
+/// A response of a server.
+pub(super) struct Response {
+    /// The status code.
+    pub(super) status: u16,
+    /// The header fields, without the status line.
+    head: String,
+    /// The body, decoded if it was chunked.
+    pub(super) body: String,
+}
+
+impl Response {
+    /// Parse the HTTP `response` of a server.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if `response` is malformed.
+    fn parse(response: &[u8]) -> io::Result<Self> {
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response");
+
+        let end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(malformed)?;
+        let head = std::str::from_utf8(&response[..end]).map_err(|_| malformed())?;
+        let body = &response[end + 4..];
+        let (status, head) = head.split_once("\r\n").unwrap_or((head, ""));
+        let status = status
+            .split(' ')
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(malformed)?;
+
+        let mut response = Self {
+            status,
+            head: head.to_owned(),
+            body: String::new(),
+        };
+        let chunked = response
+            .header("Transfer-Encoding")
+            .is_some_and(|value| value.eq_ignore_ascii_case("chunked"));
+        if !chunked {
+            response.body = String::from_utf8_lossy(body).into_owned();
+            return Ok(response);
+        }
+
+        let mut decoded = Vec::new();
+        let mut rest = body;
+        loop {
+            let line_end = rest
+                .windows(2)
+                .position(|window| window == b"\r\n")
+                .ok_or_else(malformed)?;
+            let size = std::str::from_utf8(&rest[..line_end]).map_err(|_| malformed())?;
+            let size = size.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| malformed())?;
+            if size == 0 {
+                response.body = String::from_utf8_lossy(&decoded).into_owned();
+                return Ok(response);
+            }
+
+            let chunk = rest
+                .get(line_end + 2..line_end + 2 + size)
+                .ok_or_else(malformed)?;
+            decoded.extend_from_slice(chunk);
+            rest = rest.get(line_end + 4 + size..).ok_or_else(malformed)?;
+        }
+    }
+
+    /// Whether the status code is a success.
+    pub(super) fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Get the value of the header field `name`, which is not case-sensitive.
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        self.head.split("\r\n").find_map(|line| {
+            let (field, value) = line.split_once(':')?;
+            field.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+}
+
+/// Send a request made of `head` and `body` through `stream`, and read the response until the
+/// server closes the connection.
+async fn exchange(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    head: &[u8],
+    body: &[u8],
+) -> io::Result<Vec<u8>> {
+    stream.write_all(head).await?;
+    stream.write_all(body).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE).read_to_end(&mut response).await?;
+
+    Ok(response)
+}
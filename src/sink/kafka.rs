@@ -0,0 +1,270 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Produces messages to Apache Kafka, see [`KafkaSink`].
+
+use std::{fmt::Debug, io, time::Duration};
+
+use rdkafka::{
+    message::{Header, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord},
+    ClientConfig,
+};
+
+use super::{envelope_headers, DeliveryResult, MessageSink, PayloadFormat};
+use crate::Message;
+
+/// What the key of each record is made from, which decides the partition that it is written to.
+///
+/// Records with the same key are written to the same partition, and keep their order.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum KafkaKey {
+    /// No key, spreading records over every partition.
+    None,
+    /// The ID of the session that the message was received through.
+    #[default]
+    Session,
+    /// The reverse-path of the message, which is empty for the null sender.
+    Sender,
+    /// The domain of the reverse-path of the message, which is empty for the null sender.
+    SenderDomain,
+    /// The forward-path of the first recipient of the message.
+    Recipient,
+    /// The domain of the forward-path of the first recipient of the message.
+    RecipientDomain,
+}
+
+impl KafkaKey {
+    /// Get the key of the record of `message`, or `None` for [`Self::None`].
+    #[must_use]
+    pub fn key_for(self, message: &Message) -> Option<String> {
+        let domain = |path: &str| {
+            path.rsplit_once('@')
+                .map_or("", |(_, domain)| domain)
+                .to_ascii_lowercase()
+        };
+        let sender = || {
+            message
+                .envelope()
+                .reverse_path()
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        };
+        let recipient = || {
+            message
+                .envelope()
+                .forward_paths()
+                .next()
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        };
+
+        Some(match self {
+            Self::None => return None,
+            Self::Session => message.session().id().to_string(),
+            Self::Sender => sender(),
+            Self::SenderDomain => domain(&sender()),
+            Self::Recipient => recipient(),
+            Self::RecipientDomain => domain(&recipient()),
+        })
+    }
+}
+
+/// Produces each received message as a record of a Kafka topic, for high-volume ingestion
+/// pipelines.
+///
+/// A message is only accepted once Kafka reports that its record was delivered, so the producer
+/// is created with `acks=all` and idempotence on. Records that cannot be delivered before
+/// [`Self::timeout`] are deferred.
+///
+/// Each record is laid out according to its [`PayloadFormat`], keyed according to its
+/// [`KafkaKey`], and carries the envelope of the message in its headers, with a `recipient`
+/// header for each recipient, in order.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{sink::{KafkaKey, KafkaSink}, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let sink = KafkaSink::new("kafka-1:9092,kafka-2:9092", "mail")?
+///     .with_key(KafkaKey::RecipientDomain);
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     move |_| sink.clone(),
+///     Shutdown::new(),
+/// );
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct KafkaSink {
+    /// Produces the records, shared between clones.
+    producer: FutureProducer,
+    /// The topic that records are produced to.
+    topic: String,
+    /// What the key of each record is made from.
+    key: KafkaKey,
+    /// How each message is laid out.
+    format: PayloadFormat,
+    /// How long to wait for each record to be delivered.
+    timeout: Duration,
+}
+
+impl KafkaSink {
+    /// Create a new [`Self`] that produces records to `topic` of the cluster reached through
+    /// `brokers`, a comma-separated list of hosts and ports such as `kafka-1:9092,kafka-2:9092`.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the producer cannot be created.
+    pub fn new(brokers: &str, topic: impl Into<String>) -> io::Result<Self> {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", brokers);
+
+        Self::from_config(config, topic)
+    }
+
+    /// Create a new [`Self`] that produces records to `topic` with a producer made from `config`,
+    /// such as to authenticate to the cluster.
+    ///
+    /// `acks` is set to `all` and `enable.idempotence` to `true` in `config`, unless it sets them.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the producer cannot be created from `config`.
+    pub fn from_config(mut config: ClientConfig, topic: impl Into<String>) -> io::Result<Self> {
+        for (key, value) in [("acks", "all"), ("enable.idempotence", "true")] {
+            if config.get(key).is_none() {
+                config.set(key, value);
+            }
+        }
+
+        Ok(Self {
+            producer: config.create().map_err(io::Error::other)?,
+            topic: topic.into(),
+            key: KafkaKey::default(),
+            format: PayloadFormat::default(),
+            timeout: Duration::from_secs(30),
+        })
+    }
+
+    /// Get the topic that records are produced to.
+    #[must_use]
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Get what the key of each record is made from, [`KafkaKey::Session`] by default.
+    #[must_use]
+    pub const fn key(&self) -> KafkaKey {
+        self.key
+    }
+
+    /// Get how each message is laid out, [`PayloadFormat::Raw`] by default.
+    #[must_use]
+    pub const fn format(&self) -> PayloadFormat {
+        self.format
+    }
+
+    /// Get how long to wait for each record to be delivered, including retries, 30 seconds by
+    /// default.
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Set what the key of each record is made from. See [`Self::key`].
+    #[must_use]
+    pub const fn with_key(mut self, key: KafkaKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Set how each message is laid out. See [`Self::format`].
+    #[must_use]
+    pub const fn with_format(mut self, format: PayloadFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set how long to wait for each record to be delivered. See [`Self::timeout`].
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Produce `message`, returning the partition and the offset of its record once Kafka reports
+    /// that it was delivered.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the record is not delivered in time, or Kafka refuses it.
+    pub async fn produce(&self, message: &Message) -> io::Result<(i32, i64)> {
+        let payload = self.format.payload(message)?;
+        let key = self.key.key_for(message);
+
+        let mut record = FutureRecord::to(&self.topic)
+            .payload(&payload)
+            .headers(headers(message));
+        if let Some(key) = &key {
+            record = record.key(key);
+        }
+
+        self.producer
+            .send(record, self.timeout)
+            .await
+            .map_err(|(error, _)| io::Error::other(error))
+    }
+}
+
+impl Debug for KafkaSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaSink")
+            .field("topic", &self.topic)
+            .field("key", &self.key)
+            .field("format", &self.format)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MessageSink for KafkaSink {
+    async fn deliver(&self, message: Message) -> DeliveryResult {
+        self.produce(&message).await?;
+
+        Ok(())
+    }
+}
+
+/// Get the headers of the record of `message`.
+pub(super) fn headers(message: &Message) -> OwnedHeaders {
+    envelope_headers(message)
+        .iter()
+        .fold(OwnedHeaders::new(), |headers, (key, value)| {
+            headers.insert(Header {
+                key,
+                value: Some(value.as_str()),
+            })
+        })
+}
@@ -0,0 +1,340 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Re-delivers messages to an LMTP server, see [`LmtpSink`].
+
+use std::{io, time::Duration};
+
+use ascii::AsciiStr;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use super::{DeliveryError, DeliveryResult, MessageSink};
+use crate::{handler::Response, message::envelope::RecipientStatus, Message};
+
+/// The longest reply line that is read from the LMTP server, well over the 512 octets of
+/// [RFC 5321 section 4.5.3.1.5](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.1.5).
+const MAX_LINE: u64 = 4096;
+
+/// Re-delivers each received message to an LMTP server, such as the one of Dovecot, for gateways
+/// in front of a mailbox server.
+///
+/// LMTP ([RFC 2033](https://www.rfc-editor.org/rfc/rfc2033.html)) replies to the data of a message
+/// once for each recipient, so unlike SMTP, it can deliver a message to some recipients and not
+/// others. [`Self::relay`] records those replies as the [`RecipientStatus`] of
+/// each recipient of the message.
+///
+/// As a [`MessageSink`], [`Self`] delivers a message if it was delivered to at least one
+/// recipient, and logs the recipients that it was not delivered to. If it was delivered to none of
+/// them, it is refused with the reply of the LMTP server for the first recipient that may be tried
+/// again, or for the first one otherwise. If the LMTP server cannot be reached, delivery fails,
+/// which tells the client to try again later. Handlers that must bounce each recipient that fails
+/// can call [`Self::relay`] themselves.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// # use smtp_gateway::{sink::LmtpSink, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let sink = LmtpSink::new("/run/dovecot/lmtp")
+///     .with_hostname("mx.example.com")
+///     .with_timeout(Duration::from_secs(60));
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     move |_| sink.clone(),
+///     Shutdown::new(),
+/// );
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct LmtpSink {
+    /// The host and port of the LMTP server, or the path of its Unix socket.
+    address: String,
+    /// The name given in `LHLO`.
+    hostname: String,
+    /// How long to wait for each delivery.
+    timeout: Duration,
+}
+
+impl LmtpSink {
+    /// Create a new [`Self`] that delivers messages to the LMTP server at `address`, a host and
+    /// port such as `127.0.0.1:24`, or the absolute path of a Unix socket such as
+    /// `/run/dovecot/lmtp`.
+    #[must_use]
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            hostname: "localhost".to_owned(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Get the host and port of the LMTP server, or the path of its Unix socket.
+    #[must_use]
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Get the name that [`Self`] gives for itself in `LHLO`, `localhost` by default.
+    #[must_use]
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// Get how long to wait for each delivery, including connecting to the LMTP server, 30
+    /// seconds by default.
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Set the name given in `LHLO`. See [`Self::hostname`].
+    #[must_use]
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = hostname.into();
+        self
+    }
+
+    /// Set how long to wait for each delivery. See [`Self::timeout`].
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Get whether [`Self::address`] is the path of a Unix socket, rather than a host and port.
+    fn is_socket_path(&self) -> bool {
+        self.address.starts_with('/')
+    }
+
+    /// Deliver `message` to each of its accepted recipients, returning the reply of the LMTP
+    /// server for each of them, in order.
+    ///
+    /// The status of each of those recipients is replaced with how the LMTP server replied, so
+    /// that only the ones it was delivered to are still [`RecipientStatus::Accepted`]. A reply to
+    /// `MAIL FROM` or `DATA` that refuses the message is the reply for every recipient that it
+    /// applies to.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the LMTP server cannot be reached in time, does not accept the session,
+    ///   or replies with anything other than a valid reply. The statuses are left as they were.
+    pub async fn relay(&self, message: &mut Message) -> io::Result<Vec<Response>> {
+        let replies = tokio::time::timeout(self.timeout, async {
+            #[cfg(unix)]
+            if self.is_socket_path() {
+                let stream = tokio::net::UnixStream::connect(&self.address).await?;
+                return transaction(BufReader::new(stream), &self.hostname, message).await;
+            }
+
+            let stream = TcpStream::connect(&self.address).await?;
+            transaction(BufReader::new(stream), &self.hostname, message).await
+        })
+        .await
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                "LMTP server did not respond in time",
+            )
+        })??;
+
+        let accepted = message
+            .envelope_mut()
+            .recipients
+            .iter_mut()
+            .filter(|recipient| recipient.status == RecipientStatus::Accepted);
+        for (recipient, reply) in accepted.zip(&replies) {
+            recipient.status = match reply.code() {
+                200..=299 => RecipientStatus::Accepted,
+                400..=499 => RecipientStatus::Deferred,
+                _ => RecipientStatus::Rejected,
+            };
+        }
+
+        Ok(replies)
+    }
+}
+
+impl MessageSink for LmtpSink {
+    async fn deliver(&self, mut message: Message) -> DeliveryResult {
+        let forward_paths: Vec<_> = message
+            .envelope()
+            .forward_paths()
+            .map(ToOwned::to_owned)
+            .collect();
+        let replies = self.relay(&mut message).await?;
+
+        if message.envelope().accepted().next().is_some() {
+            let id = message.session().id();
+            for (reply, forward_path) in replies.iter().zip(&forward_paths) {
+                if !is_success(reply) {
+                    println!(
+                        "[{id}] LMTP server did not deliver message to <{forward_path}>: {reply}"
+                    );
+                }
+            }
+
+            return Ok(());
+        }
+
+        let reply = replies
+            .iter()
+            .find(|reply| (400..=499).contains(&reply.code()))
+            .or_else(|| replies.first())
+            .cloned()
+            .ok_or_else(|| io::Error::other("LMTP server replied for no recipients"))?;
+
+        Err(DeliveryError::Refused(reply))
+    }
+}
+
+/// Deliver `message` over `stream` in a single LMTP session, returning the reply for each accepted
+/// recipient of `message`, in order.
+async fn transaction(
+    mut stream: BufReader<impl AsyncRead + AsyncWrite + Unpin>,
+    hostname: &str,
+    message: &Message,
+) -> io::Result<Vec<Response>> {
+    let greeting = reply(&mut stream).await?;
+    if greeting.code() != 220 {
+        return Err(io::Error::other(format!(
+            "LMTP server refused the session: {greeting}"
+        )));
+    }
+
+    let lhlo = command(&mut stream, &format!("LHLO {hostname}")).await?;
+    if lhlo.code() != 250 {
+        return Err(io::Error::other(format!(
+            "LMTP server refused LHLO: {lhlo}"
+        )));
+    }
+
+    let envelope = message.envelope();
+    let reverse_path = envelope.reverse_path().map_or("", AsciiStr::as_str);
+    let mail = command(&mut stream, &format!("MAIL FROM:<{reverse_path}>")).await?;
+    if !is_success(&mail) {
+        quit(&mut stream).await;
+        return Ok(vec![mail; envelope.forward_paths().count()]);
+    }
+
+    let mut replies = Vec::new();
+    for forward_path in envelope.forward_paths() {
+        replies.push(command(&mut stream, &format!("RCPT TO:<{forward_path}>")).await?);
+    }
+    if !replies.iter().any(is_success) {
+        quit(&mut stream).await;
+        return Ok(replies);
+    }
+
+    let data = command(&mut stream, "DATA").await?;
+    if data.code() == 354 {
+        stream.write_all(&stuff(message.data())).await?;
+        stream.flush().await?;
+    }
+    for recipient in replies.iter_mut().filter(|reply| is_success(reply)) {
+        *recipient = match data.code() {
+            354 => reply(&mut stream).await?,
+            400..=599 => data.clone(),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("LMTP server replied to DATA with {data}"),
+                ))
+            }
+        };
+    }
+
+    quit(&mut stream).await;
+    Ok(replies)
+}
+
+/// Get whether `reply` is a positive completion reply, `2yz`.
+fn is_success(reply: &Response) -> bool {
+    (200..=299).contains(&reply.code())
+}
+
+/// Send `command` and read the reply to it.
+async fn command(
+    stream: &mut BufReader<impl AsyncRead + AsyncWrite + Unpin>,
+    command: &str,
+) -> io::Result<Response> {
+    stream
+        .write_all(format!("{command}\r\n").as_bytes())
+        .await?;
+    stream.flush().await?;
+    reply(stream).await
+}
+
+/// End the session, ignoring any error as the transaction is already over.
+async fn quit(stream: &mut BufReader<impl AsyncRead + AsyncWrite + Unpin>) {
+    let _ = command(stream, "QUIT").await;
+}
+
+/// Read a reply, returning its last line for one that spans multiple lines.
+async fn reply(stream: &mut BufReader<impl AsyncRead + Unpin>) -> io::Result<Response> {
+    loop {
+        let mut line = String::new();
+        (&mut *stream).take(MAX_LINE).read_line(&mut line).await?;
+        if !line.ends_with('\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "LMTP server closed the connection or sent too long a line",
+            ));
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+
+        return Response::parse(line).map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("LMTP server sent an invalid reply {line:?}: {error}"),
+            )
+        });
+    }
+}
+
+/// Get `data` as it is sent after `DATA`: with `CRLF` line endings, with each line that starts
+/// with a period dot-stuffed, and ending with the terminating `.` line.
+///
+/// [RFC 5321 section 4.5.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.2).
+pub(super) fn stuff(data: &[u8]) -> Vec<u8> {
+    let mut stuffed = Vec::with_capacity(data.len() + 5);
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    if !data.is_empty() {
+        for line in data.split(|&byte| byte == b'\n') {
+            if line.starts_with(b".") {
+                stuffed.push(b'.');
+            }
+            stuffed.extend_from_slice(line.strip_suffix(b"\r").unwrap_or(line));
+            stuffed.extend_from_slice(b"\r\n");
+        }
+    }
+    stuffed.extend_from_slice(b".\r\n");
+
+    stuffed
+}
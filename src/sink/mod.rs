@@ -0,0 +1,323 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Deliver received messages to storage or to other systems, such as a directory, an S3 bucket, an
+//! SQLite database, a message broker, or an LMTP server, see [`MessageSink`].
+//!
+//! Sinks are combined into a pipeline with [`MessageSink::fanout`], [`MessageSink::fallback`],
+//! [`MessageSink::filter`], and [`MessageSink::dead_letter`], and every sink is an
+//! [`SmtpHandler`] that accepts a message once it is delivered, so a whole pipeline can handle the
+//! messages of a server. They are shared between sessions by cloning them in the
+//! [`crate::HandlerFactory`], such as `move |_| store.clone()`.
+
+#[cfg(feature = "amqp")]
+mod amqp;
+mod combinator;
+mod directory;
+#[cfg(any(feature = "s3", feature = "webhook"))]
+mod http;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "lmtp")]
+mod lmtp;
+#[cfg(feature = "nats")]
+mod nats;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(test)]
+mod test;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+use std::{error::Error, fmt::Display, future::Future};
+#[cfg(any(
+    feature = "amqp",
+    feature = "kafka",
+    feature = "nats",
+    feature = "redis"
+))]
+use std::{io, time::UNIX_EPOCH};
+
+#[cfg(feature = "amqp")]
+pub use self::amqp::AmqpSink;
+pub use self::combinator::{DeadLetter, Fallback, Fanout, Filter};
+pub use self::directory::DirectorySink;
+#[cfg(feature = "kafka")]
+pub use self::kafka::{KafkaKey, KafkaSink};
+#[cfg(feature = "lmtp")]
+pub use self::lmtp::LmtpSink;
+#[cfg(feature = "nats")]
+pub use self::nats::NatsSink;
+#[cfg(feature = "redis")]
+pub use self::redis::{RedisQueue, RedisSink};
+#[cfg(feature = "s3")]
+pub use self::s3::{S3Sink, MIN_PART_SIZE};
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::{MessageQuery, SqliteStore, StoredMessage};
+#[cfg(feature = "webhook")]
+pub use self::webhook::{WebhookError, WebhookFormat, WebhookSink};
+use crate::{
+    handler::{Decision, HandlerError, HandlerResult, Response, SessionContext},
+    Message, SmtpHandler,
+};
+
+/// What a [`MessageSink`] returns: nothing once the message is delivered, or why it was not.
+pub type DeliveryResult = Result<(), DeliveryError>;
+
+/// Why a [`MessageSink`] did not deliver a message.
+///
+/// Any [`Error`] converts into [`DeliveryError::Failed`], so sinks can use `?`.
+#[derive(Debug)]
+pub enum DeliveryError {
+    /// The message was refused, such as by a webhook that responded with `400`, with the reply
+    /// that the client is given: a `4yz` reply tells it to try again later, and a `5yz` reply
+    /// that it must not.
+    Refused(Response),
+    /// The message could not be delivered, such as because a database is unavailable, which tells
+    /// the client to try again later.
+    Failed(Box<dyn Error + Send + Sync>),
+}
+
+impl DeliveryError {
+    /// Get whether delivering the message again later could succeed, which is true of every
+    /// [`Self::Failed`] and of [`Self::Refused`] with a `4yz` reply.
+    #[must_use]
+    pub fn is_temporary(&self) -> bool {
+        match self {
+            Self::Refused(response) => (400..=499).contains(&response.code()),
+            Self::Failed(_) => true,
+        }
+    }
+}
+
+impl<E: Error + Send + Sync + 'static> From<E> for DeliveryError {
+    fn from(source: E) -> Self {
+        Self::Failed(Box::new(source))
+    }
+}
+
+impl Display for DeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Refused(response) => write!(f, "message refused with {response}"),
+            Self::Failed(source) => write!(f, "delivery failed: {source}"),
+        }
+    }
+}
+
+/// Delivers received messages somewhere, such as to a database or a message broker.
+///
+/// Every [`MessageSink`] is an [`SmtpHandler`] that delivers each message in
+/// [`SmtpHandler::on_message`], accepting it once [`Self::deliver`] succeeds, giving the client
+/// the reply of [`DeliveryError::Refused`], and deferring it with `451 4.3.0` on
+/// [`DeliveryError::Failed`].
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{
+/// #     sink::{DeliveryResult, MessageSink},
+/// #     Message, ServerConfig, Shutdown,
+/// # };
+/// # use tokio::net::TcpListener;
+/// #
+/// /// Prints each message.
+/// #[derive(Clone)]
+/// struct Printer;
+///
+/// impl MessageSink for Printer {
+///     async fn deliver(&self, message: Message) -> DeliveryResult {
+///         println!("{}", String::from_utf8_lossy(message.data()));
+///         Ok(())
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let sink = Printer.filter(|message| message.size().total() < 1024 * 1024);
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     move |_| sink.clone(),
+///     Shutdown::new(),
+/// );
+/// #     Ok(())
+/// # }
+/// ```
+pub trait MessageSink: Send + Sync + 'static {
+    /// Deliver `message`.
+    ///
+    /// # Errors
+    ///
+    /// - [`DeliveryError::Refused`] if the message was refused.
+    /// - [`DeliveryError::Failed`] if the message could not be delivered.
+    fn deliver(&self, message: Message) -> impl Future<Output = DeliveryResult> + Send;
+
+    /// Deliver each message to both [`Self`] and `other` at once, succeeding once both of them
+    /// do. See [`Fanout`].
+    #[must_use]
+    fn fanout<S: MessageSink>(self, other: S) -> Fanout<Self, S>
+    where
+        Self: Sized,
+    {
+        Fanout::new(self, other)
+    }
+
+    /// Deliver each message to `fallback` when [`Self`] fails to deliver it for a reason that is
+    /// temporary. See [`Fallback`].
+    #[must_use]
+    fn fallback<S: MessageSink>(self, fallback: S) -> Fallback<Self, S>
+    where
+        Self: Sized,
+    {
+        Fallback::new(self, fallback)
+    }
+
+    /// Only deliver the messages that `predicate` returns `true` for, skipping every other one as
+    /// if it was delivered. See [`Filter`].
+    #[must_use]
+    fn filter<F>(self, predicate: F) -> Filter<Self, F>
+    where
+        Self: Sized,
+        F: Fn(&Message) -> bool + Send + Sync + 'static,
+    {
+        Filter::new(self, predicate)
+    }
+
+    /// Retry each message that [`Self`] fails to deliver for a reason that is temporary, and once
+    /// there are no retries left, deliver it to `dead_letter`, such as a [`DirectorySink`], with
+    /// why it failed in its header. See [`DeadLetter`].
+    #[must_use]
+    fn dead_letter<S: MessageSink>(self, dead_letter: S) -> DeadLetter<Self, S>
+    where
+        Self: Sized,
+    {
+        DeadLetter::new(self, dead_letter)
+    }
+}
+
+impl<S: MessageSink> SmtpHandler for S {
+    async fn on_message(&mut self, _: &mut SessionContext, message: Message) -> HandlerResult {
+        match self.deliver(message).await {
+            Ok(()) => Ok(Decision::Accept),
+            Err(DeliveryError::Refused(response)) => Ok(response.into()),
+            Err(DeliveryError::Failed(source)) => Err(HandlerError::new(source)),
+        }
+    }
+}
+
+/// How the sinks that publish messages to a message broker or a queue, such as [`AmqpSink`],
+/// [`KafkaSink`], [`NatsSink`], and [`RedisSink`], lay out each one.
+#[cfg(any(
+    feature = "amqp",
+    feature = "kafka",
+    feature = "nats",
+    feature = "redis"
+))]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum PayloadFormat {
+    /// The data of the message as it was received, with its envelope in the headers that the
+    /// broker keeps alongside it.
+    #[default]
+    Raw,
+    /// The [`Message`] serialized to JSON, with its envelope and its data together.
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+#[cfg(any(
+    feature = "amqp",
+    feature = "kafka",
+    feature = "nats",
+    feature = "redis"
+))]
+impl PayloadFormat {
+    /// Get the media type of the payload.
+    #[must_use]
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Raw => "message/rfc822",
+            #[cfg(feature = "serde")]
+            Self::Json => "application/json",
+        }
+    }
+
+    /// Lay out `message` as a payload.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if `message` cannot be serialized.
+    #[cfg_attr(
+        not(feature = "serde"),
+        expect(
+            clippy::unnecessary_wraps,
+            reason = "only `Self::Json` can fail, which needs the `serde` feature"
+        )
+    )]
+    pub(crate) fn payload(self, message: &Message) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Raw => Ok(message.data().to_vec()),
+            #[cfg(feature = "serde")]
+            Self::Json => Ok(serde_json::to_vec(message)?),
+        }
+    }
+}
+
+/// Get the envelope of `message` and the details of its session as the names and values of the
+/// headers that a broker keeps alongside a payload, with a `recipient` for each recipient, in
+/// order.
+#[cfg(any(
+    feature = "amqp",
+    feature = "kafka",
+    feature = "nats",
+    feature = "redis"
+))]
+pub(crate) fn envelope_headers(message: &Message) -> Vec<(&'static str, String)> {
+    let session = message.session();
+    let received_at = message
+        .received_at()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let mut headers = vec![
+        ("session-id", session.id().to_string()),
+        ("peer-addr", session.peer_addr().to_string()),
+        ("received-at", received_at.as_millis().to_string()),
+        (
+            "reverse-path",
+            message
+                .envelope()
+                .reverse_path()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+        ),
+    ];
+    if let Some(helo) = session.helo() {
+        headers.push(("helo", helo.to_string()));
+    }
+    for recipient in message.envelope().forward_paths() {
+        headers.push(("recipient", recipient.to_string()));
+    }
+
+    headers
+}
@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Publishes messages to NATS JetStream, see [`NatsSink`].
+
+use std::{fmt::Debug, io, time::Duration};
+
+use async_nats::{jetstream, Client, HeaderMap};
+
+use super::{envelope_headers, DeliveryResult, MessageSink, PayloadFormat};
+use crate::Message;
+
+/// The fields of the envelope that a subject template can hold, each between braces.
+const FIELDS: [&str; 5] = [
+    "session",
+    "sender",
+    "sender_domain",
+    "recipient",
+    "recipient_domain",
+];
+
+/// Publishes each received message to a subject of NATS JetStream, for consumers that are
+/// already on NATS.
+///
+/// A message is only accepted once the stream that captures its subject acknowledges it, and
+/// each one is published with a `Nats-Msg-Id` made of its session and its content, so that a
+/// stream with a duplicate window keeps it once if a client sends it again.
+///
+/// The subject is made from a template that can hold these fields of the envelope between braces,
+/// such as `mail.{recipient_domain}`:
+///
+/// - `session`: the ID of the session that the message was received through.
+/// - `sender` and `sender_domain`: the reverse-path of the message and its domain, or `null` for
+///   the null sender.
+/// - `recipient` and `recipient_domain`: the forward-path of the first recipient of the message and
+///   its domain.
+///
+/// Dots, wildcards, and whitespace in a field are replaced with `_`, so that each field is one
+/// token of the subject.
+///
+/// Each message is laid out according to its [`PayloadFormat`], and carries its envelope in its
+/// headers, with a `recipient` header for each recipient, in order.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{sink::NatsSink, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let sink = NatsSink::connect("nats://127.0.0.1:4222", "mail.{recipient_domain}").await?;
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     move |_| sink.clone(),
+///     Shutdown::new(),
+/// );
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct NatsSink {
+    /// Publishes the messages, shared between clones.
+    jetstream: jetstream::Context,
+    /// The template of the subject of each message.
+    subject: String,
+    /// How each message is laid out.
+    format: PayloadFormat,
+    /// How long to wait for each message to be acknowledged.
+    timeout: Duration,
+}
+
+impl NatsSink {
+    /// Connect to the NATS server at `url`, such as `nats://127.0.0.1:4222`, to publish messages to
+    /// the subjects made from the template `subject`.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] with [`io::ErrorKind::InvalidInput`] if `subject` holds an unknown field.
+    /// - [`io::Error`] if the server cannot be reached.
+    pub async fn connect(url: &str, subject: impl Into<String>) -> io::Result<Self> {
+        let subject = subject.into();
+        check_template(&subject)?;
+        let client = async_nats::connect(url).await.map_err(io::Error::other)?;
+
+        Self::new(client, subject)
+    }
+
+    /// Create a new [`Self`] that publishes messages through `client`, such as one that was
+    /// connected with credentials, to the subjects made from the template `subject`.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] with [`io::ErrorKind::InvalidInput`] if `subject` holds an unknown field.
+    pub fn new(client: Client, subject: impl Into<String>) -> io::Result<Self> {
+        let subject = subject.into();
+        check_template(&subject)?;
+
+        Ok(Self {
+            jetstream: jetstream::new(client),
+            subject,
+            format: PayloadFormat::default(),
+            timeout: Duration::from_secs(30),
+        })
+    }
+
+    /// Get the template of the subject of each message.
+    #[must_use]
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Get how each message is laid out, [`PayloadFormat::Raw`] by default.
+    #[must_use]
+    pub const fn format(&self) -> PayloadFormat {
+        self.format
+    }
+
+    /// Get how long to wait for each message to be acknowledged, 30 seconds by default.
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Set how each message is laid out. See [`Self::format`].
+    #[must_use]
+    pub const fn with_format(mut self, format: PayloadFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set how long to wait for each message to be acknowledged. See [`Self::timeout`].
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Get the subject that `message` is published to.
+    #[must_use]
+    pub fn subject_for(&self, message: &Message) -> String {
+        expand(&self.subject, message)
+    }
+
+    /// Publish `message`, returning the sequence number that the stream gave it once the stream
+    /// acknowledges it.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if no stream acknowledges the message in time.
+    pub async fn publish(&self, message: &Message) -> io::Result<u64> {
+        let payload = self.format.payload(message)?;
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Nats-Msg-Id",
+            format!("{}-{}", message.session().id(), message.hash()),
+        );
+        for (name, value) in envelope_headers(message) {
+            headers.append(name, value);
+        }
+
+        let publish = async {
+            self.jetstream
+                .publish_with_headers(self.subject_for(message), headers, payload.into())
+                .await
+                .map_err(io::Error::other)?
+                .await
+                .map_err(io::Error::other)
+        };
+        let ack = tokio::time::timeout(self.timeout, publish)
+            .await
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "JetStream did not acknowledge in time",
+                )
+            })??;
+
+        Ok(ack.sequence)
+    }
+}
+
+impl Debug for NatsSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NatsSink")
+            .field("subject", &self.subject)
+            .field("format", &self.format)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MessageSink for NatsSink {
+    async fn deliver(&self, message: Message) -> DeliveryResult {
+        self.publish(&message).await?;
+
+        Ok(())
+    }
+}
+
+/// Check that every field in the subject template `template` is known.
+///
+/// # Errors
+///
+/// - [`io::Error`] with [`io::ErrorKind::InvalidInput`] if a field is unknown or unclosed.
+fn check_template(template: &str) -> io::Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let field = rest[start + 1..]
+            .split_once('}')
+            .map(|(field, _)| field)
+            .filter(|field| FIELDS.contains(field))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid subject template: {template}"),
+                )
+            })?;
+        rest = &rest[start + field.len() + 2..];
+    }
+
+    Ok(())
+}
+
+/// Make the subject of `message` from `template`, which was checked with [`check_template`].
+pub(super) fn expand(template: &str, message: &Message) -> String {
+    let domain = |path: &str| {
+        path.rsplit_once('@')
+            .map_or("", |(_, domain)| domain)
+            .to_owned()
+    };
+    let sender = message
+        .envelope()
+        .reverse_path()
+        .map_or_else(|| "null".to_owned(), ToString::to_string);
+    let recipient = message
+        .envelope()
+        .forward_paths()
+        .next()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+
+    let mut subject = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        subject.push_str(&rest[..start]);
+        let Some((field, after)) = rest[start + 1..].split_once('}') else {
+            break;
+        };
+        let value = match field {
+            "session" => message.session().id().to_string(),
+            "sender" => sender.clone(),
+            "sender_domain" => domain(&sender),
+            "recipient" => recipient.clone(),
+            "recipient_domain" => domain(&recipient),
+            _ => String::new(),
+        };
+        subject.extend(value.chars().map(|character| {
+            if matches!(character, '.' | '*' | '>') || character.is_whitespace() {
+                '_'
+            } else {
+                character
+            }
+        }));
+        rest = after;
+    }
+    subject.push_str(rest);
+
+    subject
+}
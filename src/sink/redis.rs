@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Queues messages in Redis, see [`RedisSink`].
+
+use std::{fmt::Debug, io};
+
+use redis::{aio::MultiplexedConnection, AsyncCommands, Client};
+
+use super::{envelope_headers, DeliveryResult, MessageSink, PayloadFormat};
+use crate::{greylist::RedisStore, Message};
+
+/// How [`RedisSink`] queues each message.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum RedisQueue {
+    /// Push the payload onto the head of a list with `LPUSH`, so that consumers take the oldest
+    /// message from its tail with `RPOP` or `BRPOP`.
+    ///
+    /// A list only holds the payload, so only [`PayloadFormat::Json`] keeps the envelope.
+    #[default]
+    List,
+    /// Add an entry to a stream with `XADD`, so that consumer groups can read and acknowledge
+    /// messages with `XREADGROUP` and `XACK`.
+    ///
+    /// Each entry holds the envelope in the same fields that the sinks for message brokers use as
+    /// headers, with a `recipient` field for each recipient, in order, then `content-type` and
+    /// the payload as `data`.
+    Stream,
+}
+
+/// Queues each received message in Redis, for consumers that take mail from a list or a stream,
+/// or for deployments that already share a Redis server between gateways.
+///
+/// A message is accepted once Redis has queued it, and deferred if Redis cannot be reached.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{sink::{RedisQueue, RedisSink}, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let sink = RedisSink::connect("redis://127.0.0.1/", "mail:inbound")
+///     .await?
+///     .with_queue(RedisQueue::Stream);
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     move |_| sink.clone(),
+///     Shutdown::new(),
+/// );
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct RedisSink {
+    /// The connection to the server, shared between sessions.
+    connection: MultiplexedConnection,
+    /// The key of the list or stream.
+    key: String,
+    /// How each message is queued.
+    queue: RedisQueue,
+    /// How each message is laid out.
+    format: PayloadFormat,
+}
+
+impl RedisSink {
+    /// Connect to the Redis server at `url`, such as `redis://127.0.0.1/`, to queue messages under
+    /// `key`.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if `url` is malformed or the server cannot be connected to.
+    pub async fn connect(url: &str, key: impl Into<String>) -> io::Result<Self> {
+        let client = Client::open(url).map_err(io::Error::other)?;
+        let connection = client
+            .get_multiplexed_tokio_connection()
+            .await
+            .map_err(io::Error::other)?;
+
+        Ok(Self::new(connection, key))
+    }
+
+    /// Create a new [`Self`] that queues messages under `key` through the connection of `store`,
+    /// so that greylisting, rate limits, and the queue share one connection.
+    #[must_use]
+    pub fn from_store(store: &RedisStore, key: impl Into<String>) -> Self {
+        Self::new(store.connection().clone(), key)
+    }
+
+    /// Create a new [`Self`] that queues messages under `key` through `connection`.
+    fn new(connection: MultiplexedConnection, key: impl Into<String>) -> Self {
+        Self {
+            connection,
+            key: key.into(),
+            queue: RedisQueue::default(),
+            format: PayloadFormat::default(),
+        }
+    }
+
+    /// Get the key of the list or stream that messages are queued in.
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get how each message is queued, [`RedisQueue::List`] by default.
+    #[must_use]
+    pub const fn queue(&self) -> RedisQueue {
+        self.queue
+    }
+
+    /// Get how each message is laid out, [`PayloadFormat::Raw`] by default.
+    #[must_use]
+    pub const fn format(&self) -> PayloadFormat {
+        self.format
+    }
+
+    /// Set how each message is queued. See [`Self::queue`].
+    #[must_use]
+    pub const fn with_queue(mut self, queue: RedisQueue) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    /// Set how each message is laid out. See [`Self::format`].
+    #[must_use]
+    pub const fn with_format(mut self, format: PayloadFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Queue `message`.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if `message` cannot be laid out, or the server cannot be reached.
+    pub async fn push(&self, message: &Message) -> io::Result<()> {
+        let mut connection = self.connection.clone();
+
+        match self.queue {
+            RedisQueue::List => connection
+                .lpush(&self.key, self.format.payload(message)?)
+                .await
+                .map_err(io::Error::other),
+            RedisQueue::Stream => connection
+                .xadd(&self.key, "*", &fields(self.format, message)?)
+                .await
+                .map_err(io::Error::other),
+        }
+    }
+}
+
+impl Debug for RedisSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisSink")
+            .field("key", &self.key)
+            .field("queue", &self.queue)
+            .field("format", &self.format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MessageSink for RedisSink {
+    async fn deliver(&self, message: Message) -> DeliveryResult {
+        self.push(&message).await?;
+
+        Ok(())
+    }
+}
+
+/// Get the fields of the stream entry that queues `message` laid out as `format`.
+///
+/// # Errors
+///
+/// - [`io::Error`] if `message` cannot be laid out.
+pub(super) fn fields(
+    format: PayloadFormat,
+    message: &Message,
+) -> io::Result<Vec<(&'static str, Vec<u8>)>> {
+    let mut fields: Vec<_> = envelope_headers(message)
+        .into_iter()
+        .map(|(name, value)| (name, value.into_bytes()))
+        .collect();
+    fields.push(("content-type", format.content_type().as_bytes().to_vec()));
+    fields.push(("data", format.payload(message)?));
+
+    Ok(fields)
+}
@@ -0,0 +1,407 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Stores messages in SQLite, see [`SqliteStore`].
+
+use std::{
+    net::SocketAddr,
+    ops::Range,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ascii::AsciiStr;
+use bytes::Bytes;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
+    QueryBuilder, Row, Sqlite, SqlitePool,
+};
+
+use super::{DeliveryResult, MessageSink};
+use crate::{session::SessionId, Message};
+
+/// The tables that messages are kept in, created when a store is opened if they do not exist.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id TEXT NOT NULL,
+    peer_addr TEXT NOT NULL,
+    helo TEXT,
+    reverse_path TEXT,
+    received_at INTEGER NOT NULL,
+    data BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS messages_session_id ON messages (session_id);
+CREATE INDEX IF NOT EXISTS messages_received_at ON messages (received_at);
+CREATE TABLE IF NOT EXISTS recipients (
+    message_id INTEGER NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    PRIMARY KEY (message_id, position)
+);
+CREATE INDEX IF NOT EXISTS recipients_address ON recipients (address COLLATE NOCASE);
+";
+
+/// The columns of a [`StoredMessage`], with its recipients joined by line feeds.
+const SELECT: &str = "
+SELECT id, session_id, peer_addr, helo, reverse_path, received_at, data, (
+    SELECT group_concat(address, char(10)) FROM (
+        SELECT address FROM recipients WHERE message_id = messages.id ORDER BY position
+    )
+) AS recipients
+FROM messages WHERE 1 = 1";
+
+/// Stores the envelope and the data of each received message in an SQLite database, for small
+/// gateways and test rigs.
+///
+/// As a [`MessageSink`], it delivers each message once it is stored. Stored messages are looked
+/// up with [`Self::find`] and a [`MessageQuery`].
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{sink::{MessageQuery, SqliteStore}, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let store = SqliteStore::open("messages.sqlite").await?;
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     {
+///         let store = store.clone();
+///         move |_| store.clone()
+///     },
+///     Shutdown::new(),
+/// );
+///
+/// let messages = store
+///     .find(&MessageQuery::new().with_recipient("jones@example.com"))
+///     .await?;
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    /// The connections to the database.
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Open the database in the file at `path`, creating it and its tables if they do not exist.
+    ///
+    /// # Errors
+    ///
+    /// - [`sqlx::Error`] if the database cannot be opened or its tables cannot be created.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+
+        Self::with_pool(SqlitePoolOptions::new().connect_with(options).await?).await
+    }
+
+    /// Open a new database kept in memory, which is lost once the store and its clones are
+    /// dropped, such as for tests.
+    ///
+    /// # Errors
+    ///
+    /// - [`sqlx::Error`] if the database cannot be opened or its tables cannot be created.
+    pub async fn memory() -> Result<Self, sqlx::Error> {
+        // Each connection to `:memory:` gets its own database, so only one is ever made.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(SqliteConnectOptions::new().in_memory(true))
+            .await?;
+
+        Self::with_pool(pool).await
+    }
+
+    /// Create a new [`Self`] on the connections of `pool`, creating its tables if they do not
+    /// exist.
+    ///
+    /// # Errors
+    ///
+    /// - [`sqlx::Error`] if the tables cannot be created.
+    pub async fn with_pool(pool: SqlitePool) -> Result<Self, sqlx::Error> {
+        sqlx::raw_sql(SCHEMA).execute(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Get the connections to the database.
+    #[must_use]
+    pub const fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    /// Store `message` with its accepted recipients, returning the ID it is stored under.
+    ///
+    /// # Errors
+    ///
+    /// - [`sqlx::Error`] if the message cannot be stored.
+    pub async fn store(&self, message: &Message) -> Result<i64, sqlx::Error> {
+        let session = message.session();
+        let envelope = message.envelope();
+        let mut transaction = self.pool.begin().await?;
+
+        let id = sqlx::query(
+            "INSERT INTO messages (session_id, peer_addr, helo, reverse_path, received_at, data) \
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(session.id().to_string())
+        .bind(session.peer_addr().to_string())
+        .bind(session.helo().map(AsciiStr::as_str))
+        .bind(envelope.reverse_path().map(AsciiStr::as_str))
+        .bind(millis(message.received_at()))
+        .bind(message.data().as_ref())
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+
+        for (position, recipient) in (0_i64..).zip(envelope.forward_paths()) {
+            sqlx::query(
+                "INSERT INTO recipients (message_id, position, address) VALUES ($1, $2, $3)",
+            )
+            .bind(id)
+            .bind(position)
+            .bind(recipient.as_str())
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(id)
+    }
+
+    /// Get the stored messages that match every condition of `query`, in the order they were
+    /// received.
+    ///
+    /// # Errors
+    ///
+    /// - [`sqlx::Error`] if the database cannot be queried.
+    pub async fn find(&self, query: &MessageQuery) -> Result<Vec<StoredMessage>, sqlx::Error> {
+        let mut builder = QueryBuilder::<Sqlite>::new(SELECT);
+
+        if let Some(recipient) = &query.recipient {
+            builder
+                .push(
+                    " AND id IN (SELECT message_id FROM recipients \
+                     WHERE address = ",
+                )
+                .push_bind(recipient)
+                .push(" COLLATE NOCASE)");
+        }
+        if let Some(received) = &query.received {
+            builder
+                .push(" AND received_at >= ")
+                .push_bind(millis(received.start))
+                .push(" AND received_at < ")
+                .push_bind(millis(received.end));
+        }
+        if let Some(session) = query.session {
+            builder
+                .push(" AND session_id = ")
+                .push_bind(session.to_string());
+        }
+        builder.push(" ORDER BY received_at, id");
+
+        builder
+            .build()
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(StoredMessage::from_row)
+            .collect()
+    }
+
+    /// Delete the stored message with the ID `id`, returning whether there was one.
+    ///
+    /// # Errors
+    ///
+    /// - [`sqlx::Error`] if the message cannot be deleted.
+    pub async fn remove(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM messages WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl MessageSink for SqliteStore {
+    async fn deliver(&self, message: Message) -> DeliveryResult {
+        self.store(&message).await?;
+
+        Ok(())
+    }
+}
+
+/// The conditions that [`SqliteStore::find`] looks up messages with, every one of which must
+/// match. Matches every message by default.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct MessageQuery {
+    /// The forward-path of a recipient of the message, which is not case-sensitive.
+    recipient: Option<String>,
+    /// When the message was received.
+    received: Option<Range<SystemTime>>,
+    /// The session that the message was received through.
+    session: Option<SessionId>,
+}
+
+impl MessageQuery {
+    /// Create a new [`Self`] that matches every message.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match messages with a recipient with the forward-path `address`, ignoring case.
+    #[must_use]
+    pub fn with_recipient(mut self, address: &str) -> Self {
+        self.recipient = Some(address.to_owned());
+        self
+    }
+
+    /// Only match messages received within `range`, to the millisecond.
+    #[must_use]
+    pub const fn with_received(mut self, range: Range<SystemTime>) -> Self {
+        self.received = Some(range);
+        self
+    }
+
+    /// Only match messages received through the session `id`.
+    #[must_use]
+    pub const fn with_session(mut self, id: SessionId) -> Self {
+        self.session = Some(id);
+        self
+    }
+}
+
+/// A message kept in a [`SqliteStore`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct StoredMessage {
+    /// The ID the message is stored under.
+    id: i64,
+    /// The session that the message was received through.
+    session_id: SessionId,
+    /// The address of the client.
+    peer_addr: SocketAddr,
+    /// The identity the client gave in `HELO`, if any.
+    helo: Option<String>,
+    /// The reverse-path, or `None` for the null reverse-path.
+    reverse_path: Option<String>,
+    /// The forward-paths of the accepted recipients.
+    recipients: Vec<String>,
+    /// When the end of the data was received, to the millisecond.
+    received_at: SystemTime,
+    /// The data of the message.
+    data: Bytes,
+}
+
+impl StoredMessage {
+    /// Read a [`Self`] from a row of [`SELECT`].
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        /// The error for a column whose value cannot be parsed.
+        fn malformed(column: &str) -> sqlx::Error {
+            sqlx::Error::Decode(format!("malformed {column}").into())
+        }
+
+        let session_id = row.try_get::<&str, _>("session_id")?;
+        let recipients = row.try_get::<Option<String>, _>("recipients")?;
+
+        Ok(Self {
+            id: row.try_get("id")?,
+            session_id: u64::from_str_radix(session_id, 16)
+                .map(SessionId::from_u64)
+                .map_err(|_| malformed("session_id"))?,
+            peer_addr: row
+                .try_get::<&str, _>("peer_addr")?
+                .parse()
+                .map_err(|_| malformed("peer_addr"))?,
+            helo: row.try_get("helo")?,
+            reverse_path: row.try_get("reverse_path")?,
+            recipients: recipients
+                .map(|recipients| recipients.lines().map(ToOwned::to_owned).collect())
+                .unwrap_or_default(),
+            received_at: u64::try_from(row.try_get::<i64, _>("received_at")?)
+                .ok()
+                .and_then(|millis| UNIX_EPOCH.checked_add(Duration::from_millis(millis)))
+                .ok_or_else(|| malformed("received_at"))?,
+            data: Bytes::from(row.try_get::<Vec<u8>, _>("data")?),
+        })
+    }
+
+    /// Get the ID the message is stored under.
+    #[must_use]
+    pub const fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Get the session that the message was received through.
+    #[must_use]
+    pub const fn session_id(&self) -> SessionId {
+        self.session_id
+    }
+
+    /// Get the address of the client.
+    #[must_use]
+    pub const fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Get the identity the client gave in `HELO`, or `None` if it did not.
+    #[must_use]
+    pub fn helo(&self) -> Option<&str> {
+        self.helo.as_deref()
+    }
+
+    /// Get the reverse-path, or `None` for the null reverse-path.
+    #[must_use]
+    pub fn reverse_path(&self) -> Option<&str> {
+        self.reverse_path.as_deref()
+    }
+
+    /// Get the forward-paths of the accepted recipients, in the order they were given.
+    #[must_use]
+    pub fn recipients(&self) -> &[String] {
+        &self.recipients
+    }
+
+    /// Get when the end of the data was received, to the millisecond.
+    #[must_use]
+    pub const fn received_at(&self) -> SystemTime {
+        self.received_at
+    }
+
+    /// Get the data of the message.
+    #[must_use]
+    pub const fn data(&self) -> &Bytes {
+        &self.data
+    }
+}
+
+/// Get the milliseconds since the Unix epoch of `time`, or zero if it is before.
+fn millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| {
+        i64::try_from(since.as_millis()).unwrap_or(i64::MAX)
+    })
+}
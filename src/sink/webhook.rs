@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Posts messages to an HTTP endpoint, see [`WebhookSink`].
+
+use std::{
+    fmt::{Debug, Display, Write as _},
+    io,
+    time::Duration,
+};
+
+use super::{http::Endpoint, DeliveryError, DeliveryResult, MessageSink};
+use crate::{handler::Response, message::export, Message};
+
+/// How each message is laid out in the body of its request.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum WebhookFormat {
+    /// A JSON object with the envelope of the message, the details of its session, and its data in
+    /// base64 as `data`.
+    #[default]
+    Json,
+    /// A `multipart/form-data` upload of the same JSON object without `data` as the `metadata`
+    /// field, and the data of the message as is as the `message` file.
+    Multipart,
+}
+
+/// An error when posting a message with [`WebhookSink::post`].
+#[derive(Debug)]
+pub enum WebhookError {
+    /// The endpoint responded with a status code that is not retried, such as `400`.
+    Refused(u16),
+    /// The endpoint could not be reached, or kept failing, until there were no retries left.
+    Failed(io::Error),
+}
+
+impl Display for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Refused(status) => write!(f, "webhook refused the message with {status}"),
+            Self::Failed(error) => write!(f, "webhook failed: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for WebhookError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Refused(_) => None,
+            Self::Failed(error) => Some(error),
+        }
+    }
+}
+
+/// Posts each received message to an HTTP endpoint, for gateways that hand mail to an API.
+///
+/// Requests that fail, time out, or get a `408`, `429`, or `5xx` response are retried up to
+/// [`Self::retries`] times, waiting [`Self::backoff`] before the first retry and twice as long
+/// before each one after. Any other response that is not a success refuses the message.
+///
+/// By default, each message is accepted as soon as it is received, and posted in the background,
+/// where failures are only logged. With [`Self::with_defer_reply`], the reply to the client waits
+/// for the endpoint instead: the message is accepted once it responds with `2xx`, rejected with
+/// `554` if it refuses the message, and deferred if it cannot be reached.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{sink::{WebhookFormat, WebhookSink}, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let sink = WebhookSink::new("http://api.internal/inbound-mail")?
+///     .with_header("Authorization", "Bearer 0123456789")
+///     .with_format(WebhookFormat::Multipart)
+///     .with_defer_reply(true);
+/// let sessions = smtp_gateway::listen(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     ServerConfig::default(),
+///     move |_| sink.clone(),
+///     Shutdown::new(),
+/// );
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct WebhookSink {
+    /// Where messages are posted.
+    endpoint: Endpoint,
+    /// How each message is laid out.
+    format: WebhookFormat,
+    /// The names and values of the headers added to each request.
+    headers: Vec<(String, String)>,
+    /// How many times a request is retried.
+    retries: u32,
+    /// How long to wait before the first retry.
+    backoff: Duration,
+    /// How long to wait for each request.
+    timeout: Duration,
+    /// Whether the reply to the client waits for the endpoint.
+    defer_reply: bool,
+}
+
+impl WebhookSink {
+    /// Create a new [`Self`] that posts messages to `url`, an `http://` or `https://` URL.
+    ///
+    /// `https://` URLs need the `tls` feature and a configuration given with [`Self::with_tls`].
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] with [`io::ErrorKind::InvalidInput`] if `url` is not a URL of a supported
+    ///   scheme.
+    pub fn new(url: &str) -> io::Result<Self> {
+        Ok(Self {
+            endpoint: Endpoint::parse(url)?,
+            format: WebhookFormat::default(),
+            headers: Vec::new(),
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            timeout: Duration::from_secs(30),
+            defer_reply: false,
+        })
+    }
+
+    /// Get the URL that messages are posted to.
+    #[must_use]
+    pub fn url(&self) -> &str {
+        self.endpoint.url()
+    }
+
+    /// Get how each message is laid out, [`WebhookFormat::Json`] by default.
+    #[must_use]
+    pub const fn format(&self) -> WebhookFormat {
+        self.format
+    }
+
+    /// Get the names and values of the headers added to each request, such as for
+    /// authentication.
+    #[must_use]
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Get how many times a request is retried, 3 by default.
+    #[must_use]
+    pub const fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Get how long to wait before the first retry, which doubles for each one after, 1 second by
+    /// default.
+    #[must_use]
+    pub const fn backoff(&self) -> Duration {
+        self.backoff
+    }
+
+    /// Get how long to wait for each request, including connecting to the endpoint, 30 seconds by
+    /// default.
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Get whether the reply to the client waits for the endpoint to respond, rather than
+    /// accepting each message before it is posted, which is the default.
+    #[must_use]
+    pub const fn defer_reply(&self) -> bool {
+        self.defer_reply
+    }
+
+    /// Set how each message is laid out. See [`Self::format`].
+    #[must_use]
+    pub const fn with_format(mut self, format: WebhookFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Add a header to each request. See [`Self::headers`].
+    ///
+    /// Headers whose name or value cannot be sent in a request are left out.
+    #[must_use]
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set how many times a request is retried. See [`Self::retries`].
+    #[must_use]
+    pub const fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Set how long to wait before the first retry. See [`Self::backoff`].
+    #[must_use]
+    pub const fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set how long to wait for each request. See [`Self::timeout`].
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set whether the reply to the client waits for the endpoint to respond. See
+    /// [`Self::defer_reply`].
+    #[must_use]
+    pub const fn with_defer_reply(mut self, defer: bool) -> Self {
+        self.defer_reply = defer;
+        self
+    }
+
+    /// Set how `https://` URLs are reached, such as with the root certificates that the
+    /// certificate of the endpoint is verified against.
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub fn with_tls(mut self, config: std::sync::Arc<crate::tls::rustls::ClientConfig>) -> Self {
+        self.endpoint.set_tls(config);
+        self
+    }
+
+    /// Post `message`, retrying until the endpoint responds with `2xx` or there are no retries
+    /// left.
+    ///
+    /// # Errors
+    ///
+    /// - [`WebhookError::Refused`] if the endpoint refuses the message.
+    /// - [`WebhookError::Failed`] if the endpoint cannot be reached, or keeps failing.
+    pub async fn post(&self, message: &Message) -> Result<(), WebhookError> {
+        let (content_type, body) = body(self.format, message);
+
+        let mut head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+             Connection: close\r\n",
+            self.endpoint.path(),
+            self.endpoint.host(),
+            body.len()
+        );
+        for (name, value) in &self.headers {
+            let sendable = !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|byte| byte.is_ascii_graphic() && byte != b':')
+                && value
+                    .bytes()
+                    .all(|byte| byte == b'\t' || !byte.is_ascii_control());
+            if sendable {
+                let _ = write!(head, "{name}: {value}\r\n");
+            }
+        }
+        head.push_str("\r\n");
+
+        let mut backoff = self.backoff;
+        let mut attempts = 0;
+        loop {
+            let error = match self
+                .endpoint
+                .send(head.as_bytes(), &body, self.timeout)
+                .await
+            {
+                Ok(response) if response.is_success() => return Ok(()),
+                Ok(response) if !matches!(response.status, 408 | 429 | 500..) => {
+                    return Err(WebhookError::Refused(response.status));
+                }
+                Ok(response) => {
+                    io::Error::other(format!("webhook responded with {}", response.status))
+                }
+                Err(error) => error,
+            };
+
+            if attempts >= self.retries {
+                return Err(WebhookError::Failed(error));
+            }
+            attempts += 1;
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+}
+
+impl Debug for WebhookSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The headers can hold credentials.
+        f.debug_struct("WebhookSink")
+            .field("url", &self.endpoint.url())
+            .field("format", &self.format)
+            .field("retries", &self.retries)
+            .field("backoff", &self.backoff)
+            .field("timeout", &self.timeout)
+            .field("defer_reply", &self.defer_reply)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MessageSink for WebhookSink {
+    async fn deliver(&self, message: Message) -> DeliveryResult {
+        if !self.defer_reply {
+            let sink = self.clone();
+            tokio::spawn(async move {
+                if let Err(error) = sink.post(&message).await {
+                    let id = message.session().id();
+                    println!("[{id}] Failed to post message to webhook: {error}");
+                }
+            });
+
+            return Ok(());
+        }
+
+        match self.post(&message).await {
+            Ok(()) => Ok(()),
+            Err(WebhookError::Refused(_)) => Err(DeliveryError::Refused(Response::parse(
+                "554 5.0.0 Message refused by webhook",
+            )?)),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+/// Get the media type and the body of the request that posts `message` as `format`.
+pub(super) fn body(format: WebhookFormat, message: &Message) -> (String, Vec<u8>) {
+    match format {
+        WebhookFormat::Json => (
+            "application/json".to_owned(),
+            message.to_json().into_bytes(),
+        ),
+        WebhookFormat::Multipart => {
+            let metadata = export::metadata(message);
+            // The data would have to hold its own hash to hold the boundary.
+            let boundary = format!("smtp-gateway-{}", message.hash());
+
+            let mut body = format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"metadata\"\r\n\
+                 Content-Type: application/json\r\n\r\n{metadata}\r\n--{boundary}\r\n\
+                 Content-Disposition: form-data; name=\"message\"; filename=\"message.eml\"\r\n\
+                 Content-Type: message/rfc822\r\n\r\n"
+            )
+            .into_bytes();
+            body.extend_from_slice(message.data());
+            body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+            (format!("multipart/form-data; boundary={boundary}"), body)
+        }
+    }
+}
@@ -0,0 +1,443 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Scanning messages for spam with [SpamAssassin](https://spamassassin.apache.org/).
+//!
+//! See [`SpamAssassinPolicy`] and [`scan`].
+//!
+//! The message is submitted to `spamd` with the `SYMBOLS` command of the protocol that `spamc`
+//! speaks, as described by the [documentation of
+//! SpamAssassin](https://svn.apache.org/repos/asf/spamassassin/trunk/spamd/PROTOCOL).
+
+#[cfg(test)]
+mod test;
+
+use std::{io, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    handler::{Defer, Response},
+    message::Message,
+    ServerConfig,
+};
+
+/// The longest response that is read from `spamd`, in bytes.
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+/// Where messages are scored by SpamAssassin, and the scores at which they are tagged or
+/// rejected.
+///
+/// Each message is scored once its data is received, before it is handed to
+/// [`crate::SmtpHandler::on_message`]. Messages that reach [`Self::reject_score`] are rejected with
+/// `554 5.7.1`, and the rest are accepted with the `X-Spam-Status:` header that SpamAssassin
+/// would add, along with `X-Spam-Flag: YES` if they reach [`Self::tag_score`]. Any of these
+/// headers that the client sent are removed first, and every accepted message keeps its
+/// [`SpamAssassinVerdict`] in [`Message::spamassassin`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use smtp_gateway::spamassassin::SpamAssassinPolicy;
+/// #
+/// let policy = SpamAssassinPolicy::new("spamd.internal:783").with_reject_score(10.0);
+///
+/// assert_eq!(policy.tag_score(), None);
+/// ```
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct SpamAssassinPolicy {
+    /// The host and port of `spamd`.
+    address: String,
+    /// How long to wait for a scan.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    timeout: Duration,
+    /// The user whose preferences `spamd` scores with, if any.
+    user: Option<String>,
+    /// The score at which messages are tagged, or `None` for the threshold of `spamd`.
+    tag_score: Option<f64>,
+    /// The score at which messages are rejected, or `None` if they are not.
+    reject_score: Option<f64>,
+    /// Whether messages are deferred when they cannot be scored.
+    defer_on_error: bool,
+}
+
+// Scores are checked to be finite when the configuration is built.
+impl Eq for SpamAssassinPolicy {}
+
+impl SpamAssassinPolicy {
+    /// Create a new [`Self`] that scores messages with the `spamd` at `address`, a host and port
+    /// such as `127.0.0.1:783`.
+    #[must_use]
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Get the host and port of `spamd`, `127.0.0.1:783` by default.
+    #[must_use]
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Get how long to wait for a scan, including connecting to `spamd`, 30 seconds by default.
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Get the user whose preferences `spamd` scores messages with, or `None` for the user that
+    /// `spamd` runs as, which is the default.
+    #[must_use]
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Get the score at which messages are tagged as spam, or `None` for the `required_score` of
+    /// `spamd`, which is the default.
+    #[must_use]
+    pub const fn tag_score(&self) -> Option<f64> {
+        self.tag_score
+    }
+
+    /// Get the score at which messages are rejected, or `None` if they are only tagged, which is
+    /// the default.
+    #[must_use]
+    pub const fn reject_score(&self) -> Option<f64> {
+        self.reject_score
+    }
+
+    /// Get whether messages are deferred with `451 4.3.0` when they cannot be scored, or accepted
+    /// without a verdict, which is the default.
+    #[must_use]
+    pub const fn defer_on_error(&self) -> bool {
+        self.defer_on_error
+    }
+
+    /// Get every score of [`Self`], to check that they are finite.
+    pub(crate) fn scores(&self) -> impl Iterator<Item = f64> {
+        self.tag_score.into_iter().chain(self.reject_score)
+    }
+
+    /// Set how long to wait for a scan. See [`Self::timeout`].
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the user whose preferences `spamd` scores messages with. See [`Self::user`].
+    #[must_use]
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Set the score at which messages are tagged as spam. See [`Self::tag_score`].
+    #[must_use]
+    pub const fn with_tag_score(mut self, score: f64) -> Self {
+        self.tag_score = Some(score);
+        self
+    }
+
+    /// Set the score at which messages are rejected. See [`Self::reject_score`].
+    #[must_use]
+    pub const fn with_reject_score(mut self, score: f64) -> Self {
+        self.reject_score = Some(score);
+        self
+    }
+
+    /// Set whether messages are deferred when they cannot be scored. See
+    /// [`Self::defer_on_error`].
+    #[must_use]
+    pub const fn with_defer_on_error(mut self, defer: bool) -> Self {
+        self.defer_on_error = defer;
+        self
+    }
+
+    /// Get what is done with a message of `score`, which `spamd` judged as spam or not.
+    fn action(&self, score: f64, is_spam: bool) -> SpamAssassinAction {
+        if self.reject_score.is_some_and(|reject| score >= reject) {
+            SpamAssassinAction::Reject
+        } else if self.tag_score.map_or(is_spam, |tag| score >= tag) {
+            SpamAssassinAction::Tag
+        } else {
+            SpamAssassinAction::Accept
+        }
+    }
+}
+
+impl Default for SpamAssassinPolicy {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:783".to_owned(),
+            timeout: Duration::from_secs(30),
+            user: None,
+            tag_score: None,
+            reject_score: None,
+            defer_on_error: false,
+        }
+    }
+}
+
+/// What is done with a message scored by SpamAssassin, see [`SpamAssassinPolicy`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum SpamAssassinAction {
+    /// Accept the message.
+    Accept,
+    /// Accept the message, tagging it as spam with `X-Spam-Flag: YES`.
+    Tag,
+    /// Reject the message.
+    Reject,
+}
+
+/// The result of scoring a message with SpamAssassin, see [`scan`].
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpamAssassinVerdict {
+    /// Whether `spamd` judged the message as spam.
+    is_spam: bool,
+    /// The score of the message.
+    score: f64,
+    /// The score at which `spamd` judges messages as spam.
+    required_score: f64,
+    /// The names of the tests that matched, in order.
+    symbols: Vec<String>,
+    /// What is done with the message.
+    action: SpamAssassinAction,
+}
+
+// Scores are parsed from decimal numbers, which cannot be NaN.
+impl Eq for SpamAssassinVerdict {}
+
+impl SpamAssassinVerdict {
+    /// Get whether `spamd` judged the message as spam, by its own `required_score`.
+    #[must_use]
+    pub const fn is_spam(&self) -> bool {
+        self.is_spam
+    }
+
+    /// Get the score of the message.
+    #[must_use]
+    pub const fn score(&self) -> f64 {
+        self.score
+    }
+
+    /// Get the score at which `spamd` judges messages as spam.
+    #[must_use]
+    pub const fn required_score(&self) -> f64 {
+        self.required_score
+    }
+
+    /// Get the names of the tests that matched the message, such as `BAYES_99`, in order.
+    #[must_use]
+    pub fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    /// Get what is done with the message, by the thresholds of [`SpamAssassinPolicy`].
+    #[must_use]
+    pub const fn action(&self) -> SpamAssassinAction {
+        self.action
+    }
+
+    /// Get the value of the `X-Spam-Status:` header for the message, as SpamAssassin formats it.
+    #[must_use]
+    pub fn status(&self) -> String {
+        format!(
+            "{}, score={:.1} required={:.1} tests={}",
+            if self.action == SpamAssassinAction::Accept {
+                "No"
+            } else {
+                "Yes"
+            },
+            self.score,
+            self.required_score,
+            if self.symbols.is_empty() {
+                "none".to_owned()
+            } else {
+                self.symbols.join(",")
+            }
+        )
+    }
+}
+
+/// Score `message` with the `spamd` of `policy`, deciding what is done with it by its thresholds.
+///
+/// # Errors
+///
+/// - [`io::Error`] if `spamd` cannot be reached in time, or its response is not a valid result.
+pub async fn scan(
+    policy: &SpamAssassinPolicy,
+    message: &Message,
+) -> io::Result<SpamAssassinVerdict> {
+    let request = request(policy, message);
+
+    let response = tokio::time::timeout(policy.timeout, async {
+        let mut stream = TcpStream::connect(&policy.address).await?;
+        stream.write_all(&request).await?;
+        stream.write_all(message.data()).await?;
+        stream.shutdown().await?;
+
+        let mut response = Vec::new();
+        stream.take(MAX_RESPONSE).read_to_end(&mut response).await?;
+        io::Result::Ok(response)
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "spamd did not respond in time"))??;
+
+    parse(policy, &response)
+}
+
+/// Create the head of the `SYMBOLS` request that submits `message` to `spamd`.
+fn request(policy: &SpamAssassinPolicy, message: &Message) -> Vec<u8> {
+    let mut request = format!(
+        "SYMBOLS SPAMC/1.5\r\nContent-length: {}\r\n",
+        message.data().len()
+    );
+    if let Some(user) = policy
+        .user
+        .as_deref()
+        .filter(|user| !user.bytes().any(|byte| byte.is_ascii_control()))
+    {
+        request.push_str("User: ");
+        request.push_str(user);
+        request.push_str("\r\n");
+    }
+    request.push_str("\r\n");
+
+    request.into_bytes()
+}
+
+/// Parse the `response` of `spamd` to `SYMBOLS` into a [`SpamAssassinVerdict`].
+///
+/// # Errors
+///
+/// - [`io::Error`] if `response` is malformed or is not a success.
+fn parse(policy: &SpamAssassinPolicy, response: &[u8]) -> io::Result<SpamAssassinVerdict> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed response from spamd");
+
+    let response = std::str::from_utf8(response).map_err(|_| malformed())?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((response, ""));
+    let mut lines = head.split("\r\n");
+
+    let status = lines.next().unwrap_or_default();
+    let mut parts = status.split(' ');
+    if !parts
+        .next()
+        .is_some_and(|protocol| protocol.starts_with("SPAMD/"))
+    {
+        return Err(malformed());
+    }
+    if parts.next() != Some("0") {
+        return Err(io::Error::other(format!("spamd responded with {status}")));
+    }
+
+    // `Spam: True ; 15.0 / 5.0`
+    let (is_spam, score, required_score) = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("Spam"))
+        .and_then(|(_, value)| {
+            let (is_spam, scores) = value.split_once(';')?;
+            let (score, required_score) = scores.split_once('/')?;
+            let is_spam = match is_spam.trim() {
+                spam if spam.eq_ignore_ascii_case("True") || spam.eq_ignore_ascii_case("Yes") => {
+                    true
+                }
+                spam if spam.eq_ignore_ascii_case("False") || spam.eq_ignore_ascii_case("No") => {
+                    false
+                }
+                _ => return None,
+            };
+            let score: f64 = score.trim().parse().ok()?;
+            let required_score: f64 = required_score.trim().parse().ok()?;
+
+            (score.is_finite() && required_score.is_finite()).then_some((
+                is_spam,
+                score,
+                required_score,
+            ))
+        })
+        .ok_or_else(malformed)?;
+
+    Ok(SpamAssassinVerdict {
+        is_spam,
+        score,
+        required_score,
+        symbols: body
+            .split(',')
+            .map(str::trim)
+            .filter(|symbol| !symbol.is_empty())
+            .map(ToOwned::to_owned)
+            .collect(),
+        action: policy.action(score, is_spam),
+    })
+}
+
+/// Score `message` if [`ServerConfig::spamassassin_policy`] is set, keeping the verdict in it and
+/// adding the headers of SpamAssassin to it.
+///
+/// # Errors
+///
+/// - The [`Response`] to reply with if the message is rejected or deferred.
+pub(crate) async fn apply(
+    config: &ServerConfig,
+    mut message: Message,
+) -> Result<Message, Response> {
+    let Some(policy) = config.spamassassin_policy() else {
+        return Ok(message);
+    };
+    let id = message.session().id();
+
+    let verdict = match scan(policy, &message).await {
+        Ok(verdict) => verdict,
+        Err(error) => {
+            println!("[{id}] Failed to score message with SpamAssassin: {error}");
+            if policy.defer_on_error {
+                return Err(Defer::LocalError.response());
+            }
+            return Ok(message);
+        }
+    };
+    println!(
+        "[{id}] SpamAssassin scored message {:.1}/{:.1}",
+        verdict.score, verdict.required_score
+    );
+    if verdict.action == SpamAssassinAction::Reject {
+        return Err(Response::parse("554 5.7.1 Message rejected as spam")
+            .expect("written in code as a valid reply"));
+    }
+
+    message.remove_header("X-Spam-Flag");
+    let result = message.replace_header("X-Spam-Status", &verdict.status());
+    if let Err(error) = result {
+        println!("[{id}] Failed to add X-Spam-Status header: {error}");
+    }
+    if verdict.action == SpamAssassinAction::Tag {
+        let _ = message.add_header("X-Spam-Flag", "YES");
+    }
+
+    Ok(message.with_spamassassin(verdict))
+}
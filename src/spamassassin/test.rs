@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use super::*;
+
+#[test]
+fn test_parse() -> io::Result<()> {
+    let policy = SpamAssassinPolicy::default();
+
+    let verdict = parse(
+        &policy,
+        b"SPAMD/1.1 0 EX_OK\r\nContent-length: 23\r\nSpam: True ; 15.2 / 5.0\r\n\r\nBAYES_99,URIBL_BLACK\r\n",
+    )?;
+    assert!(verdict.is_spam());
+    assert!((verdict.score() - 15.2).abs() < f64::EPSILON);
+    assert!((verdict.required_score() - 5.0).abs() < f64::EPSILON);
+    assert_eq!(verdict.symbols(), ["BAYES_99", "URIBL_BLACK"]);
+    assert_eq!(verdict.action(), SpamAssassinAction::Tag);
+    assert_eq!(
+        verdict.status(),
+        "Yes, score=15.2 required=5.0 tests=BAYES_99,URIBL_BLACK"
+    );
+
+    let verdict = parse(
+        &policy,
+        b"SPAMD/1.5 0 EX_OK\r\nSpam: False ; -0.5 / 5.0\r\n\r\n",
+    )?;
+    assert!(!verdict.is_spam());
+    assert_eq!(verdict.action(), SpamAssassinAction::Accept);
+    assert_eq!(verdict.status(), "No, score=-0.5 required=5.0 tests=none");
+
+    // Tests that failures and malformed responses are errors.
+    assert!(parse(&policy, b"SPAMD/1.1 76 Bad header line\r\n\r\n").is_err());
+    assert!(parse(&policy, b"SPAMD/1.1 0 EX_OK\r\n\r\n").is_err());
+    assert!(parse(&policy, b"SPAMD/1.1 0 EX_OK\r\nSpam: Maybe ; 1 / 5\r\n\r\n").is_err());
+    assert!(parse(&policy, b"HTTP/1.1 200 OK\r\n\r\n").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_thresholds() {
+    let policy = SpamAssassinPolicy::default();
+    assert_eq!(policy.action(4.0, false), SpamAssassinAction::Accept);
+    assert_eq!(policy.action(6.0, true), SpamAssassinAction::Tag);
+    // Tests that messages are never rejected without a rejection score.
+    assert_eq!(policy.action(100.0, true), SpamAssassinAction::Tag);
+
+    // Tests that the thresholds of the policy replace the judgement of `spamd`.
+    let policy = policy.with_tag_score(3.0).with_reject_score(10.0);
+    assert_eq!(policy.action(2.9, false), SpamAssassinAction::Accept);
+    assert_eq!(policy.action(3.0, false), SpamAssassinAction::Tag);
+    assert_eq!(policy.action(10.0, true), SpamAssassinAction::Reject);
+}
+
+#[test]
+fn test_request() -> Result<(), Box<dyn std::error::Error>> {
+    let message = Message::builder()
+        .reverse_path("smith@example.com")
+        .forward_path("jones@example.com")
+        .body("Hello\r\n")
+        .build()?;
+
+    let head = String::from_utf8(request(&SpamAssassinPolicy::default(), &message))?;
+    assert_eq!(
+        head,
+        format!(
+            "SYMBOLS SPAMC/1.5\r\nContent-length: {}\r\n\r\n",
+            message.data().len()
+        )
+    );
+
+    let policy = SpamAssassinPolicy::default().with_user("mail");
+    let head = String::from_utf8(request(&policy, &message))?;
+    assert!(head.ends_with("\r\nUser: mail\r\n\r\n"));
+    // Tests that users that would break the request are left out.
+    let policy = SpamAssassinPolicy::default().with_user("mail\r\nInjected: yes");
+    let head = String::from_utf8(request(&policy, &message))?;
+    assert!(!head.contains("Injected"));
+
+    Ok(())
+}
@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Expands the macros of SPF records, such as `%{i}` for the address of the client.
+//!
+//! See [`expand`].
+//!
+//! [RFC 7208 section 7](https://www.rfc-editor.org/rfc/rfc7208.html#section-7).
+
+use std::{fmt::Write, net::IpAddr};
+
+/// What the macros of an SPF record are expanded with.
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a> {
+    /// The sender being checked, such as `smith@example.com`.
+    pub sender: &'a str,
+    /// The address of the client.
+    pub ip: IpAddr,
+    /// The identity given by the client in `HELO`.
+    pub helo: &'a str,
+}
+
+/// Check whether `spec` is a valid macro-string, without expanding it.
+pub fn is_valid(spec: &str) -> bool {
+    let context = Context {
+        sender: "postmaster@example.com",
+        ip: IpAddr::from([0, 0, 0, 0]),
+        helo: "example.com",
+    };
+
+    expand(spec, "example.com", context).is_some()
+}
+
+/// Expand the macros of `spec` for the current `domain`.
+///
+/// The `p` macro always expands to `unknown`, as validating the name of the client takes many
+/// lookups and its use is discouraged. The `c`, `r`, and `t` macros are only allowed in
+/// explanations, which are not used.
+///
+/// Returns `None` if `spec` is not a valid macro-string.
+pub fn expand(spec: &str, domain: &str, context: Context<'_>) -> Option<String> {
+    let mut expanded = String::with_capacity(spec.len());
+    let mut chars = spec.chars();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '%' => match chars.next()? {
+                '%' => expanded.push('%'),
+                '_' => expanded.push(' '),
+                '-' => expanded.push_str("%20"),
+                '{' => {
+                    let rest = chars.as_str();
+                    let (macro_expand, rest) = rest.split_once('}')?;
+                    expanded.push_str(&expand_macro(macro_expand, domain, context)?);
+                    chars = rest.chars();
+                }
+                _ => return None,
+            },
+            '!'..='~' => expanded.push(char),
+            _ => return None,
+        }
+    }
+
+    Some(expanded)
+}
+
+/// Expand the inside of a `%{...}` macro, such as `ir` or `l1-`.
+fn expand_macro(macro_expand: &str, domain: &str, context: Context<'_>) -> Option<String> {
+    let mut chars = macro_expand.chars();
+    let letter = chars.next()?;
+    let rest = chars.as_str();
+
+    let (local_part, sender_domain) = context
+        .sender
+        .rsplit_once('@')
+        .unwrap_or(("postmaster", context.sender));
+    let value = match letter.to_ascii_lowercase() {
+        's' => context.sender.to_owned(),
+        'l' => local_part.to_owned(),
+        'o' => sender_domain.to_owned(),
+        'd' => domain.to_owned(),
+        'i' => dotted(context.ip),
+        'p' => "unknown".to_owned(),
+        'v' => match context.ip {
+            IpAddr::V4(_) => "in-addr".to_owned(),
+            IpAddr::V6(_) => "ip6".to_owned(),
+        },
+        'h' => context.helo.to_owned(),
+        _ => return None,
+    };
+
+    // `transformers = *DIGIT [ "r" ]`, followed by the delimiters.
+    let digits = rest.len()
+        - rest
+            .trim_start_matches(|char: char| char.is_ascii_digit())
+            .len();
+    let (keep, rest) = rest.split_at(digits);
+    let (reverse, delimiters) = rest
+        .strip_prefix(['r', 'R'])
+        .map_or((false, rest), |delimiters| (true, delimiters));
+    if !delimiters
+        .chars()
+        .all(|char| matches!(char, '.' | '-' | '+' | ',' | '/' | '_' | '='))
+    {
+        return None;
+    }
+
+    let mut parts: Vec<_> = if delimiters.is_empty() {
+        value.split('.').collect()
+    } else {
+        value.split(|char| delimiters.contains(char)).collect()
+    };
+    if reverse {
+        parts.reverse();
+    }
+    if !keep.is_empty() {
+        let keep: usize = keep.parse().ok().filter(|&keep| keep > 0)?;
+        parts.drain(..parts.len().saturating_sub(keep));
+    }
+    let value = parts.join(".");
+
+    // Uppercase macros are URL-encoded.
+    Some(if letter.is_ascii_uppercase() {
+        url_encode(&value)
+    } else {
+        value
+    })
+}
+
+/// Format an address for the `i` macro: dotted-quad for IPv4, and dot-separated nibbles for IPv6.
+fn dotted(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => {
+            let mut nibbles = String::with_capacity(63);
+            for byte in ip.octets() {
+                let _ = write!(nibbles, "{:x}.{:x}.", byte >> 4, byte & 0xf);
+            }
+            nibbles.pop();
+            nibbles
+        }
+    }
+}
+
+/// Percent-encode every character that is not unreserved in a URI.
+///
+/// [RFC 3986 section 2.3](https://www.rfc-editor.org/rfc/rfc3986.html#section-2.3).
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
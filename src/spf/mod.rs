@@ -0,0 +1,532 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Verifying that clients are allowed to send mail for the domains they claim, with the Sender
+//! Policy Framework (SPF).
+//!
+//! See [`check_host`] and [`SpfPolicy`].
+//!
+//! [RFC 7208](https://www.rfc-editor.org/rfc/rfc7208.html).
+
+mod macros;
+mod record;
+#[cfg(test)]
+mod test;
+
+use std::{fmt::Display, net::IpAddr};
+
+use futures_util::{future::BoxFuture, FutureExt};
+
+use self::{
+    macros::Context,
+    record::{Mechanism, Record},
+};
+use crate::{config::Network, resolver::Resolver, str::max_lengths, ServerConfig};
+
+/// The most DNS lookups that mechanisms and modifiers can cause in one check.
+///
+/// [RFC 7208 section 4.6.4](https://www.rfc-editor.org/rfc/rfc7208.html#section-4.6.4).
+const MAX_LOOKUPS: usize = 10;
+
+/// The most lookups that can find nothing in one check.
+///
+/// [RFC 7208 section 4.6.4](https://www.rfc-editor.org/rfc/rfc7208.html#section-4.6.4).
+const MAX_VOID_LOOKUPS: usize = 2;
+
+/// Whether SPF is checked for each session, and what is done with the results.
+///
+/// Results are kept in [`crate::session::SessionInfo::spf_helo`] for the identity given in `HELO`
+/// and in [`crate::message::envelope::Envelope::spf`] for the reverse-path, where the handler can
+/// make its own decisions with them.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum SpfPolicy {
+    /// Do not check SPF.
+    #[default]
+    Off,
+    /// Check SPF, and add a `Received-SPF:` header with the result for the reverse-path to each
+    /// received message.
+    Annotate,
+    /// Check SPF and annotate messages, and reject `HELO` or `MAIL` with `550 5.7.23` when the
+    /// result for its identity is [`SpfResult::Fail`].
+    Reject,
+}
+
+impl SpfPolicy {
+    /// Get whether the policy rejects the identity that `verdict` is for.
+    pub(crate) fn rejects(self, verdict: Option<&SpfVerdict>) -> bool {
+        self == Self::Reject && verdict.is_some_and(|verdict| verdict.result == SpfResult::Fail)
+    }
+}
+
+/// The result of checking SPF for an identity.
+///
+/// [RFC 7208 section 2.6](https://www.rfc-editor.org/rfc/rfc7208.html#section-2.6).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum SpfResult {
+    /// The domain did not publish an SPF record, or the identity has no domain to check.
+    None,
+    /// The domain makes no assertion about whether the client is allowed.
+    Neutral,
+    /// The client is allowed to send for the domain.
+    Pass,
+    /// The client is not allowed to send for the domain.
+    Fail,
+    /// The client is probably not allowed to send for the domain.
+    SoftFail,
+    /// A lookup failed, so the check can be tried again later.
+    TempError,
+    /// The records of the domain are invalid.
+    PermError,
+}
+
+impl Display for SpfResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Neutral => "neutral",
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::SoftFail => "softfail",
+            Self::TempError => "temperror",
+            Self::PermError => "permerror",
+        })
+    }
+}
+
+/// Which identity of the client SPF was checked for.
+///
+/// [RFC 7208 section 2](https://www.rfc-editor.org/rfc/rfc7208.html#section-2).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum SpfIdentity {
+    /// The identity given in `HELO`, checked as `postmaster@` that domain.
+    Helo,
+    /// The reverse-path given in `MAIL FROM`.
+    MailFrom,
+}
+
+impl Display for SpfIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Helo => "helo",
+            Self::MailFrom => "mailfrom",
+        })
+    }
+}
+
+/// The result of checking SPF for one identity of a client.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpfVerdict {
+    /// The result of the check.
+    result: SpfResult,
+    /// Which identity was checked.
+    identity: SpfIdentity,
+    /// The sender that was checked, such as `smith@example.com`.
+    sender: String,
+}
+
+impl SpfVerdict {
+    /// Create a new [`Self`] for the `result` of checking `sender` as `identity`.
+    #[must_use]
+    pub fn new(result: SpfResult, identity: SpfIdentity, sender: impl Into<String>) -> Self {
+        Self {
+            result,
+            identity,
+            sender: sender.into(),
+        }
+    }
+
+    /// Get the result of the check.
+    #[must_use]
+    pub const fn result(&self) -> SpfResult {
+        self.result
+    }
+
+    /// Get which identity of the client was checked.
+    #[must_use]
+    pub const fn identity(&self) -> SpfIdentity {
+        self.identity
+    }
+
+    /// Get the sender that was checked, such as `smith@example.com`, or `postmaster@` the identity
+    /// given in `HELO`.
+    #[must_use]
+    pub fn sender(&self) -> &str {
+        &self.sender
+    }
+
+    /// Get the domain that was checked, which is the domain of [`Self::sender`].
+    #[must_use]
+    pub fn domain(&self) -> &str {
+        self.sender
+            .rsplit_once('@')
+            .map_or(self.sender.as_str(), |(_, domain)| domain)
+    }
+}
+
+/// Check whether the client at `ip` is allowed to send mail for `domain`, which is the domain of
+/// `sender`, making DNS lookups with `resolver`.
+///
+/// `helo` is the identity that the client gave in `HELO`, which SPF records can refer to with the
+/// `%{h}` macro. Lookups of names without addresses can only be told apart from failed lookups by
+/// resolvers that support every kind of lookup, such as `HickoryResolver` in
+/// [`crate::resolver`].
+///
+/// [RFC 7208 section 4](https://www.rfc-editor.org/rfc/rfc7208.html#section-4).
+pub async fn check_host(
+    resolver: &impl Resolver,
+    ip: IpAddr,
+    domain: &str,
+    sender: &str,
+    helo: &str,
+) -> SpfResult {
+    let mut evaluation = Evaluation {
+        resolver,
+        context: Context {
+            sender,
+            ip: ip.to_canonical(),
+            helo,
+        },
+        lookups: 0,
+        void_lookups: 0,
+    };
+
+    evaluation.check(domain.to_owned()).await
+}
+
+/// Check SPF for the identity that the client at `ip` gave in `HELO`, if `config` asks for it.
+///
+/// Returns `None` if SPF is not checked, or if the identity is not a domain name.
+pub(crate) async fn verify_helo(
+    config: &ServerConfig,
+    ip: IpAddr,
+    helo: &str,
+) -> Option<SpfVerdict> {
+    if config.spf_policy() == SpfPolicy::Off || !is_domain(helo) {
+        return None;
+    }
+
+    let sender = format!("postmaster@{helo}");
+    let result = check_host(config.resolver(), ip, helo, &sender, helo).await;
+
+    Some(SpfVerdict::new(result, SpfIdentity::Helo, sender))
+}
+
+/// Check SPF for `reverse_path` from the client at `ip`, if `config` asks for it.
+///
+/// Returns `None` if SPF is not checked.
+pub(crate) async fn verify_mail_from(
+    config: &ServerConfig,
+    ip: IpAddr,
+    helo: &str,
+    reverse_path: &str,
+) -> Option<SpfVerdict> {
+    if config.spf_policy() == SpfPolicy::Off {
+        return None;
+    }
+
+    let domain = reverse_path
+        .rsplit_once('@')
+        .map_or(reverse_path, |(_, domain)| domain);
+    let result = check_host(config.resolver(), ip, domain, reverse_path, helo).await;
+
+    Some(SpfVerdict::new(result, SpfIdentity::MailFrom, reverse_path))
+}
+
+/// The state of one check, shared by every record that it evaluates.
+struct Evaluation<'a, R> {
+    /// Makes the lookups of the check.
+    resolver: &'a R,
+    /// What the macros of the records are expanded with.
+    context: Context<'a>,
+    /// The number of lookups made by mechanisms and modifiers.
+    lookups: usize,
+    /// The number of lookups that found nothing.
+    void_lookups: usize,
+}
+
+impl<R: Resolver> Evaluation<'_, R> {
+    /// Evaluate the SPF record of `domain`, including the records that it refers to.
+    fn check(&mut self, domain: String) -> BoxFuture<'_, SpfResult> {
+        async move {
+            if !is_domain(&domain) {
+                return SpfResult::None;
+            }
+
+            let record = match self.record(&domain).await {
+                Ok(Some(record)) => record,
+                Ok(None) => return SpfResult::None,
+                Err(result) => return result,
+            };
+
+            for directive in &record.directives {
+                match self.matches(&directive.mechanism, &domain).await {
+                    Ok(true) => return directive.qualifier,
+                    Ok(false) => (),
+                    Err(result) => return result,
+                }
+            }
+
+            // A redirect to a domain without a record is an error, unlike a missing record.
+            //
+            // <https://www.rfc-editor.org/rfc/rfc7208.html#section-6.1>
+            if let Some(redirect) = &record.redirect {
+                let target = match self
+                    .count_lookup()
+                    .and_then(|()| self.target(redirect, &domain))
+                {
+                    Ok(target) => target,
+                    Err(result) => return result,
+                };
+                return match self.check(target).await {
+                    SpfResult::None => SpfResult::PermError,
+                    result => result,
+                };
+            }
+
+            SpfResult::Neutral
+        }
+        .boxed()
+    }
+
+    /// Look up the SPF record of `domain`, or `None` if it has none.
+    ///
+    /// # Errors
+    ///
+    /// - [`SpfResult::TempError`] if the lookup failed.
+    /// - [`SpfResult::PermError`] if the domain has more than one record, or it is invalid.
+    async fn record(&self, domain: &str) -> Result<Option<Record>, SpfResult> {
+        let records = self
+            .resolver
+            .lookup_txt(domain)
+            .await
+            .map_err(|_| SpfResult::TempError)?;
+
+        let mut records = records.iter().filter(|text| Record::is_spf(text));
+        match (records.next(), records.next()) {
+            (None, _) => Ok(None),
+            (Some(record), None) => Record::parse(record).map(Some),
+            (Some(_), Some(_)) => Err(SpfResult::PermError),
+        }
+    }
+
+    /// Check whether `mechanism` of the record of `domain` matches the client.
+    ///
+    /// # Errors
+    ///
+    /// - The result that ends the check, such as [`SpfResult::TempError`] when a lookup failed.
+    async fn matches(&mut self, mechanism: &Mechanism, domain: &str) -> Result<bool, SpfResult> {
+        let ip = self.context.ip;
+        let matches = |address: IpAddr, length: u8| {
+            Network::new(address, length).is_ok_and(|network| network.contains(ip))
+        };
+
+        match mechanism {
+            Mechanism::All => Ok(true),
+            Mechanism::Include(spec) => {
+                self.count_lookup()?;
+                let target = self.target(spec, domain)?;
+                match self.check(target).await {
+                    SpfResult::Pass => Ok(true),
+                    SpfResult::Fail | SpfResult::SoftFail | SpfResult::Neutral => Ok(false),
+                    SpfResult::TempError => Err(SpfResult::TempError),
+                    SpfResult::PermError | SpfResult::None => Err(SpfResult::PermError),
+                }
+            }
+            Mechanism::A(spec, cidr) => {
+                self.count_lookup()?;
+                let target = self.optional_target(spec.as_deref(), domain)?;
+                let addresses = self.lookup_ip(&target).await?;
+                Ok(addresses.into_iter().any(|address| {
+                    matches(address, if address.is_ipv4() { cidr.v4 } else { cidr.v6 })
+                }))
+            }
+            Mechanism::Mx(spec, cidr) => {
+                self.count_lookup()?;
+                let target = self.optional_target(spec.as_deref(), domain)?;
+                let exchangers = self
+                    .resolver
+                    .lookup_mx(&target)
+                    .await
+                    .map_err(|_| SpfResult::TempError)?;
+                self.count_void(exchangers.is_empty())?;
+                if exchangers.len() > MAX_LOOKUPS {
+                    return Err(SpfResult::PermError);
+                }
+
+                for exchanger in exchangers {
+                    let addresses = self.lookup_ip(&exchanger).await?;
+                    if addresses.into_iter().any(|address| {
+                        matches(address, if address.is_ipv4() { cidr.v4 } else { cidr.v6 })
+                    }) {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Mechanism::Ptr(spec) => {
+                self.count_lookup()?;
+                let target = self.optional_target(spec.as_deref(), domain)?;
+                Ok(self.validated_name_in(&target).await)
+            }
+            Mechanism::Ip4(address, length) => Ok(matches(IpAddr::V4(*address), *length)),
+            Mechanism::Ip6(address, length) => Ok(matches(IpAddr::V6(*address), *length)),
+            Mechanism::Exists(spec) => {
+                self.count_lookup()?;
+                let target = self.target(spec, domain)?;
+                let addresses = self.lookup_ip(&target).await?;
+                Ok(addresses.iter().any(IpAddr::is_ipv4))
+            }
+        }
+    }
+
+    /// Check whether the client has a name in `target` that resolves back to its address, for the
+    /// `ptr` mechanism.
+    ///
+    /// Failed lookups do not match, rather than failing the check.
+    ///
+    /// [RFC 7208 section 5.5](https://www.rfc-editor.org/rfc/rfc7208.html#section-5.5).
+    async fn validated_name_in(&self, target: &str) -> bool {
+        let ip = self.context.ip;
+        let names = self.resolver.lookup_ptr(ip).await.unwrap_or_default();
+
+        for name in names.iter().take(MAX_LOOKUPS) {
+            let is_in_target = name.eq_ignore_ascii_case(target)
+                || name
+                    .len()
+                    .checked_sub(target.len() + 1)
+                    .and_then(|start| name.get(start..))
+                    .is_some_and(|suffix| {
+                        suffix.starts_with('.') && suffix[1..].eq_ignore_ascii_case(target)
+                    });
+            if is_in_target
+                && self.resolver.lookup_ip(name).await.is_ok_and(|addresses| {
+                    addresses.iter().any(|address| address.to_canonical() == ip)
+                })
+            {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Look up the addresses of `name`, counting it as a void lookup if it has none.
+    ///
+    /// # Errors
+    ///
+    /// - [`SpfResult::TempError`] if the lookup failed.
+    /// - [`SpfResult::PermError`] if too many lookups found nothing.
+    async fn lookup_ip(&mut self, name: &str) -> Result<Vec<IpAddr>, SpfResult> {
+        let addresses = self
+            .resolver
+            .lookup_ip(name)
+            .await
+            .map_err(|_| SpfResult::TempError)?;
+        self.count_void(addresses.is_empty())?;
+
+        Ok(addresses
+            .into_iter()
+            .map(|address| address.to_canonical())
+            .collect())
+    }
+
+    /// Count a lookup made by a mechanism or modifier.
+    ///
+    /// # Errors
+    ///
+    /// - [`SpfResult::PermError`] past [`MAX_LOOKUPS`].
+    const fn count_lookup(&mut self) -> Result<(), SpfResult> {
+        self.lookups += 1;
+        if self.lookups > MAX_LOOKUPS {
+            return Err(SpfResult::PermError);
+        }
+        Ok(())
+    }
+
+    /// Count a lookup that found nothing, if `is_void`.
+    ///
+    /// # Errors
+    ///
+    /// - [`SpfResult::PermError`] past [`MAX_VOID_LOOKUPS`].
+    const fn count_void(&mut self, is_void: bool) -> Result<(), SpfResult> {
+        if is_void {
+            self.void_lookups += 1;
+            if self.void_lookups > MAX_VOID_LOOKUPS {
+                return Err(SpfResult::PermError);
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand the domain-spec `spec` of the record of `domain` into the domain to look up.
+    ///
+    /// Names longer than a domain name can be are shortened by removing labels from the left.
+    ///
+    /// # Errors
+    ///
+    /// - [`SpfResult::PermError`] if `spec` is invalid.
+    fn target(&self, spec: &str, domain: &str) -> Result<String, SpfResult> {
+        let mut target = macros::expand(spec, domain, self.context).ok_or(SpfResult::PermError)?;
+
+        while target.len() > max_lengths::DOMAIN {
+            match target.split_once('.') {
+                Some((_, rest)) => target = rest.to_owned(),
+                None => return Err(SpfResult::PermError),
+            }
+        }
+
+        Ok(target)
+    }
+
+    /// Expand `spec` like [`Self::target`], or use `domain` itself if there is none.
+    ///
+    /// # Errors
+    ///
+    /// - [`SpfResult::PermError`] if `spec` is invalid.
+    fn optional_target(&self, spec: Option<&str>, domain: &str) -> Result<String, SpfResult> {
+        spec.map_or_else(|| Ok(domain.to_owned()), |spec| self.target(spec, domain))
+    }
+}
+
+/// Check whether `domain` is a multi-label domain name that can have an SPF record.
+///
+/// [RFC 7208 section 4.3](https://www.rfc-editor.org/rfc/rfc7208.html#section-4.3).
+fn is_domain(domain: &str) -> bool {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+
+    domain.len() <= max_lengths::DOMAIN
+        && domain.contains('.')
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63)
+}
@@ -0,0 +1,275 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Parses SPF records into their terms.
+//!
+//! See [`Record`].
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use super::{macros, SpfResult};
+
+/// An SPF record, as published in a `TXT` record that starts with `v=spf1`.
+///
+/// [RFC 7208 section 4.6](https://www.rfc-editor.org/rfc/rfc7208.html#section-4.6).
+#[derive(PartialEq, Eq, Debug)]
+pub struct Record {
+    /// The directives of the record, in the order they are evaluated.
+    pub directives: Vec<Directive>,
+    /// The domain-spec of the `redirect` modifier, if any.
+    pub redirect: Option<String>,
+}
+
+impl Record {
+    /// Check whether the text of a `TXT` record is an SPF record.
+    ///
+    /// [RFC 7208 section 4.5](https://www.rfc-editor.org/rfc/rfc7208.html#section-4.5).
+    pub fn is_spf(text: &str) -> bool {
+        text.get(..6)
+            .is_some_and(|version| version.eq_ignore_ascii_case("v=spf1"))
+            && matches!(text.as_bytes().get(6), None | Some(b' '))
+    }
+
+    /// Parse an SPF record, which must start with `v=spf1`, see [`Self::is_spf`].
+    ///
+    /// Unknown modifiers are ignored, as are explanations given with `exp`.
+    ///
+    /// # Errors
+    ///
+    /// - [`SpfResult::PermError`] if any term of the record is invalid, or if a modifier is given
+    ///   twice.
+    pub fn parse(text: &str) -> Result<Self, SpfResult> {
+        let mut record = Self {
+            directives: Vec::new(),
+            redirect: None,
+        };
+        let mut has_exp = false;
+
+        for term in text[6..].split(' ').filter(|term| !term.is_empty()) {
+            if let Some((name, value)) = term.split_once('=').filter(|(name, _)| is_name(name)) {
+                if !macros::is_valid(value) {
+                    return Err(SpfResult::PermError);
+                }
+
+                if name.eq_ignore_ascii_case("redirect") {
+                    if record.redirect.replace(value.to_owned()).is_some() {
+                        return Err(SpfResult::PermError);
+                    }
+                } else if name.eq_ignore_ascii_case("exp") {
+                    if has_exp {
+                        return Err(SpfResult::PermError);
+                    }
+                    has_exp = true;
+                }
+            } else {
+                record.directives.push(Directive::parse(term)?);
+            }
+        }
+
+        Ok(record)
+    }
+}
+
+/// A mechanism of an SPF record, along with the result given when it matches.
+///
+/// [RFC 7208 section 4.6.2](https://www.rfc-editor.org/rfc/rfc7208.html#section-4.6.2).
+#[derive(PartialEq, Eq, Debug)]
+pub struct Directive {
+    /// The result given when the mechanism matches.
+    pub qualifier: SpfResult,
+    /// What the client is matched against.
+    pub mechanism: Mechanism,
+}
+
+impl Directive {
+    /// Parse a directive, such as `-all` or `ip4:192.0.2.0/24`.
+    ///
+    /// # Errors
+    ///
+    /// - [`SpfResult::PermError`] if the mechanism is unknown or its arguments are invalid.
+    fn parse(term: &str) -> Result<Self, SpfResult> {
+        let (qualifier, term) = match term.as_bytes().first() {
+            Some(b'+') => (SpfResult::Pass, &term[1..]),
+            Some(b'-') => (SpfResult::Fail, &term[1..]),
+            Some(b'~') => (SpfResult::SoftFail, &term[1..]),
+            Some(b'?') => (SpfResult::Neutral, &term[1..]),
+            _ => (SpfResult::Pass, term),
+        };
+        let (name, arguments) = term.split_at(term.find([':', '/']).unwrap_or(term.len()));
+
+        let mechanism = match name.to_ascii_lowercase().as_str() {
+            "all" if arguments.is_empty() => Mechanism::All,
+            "include" => Mechanism::Include(domain_spec(arguments)?),
+            "exists" => Mechanism::Exists(domain_spec(arguments)?),
+            "ptr" => Mechanism::Ptr(optional_domain_spec(arguments)?),
+            "a" | "mx" => {
+                let (arguments, cidr) = Cidr::parse(arguments)?;
+                let domain = optional_domain_spec(arguments)?;
+
+                if name.eq_ignore_ascii_case("a") {
+                    Mechanism::A(domain, cidr)
+                } else {
+                    Mechanism::Mx(domain, cidr)
+                }
+            }
+            "ip4" => {
+                let (address, length) = network(arguments, 32)?;
+                Mechanism::Ip4(address.parse().map_err(|_| SpfResult::PermError)?, length)
+            }
+            "ip6" => {
+                let (address, length) = network(arguments, 128)?;
+                Mechanism::Ip6(address.parse().map_err(|_| SpfResult::PermError)?, length)
+            }
+            _ => return Err(SpfResult::PermError),
+        };
+
+        Ok(Self {
+            qualifier,
+            mechanism,
+        })
+    }
+}
+
+/// What a directive of an SPF record matches the client against.
+///
+/// [RFC 7208 section 5](https://www.rfc-editor.org/rfc/rfc7208.html#section-5).
+#[derive(PartialEq, Eq, Debug)]
+pub enum Mechanism {
+    /// Matches every client.
+    All,
+    /// Matches if the SPF record of the domain passes.
+    Include(String),
+    /// Matches the addresses of the domain, or of the current domain if `None`.
+    A(Option<String>, Cidr),
+    /// Matches the addresses of the mail exchangers of the domain, or of the current domain if
+    /// `None`.
+    Mx(Option<String>, Cidr),
+    /// Matches if the client's address has a validated name in the domain, or in the current
+    /// domain if `None`.
+    Ptr(Option<String>),
+    /// Matches the IPv4 addresses of the network.
+    Ip4(Ipv4Addr, u8),
+    /// Matches the IPv6 addresses of the network.
+    Ip6(Ipv6Addr, u8),
+    /// Matches if the domain has an IPv4 address.
+    Exists(String),
+}
+
+/// The lengths of the prefixes that the addresses of `a` and `mx` are compared with.
+///
+/// [RFC 7208 section 5.6](https://www.rfc-editor.org/rfc/rfc7208.html#section-5.6).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Cidr {
+    /// The length of the prefix of IPv4 addresses.
+    pub v4: u8,
+    /// The length of the prefix of IPv6 addresses.
+    pub v6: u8,
+}
+
+impl Cidr {
+    /// Split the `/24//64` suffix off of the arguments of a mechanism, if it has one.
+    ///
+    /// # Errors
+    ///
+    /// - [`SpfResult::PermError`] if a length is invalid.
+    fn parse(arguments: &str) -> Result<(&str, Self), SpfResult> {
+        let mut cidr = Self { v4: 32, v6: 128 };
+        let mut arguments = arguments;
+
+        if let Some((rest, length)) = arguments.rsplit_once("//") {
+            if length.bytes().all(|byte| byte.is_ascii_digit()) {
+                cidr.v6 = prefix_length(length, 128)?;
+                arguments = rest;
+            }
+        }
+        if let Some((rest, length)) = arguments.rsplit_once('/') {
+            if length.bytes().all(|byte| byte.is_ascii_digit()) {
+                cidr.v4 = prefix_length(length, 32)?;
+                arguments = rest;
+            }
+        }
+
+        Ok((arguments, cidr))
+    }
+}
+
+/// Check whether `name` is the name of a modifier.
+///
+/// `name = ALPHA *( ALPHA / DIGIT / "-" / "_" / "." )`
+fn is_name(name: &str) -> bool {
+    name.as_bytes().first().is_some_and(u8::is_ascii_alphabetic)
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'))
+}
+
+/// Parse the `:domain-spec` argument of a mechanism.
+///
+/// # Errors
+///
+/// - [`SpfResult::PermError`] if it is missing or invalid.
+fn domain_spec(arguments: &str) -> Result<String, SpfResult> {
+    optional_domain_spec(arguments)?.ok_or(SpfResult::PermError)
+}
+
+/// Parse the `:domain-spec` argument of a mechanism, which may be left out.
+///
+/// # Errors
+///
+/// - [`SpfResult::PermError`] if it is invalid.
+fn optional_domain_spec(arguments: &str) -> Result<Option<String>, SpfResult> {
+    if arguments.is_empty() {
+        return Ok(None);
+    }
+
+    match arguments.strip_prefix(':') {
+        Some(spec) if !spec.is_empty() && macros::is_valid(spec) => Ok(Some(spec.to_owned())),
+        _ => Err(SpfResult::PermError),
+    }
+}
+
+/// Split the `:address/length` argument of `ip4` or `ip6` into the address and the length of the
+/// prefix, which is `max` if it is left out.
+///
+/// # Errors
+///
+/// - [`SpfResult::PermError`] if the argument is missing or the length is invalid.
+fn network(arguments: &str, max: u8) -> Result<(&str, u8), SpfResult> {
+    let network = arguments.strip_prefix(':').ok_or(SpfResult::PermError)?;
+
+    match network.split_once('/') {
+        Some((address, length)) => Ok((address, prefix_length(length, max)?)),
+        None => Ok((network, max)),
+    }
+}
+
+/// Parse the length of a prefix, which must be at most `max` and written without leading zeroes.
+///
+/// # Errors
+///
+/// - [`SpfResult::PermError`] if the length is invalid.
+fn prefix_length(length: &str, max: u8) -> Result<u8, SpfResult> {
+    if length.is_empty() || (length.len() > 1 && length.starts_with('0')) {
+        return Err(SpfResult::PermError);
+    }
+
+    length
+        .parse()
+        .ok()
+        .filter(|&length| length <= max)
+        .ok_or(SpfResult::PermError)
+}
@@ -0,0 +1,326 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use super::*;
+
+/// Resolves names from tables, failing lookups of names that start with `fail.`.
+#[derive(Default)]
+struct Stub {
+    /// The `TXT` records of each name.
+    txt: HashMap<&'static str, Vec<&'static str>>,
+    /// The addresses of each name.
+    ip: HashMap<&'static str, Vec<IpAddr>>,
+    /// The mail exchangers of each name.
+    mx: HashMap<&'static str, Vec<&'static str>>,
+}
+
+impl Stub {
+    /// Publish `record` as a `TXT` record of `name`.
+    fn txt(mut self, name: &'static str, record: &'static str) -> Self {
+        self.txt.entry(name).or_default().push(record);
+        self
+    }
+
+    /// Give `name` the address `ip`.
+    fn ip(mut self, name: &'static str, ip: impl Into<IpAddr>) -> Self {
+        self.ip.entry(name).or_default().push(ip.into());
+        self
+    }
+
+    /// Give `name` the mail exchanger `exchanger`.
+    fn mx(mut self, name: &'static str, exchanger: &'static str) -> Self {
+        self.mx.entry(name).or_default().push(exchanger);
+        self
+    }
+}
+
+/// Fail lookups of names that start with `fail.`.
+fn fail_lookup(name: &str) -> io::Result<()> {
+    if name.starts_with("fail.") {
+        return Err(io::Error::other("lookup failed"));
+    }
+    Ok(())
+}
+
+impl Resolver for Stub {
+    async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        fail_lookup(name)?;
+        Ok(self.ip.get(name).cloned().unwrap_or_default())
+    }
+
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+        fail_lookup(name)?;
+        Ok(self
+            .txt
+            .get(name)
+            .map(|records| records.iter().map(ToString::to_string).collect())
+            .unwrap_or_default())
+    }
+
+    async fn lookup_mx(&self, name: &str) -> io::Result<Vec<String>> {
+        fail_lookup(name)?;
+        Ok(self
+            .mx
+            .get(name)
+            .map(|exchangers| exchangers.iter().map(ToString::to_string).collect())
+            .unwrap_or_default())
+    }
+}
+
+/// The address of the client in most tests.
+const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+/// Check `sender` for [`CLIENT`] with `resolver`.
+async fn check(resolver: &Stub, sender: &str) -> SpfResult {
+    let domain = sender.rsplit_once('@').map_or(sender, |(_, domain)| domain);
+    check_host(
+        resolver,
+        IpAddr::V4(CLIENT),
+        domain,
+        sender,
+        "client.example.org",
+    )
+    .await
+}
+
+#[test]
+fn test_record_parsing() {
+    use record::{Cidr, Directive, Mechanism, Record};
+
+    assert!(Record::is_spf("v=spf1 -all"));
+    assert!(Record::is_spf("V=SPF1"));
+    assert!(!Record::is_spf("v=spf10 -all"));
+    assert!(!Record::is_spf("google-site-verification=abc"));
+
+    assert_eq!(
+        Record::parse("v=spf1 ip4:192.0.2.0/24 a:%{d}/28//64 ~mx -all redirect=example.net"),
+        Ok(Record {
+            directives: vec![
+                Directive {
+                    qualifier: SpfResult::Pass,
+                    mechanism: Mechanism::Ip4(Ipv4Addr::new(192, 0, 2, 0), 24),
+                },
+                Directive {
+                    qualifier: SpfResult::Pass,
+                    mechanism: Mechanism::A(Some("%{d}".to_owned()), Cidr { v4: 28, v6: 64 }),
+                },
+                Directive {
+                    qualifier: SpfResult::SoftFail,
+                    mechanism: Mechanism::Mx(None, Cidr { v4: 32, v6: 128 }),
+                },
+                Directive {
+                    qualifier: SpfResult::Fail,
+                    mechanism: Mechanism::All,
+                },
+            ],
+            redirect: Some("example.net".to_owned()),
+        })
+    );
+
+    // Unknown modifiers are ignored.
+    assert!(Record::parse("v=spf1 foo=bar -all").is_ok());
+
+    for invalid in [
+        "v=spf1 ip4:192.0.2.0/33",
+        "v=spf1 ip6:192.0.2.1",
+        "v=spf1 foo",
+        "v=spf1 include",
+        "v=spf1 a:%{x}",
+        "v=spf1 redirect=a.example redirect=b.example",
+        "v=spf1 exp=a.example exp=b.example",
+    ] {
+        assert_eq!(
+            Record::parse(invalid),
+            Err(SpfResult::PermError),
+            "{invalid:?}"
+        );
+    }
+}
+
+#[test]
+fn test_macro_expansion() {
+    let context = macros::Context {
+        sender: "strong-bad@email.example.com",
+        ip: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 3)),
+        helo: "client.example.org",
+    };
+    let expand = |spec| macros::expand(spec, "email.example.com", context);
+
+    // Examples from <https://www.rfc-editor.org/rfc/rfc7208.html#section-7.4>.
+    assert_eq!(
+        expand("%{s}").as_deref(),
+        Some("strong-bad@email.example.com")
+    );
+    assert_eq!(expand("%{o}").as_deref(), Some("email.example.com"));
+    assert_eq!(expand("%{d}").as_deref(), Some("email.example.com"));
+    assert_eq!(expand("%{d4}").as_deref(), Some("email.example.com"));
+    assert_eq!(expand("%{d3}").as_deref(), Some("email.example.com"));
+    assert_eq!(expand("%{d2}").as_deref(), Some("example.com"));
+    assert_eq!(expand("%{d1}").as_deref(), Some("com"));
+    assert_eq!(expand("%{dr}").as_deref(), Some("com.example.email"));
+    assert_eq!(expand("%{d2r}").as_deref(), Some("example.email"));
+    assert_eq!(expand("%{l}").as_deref(), Some("strong-bad"));
+    assert_eq!(expand("%{l-}").as_deref(), Some("strong.bad"));
+    assert_eq!(expand("%{lr}").as_deref(), Some("strong-bad"));
+    assert_eq!(expand("%{lr-}").as_deref(), Some("bad.strong"));
+    assert_eq!(expand("%{l1r-}").as_deref(), Some("strong"));
+    assert_eq!(
+        expand("%{ir}.%{v}._spf.%{d2}").as_deref(),
+        Some("3.2.0.192.in-addr._spf.example.com")
+    );
+    assert_eq!(
+        expand("%{lr-}.lp._spf.%{d2}").as_deref(),
+        Some("bad.strong.lp._spf.example.com")
+    );
+    assert_eq!(
+        expand("%{h}%%%_%-").as_deref(),
+        Some("client.example.org% %20")
+    );
+
+    let context = macros::Context {
+        ip: IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0xcb01)),
+        ..context
+    };
+    assert_eq!(
+        macros::expand("%{ir}.%{v}._spf.%{d2}", "email.example.com", context).as_deref(),
+        Some(
+            "1.0.b.c.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6._spf.example.com"
+        )
+    );
+
+    for invalid in ["%", "%{", "%{s", "%{x}", "%{c}", "%{s0}", "%a"] {
+        assert!(!macros::is_valid(invalid), "{invalid:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_check_host() {
+    let resolver = Stub::default()
+        .txt("example.com", "v=spf1 ip4:192.0.2.0/24 -all")
+        .txt("example.com", "google-site-verification=abc")
+        .txt("soft.example.com", "v=spf1 ip4:198.51.100.1 ~all")
+        .txt("neutral.example.com", "v=spf1 ?all")
+        .txt("empty.example.com", "v=spf1")
+        .txt("include.example.com", "v=spf1 include:example.com -all")
+        .txt("redirect.example.com", "v=spf1 redirect=example.com")
+        .txt(
+            "void-redirect.example.com",
+            "v=spf1 redirect=none.example.com",
+        )
+        .txt("a.example.com", "v=spf1 a a:mail.example.net/24 -all")
+        .txt("mx.example.com", "v=spf1 mx -all")
+        .txt(
+            "exists.example.com",
+            "v=spf1 exists:%{ir}.%{l}._spf.%{d} -all",
+        )
+        .txt("double.example.com", "v=spf1 -all")
+        .txt("double.example.com", "v=spf1 +all")
+        .txt("invalid.example.com", "v=spf1 ip4:300.0.0.1 -all")
+        .txt("temp.example.com", "v=spf1 include:fail.example.com -all")
+        .ip("mail.example.net", Ipv4Addr::new(192, 0, 2, 200))
+        .mx("mx.example.com", "mx1.example.com")
+        .mx("mx.example.com", "mx2.example.com")
+        .ip("mx2.example.com", CLIENT)
+        .ip(
+            "1.2.0.192.smith._spf.exists.example.com",
+            Ipv4Addr::LOCALHOST,
+        );
+
+    for (sender, result) in [
+        ("smith@example.com", SpfResult::Pass),
+        ("smith@soft.example.com", SpfResult::SoftFail),
+        ("smith@neutral.example.com", SpfResult::Neutral),
+        ("smith@empty.example.com", SpfResult::Neutral),
+        ("smith@include.example.com", SpfResult::Pass),
+        ("smith@redirect.example.com", SpfResult::Pass),
+        ("smith@void-redirect.example.com", SpfResult::PermError),
+        ("smith@a.example.com", SpfResult::Pass),
+        ("smith@mx.example.com", SpfResult::Pass),
+        ("smith@exists.example.com", SpfResult::Pass),
+        ("jones@exists.example.com", SpfResult::Fail),
+        ("smith@double.example.com", SpfResult::PermError),
+        ("smith@invalid.example.com", SpfResult::PermError),
+        ("smith@temp.example.com", SpfResult::TempError),
+        ("smith@fail.example.com", SpfResult::TempError),
+        ("smith@none.example.com", SpfResult::None),
+        ("smith@localhost", SpfResult::None),
+    ] {
+        assert_eq!(check(&resolver, sender).await, result, "{sender:?}");
+    }
+
+    // Tests that other clients fail.
+    assert_eq!(
+        check_host(
+            &resolver,
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 2)),
+            "example.com",
+            "smith@example.com",
+            "client.example.org",
+        )
+        .await,
+        SpfResult::Fail
+    );
+    // Tests that IPv4-mapped addresses are checked as IPv4.
+    assert_eq!(
+        check_host(
+            &resolver,
+            IpAddr::V6(CLIENT.to_ipv6_mapped()),
+            "example.com",
+            "smith@example.com",
+            "client.example.org",
+        )
+        .await,
+        SpfResult::Pass
+    );
+}
+
+#[tokio::test]
+async fn test_lookup_limits() {
+    let resolver = Stub::default()
+        .txt("loop.example.com", "v=spf1 include:loop.example.com -all")
+        .txt(
+            "void.example.com",
+            "v=spf1 a:a.example.com a:b.example.com a:c.example.com -all",
+        )
+        .txt(
+            "nine.example.com",
+            "v=spf1 a a a a a a a a a ip4:192.0.2.1 -all",
+        )
+        .txt(
+            "eleven.example.com",
+            "v=spf1 a a a a a a a a a a a ip4:192.0.2.1 -all",
+        )
+        .ip("nine.example.com", Ipv4Addr::new(198, 51, 100, 1))
+        .ip("eleven.example.com", Ipv4Addr::new(198, 51, 100, 1));
+
+    for (sender, result) in [
+        ("smith@loop.example.com", SpfResult::PermError),
+        ("smith@void.example.com", SpfResult::PermError),
+        ("smith@nine.example.com", SpfResult::Pass),
+        ("smith@eleven.example.com", SpfResult::PermError),
+    ] {
+        assert_eq!(check(&resolver, sender).await, result, "{sender:?}");
+    }
+}
@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! The format of the entries in a [`super::Spool`].
+//!
+//! Each entry starts with lines of `Name: value` fields that hold the envelope and the details of
+//! the session, followed by an empty line and the data of the message as-is.
+
+use std::{fmt::Write as _, io, net::SocketAddr};
+
+use ascii::{AsciiStr, AsciiString};
+use bytes::Bytes;
+
+use super::SpoolId;
+use crate::{
+    message::{
+        envelope::{Envelope, Notify, Recipient, Ret},
+        ContentHash, Size,
+    },
+    session::{SessionId, SessionInfo, TlsInfo},
+    time::{from_millis, millis},
+    Message,
+};
+
+/// Format `message` as an entry.
+///
+/// # Errors
+///
+/// - [`io::ErrorKind::InvalidInput`] if the user that the client authenticated as contains a line
+///   ending, which cannot be kept in a field.
+pub(super) fn encode(message: &Message) -> io::Result<Vec<u8>> {
+    let session = message.session();
+    let envelope = message.envelope();
+    let mut text = String::new();
+
+    let _ = writeln!(text, "Session: {}", session.id());
+    let _ = writeln!(text, "Local: {}", session.local_addr());
+    let _ = writeln!(text, "Peer: {}", session.peer_addr());
+    if let Some(helo) = session.helo() {
+        let verb = if session.is_extended() {
+            "Ehlo"
+        } else {
+            "Helo"
+        };
+        let _ = writeln!(text, "{verb}: {helo}");
+    }
+    if let Some(tls) = session.tls() {
+        let _ = writeln!(text, "Tls: {} {}", tls.protocol(), tls.cipher());
+    }
+    if let Some(user) = session.authenticated_user() {
+        if user.contains(['\r', '\n']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "authenticated user contains a line ending",
+            ));
+        }
+        let _ = writeln!(text, "User: {user}");
+    }
+    let _ = writeln!(text, "Started: {}", millis(message.started_at()));
+    let _ = writeln!(text, "Received: {}", millis(message.received_at()));
+    let size = message.size();
+    let _ = writeln!(text, "Size: {} {}", size.header(), size.body());
+    let _ = writeln!(text, "Hash: {}", message.hash());
+    let _ = writeln!(
+        text,
+        "From: {}",
+        envelope.reverse_path().map_or("", AsciiStr::as_str)
+    );
+    if let Some(ret) = envelope.ret() {
+        let _ = writeln!(text, "Ret: {ret}");
+    }
+    if let Some(envid) = envelope.envid() {
+        let _ = writeln!(text, "Envid: {envid}");
+    }
+    for recipient in envelope.accepted() {
+        let _ = write!(text, "To: {}", recipient.forward_path());
+        if let Some(notify) = recipient.notify() {
+            let _ = write!(text, " NOTIFY={notify}");
+        }
+        if let Some(orcpt) = recipient.orcpt() {
+            let _ = write!(text, " ORCPT={orcpt}");
+        }
+        text.push('\n');
+    }
+    text.push('\n');
+
+    let mut bytes = text.into_bytes();
+    bytes.extend_from_slice(message.data());
+
+    Ok(bytes)
+}
+
+/// Parse the entry `id` from `bytes`, or `None` if it is malformed.
+pub(super) fn decode(id: SpoolId, bytes: Vec<u8>) -> Option<Message> {
+    let end = bytes.windows(2).position(|window| window == b"\n\n")?;
+    let text = std::str::from_utf8(&bytes[..=end]).ok()?;
+
+    let mut session_id = None;
+    let mut addresses = (None, None);
+    let mut helo = None;
+    let mut extended = false;
+    let mut tls = None;
+    let mut user = None;
+    let mut times = (None, None);
+    let mut size = None;
+    let mut hash = None;
+    let mut reverse_path = None;
+    let mut ret = None;
+    let mut envid = None;
+    let mut recipients = Vec::new();
+
+    for line in text.lines() {
+        let (name, value) = line.split_once(": ")?;
+        match name {
+            "Session" => session_id = u64::from_str_radix(value, 16).ok().map(SessionId::from_u64),
+            "Local" => addresses.0 = Some(value.parse::<SocketAddr>().ok()?),
+            "Peer" => addresses.1 = Some(value.parse::<SocketAddr>().ok()?),
+            "Helo" | "Ehlo" => {
+                helo = Some(AsciiString::from_ascii(value).ok()?);
+                extended = name == "Ehlo";
+            }
+            "Tls" => {
+                let (protocol, cipher) = value.split_once(' ')?;
+                tls = Some(TlsInfo::new(protocol, cipher));
+            }
+            "User" => user = Some(value.to_owned()),
+            "Started" => times.0 = Some(from_millis(value)?),
+            "Received" => times.1 = Some(from_millis(value)?),
+            "Size" => {
+                let (header, body) = value.split_once(' ')?;
+                size = Some(Size::new(header.parse().ok()?, body.parse().ok()?));
+            }
+            "Hash" => hash = Some(ContentHash::parse(value)?),
+            "From" => reverse_path = Some(AsciiString::from_ascii(value).ok()?),
+            "Ret" => ret = Some(Ret::parse(value)?),
+            "Envid" => envid = Some(AsciiString::from_ascii(value).ok()?),
+            "To" => recipients.push(recipient(value)?),
+            _ => return None,
+        }
+    }
+
+    let session = SessionInfo {
+        id: session_id?,
+        helo,
+        extended,
+        tls,
+        authenticated_user: user,
+        ..SessionInfo::new(addresses.0?, addresses.1?)
+    };
+    let envelope = Envelope {
+        recipients,
+        ret,
+        envid,
+        ..Envelope::new(reverse_path.filter(|path| !path.is_empty()))
+    };
+    let data = Bytes::from(bytes).slice(end + 2..);
+
+    Some(
+        Message::new(envelope, session, times.0?, data)
+            .with_received_at(times.1?)
+            .with_size(size?)
+            .with_hash(hash?)
+            .with_spool_id(id),
+    )
+}
+
+/// Parse a recipient from the value of a `To` field, its forward-path followed by its `NOTIFY`
+/// and `ORCPT` parameters.
+fn recipient(value: &str) -> Option<Recipient> {
+    let mut words = value.split(' ');
+    let mut recipient = Recipient::new(AsciiString::from_ascii(words.next()?).ok()?);
+
+    for word in words {
+        match word.split_once('=')? {
+            ("NOTIFY", notify) => recipient.notify = Some(Notify::parse(notify)?),
+            ("ORCPT", orcpt) => recipient.orcpt = Some(AsciiString::from_ascii(orcpt).ok()?),
+            _ => return None,
+        }
+    }
+
+    Some(recipient)
+}
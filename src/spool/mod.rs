@@ -0,0 +1,258 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! A spool on disk that keeps every accepted message until the consumer has processed it, so that
+//! mail that the client was told is received is not lost if the server crashes.
+//!
+//! See [`Spool`], and [`crate::ServerConfig::spool`].
+
+mod entry;
+#[cfg(test)]
+mod test;
+
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use tokio::sync::mpsc;
+
+use crate::{file, handler::Response, session::SessionInfo, Message, ServerConfig};
+
+/// The directory of the spool that entries are written into before they are complete.
+const TEMPORARY: &str = "tmp";
+/// The directory of the spool that complete entries are kept in.
+const QUEUE: &str = "queue";
+
+/// Keeps accepted messages on disk until the consumer has processed them.
+///
+/// With [`crate::ServerConfig::spool`], each message that passes the checks of the server is
+/// written to the spool, and synced to disk, before it is handed to the handler, and the client is
+/// only told that it was received once it is. Messages that the handler does not accept are taken
+/// out of the spool again. The consumer takes each message out with [`Self::remove`] once it has
+/// processed it, and any that are left when the server starts again can be read back with
+/// [`Self::pending`]. [`crate::listen_channel`] and [`crate::serve`] send them to the consumer
+/// as they start.
+///
+/// Each entry holds the envelope, the data, and the details of the session that a message was
+/// received through, but not the results of the checks made on it, such as its SPF or DMARC
+/// verdict, which are left in its header fields. Messages received with
+/// [`crate::listen_streaming`] are not spooled.
+///
+/// Entries are written to a temporary directory and moved into place once they are synced, so an
+/// entry is either complete or not there at all.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{spool::Spool, ServerConfig, Shutdown};
+/// # use tokio::net::TcpListener;
+/// #
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let spool = Spool::open("/var/spool/smtp_gateway")?;
+/// let config = ServerConfig::builder().spool(spool.clone()).build()?;
+/// let (sessions, mut messages) = smtp_gateway::listen_channel(
+///     TcpListener::bind("127.0.0.1:2525").await?,
+///     config,
+///     Shutdown::new(),
+/// );
+///
+/// while let Some(message) = messages.recv().await {
+///     // Deliver the message, then take it out of the spool.
+///     if let Some(id) = message.spool_id() {
+///         spool.remove(id).await?;
+///     }
+/// }
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Spool {
+    /// The directory that the spool is kept in.
+    path: PathBuf,
+}
+
+impl Spool {
+    /// Open the spool kept in the directory at `path`, creating it if it does not exist, and
+    /// clearing out the entries that were not complete when the server last stopped.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the directory cannot be created or read.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(path.join(QUEUE))?;
+
+        // Anything left here was never acknowledged to the client, so it can be thrown away.
+        let temporary = path.join(TEMPORARY);
+        match fs::remove_dir_all(&temporary) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+            _ => fs::create_dir(&temporary)?,
+        }
+
+        Ok(Self { path })
+    }
+
+    /// Get the directory that the spool is kept in.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `message` to the spool and sync it to disk, returning the [`SpoolId`] it is kept
+    /// under.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the entry cannot be written, if there is already an entry with the same
+    ///   [`SpoolId`], or if the user that the client authenticated as contains a line ending.
+    pub async fn write(&self, message: &Message) -> io::Result<SpoolId> {
+        let id = SpoolId::generate(message);
+        let temporary = self.path.join(TEMPORARY).join(id.as_str());
+        let path = self.path.join(QUEUE).join(id.as_str());
+
+        file::write_new(&temporary, &path, &entry::encode(message)?).await?;
+
+        Ok(id)
+    }
+
+    /// Take the entry `id` out of the spool, once its message has been processed. Entries that are
+    /// not in the spool are ignored.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the entry cannot be removed.
+    pub async fn remove(&self, id: &SpoolId) -> io::Result<()> {
+        match tokio::fs::remove_file(self.path.join(QUEUE).join(id.as_str())).await {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read every message in the spool, in the order they were received, each with its
+    /// [`Message::spool_id`].
+    ///
+    /// Entries that cannot be parsed are logged and skipped, and left in the spool.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the spool or an entry cannot be read.
+    pub async fn pending(&self) -> io::Result<Vec<Message>> {
+        let mut entries = tokio::fs::read_dir(self.path.join(QUEUE)).await?;
+        let mut messages = Vec::new();
+
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(id) = entry.file_name().to_str().map(|id| SpoolId(id.to_owned())) else {
+                continue;
+            };
+            let bytes = tokio::fs::read(entry.path()).await?;
+
+            match entry::decode(id.clone(), bytes) {
+                Some(message) => messages.push(message),
+                None => println!("Skipped malformed spool entry {}", entry.path().display()),
+            }
+        }
+        messages.sort_by_key(Message::received_at);
+
+        Ok(messages)
+    }
+
+    /// Send every message in the spool into `messages`, returning how many were sent. See
+    /// [`Self::pending`].
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if the spool or an entry cannot be read, or if the receiver was dropped.
+    pub async fn replay(&self, messages: &mpsc::Sender<Message>) -> io::Result<usize> {
+        let pending = self.pending().await?;
+        let count = pending.len();
+
+        for message in pending {
+            messages
+                .send(message)
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+
+        Ok(count)
+    }
+}
+
+/// The name of an entry in a [`Spool`].
+///
+/// Made of the [`crate::session::SessionId`] that its message was received through, a value that is
+/// random for each process, and a counter of the messages spooled by it, such as
+/// `67110a4500000001-5c1d2e3f4a5b6c7d-00000003`.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpoolId(String);
+
+impl SpoolId {
+    /// Generate a new [`Self`] for `message`.
+    fn generate(message: &Message) -> Self {
+        Self(message.session().id().message_name())
+    }
+
+    /// Get the name as a string slice.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for SpoolId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Write `message` to [`ServerConfig::spool`] if there is one, keeping its [`SpoolId`] in it.
+///
+/// Returns the `451 4.3.0` reply to defer the message with if it cannot be written.
+pub(crate) async fn apply(config: &ServerConfig, message: Message) -> Result<Message, Response> {
+    let Some(spool) = config.spool() else {
+        return Ok(message);
+    };
+
+    match spool.write(&message).await {
+        Ok(id) => Ok(message.with_spool_id(id)),
+        Err(error) => {
+            println!("[{}] Spooling failed: {error}", message.session().id());
+            Err(
+                Response::parse("451 4.3.0 Could not spool message, please try again later")
+                    .expect("written in code as a valid reply"),
+            )
+        }
+    }
+}
+
+/// Take the entry `id` out of [`ServerConfig::spool`] again, for a message that the handler did
+/// not accept. Failures are logged.
+pub(crate) async fn discard(config: &ServerConfig, session: &SessionInfo, id: Option<&SpoolId>) {
+    let (Some(spool), Some(id)) = (config.spool(), id) else {
+        return;
+    };
+
+    if let Err(error) = spool.remove(id).await {
+        println!(
+            "[{}] Removing spool entry {id} failed: {error}",
+            session.id()
+        );
+    }
+}
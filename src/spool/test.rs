@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use ascii::AsciiStr;
+
+use super::*;
+use crate::{message::envelope::Ret, session::TlsInfo};
+
+/// Build a message with every detail that an entry keeps.
+fn message() -> Message {
+    Message::builder()
+        .reverse_path("smith@example.com")
+        .forward_path("jones@example.com")
+        .forward_path("green@example.com")
+        .ehlo("client.example.com")
+        .tls(TlsInfo::new("TLSv1.3", "TLS13_AES_128_GCM_SHA256"))
+        .authenticated_user("smith")
+        .started_at(UNIX_EPOCH + Duration::from_millis(1500))
+        .received_at(UNIX_EPOCH + Duration::from_millis(2500))
+        .header("Subject", "Hello")
+        .body("Hi there!\r\n\r\n.\r\n")
+        .build()
+        .expect("the message is valid")
+}
+
+#[test]
+fn test_entry() {
+    // Tests that an entry is read back as the message it was written from.
+    let id = SpoolId("0000000100000002-00000003".to_owned());
+    let message = message();
+    let encoded = entry::encode(&message).expect("the user has no line ending");
+    let decoded = entry::decode(id.clone(), encoded).expect("the entry is well formed");
+    assert_eq!(decoded, message.with_spool_id(id.clone()));
+
+    // Tests that the null reverse-path and the DSN parameters are kept.
+    let decoded = entry::decode(
+        id.clone(),
+        concat!(
+            "Session: 0000000100000002\n",
+            "Local: 127.0.0.1:25\n",
+            "Peer: 192.0.2.1:4000\n",
+            "Started: 1000\n",
+            "Received: 2000\n",
+            "Size: 0 6\n",
+            "Hash: 0000000000000000000000000000000000000000000000000000000000000000\n",
+            "From: \n",
+            "Ret: HDRS\n",
+            "To: jones@example.com NOTIFY=SUCCESS,FAILURE ORCPT=rfc822;jones@example.com\n",
+            "\n",
+            "body\r\n",
+        )
+        .as_bytes()
+        .to_vec(),
+    )
+    .expect("the entry is well formed");
+    assert!(decoded.envelope().is_null_sender());
+    assert_eq!(decoded.envelope().ret(), Some(Ret::Headers));
+    let recipient = &decoded.envelope().recipients()[0];
+    assert_eq!(
+        recipient.notify().map(|n| n.to_string()).as_deref(),
+        Some("SUCCESS,FAILURE")
+    );
+    assert_eq!(
+        recipient.orcpt().map(AsciiStr::as_str),
+        Some("rfc822;jones@example.com")
+    );
+    assert_eq!(decoded.data().as_ref(), b"body\r\n");
+
+    // Tests that unknown fields and entries without their data are malformed.
+    assert!(entry::decode(id.clone(), b"Unknown: field\n\n".to_vec()).is_none());
+    assert!(entry::decode(id, b"Session: 0000000100000002\n".to_vec()).is_none());
+}
+
+#[tokio::test]
+async fn test_spool() -> io::Result<()> {
+    let path = std::env::temp_dir().join("smtp_gateway_test_spool");
+    let _ = std::fs::remove_dir_all(&path);
+
+    // Tests that written messages are pending until they are removed.
+    let spool = Spool::open(&path)?;
+    let first = spool.write(&message()).await?;
+    let second = spool.write(&message()).await?;
+    assert_ne!(first, second);
+    let pending = spool.pending().await?;
+    assert_eq!(pending.len(), 2);
+    assert!(pending.iter().all(|message| message.spool_id().is_some()));
+
+    spool.remove(&first).await?;
+    spool.remove(&first).await?;
+    let pending = spool.pending().await?;
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].spool_id(), Some(&second));
+
+    // Tests that an entry is never replaced by another with the same name.
+    let temporary = path.join(TEMPORARY).join(second.as_str());
+    let error = file::write_new(&temporary, &path.join(QUEUE).join(second.as_str()), b"")
+        .await
+        .expect_err("the entry is already there");
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    assert!(!temporary.exists());
+
+    // Tests that reopening clears out incomplete entries but keeps complete ones, and that
+    // malformed entries are skipped.
+    std::fs::write(path.join(TEMPORARY).join("incomplete"), "Session")?;
+    std::fs::write(path.join(QUEUE).join("malformed"), "malformed")?;
+    let spool = Spool::open(&path)?;
+    assert!(!path.join(TEMPORARY).join("incomplete").exists());
+    let (sender, mut receiver) = mpsc::channel(4);
+    assert_eq!(spool.replay(&sender).await?, 1);
+    assert_eq!(
+        receiver.recv().await.and_then(|m| m.spool_id().cloned()),
+        Some(second)
+    );
+
+    let _ = std::fs::remove_dir_all(&path);
+
+    Ok(())
+}
@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Aggregate statistics of the servers that a [`crate::ServerHandle`] is given to, for consumers that
+//! render their own dashboards without a metrics stack.
+//!
+//! See [`ServerStats`], and the `metrics` feature for exporting metrics to Prometheus or another
+//! recorder instead.
+
+#[cfg(test)]
+mod test;
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// The reply code that tells the client that its credentials were not accepted.
+///
+/// [RFC 4954 section 6](https://www.rfc-editor.org/rfc/rfc4954.html#section-6).
+const AUTH_FAILED: u16 = 535;
+
+/// A snapshot of the totals of what the servers did since the [`crate::ServerHandle`] that they were
+/// given to was created, as of when it was taken with [`crate::ServerHandle::stats`].
+///
+/// Rates are averaged over [`Self::uptime`]. For rates over a shorter period, such as for a
+/// dashboard that refreshes every few seconds, see [`Self::since`].
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ServerStats {
+    /// How long the statistics were collected for.
+    uptime: Duration,
+    /// The number of TCP connections accepted.
+    connections: u64,
+    /// The number of messages that the server took responsibility for.
+    messages: u64,
+    /// The number of replies with each `4xx` or `5xx` code.
+    rejections: BTreeMap<u16, u64>,
+}
+
+impl ServerStats {
+    /// Get how long the statistics were collected for.
+    #[must_use]
+    pub const fn uptime(&self) -> Duration {
+        self.uptime
+    }
+
+    /// Get the number of TCP connections accepted, including those that were turned away before
+    /// the greeting.
+    #[must_use]
+    pub const fn connections(&self) -> u64 {
+        self.connections
+    }
+
+    /// Get the number of messages that the client was told were accepted.
+    #[must_use]
+    pub const fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    /// Get the number of replies sent in SMTP sessions with each `4xx` or `5xx` code, whether they
+    /// rejected a command, deferred it, or closed the session.
+    #[must_use]
+    pub const fn rejections(&self) -> &BTreeMap<u16, u64> {
+        &self.rejections
+    }
+
+    /// Get the number of replies sent in SMTP sessions with any `4xx` or `5xx` code.
+    #[must_use]
+    pub fn total_rejections(&self) -> u64 {
+        self.rejections.values().sum()
+    }
+
+    /// Get the number of times that a client's credentials were not accepted, meaning that a
+    /// handler replied to `AUTH` with `535`.
+    #[must_use]
+    pub fn auth_failures(&self) -> u64 {
+        self.rejections
+            .get(&AUTH_FAILED)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Get the number of TCP connections accepted per second.
+    #[must_use]
+    pub fn connection_rate(&self) -> f64 {
+        self.rate(self.connections)
+    }
+
+    /// Get the number of messages accepted per second.
+    #[must_use]
+    pub fn message_rate(&self) -> f64 {
+        self.rate(self.messages)
+    }
+
+    /// Get the number of replies with any `4xx` or `5xx` code sent per second.
+    #[must_use]
+    pub fn rejection_rate(&self) -> f64 {
+        self.rate(self.total_rejections())
+    }
+
+    /// Get what the servers did between `earlier` and `self`, as if the statistics were only
+    /// collected from when `earlier` was taken.
+    ///
+    /// `earlier` should be taken from the same [`crate::ServerHandle`] before `self` was.
+    #[must_use]
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            uptime: self.uptime.saturating_sub(earlier.uptime),
+            connections: self.connections.saturating_sub(earlier.connections),
+            messages: self.messages.saturating_sub(earlier.messages),
+            rejections: self
+                .rejections
+                .iter()
+                .map(|(&code, &count)| {
+                    let before = earlier.rejections.get(&code).copied().unwrap_or_default();
+                    (code, count.saturating_sub(before))
+                })
+                .filter(|&(_, count)| count > 0)
+                .collect(),
+        }
+    }
+
+    /// Get `count` per second of [`Self::uptime`], or zero if no time has passed.
+    #[expect(
+        clippy::cast_precision_loss,
+        reason = "counts are far below the precision of `f64`"
+    )]
+    fn rate(&self, count: u64) -> f64 {
+        let seconds = self.uptime.as_secs_f64();
+        if seconds > 0.0 {
+            count as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Counts what the servers do for [`ServerStats`], shared by the [`crate::ServerHandle`] and every
+/// session that it counts.
+#[derive(Debug)]
+pub(crate) struct Counters {
+    /// When the counting started.
+    started: Instant,
+    /// The number of TCP connections accepted.
+    connections: AtomicU64,
+    /// The number of messages that the server took responsibility for.
+    messages: AtomicU64,
+    /// The number of replies with each `4xx` or `5xx` code.
+    rejections: Mutex<BTreeMap<u16, u64>>,
+}
+
+impl Counters {
+    /// Create a new [`Self`] that starts counting now.
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            connections: AtomicU64::new(0),
+            messages: AtomicU64::new(0),
+            rejections: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Count an accepted TCP connection.
+    pub(crate) fn connection(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a message that the server took responsibility for.
+    pub(crate) fn message(&self) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count the replies with `4xx` or `5xx` codes written to the client in `written`.
+    ///
+    /// Only the last line of each reply is counted, so that each multiline reply counts once.
+    pub(crate) fn replies(&self, written: &[u8]) {
+        let mut codes = reply_codes(written)
+            .filter(|code| (400..600).contains(code))
+            .peekable();
+        if codes.peek().is_none() {
+            return;
+        }
+
+        let mut rejections = self
+            .rejections
+            .lock()
+            .expect("the lock is never held across a panic");
+        for code in codes {
+            *rejections.entry(code).or_default() += 1;
+        }
+    }
+
+    /// Take a snapshot of the counts so far.
+    pub(crate) fn snapshot(&self) -> ServerStats {
+        ServerStats {
+            uptime: self.started.elapsed(),
+            connections: self.connections.load(Ordering::Relaxed),
+            messages: self.messages.load(Ordering::Relaxed),
+            rejections: self
+                .rejections
+                .lock()
+                .expect("the lock is never held across a panic")
+                .clone(),
+        }
+    }
+}
+
+/// Get the code of each reply that ends in `written`.
+///
+/// A line that does not start with a three digit code followed by a space or its end is skipped,
+/// such as the lines of a multiline reply other than its last.
+pub(crate) fn reply_codes(written: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    reply_lines(written).map(|(code, _)| code)
+}
+
+/// Get the code and the last line of each reply that ends in `written`, without its line ending.
+///
+/// Lines are skipped as with [`reply_codes`].
+pub(crate) fn reply_lines(written: &[u8]) -> impl Iterator<Item = (u16, &[u8])> + '_ {
+    written.split(|&byte| byte == b'\n').filter_map(|line| {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let (code, rest) = line.split_at_checked(3)?;
+        let is_last = matches!(rest.first(), None | Some(b' '));
+
+        (is_last && code.iter().all(u8::is_ascii_digit)).then(|| {
+            let code = code
+                .iter()
+                .fold(0, |n, digit| n * 10 + u16::from(digit - b'0'));
+            (code, line)
+        })
+    })
+}
@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use super::*;
+
+#[test]
+fn test_reply_codes() {
+    // Tests that a multiline reply is counted once, by its last line.
+    assert_eq!(
+        reply_codes(b"250-mx.example.com\r\n250-PIPELINING\r\n250 SMTPUTF8\r\n")
+            .collect::<Vec<_>>(),
+        [250]
+    );
+    assert_eq!(
+        reply_codes(b"354 End data with <CR><LF>.<CR><LF>\r\n250\r\n").collect::<Vec<_>>(),
+        [354, 250]
+    );
+    assert_eq!(reply_codes(b"").count(), 0);
+    assert_eq!(reply_codes(b"HELO client.example.com\r\n").count(), 0);
+    assert_eq!(reply_codes(b"2500 Ok\r\n").count(), 0);
+}
+
+#[test]
+fn test_counters() {
+    let counters = Counters::new();
+    counters.connection();
+    counters.connection();
+    counters.message();
+    counters.replies(b"250 OK\r\n550 5.1.1 No such user\r\n");
+    counters.replies(b"535 5.7.8 Authentication credentials invalid\r\n");
+    counters.replies(b"550-No such user\r\n550 Really\r\n");
+
+    let stats = counters.snapshot();
+    assert_eq!(stats.connections(), 2);
+    assert_eq!(stats.messages(), 1);
+    assert_eq!(stats.rejections(), &BTreeMap::from([(535, 1), (550, 2)]));
+    assert_eq!(stats.total_rejections(), 3);
+    assert_eq!(stats.auth_failures(), 1);
+    assert!(stats.connection_rate() > 0.0);
+
+    // Tests that the difference between snapshots only holds what happened between them.
+    counters.connection();
+    counters.replies(b"421 4.3.2 Shutting down\r\n");
+    let since = counters.snapshot().since(&stats);
+    assert_eq!(since.connections(), 1);
+    assert_eq!(since.messages(), 0);
+    assert_eq!(since.rejections(), &BTreeMap::from([(421, 1)]));
+    assert_eq!(since.auth_failures(), 0);
+}
+
+#[test]
+fn test_rate() {
+    // Tests that no time passing is no rate rather than a division by zero.
+    assert!(ServerStats::default().message_rate().abs() < f64::EPSILON);
+
+    let stats = ServerStats {
+        uptime: Duration::from_secs(4),
+        connections: 10,
+        messages: 2,
+        rejections: BTreeMap::from([(451, 1), (550, 1)]),
+    };
+    assert!((stats.connection_rate() - 2.5).abs() < f64::EPSILON);
+    assert!((stats.message_rate() - 0.5).abs() < f64::EPSILON);
+    assert!((stats.rejection_rate() - 0.5).abs() < f64::EPSILON);
+}
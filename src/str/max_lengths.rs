@@ -56,10 +56,3 @@ pub const REPLY_LINE: usize = 512;
 ///
 /// [RFC 5321 § 4.5.3.1.6](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.1.6).
 pub const TEXT_LINE: usize = 1_000;
-
-/// The maximum length of a message (including both the headers and body) in bytes.
-///
-/// Given the evolution of email, this value is especially recommended to be raised.
-///
-/// [RFC 5321 § 4.5.3.1.7](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.1.7).
-pub const MESSAGE: usize = 64_000;
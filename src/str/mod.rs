@@ -74,6 +74,18 @@ impl SmtpString {
         Ok(Self { str })
     }
 
+    /// Creates a new [`Self`] like [`Self::new`], replacing each character that is not ASCII with
+    /// [`AsciiChar::Question`] rather than failing.
+    pub(crate) fn new_lossy(str: &str) -> Self {
+        let str: AsciiString = str
+            .chars()
+            .map(|char| AsciiChar::from_ascii(char).unwrap_or(AsciiChar::Question))
+            .collect();
+        let str = self::replace_endings_with_crlf(&str).into_owned();
+
+        Self { str }
+    }
+
     /// Create a [`Self`] from an [`AsciiString`].
     ///
     /// # Safety
@@ -113,7 +125,7 @@ impl Display for SmtpString {
 /// - `"\n\r"` -> `"\r\n\r\n"`
 ///
 /// If the original string does not need to be modified, this function will not allocate.
-fn replace_endings_with_crlf(string: &AsciiStr) -> Cow<AsciiStr> {
+fn replace_endings_with_crlf(string: &AsciiStr) -> Cow<'_, AsciiStr> {
     let mut output = Cow::Borrowed(string);
     let mut previous = None;
 
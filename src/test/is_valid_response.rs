@@ -32,10 +32,222 @@ pub fn server_greeting(str: &str) -> bool {
     str.starts_with("220") && smtp_line(str)
 }
 
+/// Checks if the server's opening message is the `554` reply that refuses the connection, per [RFC
+/// 5321, section 3.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-3.1).
+pub fn no_service(str: &str) -> bool {
+    str.starts_with("554") && smtp_line(str)
+}
+
 pub fn helo(str: &str) -> bool {
     smtp_line(str) && str.starts_with("250")
 }
 
+/// Checks if the server's response is a generic success (`250`), as given to `MAIL`, `RCPT`,
+/// `RSET`, `NOOP`, and the end of the data.
+pub fn ok(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("250")
+}
+
+/// Checks if the server's response to the `DATA` command is the intermediate `354` reply, per [RFC
+/// 5321, section 4.1.1.4](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.4).
+pub fn data(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("354")
+}
+
+/// Checks if the server's response is a syntax error (`500`), such as for a line that is too long.
+pub fn syntax_error(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("500")
+}
+
+/// Checks if the server's response is a syntax error in the arguments of a command (`501`).
+pub fn argument_error(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("501")
+}
+
+/// Checks if the server's response is a bad sequence of commands error (`503`).
+pub fn bad_sequence(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("503")
+}
+
+/// Checks if the server's response to a `DATA` command without any recipients is the `554` reply,
+/// per [RFC 5321, section 4.1.1.4](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.4).
+pub fn no_valid_recipients(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("554")
+}
+
+/// Checks if the server's response to the end of the data is a transaction failure (`554`).
+pub fn transaction_failed(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("554")
+}
+
+/// Checks if the server's response is a temporarily unavailable mailbox (`450`), as given to a `RCPT`
+/// command that was deferred.
+pub fn mailbox_busy(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("450")
+}
+
+/// Checks if the server's response to `MAIL FROM:<>` past
+/// [`crate::config::NullSenderPolicy::max_transactions`] is the `450` reply.
+pub fn too_many_null_senders(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("450 4.7.1")
+}
+
+/// Checks if the server's response to `MAIL` is the `550 5.1.8` reply that rejects senders at
+/// domains that do not exist, with [`crate::config::SenderDomainPolicy::Reject`].
+pub fn sender_domain_missing(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("550 5.1.8")
+}
+
+/// Checks if the server's response to `MAIL` is the `451 4.4.3` reply that defers senders whose
+/// domains could not be looked up.
+pub fn sender_domain_unverified(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("451 4.4.3")
+}
+
+/// Checks if the server's response to `RCPT` or `VRFY` is the `550 5.1.1` reply that rejects
+/// addresses without a mailbox.
+#[cfg(feature = "directory")]
+pub fn user_unknown(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("550 5.1.1")
+}
+
+/// Checks if the server's response to `RCPT` or `VRFY` is the `451 4.3.0` reply that defers
+/// addresses whose lookup failed.
+#[cfg(feature = "directory")]
+pub fn address_unverified(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("451 4.3.0")
+}
+
+/// Checks if the server's response to `VRFY` is the `252` reply given to arguments that cannot be
+/// verified.
+#[cfg(feature = "directory")]
+pub fn cannot_verify(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("252")
+}
+
+/// Checks if the server's response is a service not available error (`421`), as given when the
+/// session is being closed.
+pub fn service_unavailable(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("421")
+}
+
+/// Checks if the server's response is a local error in processing (`451`), as given when the
+/// consumer could not take responsibility for a message.
+pub fn local_error(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("451")
+}
+
+/// Checks if the server's response is an exceeded storage allocation error (`552`), such as for a
+/// message that is too large.
+pub fn exceeded_storage(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("552")
+}
+
+/// Checks if the server's response to a `RCPT` command past the recipient limit is the `452` reply,
+/// per [RFC 5321, section 4.5.3.1.10](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.1.10).
+pub fn too_many_recipients(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("452")
+}
+
+/// Checks if the server's response is a mailbox unavailable error (`550`), as given to a `MAIL` or
+/// `RCPT` command that was rejected.
+pub fn mailbox_unavailable(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("550")
+}
+
+/// Checks if the server's response is a requested action not taken error (`550`), as given to a
+/// `HELO` command whose identity failed a check.
+pub fn action_not_taken(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("550")
+}
+
+/// Checks if the server's response to `HELO` or `MAIL` is the `550 5.7.23` reply given when SPF
+/// fails under [`crate::spf::SpfPolicy::Reject`], per [RFC 7372 section
+/// 3.2](https://www.rfc-editor.org/rfc/rfc7372.html#section-3.2).
+#[cfg(feature = "spf")]
+pub fn spf_failed(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("550 5.7.23")
+}
+
+/// Checks if the server's response to the end of the data is the `550 5.7.1` reply given when
+/// DMARC rejects the message, per [RFC 7489 section
+/// 6.7](https://www.rfc-editor.org/rfc/rfc7489.html#section-6.7).
+#[cfg(feature = "dmarc")]
+pub fn dmarc_rejected(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("550 5.7.1")
+}
+
+/// Checks if the server's response to the end of the data is the `550 5.7.1` reply given to
+/// messages that ClamAV found a virus in, with [`crate::clamav::ClamAvAction::Reject`].
+#[cfg(feature = "clamav")]
+pub fn virus_rejected(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("550 5.7.1 Virus found")
+}
+
+/// Checks if the server's response to the end of the data is the `552 5.7.0` reply given to
+/// messages with attachments blocked by [`crate::attachment::AttachmentPolicy`].
+#[cfg(feature = "attachment")]
+pub fn attachment_rejected(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("552 5.7.0")
+}
+
+/// Checks if the server's greeting is the `554 5.7.25` reply that refuses clients without a
+/// confirmed name under [`crate::config::ReverseDnsPolicy::Reject`], per [RFC 7372 section
+/// 3.3](https://www.rfc-editor.org/rfc/rfc7372.html#section-3.3).
+pub fn reverse_dns_failed(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("554 5.7.25")
+}
+
+/// Checks if the server's response to `MAIL` is the `550 5.7.1` reply given to clients that are
+/// listed on a DNS blocklist with [`crate::dnsbl::DnsblAction::Reject`].
+#[cfg(feature = "dnsbl")]
+pub fn dnsbl_rejected(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("550 5.7.1") && str.contains("blocked using")
+}
+
+/// Checks if the server's greeting is the `554 5.7.1` reply that refuses clients that are listed
+/// on a DNS blocklist with [`crate::dnsbl::DnsblAction::Refuse`], per [RFC 5321 section
+/// 3.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-3.1).
+#[cfg(feature = "dnsbl")]
+pub fn dnsbl_refused(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("554 5.7.1") && str.contains("blocked using")
+}
+
+/// Checks if the server's response to `RCPT` is the `450 4.7.1` reply given to recipients that
+/// are greylisted by [`crate::greylist::GreylistPolicy`].
+#[cfg(feature = "greylist")]
+pub fn greylisted(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("450 4.7.1")
+}
+
+/// Checks if the server's response to the end of the data is the `554 5.7.1` reply given to
+/// messages that Rspamd recommends rejecting, or that SpamAssassin scores past the rejection
+/// score.
+#[cfg(any(feature = "rspamd", feature = "spamassassin"))]
+pub fn spam_rejected(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("554 5.7.1")
+}
+
+/// Checks if the server's response to the end of the data is the `550 5.7.1` reply given to
+/// messages that match a filter rule with [`crate::filter::FilterAction::Reject`].
+#[cfg(feature = "filter")]
+pub fn filter_rejected(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("550 5.7.1")
+}
+
+/// Checks if the server's response is the `530` reply that requires `STARTTLS` first, per [RFC
+/// 3207, section 4](https://www.rfc-editor.org/rfc/rfc3207.html#section-4).
+#[cfg(feature = "tls")]
+pub fn tls_required(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("530")
+}
+
+/// Checks if the server's response is the `530` reply that requires authentication first, per [RFC
+/// 4954, section 6](https://www.rfc-editor.org/rfc/rfc4954.html#section-6).
+pub fn auth_required(str: &str) -> bool {
+    smtp_line(str) && str.starts_with("530 5.7.0")
+}
+
 /// Checks if the server's response to the `QUIT` command matches [RFC 5321, section
 /// 4.1.1.10](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.1.1.10).
 pub fn quit(str: &str) -> bool {
@@ -15,76 +15,453 @@
 // You should have received a copy of the GNU Affero General Public License along with
 // smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
 
-use std::error::Error;
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
+use ascii::{AsciiStr, IntoAsciiString};
+use futures_core::Stream;
 use futures_util::{pin_mut, StreamExt};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
 };
 
-use crate::{read_line, timeouts};
+use crate::{
+    config::{
+        Access, AuthLimits, CommandLimits, ConfigHandle, ConnectionOverflow, HeloAction,
+        HeloPolicy, Network, NullSenderPolicy, PeerLimits, Tarpit,
+    },
+    event::SessionEvent,
+    handler::{
+        AcceptAll, Command, ConnectDecision, Decision, Defer, HandlerError, HandlerFactory,
+        HandlerResult, Refuse, Response, SessionContext, SmtpHandler,
+    },
+    message::envelope::{Envelope, Recipient},
+    read_line,
+    session::SessionInfo,
+    timeouts, Listener, Message, ServerConfig, ServerHandle, Session, Shutdown,
+};
 
 mod is_valid_response;
 
 type Result = std::result::Result<(), Box<dyn Error>>;
 
+/// Test the responses to a series of SMTP commands.
+///
+/// Implicitly `await`s, returns on errors, and panics on invalid responses.
+macro_rules! test_response {
+    (
+        $write_stream:expr, $reader:expr, [$(
+            ( $message:expr, $timeout:expr, $test_fn:expr $(,)? )
+       ),+ $(,)? ] $(,)?
+    ) => {
+        $(
+            $crate::write_line!($write_stream, $message)?;
+            assert!($test_fn(
+                &::tokio::time::timeout($timeout, $crate::read_line!($reader)).await??
+            ))
+        );+
+    };
+}
+
+// Declared after `test_response` so that the macro is in scope.
+#[cfg(feature = "tls")]
+mod tls;
+
 // 4.5.1 Minimum Implementation:
 //
-// - [ ] `EHLO`
+// - [x] `EHLO`
 // - [x] `HELO`
-// - [ ] `MAIL`
-// - [ ] `RCPT`
-// - [ ] `DATA`
-// - [ ] `RSET`
-// - [ ] `NOOP`
-// - [ ] `VRFY`
+// - [x] `MAIL`
+// - [x] `RCPT`
+// - [x] `DATA`
+// - [x] `RSET`
+// - [x] `NOOP`
+// - [x] `VRFY`
 // - [x] `QUIT`
 //
 // <https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.1>
 #[tokio::test]
 async fn test_listen() -> Result {
-    /// Test the responses to a series of SMTP commands.
-    ///
-    /// Implicitly `await`s, returns on errors, and panics on invalid responses.
-    macro_rules! test_response {
-        (
-            $write_stream:expr, $reader:expr, [$(
-                ( $message:expr, $timeout:expr, $test_fn:expr $(,)? )
-           ),+ $(,)? ] $(,)?
-        ) => {
-            $(
-                $crate::write_line!($write_stream, $message)?;
-                assert!($test_fn(
-                    &::tokio::time::timeout($timeout, $crate::read_line!($reader)).await??
-                ))
-            );+
-        };
+    const ADDR: &str = "127.0.0.1:8080";
+
+    spawn_server(ADDR).await?;
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "HELO",
+                timeouts::INITIAL_220_MESSAGE,
+                is_valid_response::helo,
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transaction() -> Result {
+    const ADDR: &str = "127.0.0.1:8081";
+
+    spawn_server(ADDR).await?;
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::bad_sequence
+            ),
+            (
+                "HELO client.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::helo
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::RCPT,
+                is_valid_response::bad_sequence
+            ),
+            (
+                "MAIL FROM:<smith@example.com> SIZE=99999999999",
+                timeouts::MAIL,
+                is_valid_response::exceeded_storage
+            ),
+            (
+                "MAIL FROM:<smith@example.com> SIZE=100",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            ("RSET", timeouts::EXPECTED, is_valid_response::ok),
+            (
+                "MAIL FROM:<smith@example.com> RET=HDRS ENVID=QQ314159",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            (
+                "DATA",
+                timeouts::DATA_INITIALIZATION,
+                is_valid_response::no_valid_recipients
+            ),
+            (
+                "RCPT TO:<jones@example.com> NOTIFY=SUCCESS,FAILURE ORCPT=rfc822;jones@example.com",
+                timeouts::RCPT,
+                is_valid_response::ok
+            ),
+            ("NOOP", timeouts::EXPECTED, is_valid_response::ok),
+            (
+                "DATA",
+                timeouts::DATA_INITIALIZATION,
+                is_valid_response::data
+            ),
+        ],
+    );
+
+    write_stream
+        .write_all(b"Subject: test\r\n\r\n..leading period\r\n.\r\n")
+        .await?;
+    assert!(is_valid_response::ok(
+        &tokio::time::timeout(timeouts::DATA_TERMINATION, read_line!(reader)).await??
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_streaming() -> Result {
+    const ADDR: &str = "127.0.0.1:8082";
+
+    let (sessions, mut messages) = crate::listen_streaming(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    );
+    spawn_sessions(sessions);
+
+    // Accepts the first message and rejects the second.
+    let consumer = tokio::spawn(async move {
+        let mut bodies = Vec::new();
+
+        for accept in [true, false] {
+            let mut message = messages.recv().await.unwrap();
+            assert_eq!(message.envelope().accepted().count(), 1);
+            assert_eq!(
+                message.session().helo().map(AsciiStr::as_str),
+                Some("client.example.com")
+            );
+
+            let mut body = Vec::new();
+            while let Some(chunk) = message.body_mut().next().await {
+                body.extend_from_slice(&chunk.unwrap());
+            }
+            bodies.push(body);
+
+            if accept {
+                message.accept();
+            } else {
+                message.reject();
+            }
+        }
+
+        bodies
+    });
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [(
+            "HELO client.example.com",
+            timeouts::EXPECTED,
+            is_valid_response::helo
+        )],
+    );
+
+    for is_valid_end in [is_valid_response::ok, is_valid_response::transaction_failed] {
+        test_response!(
+            write_stream,
+            reader,
+            [
+                (
+                    "MAIL FROM:<smith@example.com>",
+                    timeouts::MAIL,
+                    is_valid_response::ok
+                ),
+                (
+                    "RCPT TO:<jones@example.com>",
+                    timeouts::RCPT,
+                    is_valid_response::ok
+                ),
+                (
+                    "DATA",
+                    timeouts::DATA_INITIALIZATION,
+                    is_valid_response::data
+                ),
+            ],
+        );
+
+        write_stream
+            .write_all(b"Subject: test\r\n\r\n..leading period\r\n.\r\n")
+            .await?;
+        assert!(is_valid_end(
+            &tokio::time::timeout(timeouts::DATA_TERMINATION, read_line!(reader)).await??
+        ));
     }
 
-    const ADDR: &str = "127.0.0.1:8080";
+    test_response!(
+        write_stream,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
 
-    let stream = crate::listen(TcpListener::bind(ADDR).await?);
+    for body in consumer.await? {
+        assert!(body.starts_with(b"Received: from client.example.com"));
+        assert!(body.ends_with(b"\r\nSubject: test\r\n\r\n.leading period\r\n"));
+    }
 
-    // Can be bound to a variable which exposes `.abort()`
-    tokio::spawn(async move {
-        pin_mut!(stream);
+    Ok(())
+}
 
-        loop {
-            // Get the `Next` and unwrap it
-            let session = stream
-                .next()
-                .await
-                .unwrap()
-                // Unwrap the [`TcpListener::accept`]
-                .unwrap()
-                // Await and unwrap the [`JoinHandle`]
-                .await
-                .unwrap();
+#[tokio::test]
+async fn test_acknowledged() -> Result {
+    const ADDR: &str = "127.0.0.1:8142";
+
+    let config = ServerConfig::builder()
+        .hostname("mx.example.com")
+        .timeouts(timeouts::Timeouts::rfc5321().with_data_termination(Duration::from_millis(500)))
+        .build()?;
+    let (sessions, mut messages) =
+        crate::listen_acknowledged(TcpListener::bind(ADDR).await?, config, Shutdown::new());
+    spawn_sessions(sessions);
+
+    // Accepts the first message, rejects the second, drops the third, and holds on to the fourth
+    // for longer than the timeout.
+    let consumer = tokio::spawn(async move {
+        let message = messages.recv().await.unwrap();
+        assert_eq!(message.message().envelope().accepted().count(), 1);
+        message.accept();
+        messages.recv().await.unwrap().reject();
+        drop(messages.recv().await.unwrap());
+
+        let message = messages.recv().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        message.accept();
+    });
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    for is_valid_end in [
+        is_valid_response::ok,
+        is_valid_response::transaction_failed,
+        is_valid_response::local_error,
+        is_valid_response::local_error,
+    ] {
+        test_response!(
+            writer,
+            reader,
+            [
+                (
+                    "MAIL FROM:<smith@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                (
+                    "RCPT TO:<jones@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ],
+        );
+        writer
+            .write_all(b"Subject: test\r\n\r\nbody\r\n.\r\n")
+            .await?;
+        assert!(is_valid_end(
+            &tokio::time::timeout(timeouts::DATA_TERMINATION, read_line!(reader)).await??
+        ));
+    }
+
+    consumer.await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_channel() -> Result {
+    const ADDR: &str = "127.0.0.1:8085";
+
+    let (sessions, mut messages) =
+        crate::listen_channel(TcpListener::bind(ADDR).await?, config(), Shutdown::new());
+    spawn_sessions(sessions);
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
 
-            // Unwrap the [`Session`] itself
-            session.unwrap();
+    test_response!(
+        write_stream,
+        reader,
+        [(
+            "HELO client.example.com",
+            timeouts::EXPECTED,
+            is_valid_response::helo
+        )],
+    );
+
+    // The second message is sent after the receiver is dropped.
+    for is_valid_end in [is_valid_response::ok, is_valid_response::local_error] {
+        test_response!(
+            write_stream,
+            reader,
+            [
+                (
+                    "MAIL FROM:<smith@example.com>",
+                    timeouts::MAIL,
+                    is_valid_response::ok
+                ),
+                (
+                    "RCPT TO:<jones@example.com>",
+                    timeouts::RCPT,
+                    is_valid_response::ok
+                ),
+                (
+                    "DATA",
+                    timeouts::DATA_INITIALIZATION,
+                    is_valid_response::data
+                ),
+            ],
+        );
+
+        write_stream
+            .write_all(b"Subject: test\r\n\r\nbody\r\n.\r\n")
+            .await?;
+        let end = tokio::time::timeout(timeouts::DATA_TERMINATION, read_line!(reader)).await??;
+        assert!(is_valid_end(&end));
+
+        if !messages.is_closed() {
+            let message = messages.try_recv()?;
+            let id = message.session().id().to_string();
+            assert!(end.trim_end().ends_with(&format!("queued as {id}")));
+            assert!(message
+                .headers()
+                .get("Received")
+                .is_some_and(|received| received.contains(&format!("id {id}"))));
+            assert_eq!(message.headers().get("Subject"), Some("test"));
+            assert_eq!(
+                message.envelope().reverse_path().map(AsciiStr::as_str),
+                Some("smith@example.com")
+            );
+            messages.close();
         }
+    }
+
+    test_response!(
+        write_stream,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_serve() -> Result {
+    const ADDR: &str = "127.0.0.1:8086";
+
+    let messages = crate::serve(TcpListener::bind(ADDR).await?, config(), Shutdown::new());
+    let consumer = tokio::spawn(async move {
+        pin_mut!(messages);
+        messages.next().await.unwrap().unwrap()
     });
 
     let mut stream = TcpStream::connect(ADDR).await?;
@@ -101,13 +478,3993 @@ async fn test_listen() -> Result {
         reader,
         [
             (
-                "HELO",
-                timeouts::INITIAL_220_MESSAGE,
-                is_valid_response::helo,
+                "HELO client.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::helo
+            ),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::RCPT,
+                is_valid_response::ok
+            ),
+            (
+                "DATA",
+                timeouts::DATA_INITIALIZATION,
+                is_valid_response::data
             ),
-            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
         ],
     );
 
+    write_stream
+        .write_all(b"Subject: test\r\n\r\nbody\r\n.\r\n")
+        .await?;
+    assert!(is_valid_response::ok(
+        &tokio::time::timeout(timeouts::DATA_TERMINATION, read_line!(reader)).await??
+    ));
+
+    let message = consumer.await?;
+    assert_eq!(message.headers().get("Subject"), Some("test"));
+    assert_eq!(message.envelope().accepted().count(), 1);
+
+    test_response!(
+        write_stream,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_serve_shutdown() -> Result {
+    const ADDR: &str = "127.0.0.1:8159";
+
+    let server = ServerHandle::new();
+    let messages = crate::serve(TcpListener::bind(ADDR).await?, config(), server.clone());
+    let consumer = tokio::spawn(async move {
+        pin_mut!(messages);
+        while let Some(message) = messages.next().await {
+            message.unwrap();
+        }
+    });
+
+    let (mut reader, _writer) = greeted_session(ADDR).await?;
+    assert_eq!(server.active_sessions(), 1);
+
+    // Tests that shutting down closes the session, then ends the stream of messages.
+    server.shutdown().shutdown(Duration::from_millis(500)).await;
+    let closed = tokio::time::timeout(timeouts::EXPECTED, read_line!(reader)).await??;
+    assert!(is_valid_response::service_unavailable(&closed));
+    tokio::time::timeout(timeouts::EXPECTED, consumer).await??;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connect_decision() -> Result {
+    /// Decides the same for every connection.
+    struct Screen(ConnectDecision);
+
+    impl HandlerFactory for Screen {
+        type Handler = AcceptAll;
+
+        async fn on_connect(&self, _: SocketAddr) -> ConnectDecision {
+            self.0
+        }
+
+        fn create(&self, _: SessionInfo) -> AcceptAll {
+            AcceptAll
+        }
+    }
+
+    for (addr, decision) in [
+        ("127.0.0.1:8088", ConnectDecision::Drop),
+        ("127.0.0.1:8089", ConnectDecision::Reject),
+        (
+            "127.0.0.1:8090",
+            ConnectDecision::Tarpit(Duration::from_millis(50)),
+        ),
+    ] {
+        spawn_sessions(crate::listen(
+            TcpListener::bind(addr).await?,
+            config(),
+            Screen(decision),
+            Shutdown::new(),
+        ));
+
+        let mut stream = TcpStream::connect(addr).await?;
+        let mut reader = BufReader::new(&mut stream);
+        let greeting = read_line!(reader).await;
+
+        match decision {
+            ConnectDecision::Drop => assert!(greeting.is_err()),
+            ConnectDecision::Reject => {
+                assert!(is_valid_response::no_service(&greeting?));
+                assert!(read_line!(reader).await.is_err());
+            }
+            _ => assert!(is_valid_response::server_greeting(&greeting?)),
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_slow_connect_decision() -> Result {
+    const ADDR: &str = "127.0.0.1:8160";
+
+    /// Takes its time deciding on the first connection only.
+    struct Slow(AtomicBool);
+
+    impl HandlerFactory for Slow {
+        type Handler = AcceptAll;
+
+        async fn on_connect(&self, _: SocketAddr) -> ConnectDecision {
+            if !self.0.swap(true, Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+            }
+            ConnectDecision::Accept
+        }
+
+        fn create(&self, _: SessionInfo) -> AcceptAll {
+            AcceptAll
+        }
+    }
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        Slow(AtomicBool::new(false)),
+        Shutdown::new(),
+    ));
+
+    // Tests that a connection is greeted while the decision on another is still being made.
+    let _waiting = TcpStream::connect(ADDR).await?;
+    tokio::time::timeout(timeouts::EXPECTED, greeted_session(ADDR)).await??;
+
     Ok(())
 }
+
+#[tokio::test]
+async fn test_command_observer() -> Result {
+    const ADDR: &str = "127.0.0.1:8091";
+
+    /// Limits `NOOP` to two per session and refuses `VRFY` outright.
+    #[derive(Default)]
+    struct Limit {
+        noops: usize,
+    }
+
+    impl SmtpHandler for Limit {
+        async fn on_command(
+            &mut self,
+            _: &mut SessionContext,
+            command: &Command<'_>,
+        ) -> HandlerResult {
+            Ok(match command.verb().as_str() {
+                "NOOP" => {
+                    self.noops += 1;
+
+                    if self.noops > 2 {
+                        Defer::MailboxUnavailable.into()
+                    } else {
+                        Decision::Accept
+                    }
+                }
+                "VRFY" => Decision::Reject,
+                _ => Decision::Accept,
+            })
+        }
+    }
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| Limit::default(),
+        Shutdown::new(),
+    ));
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            ("NOOP", timeouts::EXPECTED, is_valid_response::ok),
+            ("noop", timeouts::EXPECTED, is_valid_response::ok),
+            ("NOOP", timeouts::EXPECTED, is_valid_response::mailbox_busy),
+            (
+                "VRFY smith",
+                timeouts::EXPECTED,
+                is_valid_response::mailbox_unavailable
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_events() -> Result {
+    const ADDR: &str = "127.0.0.1:8092";
+
+    let (sessions, mut events) = crate::listen_events(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    );
+    spawn_sessions(sessions);
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "HELO client.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::helo
+            ),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::RCPT,
+                is_valid_response::ok
+            ),
+            (
+                "DATA",
+                timeouts::DATA_INITIALIZATION,
+                is_valid_response::data
+            ),
+            (
+                "Subject: test\r\n\r\n..body\r\n.",
+                timeouts::DATA_TERMINATION,
+                is_valid_response::ok
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    let Some(mut session) = events.recv().await else {
+        panic!("the session should have sent its events");
+    };
+
+    let Some(SessionEvent::Connected(info)) = session.next().await else {
+        panic!("the first event should be `Connected`");
+    };
+    assert_eq!(info.peer_addr(), stream.local_addr()?);
+    assert_eq!(
+        session.next().await,
+        Some(SessionEvent::Helo(
+            "client.example.com".into_ascii_string()?
+        ))
+    );
+    assert_eq!(
+        session.next().await,
+        Some(SessionEvent::MailFrom(Envelope::new(Some(
+            "smith@example.com".into_ascii_string()?
+        ))))
+    );
+    assert_eq!(
+        session.next().await,
+        Some(SessionEvent::RcptTo(Recipient::new(
+            "jones@example.com".into_ascii_string()?
+        )))
+    );
+    // Tests that the transparency dot-stuffing is removed.
+    for line in ["Subject: test\r\n", "\r\n", ".body\r\n"] {
+        assert_eq!(
+            session.next().await,
+            Some(SessionEvent::DataChunk(line.into()))
+        );
+    }
+    let Some(SessionEvent::MessageComplete(message)) = session.next().await else {
+        panic!("the data should be followed by `MessageComplete`");
+    };
+    assert_eq!(message.body(), &b".body\r\n"[..]);
+    assert_eq!(session.next().await, Some(SessionEvent::Closed));
+    assert_eq!(session.next().await, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_recipient_limit() -> Result {
+    const ADDR: &str = "127.0.0.1:8083";
+
+    spawn_server(ADDR).await?;
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "HELO client.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::helo
+            ),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+        ],
+    );
+
+    // RFC 5321 requires that at least 100 recipients are accepted.
+    for _ in 0..100 {
+        test_response!(
+            write_stream,
+            reader,
+            [(
+                "RCPT TO:<jones@example.com>",
+                timeouts::RCPT,
+                is_valid_response::ok
+            )],
+        );
+    }
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "RCPT TO:<green@example.com>",
+                timeouts::RCPT,
+                is_valid_response::too_many_recipients
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handler() -> Result {
+    const ADDR: &str = "127.0.0.1:8084";
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| Policy,
+        Shutdown::new(),
+    ));
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "HELO client.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::helo
+            ),
+            (
+                "MAIL FROM:<spammer@example.com>",
+                timeouts::MAIL,
+                is_valid_response::mailbox_unavailable
+            ),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            (
+                "RCPT TO:<jones@example.org>",
+                timeouts::RCPT,
+                is_valid_response::mailbox_unavailable
+            ),
+            (
+                "RCPT TO:<full@example.com>",
+                timeouts::RCPT,
+                is_valid_response::exceeded_storage
+            ),
+            (
+                "RCPT TO:<greylisted@example.com>",
+                timeouts::RCPT,
+                is_valid_response::mailbox_busy
+            ),
+            (
+                "RCPT TO:<unreachable@example.com>",
+                timeouts::RCPT,
+                is_valid_response::local_error
+            ),
+            (
+                "DATA",
+                timeouts::DATA_INITIALIZATION,
+                is_valid_response::no_valid_recipients
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::RCPT,
+                is_valid_response::ok
+            ),
+        ],
+    );
+
+    test_response!(
+        write_stream,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_message_inspection() -> Result {
+    const ADDR: &str = "127.0.0.1:8087";
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| Policy,
+        Shutdown::new(),
+    ));
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [(
+            "HELO client.example.com",
+            timeouts::EXPECTED,
+            is_valid_response::helo
+        )],
+    );
+
+    // An error that closes the session comes last.
+    for (subject, is_valid_end) in [
+        (
+            "spam",
+            is_valid_response::transaction_failed as fn(&str) -> bool,
+        ),
+        ("virus", is_valid_response::mailbox_unavailable),
+        ("later", is_valid_response::local_error),
+        ("test", is_valid_response::ok),
+        ("crash", is_valid_response::service_unavailable),
+    ] {
+        test_response!(
+            write_stream,
+            reader,
+            [
+                (
+                    "MAIL FROM:<smith@example.com>",
+                    timeouts::MAIL,
+                    is_valid_response::ok
+                ),
+                (
+                    "RCPT TO:<jones@example.com>",
+                    timeouts::RCPT,
+                    is_valid_response::ok
+                ),
+                (
+                    "DATA",
+                    timeouts::DATA_INITIALIZATION,
+                    is_valid_response::data
+                ),
+            ],
+        );
+
+        write_stream
+            .write_all(format!("Subject: {subject}\r\n\r\nbody\r\n.\r\n").as_bytes())
+            .await?;
+        assert!(is_valid_end(
+            &tokio::time::timeout(timeouts::DATA_TERMINATION, read_line!(reader)).await??
+        ));
+    }
+
+    assert!(read_line!(reader).await.is_err());
+
+    Ok(())
+}
+
+/// Rejects one sender, recipients outside of `example.com`, and messages by their subject.
+struct Policy;
+
+impl SmtpHandler for Policy {
+    async fn on_mail(&mut self, _: &mut SessionContext, envelope: &Envelope) -> HandlerResult {
+        Ok(
+            if envelope.reverse_path().map(AsciiStr::as_str) == Some("spammer@example.com") {
+                Response::parse("550 5.7.1 Blocked by policy")?.into()
+            } else {
+                Decision::Accept
+            },
+        )
+    }
+
+    async fn on_rcpt(&mut self, _: &mut SessionContext, recipient: &Recipient) -> HandlerResult {
+        Ok(match recipient.forward_path().as_str() {
+            "greylisted@example.com" => Decision::Defer(Defer::MailboxUnavailable),
+            "full@example.com" => Refuse::MailboxFull.into(),
+            "unreachable@example.com" => return Err(HandlerError::new("directory unavailable")),
+            path if path.ends_with("@example.com") => Decision::Accept,
+            _ => Refuse::UnknownUser.into(),
+        })
+    }
+
+    async fn on_message(&mut self, _: &mut SessionContext, message: Message) -> HandlerResult {
+        Ok(match message.headers().get("Subject") {
+            Some("spam") => Decision::Reject,
+            Some("virus") => Refuse::PolicyViolation.into(),
+            Some("later") => Defer::LocalError.into(),
+            Some("crash") => {
+                return Err(HandlerError::new("queue unavailable").close_session());
+            }
+            _ => Decision::Accept,
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_ehlo() -> Result {
+    const ADDR: &str = "127.0.0.1:8094";
+
+    spawn_server(ADDR).await?;
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    // Tests that the maximum message size is advertised with the `SIZE` extension.
+    crate::write_line!(write_stream, "EHLO client.example.com")?;
+    assert_eq!(
+        read_line!(reader).await?,
+        "250-mx.example.com greets client.example.com\r\n"
+    );
+    assert_eq!(read_line!(reader).await?, "250 SIZE 10485760\r\n");
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com> SIZE=10485761",
+                timeouts::MAIL,
+                is_valid_response::exceeded_storage
+            ),
+            (
+                "MAIL FROM:<smith@example.com> SIZE=10485760",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_line_length() -> Result {
+    const ADDR: &str = "127.0.0.1:8095";
+
+    spawn_server(ADDR).await?;
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    // Tests that an overlong command is refused without losing track of the next command.
+    let long_command = format!("NOOP {}\r\n", "a".repeat(1024));
+    write_stream.write_all(long_command.as_bytes()).await?;
+    assert!(is_valid_response::syntax_error(
+        &tokio::time::timeout(timeouts::EXPECTED, read_line!(reader)).await??
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            ("HELO", timeouts::EXPECTED, is_valid_response::helo),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::RCPT,
+                is_valid_response::ok
+            ),
+            (
+                "DATA",
+                timeouts::DATA_INITIALIZATION,
+                is_valid_response::data
+            ),
+        ],
+    );
+
+    // Tests that a message with an overlong line is refused once its data ends.
+    let long_line = format!("Subject: test\r\n\r\n{}\r\n", "a".repeat(2000));
+    write_stream.write_all(long_line.as_bytes()).await?;
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                ".",
+                timeouts::DATA_TERMINATION,
+                is_valid_response::exceeded_storage
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_listen_all() -> Result {
+    const RELAY_ADDR: &str = "127.0.0.1:8096";
+    const SUBMISSION_ADDR: &str = "127.0.0.1:8097";
+
+    spawn_sessions(crate::listen_all(
+        [
+            Listener::new(TcpListener::bind(RELAY_ADDR).await?),
+            Listener::new(TcpListener::bind(SUBMISSION_ADDR).await?).with_config(
+                ServerConfig::builder()
+                    .hostname("submission.example.com")
+                    .build()?,
+            ),
+        ],
+        config(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    // Tests that each listener follows its own configuration, or the server's if it has none.
+    for (addr, hostname) in [
+        (RELAY_ADDR, "mx.example.com"),
+        (SUBMISSION_ADDR, "submission.example.com"),
+    ] {
+        let mut stream = TcpStream::connect(addr).await?;
+        let (read_stream, mut write_stream) = stream.split();
+
+        let mut reader = BufReader::new(read_stream);
+
+        let greeting = read_line!(reader).await?;
+        assert!(is_valid_response::server_greeting(&greeting));
+        assert!(greeting.starts_with(&format!("220 {hostname} ")));
+
+        test_response!(
+            write_stream,
+            reader,
+            [
+                ("HELO", timeouts::EXPECTED, is_valid_response::helo),
+                ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+            ],
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_listen_all_shutdown() -> Result {
+    const RELAY_ADDR: &str = "127.0.0.1:8154";
+    const SUBMISSION_ADDR: &str = "127.0.0.1:8155";
+
+    let server = ServerHandle::new();
+    let sessions = crate::listen_all(
+        [
+            Listener::new(TcpListener::bind(RELAY_ADDR).await?),
+            Listener::new(TcpListener::bind(SUBMISSION_ADDR).await?),
+        ],
+        config(),
+        |_| AcceptAll,
+        server.clone(),
+    );
+    let serving = tokio::spawn(async move {
+        let sessions: Vec<_> = sessions.collect().await;
+        for session in sessions {
+            session.unwrap().await.unwrap().unwrap();
+        }
+    });
+
+    let (mut relay_reader, _relay_writer) = greeted_session(RELAY_ADDR).await?;
+    let (mut submission_reader, _submission_writer) = greeted_session(SUBMISSION_ADDR).await?;
+    assert_eq!(server.active_sessions(), 2);
+
+    // Tests that shutting down closes the sessions of every listener, then ends the stream.
+    server.shutdown().shutdown(Duration::from_millis(500)).await;
+    for reader in [&mut relay_reader, &mut submission_reader] {
+        let closed = tokio::time::timeout(timeouts::EXPECTED, read_line!(reader)).await??;
+        assert!(is_valid_response::service_unavailable(&closed));
+    }
+    tokio::time::timeout(timeouts::EXPECTED, serving).await??;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shutdown() -> Result {
+    const ADDR: &str = "127.0.0.1:8098";
+
+    let server = ServerHandle::new();
+    let sessions = crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| AcceptAll,
+        server.clone(),
+    );
+    // Unlike `spawn_sessions`, expects the stream to end, then for every session to end cleanly.
+    let serving = tokio::spawn(async move {
+        let sessions: Vec<_> = sessions.collect().await;
+        for session in sessions {
+            session.unwrap().await.unwrap().unwrap();
+        }
+    });
+
+    let (mut idle_reader, _idle_writer) = greeted_session(ADDR).await?;
+    let (mut sending_reader, mut sending_writer) = greeted_session(ADDR).await?;
+    let (mut stalled_reader, mut stalled_writer) = greeted_session(ADDR).await?;
+    for writer in [&mut sending_writer, &mut stalled_writer] {
+        crate::write_line!(writer, "MAIL FROM:<smith@example.com>")?;
+    }
+    for reader in [&mut sending_reader, &mut stalled_reader] {
+        assert!(is_valid_response::ok(&read_line!(reader).await?));
+    }
+    assert_eq!(server.active_sessions(), 3);
+
+    let deadline = Duration::from_millis(500);
+    let shutting_down = tokio::spawn({
+        let server = server.clone();
+        async move { server.shutdown().shutdown(deadline).await }
+    });
+
+    // Tests that idle sessions are closed right away.
+    let closed = tokio::time::timeout(timeouts::EXPECTED, read_line!(idle_reader)).await??;
+    assert!(is_valid_response::service_unavailable(&closed));
+    assert!(server.shutdown().is_shutting_down());
+
+    // Tests that a mail transaction in progress can finish before its session is closed.
+    test_response!(
+        sending_writer,
+        sending_reader,
+        [
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::RCPT,
+                is_valid_response::ok
+            ),
+            (
+                "DATA",
+                timeouts::DATA_INITIALIZATION,
+                is_valid_response::data
+            ),
+        ],
+    );
+    sending_writer
+        .write_all(b"Subject: test\r\n\r\nbody\r\n.\r\n")
+        .await?;
+    let queued = tokio::time::timeout(timeouts::EXPECTED, read_line!(sending_reader)).await??;
+    assert!(is_valid_response::ok(&queued));
+    let closed = tokio::time::timeout(timeouts::EXPECTED, read_line!(sending_reader)).await??;
+    assert!(is_valid_response::service_unavailable(&closed));
+
+    // Tests that a stalled mail transaction is closed once the deadline passes.
+    let closed = tokio::time::timeout(timeouts::EXPECTED, read_line!(stalled_reader)).await??;
+    assert!(is_valid_response::service_unavailable(&closed));
+
+    tokio::time::timeout(timeouts::EXPECTED, shutting_down).await??;
+    tokio::time::timeout(timeouts::EXPECTED, serving).await??;
+    assert_eq!(server.active_sessions(), 0);
+
+    // Tests that new connections are no longer accepted.
+    assert!(TcpStream::connect(ADDR).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_introspection() -> Result {
+    const ADDR: &str = "127.0.0.1:8143";
+
+    let server = ServerHandle::new();
+    let sessions = crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| AcceptAll,
+        server.clone(),
+    );
+    // Unlike `spawn_sessions`, expects the session to be cancelled.
+    tokio::spawn(async move {
+        pin_mut!(sessions);
+        let session = sessions.next().await.unwrap().unwrap();
+        assert!(session.await.unwrap_err().is_cancelled());
+    });
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [(
+            "MAIL FROM:<smith@example.com>",
+            timeouts::EXPECTED,
+            is_valid_response::ok
+        )],
+    );
+
+    // Tests that the session is listed with what it has done so far.
+    let sessions = server.sessions();
+    assert_eq!(sessions.len(), 1);
+    let status = sessions[0];
+    assert_eq!(status.peer_addr(), writer.local_addr()?);
+    assert_eq!(status.phase(), crate::handler::Phase::Mail);
+    assert_eq!(status.received(), 37);
+    assert!(status.sent() > 0);
+    assert!(status.idle() <= status.age());
+
+    // Tests that closing the session drops its connection without a reply.
+    assert!(server.close_session(status.id()));
+    let closed = tokio::time::timeout(timeouts::EXPECTED, read_line!(reader)).await?;
+    assert!(closed.is_err());
+    tokio::time::timeout(timeouts::EXPECTED, async {
+        while server.active_sessions() > 0 {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await?;
+    assert!(server.sessions().is_empty());
+    assert!(!server.close_session(status.id()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transcript() -> Result {
+    use tokio::sync::mpsc;
+
+    use crate::{
+        session::SessionId,
+        transcript::{Direction, Transcript, TranscriptTarget},
+    };
+
+    const ADDR: &str = "127.0.0.1:8144";
+
+    /// Sends the transcript of each session as it closes.
+    struct SendTranscript(mpsc::UnboundedSender<(SessionId, Option<Transcript>)>);
+
+    impl SmtpHandler for SendTranscript {
+        async fn on_close(&mut self, context: &mut SessionContext) {
+            let _ = self.0.send((context.session().id(), context.transcript()));
+        }
+    }
+
+    let path = std::env::temp_dir().join("smtp_gateway_test_transcripts");
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path)?;
+    let config = ServerConfig::builder()
+        .hostname("mx.example.com")
+        .transcripts(TranscriptTarget::Directory(path.clone()))
+        .build()?;
+
+    let (sender, mut transcripts) = mpsc::unbounded_channel();
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config,
+        move |_| SendTranscript(sender.clone()),
+        Shutdown::new(),
+    ));
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "AUTH PLAIN AHVzZXIAcGFzc3dvcmQ=",
+                timeouts::EXPECTED,
+                |_: &str| true
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    // Tests that the handler is given the whole session as it closes, without the credentials.
+    let (id, transcript) = tokio::time::timeout(timeouts::EXPECTED, transcripts.recv())
+        .await?
+        .expect("the sender is kept by the server");
+    let transcript = transcript.expect("transcripts are recorded");
+    let lines: Vec<_> = transcript
+        .lines()
+        .iter()
+        .map(|line| (line.direction(), line.text()))
+        .collect();
+    assert_eq!(lines.len(), 7);
+    assert!(lines[0].1.starts_with("220 mx.example.com"));
+    assert_eq!(lines[1], (Direction::Received, "HELO"));
+    assert_eq!(lines[3], (Direction::Received, "AUTH PLAIN [redacted]"));
+    assert_eq!(lines[5], (Direction::Received, "QUIT"));
+    assert!(lines[6].1.starts_with("221"));
+
+    // Tests that the transcript is written to the directory before the handler is told.
+    let written = std::fs::read_to_string(path.join(format!("{id}.log")))?;
+    assert_eq!(written, transcript.to_string());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_health_and_stats() -> Result {
+    use std::sync::Arc;
+
+    use tokio::sync::Notify;
+
+    use crate::server::ListenerStatus;
+
+    const ADDR: &str = "127.0.0.1:8145";
+
+    /// Holds each message until it is notified.
+    struct Hold(Arc<Notify>);
+
+    impl SmtpHandler for Hold {
+        async fn on_message(&mut self, _: &mut SessionContext, _: Message) -> HandlerResult {
+            self.0.notified().await;
+            Ok(Decision::Accept)
+        }
+    }
+
+    let server = ServerHandle::new();
+    let release = Arc::new(Notify::new());
+    let handler_release = release.clone();
+    let sessions = crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        move |_| Hold(handler_release.clone()),
+        server.clone(),
+    );
+    // Unlike `spawn_sessions`, expects the stream to end once the server shuts down.
+    tokio::spawn(async move {
+        pin_mut!(sessions);
+        while let Some(session) = sessions.next().await {
+            tokio::spawn(session.unwrap());
+        }
+    });
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    let health = server.health();
+    assert_eq!(health.listener(), ListenerStatus::Listening);
+    assert!(health.is_ready());
+    assert_eq!(health.active_sessions(), 1);
+    assert_eq!(health.backlog(), 0);
+
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+        ],
+    );
+    writer
+        .write_all(b"Subject: test\r\n\r\nbody\r\n.\r\n")
+        .await?;
+
+    // Tests that a message is counted in the backlog until the handler takes it.
+    tokio::time::timeout(timeouts::EXPECTED, async {
+        while server.health().backlog() == 0 {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await?;
+    release.notify_one();
+    assert!(is_valid_response::ok(
+        &tokio::time::timeout(timeouts::EXPECTED, read_line!(reader)).await??
+    ));
+    assert_eq!(server.health().backlog(), 0);
+
+    // Tests that the totals of the server count the connection, the message, and rejections.
+    test_response!(
+        writer,
+        reader,
+        [("FOO", timeouts::EXPECTED, |reply: &str| reply
+            .starts_with("500"))],
+    );
+    let stats = server.stats();
+    assert_eq!(stats.connections(), 1);
+    assert_eq!(stats.messages(), 1);
+    assert_eq!(stats.rejections().get(&500), Some(&1));
+    assert_eq!(stats.total_rejections(), 1);
+
+    // Tests that the server is no longer ready once it shuts down.
+    test_response!(
+        writer,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+    tokio::time::timeout(
+        timeouts::EXPECTED,
+        server.shutdown().shutdown(Duration::ZERO),
+    )
+    .await?;
+    let health = server.health();
+    assert_eq!(health.listener(), ListenerStatus::Stopped);
+    assert!(!health.is_ready());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rejection_audit() -> Result {
+    use crate::audit::RejectionSource;
+
+    const ADDR: &str = "127.0.0.1:8146";
+
+    let server = ServerHandle::new();
+    let mut rejections = server.rejections();
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| Policy,
+        server,
+    ));
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            ("FOO", timeouts::EXPECTED, |reply: &str| reply
+                .starts_with("500")),
+            (
+                "MAIL FROM:<spammer@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::mailbox_unavailable,
+            ),
+        ],
+    );
+
+    // Tests that each rejection is published with the command and what triggered it.
+    let rejection = tokio::time::timeout(timeouts::EXPECTED, rejections.recv()).await??;
+    assert_eq!(rejection.code(), 500);
+    assert_eq!(rejection.command(), Some("FOO"));
+    assert_eq!(rejection.source(), RejectionSource::Protocol);
+    let rejection = tokio::time::timeout(timeouts::EXPECTED, rejections.recv()).await??;
+    assert_eq!(rejection.code(), 550);
+    assert_eq!(rejection.command(), Some("MAIL FROM:<spammer@example.com>"));
+    assert_eq!(rejection.source(), RejectionSource::Handler);
+    assert!(rejection.to_string().ends_with("(handler)"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_summary() -> Result {
+    use crate::session::CloseReason;
+
+    const ADDR: &str = "127.0.0.1:8147";
+
+    let sessions = crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    );
+    pin_mut!(sessions);
+
+    // Tests that joining a session tells how it ended.
+    let (greeted, session) = tokio::join!(greeted_session(ADDR), sessions.next());
+    let ((mut reader, mut writer), session) = (greeted?, session.unwrap()?);
+    test_response!(
+        writer,
+        reader,
+        [
+            ("FOO", timeouts::EXPECTED, |reply: &str| reply
+                .starts_with("500")),
+            ("NOOP", timeouts::EXPECTED, is_valid_response::ok),
+            ("BAR", timeouts::EXPECTED, |reply: &str| reply
+                .starts_with("500")),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+    let summary = tokio::time::timeout(timeouts::EXPECTED, session).await???;
+    assert_eq!(summary.close_reason(), &CloseReason::Quit);
+    assert_eq!(summary.messages_accepted(), 0);
+    assert_eq!(summary.errors(), 2);
+
+    // Tests that a client that hangs up is told apart from one that quits.
+    let (greeted, session) = tokio::join!(greeted_session(ADDR), sessions.next());
+    let session = session.unwrap()?;
+    drop(greeted?);
+    let summary = tokio::time::timeout(timeouts::EXPECTED, session).await???;
+    assert_eq!(summary.close_reason(), &CloseReason::ClosedByClient);
+    assert_eq!(summary.close_reason().to_string(), "closed_by_client");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_credential_redaction() -> Result {
+    use tokio::sync::mpsc;
+
+    use crate::{handler::Response, transcript::TranscriptTarget};
+
+    const ADDR: &str = "127.0.0.1:8148";
+    const CREDENTIAL: &str = "c2VjcmV0";
+
+    /// Challenges `AUTH LOGIN` and refuses the credential, sending the [`Debug`] output of each
+    /// command, and the transcript as the session closes.
+    struct Login(mpsc::UnboundedSender<String>);
+
+    impl SmtpHandler for Login {
+        async fn on_command(
+            &mut self,
+            _context: &mut SessionContext,
+            command: &Command<'_>,
+        ) -> HandlerResult {
+            let _ = self.0.send(format!("{command:?}"));
+
+            Ok(if command.is_credential() {
+                Decision::Reply(Response::new(535, "Authentication credentials invalid")?)
+            } else if command.verb() == "AUTH" {
+                Decision::Reply(Response::new(334, "VXNlcm5hbWU6")?)
+            } else {
+                Decision::Accept
+            })
+        }
+
+        async fn on_close(&mut self, context: &mut SessionContext) {
+            if let Some(transcript) = context.transcript() {
+                let _ = self.0.send(transcript.to_string());
+            }
+        }
+    }
+
+    let config = ServerConfig::builder()
+        .hostname("mx.example.com")
+        .transcripts(TranscriptTarget::Memory)
+        .build()?;
+    let server = ServerHandle::new();
+    let mut rejections = server.rejections();
+    let (sender, mut outputs) = mpsc::unbounded_channel();
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config,
+        move |_| Login(sender.clone()),
+        server,
+    ));
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            ("AUTH LOGIN", timeouts::EXPECTED, |reply: &str| reply
+                .starts_with("334")),
+            ("c2VjcmV0", timeouts::EXPECTED, |reply: &str| reply
+                .starts_with("535")),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    // Tests that the credential is left out of the commands, the transcript, and the rejection.
+    let mut output = String::new();
+    while !output.contains("221") {
+        let next = tokio::time::timeout(timeouts::EXPECTED, outputs.recv())
+            .await?
+            .expect("the sender is kept by the server");
+        output.push_str(&next);
+    }
+    assert!(output.contains("[redacted]"));
+    assert!(!output.contains(CREDENTIAL));
+    let rejection = tokio::time::timeout(timeouts::EXPECTED, rejections.recv()).await??;
+    assert_eq!(rejection.code(), 535);
+    assert_eq!(rejection.command(), Some("[redacted]"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_non_ascii_command() -> Result {
+    const ADDR: &str = "127.0.0.1:8149";
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    // Tests that bytes that are not UTF-8 are refused by the parser, rather than ending the
+    // session.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    writer.write_all(b"NO\xffOP\r\n").await?;
+    let reply = tokio::time::timeout(timeouts::EXPECTED, read_line!(reader)).await??;
+    assert!(reply.starts_with("500"), "{reply}");
+    test_response!(
+        writer,
+        reader,
+        [
+            ("NOOP", timeouts::EXPECTED, is_valid_response::ok),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_max_connections() -> Result {
+    const REFUSE_ADDR: &str = "127.0.0.1:8099";
+    const WAIT_ADDR: &str = "127.0.0.1:8100";
+    const FIRST_ADDR: &str = "127.0.0.1:8150";
+    const SECOND_ADDR: &str = "127.0.0.1:8151";
+
+    for (addr, overflow) in [
+        (REFUSE_ADDR, ConnectionOverflow::Refuse),
+        (WAIT_ADDR, ConnectionOverflow::Wait),
+    ] {
+        let config = ServerConfig::builder()
+            .hostname("mx.example.com")
+            .max_connections(1)
+            .connection_overflow(overflow)
+            .build()?;
+        spawn_sessions(crate::listen(
+            TcpListener::bind(addr).await?,
+            config,
+            |_| AcceptAll,
+            Shutdown::new(),
+        ));
+    }
+
+    // Tests that connections past the limit are refused with `421`.
+    let (mut reader, mut writer) = greeted_session(REFUSE_ADDR).await?;
+    let mut refused = BufReader::new(TcpStream::connect(REFUSE_ADDR).await?);
+    assert!(is_valid_response::service_unavailable(
+        &read_line!(refused).await?
+    ));
+    test_response!(
+        writer,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    greeted_session(REFUSE_ADDR).await?;
+
+    // Tests that connections past the limit wait until a session closes.
+    let (mut reader, mut writer) = greeted_session(WAIT_ADDR).await?;
+    let mut waiting = BufReader::new(TcpStream::connect(WAIT_ADDR).await?);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), read_line!(waiting))
+            .await
+            .is_err()
+    );
+    test_response!(
+        writer,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+    assert!(is_valid_response::server_greeting(
+        &tokio::time::timeout(timeouts::EXPECTED, read_line!(waiting)).await??
+    ));
+
+    // Tests that a listener that was already accepting when another took the last session waits
+    // too, rather than refusing.
+    spawn_sessions(crate::listen_all(
+        [
+            Listener::new(TcpListener::bind(FIRST_ADDR).await?),
+            Listener::new(TcpListener::bind(SECOND_ADDR).await?),
+        ],
+        ServerConfig::builder()
+            .hostname("mx.example.com")
+            .max_connections(1)
+            .connection_overflow(ConnectionOverflow::Wait)
+            .build()?,
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+    let (mut reader, mut writer) = greeted_session(FIRST_ADDR).await?;
+    let mut waiting = BufReader::new(TcpStream::connect(SECOND_ADDR).await?);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), read_line!(waiting))
+            .await
+            .is_err()
+    );
+    test_response!(
+        writer,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+    assert!(is_valid_response::server_greeting(
+        &tokio::time::timeout(timeouts::EXPECTED, read_line!(waiting)).await??
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_limits() -> Result {
+    const CONNECTIONS_ADDR: &str = "127.0.0.1:8101";
+    const RATE_ADDR: &str = "127.0.0.1:8102";
+    const TRUSTED_ADDR: &str = "127.0.0.1:8103";
+
+    let limits = PeerLimits::unlimited()
+        .with_max_connections(1)
+        .with_max_rate(2, Duration::from_mins(1));
+    let loopback = Network::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8)?;
+    for (addr, network_limits) in [
+        (CONNECTIONS_ADDR, None),
+        (RATE_ADDR, None),
+        (TRUSTED_ADDR, Some(PeerLimits::unlimited())),
+    ] {
+        let builder = ServerConfig::builder()
+            .hostname("mx.example.com")
+            .peer_limits(limits);
+        let builder = match network_limits {
+            Some(network_limits) => builder.network_limits(loopback, network_limits),
+            None => builder,
+        };
+        spawn_sessions(crate::listen(
+            TcpListener::bind(addr).await?,
+            builder.build()?,
+            |_| AcceptAll,
+            Shutdown::new(),
+        ));
+    }
+
+    // Tests that a second session at once from the same address is refused.
+    let (mut reader, mut writer) = greeted_session(CONNECTIONS_ADDR).await?;
+    let mut refused = BufReader::new(TcpStream::connect(CONNECTIONS_ADDR).await?);
+    assert!(is_valid_response::service_unavailable(
+        &read_line!(refused).await?
+    ));
+    test_response!(
+        writer,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+
+    // Tests that a third connection in the period is refused, even after the others closed.
+    for _ in 0..2 {
+        let (mut reader, mut writer) = greeted_session(RATE_ADDR).await?;
+        test_response!(
+            writer,
+            reader,
+            [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let mut refused = BufReader::new(TcpStream::connect(RATE_ADDR).await?);
+    assert!(is_valid_response::service_unavailable(
+        &read_line!(refused).await?
+    ));
+
+    // Tests that trusted networks are held to their own limits.
+    let _first = greeted_session(TRUSTED_ADDR).await?;
+    let _second = greeted_session(TRUSTED_ADDR).await?;
+    let _third = greeted_session(TRUSTED_ADDR).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_helo_policy() -> Result {
+    const ADDR: &str = "127.0.0.1:8104";
+
+    let policy = HeloPolicy::accept_all()
+        .with_syntax(HeloAction::Reject)
+        .with_matches_peer(HeloAction::Reject);
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        ServerConfig::builder()
+            .hostname("mx.example.com")
+            .helo_policy(policy)
+            .build()?,
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    // Tests that rejected identities leave the client to try again.
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "HELO",
+                timeouts::EXPECTED,
+                is_valid_response::argument_error
+            ),
+            (
+                "HELO plus+.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::argument_error
+            ),
+            (
+                "HELO [192.0.2.1]",
+                timeouts::EXPECTED,
+                is_valid_response::action_not_taken
+            ),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::bad_sequence
+            ),
+            (
+                "HELO [127.0.0.1]",
+                timeouts::EXPECTED,
+                is_valid_response::helo
+            ),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_config_reload() -> Result {
+    const ADDR: &str = "127.0.0.1:8105";
+
+    let config = ConfigHandle::new(
+        ServerConfig::builder()
+            .hostname("mx.example.com")
+            .quit_text("Bye")
+            .build()?,
+    );
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config.clone(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    let (mut old_reader, mut old_writer) = greeted_session(ADDR).await?;
+
+    let previous = config.replace(
+        ServerConfig::builder()
+            .hostname("mx2.example.com")
+            .quit_text("Later")
+            .build()?,
+    );
+    assert_eq!(previous.hostname(), "mx.example.com");
+    assert_eq!(config.current().hostname(), "mx2.example.com");
+
+    // Tests that new sessions follow the replacement.
+    let mut reader = BufReader::new(TcpStream::connect(ADDR).await?);
+    assert_eq!(
+        read_line!(reader).await?,
+        "220 mx2.example.com ESMTP service ready\r\n"
+    );
+
+    // Tests that sessions in progress keep the configuration they started with.
+    crate::write_line!(old_writer, "QUIT")?;
+    assert_eq!(read_line!(old_reader).await?, "221 Bye\r\n");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_access_rules() -> Result {
+    const ADDR: &str = "127.0.0.1:8112";
+
+    let loopback = Network::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 32)?;
+    let with_access = |access| {
+        ServerConfig::builder()
+            .hostname("mx.example.com")
+            .access(loopback, access)
+            .build()
+    };
+    let config = ConfigHandle::new(with_access(Access::Reject)?);
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config.clone(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    // Tests that rejected clients are refused in place of the greeting.
+    let mut reader = BufReader::new(TcpStream::connect(ADDR).await?);
+    assert!(is_valid_response::no_service(&read_line!(reader).await?));
+
+    // Tests that dropped clients are disconnected without a reply.
+    config.replace(with_access(Access::Drop)?);
+    let mut reader = BufReader::new(TcpStream::connect(ADDR).await?);
+    assert_eq!(
+        read_line!(reader).await.map_err(|err| err.kind()),
+        Err(std::io::ErrorKind::ConnectionAborted)
+    );
+
+    // Tests that the first network that contains the client decides, and that other clients get
+    // the default.
+    config.replace(
+        ServerConfig::builder()
+            .hostname("mx.example.com")
+            .access(loopback, Access::Accept)
+            .access(
+                Network::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 0)), 8)?,
+                Access::Drop,
+            )
+            .default_access(Access::Drop)
+            .build()?,
+    );
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_command_limits() -> Result {
+    const ADDR: &str = "127.0.0.1:8113";
+    const DELAY: Duration = Duration::from_millis(200);
+
+    let with_limits = |limits| {
+        ServerConfig::builder()
+            .hostname("mx.example.com")
+            .command_limits(limits)
+            .build()
+    };
+    let config = ConfigHandle::new(with_limits(
+        CommandLimits::unlimited()
+            .with_max_errors(3)
+            .with_error_delay(DELAY),
+    )?);
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config.clone(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    // Tests that a successful command resets the count of errors in a row, and that replies are
+    // delayed after an error.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            ("FOO", timeouts::EXPECTED, is_valid_response::syntax_error),
+            ("MAIL FROM:", DELAY * 2, is_valid_response::argument_error),
+            ("NOOP", DELAY * 2, is_valid_response::ok),
+        ],
+    );
+    let started = tokio::time::Instant::now();
+    test_response!(
+        writer,
+        reader,
+        [
+            ("FOO", timeouts::EXPECTED, is_valid_response::syntax_error),
+            ("FOO", DELAY * 2, is_valid_response::syntax_error),
+        ],
+    );
+    assert!(started.elapsed() >= DELAY);
+
+    // Tests that the session is closed once the client makes too many errors in a row.
+    test_response!(
+        writer,
+        reader,
+        [("FOO", DELAY * 2, is_valid_response::syntax_error)],
+    );
+    assert!(is_valid_response::service_unavailable(
+        &read_line!(reader).await?
+    ));
+    assert_eq!(
+        read_line!(reader).await.map_err(|err| err.kind()),
+        Err(std::io::ErrorKind::ConnectionAborted)
+    );
+
+    // Tests that the session is closed once the client sends commands too quickly.
+    config.replace(with_limits(
+        CommandLimits::unlimited().with_max_command_rate(3, Duration::from_mins(1)),
+    )?);
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            ("NOOP", timeouts::EXPECTED, is_valid_response::ok),
+            ("NOOP", timeouts::EXPECTED, is_valid_response::ok),
+            (
+                "NOOP",
+                timeouts::EXPECTED,
+                is_valid_response::service_unavailable,
+            ),
+        ],
+    );
+
+    Ok(())
+}
+
+/// Refuses every credential given with `AUTH`.
+struct RefuseCredentials;
+
+impl SmtpHandler for RefuseCredentials {
+    async fn on_command(
+        &mut self,
+        _context: &mut SessionContext,
+        command: &Command<'_>,
+    ) -> HandlerResult {
+        Ok(if command.verb() == "AUTH" {
+            Decision::Reply(Response::new(535, "Authentication credentials invalid")?)
+        } else {
+            Decision::Accept
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_auth_limits() -> Result {
+    const ADDR: &str = "127.0.0.1:8152";
+    const DELAY: Duration = Duration::from_millis(100);
+
+    let is_failed = |reply: &str| reply.starts_with("535");
+    let config = ServerConfig::builder()
+        .hostname("mx.example.com")
+        .auth_limits(
+            AuthLimits::unlimited()
+                .with_max_failures(3)
+                .with_failure_delay(DELAY),
+        )
+        .build()?;
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config,
+        |_| RefuseCredentials,
+        Shutdown::new(),
+    ));
+
+    // Tests that replies are delayed longer after each failure, and that the session is closed
+    // once the client fails too many times.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    let started = tokio::time::Instant::now();
+    test_response!(
+        writer,
+        reader,
+        [
+            ("AUTH PLAIN AGEAYg==", timeouts::EXPECTED, is_failed),
+            ("AUTH PLAIN AGEAYg==", DELAY * 2, is_failed),
+            ("AUTH PLAIN AGEAYg==", DELAY * 3, is_failed),
+        ],
+    );
+    assert!(started.elapsed() >= DELAY * 3);
+    assert!(is_valid_response::service_unavailable(
+        &read_line!(reader).await?
+    ));
+    assert_eq!(
+        read_line!(reader).await.map_err(|err| err.kind()),
+        Err(std::io::ErrorKind::ConnectionAborted)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_auth_bans() -> Result {
+    use crate::audit::RejectionSource;
+
+    const ADDR: &str = "127.0.0.1:8153";
+
+    let is_failed = |reply: &str| reply.starts_with("535");
+    let config = ServerConfig::builder()
+        .hostname("mx.example.com")
+        .auth_limits(
+            AuthLimits::unlimited()
+                .with_max_failure_rate(2, Duration::from_mins(1))
+                .with_ban_duration(Duration::from_hours(1)),
+        )
+        .build()?;
+    let server = ServerHandle::new();
+    let mut rejections = server.rejections();
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config,
+        |_| RefuseCredentials,
+        server,
+    ));
+
+    // Tests that failures are counted across the sessions from an address, which is then banned.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            ("AUTH PLAIN AGEAYg==", timeouts::EXPECTED, is_failed),
+            ("AUTH PLAIN AGEAYg==", timeouts::EXPECTED, is_failed),
+        ],
+    );
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [("AUTH PLAIN AGEAYg==", timeouts::EXPECTED, is_failed)],
+    );
+    assert!(is_valid_response::service_unavailable(
+        &read_line!(reader).await?
+    ));
+    let mut refused = BufReader::new(TcpStream::connect(ADDR).await?);
+    assert!(is_valid_response::service_unavailable(
+        &read_line!(refused).await?
+    ));
+
+    // Tests that the lockout and the refusal are sent as rejections of `auth_limits`.
+    let mut lockouts = Vec::new();
+    while lockouts.len() < 2 {
+        let rejection = tokio::time::timeout(timeouts::EXPECTED, rejections.recv()).await??;
+        if rejection.source() == RejectionSource::Limit("auth_limits") {
+            lockouts.push(rejection.session_id().is_some());
+        }
+    }
+    assert_eq!(lockouts, [true, false]);
+
+    Ok(())
+}
+
+/// Authenticates any client that sends `AUTH`.
+struct AcceptCredentials;
+
+impl SmtpHandler for AcceptCredentials {
+    async fn on_command(
+        &mut self,
+        context: &mut SessionContext,
+        command: &Command<'_>,
+    ) -> HandlerResult {
+        Ok(if command.verb() == "AUTH" {
+            context.authenticate("smith");
+            Decision::Reply(Response::new(235, "Authentication successful")?)
+        } else {
+            Decision::Accept
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_required_auth() -> Result {
+    const RELAY_ADDR: &str = "127.0.0.1:8156";
+    const SUBMISSION_ADDR: &str = "127.0.0.1:8157";
+
+    spawn_sessions(crate::listen_all(
+        [
+            Listener::new(TcpListener::bind(RELAY_ADDR).await?),
+            Listener::new(TcpListener::bind(SUBMISSION_ADDR).await?).with_required_auth(),
+        ],
+        config(),
+        |_| AcceptCredentials,
+        Shutdown::new(),
+    ));
+
+    // Tests that only the listener that requires authentication rejects mail without it.
+    let (mut reader, mut writer) = greeted_session(RELAY_ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [(
+            "MAIL FROM:<smith@example.com>",
+            timeouts::MAIL,
+            is_valid_response::ok
+        )],
+    );
+    let (mut reader, mut writer) = greeted_session(SUBMISSION_ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::auth_required
+            ),
+            ("AUTH PLAIN AGEAYg==", timeouts::EXPECTED, |reply: &str| {
+                reply.starts_with("235")
+            }),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tarpit() -> Result {
+    const ADDR: &str = "127.0.0.1:8114";
+
+    /// Flags every client that greets the server as `spammer.example.com`.
+    struct FlagSpammers;
+
+    impl SmtpHandler for FlagSpammers {
+        async fn on_ehlo(
+            &mut self,
+            context: &mut SessionContext,
+            identity: &AsciiStr,
+        ) -> HandlerResult {
+            if identity.as_str() == "spammer.example.com" {
+                context.tarpit();
+            }
+
+            Ok(Decision::Accept)
+        }
+    }
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        ServerConfig::builder()
+            .tarpit(
+                Tarpit::default()
+                    .with_initial_delay(Duration::from_millis(100))
+                    .with_max_delay(Duration::from_millis(200))
+                    .with_max_duration(Duration::from_millis(400)),
+            )
+            .build()?,
+        |_| FlagSpammers,
+        Shutdown::new(),
+    ));
+
+    let (read_stream, mut writer) = TcpStream::connect(ADDR).await?.into_split();
+    let mut reader = BufReader::new(read_stream);
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    // Tests that replies are delayed for longer and longer once the client is flagged.
+    let started = tokio::time::Instant::now();
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "HELO spammer.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::helo,
+            ),
+            ("NOOP", timeouts::EXPECTED, is_valid_response::ok),
+            ("NOOP", timeouts::EXPECTED, is_valid_response::ok),
+            ("NOOP", timeouts::EXPECTED, is_valid_response::ok),
+        ],
+    );
+    assert!(started.elapsed() >= Duration::from_millis(500));
+
+    // Tests that the session is closed once it has been delayed for long enough.
+    test_response!(
+        writer,
+        reader,
+        [(
+            "NOOP",
+            timeouts::EXPECTED,
+            is_valid_response::service_unavailable,
+        )],
+    );
+
+    // Tests that other clients are not delayed.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    let started = tokio::time::Instant::now();
+    test_response!(
+        writer,
+        reader,
+        [("NOOP", timeouts::EXPECTED, is_valid_response::ok)],
+    );
+    assert!(started.elapsed() < Duration::from_millis(100));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_early_talkers() -> Result {
+    const ADDR: &str = "127.0.0.1:8115";
+    const DELAY: Duration = Duration::from_millis(200);
+
+    /// Rejects the greetings of clients that talked before they were greeted.
+    struct RejectEarlyTalkers;
+
+    impl SmtpHandler for RejectEarlyTalkers {
+        async fn on_ehlo(&mut self, context: &mut SessionContext, _: &AsciiStr) -> HandlerResult {
+            Ok(if context.session().is_early_talker() {
+                Decision::Reject
+            } else {
+                Decision::Accept
+            })
+        }
+    }
+
+    let with_rejection = |reject| {
+        ServerConfig::builder()
+            .greeting_delay(DELAY)
+            .reject_early_talkers(reject)
+            .build()
+    };
+    let config = ConfigHandle::new(with_rejection(false)?);
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config.clone(),
+        |_| RejectEarlyTalkers,
+        Shutdown::new(),
+    ));
+
+    // Tests that clients that wait for the greeting are greeted after the delay.
+    let started = tokio::time::Instant::now();
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    assert!(started.elapsed() >= DELAY);
+    test_response!(
+        writer,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+
+    // Tests that early talkers are noted for the handler, and that what they sent is still read.
+    let (read_stream, mut writer) = TcpStream::connect(ADDR).await?.into_split();
+    let mut reader = BufReader::new(read_stream);
+    crate::write_line!(writer, "HELO client.example.com")?;
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+    assert!(is_valid_response::action_not_taken(
+        &read_line!(reader).await?
+    ));
+
+    // Tests that early talkers are refused in place of the greeting.
+    config.replace(with_rejection(true)?);
+    let (read_stream, mut writer) = TcpStream::connect(ADDR).await?.into_split();
+    let mut reader = BufReader::new(read_stream);
+    crate::write_line!(writer, "HELO client.example.com")?;
+    assert!(is_valid_response::no_service(&read_line!(reader).await?));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_null_sender_policy() -> Result {
+    const ADDR: &str = "127.0.0.1:8116";
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        ServerConfig::builder()
+            .null_sender_policy(
+                NullSenderPolicy::unlimited()
+                    .with_max_recipients(1)
+                    .with_max_transactions(1),
+            )
+            .build()?,
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            // Tests that the null reverse-path is held to its own recipient limit.
+            ("MAIL FROM:<>", timeouts::EXPECTED, is_valid_response::ok),
+            (
+                "RCPT TO:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::too_many_recipients,
+            ),
+            ("RSET", timeouts::EXPECTED, is_valid_response::ok),
+            // Tests that transactions with the null reverse-path are deferred past the limit.
+            (
+                "MAIL FROM:<>",
+                timeouts::EXPECTED,
+                is_valid_response::too_many_null_senders,
+            ),
+            // Tests that other reverse-paths are not limited.
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+        ],
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "hickory")]
+#[test]
+fn test_hickory_names() -> Result {
+    use hickory_resolver::Name;
+
+    // Tests that the labels of internationalized names stay in punycode, as the names that a
+    // resolver gives are ASCII, rather than being decoded like when they are displayed.
+    let name = Name::from_ascii("xn--bcher-kva.example.")?;
+    assert_eq!(name.to_string(), "bücher.example.");
+    assert_eq!(
+        crate::resolver::hickory_name(&name),
+        "xn--bcher-kva.example"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sender_domain() -> Result {
+    use crate::{config::SenderDomainPolicy, resolver::Resolver};
+
+    const ADDR: &str = "127.0.0.1:8132";
+
+    /// Only gives `example.com` an `MX` record, and fails lookups of `fail.example.com`.
+    struct Stub;
+
+    impl Resolver for Stub {
+        async fn lookup_ip(&self, name: &str) -> std::io::Result<Vec<IpAddr>> {
+            if name == "fail.example.com" {
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            Ok(Vec::new())
+        }
+
+        async fn lookup_mx(&self, name: &str) -> std::io::Result<Vec<String>> {
+            Ok(if name == "example.com" {
+                vec!["mail.example.com".to_owned()]
+            } else {
+                Vec::new()
+            })
+        }
+    }
+
+    let (sessions, _messages) = crate::listen_channel(
+        TcpListener::bind(ADDR).await?,
+        ServerConfig::builder()
+            .resolver(Stub)
+            .sender_domain_policy(SenderDomainPolicy::Reject)
+            .build()?,
+        Shutdown::new(),
+    );
+    spawn_sessions(sessions);
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            // Tests that senders at missing domains are rejected, and that failed lookups are
+            // deferred.
+            (
+                "MAIL FROM:<smith@missing.example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::sender_domain_missing,
+            ),
+            (
+                "MAIL FROM:<smith@fail.example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::sender_domain_unverified,
+            ),
+            // Tests that existing domains and the null reverse-path are accepted.
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("RSET", timeouts::EXPECTED, is_valid_response::ok),
+            ("MAIL FROM:<>", timeouts::EXPECTED, is_valid_response::ok),
+        ],
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "directory")]
+#[tokio::test]
+async fn test_address_verifier() -> Result {
+    use crate::directory::{AddressVerifier, StaticVerifier};
+
+    const ADDR: &str = "127.0.0.1:8133";
+
+    /// Fails lookups of `fail@example.com`, and looks up the rest in a [`StaticVerifier`].
+    struct Stub(StaticVerifier);
+
+    impl AddressVerifier for Stub {
+        async fn verify(&self, address: &str) -> std::io::Result<bool> {
+            if address == "fail@example.com" {
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            self.0.verify(address).await
+        }
+    }
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        ServerConfig::builder()
+            .address_verifier(Stub(
+                StaticVerifier::new().with_address("jones@example.com"),
+            ))
+            .build()?,
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            // Tests that recipients with a mailbox and `postmaster` are accepted, that those
+            // without one are rejected, and that failed lookups are deferred.
+            (
+                "RCPT TO:<Jones@Example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<postmaster@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<green@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::user_unknown,
+            ),
+            (
+                "RCPT TO:<fail@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::address_unverified,
+            ),
+            // Tests that `VRFY` answers from the verifier, and cannot verify user names.
+            (
+                "VRFY <jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "VRFY green@example.com",
+                timeouts::EXPECTED,
+                is_valid_response::user_unknown,
+            ),
+            (
+                "VRFY fail@example.com",
+                timeouts::EXPECTED,
+                is_valid_response::address_unverified,
+            ),
+            (
+                "VRFY jones",
+                timeouts::EXPECTED,
+                is_valid_response::cannot_verify
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ("body\r\n.", timeouts::EXPECTED, is_valid_response::ok),
+        ],
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "spool")]
+#[tokio::test]
+async fn test_spool() -> Result {
+    use crate::spool::Spool;
+
+    const ADDR: &str = "127.0.0.1:8134";
+    const REJECTING_ADDR: &str = "127.0.0.1:8135";
+
+    /// Rejects every message.
+    struct RejectMessages;
+
+    impl SmtpHandler for RejectMessages {
+        async fn on_message(&mut self, _: &mut SessionContext, _: Message) -> HandlerResult {
+            Ok(Decision::Reject)
+        }
+    }
+
+    let path = std::env::temp_dir().join("smtp_gateway_test_spool_sessions");
+    let _ = std::fs::remove_dir_all(&path);
+    let spool = Spool::open(&path)?;
+    let left = spool
+        .write(
+            &Message::builder()
+                .forward_path("jones@example.com")
+                .build()?,
+        )
+        .await?;
+    let config = ServerConfig::builder().spool(spool.clone()).build()?;
+
+    let (sessions, mut messages) = crate::listen_channel(
+        TcpListener::bind(ADDR).await?,
+        config.clone(),
+        Shutdown::new(),
+    );
+    spawn_sessions(sessions);
+
+    // Tests that the message left in the spool is sent to the consumer as the server starts.
+    let replayed = tokio::time::timeout(timeouts::EXPECTED, messages.recv())
+        .await?
+        .expect("the sender is kept by the server");
+    assert_eq!(replayed.spool_id(), Some(&left));
+    spool.remove(&left).await?;
+
+    // Tests that a received message is in the spool once the client is told that it was received,
+    // until the consumer takes it out.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ("body\r\n.", timeouts::EXPECTED, is_valid_response::ok),
+        ],
+    );
+    let pending = spool.pending().await?;
+    assert_eq!(pending.len(), 1);
+    let message = messages
+        .recv()
+        .await
+        .expect("the sender is kept by the server");
+    assert_eq!(message.spool_id(), pending[0].spool_id());
+    assert_eq!(message.data(), pending[0].data());
+    spool
+        .remove(message.spool_id().expect("the message was spooled"))
+        .await?;
+
+    // Tests that messages that the handler does not accept are taken out of the spool again.
+    spawn_sessions(crate::listen(
+        TcpListener::bind(REJECTING_ADDR).await?,
+        config,
+        |_| RejectMessages,
+        Shutdown::new(),
+    ));
+    let (mut reader, mut writer) = greeted_session(REJECTING_ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            (
+                "body\r\n.",
+                timeouts::EXPECTED,
+                is_valid_response::transaction_failed,
+            ),
+        ],
+    );
+    assert!(spool.pending().await?.is_empty());
+
+    let _ = std::fs::remove_dir_all(&path);
+
+    Ok(())
+}
+
+#[cfg(feature = "s3")]
+#[tokio::test]
+async fn test_s3() -> Result {
+    use std::sync::{Arc, Mutex};
+
+    use crate::sink::S3Sink;
+
+    const ADDR: &str = "127.0.0.1:8136";
+    const S3_ADDR: &str = "127.0.0.1:8137";
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    spawn_s3(TcpListener::bind(S3_ADDR).await?, requests.clone());
+    let sink = S3Sink::new(&format!("http://{S3_ADDR}"), "mail")?
+        .with_credentials("access", "secret")
+        .with_prefix("archive/")
+        .with_part_size(1024);
+    let (sessions, mut messages) = crate::listen_streaming(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    );
+    spawn_sessions(sessions);
+
+    let consumer = tokio::spawn(async move {
+        let mut keys = Vec::new();
+        for _ in 0..2 {
+            let message = messages.recv().await.unwrap();
+            keys.push(sink.deliver_streaming(message).await.unwrap());
+        }
+        keys
+    });
+
+    // Tests that short messages are uploaded at once, and longer ones in parts.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    let long = "long\r\n".repeat(500);
+    for data in ["short\r\n", long.as_str()] {
+        test_response!(
+            writer,
+            reader,
+            [
+                (
+                    "MAIL FROM:<smith@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                (
+                    "RCPT TO:<jones@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ],
+        );
+        writer.write_all(format!("{data}.\r\n").as_bytes()).await?;
+        assert!(is_valid_response::ok(
+            &tokio::time::timeout(timeouts::DATA_TERMINATION, read_line!(reader)).await??
+        ));
+    }
+
+    let keys = consumer.await?;
+    assert!(keys.iter().all(|key| key.starts_with("archive/")));
+    assert_ne!(keys[0], keys[1]);
+
+    let requests = requests.lock().unwrap().clone();
+    let (method, target, body) = &requests[0];
+    assert_eq!(
+        (method.as_str(), target.as_str()),
+        ("PUT", &*format!("/mail/{}", keys[0]))
+    );
+    assert!(body.starts_with(b"Received: ") && body.ends_with(b"\r\nshort\r\n"));
+
+    let upload = format!("/mail/{}", keys[1]);
+    assert_eq!(requests[1].0, "POST");
+    assert_eq!(requests[1].1, format!("{upload}?uploads="));
+    let parts = &requests[2..requests.len() - 1];
+    assert!(parts.len() > 1);
+    let mut data = Vec::new();
+    for (number, (method, target, body)) in parts.iter().enumerate() {
+        assert_eq!(method, "PUT");
+        assert_eq!(
+            *target,
+            format!("{upload}?partNumber={}&uploadId=upload-1", number + 1)
+        );
+        data.extend_from_slice(body);
+    }
+    assert!(data.ends_with(long.as_bytes()));
+    let (method, target, body) = &requests[requests.len() - 1];
+    assert_eq!(method, "POST");
+    assert_eq!(*target, format!("{upload}?uploadId=upload-1"));
+    assert!(String::from_utf8_lossy(body)
+        .contains(&format!("<PartNumber>{}</PartNumber>", parts.len())));
+
+    Ok(())
+}
+
+/// The method, target, and body of each request that [`spawn_s3`] received.
+#[cfg(feature = "s3")]
+type S3Requests = std::sync::Arc<std::sync::Mutex<Vec<(String, String, Vec<u8>)>>>;
+
+/// Respond to requests like an S3 bucket that accepts every upload, keeping each in `requests`.
+///
+/// # Panics
+///
+/// The background task panics if a request is not signed, or if a request that creates an object
+/// would replace one.
+#[cfg(feature = "s3")]
+fn spawn_s3(s3: TcpListener, requests: S3Requests) {
+    use tokio::io::AsyncReadExt;
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = s3.accept().await {
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            let _ = reader.read_line(&mut request_line).await;
+            let mut length = 0;
+            let mut signed = false;
+            let mut exclusive = false;
+            let mut line = String::new();
+            while reader.read_line(&mut line).await.is_ok_and(|read| read > 2) {
+                if let Some(value) = line.strip_prefix("content-length: ") {
+                    length = value.trim().parse().unwrap_or_default();
+                }
+                signed |= line.starts_with("authorization: AWS4-HMAC-SHA256 Credential=access/");
+                exclusive |= line == "if-none-match: *\r\n";
+                line.clear();
+            }
+            assert!(signed);
+            let creates = request_line.starts_with("PUT") && !request_line.contains('?')
+                || request_line.starts_with("POST") && request_line.contains("?uploadId=");
+            assert_eq!(exclusive, creates, "{request_line}");
+            let mut body = vec![0; length];
+            let _ = reader.read_exact(&mut body).await;
+
+            let mut parts = request_line.split(' ');
+            let method = parts.next().unwrap_or_default().to_owned();
+            let target = parts.next().unwrap_or_default().to_owned();
+            let response = if target.ends_with("?uploads=") {
+                "HTTP/1.1 200 OK\r\nContent-Length: 47\r\n\r\n<Result><UploadId>upload-1</UploadId></Result>"
+            } else if target.contains("partNumber=") {
+                "HTTP/1.1 200 OK\r\nETag: \"part\"\r\nContent-Length: 0\r\n\r\n"
+            } else {
+                "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+            };
+            requests.lock().unwrap().push((method, target, body));
+            let _ = reader.into_inner().write_all(response.as_bytes()).await;
+        }
+    });
+}
+
+#[cfg(feature = "webhook")]
+#[tokio::test]
+async fn test_webhook() -> Result {
+    use std::sync::{Arc, Mutex};
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    use crate::sink::WebhookSink;
+
+    const ADDR: &str = "127.0.0.1:8138";
+    const WEBHOOK_ADDR: &str = "127.0.0.1:8139";
+
+    let bodies = Arc::new(Mutex::new(Vec::new()));
+    spawn_webhook(TcpListener::bind(WEBHOOK_ADDR).await?, bodies.clone());
+    let sink = WebhookSink::new(&format!("http://{WEBHOOK_ADDR}/inbound"))?
+        .with_backoff(Duration::from_millis(10))
+        .with_defer_reply(true);
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        move |_| sink.clone(),
+        Shutdown::new(),
+    ));
+
+    // Tests that failed requests are retried, and that refused messages are rejected.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    for (data, is_valid_end) in [
+        ("hello\r\n", is_valid_response::ok as fn(&str) -> bool),
+        ("refuse\r\n", is_valid_response::transaction_failed),
+    ] {
+        test_response!(
+            writer,
+            reader,
+            [
+                (
+                    "MAIL FROM:<smith@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                (
+                    "RCPT TO:<jones@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ],
+        );
+        writer.write_all(format!("{data}.\r\n").as_bytes()).await?;
+        assert!(is_valid_end(
+            &tokio::time::timeout(timeouts::DATA_TERMINATION, read_line!(reader)).await??
+        ));
+    }
+
+    let bodies = bodies.lock().unwrap().clone();
+    assert_eq!(bodies.len(), 3);
+    assert_eq!(bodies[0], bodies[1]);
+    let metadata: serde_json::Value = serde_json::from_slice(&bodies[0])?;
+    assert_eq!(metadata["reverse_path"], "smith@example.com");
+    assert_eq!(metadata["recipients"][0], "jones@example.com");
+    let data = STANDARD.decode(metadata["data"].as_str().unwrap_or_default())?;
+    assert!(data.ends_with(b"\r\nhello\r\n"));
+
+    Ok(())
+}
+
+/// Respond to requests like a webhook that fails the first request, refuses messages that contain
+/// `refuse`, and accepts every other one, keeping the body of each request in `bodies`.
+#[cfg(feature = "webhook")]
+fn spawn_webhook(webhook: TcpListener, bodies: std::sync::Arc<std::sync::Mutex<Vec<Vec<u8>>>>) {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use tokio::io::AsyncReadExt;
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = webhook.accept().await {
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).await.is_ok_and(|read| read > 2) {
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap_or_default();
+                }
+                line.clear();
+            }
+            let mut body = vec![0; length];
+            let _ = reader.read_exact(&mut body).await;
+
+            let data = serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|metadata| STANDARD.decode(metadata["data"].as_str()?).ok())
+                .unwrap_or_default();
+            let first = {
+                let mut bodies = bodies.lock().unwrap();
+                bodies.push(body);
+                bodies.len() == 1
+            };
+            let status = if first {
+                "503 Service Unavailable"
+            } else if String::from_utf8_lossy(&data).contains("refuse") {
+                "400 Bad Request"
+            } else {
+                "200 OK"
+            };
+            let _ = reader
+                .into_inner()
+                .write_all(format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").as_bytes())
+                .await;
+        }
+    });
+}
+
+#[cfg(feature = "lmtp")]
+#[tokio::test]
+async fn test_lmtp() -> Result {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{message::envelope::RecipientStatus, sink::LmtpSink, Message};
+
+    const ADDR: &str = "127.0.0.1:8140";
+    const LMTP_ADDR: &str = "127.0.0.1:8141";
+
+    let data = Arc::new(Mutex::new(Vec::new()));
+    spawn_lmtp(TcpListener::bind(LMTP_ADDR).await?, data.clone());
+    let sink = LmtpSink::new(LMTP_ADDR).with_hostname("gateway.example.com");
+
+    // Tests that each recipient gets the reply of the LMTP server for it.
+    let mut message = Message::builder()
+        .reverse_path("smith@example.com")
+        .forward_path("jones@example.com")
+        .forward_path("nobody@example.com")
+        .forward_path("full@example.com")
+        .body(".hidden\r\nhello")
+        .build()?;
+    let replies = sink.relay(&mut message).await?;
+    let codes: Vec<_> = replies.iter().map(crate::handler::Response::code).collect();
+    assert_eq!(codes, [250, 550, 452]);
+    let statuses: Vec<_> = message
+        .envelope()
+        .recipients()
+        .iter()
+        .map(crate::message::envelope::Recipient::status)
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            RecipientStatus::Accepted,
+            RecipientStatus::Rejected,
+            RecipientStatus::Deferred,
+        ]
+    );
+    assert_eq!(data.lock().unwrap()[0], "..hidden\r\nhello\r\n.\r\n");
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(),
+        move |_| sink.clone(),
+        Shutdown::new(),
+    ));
+
+    // Tests that messages are accepted if any recipient takes them, and get the reply of the LMTP
+    // server otherwise.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    for (recipients, is_valid_end) in [
+        (
+            &["jones@example.com", "full@example.com"][..],
+            is_valid_response::ok as fn(&str) -> bool,
+        ),
+        (
+            &["full@example.com"],
+            is_valid_response::too_many_recipients,
+        ),
+        (
+            &["nobody@example.com"],
+            is_valid_response::mailbox_unavailable,
+        ),
+    ] {
+        test_response!(
+            writer,
+            reader,
+            [(
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            )],
+        );
+        for recipient in recipients {
+            writer
+                .write_all(format!("RCPT TO:<{recipient}>\r\n").as_bytes())
+                .await?;
+            assert!(is_valid_response::ok(
+                &tokio::time::timeout(timeouts::EXPECTED, read_line!(reader)).await??
+            ));
+        }
+        test_response!(
+            writer,
+            reader,
+            [("DATA", timeouts::EXPECTED, is_valid_response::data)],
+        );
+        writer.write_all(b"hello\r\n.\r\n").await?;
+        assert!(is_valid_end(
+            &tokio::time::timeout(timeouts::DATA_TERMINATION, read_line!(reader)).await??
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reply like an LMTP server that has no mailbox for `nobody`, and whose mailbox for `full` is
+/// full, keeping the data of each message in `data`.
+#[cfg(feature = "lmtp")]
+fn spawn_lmtp(lmtp: TcpListener, data: std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = lmtp.accept().await {
+            let data = data.clone();
+            tokio::spawn(async move {
+                let mut reader = BufReader::new(stream);
+                let mut recipients = Vec::new();
+                let _ = reader.write_all(b"220 lmtp.example.com LMTP\r\n").await;
+                let mut line = String::new();
+                while reader.read_line(&mut line).await.is_ok_and(|read| read > 0) {
+                    let reply = if line.starts_with("LHLO") {
+                        "250-lmtp.example.com\r\n250 PIPELINING\r\n".to_owned()
+                    } else if line.starts_with("RCPT TO:<nobody@") {
+                        "550 5.1.1 No such user\r\n".to_owned()
+                    } else if line.starts_with("RCPT") {
+                        recipients.push(line.clone());
+                        "250 2.1.5 OK\r\n".to_owned()
+                    } else if line.starts_with("DATA") {
+                        let _ = reader.write_all(b"354 Go ahead\r\n").await;
+                        let mut message = String::new();
+                        while !message.ends_with("\r\n.\r\n")
+                            && reader
+                                .read_line(&mut message)
+                                .await
+                                .is_ok_and(|read| read > 0)
+                        {}
+                        data.lock().unwrap().push(message);
+                        std::mem::take(&mut recipients)
+                            .into_iter()
+                            .map(|recipient| {
+                                if recipient.starts_with("RCPT TO:<full@") {
+                                    "452 4.2.2 Mailbox full\r\n"
+                                } else {
+                                    "250 2.0.0 Delivered\r\n"
+                                }
+                            })
+                            .collect()
+                    } else if line.starts_with("QUIT") {
+                        let _ = reader.write_all(b"221 Bye\r\n").await;
+                        break;
+                    } else {
+                        "250 2.0.0 OK\r\n".to_owned()
+                    };
+                    let _ = reader.write_all(reply.as_bytes()).await;
+                    line.clear();
+                }
+            });
+        }
+    });
+}
+
+#[cfg(feature = "spf")]
+#[tokio::test]
+async fn test_spf() -> Result {
+    use crate::{
+        resolver::Resolver,
+        spf::{SpfIdentity, SpfPolicy, SpfResult, SpfVerdict},
+    };
+
+    const ADDR: &str = "127.0.0.1:8117";
+
+    /// Only allows the loopback address to send for `allow.example.com` and
+    /// `client.example.com`.
+    struct Stub;
+
+    impl Resolver for Stub {
+        async fn lookup_ip(&self, _: &str) -> std::io::Result<Vec<IpAddr>> {
+            Ok(Vec::new())
+        }
+
+        async fn lookup_txt(&self, name: &str) -> std::io::Result<Vec<String>> {
+            Ok(match name {
+                "allow.example.com" | "client.example.com" => {
+                    vec!["v=spf1 ip4:127.0.0.0/8 -all".to_owned()]
+                }
+                _ => vec!["v=spf1 -all".to_owned()],
+            })
+        }
+    }
+
+    let (sessions, mut messages) = crate::listen_channel(
+        TcpListener::bind(ADDR).await?,
+        ServerConfig::builder()
+            .resolver(Stub)
+            .spf_policy(SpfPolicy::Reject)
+            .build()?,
+        Shutdown::new(),
+    );
+    spawn_sessions(sessions);
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            // Tests that an identity in `HELO` that fails is rejected.
+            (
+                "HELO deny.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::spf_failed,
+            ),
+            (
+                "HELO client.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::helo,
+            ),
+            // Tests that a reverse-path that fails is rejected.
+            (
+                "MAIL FROM:<smith@deny.example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::spf_failed,
+            ),
+            (
+                "MAIL FROM:<smith@allow.example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            (
+                "Subject: test\r\n\r\nbody\r\n.",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+        ],
+    );
+
+    // Tests that the result is kept and stamped on the message.
+    let message = messages.try_recv()?;
+    let spf = message.envelope().spf().ok_or("SPF was not checked")?;
+    assert_eq!(spf.result(), SpfResult::Pass);
+    assert_eq!(spf.identity(), SpfIdentity::MailFrom);
+    assert_eq!(spf.domain(), "allow.example.com");
+    assert_eq!(
+        message.session().spf_helo().map(SpfVerdict::result),
+        Some(SpfResult::Pass)
+    );
+    assert!(message
+        .headers()
+        .get("Received-SPF")
+        .is_some_and(|received_spf| received_spf.starts_with("pass (")
+            && received_spf.contains("identity=mailfrom;")));
+
+    Ok(())
+}
+
+#[cfg(feature = "dmarc")]
+#[tokio::test]
+async fn test_dmarc() -> Result {
+    use crate::{
+        dmarc::{DmarcDisposition, DmarcPolicy, DmarcResult},
+        resolver::Resolver,
+        spf::SpfPolicy,
+    };
+
+    const ADDR: &str = "127.0.0.1:8118";
+
+    /// Only allows the loopback address to send for `example.com`, which rejects failing mail,
+    /// and `example.org`, which quarantines it.
+    struct Stub;
+
+    impl Resolver for Stub {
+        async fn lookup_ip(&self, _: &str) -> std::io::Result<Vec<IpAddr>> {
+            Ok(Vec::new())
+        }
+
+        async fn lookup_txt(&self, name: &str) -> std::io::Result<Vec<String>> {
+            Ok(vec![match name {
+                "example.com" | "example.org" => "v=spf1 ip4:127.0.0.0/8 -all",
+                "_dmarc.example.com" => "v=DMARC1; p=reject",
+                "_dmarc.example.org" => "v=DMARC1; p=quarantine",
+                _ => "v=spf1 -all",
+            }
+            .to_owned()])
+        }
+    }
+
+    let (sessions, mut messages) = crate::listen_channel(
+        TcpListener::bind(ADDR).await?,
+        ServerConfig::builder()
+            .resolver(Stub)
+            .spf_policy(SpfPolicy::Annotate)
+            .dmarc_policy(DmarcPolicy::default())
+            .build()?,
+        Shutdown::new(),
+    );
+    spawn_sessions(sessions);
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    for (mail_from, from, is_valid_end) in [
+        // Tests that an aligned SPF pass is accepted.
+        (
+            "MAIL FROM:<smith@example.com>",
+            "From: <smith@example.com>",
+            is_valid_response::ok as fn(&str) -> bool,
+        ),
+        // Tests that an unaligned sender is rejected.
+        (
+            "MAIL FROM:<smith@example.net>",
+            "From: Smith <smith@example.com>",
+            is_valid_response::dmarc_rejected,
+        ),
+        // Tests that a domain that asks for quarantine has its message tagged.
+        (
+            "MAIL FROM:<smith@example.net>",
+            "From: <smith@example.org>",
+            is_valid_response::ok,
+        ),
+    ] {
+        writer
+            .write_all(format!("{mail_from}\r\n").as_bytes())
+            .await?;
+        assert!(is_valid_response::ok(&read_line!(reader).await?));
+        test_response!(
+            writer,
+            reader,
+            [
+                (
+                    "RCPT TO:<jones@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ],
+        );
+        writer
+            .write_all(format!("{from}\r\n\r\nbody\r\n.\r\n").as_bytes())
+            .await?;
+        assert!(is_valid_end(
+            &tokio::time::timeout(timeouts::EXPECTED, read_line!(reader)).await??
+        ));
+    }
+
+    let message = messages.try_recv()?;
+    let verdict = message.dmarc().ok_or("DMARC was not evaluated")?;
+    assert_eq!(verdict.result(), DmarcResult::Pass);
+    assert!(message
+        .headers()
+        .get("Authentication-Results")
+        .is_some_and(|results| results.contains("dmarc=pass")));
+
+    let message = messages.try_recv()?;
+    let verdict = message.dmarc().ok_or("DMARC was not evaluated")?;
+    assert_eq!(verdict.result(), DmarcResult::Fail);
+    assert_eq!(verdict.domain(), Some("example.org"));
+    assert_eq!(verdict.disposition(), DmarcDisposition::Quarantine);
+    assert!(message
+        .headers()
+        .get("Authentication-Results")
+        .is_some_and(|results| results.contains("dis=quarantine")));
+    assert!(messages.try_recv().is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "arc")]
+#[tokio::test]
+async fn test_arc() -> Result {
+    use crate::{
+        arc::{self, ArcResult, ArcSealer},
+        resolver::Resolver,
+    };
+
+    const ADDR: &str = "127.0.0.1:8119";
+
+    /// Publishes the public half of the test sealing key under `arc._domainkey.example.com`.
+    struct Stub;
+
+    impl Resolver for Stub {
+        async fn lookup_ip(&self, _: &str) -> std::io::Result<Vec<IpAddr>> {
+            Ok(Vec::new())
+        }
+
+        async fn lookup_txt(&self, name: &str) -> std::io::Result<Vec<String>> {
+            Ok(match name {
+                "arc._domainkey.example.com" => vec![format!(
+                    "v=DKIM1; k=rsa; p={}",
+                    include_str!("certs/arc.pub").trim()
+                )],
+                _ => Vec::new(),
+            })
+        }
+    }
+
+    let (sessions, mut messages) = crate::listen_channel(
+        TcpListener::bind(ADDR).await?,
+        ServerConfig::builder()
+            .resolver(Stub)
+            .arc_sealer(ArcSealer::from_pem(
+                "example.com",
+                "arc",
+                include_str!("certs/arc.key"),
+            )?)
+            .build()?,
+        Shutdown::new(),
+    );
+    spawn_sessions(sessions);
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            (
+                "From: <smith@example.com>\r\nSubject: Hello\r\n\r\nbody\r\n.",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+        ],
+    );
+
+    // Tests that a message without a chain is sealed as the first instance of a new one.
+    let message = messages.try_recv()?;
+    assert_eq!(message.arc(), Some(ArcResult::None));
+    assert!(message
+        .headers()
+        .get("ARC-Seal")
+        .is_some_and(|seal| seal.contains("i=1") && seal.contains("cv=none")));
+    assert_eq!(arc::verify(&Stub, message.data()).await, ArcResult::Pass);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reverse_dns() -> Result {
+    use crate::{config::ReverseDnsPolicy, resolver::Resolver};
+
+    const ADDR: &str = "127.0.0.1:8121";
+
+    /// Names the loopback address `client.example.com`, which only resolves back to it if the
+    /// stub confirms it.
+    struct Stub(bool);
+
+    impl Resolver for Stub {
+        async fn lookup_ip(&self, _: &str) -> std::io::Result<Vec<IpAddr>> {
+            Ok(if self.0 {
+                vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]
+            } else {
+                Vec::new()
+            })
+        }
+
+        async fn lookup_ptr(&self, _: IpAddr) -> std::io::Result<Vec<String>> {
+            Ok(vec!["client.example.com".to_owned()])
+        }
+    }
+
+    let with_resolver = |confirms| {
+        ServerConfig::builder()
+            .resolver(Stub(confirms))
+            .reverse_dns_policy(ReverseDnsPolicy::Reject)
+            .build()
+    };
+    let config = ConfigHandle::new(with_resolver(true)?);
+    let (sessions, mut messages) = crate::listen_channel(
+        TcpListener::bind(ADDR).await?,
+        config.clone(),
+        Shutdown::new(),
+    );
+    spawn_sessions(sessions);
+
+    // Tests that the confirmed name of the client is given to the handler and in the `Received:`
+    // header.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ("body\r\n.", timeouts::EXPECTED, is_valid_response::ok),
+        ],
+    );
+    let message = messages.try_recv()?;
+    assert_eq!(
+        message.session().peer_hostname(),
+        Some("client.example.com")
+    );
+    assert!(message
+        .headers()
+        .get("Received")
+        .is_some_and(|received| received.contains("(client.example.com [127.0.0.1])")));
+
+    // Tests that clients without a confirmed name are refused in place of the greeting.
+    config.replace(with_resolver(false)?);
+    let mut reader = BufReader::new(TcpStream::connect(ADDR).await?);
+    assert!(is_valid_response::reverse_dns_failed(
+        &read_line!(reader).await?
+    ));
+
+    Ok(())
+}
+
+#[cfg(feature = "dnsbl")]
+#[tokio::test]
+async fn test_dnsbl() -> Result {
+    use crate::{
+        dnsbl::{Blocklist, DnsblAction, DnsblPolicy},
+        resolver::Resolver,
+    };
+
+    const ADDR: &str = "127.0.0.1:8120";
+
+    /// Lists the loopback address on every list.
+    struct Stub;
+
+    impl Resolver for Stub {
+        async fn lookup_ip(&self, name: &str) -> std::io::Result<Vec<IpAddr>> {
+            Ok(if name.starts_with("1.0.0.127.") {
+                vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))]
+            } else {
+                Vec::new()
+            })
+        }
+    }
+
+    let with_action = |action| {
+        ServerConfig::builder()
+            .resolver(Stub)
+            .dnsbl_policy(
+                DnsblPolicy::default().with_list(Blocklist::new("bl.example").with_action(action)),
+            )
+            .build()
+    };
+    let config = ConfigHandle::new(with_action(DnsblAction::Annotate)?);
+    let (sessions, mut messages) = crate::listen_channel(
+        TcpListener::bind(ADDR).await?,
+        config.clone(),
+        Shutdown::new(),
+    );
+    spawn_sessions(sessions);
+
+    // Tests that listed clients are noted in the `Received:` header.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ("body\r\n.", timeouts::EXPECTED, is_valid_response::ok),
+        ],
+    );
+    let message = messages.try_recv()?;
+    assert_eq!(
+        message
+            .session()
+            .dnsbl()
+            .map(crate::dnsbl::DnsblVerdict::score),
+        Some(1)
+    );
+    assert!(message
+        .headers()
+        .get("Received")
+        .is_some_and(|received| received.contains("(listed by bl.example)")));
+
+    // Tests that listed clients are greeted, but cannot send mail.
+    config.replace(with_action(DnsblAction::Reject)?);
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [(
+            "MAIL FROM:<smith@example.com>",
+            timeouts::EXPECTED,
+            is_valid_response::dnsbl_rejected,
+        )],
+    );
+
+    // Tests that listed clients are refused in place of the greeting.
+    config.replace(with_action(DnsblAction::Refuse)?);
+    let mut reader = BufReader::new(TcpStream::connect(ADDR).await?);
+    assert!(is_valid_response::dnsbl_refused(&read_line!(reader).await?));
+
+    Ok(())
+}
+
+#[cfg(feature = "greylist")]
+#[tokio::test]
+async fn test_greylist() -> Result {
+    use crate::greylist::{GreylistPolicy, MemoryStore};
+
+    const ADDR: &str = "127.0.0.1:8122";
+
+    let config = ServerConfig::builder()
+        .greylist_policy(
+            GreylistPolicy::default()
+                .with_initial_delay(Duration::from_millis(200))
+                .with_store(MemoryStore::new()),
+        )
+        .build()?;
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config,
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    // Tests that a new triplet is deferred, and still is when retried too soon.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::greylisted,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::greylisted,
+            ),
+        ],
+    );
+
+    // Tests that a retry after the initial delay is accepted, but a new recipient is not.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<green@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::greylisted,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ("body\r\n.", timeouts::EXPECTED, is_valid_response::ok),
+        ],
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "rspamd")]
+#[tokio::test]
+async fn test_rspamd() -> Result {
+    use crate::rspamd::{RspamdAction, RspamdPolicy};
+
+    const ADDR: &str = "127.0.0.1:8123";
+    const RSPAMD_ADDR: &str = "127.0.0.1:8124";
+
+    spawn_rspamd(TcpListener::bind(RSPAMD_ADDR).await?);
+    let config = ServerConfig::builder()
+        .rspamd_policy(RspamdPolicy::new(RSPAMD_ADDR))
+        .build()?;
+    let (sessions, mut messages) =
+        crate::listen_channel(TcpListener::bind(ADDR).await?, config, Shutdown::new());
+    spawn_sessions(sessions);
+
+    // Tests that spam is marked, that headers are added, and that forged marks are removed.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            (
+                "X-Spam: No\r\n\r\nspam\r\n.",
+                timeouts::EXPECTED,
+                is_valid_response::ok
+            ),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ("clean\r\n.", timeouts::EXPECTED, is_valid_response::ok),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            (
+                "RCPT TO:<jones@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            ),
+            ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            (
+                "reject\r\n.",
+                timeouts::EXPECTED,
+                is_valid_response::spam_rejected,
+            ),
+        ],
+    );
+
+    let message = messages.try_recv()?;
+    assert_eq!(
+        message.rspamd().map(crate::rspamd::RspamdVerdict::action),
+        Some(RspamdAction::AddHeader)
+    );
+    let headers = message.headers();
+    assert_eq!(headers.get_all("X-Spam").collect::<Vec<_>>(), ["Yes"]);
+    assert_eq!(headers.get("X-Spamd-Bar"), Some("++++++++"));
+
+    let message = messages.try_recv()?;
+    assert_eq!(
+        message.rspamd().map(crate::rspamd::RspamdVerdict::action),
+        Some(RspamdAction::Accept)
+    );
+    assert!(!message.headers().contains("X-Spam"));
+    // Tests that rejected messages are not handed on.
+    assert!(messages.try_recv().is_err());
+
+    Ok(())
+}
+
+/// Serve a stand-in for Rspamd on `listener`, which scans messages that mention `reject` as spam
+/// to reject, those that mention `spam` as spam to mark, and the rest as clean.
+#[cfg(feature = "rspamd")]
+fn spawn_rspamd(rspamd: TcpListener) {
+    use tokio::io::AsyncReadExt;
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = rspamd.accept().await {
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).await.is_ok_and(|read| read > 2) {
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap_or_default();
+                }
+                line.clear();
+            }
+            let mut data = vec![0; length];
+            let _ = reader.read_exact(&mut data).await;
+
+            let data = String::from_utf8_lossy(&data);
+            let reply = if data.contains("reject") {
+                r#"{ "action": "reject", "score": 20 }"#
+            } else if data.contains("spam") {
+                r#"{ "action": "add header", "score": 8, "milter": { "add_headers": { "X-Spamd-Bar": "++++++++" } } }"#
+            } else {
+                r#"{ "action": "no action", "score": 0.5 }"#
+            };
+            let _ = reader
+                .into_inner()
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{reply}",
+                        reply.len()
+                    )
+                    .as_bytes(),
+                )
+                .await;
+        }
+    });
+}
+
+#[cfg(feature = "spamassassin")]
+#[tokio::test]
+async fn test_spamassassin() -> Result {
+    use crate::spamassassin::{SpamAssassinAction, SpamAssassinPolicy, SpamAssassinVerdict};
+
+    const ADDR: &str = "127.0.0.1:8125";
+    const SPAMD_ADDR: &str = "127.0.0.1:8126";
+
+    spawn_spamd(TcpListener::bind(SPAMD_ADDR).await?);
+    let config = ServerConfig::builder()
+        .spamassassin_policy(SpamAssassinPolicy::new(SPAMD_ADDR).with_reject_score(15.0))
+        .build()?;
+    let (sessions, mut messages) =
+        crate::listen_channel(TcpListener::bind(ADDR).await?, config, Shutdown::new());
+    spawn_sessions(sessions);
+
+    // Tests that spam is tagged, and that forged tags are removed, and that messages past the
+    // rejection score are rejected.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    for (data, test_fn) in [
+        (
+            "X-Spam-Flag: YES\r\n\r\nclean",
+            is_valid_response::ok as fn(&str) -> bool,
+        ),
+        ("spam", is_valid_response::ok),
+        ("reject", is_valid_response::spam_rejected),
+    ] {
+        test_response!(
+            writer,
+            reader,
+            [
+                (
+                    "MAIL FROM:<smith@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                (
+                    "RCPT TO:<jones@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ],
+        );
+        writer
+            .write_all(format!("{data}\r\n.\r\n").as_bytes())
+            .await?;
+        assert!(test_fn(&read_line!(reader).await?));
+    }
+
+    let message = messages.try_recv()?;
+    assert_eq!(
+        message.spamassassin().map(SpamAssassinVerdict::action),
+        Some(SpamAssassinAction::Accept)
+    );
+    assert!(!message.headers().contains("X-Spam-Flag"));
+    assert_eq!(
+        message.headers().get("X-Spam-Status"),
+        Some("No, score=1.0 required=5.0 tests=none")
+    );
+
+    let message = messages.try_recv()?;
+    assert_eq!(message.headers().get("X-Spam-Flag"), Some("YES"));
+    assert_eq!(
+        message.headers().get("X-Spam-Status"),
+        Some("Yes, score=8.0 required=5.0 tests=BAYES_99")
+    );
+    assert!(messages.try_recv().is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "clamav")]
+#[tokio::test]
+async fn test_clamav() -> Result {
+    use crate::clamav::{ClamAvAction, ClamAvPolicy, ClamAvVerdict};
+
+    const REJECT_ADDR: &str = "127.0.0.1:8129";
+    const QUARANTINE_ADDR: &str = "127.0.0.1:8130";
+    const CLAMD_ADDR: &str = "127.0.0.1:8131";
+
+    spawn_clamd(TcpListener::bind(CLAMD_ADDR).await?);
+    let mut messages = Vec::new();
+    for (addr, action) in [
+        (REJECT_ADDR, ClamAvAction::Reject),
+        (QUARANTINE_ADDR, ClamAvAction::Quarantine),
+    ] {
+        let config = ServerConfig::builder()
+            .clamav_policy(ClamAvPolicy::new(CLAMD_ADDR).with_action(action))
+            .build()?;
+        let (sessions, receiver) =
+            crate::listen_channel(TcpListener::bind(addr).await?, config, Shutdown::new());
+        spawn_sessions(sessions);
+        messages.push(receiver);
+    }
+
+    // Tests that infected messages are rejected or quarantined, and that clean ones are marked as
+    // such in place of forged marks.
+    for (addr, data, test_fn) in [
+        (
+            REJECT_ADDR,
+            "EICAR",
+            is_valid_response::virus_rejected as fn(&str) -> bool,
+        ),
+        (
+            REJECT_ADDR,
+            "X-Virus-Status: Infected (Forged)\r\n\r\nclean",
+            is_valid_response::ok,
+        ),
+        (QUARANTINE_ADDR, "EICAR", is_valid_response::ok),
+    ] {
+        let (mut reader, mut writer) = greeted_session(addr).await?;
+        test_response!(
+            writer,
+            reader,
+            [
+                (
+                    "MAIL FROM:<smith@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                (
+                    "RCPT TO:<jones@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ],
+        );
+        writer
+            .write_all(format!("{data}\r\n.\r\n").as_bytes())
+            .await?;
+        assert!(test_fn(&read_line!(reader).await?));
+    }
+
+    let message = messages[0].try_recv()?;
+    assert_eq!(
+        message.clamav().map(ClamAvVerdict::is_infected),
+        Some(false)
+    );
+    assert_eq!(message.headers().get("X-Virus-Status"), Some("Clean"));
+    assert!(messages[0].try_recv().is_err());
+
+    let message = messages[1].try_recv()?;
+    assert_eq!(
+        message.clamav().and_then(ClamAvVerdict::virus),
+        Some("Eicar-Signature")
+    );
+    assert_eq!(
+        message.headers().get("X-Virus-Status"),
+        Some("Infected (Eicar-Signature)")
+    );
+
+    Ok(())
+}
+
+/// Serve a stand-in for `clamd` on `listener`, which finds `Eicar-Signature` in messages that
+/// mention `EICAR`.
+#[cfg(feature = "clamav")]
+fn spawn_clamd(clamd: TcpListener) {
+    use tokio::io::AsyncReadExt;
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = clamd.accept().await {
+            let mut command = [0; 10];
+            let _ = stream.read_exact(&mut command).await;
+            let mut data = Vec::new();
+            loop {
+                let mut length = [0; 4];
+                if stream.read_exact(&mut length).await.is_err() {
+                    break;
+                }
+                let start = data.len();
+                data.resize(start + u32::from_be_bytes(length) as usize, 0);
+                if start == data.len() || stream.read_exact(&mut data[start..]).await.is_err() {
+                    break;
+                }
+            }
+
+            let response: &[u8] = if &command != b"zINSTREAM\0" {
+                b"UNKNOWN COMMAND\0"
+            } else if String::from_utf8_lossy(&data).contains("EICAR") {
+                b"stream: Eicar-Signature FOUND\0"
+            } else {
+                b"stream: OK\0"
+            };
+            let _ = stream.write_all(response).await;
+        }
+    });
+}
+
+/// Serve a stand-in for `spamd` on `listener`, which scores messages that mention `reject` at 20,
+/// those that mention `spam` at 8, and the rest at 1, with a threshold of 5.
+#[cfg(feature = "spamassassin")]
+fn spawn_spamd(spamd: TcpListener) {
+    use tokio::io::AsyncReadExt;
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = spamd.accept().await {
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).await.is_ok_and(|read| read > 2) {
+                if let Some(value) = line.strip_prefix("Content-length: ") {
+                    length = value.trim().parse().unwrap_or_default();
+                }
+                line.clear();
+            }
+            let mut data = vec![0; length];
+            let _ = reader.read_exact(&mut data).await;
+
+            let data = String::from_utf8_lossy(&data);
+            let (spam, symbols) = if data.contains("reject") {
+                ("True ; 20.0 / 5.0", "BAYES_99,URIBL_BLACK")
+            } else if data.contains("spam") {
+                ("True ; 8.0 / 5.0", "BAYES_99")
+            } else {
+                ("False ; 1.0 / 5.0", "")
+            };
+            let _ = reader
+                .into_inner()
+                .write_all(
+                    format!(
+                        "SPAMD/1.1 0 EX_OK\r\nContent-length: {}\r\nSpam: {spam}\r\n\r\n{symbols}",
+                        symbols.len()
+                    )
+                    .as_bytes(),
+                )
+                .await;
+        }
+    });
+}
+
+#[cfg(feature = "filter")]
+#[tokio::test]
+async fn test_filter() -> Result {
+    use crate::filter::{FilterAction, FilterCondition, FilterPattern, FilterRule};
+
+    const ADDR: &str = "127.0.0.1:8127";
+
+    let subject = |text| FilterCondition::header("Subject", FilterPattern::contains(text));
+    let config = ServerConfig::builder()
+        .filter_rule(FilterRule::new(
+            "blocked",
+            subject("blocked"),
+            FilterAction::Reject,
+        ))
+        .filter_rule(FilterRule::new(
+            "invoices",
+            subject("invoice"),
+            FilterAction::Tag,
+        ))
+        .filter_rule(FilterRule::new(
+            "billing",
+            subject("invoice"),
+            FilterAction::Route("billing".to_owned()),
+        ))
+        .build()?;
+    let (sessions, mut messages) =
+        crate::listen_channel(TcpListener::bind(ADDR).await?, config, Shutdown::new());
+    spawn_sessions(sessions);
+
+    // Tests that matching messages are tagged and routed, and that rejected ones are not handed
+    // on.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    for (data, test_fn) in [
+        (
+            "Subject: Invoice\r\n\r\nbody",
+            is_valid_response::ok as fn(&str) -> bool,
+        ),
+        (
+            "Subject: Blocked\r\n\r\nbody",
+            is_valid_response::filter_rejected,
+        ),
+    ] {
+        test_response!(
+            writer,
+            reader,
+            [
+                (
+                    "MAIL FROM:<smith@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                (
+                    "RCPT TO:<jones@example.com>",
+                    timeouts::EXPECTED,
+                    is_valid_response::ok,
+                ),
+                ("DATA", timeouts::EXPECTED, is_valid_response::data),
+            ],
+        );
+        writer
+            .write_all(format!("{data}\r\n.\r\n").as_bytes())
+            .await?;
+        assert!(test_fn(&read_line!(reader).await?));
+    }
+
+    let message = messages.try_recv()?;
+    let verdict = message.filter().ok_or("the message was not filtered")?;
+    assert_eq!(verdict.tags(), ["invoices"]);
+    assert_eq!(verdict.route(), Some("billing"));
+    assert_eq!(message.headers().get("X-Filter-Tag"), Some("invoices"));
+    assert!(messages.try_recv().is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "attachment")]
+#[tokio::test]
+async fn test_attachment() -> Result {
+    use crate::attachment::{AttachmentPolicy, AttachmentRules};
+
+    const ADDR: &str = "127.0.0.1:8128";
+
+    let config = ServerConfig::builder()
+        .attachment_policy(
+            AttachmentPolicy::default()
+                .with_domain("lab.example.com", AttachmentRules::allow_all()),
+        )
+        .build()?;
+    let (sessions, mut messages) =
+        crate::listen_channel(TcpListener::bind(ADDR).await?, config, Shutdown::new());
+    spawn_sessions(sessions);
+
+    let data = concat!(
+        "Content-Type: multipart/mixed; boundary=b\r\n",
+        "\r\n",
+        "--b\r\n",
+        "Content-Type: text/plain\r\n",
+        "\r\n",
+        "See attached.\r\n",
+        "--b\r\n",
+        "Content-Type: application/octet-stream\r\n",
+        "Content-Disposition: attachment; filename=\"setup.exe\"\r\n",
+        "Content-Transfer-Encoding: base64\r\n",
+        "\r\n",
+        "TVqQAAMAAAAEAAAA\r\n",
+        "--b--",
+    );
+
+    // Tests that blocked attachments are rejected, except for the domain that allows them all.
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    for (recipient, test_fn) in [
+        (
+            "RCPT TO:<jones@example.com>",
+            is_valid_response::attachment_rejected as fn(&str) -> bool,
+        ),
+        ("RCPT TO:<jones@lab.example.com>", is_valid_response::ok),
+    ] {
+        test_response!(
+            writer,
+            reader,
+            [(
+                "MAIL FROM:<smith@example.com>",
+                timeouts::EXPECTED,
+                is_valid_response::ok,
+            )],
+        );
+        writer
+            .write_all(format!("{recipient}\r\n").as_bytes())
+            .await?;
+        assert!(is_valid_response::ok(&read_line!(reader).await?));
+        test_response!(
+            writer,
+            reader,
+            [("DATA", timeouts::EXPECTED, is_valid_response::data)],
+        );
+        writer
+            .write_all(format!("{data}\r\n.\r\n").as_bytes())
+            .await?;
+        assert!(test_fn(&read_line!(reader).await?));
+    }
+
+    let message = messages.try_recv()?;
+    assert_eq!(
+        message
+            .envelope()
+            .forward_paths()
+            .next()
+            .map(AsciiStr::as_str),
+        Some("jones@lab.example.com")
+    );
+    assert!(messages.try_recv().is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_from_std() -> Result {
+    const ADDR: &str = "127.0.0.1:8106";
+
+    // Tests that a listener that was not started with socket activation gets no sockets.
+    #[cfg(unix)]
+    assert!(Listener::from_systemd()?.is_empty());
+
+    let listener = Listener::from_std(std::net::TcpListener::bind(ADDR)?)?;
+    spawn_sessions(crate::listen_all(
+        [listener],
+        config(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    let (mut reader, mut writer) = greeted_session(ADDR).await?;
+    test_response!(
+        writer,
+        reader,
+        [("QUIT", timeouts::EXPECTED, is_valid_response::quit)],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_timeouts() -> Result {
+    const ADDR: &str = "127.0.0.1:8093";
+
+    let config = ServerConfig::builder()
+        .hostname("mx.example.com")
+        .timeouts(
+            timeouts::Timeouts::rfc5321()
+                .with_greeting(Duration::from_millis(200))
+                .with_server(Duration::from_secs(1)),
+        )
+        .build()?;
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config,
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    // Tests that a client that never sends a command is disconnected after the greeting timeout.
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let mut reader = BufReader::new(&mut stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+    let closed = tokio::time::timeout(timeouts::EXPECTED, read_line!(reader)).await?;
+    assert_eq!(
+        closed.map_err(|err| err.kind()),
+        Err(std::io::ErrorKind::ConnectionAborted)
+    );
+
+    // Tests that later commands are held to the server timeout instead.
+    let mut stream = TcpStream::connect(ADDR).await?;
+    let (read_stream, mut write_stream) = stream.split();
+
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    test_response!(
+        write_stream,
+        reader,
+        [("HELO", timeouts::EXPECTED, is_valid_response::helo)],
+    );
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    test_response!(
+        write_stream,
+        reader,
+        [("NOOP", timeouts::EXPECTED, is_valid_response::ok)],
+    );
+
+    let closed = tokio::time::timeout(timeouts::EXPECTED, read_line!(reader)).await?;
+    assert_eq!(
+        closed.map_err(|err| err.kind()),
+        Err(std::io::ErrorKind::ConnectionAborted)
+    );
+
+    Ok(())
+}
+
+/// Create the [`ServerConfig`] that every test server uses.
+///
+/// # Panics
+///
+/// Panics if the configuration is invalid.
+fn config() -> ServerConfig {
+    ServerConfig::builder()
+        .hostname("mx.example.com")
+        .build()
+        .expect("the configuration is valid")
+}
+
+/// Bind to `addr` and handle every incoming connection as an SMTP session in the background.
+///
+/// # Panics
+///
+/// The background task panics if a session encounters an error.
+async fn spawn_server(addr: &str) -> Result {
+    spawn_sessions(crate::listen(
+        TcpListener::bind(addr).await?,
+        config(),
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    Ok(())
+}
+
+/// Connect to `addr` and greet the server, returning each half of the connection.
+async fn greeted_session(
+    addr: &str,
+) -> std::result::Result<(BufReader<OwnedReadHalf>, OwnedWriteHalf), Box<dyn Error>> {
+    let (read_stream, mut write_stream) = TcpStream::connect(addr).await?.into_split();
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+    test_response!(
+        write_stream,
+        reader,
+        [("HELO", timeouts::EXPECTED, is_valid_response::helo)],
+    );
+
+    Ok((reader, write_stream))
+}
+
+/// Handle every [`Session`] from `stream` in the background.
+///
+/// # Panics
+///
+/// The background task panics if a session encounters an error.
+fn spawn_sessions(stream: impl Stream<Item = std::io::Result<Session>> + Send + 'static) {
+    // Can be bound to a variable which exposes `.abort()`
+    tokio::spawn(async move {
+        pin_mut!(stream);
+
+        loop {
+            // Get the `Next` and unwrap it
+            let session = stream
+                .next()
+                .await
+                .unwrap()
+                // Unwrap the [`TcpListener::accept`]
+                .unwrap();
+
+            // Await the session alongside the others, so that they can overlap
+            tokio::spawn(async move {
+                // Await and unwrap the [`JoinHandle`], then unwrap the [`Session`] itself
+                session.await.unwrap().unwrap();
+            });
+        }
+    });
+}
@@ -0,0 +1,514 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for encrypting sessions with `STARTTLS`.
+
+use std::{error::Error, sync::Arc, time::Duration};
+
+use ascii::AsciiStr;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+        version::TLS12,
+        ClientConfig, ConfigBuilder, RootCertStore, SupportedProtocolVersion, DEFAULT_VERSIONS,
+    },
+    TlsConnector,
+};
+
+use super::{is_valid_response, spawn_sessions, Result};
+use crate::{
+    handler::{AcceptAll, Decision, HandlerResult, SessionContext, SmtpHandler},
+    message::{envelope::Envelope, ContentHash},
+    read_line,
+    session::TlsInfo,
+    timeouts,
+    tls::{ClientAuth, TlsConfig, TlsConfigBuilder, TlsPolicy, TlsVersion},
+    Listener, ServerConfig, Shutdown,
+};
+
+/// The certificate authority that issued every other certificate.
+const CA: &[u8] = include_bytes!("certs/ca.pem");
+/// The certificate of the client, for `relay.example.com`.
+const CLIENT: &[u8] = include_bytes!("certs/client.pem");
+/// The private key of the client.
+const CLIENT_KEY: &[u8] = include_bytes!("certs/client.key");
+/// The certificate of the server for `localhost`.
+const SERVER: &[u8] = include_bytes!("certs/server.pem");
+/// The private key of the server for `localhost`.
+const SERVER_KEY: &[u8] = include_bytes!("certs/server.key");
+/// The self-signed certificate of the server for `mx.example.net`.
+const OTHER: &[u8] = include_bytes!("certs/other.pem");
+/// The private key of the server for `mx.example.net`.
+const OTHER_KEY: &[u8] = include_bytes!("certs/other.key");
+
+/// Treats a client that gave the certificate with the fingerprint as authenticated, and only takes
+/// mail from authenticated clients.
+struct CertificateAuth(String);
+
+impl SmtpHandler for CertificateAuth {
+    async fn on_ehlo(&mut self, context: &mut SessionContext, _: &AsciiStr) -> HandlerResult {
+        let fingerprint = context.session().tls().and_then(TlsInfo::peer_fingerprint);
+        if fingerprint.as_ref() == Some(&self.0) {
+            context.authenticate("relay.example.com");
+        }
+
+        Ok(Decision::Accept)
+    }
+
+    async fn on_mail(&mut self, context: &mut SessionContext, _: &Envelope) -> HandlerResult {
+        if context.session().authenticated_user() == Some("relay.example.com") {
+            Ok(Decision::Accept)
+        } else {
+            Ok(Decision::Reject)
+        }
+    }
+}
+
+/// Rejects `HELO` over encrypted connections unless the client asked for `mx.example.net` with
+/// Server Name Indication over TLS 1.3.
+struct ExpectServerName;
+
+impl SmtpHandler for ExpectServerName {
+    async fn on_ehlo(&mut self, context: &mut SessionContext, _: &AsciiStr) -> HandlerResult {
+        match context.session().tls() {
+            Some(tls)
+                if tls.server_name() != Some("mx.example.net") || tls.protocol() != "TLSv1.3" =>
+            {
+                Ok(Decision::Reject)
+            }
+            _ => Ok(Decision::Accept),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_start_tls() -> Result {
+    const ADDR: &str = "127.0.0.1:8107";
+
+    let fingerprint = ContentHash::of(&CertificateDer::from_pem_slice(CLIENT)?).to_string();
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(ClientAuth::Request)?,
+        move |_| CertificateAuth(fingerprint.clone()),
+        Shutdown::new(),
+    ));
+
+    // Tests that a client certificate authenticates the client once the connection is encrypted.
+    let client = client_builder(DEFAULT_VERSIONS)?.with_client_auth_cert(
+        vec![CertificateDer::from_pem_slice(CLIENT)?],
+        PrivateKeyDer::from_pem_slice(CLIENT_KEY)?,
+    )?;
+    let (read_stream, mut write_stream) =
+        tokio::io::split(start_tls(ADDR, client, "localhost").await?);
+    let mut reader = BufReader::new(read_stream);
+
+    // Tests that `STARTTLS` is no longer advertised once the connection is encrypted.
+    crate::write_line!(write_stream, "EHLO client.example.com")?;
+    assert_eq!(
+        read_line!(reader).await?,
+        "250-mx.example.com greets client.example.com\r\n"
+    );
+    assert_eq!(read_line!(reader).await?, "250 SIZE 10485760\r\n");
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "STARTTLS",
+                timeouts::EXPECTED,
+                is_valid_response::bad_sequence
+            ),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    // Tests that a client without a certificate can encrypt the connection, but is not
+    // authenticated.
+    let client = client_builder(DEFAULT_VERSIONS)?.with_no_client_auth();
+    let (read_stream, mut write_stream) =
+        tokio::io::split(start_tls(ADDR, client, "localhost").await?);
+    let mut reader = BufReader::new(read_stream);
+
+    test_response!(
+        write_stream,
+        reader,
+        [(
+            "HELO client.example.com",
+            timeouts::EXPECTED,
+            is_valid_response::helo
+        )],
+    );
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::action_not_taken
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_name() -> Result {
+    const ADDR: &str = "127.0.0.1:8109";
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(ClientAuth::None)?,
+        |_| ExpectServerName,
+        Shutdown::new(),
+    ));
+
+    // Tests that the certificate for a wildcard name is chosen by the name the client asked for,
+    // regardless of case, as the handshake would fail with the default certificate.
+    let client = client_builder(DEFAULT_VERSIONS)?.with_no_client_auth();
+    let stream = start_tls(ADDR, client, "MX.example.net").await?;
+    let (read_stream, mut write_stream) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_stream);
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "HELO client.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::helo
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    // Tests that the default certificate is given for any other name.
+    let client = client_builder(DEFAULT_VERSIONS)?.with_no_client_auth();
+    let stream = start_tls(ADDR, client, "localhost").await?;
+    let (read_stream, mut write_stream) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_stream);
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "HELO client.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::action_not_taken
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tls_policy() -> Result {
+    const ADDR: &str = "127.0.0.1:8110";
+
+    let tls = tls_builder(ClientAuth::None)?
+        .min_version(TlsVersion::Tls13)
+        .build()?;
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        ServerConfig::builder()
+            .hostname("mx.example.com")
+            .tls(tls)
+            .tls_policy(TlsPolicy::Required)
+            .build()?,
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    // Tests that mail is rejected until the connection is encrypted.
+    let (mut reader, mut write_stream) = super::greeted_session(ADDR).await?;
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::tls_required
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    // Tests that clients are held to the oldest version of the protocol.
+    let client = client_builder(&[&TLS12])?.with_no_client_auth();
+    assert!(start_tls(ADDR, client, "localhost").await.is_err());
+
+    let client = client_builder(DEFAULT_VERSIONS)?.with_no_client_auth();
+    let stream = start_tls(ADDR, client, "localhost").await?;
+    let (read_stream, mut write_stream) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_stream);
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "HELO client.example.com",
+                timeouts::EXPECTED,
+                is_valid_response::helo
+            ),
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_implicit_tls() -> Result {
+    const ADDR: &str = "127.0.0.1:8158";
+
+    spawn_sessions(crate::listen_all(
+        [Listener::new(TcpListener::bind(ADDR).await?).with_implicit_tls()],
+        config(ClientAuth::None)?,
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    // Tests that the handshake comes before the greeting, and that `STARTTLS` is not offered.
+    let client = client_builder(DEFAULT_VERSIONS)?.with_no_client_auth();
+    let stream = TlsConnector::from(Arc::new(client))
+        .connect(
+            ServerName::try_from("localhost")?,
+            TcpStream::connect(ADDR).await?,
+        )
+        .await?;
+    let (read_stream, mut write_stream) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+    crate::write_line!(write_stream, "EHLO client.example.com")?;
+    assert_eq!(
+        read_line!(reader).await?,
+        "250-mx.example.com greets client.example.com\r\n"
+    );
+    assert_eq!(read_line!(reader).await?, "250 SIZE 10485760\r\n");
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "MAIL FROM:<smith@example.com>",
+                timeouts::MAIL,
+                is_valid_response::ok
+            ),
+            ("QUIT", timeouts::EXPECTED, is_valid_response::quit),
+        ],
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reload() -> Result {
+    const ADDR: &str = "127.0.0.1:8111";
+
+    let directory = std::env::temp_dir().join("smtp_gateway_test_reload");
+    std::fs::create_dir_all(&directory)?;
+    let (certificate_path, key_path) = (directory.join("cert.pem"), directory.join("key.pem"));
+    std::fs::write(&certificate_path, SERVER)?;
+    std::fs::write(&key_path, SERVER_KEY)?;
+
+    let tls = TlsConfig::builder_from_pem(&certificate_path, &key_path)?.build()?;
+    let watcher = tls.watch_pem(&certificate_path, &key_path, Duration::from_millis(20));
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        ServerConfig::builder()
+            .hostname("mx.example.com")
+            .tls(tls.clone())
+            .build()?,
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    let client = || -> std::result::Result<_, Box<dyn Error>> {
+        Ok(client_builder(DEFAULT_VERSIONS)?.with_no_client_auth())
+    };
+    start_tls(ADDR, client()?, "localhost").await?;
+
+    // Tests that a replaced certificate is presented in the next handshake.
+    tls.reload(
+        vec![CertificateDer::from_pem_slice(OTHER)?],
+        PrivateKeyDer::from_pem_slice(OTHER_KEY)?,
+    )?;
+    assert!(start_tls(ADDR, client()?, "localhost").await.is_err());
+    start_tls(ADDR, client()?, "mx.example.net").await?;
+
+    // Tests that modified files are picked up by the watcher.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    std::fs::write(&certificate_path, SERVER)?;
+    std::fs::write(&key_path, SERVER_KEY)?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    start_tls(ADDR, client()?, "localhost").await?;
+
+    // Tests that a certificate that does not suit its key is refused.
+    assert!(tls
+        .reload(
+            vec![CertificateDer::from_pem_slice(OTHER)?],
+            PrivateKeyDer::from_pem_slice(SERVER_KEY)?,
+        )
+        .is_err());
+
+    watcher.abort();
+    std::fs::remove_dir_all(directory)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_certificate_required() -> Result {
+    const ADDR: &str = "127.0.0.1:8108";
+
+    spawn_sessions(crate::listen(
+        TcpListener::bind(ADDR).await?,
+        config(ClientAuth::Require)?,
+        |_| AcceptAll,
+        Shutdown::new(),
+    ));
+
+    // Tests that a client without a certificate is turned away, whether the handshake fails on its
+    // side or the connection is closed right after.
+    if let Ok(stream) = start_tls(
+        ADDR,
+        client_builder(DEFAULT_VERSIONS)?.with_no_client_auth(),
+        "localhost",
+    )
+    .await
+    {
+        let (read_stream, mut write_stream) = tokio::io::split(stream);
+        let mut reader = BufReader::new(read_stream);
+
+        crate::write_line!(write_stream, "EHLO client.example.com")?;
+        assert!(read_line!(reader).await.is_err());
+    }
+
+    Ok(())
+}
+
+/// Create the configuration of a server that offers `STARTTLS`, asking clients for certificates
+/// according to `client_auth`.
+fn config(client_auth: ClientAuth) -> std::result::Result<ServerConfig, Box<dyn Error>> {
+    Ok(ServerConfig::builder()
+        .hostname("mx.example.com")
+        .tls(tls_builder(client_auth)?.build()?)
+        .build()?)
+}
+
+/// Start the TLS configuration of a server, asking clients for certificates according to
+/// `client_auth`.
+fn tls_builder(client_auth: ClientAuth) -> std::result::Result<TlsConfigBuilder, Box<dyn Error>> {
+    Ok(TlsConfig::builder(
+        vec![CertificateDer::from_pem_slice(SERVER)?],
+        PrivateKeyDer::from_pem_slice(SERVER_KEY)?,
+    )
+    .certificate_for(
+        "*.example.net",
+        vec![CertificateDer::from_pem_slice(OTHER)?],
+        PrivateKeyDer::from_pem_slice(OTHER_KEY)?,
+    )
+    .client_auth(client_auth)
+    .client_root(CertificateDer::from_pem_slice(CA)?)?)
+}
+
+/// Start the configuration of a client that trusts the server and uses `versions` of the protocol,
+/// leaving its certificate to be set.
+fn client_builder(
+    versions: &[&'static SupportedProtocolVersion],
+) -> std::result::Result<
+    ConfigBuilder<ClientConfig, tokio_rustls::rustls::client::WantsClientCert>,
+    Box<dyn Error>,
+> {
+    let mut roots = RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_slice(CA)?)?;
+    roots.add(CertificateDer::from_pem_slice(OTHER)?)?;
+
+    Ok(ClientConfig::builder_with_protocol_versions(versions).with_root_certificates(roots))
+}
+
+/// Connect to `addr`, greet the server, and start TLS as `client` asking for `server_name`,
+/// returning the encrypted connection.
+async fn start_tls(
+    addr: &str,
+    client: ClientConfig,
+    server_name: &str,
+) -> std::result::Result<TlsStream<TcpStream>, Box<dyn Error>> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let (read_stream, mut write_stream) = stream.split();
+    let mut reader = BufReader::new(read_stream);
+
+    assert!(is_valid_response::server_greeting(
+        &read_line!(reader).await?
+    ));
+
+    // Tests that `STARTTLS` is advertised while the connection is not encrypted.
+    crate::write_line!(write_stream, "EHLO client.example.com")?;
+    assert_eq!(
+        read_line!(reader).await?,
+        "250-mx.example.com greets client.example.com\r\n"
+    );
+    assert_eq!(read_line!(reader).await?, "250-STARTTLS\r\n");
+    assert_eq!(read_line!(reader).await?, "250 SIZE 10485760\r\n");
+
+    test_response!(
+        write_stream,
+        reader,
+        [
+            (
+                "STARTTLS now",
+                timeouts::EXPECTED,
+                is_valid_response::argument_error
+            ),
+            (
+                "STARTTLS",
+                timeouts::EXPECTED,
+                is_valid_response::server_greeting
+            ),
+        ],
+    );
+
+    Ok(TlsConnector::from(Arc::new(client))
+        .connect(ServerName::try_from(server_name.to_owned())?, stream)
+        .await?)
+}
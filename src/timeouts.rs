@@ -23,12 +23,15 @@
 //! 4.5.3.2](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.2) defines a list of
 //! timeouts in minutes.
 //!
-//! Notably, [`SERVER_TIMEOUT`] is the only timeout relevant to a server implementation. The
-//! timeouts used by clients are here for the sake of testing and thoroughness.
+//! The server waits according to [`Timeouts`], which is part of [`crate::ServerConfig`] and defaults
+//! to [`Timeouts::rfc5321`]. The timeouts used by clients are here for the sake of testing and
+//! thoroughness.
 //!
 //! Note that, when testing, all timeouts are overridden to [`EXPECTED`]; because a testing
 //! environment can be expected to have better performance than the real world.
 
+use std::time::Duration;
+
 /// A very strict timeout for how long participants should wait for anything.
 ///
 /// Not specified by RFC 5321. This is for identifying unusual performance for testing and logging.
@@ -65,6 +68,10 @@ minute_durations![
     /// client should wait after the connection is accepted for the `220` reply.
     ///
     /// [RFC 5321 § 4.5.3.2.1](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.2.1).
+    #[expect(
+        clippy::too_long_first_doc_paragraph,
+        reason = "the description of the timeout is one paragraph"
+    )]
     INITIAL_220_MESSAGE = 2,
     /// The minimum length in minutes a client should wait for a reply after sending the `MAIL`
     /// command.
@@ -76,6 +83,10 @@ minute_durations![
     /// longer, depending on when those are processed.
     ///
     /// [RFC 5321 § 4.5.3.2.3](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.2.3).
+    #[expect(
+        clippy::too_long_first_doc_paragraph,
+        reason = "the description of the timeout is one paragraph"
+    )]
     RCPT = 5,
     /// The minimum length in minutes a client should wait for the `354` reply after sending the
     /// `DATA` command.
@@ -93,9 +104,120 @@ minute_durations![
     /// messages.
     ///
     /// [RFC 5321 § 4.5.3.2.6](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.2.6).
+    #[expect(
+        clippy::too_long_first_doc_paragraph,
+        reason = "the description of the timeout is one paragraph"
+    )]
     DATA_TERMINATION = 10,
     /// The minimum length in minutes a server should wait for the next command from a client.
     ///
     /// [RFC 5321 § 4.5.3.2.7](https://www.rfc-editor.org/rfc/rfc5321.html#section-4.5.3.2.7).
     SERVER_TIMEOUT = 5,
 ];
+
+/// How long the server waits on a client in each phase of an SMTP session before ending it.
+///
+/// Defaults to [`Self::rfc5321`]. Each `with_*` method replaces one of the timeouts. With the
+/// `serde` feature, each timeout is (de)serialized as a number of seconds.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use smtp_gateway::timeouts::Timeouts;
+/// #
+/// let timeouts = Timeouts::rfc5321().with_server(Duration::from_secs(60));
+///
+/// assert_eq!(timeouts.server(), Duration::from_secs(60));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Timeouts {
+    /// How long to wait for the first command after the greeting.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    greeting: Duration,
+    /// How long to wait for each command after the first.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    server: Duration,
+    /// How long to wait for each line of the data of a message.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    data_block: Duration,
+    /// How long to wait for a message to be accepted after the end of its data.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::seconds"))]
+    data_termination: Duration,
+}
+
+impl Timeouts {
+    /// Create a new [`Self`] with the timeouts that RFC 5321 recommends: [`INITIAL_220_MESSAGE`],
+    /// [`SERVER_TIMEOUT`], [`DATA_BLOCK`], and [`DATA_TERMINATION`].
+    #[must_use]
+    pub const fn rfc5321() -> Self {
+        Self {
+            greeting: INITIAL_220_MESSAGE,
+            server: SERVER_TIMEOUT,
+            data_block: DATA_BLOCK,
+            data_termination: DATA_TERMINATION,
+        }
+    }
+
+    /// Get how long the server waits for the first command after its greeting.
+    #[must_use]
+    pub const fn greeting(&self) -> Duration {
+        self.greeting
+    }
+
+    /// Get how long the server waits for each command after the first.
+    #[must_use]
+    pub const fn server(&self) -> Duration {
+        self.server
+    }
+
+    /// Get how long the server waits for each line of the data of a message.
+    #[must_use]
+    pub const fn data_block(&self) -> Duration {
+        self.data_block
+    }
+
+    /// Get how long the server waits for a message to be accepted after the end of its data,
+    /// after which the client is told to try again later.
+    #[must_use]
+    pub const fn data_termination(&self) -> Duration {
+        self.data_termination
+    }
+
+    /// Replace how long the server waits for the first command. See [`Self::greeting`].
+    #[must_use]
+    pub const fn with_greeting(mut self, timeout: Duration) -> Self {
+        self.greeting = timeout;
+        self
+    }
+
+    /// Replace how long the server waits for each command after the first. See [`Self::server`].
+    #[must_use]
+    pub const fn with_server(mut self, timeout: Duration) -> Self {
+        self.server = timeout;
+        self
+    }
+
+    /// Replace how long the server waits for each line of data. See [`Self::data_block`].
+    #[must_use]
+    pub const fn with_data_block(mut self, timeout: Duration) -> Self {
+        self.data_block = timeout;
+        self
+    }
+
+    /// Replace how long the server waits for a message to be accepted. See
+    /// [`Self::data_termination`].
+    #[must_use]
+    pub const fn with_data_termination(mut self, timeout: Duration) -> Self {
+        self.data_termination = timeout;
+        self
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::rfc5321()
+    }
+}
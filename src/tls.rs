@@ -0,0 +1,584 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Encrypting sessions with `STARTTLS`.
+//!
+//! See [`TlsConfig`].
+//!
+//! [RFC 3207](https://www.rfc-editor.org/rfc/rfc3207.html).
+
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tokio::task::JoinHandle;
+pub use tokio_rustls::rustls;
+use tokio_rustls::{
+    rustls::{
+        crypto::CryptoProvider,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::{ClientHello, ResolvesServerCert, VerifierBuilderError, WebPkiClientVerifier},
+        sign::CertifiedKey,
+        version::{TLS12, TLS13},
+        RootCertStore, SupportedCipherSuite, SupportedProtocolVersion,
+    },
+    TlsAcceptor,
+};
+
+/// How sessions are encrypted when clients ask for it with `STARTTLS`.
+///
+/// `STARTTLS` is only offered when [`crate::ServerConfig::tls`] is set. Created with
+/// [`Self::builder`] from the certificate chain and private key of the server, or from a
+/// [`rustls::ServerConfig`] for full control.
+///
+/// A server that receives mail for several domains can present a different certificate for each
+/// with [`TlsConfigBuilder::certificate_for`], chosen by the name that the client asks for with
+/// Server Name Indication.
+///
+/// The certificates of a [`TlsConfig`] made by [`TlsConfigBuilder::build`] can be replaced while
+/// the server is running with [`Self::reload`], or by watching their files with
+/// [`Self::watch_pem`], such as when they are renewed. Every clone shares the same certificates,
+/// and the next handshake presents the new ones.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smtp_gateway::{tls::{ClientAuth, TlsConfig}, ServerConfig};
+/// #
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let tls = TlsConfig::builder_from_pem("/etc/smtp/cert.pem", "/etc/smtp/key.pem")?
+///     .client_auth(ClientAuth::Request)
+///     .build()?;
+/// let config = ServerConfig::builder()
+///     .hostname("mx.example.com")
+///     .tls(tls)
+///     .build()?;
+/// #     Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct TlsConfig {
+    /// Accepts the handshakes of clients.
+    acceptor: TlsAcceptor,
+    /// Chooses the certificate presented in each handshake, or `None` if the configuration of
+    /// `rustls` was given with [`Self::from_rustls`].
+    resolver: Option<Arc<CertificateResolver>>,
+}
+
+impl TlsConfig {
+    /// Create a [`TlsConfigBuilder`] for a server that presents `certificates`, starting with its
+    /// own, and signs with `key`.
+    pub fn builder(
+        certificates: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> TlsConfigBuilder {
+        TlsConfigBuilder {
+            certificates,
+            key,
+            by_name: Vec::new(),
+            min_version: TlsVersion::Tls12,
+            cipher_suites: None,
+            client_auth: ClientAuth::None,
+            client_roots: RootCertStore::empty(),
+        }
+    }
+
+    /// Create a [`TlsConfigBuilder`] from a PEM file of the certificate chain of the server and a
+    /// PEM file of its private key.
+    ///
+    /// # Errors
+    ///
+    /// - [`TlsError::Pem`] if either file could not be read or has no certificate or key.
+    pub fn builder_from_pem(
+        certificates: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Result<TlsConfigBuilder, TlsError> {
+        Ok(Self::builder(
+            CertificateDer::pem_file_iter(certificates)?.collect::<Result<_, _>>()?,
+            PrivateKeyDer::from_pem_file(key)?,
+        ))
+    }
+
+    /// Create a new [`Self`] from a configuration of `rustls`, which must be able to accept TLS 1.2
+    /// or later.
+    ///
+    /// Its certificates cannot be replaced with [`Self::reload`], but `config` can choose its own
+    /// with [`rustls::server::ResolvesServerCert`].
+    #[must_use]
+    pub fn from_rustls(config: Arc<rustls::ServerConfig>) -> Self {
+        Self {
+            acceptor: TlsAcceptor::from(config),
+            resolver: None,
+        }
+    }
+
+    /// Replace the certificate chain and private key presented by default, such as when they are
+    /// renewed. Every handshake after this presents the new certificate.
+    ///
+    /// # Errors
+    ///
+    /// - [`TlsError::NotReloadable`] if [`Self`] was created with [`Self::from_rustls`].
+    /// - [`TlsError::Rustls`] if the private key does not suit the certificate.
+    pub fn reload(
+        &self,
+        certificates: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<(), TlsError> {
+        let resolver = self.resolver.as_ref().ok_or(TlsError::NotReloadable)?;
+        let certified_key = resolver.certified_key(certificates, key)?;
+        resolver.write().default = certified_key;
+        Ok(())
+    }
+
+    /// Replace the certificate chain and private key presented when the client asks for `name`,
+    /// or start presenting them for it. See [`TlsConfigBuilder::certificate_for`].
+    ///
+    /// # Errors
+    ///
+    /// - [`TlsError::NotReloadable`] if [`Self`] was created with [`Self::from_rustls`].
+    /// - [`TlsError::Rustls`] if the private key does not suit the certificate.
+    pub fn reload_for(
+        &self,
+        name: impl Into<String>,
+        certificates: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<(), TlsError> {
+        let resolver = self.resolver.as_ref().ok_or(TlsError::NotReloadable)?;
+        let certified_key = resolver.certified_key(certificates, key)?;
+        resolver
+            .write()
+            .by_name
+            .insert(name.into().to_ascii_lowercase(), certified_key);
+        Ok(())
+    }
+
+    /// Replace the certificate chain and private key presented by default with those in PEM
+    /// files. See [`Self::reload`].
+    ///
+    /// # Errors
+    ///
+    /// - [`TlsError::Pem`] if either file could not be read or has no certificate or key.
+    /// - Any errors from [`Self::reload`].
+    pub fn reload_from_pem(
+        &self,
+        certificates: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Result<(), TlsError> {
+        self.reload(
+            CertificateDer::pem_file_iter(certificates)?.collect::<Result<_, _>>()?,
+            PrivateKeyDer::from_pem_file(key)?,
+        )
+    }
+
+    /// Check the PEM files of the default certificate chain and private key every `interval` in
+    /// the background, and [`Self::reload_from_pem`] them whenever either is modified.
+    ///
+    /// Failures to reload are logged and retried at the next check, so a renewal that writes the
+    /// two files one at a time is picked up once both are written. The task runs until it is
+    /// aborted through the returned [`JoinHandle`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    pub fn watch_pem(
+        &self,
+        certificates: impl Into<PathBuf>,
+        key: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        /// Get when the file at `path` was last modified, or `None` if that is unknown.
+        async fn modified(path: &Path) -> Option<SystemTime> {
+            tokio::fs::metadata(path).await.ok()?.modified().ok()
+        }
+
+        let config = self.clone();
+        let (certificates, key) = (certificates.into(), key.into());
+
+        tokio::spawn(async move {
+            let mut loaded = (modified(&certificates).await, modified(&key).await);
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                let current = (modified(&certificates).await, modified(&key).await);
+                if current == loaded {
+                    continue;
+                }
+                match config.reload_from_pem(&certificates, &key) {
+                    Ok(()) => {
+                        println!("Reloaded TLS certificate from {}", certificates.display());
+                        loaded = current;
+                    }
+                    Err(error) => eprintln!(
+                        "Failed to reload TLS certificate from {}: {error}",
+                        certificates.display()
+                    ),
+                }
+            }
+        })
+    }
+
+    /// Get the acceptor that handshakes are made with.
+    pub(crate) const fn acceptor(&self) -> &TlsAcceptor {
+        &self.acceptor
+    }
+}
+
+impl PartialEq for TlsConfig {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(self.acceptor.config(), other.acceptor.config())
+    }
+}
+
+impl Eq for TlsConfig {}
+
+impl Debug for TlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConfig").finish_non_exhaustive()
+    }
+}
+
+/// Builds a [`TlsConfig`].
+///
+/// Created by [`TlsConfig::builder`] or [`TlsConfig::builder_from_pem`].
+#[must_use]
+pub struct TlsConfigBuilder {
+    /// The certificate chain of the server, starting with its own.
+    certificates: Vec<CertificateDer<'static>>,
+    /// The private key of the server.
+    key: PrivateKeyDer<'static>,
+    /// The certificate chains and private keys presented for particular names, see
+    /// [`Self::certificate_for`].
+    by_name: Vec<(String, Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    /// The oldest version of the protocol that clients may use.
+    min_version: TlsVersion,
+    /// The cipher suites that clients may use, or `None` for those of the crypto provider.
+    cipher_suites: Option<Vec<SupportedCipherSuite>>,
+    /// Whether clients are asked for certificates.
+    client_auth: ClientAuth,
+    /// The certificate authorities that client certificates must be issued by.
+    client_roots: RootCertStore,
+}
+
+impl TlsConfigBuilder {
+    /// Present `certificates`, starting with its own, and sign with `key` when the client asks for
+    /// `name` with Server Name Indication, instead of the default certificate.
+    ///
+    /// `name` is matched without regard to case, and may be a wildcard such as `*.example.com`,
+    /// which matches a single label in its place. An exact match is preferred over a wildcard.
+    /// Clients that ask for no name or another name are given the default certificate.
+    ///
+    /// The name that was asked for is kept in [`crate::session::TlsInfo::server_name`].
+    pub fn certificate_for(
+        mut self,
+        name: impl Into<String>,
+        certificates: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Self {
+        self.by_name
+            .push((name.into().to_ascii_lowercase(), certificates, key));
+        self
+    }
+
+    /// Present the certificate chain in the PEM file `certificates` and sign with the private key
+    /// in the PEM file `key` when the client asks for `name`. See [`Self::certificate_for`].
+    ///
+    /// # Errors
+    ///
+    /// - [`TlsError::Pem`] if either file could not be read or has no certificate or key.
+    pub fn certificate_for_pem(
+        self,
+        name: impl Into<String>,
+        certificates: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Result<Self, TlsError> {
+        Ok(self.certificate_for(
+            name,
+            CertificateDer::pem_file_iter(certificates)?.collect::<Result<_, _>>()?,
+            PrivateKeyDer::from_pem_file(key)?,
+        ))
+    }
+
+    /// Set the oldest version of the protocol that clients may use. Defaults to
+    /// [`TlsVersion::Tls12`].
+    pub const fn min_version(mut self, version: TlsVersion) -> Self {
+        self.min_version = version;
+        self
+    }
+
+    /// Set the cipher suites that clients may use, in order of preference, such as those in
+    /// [`rustls::crypto::ring::cipher_suite`]. Defaults to every cipher suite of the default
+    /// crypto provider of `rustls`.
+    pub fn cipher_suites(mut self, suites: Vec<SupportedCipherSuite>) -> Self {
+        self.cipher_suites = Some(suites);
+        self
+    }
+
+    /// Set whether clients are asked for certificates. See [`ClientAuth`].
+    pub const fn client_auth(mut self, client_auth: ClientAuth) -> Self {
+        self.client_auth = client_auth;
+        self
+    }
+
+    /// Trust client certificates issued by `certificate`, a certificate authority. Client
+    /// certificates are only verified against the authorities that are added this way.
+    ///
+    /// # Errors
+    ///
+    /// - [`TlsError::Rustls`] if `certificate` is not a valid certificate authority.
+    pub fn client_root(mut self, certificate: CertificateDer<'_>) -> Result<Self, TlsError> {
+        self.client_roots.add(certificate)?;
+        Ok(self)
+    }
+
+    /// Build the [`TlsConfig`].
+    ///
+    /// # Errors
+    ///
+    /// - [`TlsError::ClientVerifier`] if clients are asked for certificates without any
+    ///   certificate authorities to verify them against, see [`Self::client_root`].
+    /// - [`TlsError::Rustls`] if a private key does not suit its certificate, or if none of the
+    ///   cipher suites suit the versions of the protocol.
+    pub fn build(self) -> Result<TlsConfig, TlsError> {
+        let mut provider = CryptoProvider::get_default()
+            .map_or_else(rustls::crypto::ring::default_provider, |provider| {
+                CryptoProvider::clone(provider)
+            });
+        if let Some(suites) = self.cipher_suites {
+            provider.cipher_suites = suites;
+        }
+        let builder = rustls::ServerConfig::builder_with_provider(Arc::new(provider))
+            .with_protocol_versions(self.min_version.and_later())?;
+        let builder = match self.client_auth {
+            ClientAuth::None => builder.with_no_client_auth(),
+            ClientAuth::Request | ClientAuth::Require => {
+                let verifier = WebPkiClientVerifier::builder(Arc::new(self.client_roots));
+                let verifier = if self.client_auth == ClientAuth::Request {
+                    verifier.allow_unauthenticated()
+                } else {
+                    verifier
+                };
+                builder.with_client_cert_verifier(verifier.build()?)
+            }
+        };
+
+        let provider = builder.crypto_provider().clone();
+        let certified_key =
+            |certificates, key| CertifiedKey::from_der(certificates, key, &provider).map(Arc::new);
+        let certificates = Certificates {
+            default: certified_key(self.certificates, self.key)?,
+            by_name: self
+                .by_name
+                .into_iter()
+                .map(|(name, certificates, key)| Ok((name, certified_key(certificates, key)?)))
+                .collect::<Result<_, rustls::Error>>()?,
+        };
+        let resolver = Arc::new(CertificateResolver {
+            provider,
+            certificates: RwLock::new(certificates),
+        });
+
+        Ok(TlsConfig {
+            acceptor: TlsAcceptor::from(Arc::new(builder.with_cert_resolver(resolver.clone()))),
+            resolver: Some(resolver),
+        })
+    }
+}
+
+/// Chooses the certificate to present by the name that the client asks for with Server Name
+/// Indication, from certificates that can be replaced. See [`TlsConfigBuilder::certificate_for`]
+/// and [`TlsConfig::reload`].
+#[derive(Debug)]
+struct CertificateResolver {
+    /// Loads the private keys of replacement certificates.
+    provider: Arc<CryptoProvider>,
+    /// The certificates presented.
+    certificates: RwLock<Certificates>,
+}
+
+/// The certificates presented by a [`CertificateResolver`].
+#[derive(Debug)]
+struct Certificates {
+    /// The certificate presented when no other matches.
+    default: Arc<CertifiedKey>,
+    /// The certificates presented for particular names, which are lowercase.
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl CertificateResolver {
+    /// Pair `certificates` with `key`, checking that the key suits the certificate.
+    fn certified_key(
+        &self,
+        certificates: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Arc<CertifiedKey>, rustls::Error> {
+        CertifiedKey::from_der(certificates, key, &self.provider).map(Arc::new)
+    }
+
+    /// Lock the certificates to replace them.
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Certificates> {
+        self.certificates
+            .write()
+            .expect("the lock is never held across a panic")
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let name = client_hello.server_name().map(str::to_ascii_lowercase);
+        let wildcard = name
+            .as_deref()
+            .and_then(|name| name.split_once('.'))
+            .map(|(_, parent)| format!("*.{parent}"));
+
+        let certificates = self
+            .certificates
+            .read()
+            .expect("the lock is never held across a panic");
+        let by_name = |name: Option<String>| certificates.by_name.get(&name?);
+        Some(
+            by_name(name)
+                .or_else(|| by_name(wildcard))
+                .unwrap_or(&certificates.default)
+                .clone(),
+        )
+    }
+}
+
+/// A version of the TLS protocol, for the oldest that clients may use.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord, Default)]
+pub enum TlsVersion {
+    /// TLS 1.2, [RFC 5246](https://www.rfc-editor.org/rfc/rfc5246.html).
+    #[default]
+    Tls12,
+    /// TLS 1.3, [RFC 8446](https://www.rfc-editor.org/rfc/rfc8446.html).
+    Tls13,
+}
+
+impl TlsVersion {
+    /// Get this version and every later one.
+    const fn and_later(self) -> &'static [&'static SupportedProtocolVersion] {
+        /// TLS 1.2 and later, most preferred first.
+        static TLS12_AND_LATER: [&SupportedProtocolVersion; 2] = [&TLS13, &TLS12];
+        /// TLS 1.3 and later.
+        static TLS13_AND_LATER: [&SupportedProtocolVersion; 1] = [&TLS13];
+
+        match self {
+            Self::Tls12 => &TLS12_AND_LATER,
+            Self::Tls13 => &TLS13_AND_LATER,
+        }
+    }
+}
+
+/// Whether `STARTTLS` is offered, and whether clients must use it before sending mail.
+///
+/// [RFC 3207 section 4](https://www.rfc-editor.org/rfc/rfc3207.html#section-4).
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum TlsPolicy {
+    /// Do not offer `STARTTLS`, even if [`crate::ServerConfig::tls`] is set.
+    Off,
+    /// Offer `STARTTLS` if [`crate::ServerConfig::tls`] is set, but take mail without it.
+    #[default]
+    Opportunistic,
+    /// Offer `STARTTLS`, and reject `MAIL` with `530` until the connection is encrypted.
+    ///
+    /// [`crate::ServerConfig::tls`] must be set.
+    Required,
+}
+
+/// Whether clients are asked for a certificate during the handshake, such as for relays that
+/// identify themselves with one.
+///
+/// A certificate that is given is always verified, and the handshake fails if it is invalid. The
+/// verified chain is kept in [`crate::session::TlsInfo::peer_certificates`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ClientAuth {
+    /// Do not ask for a certificate.
+    #[default]
+    None,
+    /// Ask for a certificate, continuing without one if the client has none.
+    Request,
+    /// Ask for a certificate, failing the handshake if the client has none.
+    Require,
+}
+
+/// Possible errors encountered when building a [`TlsConfig`].
+#[derive(Debug)]
+pub enum TlsError {
+    /// A PEM file could not be read, or did not hold what was expected.
+    Pem(rustls::pki_types::pem::Error),
+    /// `rustls` refused a certificate or key.
+    Rustls(rustls::Error),
+    /// The verifier of client certificates could not be built.
+    ClientVerifier(VerifierBuilderError),
+    /// The certificates of a [`TlsConfig`] created with [`TlsConfig::from_rustls`] cannot be
+    /// replaced.
+    NotReloadable,
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pem(e) => write!(f, "failed to read PEM file: {e}"),
+            Self::Rustls(e) => write!(f, "invalid certificate or key: {e}"),
+            Self::ClientVerifier(e) => write!(f, "invalid client certificate authorities: {e}"),
+            Self::NotReloadable => {
+                f.write_str("certificates are chosen by the rustls configuration")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TlsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Pem(e) => Some(e),
+            Self::Rustls(e) => Some(e),
+            Self::ClientVerifier(e) => Some(e),
+            Self::NotReloadable => None,
+        }
+    }
+}
+
+impl From<rustls::pki_types::pem::Error> for TlsError {
+    fn from(error: rustls::pki_types::pem::Error) -> Self {
+        Self::Pem(error)
+    }
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(error: rustls::Error) -> Self {
+        Self::Rustls(error)
+    }
+}
+
+impl From<VerifierBuilderError> for TlsError {
+    fn from(error: VerifierBuilderError) -> Self {
+        Self::ClientVerifier(error)
+    }
+}
@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Records every line of an SMTP session for debugging interoperability with clients.
+//!
+//! See [`Transcript`], and [`crate::ServerConfig::transcripts`].
+
+#[cfg(test)]
+mod test;
+
+use std::{
+    fmt::Display,
+    future::Future,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{handler::SessionContext, redact::Redactor};
+
+/// The most lines that a [`Transcript`] keeps, after which the rest of the session is left out.
+const MAX_LINES: usize = 10_000;
+
+/// Where the [`Transcript`] of each session is kept, see [`crate::ServerConfig::transcripts`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum TranscriptTarget {
+    /// Keep each transcript in memory for the handler, see
+    /// [`crate::handler::SessionContext::transcript`].
+    Memory,
+    /// Keep each transcript in memory for the handler, and write it to a file named after the
+    /// [`crate::session::SessionId`] of its session in this directory as the session closes.
+    Directory(PathBuf),
+}
+
+/// Whether a line of a [`Transcript`] was received from the client or sent to it.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Direction {
+    /// The line was received from the client.
+    Received,
+    /// The line was sent to the client.
+    Sent,
+}
+
+/// A line of a [`Transcript`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TranscriptLine {
+    /// Whether the line was received or sent.
+    direction: Direction,
+    /// How long after the start of the session the line was completed.
+    elapsed: Duration,
+    /// The line without its line ending, with credentials redacted.
+    text: String,
+}
+
+impl TranscriptLine {
+    /// Get whether the line was received from the client or sent to it.
+    #[must_use]
+    pub const fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Get how long after the start of the session the line was completed.
+    #[must_use]
+    pub const fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Get the line without its line ending.
+    ///
+    /// Bytes that are not valid UTF-8 are replaced, and credentials given with `AUTH` are replaced
+    /// with `[redacted]`.
+    #[must_use]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// Every line received and sent in an SMTP session, in order, as seen inside of TLS.
+///
+/// Credentials given in `AUTH` and in reply to its `334` challenges are redacted. Only the first
+/// 10,000 lines are kept, see [`Self::is_truncated`].
+///
+/// Formats with [`Display`] as one line for each line of the session, such as
+/// `0.012 S: 250 OK`, with the seconds since the session started and `C` for the client or `S`
+/// for the server.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Transcript {
+    /// The lines of the session.
+    lines: Vec<TranscriptLine>,
+    /// Whether lines were left out because there were too many.
+    truncated: bool,
+}
+
+impl Transcript {
+    /// Get the lines of the session, in order.
+    #[must_use]
+    pub fn lines(&self) -> &[TranscriptLine] {
+        &self.lines
+    }
+
+    /// Get whether the session had more lines than are kept, and the rest were left out.
+    #[must_use]
+    pub const fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl Display for Transcript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            let direction = match line.direction {
+                Direction::Received => 'C',
+                Direction::Sent => 'S',
+            };
+            writeln!(
+                f,
+                "{:.3} {direction}: {}",
+                line.elapsed.as_secs_f64(),
+                line.text
+            )?;
+        }
+        if self.truncated {
+            writeln!(f, "[truncated]")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds the [`Transcript`] of a session out of the bytes of its connection as they go by.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    /// When the session started.
+    started: Instant,
+    /// The transcript so far, and the lines in progress.
+    state: Mutex<RecorderState>,
+}
+
+/// The mutable part of a [`Recorder`].
+#[derive(Debug, Default)]
+struct RecorderState {
+    /// The transcript so far.
+    transcript: Transcript,
+    /// The start of the line being received.
+    received: Vec<u8>,
+    /// The start of the line being sent.
+    sent: Vec<u8>,
+    /// Redacts the credentials in the lines received.
+    redactor: Redactor,
+}
+
+impl Recorder {
+    /// Create a new [`Self`] for a session that just started.
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Record `bytes` received from the client.
+    pub(crate) fn received(&self, bytes: &[u8]) {
+        self.record(Direction::Received, bytes);
+    }
+
+    /// Record `bytes` sent to the client.
+    pub(crate) fn sent(&self, bytes: &[u8]) {
+        self.record(Direction::Sent, bytes);
+    }
+
+    /// Get the transcript so far, without the lines that are still in progress.
+    pub(crate) fn transcript(&self) -> Transcript {
+        self.lock().transcript.clone()
+    }
+
+    /// Add `bytes` to the line in progress in `direction`, moving each line that they finish into
+    /// the transcript.
+    fn record(&self, direction: Direction, mut bytes: &[u8]) {
+        let elapsed = self.started.elapsed();
+        let mut state = self.lock();
+
+        while let Some(end) = bytes.iter().position(|&byte| byte == b'\n') {
+            let partial = match direction {
+                Direction::Received => &mut state.received,
+                Direction::Sent => &mut state.sent,
+            };
+            partial.extend_from_slice(&bytes[..=end]);
+            let line = std::mem::take(partial);
+            bytes = &bytes[end + 1..];
+
+            state.push(direction, elapsed, &line);
+        }
+
+        if !state.transcript.truncated {
+            match direction {
+                Direction::Received => state.received.extend_from_slice(bytes),
+                Direction::Sent => state.sent.extend_from_slice(bytes),
+            }
+        }
+    }
+
+    /// Lock the mutable part of `self`.
+    fn lock(&self) -> std::sync::MutexGuard<'_, RecorderState> {
+        self.state
+            .lock()
+            .expect("the lock is never held across a panic")
+    }
+}
+
+impl RecorderState {
+    /// Add `line` in `direction` to the transcript, redacting it if it carries credentials.
+    fn push(&mut self, direction: Direction, elapsed: Duration, line: &[u8]) {
+        if self.transcript.lines.len() >= MAX_LINES {
+            self.transcript.truncated = true;
+            return;
+        }
+
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches(['\r', '\n']);
+        let text = match direction {
+            Direction::Received => self.redactor.received(line).into_owned(),
+            Direction::Sent => {
+                self.redactor.sent(line);
+                line.to_owned()
+            }
+        };
+
+        self.transcript.lines.push(TranscriptLine {
+            direction,
+            elapsed,
+            text,
+        });
+    }
+}
+
+/// Write the transcript of the session of `state` to a file if [`TranscriptTarget::Directory`] is
+/// where transcripts are kept.
+///
+/// Errors are logged, as the session is closing either way.
+pub(crate) fn save(state: &SessionContext) -> impl Future<Output = ()> + Send {
+    let file = match (state.config.transcripts(), state.transcript()) {
+        (Some(TranscriptTarget::Directory(directory)), Some(transcript)) => {
+            let id = state.session.id();
+            Some((id, directory.join(format!("{id}.log")), transcript))
+        }
+        _ => None,
+    };
+
+    async move {
+        let Some((id, path, transcript)) = file else {
+            return;
+        };
+
+        if let Err(error) = tokio::fs::write(&path, transcript.to_string()).await {
+            println!(
+                "[{id}] The transcript could not be written to {}: {error}",
+                path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// Copyright © 2024 RemasteredArch
+//
+// This file is part of smtp_gateway.
+//
+// smtp_gateway is free software: you can redistribute it and/or modify it under the terms of the
+// GNU Affero General Public License as published by the Free Software Foundation, either version
+// 3 of the License, or (at your option) any later version.
+//
+// smtp_gateway is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License along with
+// smtp_gateway. If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests for [`super`].
+
+use super::*;
+
+/// Get the lines of `transcript` as their directions and text.
+fn lines(transcript: &Transcript) -> Vec<(Direction, &str)> {
+    transcript
+        .lines()
+        .iter()
+        .map(|line| (line.direction(), line.text()))
+        .collect()
+}
+
+#[test]
+fn test_recorder() {
+    let recorder = Recorder::new();
+    recorder.sent(b"220 mx.example.com ESMTP\r\n");
+    // Tests that a line split across reads is recorded once it is finished.
+    recorder.received(b"EHLO client.exa");
+    assert_eq!(recorder.transcript().lines().len(), 1);
+    recorder.received(b"mple.com\r\nNOOP\r\n");
+    recorder.sent(b"250-mx.example.com\r\n250 PIPELINING\r\n");
+
+    let transcript = recorder.transcript();
+    assert_eq!(
+        lines(&transcript),
+        [
+            (Direction::Sent, "220 mx.example.com ESMTP"),
+            (Direction::Received, "EHLO client.example.com"),
+            (Direction::Received, "NOOP"),
+            (Direction::Sent, "250-mx.example.com"),
+            (Direction::Sent, "250 PIPELINING"),
+        ]
+    );
+    assert!(!transcript.is_truncated());
+
+    let display = transcript.to_string();
+    assert!(display.starts_with("0.000 S: 220 mx.example.com ESMTP\n"));
+    assert!(display.ends_with(" S: 250 PIPELINING\n"));
+}
+
+#[test]
+fn test_recorder_redaction() {
+    let recorder = Recorder::new();
+    recorder.received(b"AUTH PLAIN AHVzZXIAcGFzc3dvcmQ=\r\n");
+    recorder.sent(b"235 2.7.0 Authentication successful\r\n");
+    recorder.received(b"AUTH LOGIN\r\n");
+    recorder.sent(b"334 VXNlcm5hbWU6\r\n");
+    recorder.received(b"dXNlcg==\r\n");
+    recorder.sent(b"334 UGFzc3dvcmQ6\r\n");
+    recorder.received(b"cGFzc3dvcmQ=\r\n");
+    recorder.sent(b"235 2.7.0 Authentication successful\r\n");
+    recorder.received(b"MAIL FROM:<user@example.com>\r\n");
+
+    assert_eq!(
+        lines(&recorder.transcript()),
+        [
+            (Direction::Received, "AUTH PLAIN [redacted]"),
+            (Direction::Sent, "235 2.7.0 Authentication successful"),
+            (Direction::Received, "AUTH LOGIN"),
+            (Direction::Sent, "334 VXNlcm5hbWU6"),
+            (Direction::Received, "[redacted]"),
+            (Direction::Sent, "334 UGFzc3dvcmQ6"),
+            (Direction::Received, "[redacted]"),
+            (Direction::Sent, "235 2.7.0 Authentication successful"),
+            (Direction::Received, "MAIL FROM:<user@example.com>"),
+        ]
+    );
+}
+
+#[test]
+fn test_recorder_truncation() {
+    let recorder = Recorder::new();
+    for _ in 0..=MAX_LINES {
+        recorder.received(b"NOOP\r\n");
+    }
+
+    let transcript = recorder.transcript();
+    assert_eq!(transcript.lines().len(), MAX_LINES);
+    assert!(transcript.is_truncated());
+    assert!(transcript.to_string().ends_with("NOOP\n[truncated]\n"));
+}